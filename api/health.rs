@@ -1,15 +1,25 @@
 use anyhow::anyhow;
 use postgrest::Postgrest;
 use serde_json::json;
+use shared::health::{check_service, overall_status, probe};
+use shared::problem::respond;
+use shared::tenant::scope;
 use std::env;
 use vercel_runtime::{run, Body, Error, Request, Response};
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    run(handler).await
+    run(|req| respond(scope(req, handler))).await
 }
 
-pub async fn handler(_req: Request) -> Result<Response<Body>, Error> {
+pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
+    let deep = url::form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes())
+        .any(|(key, value)| key == "deep" && (value == "true" || value == "1"));
+
+    if deep {
+        return deep_health().await;
+    }
+
     match get_database_health().await {
         Ok(agent_count) => {
             let response_body = json!({
@@ -48,6 +58,35 @@ pub async fn handler(_req: Request) -> Result<Response<Body>, Error> {
     }
 }
 
+/// Probe the database, storage and auth services concurrently
+async fn deep_health() -> Result<Response<Body>, Error> {
+    let (database, storage, auth) = tokio::join!(
+        probe("database", async {
+            get_database_health().await.map(|_| ())
+        }),
+        probe("storage", check_service("storage/v1/bucket/agent-packages")),
+        probe("auth", check_service("auth/v1/health")),
+    );
+    let components = [database, storage, auth];
+
+    let status = overall_status(&components);
+
+    let response_body = json!({
+        "status": status,
+        "service": "carp-api",
+        "environment": "serverless",
+        "message": format!("Checked {} components", components.len()),
+        "components": components,
+        "timestamp": chrono::Utc::now().to_rfc3339()
+    });
+
+    Ok(Response::builder()
+        .status(if status == "unhealthy" { 503 } else { 200 })
+        .header("content-type", "application/json")
+        .header("Cache-Control", "no-store")
+        .body(response_body.to_string().into())?)
+}
+
 async fn get_database_health() -> Result<i64, anyhow::Error> {
     // Get database connection details from environment
     let supabase_url = env::var("SUPABASE_URL")
@@ -0,0 +1,26 @@
+use serde_json::json;
+use shared::problem::respond;
+use shared::tenant::scope;
+use vercel_runtime::{run, Body, Error, Request, Response};
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    run(|req| respond(scope(req, handler))).await
+}
+
+/// Liveness probe: answers as long as the function can run at all.
+///
+/// Dependencies are deliberately not checked here so an outage in the
+/// database or storage never gets a healthy instance restarted.
+pub async fn handler(_req: Request) -> Result<Response<Body>, Error> {
+    let response_body = json!({
+        "status": "ok",
+        "timestamp": chrono::Utc::now().to_rfc3339()
+    });
+
+    Ok(Response::builder()
+        .status(200)
+        .header("content-type", "application/json")
+        .header("Cache-Control", "no-store")
+        .body(response_body.to_string().into())?)
+}
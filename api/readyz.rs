@@ -0,0 +1,41 @@
+use serde_json::json;
+use shared::health::{check_schema_version, check_service, probe};
+use shared::problem::respond;
+use shared::tenant::scope;
+use vercel_runtime::{run, Body, Error, Request, Response};
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    run(|req| respond(scope(req, handler))).await
+}
+
+/// Readiness probe: only ready when downloads can actually be served.
+///
+/// The database and storage must be reachable and the schema must include
+/// every migration this build relies on. Slow dependencies still count as
+/// ready; only failures take the instance out of rotation.
+pub async fn handler(_req: Request) -> Result<Response<Body>, Error> {
+    let (database, storage, migrations) = tokio::join!(
+        probe(
+            "database",
+            check_service("rest/v1/agents?select=id&limit=1")
+        ),
+        probe("storage", check_service("storage/v1/bucket/agent-packages")),
+        probe("migrations", check_schema_version()),
+    );
+    let components = [database, storage, migrations];
+
+    let ready = components.iter().all(|c| c.status != "unhealthy");
+
+    let response_body = json!({
+        "status": if ready { "ready" } else { "not_ready" },
+        "components": components,
+        "timestamp": chrono::Utc::now().to_rfc3339()
+    });
+
+    Ok(Response::builder()
+        .status(if ready { 200 } else { 503 })
+        .header("content-type", "application/json")
+        .header("Cache-Control", "no-store")
+        .body(response_body.to_string().into())?)
+}
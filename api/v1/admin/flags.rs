@@ -0,0 +1,146 @@
+use serde::Deserialize;
+use vercel_runtime::{run, Body, Error, Request, Response};
+
+use shared::feature_flags::{self, Flag, FlagState, FlagStore};
+use shared::problem::respond;
+use shared::registry::service_config;
+use shared::tenant::scope;
+use shared::{api_key_middleware, require_scope, ApiError, Validate, ValidatedJson};
+
+/// Most environments a flag can be limited to
+const MAX_ENVIRONMENTS: usize = 10;
+
+#[derive(Debug, Deserialize)]
+pub struct FlagRequest {
+    pub flag: String,
+    pub enabled: bool,
+    #[serde(default = "full_rollout")]
+    pub rollout_percent: u8,
+    #[serde(default)]
+    pub environments: Vec<String>,
+}
+
+fn full_rollout() -> u8 {
+    100
+}
+
+impl Validate for FlagRequest {
+    fn validate(&self) -> Vec<(String, String)> {
+        let mut errors = Vec::new();
+        if Flag::parse(&self.flag).is_none() {
+            let flags: Vec<_> = Flag::ALL.iter().map(|flag| flag.name()).collect();
+            errors.push((
+                "flag".to_string(),
+                format!("flag must be one of {}", flags.join(", ")),
+            ));
+        }
+        if self.rollout_percent > 100 {
+            errors.push((
+                "rollout_percent".to_string(),
+                "rollout_percent must be between 0 and 100".to_string(),
+            ));
+        }
+        if self.environments.len() > MAX_ENVIRONMENTS {
+            errors.push((
+                "environments".to_string(),
+                format!("A flag can be limited to at most {MAX_ENVIRONMENTS} environments"),
+            ));
+        }
+        for (i, environment) in self.environments.iter().enumerate() {
+            if environment.is_empty()
+                || environment.len() > 32
+                || !environment
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+            {
+                errors.push((
+                    format!("environments[{i}]"),
+                    format!(
+                        "'{environment}' is not an environment name like production or preview"
+                    ),
+                ));
+            }
+        }
+        errors
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    run(|req| respond(scope(req, handler))).await
+}
+
+/// Lists the feature flags, and lets admins switch them or change who
+/// they're rolled out to
+pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
+    match req.method().as_str() {
+        "GET" => return json_response(&feature_flags::current().await),
+        "PUT" => {}
+        _ => {
+            return error_response(
+                405,
+                "method_not_allowed",
+                "Only GET and PUT requests are allowed".to_string(),
+            )
+        }
+    }
+
+    let authenticated_user = match api_key_middleware(&req).await {
+        Ok(user) => user,
+        Err(error_response) => return Ok(error_response),
+    };
+
+    if let Err(error_response) = require_scope(&authenticated_user, "admin") {
+        return Ok(error_response);
+    }
+
+    let ValidatedJson(request) = match ValidatedJson::<FlagRequest>::from_request(&req) {
+        Ok(request) => request,
+        Err(response) => return Ok(response),
+    };
+    // Checked by `FlagRequest::validate`
+    let Some(flag) = Flag::parse(&request.flag) else {
+        return error_response(400, "bad_request", "Unknown flag".to_string());
+    };
+
+    let (supabase_url, supabase_key) = service_config().map_err(Error::from)?;
+    let store = FlagStore::new(supabase_url, supabase_key);
+    let stored = store
+        .set(
+            flag,
+            request.enabled,
+            request.rollout_percent,
+            &request.environments,
+            authenticated_user.user_id,
+        )
+        .await
+        .map_err(|e| Error::from(format!("{e:#}")))?;
+
+    // FEATURE_<NAME> wins over the stored setting
+    json_response(&FlagState::from_env(flag).unwrap_or(stored))
+}
+
+fn json_response<T: serde::Serialize>(body: &T) -> Result<Response<Body>, Error> {
+    Ok(Response::builder()
+        .status(200)
+        .header("content-type", "application/json")
+        .header("Cache-Control", "no-store")
+        .body(serde_json::to_string(body)?.into())?)
+}
+
+fn error_response(status: u16, error: &str, message: String) -> Result<Response<Body>, Error> {
+    let error = ApiError {
+        error: error.to_string(),
+        message,
+        details: None,
+    };
+
+    let mut builder = Response::builder()
+        .status(status)
+        .header("content-type", "application/json");
+    if status == 405 {
+        builder = builder.header("allow", "GET, PUT");
+    }
+
+    Ok(builder.body(serde_json::to_string(&error)?.into())?)
+}
@@ -0,0 +1,112 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use vercel_runtime::{run, Body, Error, Request, Response};
+
+use shared::jobs::{Job, JobFilter, JobQueue, JobStatus};
+use shared::problem::respond;
+use shared::tenant::scope;
+use shared::{api_key_middleware, require_scope, ApiError};
+
+/// Jobs returned when no limit is given
+const DEFAULT_LIMIT: usize = 50;
+
+const MAX_LIMIT: usize = 500;
+
+/// The state of the background job queue
+#[derive(Debug, Serialize)]
+pub struct JobsResponse {
+    /// Jobs in each status, across the whole queue
+    pub counts: BTreeMap<String, u64>,
+    /// Matching jobs, most recently updated first
+    pub jobs: Vec<Job>,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    run(|req| respond(scope(req, handler))).await
+}
+
+/// Lists background jobs for admins, optionally filtered by `status` and
+/// `kind`, so failed ones and their errors can be looked into
+pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
+    if req.method() != "GET" {
+        return error_response(
+            405,
+            "method_not_allowed",
+            "Only GET requests are allowed".to_string(),
+        );
+    }
+
+    let authenticated_user = match api_key_middleware(&req).await {
+        Ok(user) => user,
+        Err(error_response) => return Ok(error_response),
+    };
+
+    if let Err(error_response) = require_scope(&authenticated_user, "admin") {
+        return Ok(error_response);
+    }
+
+    let query = req.uri().query().unwrap_or("");
+    let params: HashMap<String, String> = url::form_urlencoded::parse(query.as_bytes())
+        .into_owned()
+        .collect();
+
+    let status = match params.get("status").map(String::as_str) {
+        None | Some("") => None,
+        Some(status) => match JobStatus::parse(status) {
+            Some(status) => Some(status),
+            None => {
+                let statuses: Vec<&str> = JobStatus::ALL.iter().map(|s| s.as_str()).collect();
+                return error_response(
+                    400,
+                    "bad_request",
+                    format!(
+                        "Unknown status '{status}'; expected one of {}",
+                        statuses.join(", ")
+                    ),
+                );
+            }
+        },
+    };
+    // Any kind, including ones a newer deployment queued
+    let kind = params.get("kind").filter(|kind| !kind.is_empty()).cloned();
+    let limit = params
+        .get("limit")
+        .and_then(|s| s.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_LIMIT)
+        .clamp(1, MAX_LIMIT);
+
+    let queue = JobQueue::from_env().ok_or_else(|| {
+        Error::from("Database not configured - missing SUPABASE_URL or SUPABASE_SERVICE_ROLE_KEY")
+    })?;
+    let filter = JobFilter {
+        status,
+        kind,
+        limit,
+    };
+    let (counts, jobs) = tokio::try_join!(queue.counts(), queue.list(&filter))
+        .map_err(|e| Error::from(format!("{e:#}")))?;
+
+    Ok(Response::builder()
+        .status(200)
+        .header("content-type", "application/json")
+        .header("Cache-Control", "no-store")
+        .body(serde_json::to_string(&JobsResponse { counts, jobs })?.into())?)
+}
+
+fn error_response(status: u16, error: &str, message: String) -> Result<Response<Body>, Error> {
+    let error = ApiError {
+        error: error.to_string(),
+        message,
+        details: None,
+    };
+
+    let mut builder = Response::builder()
+        .status(status)
+        .header("content-type", "application/json");
+    if status == 405 {
+        builder = builder.header("allow", "GET");
+    }
+
+    Ok(builder.body(serde_json::to_string(&error)?.into())?)
+}
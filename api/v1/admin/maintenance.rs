@@ -0,0 +1,111 @@
+use serde::Deserialize;
+use vercel_runtime::{run, Body, Error, Request, Response};
+
+use shared::maintenance::{self, Maintenance, MaintenanceStore};
+use shared::problem::respond;
+use shared::registry::service_config;
+use shared::tenant::scope;
+use shared::{api_key_middleware, require_scope, ApiError, Validate, ValidatedJson};
+
+#[derive(Debug, Deserialize)]
+pub struct MaintenanceRequest {
+    pub read_only: bool,
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+impl MaintenanceRequest {
+    /// The message to show, if one was given
+    fn message(&self) -> Option<&str> {
+        self.message
+            .as_deref()
+            .map(str::trim)
+            .filter(|message| !message.is_empty())
+    }
+}
+
+impl Validate for MaintenanceRequest {
+    fn validate(&self) -> Vec<(String, String)> {
+        if self
+            .message()
+            .is_some_and(|message| message.chars().count() > 500)
+        {
+            return vec![(
+                "message".to_string(),
+                "The message can be at most 500 characters".to_string(),
+            )];
+        }
+        Vec::new()
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    run(|req| respond(scope(req, handler))).await
+}
+
+/// Shows whether the registry is in read-only maintenance, and lets admins
+/// switch it on or off
+pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
+    match req.method().as_str() {
+        "GET" => return json_response(&maintenance::current().await),
+        "PUT" => {}
+        _ => {
+            return error_response(
+                405,
+                "method_not_allowed",
+                "Only GET and PUT requests are allowed".to_string(),
+            )
+        }
+    }
+
+    let authenticated_user = match api_key_middleware(&req).await {
+        Ok(user) => user,
+        Err(error_response) => return Ok(error_response),
+    };
+
+    if let Err(error_response) = require_scope(&authenticated_user, "admin") {
+        return Ok(error_response);
+    }
+
+    let ValidatedJson(request) = match ValidatedJson::<MaintenanceRequest>::from_request(&req) {
+        Ok(request) => request,
+        Err(response) => return Ok(response),
+    };
+    let message = request.message();
+
+    let (supabase_url, supabase_key) = service_config().map_err(Error::from)?;
+    let store = MaintenanceStore::new(supabase_url, supabase_key);
+    let stored = store
+        .set(request.read_only, message, authenticated_user.user_id)
+        .await
+        .map_err(|e| Error::from(format!("{e:#}")))?;
+
+    // MAINTENANCE_MODE wins over the stored setting
+    json_response(&Maintenance::from_env().unwrap_or(stored))
+}
+
+fn json_response(maintenance: &Maintenance) -> Result<Response<Body>, Error> {
+    Ok(Response::builder()
+        .status(200)
+        .header("content-type", "application/json")
+        .header("Cache-Control", "no-store")
+        .body(serde_json::to_string(maintenance)?.into())?)
+}
+
+fn error_response(status: u16, error: &str, message: String) -> Result<Response<Body>, Error> {
+    let error = ApiError {
+        error: error.to_string(),
+        message,
+        details: None,
+    };
+
+    let mut builder = Response::builder()
+        .status(status)
+        .header("content-type", "application/json");
+    if status == 405 {
+        builder = builder.header("allow", "GET, PUT");
+    }
+
+    Ok(builder.body(serde_json::to_string(&error)?.into())?)
+}
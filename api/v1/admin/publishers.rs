@@ -0,0 +1,108 @@
+use serde::Deserialize;
+use vercel_runtime::{run, Body, Error, Request, Response};
+
+use shared::namespaces::{NamespaceStore, Publisher};
+use shared::problem::respond;
+use shared::registry::service_config;
+use shared::tenant::scope;
+use shared::validation::validation_failed;
+use shared::{
+    api_key_middleware, ensure_writable, require_scope, ApiError, Validate, ValidatedJson,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyRequest {
+    /// A GitHub username, or `@slug` for an organization
+    pub publisher: String,
+    pub verified: bool,
+}
+
+impl Validate for VerifyRequest {
+    fn validate(&self) -> Vec<(String, String)> {
+        if Publisher::parse(&self.publisher).is_some() {
+            return Vec::new();
+        }
+        vec![(
+            "publisher".to_string(),
+            format!(
+                "'{}' is not a GitHub username or @organization",
+                self.publisher
+            ),
+        )]
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    run(|req| respond(scope(req, handler))).await
+}
+
+/// Lets admins mark a user or organization as a verified publisher, or take
+/// it back, so their agents are shown as official
+pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
+    if req.method() != "PUT" {
+        return error_response(
+            405,
+            "method_not_allowed",
+            "Only PUT requests are allowed".to_string(),
+        );
+    }
+
+    if let Err(response) = ensure_writable().await {
+        return Ok(response);
+    }
+
+    let authenticated_user = match api_key_middleware(&req).await {
+        Ok(user) => user,
+        Err(error_response) => return Ok(error_response),
+    };
+
+    if let Err(error_response) = require_scope(&authenticated_user, "admin") {
+        return Ok(error_response);
+    }
+
+    let ValidatedJson(request) = match ValidatedJson::<VerifyRequest>::from_request(&req) {
+        Ok(request) => request,
+        Err(response) => return Ok(response),
+    };
+    let Some(publisher) = Publisher::parse(&request.publisher) else {
+        return Ok(validation_failed(&request.validate()));
+    };
+
+    let (supabase_url, supabase_key) = service_config().map_err(Error::from)?;
+    let store = NamespaceStore::new(supabase_url, supabase_key);
+    let Some(verification) = store
+        .set_verified(&publisher, authenticated_user.user_id, request.verified)
+        .await
+        .map_err(|e| Error::from(format!("{e:#}")))?
+    else {
+        return error_response(
+            404,
+            "not_found",
+            format!("There is no publisher named '{publisher}'"),
+        );
+    };
+
+    Ok(Response::builder()
+        .status(200)
+        .header("content-type", "application/json")
+        .header("Cache-Control", "no-store")
+        .body(serde_json::to_string(&verification)?.into())?)
+}
+
+fn error_response(status: u16, error: &str, message: String) -> Result<Response<Body>, Error> {
+    let error = ApiError {
+        error: error.to_string(),
+        message,
+        details: None,
+    };
+
+    let mut builder = Response::builder()
+        .status(status)
+        .header("content-type", "application/json");
+    if status == 405 {
+        builder = builder.header("allow", "PUT");
+    }
+
+    Ok(builder.body(serde_json::to_string(&error)?.into())?)
+}
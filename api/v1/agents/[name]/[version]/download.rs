@@ -1,56 +1,50 @@
-use anyhow::{anyhow, Result as AnyhowResult};
-use reqwest;
-use serde::{Deserialize, Serialize};
-use serde_json::json;
 use std::env;
 use vercel_runtime::{run, Body, Error, Request, Response};
 
 // Use shared authentication module
-use shared::{api_key_middleware, extract_bearer_token, ApiError, AuthenticatedUser};
-
-/// Optional authentication for downloads - allows both authenticated and unauthenticated access
-async fn optional_authenticate(req: &Request) -> Option<AuthenticatedUser> {
-    // Only attempt authentication if a token is provided
-    if extract_bearer_token(req).is_some() {
-        api_key_middleware(req).await.ok()
-    } else {
-        None
-    }
-}
-
-/// Agent download information
-#[derive(Debug, Serialize, Deserialize)]
-pub struct AgentDownload {
-    pub agent_id: String,
-    pub name: String,
-    pub author: String,
-    pub version: String,
-    pub download_url: String,
-    pub file_size: u64,
-    pub checksum: String,
-    pub content_type: String,
-    pub definition: serde_json::Value,
-}
-
-// ApiError is now imported from shared module
-
-/// Supabase storage response for signed URLs
-#[derive(Debug, Serialize, Deserialize)]
-pub struct SignedUrlResponse {
-    #[serde(rename = "signedURL")]
-    pub signed_url: String,
-}
+use shared::package_cache::PackageCache;
+use shared::problem;
+use shared::registry::download::{
+    download_info, find_download, load_package, DownloadRequest, Downloader, InlineTooLarge,
+};
+use shared::tenant;
+use shared::{check_rate_limit, optional_api_key_middleware, ApiError, AuthenticatedUser};
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    run(handler).await
+    run(|req| problem::respond(tenant::scope(req, handler))).await
 }
 
 pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
     // Optional authentication - if API key is provided, validate it
-    // This allows both authenticated and unauthenticated access
-    let authenticated_user = optional_authenticate(&req).await;
+    // This allows both authenticated and unauthenticated access, and lets
+    // owners download their private agents
+    let authenticated_user = optional_api_key_middleware(&req).await;
+
+    // Anonymous downloads are limited per IP; an API key raises the limit.
+    // Proxied package bytes are counted separately from the lookups that
+    // hand out their URLs.
+    let endpoint = if query_flag(&req, "raw") {
+        "package"
+    } else {
+        "download"
+    };
+    let rate_limit = check_rate_limit(&req, authenticated_user.as_ref(), endpoint).await;
+    if let Some(limit) = rate_limit.as_ref().filter(|limit| limit.exceeded()) {
+        return Ok(limit.exceeded_response(chrono::Utc::now()));
+    }
 
+    let mut response = respond(&req, authenticated_user.as_ref()).await?;
+    if let Some(limit) = &rate_limit {
+        limit.apply_headers(&mut response);
+    }
+    Ok(response)
+}
+
+async fn respond(
+    req: &Request,
+    authenticated_user: Option<&AuthenticatedUser>,
+) -> Result<Response<Body>, Error> {
     // Extract path parameters from URL path
     let path = req.uri().path();
     let path_segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
@@ -74,12 +68,46 @@ pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
     let version = urlencoding::decode(path_segments[4])
         .map_err(|_| Error::from("Invalid version encoding"))?;
 
-    // Get agent download info from database
-    match get_agent_download_info(&agent_name, &version, &req, authenticated_user.as_ref()).await {
+    // `?raw=true` is the package proxy that cached download URLs point at
+    if query_flag(req, "raw") {
+        return serve_package(&agent_name, &version).await;
+    }
+
+    // `?inline=true` returns the package itself instead of a storage URL
+    let inline = query_flag(req, "inline");
+    // `?prefetch=true` looks ahead for a picker without counting a download
+    let prefetch = query_flag(req, "prefetch");
+
+    let request = DownloadRequest {
+        name: &agent_name,
+        version: &version,
+        inline,
+        proxy_base: Some(proxy_base(req)),
+        prefetch,
+        downloader: Downloader::from_request(req, authenticated_user),
+    };
+    let supabase_url = env::var("SUPABASE_URL")
+        .map_err(|_| Error::from("SUPABASE_URL environment variable not set"))?;
+    let supabase_key = env::var("SUPABASE_SERVICE_ROLE_KEY")
+        .map_err(|_| Error::from("SUPABASE_SERVICE_ROLE_KEY environment variable not set"))?;
+    let client = tenant::http_client();
+
+    match download_info(&client, &supabase_url, &supabase_key, &request).await {
         Ok(download_info) => Ok(Response::builder()
             .status(200)
             .header("content-type", "application/json")
             .body(serde_json::to_string(&download_info)?.into())?),
+        Err(e) if e.is::<InlineTooLarge>() => {
+            let error = ApiError {
+                error: "package_too_large".to_string(),
+                message: format!("{e}. Download it from the storage URL instead."),
+                details: None,
+            };
+            Ok(Response::builder()
+                .status(413)
+                .header("content-type", "application/json")
+                .body(serde_json::to_string(&error)?.into())?)
+        }
         Err(e) => {
             let error = ApiError {
                 error: "not_found".to_string(),
@@ -97,225 +125,72 @@ pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
     }
 }
 
-async fn get_agent_download_info(
-    name: &str,
-    version: &str,
-    req: &Request,
-    authenticated_user: Option<&AuthenticatedUser>,
-) -> AnyhowResult<AgentDownload> {
-    // Get database connection parameters
-    let supabase_url = env::var("SUPABASE_URL")
-        .map_err(|_| anyhow!("SUPABASE_URL environment variable not set"))?;
-    let supabase_key = env::var("SUPABASE_SERVICE_ROLE_KEY")
-        .map_err(|_| anyhow!("SUPABASE_SERVICE_ROLE_KEY environment variable not set"))?;
+fn query_flag(req: &Request, flag: &str) -> bool {
+    url::form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes())
+        .any(|(key, value)| key == flag && (value == "true" || value == "1"))
+}
 
-    let client = reqwest::Client::new();
+/// Serve a public package's bytes, from the cache when it's there
+async fn serve_package(name: &str, version: &str) -> Result<Response<Body>, Error> {
+    let not_found = |message: String| {
+        let error = ApiError {
+            error: "not_found".to_string(),
+            message,
+            details: None,
+        };
+        Ok(Response::builder()
+            .status(404)
+            .header("content-type", "application/json")
+            .body(serde_json::to_string(&error)?.into())?)
+    };
+
+    let cache = PackageCache::from_env().map_err(|e| Error::from(e.to_string()))?;
+    let Some(cache) = cache else {
+        return not_found("Package proxy is not enabled on this registry".to_string());
+    };
+
+    let supabase_url = env::var("SUPABASE_URL").unwrap_or_default();
+    let supabase_key = env::var("SUPABASE_SERVICE_ROLE_KEY").unwrap_or_default();
+    let client = tenant::http_client();
+
+    // Only public agents are proxied, so look the version up anonymously
+    let agent_info =
+        match find_download(&client, &supabase_url, &supabase_key, name, version, None).await {
+            Ok(agent_info) if PackageCache::accepts(agent_info.file_size) => agent_info,
+            _ => return not_found(format!("Agent '{name}' version '{version}' not found")),
+        };
 
-    // Query the database for agent information
-    let agent_info = query_agent_info(
+    let package = load_package(
         &client,
         &supabase_url,
         &supabase_key,
-        name,
-        version,
-        authenticated_user,
+        &agent_info.file_path,
+        Some(&cache),
     )
-    .await?;
-
-    // Generate signed URL for download
-    let download_url =
-        generate_signed_url(&client, &supabase_url, &supabase_key, &agent_info.file_path).await?;
-
-    // Record the download
-    record_download(&client, &supabase_url, &supabase_key, name, version, req).await?;
-
-    Ok(AgentDownload {
-        agent_id: agent_info.agent_id,
-        name: agent_info.name,
-        author: agent_info.author,
-        version: agent_info.version,
-        download_url,
-        file_size: agent_info.file_size,
-        checksum: agent_info.checksum,
-        content_type: "application/zip".to_string(), // Default content type
-        definition: agent_info.definition,
-    })
-}
-
-#[derive(Debug)]
-struct AgentInfo {
-    agent_id: String,
-    name: String,
-    author: String,
-    version: String,
-    file_path: String,
-    checksum: String,
-    file_size: u64,
-    definition: serde_json::Value,
+    .await
+    .map_err(|e| Error::from(e.to_string()))?;
+
+    Ok(Response::builder()
+        .status(200)
+        .header("content-type", agent_info.content_type)
+        .header("content-length", package.len())
+        // A published version's package never changes
+        .header("Cache-Control", "public, max-age=3600")
+        .body(package.to_vec().into())?)
 }
 
-async fn query_agent_info(
-    client: &reqwest::Client,
-    supabase_url: &str,
-    supabase_key: &str,
-    name: &str,
-    version: &str,
-    _authenticated_user: Option<&AuthenticatedUser>,
-) -> AnyhowResult<AgentInfo> {
-    let url = format!("{}/rest/v1/rpc/get_agent_download_info", supabase_url);
-
-    let payload = json!({
-        "p_agent_name": name,
-        "p_version_text": if version == "latest" { "" } else { version }
-    });
-
-    let response = client
-        .post(&url)
-        .header("apikey", supabase_key)
-        .header("Authorization", format!("Bearer {}", supabase_key))
-        .header("Content-Type", "application/json")
-        .json(&payload)
-        .send()
-        .await?;
-
-    if !response.status().is_success() {
-        let error_text = response.text().await.unwrap_or_default();
-        return Err(anyhow!("Database query failed: {}", error_text));
-    }
-
-    let result: serde_json::Value = response.json().await?;
-
-    // Parse the result from the database function
-    if let Some(data) = result.as_array().and_then(|arr| arr.first()) {
-        // Database function only returns public agents, so no additional access control needed
-        Ok(AgentInfo {
-            agent_id: data
-                .get("agent_id")
-                .and_then(|v| v.as_str())
-                .ok_or_else(|| anyhow!("Missing agent_id in database response"))?
-                .to_string(),
-            name: data
-                .get("agent_name")
-                .and_then(|v| v.as_str())
-                .unwrap_or(name)
-                .to_string(),
-            author: data
-                .get("author")
-                .and_then(|v| v.as_str())
-                .unwrap_or("Unknown")
-                .to_string(),
-            version: data
-                .get("version")
-                .and_then(|v| v.as_str())
-                .unwrap_or(version)
-                .to_string(),
-            file_path: data
-                .get("file_path")
-                .and_then(|v| v.as_str())
-                .ok_or_else(|| anyhow!("Missing file_path in database response"))?
-                .to_string(),
-            checksum: data
-                .get("checksum")
-                .and_then(|v| v.as_str())
-                .unwrap_or("")
-                .to_string(),
-            file_size: data.get("file_size").and_then(|v| v.as_u64()).unwrap_or(0),
-            definition: data
-                .get("definition")
-                .cloned()
-                .unwrap_or(serde_json::json!({})),
+/// Base URL of this registry, which the package proxy is served under
+fn proxy_base(req: &Request) -> String {
+    env::var("API_BASE_URL")
+        .ok()
+        .filter(|url| !url.is_empty())
+        .unwrap_or_else(|| {
+            let host = req
+                .headers()
+                .get("x-forwarded-host")
+                .or_else(|| req.headers().get("host"))
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or("localhost");
+            format!("https://{host}")
         })
-    } else {
-        Err(anyhow!(
-            "Agent not found or no valid response from database"
-        ))
-    }
-}
-
-async fn generate_signed_url(
-    client: &reqwest::Client,
-    supabase_url: &str,
-    supabase_key: &str,
-    file_path: &str,
-) -> AnyhowResult<String> {
-    let url = format!(
-        "{}/storage/v1/object/sign/agent-packages/{}",
-        supabase_url, file_path
-    );
-
-    let payload = json!({
-        "expiresIn": 3600 // 1 hour expiration
-    });
-
-    let response = client
-        .post(&url)
-        .header("apikey", supabase_key)
-        .header("Authorization", format!("Bearer {}", supabase_key))
-        .header("Content-Type", "application/json")
-        .json(&payload)
-        .send()
-        .await?;
-
-    if !response.status().is_success() {
-        let error_text = response.text().await.unwrap_or_default();
-        return Err(anyhow!("Failed to generate signed URL: {}", error_text));
-    }
-
-    let signed_response: SignedUrlResponse = response.json().await?;
-    Ok(format!("{}{}", supabase_url, signed_response.signed_url))
-}
-
-async fn record_download(
-    client: &reqwest::Client,
-    supabase_url: &str,
-    supabase_key: &str,
-    name: &str,
-    version: &str,
-    req: &Request,
-) -> AnyhowResult<()> {
-    let url = format!("{}/rest/v1/rpc/record_download", supabase_url);
-
-    // Extract user agent and IP from request headers
-    let user_agent = req
-        .headers()
-        .get("user-agent")
-        .and_then(|v| v.to_str().ok())
-        .unwrap_or("")
-        .to_string();
-
-    let ip_addr = req
-        .headers()
-        .get("x-forwarded-for")
-        .or_else(|| req.headers().get("x-real-ip"))
-        .and_then(|v| v.to_str().ok())
-        .unwrap_or("127.0.0.1")
-        .split(',')
-        .next()
-        .unwrap_or("127.0.0.1")
-        .trim()
-        .to_string();
-
-    let payload = json!({
-        "agent_name": name,
-        "version_text": if version == "latest" { "" } else { version },
-        "user_agent_text": user_agent,
-        "ip_addr": ip_addr
-    });
-
-    let response = client
-        .post(&url)
-        .header("apikey", supabase_key)
-        .header("Authorization", format!("Bearer {}", supabase_key))
-        .header("Content-Type", "application/json")
-        .json(&payload)
-        .send()
-        .await?;
-
-    if !response.status().is_success() {
-        let error_text = response.text().await.unwrap_or_default();
-        // Don't fail the entire request if download tracking fails
-        eprintln!("Warning: Failed to record download: {}", error_text);
-    }
-
-    Ok(())
 }
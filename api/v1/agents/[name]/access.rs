@@ -0,0 +1,467 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::env;
+use uuid::Uuid;
+use vercel_runtime::{run, Body, Error, Request, Response};
+
+use shared::problem::respond;
+use shared::tenant::{self, scope};
+use shared::{
+    api_key_middleware, ensure_writable, require_scope, ApiError, AuthenticatedUser, Validate,
+    ValidatedJson,
+};
+
+/// The agent whose access is being managed
+#[derive(Debug, Deserialize)]
+struct DbAgent {
+    id: Uuid,
+    name: String,
+    user_id: Uuid,
+    is_public: bool,
+}
+
+/// Row of `agent_access_grants`
+#[derive(Debug, Deserialize)]
+struct DbGrant {
+    user_id: Option<Uuid>,
+    grantee_name: String,
+    created_at: DateTime<Utc>,
+}
+
+/// Who a grant is for. Exactly one of the fields must be set.
+#[derive(Debug, Deserialize)]
+pub struct AccessRequest {
+    /// GitHub username
+    #[serde(default)]
+    pub user: Option<String>,
+    /// Organization slug
+    #[serde(default)]
+    pub org: Option<String>,
+}
+
+#[derive(Debug)]
+enum Grantee {
+    User(String),
+    Org(String),
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GranteeKind {
+    User,
+    Org,
+}
+
+/// Someone who can read a private agent besides its owner
+#[derive(Debug, Serialize)]
+pub struct AccessGrant {
+    pub kind: GranteeKind,
+    pub name: String,
+    pub granted_at: DateTime<Utc>,
+}
+
+/// The agent's current grants
+#[derive(Debug, Serialize)]
+pub struct AccessResponse {
+    pub name: String,
+    pub is_public: bool,
+    pub grants: Vec<AccessGrant>,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    run(|req| respond(scope(req, handler))).await
+}
+
+/// `GET` lists who a private agent is shared with, `PUT` grants a user or
+/// organization read access and `DELETE` revokes it. Only the agent's owner
+/// can manage access; every method responds with the resulting grants.
+pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
+    let method = req.method().as_str().to_string();
+    if !matches!(method.as_str(), "GET" | "PUT" | "DELETE") {
+        return error_response(
+            405,
+            "method_not_allowed",
+            "Only GET, PUT and DELETE requests are allowed".to_string(),
+        );
+    }
+
+    if method != "GET" {
+        if let Err(response) = ensure_writable().await {
+            return Ok(response);
+        }
+    }
+
+    let authenticated_user = match api_key_middleware(&req).await {
+        Ok(user) => user,
+        Err(error_response) => return Ok(error_response),
+    };
+
+    let scope = if method == "GET" { "read" } else { "write" };
+    if let Err(error_response) = require_scope(&authenticated_user, scope) {
+        return Ok(error_response);
+    }
+
+    // Expected format: api/v1/agents/{name}/access
+    let path_segments: Vec<&str> = req
+        .uri()
+        .path()
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .collect();
+
+    if path_segments.len() < 5 {
+        return error_response(
+            400,
+            "bad_request",
+            "Invalid path format. Expected /api/v1/agents/{name}/access".to_string(),
+        );
+    }
+
+    let agent_name = urlencoding::decode(path_segments[3])
+        .map_err(|_| Error::from("Invalid agent name encoding"))?
+        .into_owned();
+
+    let Some(agent) = find_agent(&agent_name).await? else {
+        return not_found(&agent_name);
+    };
+
+    if agent.user_id != authenticated_user.user_id {
+        // Don't reveal that someone else's private agent exists
+        if !agent.is_public {
+            return not_found(&agent_name);
+        }
+        return error_response(
+            403,
+            "forbidden",
+            format!("Only the owner of '{agent_name}' can manage its access"),
+        );
+    }
+
+    if method != "GET" {
+        let grantee = match ValidatedJson::<AccessRequest>::from_request(&req) {
+            Ok(ValidatedJson(request)) => request.grantee(),
+            Err(response) => return Ok(response),
+        };
+
+        if method == "PUT" {
+            if agent.is_public {
+                return error_response(
+                    400,
+                    "agent_is_public",
+                    format!("Agent '{agent_name}' is public, so everyone can already read it"),
+                );
+            }
+
+            let Some(grantee_id) = resolve_grantee(&grantee).await? else {
+                return error_response(404, "not_found", grantee_not_found(&grantee));
+            };
+            if grantee_id == GranteeId::User(authenticated_user.user_id) {
+                return error_response(
+                    400,
+                    "validation_failed",
+                    "You already own this agent".to_string(),
+                );
+            }
+
+            add_grant(&agent, &grantee, grantee_id, &authenticated_user).await?;
+        } else if !remove_grant(&agent, &grantee).await? {
+            return error_response(
+                404,
+                "not_found",
+                format!(
+                    "Agent '{agent_name}' is not shared with {}",
+                    describe(&grantee)
+                ),
+            );
+        }
+    }
+
+    let response = AccessResponse {
+        name: agent.name.clone(),
+        is_public: agent.is_public,
+        grants: list_grants(&agent).await?,
+    };
+
+    Ok(Response::builder()
+        .status(200)
+        .header("content-type", "application/json")
+        .header("Cache-Control", "no-store")
+        .body(serde_json::to_string(&response)?.into())?)
+}
+
+impl AccessRequest {
+    /// The one grantee named, with the username or slug normalized
+    fn grantee(&self) -> Grantee {
+        match (&self.user, &self.org) {
+            (Some(user), _) => Grantee::User(user.trim().trim_start_matches('@').to_string()),
+            (None, org) => Grantee::Org(org.as_deref().unwrap_or_default().trim().to_lowercase()),
+        }
+    }
+}
+
+impl Validate for AccessRequest {
+    fn validate(&self) -> Vec<(String, String)> {
+        if self.user.is_some() == self.org.is_some() {
+            return vec![(
+                "user".to_string(),
+                "Set exactly one of 'user' or 'org'".to_string(),
+            )];
+        }
+
+        let (field, name, message) = match self.grantee() {
+            Grantee::User(user) => (
+                "user",
+                user.clone(),
+                format!("'{user}' is not a valid GitHub username"),
+            ),
+            Grantee::Org(org) => (
+                "org",
+                org.clone(),
+                format!("'{org}' is not a valid organization"),
+            ),
+        };
+        if is_valid_username(&name) {
+            Vec::new()
+        } else {
+            vec![(field.to_string(), message)]
+        }
+    }
+}
+
+/// GitHub usernames and organization slugs share the same rules
+fn is_valid_username(name: &str) -> bool {
+    (1..=39).contains(&name.len())
+        && !name.starts_with('-')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+fn describe(grantee: &Grantee) -> String {
+    match grantee {
+        Grantee::User(user) => format!("user '{user}'"),
+        Grantee::Org(org) => format!("organization '{org}'"),
+    }
+}
+
+fn grantee_not_found(grantee: &Grantee) -> String {
+    match grantee {
+        Grantee::User(user) => format!("User '{user}' not found"),
+        Grantee::Org(org) => format!("Organization '{org}' not found"),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GranteeId {
+    User(Uuid),
+    Org(Uuid),
+}
+
+fn database_config() -> Result<(String, String), Error> {
+    let supabase_url = env::var("SUPABASE_URL").unwrap_or_default();
+    let supabase_key = env::var("SUPABASE_SERVICE_ROLE_KEY").unwrap_or_default();
+
+    if supabase_url.is_empty() || supabase_key.is_empty() {
+        return Err(Error::from(
+            "Database not configured - missing SUPABASE_URL or SUPABASE_SERVICE_ROLE_KEY",
+        ));
+    }
+
+    Ok((supabase_url, supabase_key))
+}
+
+/// GET a PostgREST path with the service role
+async fn query(path: &str) -> Result<String, Error> {
+    let (supabase_url, supabase_key) = database_config()?;
+
+    let response = tenant::http_client()
+        .get(format!("{supabase_url}/rest/v1/{path}"))
+        .header("apikey", &supabase_key)
+        .header("Authorization", format!("Bearer {supabase_key}"))
+        .send()
+        .await
+        .map_err(|e| Error::from(format!("Database query failed: {e}")))?;
+
+    checked_body(response).await
+}
+
+async fn find_agent(name: &str) -> Result<Option<DbAgent>, Error> {
+    let body = query(&format!(
+        "agents?name=eq.{}&select=id,name,user_id,is_public&limit=1",
+        urlencoding::encode(name)
+    ))
+    .await?;
+
+    let agents: Vec<DbAgent> = serde_json::from_str(&body)
+        .map_err(|e| Error::from(format!("Failed to parse agent: {e}")))?;
+
+    Ok(agents.into_iter().next())
+}
+
+async fn resolve_grantee(grantee: &Grantee) -> Result<Option<GranteeId>, Error> {
+    #[derive(Deserialize)]
+    struct Row {
+        id: Uuid,
+    }
+
+    let path = match grantee {
+        // Validated names can't contain LIKE wildcards
+        Grantee::User(user) => format!(
+            "profiles?github_username=ilike.{}&select=id:user_id&limit=1",
+            urlencoding::encode(user)
+        ),
+        Grantee::Org(org) => format!(
+            "organizations?slug=eq.{}&select=id&limit=1",
+            urlencoding::encode(org)
+        ),
+    };
+
+    let body = query(&path).await?;
+    let rows: Vec<Row> = serde_json::from_str(&body)
+        .map_err(|e| Error::from(format!("Failed to parse grantee: {e}")))?;
+
+    Ok(rows.into_iter().next().map(|row| match grantee {
+        Grantee::User(_) => GranteeId::User(row.id),
+        Grantee::Org(_) => GranteeId::Org(row.id),
+    }))
+}
+
+async fn list_grants(agent: &DbAgent) -> Result<Vec<AccessGrant>, Error> {
+    let body = query(&format!(
+        "agent_access_grants?agent_id=eq.{}&select=user_id,grantee_name,created_at&order=created_at.asc",
+        agent.id
+    ))
+    .await?;
+
+    let grants: Vec<DbGrant> = serde_json::from_str(&body)
+        .map_err(|e| Error::from(format!("Failed to parse grants: {e}")))?;
+
+    Ok(grants
+        .into_iter()
+        .map(|grant| AccessGrant {
+            kind: if grant.user_id.is_some() {
+                GranteeKind::User
+            } else {
+                GranteeKind::Org
+            },
+            name: grant.grantee_name,
+            granted_at: grant.created_at,
+        })
+        .collect())
+}
+
+/// Record a grant; granting twice keeps the original
+async fn add_grant(
+    agent: &DbAgent,
+    grantee: &Grantee,
+    grantee_id: GranteeId,
+    user: &AuthenticatedUser,
+) -> Result<(), Error> {
+    let (supabase_url, supabase_key) = database_config()?;
+
+    let (conflict, row) = match (grantee, grantee_id) {
+        (Grantee::User(name), GranteeId::User(id)) => (
+            "agent_id,user_id",
+            json!({
+                "agent_id": agent.id,
+                "user_id": id,
+                "grantee_name": name,
+                "granted_by": user.user_id,
+            }),
+        ),
+        (Grantee::Org(name), GranteeId::Org(id)) => (
+            "agent_id,org_id",
+            json!({
+                "agent_id": agent.id,
+                "org_id": id,
+                "grantee_name": name,
+                "granted_by": user.user_id,
+            }),
+        ),
+        _ => return Err(Error::from("Grantee doesn't match its resolved id")),
+    };
+
+    let response = tenant::http_client()
+        .post(format!(
+            "{supabase_url}/rest/v1/agent_access_grants?on_conflict={conflict}"
+        ))
+        .header("apikey", &supabase_key)
+        .header("Authorization", format!("Bearer {supabase_key}"))
+        .header("Prefer", "resolution=ignore-duplicates,return=minimal")
+        .json(&row)
+        .send()
+        .await
+        .map_err(|e| Error::from(format!("Database insert failed: {e}")))?;
+
+    checked_body(response).await.map(|_| ())
+}
+
+/// Revoke a grant, returning whether there was one
+async fn remove_grant(agent: &DbAgent, grantee: &Grantee) -> Result<bool, Error> {
+    let (supabase_url, supabase_key) = database_config()?;
+
+    // Grants are matched on the name they were made with
+    let column = match grantee {
+        Grantee::User(_) => "user_id",
+        Grantee::Org(_) => "org_id",
+    };
+    let name = match grantee {
+        Grantee::User(name) | Grantee::Org(name) => name,
+    };
+
+    let response = tenant::http_client()
+        .delete(format!(
+            "{supabase_url}/rest/v1/agent_access_grants?agent_id=eq.{}&{column}=not.is.null&grantee_name=ilike.{}&select=id",
+            agent.id,
+            urlencoding::encode(name)
+        ))
+        .header("apikey", &supabase_key)
+        .header("Authorization", format!("Bearer {supabase_key}"))
+        .header("Prefer", "return=representation")
+        .send()
+        .await
+        .map_err(|e| Error::from(format!("Database delete failed: {e}")))?;
+
+    let body = checked_body(response).await?;
+    let deleted: Vec<serde_json::Value> = serde_json::from_str(&body)
+        .map_err(|e| Error::from(format!("Failed to parse response: {e}")))?;
+
+    Ok(!deleted.is_empty())
+}
+
+async fn checked_body(response: reqwest::Response) -> Result<String, Error> {
+    let status = response.status();
+    let body = response
+        .text()
+        .await
+        .map_err(|e| Error::from(format!("Failed to read response: {e}")))?;
+
+    if !status.is_success() {
+        return Err(Error::from(format!(
+            "Database request failed with status {status}: {body}"
+        )));
+    }
+
+    Ok(body)
+}
+
+fn not_found(name: &str) -> Result<Response<Body>, Error> {
+    error_response(404, "not_found", format!("Agent '{name}' not found"))
+}
+
+fn error_response(status: u16, error: &str, message: String) -> Result<Response<Body>, Error> {
+    let error = ApiError {
+        error: error.to_string(),
+        message,
+        details: None,
+    };
+
+    let mut builder = Response::builder()
+        .status(status)
+        .header("content-type", "application/json");
+    if status == 405 {
+        builder = builder.header("allow", "GET, PUT, DELETE");
+    }
+
+    Ok(builder.body(serde_json::to_string(&error)?.into())?)
+}
@@ -0,0 +1,139 @@
+use serde::{Deserialize, Serialize};
+use vercel_runtime::{run, Body, Error, Request, Response};
+
+use shared::problem::respond;
+use shared::registry::download::{find_download, DownloadNotFound};
+use shared::registry::service_config;
+use shared::tenant::{self, scope};
+use shared::{optional_api_key_middleware, ApiError};
+
+/// The latest version of an agent and its package checksum, for clients that
+/// only need to know whether it changed
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AgentChecksum {
+    pub name: String,
+    pub version: String,
+    /// SHA-256 of the package, as `sha256:<hex>`
+    pub checksum: String,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    run(|req| respond(scope(req, handler))).await
+}
+
+/// `GET` returns the checksum as JSON; `HEAD` only sets the `ETag`,
+/// `X-Carp-Version` and `X-Carp-Checksum` headers. A matching
+/// `If-None-Match` gets a 304.
+pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
+    let head = req.method() == "HEAD";
+    if req.method() != "GET" && !head {
+        let error = ApiError {
+            error: "method_not_allowed".to_string(),
+            message: "Only GET and HEAD requests are allowed".to_string(),
+            details: None,
+        };
+        return Ok(Response::builder()
+            .status(405)
+            .header("content-type", "application/json")
+            .header("allow", "GET, HEAD")
+            .body(serde_json::to_string(&error)?.into())?);
+    }
+
+    // Expected format: api/v1/agents/{name}/checksum
+    let path_segments: Vec<&str> = req
+        .uri()
+        .path()
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .collect();
+
+    if path_segments.len() < 5 {
+        let error = ApiError {
+            error: "bad_request".to_string(),
+            message: "Invalid path format. Expected /api/v1/agents/{name}/checksum".to_string(),
+            details: None,
+        };
+        return Ok(Response::builder()
+            .status(400)
+            .header("content-type", "application/json")
+            .body(serde_json::to_string(&error)?.into())?);
+    }
+
+    let agent_name = urlencoding::decode(path_segments[3])
+        .map_err(|_| Error::from("Invalid agent name encoding"))?;
+
+    // Owners can see their private agents; to anyone else they don't exist
+    let authenticated_user = optional_api_key_middleware(&req).await;
+    let (supabase_url, supabase_key) = service_config().map_err(Error::from)?;
+    let target = find_download(
+        &tenant::http_client(),
+        &supabase_url,
+        &supabase_key,
+        &agent_name,
+        "latest",
+        authenticated_user.as_ref().map(|user| user.user_id),
+    )
+    .await;
+
+    let target = match target {
+        Ok(target) => target,
+        Err(e) if e.is::<DownloadNotFound>() => {
+            let error = ApiError {
+                error: "not_found".to_string(),
+                message: format!("Agent '{agent_name}' not found"),
+                details: None,
+            };
+            return Ok(Response::builder()
+                .status(404)
+                .header("content-type", "application/json")
+                .body(serde_json::to_string(&error)?.into())?);
+        }
+        Err(e) => {
+            eprintln!("ERROR: Checksum lookup for '{agent_name}' failed: {e:#}");
+            let error = ApiError {
+                error: "internal_error".to_string(),
+                message: "Failed to look up the agent".to_string(),
+                details: None,
+            };
+            return Ok(Response::builder()
+                .status(500)
+                .header("content-type", "application/json")
+                .body(serde_json::to_string(&error)?.into())?);
+        }
+    };
+
+    let etag = format!("\"{}\"", target.checksum);
+    let not_modified = req
+        .headers()
+        .get("if-none-match")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|tags| tags.split(',').any(|tag| tag.trim() == etag));
+
+    let response = Response::builder()
+        .status(if not_modified { 304 } else { 200 })
+        .header("content-type", "application/json")
+        .header("etag", &etag)
+        .header("x-carp-version", &target.version)
+        .header("x-carp-checksum", &target.checksum)
+        // Always revalidated, which the ETag keeps cheap, so a publish shows up at once
+        .header(
+            "cache-control",
+            if authenticated_user.is_some() {
+                "private, no-store"
+            } else {
+                "no-cache"
+            },
+        )
+        .header("vary", "Authorization");
+    if head || not_modified {
+        return Ok(response.body(Body::Empty)?);
+    }
+
+    let body = AgentChecksum {
+        name: target.name,
+        version: target.version,
+        checksum: target.checksum,
+    };
+    Ok(response.body(serde_json::to_string(&body)?.into())?)
+}
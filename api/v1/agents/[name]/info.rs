@@ -0,0 +1,85 @@
+use vercel_runtime::{run, Body, Error, Request, Response};
+
+use shared::problem::respond;
+use shared::registry::info::get_agent_info;
+use shared::tenant::scope;
+use shared::{optional_api_key_middleware, AgentVisibility, ApiError};
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    run(|req| respond(scope(req, handler))).await
+}
+
+pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
+    if req.method() != "GET" {
+        let error = ApiError {
+            error: "method_not_allowed".to_string(),
+            message: "Only GET requests are allowed".to_string(),
+            details: None,
+        };
+        return Ok(Response::builder()
+            .status(405)
+            .header("content-type", "application/json")
+            .header("allow", "GET")
+            .body(serde_json::to_string(&error)?.into())?);
+    }
+
+    // Expected format: api/v1/agents/{name}/info
+    let path_segments: Vec<&str> = req
+        .uri()
+        .path()
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .collect();
+
+    if path_segments.len() < 5 {
+        let error = ApiError {
+            error: "bad_request".to_string(),
+            message: "Invalid path format. Expected /api/v1/agents/{name}/info".to_string(),
+            details: None,
+        };
+        return Ok(Response::builder()
+            .status(400)
+            .header("content-type", "application/json")
+            .body(serde_json::to_string(&error)?.into())?);
+    }
+
+    let agent_name = urlencoding::decode(path_segments[3])
+        .map_err(|_| Error::from("Invalid agent name encoding"))?;
+
+    // Owners can see their private agents; to anyone else they don't exist
+    let authenticated_user = optional_api_key_middleware(&req).await;
+    let visibility = AgentVisibility::for_user(authenticated_user.as_ref())
+        .await
+        .map_err(Error::from)?;
+
+    match get_agent_info(&agent_name, &visibility)
+        .await
+        .map_err(Error::from)?
+    {
+        Some(info) => Ok(Response::builder()
+            .status(200)
+            .header("content-type", "application/json")
+            .header(
+                "Cache-Control",
+                if info.is_public {
+                    "public, max-age=30"
+                } else {
+                    "private, no-store"
+                },
+            )
+            .header("Vary", "Authorization")
+            .body(serde_json::to_string(&info)?.into())?),
+        None => {
+            let error = ApiError {
+                error: "not_found".to_string(),
+                message: format!("Agent '{agent_name}' not found"),
+                details: None,
+            };
+            Ok(Response::builder()
+                .status(404)
+                .header("content-type", "application/json")
+                .body(serde_json::to_string(&error)?.into())?)
+        }
+    }
+}
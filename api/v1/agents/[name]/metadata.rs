@@ -0,0 +1,166 @@
+use serde_json::json;
+use vercel_runtime::{run, Body, Error, Request, Response};
+
+use shared::problem::respond;
+use shared::registry::metadata::{
+    fetch_metadata, update_metadata, AgentMetadata, IfMatch, MetadataUpdate,
+};
+use shared::tenant::scope;
+use shared::{api_key_middleware, ensure_writable, require_scope, ApiError, ValidatedJson};
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    run(|req| respond(scope(req, handler))).await
+}
+
+pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
+    let method = req.method().as_str().to_string();
+    if method != "GET" && method != "PATCH" {
+        return error_response(
+            405,
+            "method_not_allowed",
+            "Only GET and PATCH requests are allowed".to_string(),
+        );
+    }
+
+    if method != "GET" {
+        if let Err(response) = ensure_writable().await {
+            return Ok(response);
+        }
+    }
+
+    let authenticated_user = match api_key_middleware(&req).await {
+        Ok(user) => user,
+        Err(error_response) => return Ok(error_response),
+    };
+
+    let scope = if method == "GET" { "read" } else { "write" };
+    if let Err(error_response) = require_scope(&authenticated_user, scope) {
+        return Ok(error_response);
+    }
+
+    // Expected format: api/v1/agents/{name}/metadata
+    let path_segments: Vec<&str> = req
+        .uri()
+        .path()
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .collect();
+
+    if path_segments.len() < 5 {
+        return error_response(
+            400,
+            "bad_request",
+            "Invalid path format. Expected /api/v1/agents/{name}/metadata".to_string(),
+        );
+    }
+
+    let agent_name = urlencoding::decode(path_segments[3])
+        .map_err(|_| Error::from("Invalid agent name encoding"))?
+        .into_owned();
+
+    if method == "GET" {
+        return match fetch_metadata(&agent_name, &authenticated_user)
+            .await
+            .map_err(Error::from)?
+        {
+            Some(metadata) => metadata_response(200, &metadata),
+            None => not_found(&agent_name),
+        };
+    }
+
+    let if_match = match req
+        .headers()
+        .get("if-match")
+        .and_then(|h| h.to_str().ok())
+        .map(IfMatch::parse)
+    {
+        Some(Some(if_match)) => if_match,
+        Some(None) => {
+            return error_response(
+                400,
+                "bad_request",
+                "If-Match must be an ETag returned by GET or *".to_string(),
+            )
+        }
+        None => {
+            return error_response(
+                428,
+                "precondition_required",
+                "Send the ETag from GET in an If-Match header to update metadata".to_string(),
+            )
+        }
+    };
+
+    let ValidatedJson(update) = match ValidatedJson::<MetadataUpdate>::from_request(&req) {
+        Ok(update) => update,
+        Err(response) => return Ok(response),
+    };
+
+    if let Some(updated) = update_metadata(&agent_name, &authenticated_user, if_match, &update)
+        .await
+        .map_err(Error::from)?
+    {
+        return metadata_response(200, &updated);
+    }
+
+    // Nothing matched: either the agent isn't ours or someone else got there first
+    match fetch_metadata(&agent_name, &authenticated_user)
+        .await
+        .map_err(Error::from)?
+    {
+        Some(current) => {
+            let error = ApiError {
+                error: "edit_conflict".to_string(),
+                message: format!(
+                    "Agent '{agent_name}' was changed by someone else. Fetch the latest metadata and try again."
+                ),
+                details: Some(json!({ "current": current })),
+            };
+            Ok(Response::builder()
+                .status(409)
+                .header("content-type", "application/json")
+                .header("ETag", etag(current.metadata_version))
+                .body(serde_json::to_string(&error)?.into())?)
+        }
+        None => not_found(&agent_name),
+    }
+}
+
+fn etag(version: i64) -> String {
+    format!("\"{version}\"")
+}
+
+fn metadata_response(status: u16, metadata: &AgentMetadata) -> Result<Response<Body>, Error> {
+    Ok(Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .header("ETag", etag(metadata.metadata_version))
+        .header("Cache-Control", "no-store")
+        .body(serde_json::to_string(metadata)?.into())?)
+}
+
+fn not_found(name: &str) -> Result<Response<Body>, Error> {
+    error_response(
+        404,
+        "not_found",
+        format!("Agent '{name}' not found or not owned by you"),
+    )
+}
+
+fn error_response(status: u16, error: &str, message: String) -> Result<Response<Body>, Error> {
+    let error = ApiError {
+        error: error.to_string(),
+        message,
+        details: None,
+    };
+
+    let mut builder = Response::builder()
+        .status(status)
+        .header("content-type", "application/json");
+    if status == 405 {
+        builder = builder.header("allow", "GET, PATCH");
+    }
+
+    Ok(builder.body(serde_json::to_string(&error)?.into())?)
+}
@@ -0,0 +1,236 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::env;
+use uuid::Uuid;
+use vercel_runtime::{run, Body, Error, Request, Response};
+
+use shared::problem::respond;
+use shared::tenant::{self, scope};
+use shared::{
+    api_key_middleware, ensure_writable, require_scope, ApiError, AuthenticatedUser, ReportReason,
+    Validate, ValidatedJson,
+};
+
+/// Longest report details accepted, matching the database constraint
+const MAX_DETAILS_LENGTH: usize = 2000;
+
+/// Body of a report
+#[derive(Debug, Deserialize)]
+pub struct ReportRequest {
+    pub reason: ReportReason,
+    #[serde(default)]
+    pub details: String,
+}
+
+/// Acknowledgement of a report
+#[derive(Debug, Serialize)]
+pub struct ReportResponse {
+    pub name: String,
+    pub reason: ReportReason,
+    /// False when the caller already has an open report on this agent
+    pub created: bool,
+    pub message: String,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    run(|req| respond(scope(req, handler))).await
+}
+
+/// Files an abuse report against an agent for moderators to review
+pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
+    if req.method() != "POST" {
+        return error_response(
+            405,
+            "method_not_allowed",
+            "Only POST requests are allowed".to_string(),
+        );
+    }
+
+    if let Err(response) = ensure_writable().await {
+        return Ok(response);
+    }
+
+    let authenticated_user = match api_key_middleware(&req).await {
+        Ok(user) => user,
+        Err(error_response) => return Ok(error_response),
+    };
+
+    // Anyone who can read the registry can flag what they find in it
+    if let Err(error_response) = require_scope(&authenticated_user, "read") {
+        return Ok(error_response);
+    }
+
+    // Expected format: api/v1/agents/{name}/report
+    let path_segments: Vec<&str> = req
+        .uri()
+        .path()
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .collect();
+
+    if path_segments.len() < 5 {
+        return error_response(
+            400,
+            "bad_request",
+            "Invalid path format. Expected /api/v1/agents/{name}/report".to_string(),
+        );
+    }
+
+    let agent_name = urlencoding::decode(path_segments[3])
+        .map_err(|_| Error::from("Invalid agent name encoding"))?
+        .into_owned();
+
+    let ValidatedJson(request) = match ValidatedJson::<ReportRequest>::from_request(&req) {
+        Ok(request) => request,
+        Err(response) => return Ok(response),
+    };
+
+    let Some(agent_id) = find_agent(&agent_name).await? else {
+        return error_response(404, "not_found", format!("Agent '{agent_name}' not found"));
+    };
+
+    let created = insert_report(agent_id, &authenticated_user, &request).await?;
+    let message = if created {
+        "Thanks, your report was sent to the moderators".to_string()
+    } else {
+        "You already have an open report on this agent; moderators will review it".to_string()
+    };
+
+    let response = ReportResponse {
+        name: agent_name,
+        reason: request.reason,
+        created,
+        message,
+    };
+
+    Ok(Response::builder()
+        .status(if created { 201 } else { 200 })
+        .header("content-type", "application/json")
+        .header("Cache-Control", "no-store")
+        .body(serde_json::to_string(&response)?.into())?)
+}
+
+impl Validate for ReportRequest {
+    fn validate(&self) -> Vec<(String, String)> {
+        let details = self.details.trim();
+        let message = if self.reason == ReportReason::Other && details.is_empty() {
+            "Details are required when the reason is 'other'".to_string()
+        } else if details.chars().count() > MAX_DETAILS_LENGTH {
+            format!("Details cannot exceed {MAX_DETAILS_LENGTH} characters")
+        } else {
+            return Vec::new();
+        };
+        vec![("details".to_string(), message)]
+    }
+}
+
+fn database_config() -> Result<(String, String), Error> {
+    let supabase_url = env::var("SUPABASE_URL").unwrap_or_default();
+    let supabase_key = env::var("SUPABASE_SERVICE_ROLE_KEY").unwrap_or_default();
+
+    if supabase_url.is_empty() || supabase_key.is_empty() {
+        return Err(Error::from(
+            "Database not configured - missing SUPABASE_URL or SUPABASE_SERVICE_ROLE_KEY",
+        ));
+    }
+
+    Ok((supabase_url, supabase_key))
+}
+
+async fn find_agent(name: &str) -> Result<Option<Uuid>, Error> {
+    #[derive(Deserialize)]
+    struct Row {
+        id: Uuid,
+    }
+
+    let (supabase_url, supabase_key) = database_config()?;
+
+    let response = tenant::http_client()
+        .get(format!(
+            "{supabase_url}/rest/v1/agents?name=eq.{}&tenant_id=eq.{}&is_public=eq.true&select=id&limit=1",
+            urlencoding::encode(name),
+            tenant::current().id
+        ))
+        .header("apikey", &supabase_key)
+        .header("Authorization", format!("Bearer {supabase_key}"))
+        .send()
+        .await
+        .map_err(|e| Error::from(format!("Database query failed: {e}")))?;
+
+    let status = response.status();
+    let body = response
+        .text()
+        .await
+        .map_err(|e| Error::from(format!("Failed to read response: {e}")))?;
+
+    if !status.is_success() {
+        return Err(Error::from(format!(
+            "Database query failed with status {status}: {body}"
+        )));
+    }
+
+    let rows: Vec<Row> = serde_json::from_str(&body)
+        .map_err(|e| Error::from(format!("Failed to parse agent: {e}")))?;
+
+    Ok(rows.into_iter().next().map(|row| row.id))
+}
+
+/// Store the report, returning false if the user already has an open one
+async fn insert_report(
+    agent_id: Uuid,
+    user: &AuthenticatedUser,
+    request: &ReportRequest,
+) -> Result<bool, Error> {
+    let (supabase_url, supabase_key) = database_config()?;
+
+    let response = tenant::http_client()
+        .post(format!("{supabase_url}/rest/v1/agent_reports"))
+        .header("apikey", &supabase_key)
+        .header("Authorization", format!("Bearer {supabase_key}"))
+        .header("Prefer", "return=minimal")
+        .json(&json!({
+            "agent_id": agent_id,
+            "reporter_id": user.user_id,
+            "reason": request.reason,
+            "details": request.details.trim(),
+        }))
+        .send()
+        .await
+        .map_err(|e| Error::from(format!("Database insert failed: {e}")))?;
+
+    let status = response.status();
+    if status.is_success() {
+        return Ok(true);
+    }
+
+    // PostgREST reports the one-open-report-per-user unique index as a conflict
+    if status == reqwest::StatusCode::CONFLICT {
+        return Ok(false);
+    }
+
+    let body = response
+        .text()
+        .await
+        .unwrap_or_else(|_| "Unknown error".to_string());
+    Err(Error::from(format!(
+        "Database insert failed with status {status}: {body}"
+    )))
+}
+
+fn error_response(status: u16, error: &str, message: String) -> Result<Response<Body>, Error> {
+    let error = ApiError {
+        error: error.to_string(),
+        message,
+        details: None,
+    };
+
+    let mut builder = Response::builder()
+        .status(status)
+        .header("content-type", "application/json");
+    if status == 405 {
+        builder = builder.header("allow", "POST");
+    }
+
+    Ok(builder.body(serde_json::to_string(&error)?.into())?)
+}
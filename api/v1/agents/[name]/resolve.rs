@@ -0,0 +1,152 @@
+use chrono::Utc;
+use std::collections::HashMap;
+use vercel_runtime::{run, Body, Error, Request, Response};
+
+use shared::problem::respond;
+use shared::registry::service_config;
+use shared::resolution::{
+    resolve, ResolutionManifest, ResolutionSigner, ResolutionStore, ResolveError,
+};
+use shared::tenant::scope;
+use shared::{optional_api_key_middleware, ApiError};
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    run(|req| respond(scope(req, handler))).await
+}
+
+/// Resolves an agent and everything it depends on, answering with a
+/// resolution manifest signed by the registry
+///
+/// `?version=` takes a semver requirement and defaults to the latest version.
+/// Private agents are only resolved for their owners and those they're
+/// shared with.
+pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
+    if req.method() != "GET" {
+        return error_response(
+            405,
+            "method_not_allowed",
+            "Only GET requests are allowed".to_string(),
+        );
+    }
+
+    // Expected format: api/v1/agents/{name}/resolve
+    let path_segments: Vec<&str> = req
+        .uri()
+        .path()
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .collect();
+    if path_segments.len() < 5 {
+        return error_response(
+            400,
+            "bad_request",
+            "Invalid path format. Expected /api/v1/agents/{name}/resolve".to_string(),
+        );
+    }
+    let agent_name = urlencoding::decode(path_segments[3])
+        .map_err(|_| Error::from("Invalid agent name encoding"))?
+        .into_owned();
+
+    let query = req.uri().query().unwrap_or("");
+    let params: HashMap<String, String> = url::form_urlencoded::parse(query.as_bytes())
+        .into_owned()
+        .collect();
+    let requirement = params
+        .get("version")
+        .map(|version| version.trim().to_string())
+        .filter(|version| !version.is_empty())
+        .unwrap_or_else(|| "latest".to_string());
+
+    let signer = match ResolutionSigner::from_env() {
+        Ok(Some(signer)) => signer,
+        Ok(None) => {
+            return error_response(
+                503,
+                "resolution_unavailable",
+                "This registry has no RESOLUTION_SIGNING_KEY, so it can't sign resolutions"
+                    .to_string(),
+            )
+        }
+        Err(e) => {
+            eprintln!("ERROR: Invalid resolution signing key: {e:#}");
+            return error_response(
+                500,
+                "resolution_misconfigured",
+                "The registry's resolution signing key is invalid".to_string(),
+            );
+        }
+    };
+
+    let authenticated_user = optional_api_key_middleware(&req).await;
+    let requester_id = authenticated_user.as_ref().map(|user| user.user_id);
+    let (supabase_url, supabase_key) = service_config().map_err(Error::from)?;
+    let store = ResolutionStore::new(supabase_url, supabase_key);
+
+    let agents = match resolve(&agent_name, &requirement, |names| {
+        let store = &store;
+        async move { store.candidates(&names, requester_id).await }
+    })
+    .await
+    {
+        Ok(agents) => agents,
+        Err(e @ ResolveError::InvalidRequirement { .. }) => {
+            return error_response(400, "bad_request", e.to_string())
+        }
+        Err(e @ ResolveError::NotFound { .. }) => {
+            return error_response(404, "not_found", e.to_string())
+        }
+        Err(e @ (ResolveError::Conflict { .. } | ResolveError::TooLarge)) => {
+            return error_response(409, "unresolvable", e.to_string())
+        }
+        Err(ResolveError::Lookup(e)) => {
+            eprintln!("ERROR: Resolving '{agent_name}' failed: {e:#}");
+            return error_response(
+                500,
+                "internal_error",
+                "Failed to look up versions".to_string(),
+            );
+        }
+    };
+
+    let manifest = ResolutionManifest {
+        root: agent_name,
+        requirement,
+        agents,
+        issued_at: Utc::now(),
+    };
+    let signed = signer
+        .sign(&manifest)
+        .map_err(|e| Error::from(format!("{e:#}")))?;
+
+    Ok(Response::builder()
+        .status(200)
+        .header("content-type", "application/json")
+        .header(
+            "cache-control",
+            if authenticated_user.is_some() {
+                "private, no-store"
+            } else {
+                "no-cache"
+            },
+        )
+        .header("vary", "Authorization")
+        .body(serde_json::to_string(&signed)?.into())?)
+}
+
+fn error_response(status: u16, error: &str, message: String) -> Result<Response<Body>, Error> {
+    let error = ApiError {
+        error: error.to_string(),
+        message,
+        details: None,
+    };
+
+    let mut builder = Response::builder()
+        .status(status)
+        .header("content-type", "application/json");
+    if status == 405 {
+        builder = builder.header("allow", "GET");
+    }
+
+    Ok(builder.body(serde_json::to_string(&error)?.into())?)
+}
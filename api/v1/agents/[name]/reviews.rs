@@ -0,0 +1,363 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::env;
+use uuid::Uuid;
+use vercel_runtime::{run, Body, Error, Request, Response};
+
+use shared::problem::respond;
+use shared::tenant::{self, scope};
+use shared::{
+    api_key_middleware, ensure_enabled, ensure_writable, require_scope, screen_text, ApiError,
+    AuthenticatedUser, Flag, ModerationStatus, Screening,
+};
+
+/// Columns returned for each review
+const REVIEW_COLUMNS: &str = "author_name,rating,body,status,created_at,updated_at";
+
+/// Longest review text accepted, matching the database constraint
+const MAX_BODY_LENGTH: usize = 5000;
+
+/// Reviews returned when no limit is given
+const DEFAULT_LIMIT: usize = 20;
+
+/// Upper bound on `limit`
+const MAX_LIMIT: usize = 100;
+
+/// The reviewed agent and its current rating
+#[derive(Debug, Deserialize)]
+struct DbAgent {
+    id: Uuid,
+    name: String,
+    rating_count: u64,
+    rating_average: Option<f64>,
+}
+
+/// A review as returned to clients
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Review {
+    pub author_name: String,
+    pub rating: u8,
+    pub body: String,
+    /// `published`, or `pending` while a held review awaits a moderator
+    pub status: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Body of a PUT, creating or replacing the caller's review
+#[derive(Debug, Deserialize)]
+pub struct ReviewRequest {
+    pub rating: u8,
+    #[serde(default)]
+    pub body: String,
+}
+
+/// An agent's rating and its most recent published reviews
+#[derive(Debug, Serialize)]
+pub struct ReviewsResponse {
+    pub name: String,
+    pub rating_average: Option<f64>,
+    pub rating_count: u64,
+    pub reviews: Vec<Review>,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    run(|req| respond(scope(req, handler))).await
+}
+
+/// `GET` lists published reviews; `PUT` creates or replaces the caller's
+/// review and `DELETE` removes it
+pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
+    let method = req.method().as_str().to_string();
+    if method != "GET" && method != "PUT" && method != "DELETE" {
+        return error_response(
+            405,
+            "method_not_allowed",
+            "Only GET, PUT and DELETE requests are allowed".to_string(),
+        );
+    }
+
+    if method != "GET" {
+        if let Err(response) = ensure_writable().await {
+            return Ok(response);
+        }
+    }
+
+    // Expected format: api/v1/agents/{name}/reviews
+    let path_segments: Vec<&str> = req
+        .uri()
+        .path()
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .collect();
+
+    if path_segments.len() < 5 {
+        return error_response(
+            400,
+            "bad_request",
+            "Invalid path format. Expected /api/v1/agents/{name}/reviews".to_string(),
+        );
+    }
+
+    let agent_name = urlencoding::decode(path_segments[3])
+        .map_err(|_| Error::from("Invalid agent name encoding"))?
+        .into_owned();
+
+    if method == "GET" {
+        let query = req.uri().query().unwrap_or("");
+        let params: HashMap<String, String> = url::form_urlencoded::parse(query.as_bytes())
+            .into_owned()
+            .collect();
+        let limit = params
+            .get("limit")
+            .and_then(|s| s.parse::<usize>().ok())
+            .unwrap_or(DEFAULT_LIMIT)
+            .clamp(1, MAX_LIMIT);
+
+        let Some(agent) = find_agent(&agent_name).await? else {
+            return not_found(&agent_name);
+        };
+        let reviews = list_reviews(&agent, limit).await?;
+        let response = ReviewsResponse {
+            name: agent.name,
+            rating_average: agent.rating_average,
+            rating_count: agent.rating_count,
+            reviews,
+        };
+        return Ok(Response::builder()
+            .status(200)
+            .header("content-type", "application/json")
+            .header("Cache-Control", "public, max-age=30")
+            .body(serde_json::to_string(&response)?.into())?);
+    }
+
+    let authenticated_user = match api_key_middleware(&req).await {
+        Ok(user) => user,
+        Err(error_response) => return Ok(error_response),
+    };
+
+    if let Err(error_response) = require_scope(&authenticated_user, "write") {
+        return Ok(error_response);
+    }
+
+    if let Err(response) =
+        ensure_enabled(Flag::Reviews, &authenticated_user.user_id.to_string()).await
+    {
+        return Ok(response);
+    }
+
+    let Some(agent) = find_agent(&agent_name).await? else {
+        return not_found(&agent_name);
+    };
+
+    if method == "DELETE" {
+        return if delete_review(&agent, &authenticated_user).await? {
+            Ok(Response::builder().status(204).body(Body::Empty)?)
+        } else {
+            error_response(
+                404,
+                "not_found",
+                format!("You haven't reviewed '{agent_name}'"),
+            )
+        };
+    }
+
+    let request: ReviewRequest = match serde_json::from_slice(req.body()) {
+        Ok(request) => request,
+        Err(e) => {
+            return error_response(
+                400,
+                "bad_request",
+                format!("Invalid JSON in request body: {e}"),
+            )
+        }
+    };
+
+    if let Err(message) = validate_review(&request) {
+        return error_response(400, "validation_failed", message);
+    }
+
+    let review = save_review(&agent, &authenticated_user, &request).await?;
+
+    Ok(Response::builder()
+        .status(200)
+        .header("content-type", "application/json")
+        .header("Cache-Control", "no-store")
+        .body(serde_json::to_string(&review)?.into())?)
+}
+
+fn validate_review(request: &ReviewRequest) -> Result<(), String> {
+    if !(1..=5).contains(&request.rating) {
+        return Err("Rating must be between 1 and 5".to_string());
+    }
+    if request.body.chars().count() > MAX_BODY_LENGTH {
+        return Err(format!(
+            "Review text cannot exceed {MAX_BODY_LENGTH} characters"
+        ));
+    }
+    Ok(())
+}
+
+fn database_config() -> Result<(String, String), Error> {
+    let supabase_url = env::var("SUPABASE_URL").unwrap_or_default();
+    let supabase_key = env::var("SUPABASE_SERVICE_ROLE_KEY").unwrap_or_default();
+
+    if supabase_url.is_empty() || supabase_key.is_empty() {
+        return Err(Error::from(
+            "Database not configured - missing SUPABASE_URL or SUPABASE_SERVICE_ROLE_KEY",
+        ));
+    }
+
+    Ok((supabase_url, supabase_key))
+}
+
+/// Only public agents can be reviewed
+async fn find_agent(name: &str) -> Result<Option<DbAgent>, Error> {
+    let (supabase_url, supabase_key) = database_config()?;
+
+    let response = tenant::http_client()
+        .get(format!(
+            "{supabase_url}/rest/v1/agents?name=eq.{}&tenant_id=eq.{}&is_public=eq.true&select=id,name,rating_count,rating_average&limit=1",
+            urlencoding::encode(name),
+            tenant::current().id
+        ))
+        .header("apikey", &supabase_key)
+        .header("Authorization", format!("Bearer {supabase_key}"))
+        .send()
+        .await
+        .map_err(|e| Error::from(format!("Database query failed: {e}")))?;
+
+    let body = checked_body(response).await?;
+    let agents: Vec<DbAgent> = serde_json::from_str(&body)
+        .map_err(|e| Error::from(format!("Failed to parse agent: {e}")))?;
+
+    Ok(agents.into_iter().next())
+}
+
+async fn list_reviews(agent: &DbAgent, limit: usize) -> Result<Vec<Review>, Error> {
+    let (supabase_url, supabase_key) = database_config()?;
+
+    let response = tenant::http_client()
+        .get(format!(
+            "{supabase_url}/rest/v1/agent_reviews?agent_id=eq.{}&status=eq.{}&select={REVIEW_COLUMNS}&order=updated_at.desc&limit={limit}",
+            agent.id,
+            ModerationStatus::Published.as_str()
+        ))
+        .header("apikey", &supabase_key)
+        .header("Authorization", format!("Bearer {supabase_key}"))
+        .send()
+        .await
+        .map_err(|e| Error::from(format!("Database query failed: {e}")))?;
+
+    let body = checked_body(response).await?;
+    serde_json::from_str(&body).map_err(|e| Error::from(format!("Failed to parse reviews: {e}")))
+}
+
+/// Create or replace the user's review, screening its text first
+async fn save_review(
+    agent: &DbAgent,
+    user: &AuthenticatedUser,
+    request: &ReviewRequest,
+) -> Result<Review, Error> {
+    let (supabase_url, supabase_key) = database_config()?;
+
+    let screening = screen_text(&request.body);
+    let moderation_reason = match &screening {
+        Screening::Allow => None,
+        Screening::Hold(reason) => Some(reason.clone()),
+    };
+
+    let row = json!({
+        "agent_id": agent.id,
+        "user_id": user.user_id,
+        "author_name": user.metadata.github_username.as_deref().unwrap_or("Unknown"),
+        "rating": request.rating,
+        "body": request.body.trim(),
+        "status": screening.status().as_str(),
+        "moderation_reason": moderation_reason,
+    });
+
+    let response = tenant::http_client()
+        .post(format!(
+            "{supabase_url}/rest/v1/agent_reviews?on_conflict=agent_id,user_id&select={REVIEW_COLUMNS}"
+        ))
+        .header("apikey", &supabase_key)
+        .header("Authorization", format!("Bearer {supabase_key}"))
+        .header("Prefer", "resolution=merge-duplicates,return=representation")
+        .json(&row)
+        .send()
+        .await
+        .map_err(|e| Error::from(format!("Database insert failed: {e}")))?;
+
+    let body = checked_body(response).await?;
+    let reviews: Vec<Review> = serde_json::from_str(&body)
+        .map_err(|e| Error::from(format!("Failed to parse review: {e}")))?;
+
+    reviews
+        .into_iter()
+        .next()
+        .ok_or_else(|| Error::from("Database returned no review"))
+}
+
+/// Delete the user's review, returning whether there was one
+async fn delete_review(agent: &DbAgent, user: &AuthenticatedUser) -> Result<bool, Error> {
+    let (supabase_url, supabase_key) = database_config()?;
+
+    let response = tenant::http_client()
+        .delete(format!(
+            "{supabase_url}/rest/v1/agent_reviews?agent_id=eq.{}&user_id=eq.{}&select=rating",
+            agent.id, user.user_id
+        ))
+        .header("apikey", &supabase_key)
+        .header("Authorization", format!("Bearer {supabase_key}"))
+        .header("Prefer", "return=representation")
+        .send()
+        .await
+        .map_err(|e| Error::from(format!("Database delete failed: {e}")))?;
+
+    let body = checked_body(response).await?;
+    let deleted: Vec<serde_json::Value> = serde_json::from_str(&body)
+        .map_err(|e| Error::from(format!("Failed to parse response: {e}")))?;
+
+    Ok(!deleted.is_empty())
+}
+
+async fn checked_body(response: reqwest::Response) -> Result<String, Error> {
+    let status = response.status();
+    let body = response
+        .text()
+        .await
+        .map_err(|e| Error::from(format!("Failed to read response: {e}")))?;
+
+    if !status.is_success() {
+        return Err(Error::from(format!(
+            "Database request failed with status {status}: {body}"
+        )));
+    }
+
+    Ok(body)
+}
+
+fn not_found(name: &str) -> Result<Response<Body>, Error> {
+    error_response(404, "not_found", format!("Agent '{name}' not found"))
+}
+
+fn error_response(status: u16, error: &str, message: String) -> Result<Response<Body>, Error> {
+    let error = ApiError {
+        error: error.to_string(),
+        message,
+        details: None,
+    };
+
+    let mut builder = Response::builder()
+        .status(status)
+        .header("content-type", "application/json");
+    if status == 405 {
+        builder = builder.header("allow", "GET, PUT, DELETE");
+    }
+
+    Ok(builder.body(serde_json::to_string(&error)?.into())?)
+}
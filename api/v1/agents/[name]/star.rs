@@ -0,0 +1,210 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::env;
+use uuid::Uuid;
+use vercel_runtime::{run, Body, Error, Request, Response};
+
+use shared::problem::respond;
+use shared::tenant::{self, scope};
+use shared::{api_key_middleware, ensure_writable, require_scope, ApiError, AuthenticatedUser};
+
+/// The starred agent's row
+#[derive(Debug, Deserialize)]
+struct DbAgent {
+    id: Uuid,
+    name: String,
+    star_count: u64,
+}
+
+/// Result of starring or unstarring an agent
+#[derive(Debug, Serialize)]
+pub struct StarResponse {
+    pub name: String,
+    pub starred: bool,
+    pub star_count: u64,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    run(|req| respond(scope(req, handler))).await
+}
+
+/// `PUT` stars an agent for the caller and `DELETE` removes the star. Both
+/// are idempotent, so starring twice is not an error.
+pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
+    let method = req.method().as_str().to_string();
+    if method != "PUT" && method != "DELETE" {
+        return error_response(
+            405,
+            "method_not_allowed",
+            "Only PUT and DELETE requests are allowed".to_string(),
+        );
+    }
+
+    if let Err(response) = ensure_writable().await {
+        return Ok(response);
+    }
+
+    let authenticated_user = match api_key_middleware(&req).await {
+        Ok(user) => user,
+        Err(error_response) => return Ok(error_response),
+    };
+
+    if let Err(error_response) = require_scope(&authenticated_user, "write") {
+        return Ok(error_response);
+    }
+
+    // Expected format: api/v1/agents/{name}/star
+    let path_segments: Vec<&str> = req
+        .uri()
+        .path()
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .collect();
+
+    if path_segments.len() < 5 {
+        return error_response(
+            400,
+            "bad_request",
+            "Invalid path format. Expected /api/v1/agents/{name}/star".to_string(),
+        );
+    }
+
+    let agent_name = urlencoding::decode(path_segments[3])
+        .map_err(|_| Error::from("Invalid agent name encoding"))?
+        .into_owned();
+
+    let Some(agent) = find_agent(&agent_name).await? else {
+        return error_response(404, "not_found", format!("Agent '{agent_name}' not found"));
+    };
+
+    let starred = method == "PUT";
+    if starred {
+        add_star(&agent, &authenticated_user).await?;
+    } else {
+        remove_star(&agent, &authenticated_user).await?;
+    }
+
+    // Re-read so the count includes the trigger's update
+    let star_count = find_agent(&agent_name)
+        .await?
+        .map_or(agent.star_count, |agent| agent.star_count);
+
+    let response = StarResponse {
+        name: agent.name,
+        starred,
+        star_count,
+    };
+
+    Ok(Response::builder()
+        .status(200)
+        .header("content-type", "application/json")
+        .header("Cache-Control", "no-store")
+        .body(serde_json::to_string(&response)?.into())?)
+}
+
+fn database_config() -> Result<(String, String), Error> {
+    let supabase_url = env::var("SUPABASE_URL").unwrap_or_default();
+    let supabase_key = env::var("SUPABASE_SERVICE_ROLE_KEY").unwrap_or_default();
+
+    if supabase_url.is_empty() || supabase_key.is_empty() {
+        return Err(Error::from(
+            "Database not configured - missing SUPABASE_URL or SUPABASE_SERVICE_ROLE_KEY",
+        ));
+    }
+
+    Ok((supabase_url, supabase_key))
+}
+
+/// Public agents can be starred; anything else is reported as not found
+async fn find_agent(name: &str) -> Result<Option<DbAgent>, Error> {
+    let (supabase_url, supabase_key) = database_config()?;
+
+    let response = tenant::http_client()
+        .get(format!(
+            "{supabase_url}/rest/v1/agents?name=eq.{}&tenant_id=eq.{}&is_public=eq.true&select=id,name,star_count&limit=1",
+            urlencoding::encode(name),
+            tenant::current().id
+        ))
+        .header("apikey", &supabase_key)
+        .header("Authorization", format!("Bearer {supabase_key}"))
+        .send()
+        .await
+        .map_err(|e| Error::from(format!("Database query failed: {e}")))?;
+
+    let body = checked_body(response).await?;
+    let agents: Vec<DbAgent> = serde_json::from_str(&body)
+        .map_err(|e| Error::from(format!("Failed to parse agent: {e}")))?;
+
+    Ok(agents.into_iter().next())
+}
+
+async fn add_star(agent: &DbAgent, user: &AuthenticatedUser) -> Result<(), Error> {
+    let (supabase_url, supabase_key) = database_config()?;
+
+    let response = tenant::http_client()
+        .post(format!(
+            "{supabase_url}/rest/v1/agent_stars?on_conflict=user_id,agent_id"
+        ))
+        .header("apikey", &supabase_key)
+        .header("Authorization", format!("Bearer {supabase_key}"))
+        // Starring an already starred agent leaves the existing star alone
+        .header("Prefer", "resolution=ignore-duplicates,return=minimal")
+        .json(&json!({ "user_id": user.user_id, "agent_id": agent.id }))
+        .send()
+        .await
+        .map_err(|e| Error::from(format!("Database insert failed: {e}")))?;
+
+    checked_body(response).await.map(|_| ())
+}
+
+async fn remove_star(agent: &DbAgent, user: &AuthenticatedUser) -> Result<(), Error> {
+    let (supabase_url, supabase_key) = database_config()?;
+
+    let response = tenant::http_client()
+        .delete(format!(
+            "{supabase_url}/rest/v1/agent_stars?user_id=eq.{}&agent_id=eq.{}",
+            user.user_id, agent.id
+        ))
+        .header("apikey", &supabase_key)
+        .header("Authorization", format!("Bearer {supabase_key}"))
+        .header("Prefer", "return=minimal")
+        .send()
+        .await
+        .map_err(|e| Error::from(format!("Database delete failed: {e}")))?;
+
+    checked_body(response).await.map(|_| ())
+}
+
+async fn checked_body(response: reqwest::Response) -> Result<String, Error> {
+    let status = response.status();
+    let body = response
+        .text()
+        .await
+        .map_err(|e| Error::from(format!("Failed to read response: {e}")))?;
+
+    if !status.is_success() {
+        return Err(Error::from(format!(
+            "Database request failed with status {status}: {body}"
+        )));
+    }
+
+    Ok(body)
+}
+
+fn error_response(status: u16, error: &str, message: String) -> Result<Response<Body>, Error> {
+    let error = ApiError {
+        error: error.to_string(),
+        message,
+        details: None,
+    };
+
+    let mut builder = Response::builder()
+        .status(status)
+        .header("content-type", "application/json");
+    if status == 405 {
+        builder = builder.header("allow", "PUT, DELETE");
+    }
+
+    Ok(builder.body(serde_json::to_string(&error)?.into())?)
+}
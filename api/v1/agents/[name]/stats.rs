@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+use vercel_runtime::{run, Body, Error, Request, Response};
+
+use shared::problem::respond;
+use shared::registry::stats::{get_agent_stats, DEFAULT_DAYS};
+use shared::tenant::scope;
+use shared::{optional_api_key_middleware, AgentVisibility, ApiError};
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    run(|req| respond(scope(req, handler))).await
+}
+
+/// Daily and weekly download totals for an agent over the last `days` days
+/// (default 30, at most 365)
+pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
+    if req.method() != "GET" {
+        let error = ApiError {
+            error: "method_not_allowed".to_string(),
+            message: "Only GET requests are allowed".to_string(),
+            details: None,
+        };
+        return Ok(Response::builder()
+            .status(405)
+            .header("content-type", "application/json")
+            .header("allow", "GET")
+            .body(serde_json::to_string(&error)?.into())?);
+    }
+
+    // Expected format: api/v1/agents/{name}/stats
+    let path_segments: Vec<&str> = req
+        .uri()
+        .path()
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .collect();
+
+    if path_segments.len() < 5 {
+        let error = ApiError {
+            error: "bad_request".to_string(),
+            message: "Invalid path format. Expected /api/v1/agents/{name}/stats".to_string(),
+            details: None,
+        };
+        return Ok(Response::builder()
+            .status(400)
+            .header("content-type", "application/json")
+            .body(serde_json::to_string(&error)?.into())?);
+    }
+
+    let agent_name = urlencoding::decode(path_segments[3])
+        .map_err(|_| Error::from("Invalid agent name encoding"))?;
+
+    let query = req.uri().query().unwrap_or("");
+    let params: HashMap<String, String> = url::form_urlencoded::parse(query.as_bytes())
+        .into_owned()
+        .collect();
+    let days = params
+        .get("days")
+        .and_then(|s| s.parse::<u32>().ok())
+        .unwrap_or(DEFAULT_DAYS);
+
+    // Owners can see their private agents; to anyone else they don't exist
+    let authenticated_user = optional_api_key_middleware(&req).await;
+    let visibility = AgentVisibility::for_user(authenticated_user.as_ref())
+        .await
+        .map_err(Error::from)?;
+
+    match get_agent_stats(&agent_name, &visibility, days)
+        .await
+        .map_err(Error::from)?
+    {
+        Some(stats) => Ok(Response::builder()
+            .status(200)
+            .header("content-type", "application/json")
+            // Rollups only change every few minutes
+            .header(
+                "Cache-Control",
+                if stats.is_public {
+                    "public, max-age=300"
+                } else {
+                    "private, no-store"
+                },
+            )
+            .header("Vary", "Authorization")
+            .body(serde_json::to_string(&stats)?.into())?),
+        None => {
+            let error = ApiError {
+                error: "not_found".to_string(),
+                message: format!("Agent '{agent_name}' not found"),
+                details: None,
+            };
+            Ok(Response::builder()
+                .status(404)
+                .header("content-type", "application/json")
+                .body(serde_json::to_string(&error)?.into())?)
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::env;
+use vercel_runtime::{run, Body, Error, Request, Response};
+
+use shared::problem::respond;
+use shared::tenant::{self, scope};
+use shared::{optional_api_key_middleware, AgentVisibility, ApiError};
+
+/// Database agent row, only what's needed to find its versions
+#[derive(Debug, Deserialize)]
+struct DbAgent {
+    pub id: String,
+    pub name: String,
+    pub is_public: bool,
+}
+
+/// Database agent_versions row
+#[derive(Debug, Deserialize)]
+struct DbVersion {
+    pub version: String,
+    pub checksum: Option<String>,
+    pub package_size: Option<u64>,
+    pub yanked: Option<bool>,
+    pub yanked_reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// One published version of an agent
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AgentVersion {
+    pub version: String,
+    pub checksum: Option<String>,
+    pub file_size: u64,
+    pub yanked: bool,
+    pub yanked_reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<DbVersion> for AgentVersion {
+    fn from(db_version: DbVersion) -> Self {
+        AgentVersion {
+            version: db_version.version,
+            checksum: db_version.checksum,
+            file_size: db_version.package_size.unwrap_or(0),
+            yanked: db_version.yanked.unwrap_or(false),
+            yanked_reason: db_version.yanked_reason,
+            created_at: db_version.created_at,
+        }
+    }
+}
+
+/// Every version of an agent, yanked ones included
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AgentVersionsResponse {
+    pub name: String,
+    /// Oldest first
+    pub versions: Vec<AgentVersion>,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    run(|req| respond(scope(req, handler))).await
+}
+
+pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
+    if req.method() != "GET" {
+        let error = ApiError {
+            error: "method_not_allowed".to_string(),
+            message: "Only GET requests are allowed".to_string(),
+            details: None,
+        };
+        return Ok(Response::builder()
+            .status(405)
+            .header("content-type", "application/json")
+            .header("allow", "GET")
+            .body(serde_json::to_string(&error)?.into())?);
+    }
+
+    // Expected format: api/v1/agents/{name}/versions
+    let path_segments: Vec<&str> = req
+        .uri()
+        .path()
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .collect();
+
+    if path_segments.len() < 5 {
+        let error = ApiError {
+            error: "bad_request".to_string(),
+            message: "Invalid path format. Expected /api/v1/agents/{name}/versions".to_string(),
+            details: None,
+        };
+        return Ok(Response::builder()
+            .status(400)
+            .header("content-type", "application/json")
+            .body(serde_json::to_string(&error)?.into())?);
+    }
+
+    let agent_name = urlencoding::decode(path_segments[3])
+        .map_err(|_| Error::from("Invalid agent name encoding"))?;
+
+    // Owners can see their private agents; to anyone else they don't exist
+    let authenticated_user = optional_api_key_middleware(&req).await;
+    let visibility = AgentVisibility::for_user(authenticated_user.as_ref())
+        .await
+        .map_err(Error::from)?;
+
+    match get_agent_versions(&agent_name, &visibility).await? {
+        Some((versions, is_public)) => Ok(Response::builder()
+            .status(200)
+            .header("content-type", "application/json")
+            .header(
+                "Cache-Control",
+                if is_public {
+                    "public, max-age=30"
+                } else {
+                    "private, no-store"
+                },
+            )
+            .header("Vary", "Authorization")
+            .body(serde_json::to_string(&versions)?.into())?),
+        None => {
+            let error = ApiError {
+                error: "not_found".to_string(),
+                message: format!("Agent '{agent_name}' not found"),
+                details: None,
+            };
+            Ok(Response::builder()
+                .status(404)
+                .header("content-type", "application/json")
+                .body(serde_json::to_string(&error)?.into())?)
+        }
+    }
+}
+
+/// The agent's versions, and whether the agent is public
+async fn get_agent_versions(
+    name: &str,
+    visibility: &AgentVisibility,
+) -> Result<Option<(AgentVersionsResponse, bool)>, Error> {
+    let supabase_url = env::var("SUPABASE_URL").unwrap_or_default();
+    // As for agent info, owners of private agents are served with the service role
+    let supabase_key = if visibility.includes_private() {
+        env::var("SUPABASE_SERVICE_ROLE_KEY").unwrap_or_default()
+    } else {
+        env::var("SUPABASE_ANON_KEY")
+            .or_else(|_| env::var("SUPABASE_SERVICE_ROLE_KEY"))
+            .unwrap_or_default()
+    };
+
+    if supabase_url.is_empty() || supabase_key.is_empty() {
+        return Err(Error::from(
+            "Database not configured - missing SUPABASE_URL or SUPABASE_ANON_KEY",
+        ));
+    }
+
+    let mut client =
+        tenant::database(format!("{supabase_url}/rest/v1")).insert_header("apikey", &supabase_key);
+    if visibility.includes_private() {
+        client = client.insert_header("Authorization", format!("Bearer {supabase_key}"));
+    }
+
+    let query = client
+        .from("agents")
+        .select("id,name,is_public")
+        .eq("name", name);
+    let db_agents: Vec<DbAgent> = fetch(visibility.apply(query).limit(1)).await?;
+    let Some(db_agent) = db_agents.into_iter().next() else {
+        return Ok(None);
+    };
+
+    // Versions recorded without a stored package were never downloadable
+    let query = client
+        .from("agent_versions")
+        .select("version,checksum,package_size,yanked,yanked_reason,created_at")
+        .eq("agent_id", &db_agent.id)
+        .not("is", "file_path", "null")
+        .order("created_at.asc");
+    let db_versions: Vec<DbVersion> = fetch(query).await?;
+
+    Ok(Some((
+        AgentVersionsResponse {
+            name: db_agent.name,
+            versions: db_versions.into_iter().map(AgentVersion::from).collect(),
+        },
+        db_agent.is_public,
+    )))
+}
+
+async fn fetch<T: serde::de::DeserializeOwned>(query: postgrest::Builder) -> Result<T, Error> {
+    let response = query
+        .execute()
+        .await
+        .map_err(|e| Error::from(format!("Database query failed: {e}")))?;
+
+    let body = response
+        .text()
+        .await
+        .map_err(|e| Error::from(format!("Failed to read response: {e}")))?;
+
+    serde_json::from_str(&body).map_err(|e| Error::from(format!("Failed to parse response: {e}")))
+}
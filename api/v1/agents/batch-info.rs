@@ -0,0 +1,85 @@
+use serde::Deserialize;
+use std::collections::HashSet;
+use vercel_runtime::{run, Body, Error, Request, Response};
+
+use shared::problem::respond;
+use shared::registry::info::get_agents_info;
+use shared::tenant::scope;
+use shared::{optional_api_key_middleware, AgentVisibility, ApiError, Validate, ValidatedJson};
+
+/// Most agents that can be looked up in one request
+const MAX_BATCH_SIZE: usize = 100;
+
+/// Agents to look up
+#[derive(Debug, Deserialize)]
+pub struct BatchInfoRequest {
+    pub names: Vec<String>,
+}
+
+impl BatchInfoRequest {
+    /// Each name once, trimmed, keeping the order of first mention
+    fn distinct_names(&self) -> Vec<String> {
+        let mut seen = HashSet::new();
+        self.names
+            .iter()
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty() && seen.insert(name.clone()))
+            .collect()
+    }
+}
+
+impl Validate for BatchInfoRequest {
+    fn validate(&self) -> Vec<(String, String)> {
+        let count = self.distinct_names().len();
+        let message = if count == 0 {
+            "names must list at least one agent".to_string()
+        } else if count > MAX_BATCH_SIZE {
+            format!("At most {MAX_BATCH_SIZE} agents can be looked up at once")
+        } else {
+            return Vec::new();
+        };
+        vec![("names".to_string(), message)]
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    run(|req| respond(scope(req, handler))).await
+}
+
+pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
+    if req.method() != "POST" {
+        let error = ApiError {
+            error: "method_not_allowed".to_string(),
+            message: "Only POST requests are allowed".to_string(),
+            details: None,
+        };
+        return Ok(Response::builder()
+            .status(405)
+            .header("content-type", "application/json")
+            .header("allow", "POST")
+            .body(serde_json::to_string(&error)?.into())?);
+    }
+
+    let ValidatedJson(request) = match ValidatedJson::<BatchInfoRequest>::from_request(&req) {
+        Ok(request) => request,
+        Err(response) => return Ok(response),
+    };
+    let names = request.distinct_names();
+
+    // As for agent info, private agents are only returned to those who may see them
+    let authenticated_user = optional_api_key_middleware(&req).await;
+    let visibility = AgentVisibility::for_user(authenticated_user.as_ref())
+        .await
+        .map_err(Error::from)?;
+
+    let response_body = get_agents_info(&names, &visibility)
+        .await
+        .map_err(Error::from)?;
+
+    Ok(Response::builder()
+        .status(200)
+        .header("content-type", "application/json")
+        .header("Cache-Control", "private, no-store")
+        .body(serde_json::to_string(&response_body)?.into())?)
+}
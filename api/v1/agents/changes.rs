@@ -0,0 +1,66 @@
+use serde::Deserialize;
+use vercel_runtime::{run, Body, Error, Request, Response};
+
+use shared::problem::respond;
+use shared::registry::changes::{changes, parse_cursor, DEFAULT_LIMIT, MAX_LIMIT};
+use shared::tenant::scope;
+use shared::{Validate, ValidatedQuery};
+
+/// Query string of a change feed request
+#[derive(Debug, Deserialize)]
+pub struct ChangesParams {
+    /// Cursor from the previous page; the whole feed without it
+    pub since: Option<String>,
+    pub limit: Option<usize>,
+}
+
+impl Validate for ChangesParams {
+    fn validate(&self) -> Vec<(String, String)> {
+        let mut errors = Vec::new();
+        if self
+            .since
+            .as_deref()
+            .is_some_and(|since| parse_cursor(since).is_none())
+        {
+            errors.push((
+                "since".to_string(),
+                "since must be a cursor returned by this endpoint".to_string(),
+            ));
+        }
+        if self
+            .limit
+            .is_some_and(|limit| limit == 0 || limit > MAX_LIMIT)
+        {
+            errors.push((
+                "limit".to_string(),
+                format!("limit must be between 1 and {MAX_LIMIT}"),
+            ));
+        }
+        errors
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    run(|req| respond(scope(req, handler))).await
+}
+
+/// The latest change to each public agent since a cursor, for indexes,
+/// mirrors and replicas keeping a copy of the listing current
+pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
+    let ValidatedQuery(params) = match ValidatedQuery::<ChangesParams>::from_request(&req) {
+        Ok(params) => params,
+        Err(response) => return Ok(response),
+    };
+    // Checked by `ChangesParams::validate`
+    let since = params.since.as_deref().and_then(parse_cursor).unwrap_or(0);
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT);
+
+    let page = changes(since, limit).await.map_err(Error::from)?;
+
+    Ok(Response::builder()
+        .status(200)
+        .header("content-type", "application/json")
+        .header("Cache-Control", "public, max-age=30")
+        .body(serde_json::to_string(&page)?.into())?)
+}
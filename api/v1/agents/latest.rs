@@ -1,5 +1,7 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use shared::problem::respond;
+use shared::tenant::{self, scope};
 use std::env;
 use vercel_runtime::{run, Body, Error, Request, Response};
 
@@ -48,7 +50,7 @@ pub struct LatestAgentsResponse {
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    run(handler).await
+    run(|req| respond(scope(req, handler))).await
 }
 
 pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
@@ -59,7 +61,7 @@ pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
             .header("Access-Control-Allow-Origin", "*")
             .header("Access-Control-Allow-Methods", "GET, OPTIONS")
             .header("Access-Control-Allow-Headers", "Content-Type")
-            .body(Body::Empty)?)
+            .body(Body::Empty)?);
     }
 
     // Parse limit parameter (default 10, max 50)
@@ -104,7 +106,10 @@ async fn get_latest_agents(limit: usize) -> Result<Vec<Agent>, Error> {
 
     eprintln!("[DEBUG] SUPABASE_URL present: {}", !supabase_url.is_empty());
     eprintln!("[DEBUG] SUPABASE_KEY present: {}", !supabase_key.is_empty());
-    eprintln!("[DEBUG] URL prefix: {}", supabase_url.chars().take(30).collect::<String>());
+    eprintln!(
+        "[DEBUG] URL prefix: {}",
+        supabase_url.chars().take(30).collect::<String>()
+    );
 
     if supabase_url.is_empty() || supabase_key.is_empty() {
         eprintln!("[ERROR] Database not configured - missing environment variables");
@@ -113,14 +118,17 @@ async fn get_latest_agents(limit: usize) -> Result<Vec<Agent>, Error> {
         ));
     }
 
-    let client = postgrest::Postgrest::new(format!("{supabase_url}/rest/v1"))
+    let client = tenant::database(format!("{supabase_url}/rest/v1"))
         .insert_header("apikey", &supabase_key)
         .insert_header("Authorization", format!("Bearer {}", &supabase_key));
 
     // Optimized query: Only fetch what we need, use existing optimal index
     // Handle potential missing fields gracefully
-    eprintln!("[DEBUG] Executing query on agents table with limit: {}", limit);
-    
+    eprintln!(
+        "[DEBUG] Executing query on agents table with limit: {}",
+        limit
+    );
+
     // First try a simple query to verify connection
     let test_response = client
         .from("agents")
@@ -128,7 +136,7 @@ async fn get_latest_agents(limit: usize) -> Result<Vec<Agent>, Error> {
         .limit(1)
         .execute()
         .await;
-    
+
     match test_response {
         Ok(resp) => {
             eprintln!("[DEBUG] Test query status: {}", resp.status());
@@ -137,10 +145,12 @@ async fn get_latest_agents(limit: usize) -> Result<Vec<Agent>, Error> {
         }
         Err(e) => eprintln!("[ERROR] Test query failed: {}", e),
     }
-    
+
     let response = client
         .from("agents")
         .select("id,name,description,created_at,updated_at,tags,author_name,current_version,download_count,view_count,definition,user_id")
+        .eq("tenant_id", tenant::current().id)
+        .eq("is_public", "true")
         .order("created_at.desc") // Uses idx_agents_public_created index
         .limit(limit)
         .execute()
@@ -173,7 +183,10 @@ async fn get_latest_agents(limit: usize) -> Result<Vec<Agent>, Error> {
     }
 
     eprintln!("[DEBUG] Response body length: {}", body.len());
-    eprintln!("[DEBUG] Response preview: {}", body.chars().take(200).collect::<String>());
+    eprintln!(
+        "[DEBUG] Response preview: {}",
+        body.chars().take(200).collect::<String>()
+    );
 
     let mut agents: Vec<Agent> = serde_json::from_str(&body).map_err(|e| {
         eprintln!("[ERROR] Failed to parse agents response: {}", body);
@@ -182,36 +195,36 @@ async fn get_latest_agents(limit: usize) -> Result<Vec<Agent>, Error> {
     })?;
 
     eprintln!("[DEBUG] Successfully parsed {} agents", agents.len());
-    
+
     // Fetch profiles for the agents
     if !agents.is_empty() {
         let user_ids: Vec<String> = agents.iter().map(|a| a.user_id.clone()).collect();
-        
+
         eprintln!("[DEBUG] Fetching profiles for {} users", user_ids.len());
-        
+
         let profile_response = client
             .from("profiles")
             .select("user_id,github_username,display_name,avatar_url")
             .in_("user_id", &user_ids)
             .execute()
             .await?;
-            
+
         if profile_response.status().is_success() {
             let profile_body = profile_response.text().await?;
-            
+
             if !profile_body.is_empty() && profile_body != "[]" {
                 let profiles: Vec<Profile> = serde_json::from_str(&profile_body).map_err(|e| {
                     eprintln!("[ERROR] Failed to parse profiles: {}", e);
                     Error::from(format!("Failed to parse profiles: {e}"))
                 })?;
-                
+
                 // Create a map for quick lookup
                 use std::collections::HashMap;
                 let profile_map: HashMap<String, Profile> = profiles
                     .into_iter()
                     .map(|p| (p.user_id.clone(), p))
                     .collect();
-                
+
                 // Attach profiles to agents
                 for agent in &mut agents {
                     if let Some(profile) = profile_map.get(&agent.user_id) {
@@ -221,6 +234,6 @@ async fn get_latest_agents(limit: usize) -> Result<Vec<Agent>, Error> {
             }
         }
     }
-    
+
     Ok(agents)
 }
@@ -1,11 +1,16 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use serde_json::json;
 use std::env;
 use vercel_runtime::{run, Body, Error, Request, Response};
 
 // Use shared authentication module
-use shared::{api_key_middleware, require_scope, ApiError, AuthenticatedUser};
+use shared::problem::respond;
+use shared::tenant::scope;
+use shared::validation::{check_agent_name, check_text};
+use shared::{
+    api_key_middleware, claim_idempotency_key, ensure_enabled, ensure_writable, require_scope,
+    validate_package, ApiError, AuthenticatedUser, Flag, IdempotencyClaim, Validate, ValidatedJson,
+};
 
 /// Agent metadata returned by the API
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -37,6 +42,34 @@ pub struct PublishRequest {
     pub tags: Vec<String>,
 }
 
+impl Validate for PublishRequest {
+    fn validate(&self) -> Vec<(String, String)> {
+        let mut errors: Vec<_> = [
+            check_agent_name("name", &self.name),
+            check_text("description", &self.description, 1000),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        if semver::Version::parse(&self.version).is_err() {
+            errors.push((
+                "version".to_string(),
+                format!("'{}' is not a semantic version", self.version),
+            ));
+        }
+        for (index, tag) in self.tags.iter().enumerate() {
+            errors.extend(check_text(&format!("tags[{index}]"), tag, 50));
+        }
+        if self.tags.len() > 20 {
+            errors.push((
+                "tags".to_string(),
+                "Cannot have more than 20 tags".to_string(),
+            ));
+        }
+        errors
+    }
+}
+
 /// Response from publishing an agent
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PublishResponse {
@@ -49,10 +82,14 @@ pub struct PublishResponse {
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    run(handler).await
+    run(|req| respond(scope(req, handler))).await
 }
 
 pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
+    if let Err(response) = ensure_writable().await {
+        return Ok(response);
+    }
+
     // Authenticate the request using API key only
     let authenticated_user = match api_key_middleware(&req).await {
         Ok(user) => user,
@@ -64,6 +101,12 @@ pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
         return Ok(error_response);
     }
 
+    if let Err(response) =
+        ensure_enabled(Flag::Publish, &authenticated_user.user_id.to_string()).await
+    {
+        return Ok(response);
+    }
+
     let headers = req.headers();
 
     // Parse multipart form data
@@ -84,31 +127,70 @@ pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
             .body(serde_json::to_string(&error)?.into())?);
     }
 
-    // For simplicity, we'll mock the parsing of multipart data
-    // In production, you'd use a proper multipart parser
-    let mock_publish_request = PublishRequest {
-        name: "example-agent".to_string(),
-        version: "1.0.0".to_string(),
-        description: "An example agent".to_string(),
-        readme: Some("# Example Agent\n\nThis is an example.".to_string()),
-        homepage: None,
-        repository: None,
-        license: Some("MIT".to_string()),
-        tags: vec!["example".to_string()],
+    // The package must be a supported archive whose declared type matches its bytes
+    let boundary = content_type
+        .split(';')
+        .filter_map(|param| param.trim().strip_prefix("boundary="))
+        .next()
+        .map(|boundary| boundary.trim_matches('"'))
+        .unwrap_or("");
+
+    let package_check = match form_part(req.body(), boundary, "content") {
+        Some((part_type, data)) => validate_package(&part_type, data).map(|_| data),
+        None => Err("Request must include the package archive in a 'content' part".to_string()),
+    };
+
+    let package = match package_check {
+        Ok(package) => package,
+        Err(message) => {
+            let error = ApiError {
+                error: "invalid_package".to_string(),
+                message,
+                details: None,
+            };
+            return Ok(Response::builder()
+                .status(400)
+                .header("content-type", "application/json")
+                .body(serde_json::to_string(&error)?.into())?);
+        }
+    };
+
+    // Multipart boundaries change between attempts, so key on the package itself
+    let idempotency =
+        match claim_idempotency_key(&req, &authenticated_user, "publish", package).await {
+            Ok(IdempotencyClaim::Replay(response)) => return Ok(response),
+            Ok(claim) => claim,
+            Err(error_response) => return Ok(error_response),
+        };
+
+    let ValidatedJson(publish_request) = match form_part(req.body(), boundary, "metadata") {
+        Some((_, metadata)) => match ValidatedJson::<PublishRequest>::from_slice(metadata) {
+            Ok(request) => request,
+            Err(response) => return Ok(response),
+        },
+        None => {
+            let error = ApiError {
+                error: "bad_request".to_string(),
+                message: "Request must include the agent's metadata in a 'metadata' part"
+                    .to_string(),
+                details: None,
+            };
+            return Ok(Response::builder()
+                .status(400)
+                .header("content-type", "application/json")
+                .body(serde_json::to_string(&error)?.into())?);
+        }
     };
 
     // Process the publish request
-    match publish_agent(mock_publish_request, &authenticated_user).await {
+    let (status, body) = match publish_agent(publish_request, &authenticated_user).await {
         Ok(agent) => {
             let response = PublishResponse {
                 success: true,
                 message: "Agent published successfully".to_string(),
                 agent: Some(agent),
             };
-            Ok(Response::builder()
-                .status(201)
-                .header("content-type", "application/json")
-                .body(serde_json::to_string(&response)?.into())?)
+            (201, serde_json::to_string(&response)?)
         }
         Err(err_msg) => {
             let error = ApiError {
@@ -116,12 +198,59 @@ pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
                 message: err_msg,
                 details: None,
             };
-            Ok(Response::builder()
-                .status(400)
-                .header("content-type", "application/json")
-                .body(serde_json::to_string(&error)?.into())?)
+            (400, serde_json::to_string(&error)?)
+        }
+    };
+
+    idempotency.finish(status, &body).await;
+
+    Ok(Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(body.into())?)
+}
+
+/// Find the part called `name` in a multipart body, returning its declared
+/// content type and raw bytes
+fn form_part<'a>(body: &'a [u8], boundary: &str, name: &str) -> Option<(String, &'a [u8])> {
+    if boundary.is_empty() {
+        return None;
+    }
+
+    let delimiter = format!("--{boundary}");
+    let delimiter = delimiter.as_bytes();
+
+    let mut rest = body;
+    while let Some(start) = find(rest, delimiter) {
+        rest = &rest[start + delimiter.len()..];
+        let end = find(rest, delimiter).unwrap_or(rest.len());
+        let part = rest[..end].strip_prefix(b"\r\n").unwrap_or(&rest[..end]);
+
+        let Some(header_end) = find(part, b"\r\n\r\n") else {
+            continue;
+        };
+        let headers = String::from_utf8_lossy(&part[..header_end]).to_ascii_lowercase();
+
+        if headers.contains(&format!("name=\"{name}\"")) {
+            let content_type = headers
+                .lines()
+                .find_map(|line| line.strip_prefix("content-type:"))
+                .unwrap_or("application/octet-stream")
+                .trim()
+                .to_string();
+            let data = &part[header_end + 4..];
+            let data = data.strip_suffix(b"\r\n").unwrap_or(data);
+            return Some((content_type, data));
         }
     }
+
+    None
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
 }
 
 // JWT token validation removed - now using API key authentication
@@ -1,65 +1,10 @@
-use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::env;
 use vercel_runtime::{run, Body, Error, Request, Response};
 
-/// Database agent structure (matches actual DB schema)
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct DbAgent {
-    pub name: String,
-    #[serde(rename = "current_version")]
-    pub version: String,
-    pub description: String,
-    #[serde(rename = "author_name")]
-    pub author_name: Option<String>,
-    pub created_at: DateTime<Utc>,
-    pub updated_at: DateTime<Utc>,
-    pub download_count: u64,
-    pub tags: Option<Vec<String>>,
-    pub readme: Option<String>,
-    pub homepage: Option<String>,
-    pub repository: Option<String>,
-    pub license: Option<String>,
-}
-
-/// Agent metadata returned by the API (matches expected client schema)
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Agent {
-    pub name: String,
-    pub version: String,
-    pub description: String,
-    pub author: String,
-    pub created_at: DateTime<Utc>,
-    pub updated_at: DateTime<Utc>,
-    pub download_count: u64,
-    pub tags: Vec<String>,
-    pub readme: Option<String>,
-    pub homepage: Option<String>,
-    pub repository: Option<String>,
-    pub license: Option<String>,
-}
-
-impl From<DbAgent> for Agent {
-    fn from(db_agent: DbAgent) -> Self {
-        Agent {
-            name: db_agent.name,
-            version: db_agent.version,
-            description: db_agent.description,
-            author: db_agent
-                .author_name
-                .unwrap_or_else(|| "Unknown".to_string()),
-            created_at: db_agent.created_at,
-            updated_at: db_agent.updated_at,
-            download_count: db_agent.download_count,
-            tags: db_agent.tags.unwrap_or_else(Vec::new),
-            readme: db_agent.readme,
-            homepage: db_agent.homepage,
-            repository: db_agent.repository,
-            license: db_agent.license,
-        }
-    }
-}
+use shared::problem::respond;
+use shared::registry::search::{search, Agent, SearchError, SearchMode, SearchQuery, SearchSort};
+use shared::tenant::scope;
+use shared::{optional_api_key_middleware, AgentVisibility, ApiError, Validate, ValidatedQuery};
 
 /// Search results from the API
 #[derive(Debug, Serialize, Deserialize)]
@@ -70,32 +15,135 @@ pub struct SearchResponse {
     pub per_page: usize,
 }
 
+/// Most results one page may hold
+const MAX_LIMIT: usize = 1000;
+
+/// Query string of a search
+#[derive(Debug, Deserialize)]
+pub struct SearchParams {
+    pub q: Option<String>,
+    pub limit: Option<usize>,
+    pub page: Option<usize>,
+    pub mode: Option<String>,
+    /// Same as `mode=exact`, from before `mode`
+    pub exact: Option<String>,
+    pub sort: Option<String>,
+    pub mine: Option<String>,
+}
+
+impl Validate for SearchParams {
+    fn validate(&self) -> Vec<(String, String)> {
+        let mut errors = Vec::new();
+        if self.q.as_ref().is_some_and(|q| q.chars().count() > 200) {
+            errors.push((
+                "q".to_string(),
+                "Search queries cannot exceed 200 characters".to_string(),
+            ));
+        }
+        if self
+            .limit
+            .is_some_and(|limit| limit == 0 || limit > MAX_LIMIT)
+        {
+            errors.push((
+                "limit".to_string(),
+                format!("limit must be between 1 and {MAX_LIMIT}"),
+            ));
+        }
+        if self.page == Some(0) {
+            errors.push(("page".to_string(), "page starts at 1".to_string()));
+        }
+        if self
+            .mode
+            .as_deref()
+            .is_some_and(|mode| SearchMode::parse(mode).is_none())
+        {
+            errors.push((
+                "mode".to_string(),
+                "mode must be one of substring, exact, glob or regex".to_string(),
+            ));
+        }
+        if self
+            .sort
+            .as_deref()
+            .is_some_and(|sort| SearchSort::parse(sort).is_none())
+        {
+            errors.push((
+                "sort".to_string(),
+                "sort must be one of downloads, stars or recent".to_string(),
+            ));
+        }
+        errors
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    run(handler).await
+    run(|req| respond(scope(req, handler))).await
 }
 
 pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
-    // Extract query parameters for search functionality
-    let query = req.uri().query().unwrap_or("");
-    let search_params: HashMap<String, String> = url::form_urlencoded::parse(query.as_bytes())
-        .into_owned()
-        .collect();
+    // Signed-in callers also see their own private agents
+    let authenticated_user = optional_api_key_middleware(&req).await;
 
-    let search_query = search_params.get("q").map(|s| s.as_str()).unwrap_or("");
-    let limit = search_params
-        .get("limit")
-        .and_then(|s| s.parse::<usize>().ok())
-        .unwrap_or(20);
-    let page = search_params
-        .get("page")
-        .and_then(|s| s.parse::<usize>().ok())
-        .unwrap_or(1);
-    let exact = search_params.get("exact").is_some();
+    let ValidatedQuery(params) = match ValidatedQuery::<SearchParams>::from_request(&req) {
+        Ok(params) => params,
+        Err(response) => return Ok(response),
+    };
+    let search_query = params.q.unwrap_or_default();
+    let limit = params.limit.unwrap_or(20);
+    let page = params.page.unwrap_or(1);
+    // Both were checked by `SearchParams::validate`
+    let mode = match params.mode.as_deref() {
+        Some(mode) => SearchMode::parse(mode).unwrap_or(SearchMode::Substring),
+        // `exact` predates `mode` and is still accepted
+        None if params.exact.is_some() => SearchMode::Exact,
+        None => SearchMode::Substring,
+    };
+    let sort = params
+        .sort
+        .as_deref()
+        .and_then(SearchSort::parse)
+        .unwrap_or(SearchSort::Downloads);
+    // `mine=true` lists only the caller's agents, private ones included
+    let mine = params
+        .mine
+        .as_deref()
+        .is_some_and(|v| v == "true" || v == "1");
+    let visibility = match (&authenticated_user, mine) {
+        (Some(user), true) => AgentVisibility::Own(user.user_id),
+        (None, true) => {
+            let error = ApiError {
+                error: "authentication_required".to_string(),
+                message: "Listing your own agents requires a valid API key".to_string(),
+                details: None,
+            };
+            return Ok(Response::builder()
+                .status(401)
+                .header("content-type", "application/json")
+                .header("WWW-Authenticate", "Bearer")
+                .body(serde_json::to_string(&error)?.into())?);
+        }
+        (user, false) => AgentVisibility::for_user(user.as_ref())
+            .await
+            .map_err(Error::from)?,
+    };
 
     // Search agents in database
-    let agents = search_agents_in_db(search_query, limit, page, exact).await?;
-    let total = get_total_agent_count(search_query, exact).await?;
+    let search_query = SearchQuery {
+        query: &search_query,
+        mode,
+        sort,
+        limit,
+        page,
+        templates_only: false,
+    };
+    let (agents, total) = match search(&search_query, &visibility).await {
+        Ok(results) => results,
+        Err(SearchError::InvalidPattern(message)) => {
+            return bad_request("invalid_pattern", &message)
+        }
+        Err(SearchError::Internal(e)) => return Err(Error::from(e)),
+    };
 
     let response_body = SearchResponse {
         agents,
@@ -107,151 +155,22 @@ pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
     let response = Response::builder()
         .status(200)
         .header("content-type", "application/json")
-        .header("Cache-Control", "public, max-age=30") // Short cache for search results
+        // Short cache for search results, unless they may include private agents
+        .header("Cache-Control", visibility.cache_control())
+        .header("Vary", "Authorization")
         .body(serde_json::to_string(&response_body)?.into())?;
 
     Ok(response)
 }
 
-async fn search_agents_in_db(
-    query: &str,
-    limit: usize,
-    page: usize,
-    exact: bool,
-) -> Result<Vec<Agent>, Error> {
-    // Get database connection
-    let supabase_url = env::var("SUPABASE_URL").unwrap_or_default();
-    // For public search operations, use anon key for proper public access
-    let supabase_key = env::var("SUPABASE_ANON_KEY")
-        .or_else(|_| env::var("SUPABASE_SERVICE_ROLE_KEY"))
-        .unwrap_or_default();
-
-    if supabase_url.is_empty() || supabase_key.is_empty() {
-        return Err(Error::from(
-            "Database not configured - missing SUPABASE_URL or SUPABASE_ANON_KEY",
-        ));
-    }
-
-    // Create Supabase client for public read access (search endpoint should be public)
-    // Use only apikey header, no Authorization Bearer token needed for public reads
-    let client = postgrest::Postgrest::new(format!("{supabase_url}/rest/v1"))
-        .insert_header("apikey", &supabase_key);
-
-    // Calculate offset for pagination
-    let offset = (page - 1) * limit;
-
-    // Build query based on search parameters
-    // Note: Using actual database column names
-    let mut query_builder = client
-        .from("agents")
-        .select("name,current_version,description,author_name,created_at,updated_at,download_count,tags,readme,homepage,repository,license");
-
-    // Apply search filter if query is provided
-    if !query.is_empty() {
-        if exact {
-            // Exact match on name
-            query_builder = query_builder.eq("name", query);
-        } else {
-            // Use full-text search with existing GIN index for better performance
-            // Falls back to ILIKE if FTS doesn't work
-            query_builder = query_builder
-                .or(format!(
-                    "name.ilike.*{query}*,description.ilike.*{query}*,author_name.ilike.*{query}*,tags.cs.{{{query}}}"
-                ));
-        }
-    }
-
-    // Apply constraints for public agents and optimize ordering
-    query_builder = query_builder
-        .eq("is_public", "true")
-        .range(offset, offset + limit - 1)
-        .order("download_count.desc,updated_at.desc"); // Uses idx_agents_public_downloads index
-
-    // Execute query
-    let response = query_builder
-        .execute()
-        .await
-        .map_err(|e| Error::from(format!("Database query failed: {e}")))?;
-
-    let body = response
-        .text()
-        .await
-        .map_err(|e| Error::from(format!("Failed to read response: {e}")))?;
-
-    // Parse response as Vec<DbAgent> then convert to Vec<Agent>
-    let db_agents: Vec<DbAgent> = serde_json::from_str(&body)
-        .map_err(|e| Error::from(format!("Failed to parse agents: {e}")))?;
-
-    let agents: Vec<Agent> = db_agents.into_iter().map(Agent::from).collect();
-
-    Ok(agents)
-}
-
-async fn get_total_agent_count(query: &str, exact: bool) -> Result<usize, Error> {
-    // Get database connection
-    let supabase_url = env::var("SUPABASE_URL").unwrap_or_default();
-    // For public search operations, use anon key for proper public access
-    let supabase_key = env::var("SUPABASE_ANON_KEY")
-        .or_else(|_| env::var("SUPABASE_SERVICE_ROLE_KEY"))
-        .unwrap_or_default();
-
-    if supabase_url.is_empty() || supabase_key.is_empty() {
-        return Err(Error::from(
-            "Database not configured - missing SUPABASE_URL or SUPABASE_ANON_KEY",
-        ));
-    }
-
-    // Create Supabase client for public read access (search endpoint should be public)
-    // Use only apikey header, no Authorization Bearer token needed for public reads
-    let client = postgrest::Postgrest::new(format!("{supabase_url}/rest/v1"))
-        .insert_header("apikey", &supabase_key);
-
-    // Build count query using PostgREST's exact_count feature
-    let mut query_builder = client.from("agents").select("id").exact_count();
-
-    // Apply same search filter as main query
-    if !query.is_empty() {
-        if exact {
-            query_builder = query_builder.eq("name", query);
-        } else {
-            // Use proper PostgREST text search syntax with expanded fields
-            query_builder = query_builder
-                .or(format!(
-                    "name.ilike.*{query}*,description.ilike.*{query}*,author_name.ilike.*{query}*,tags.cs.{{{query}}}"
-                ));
-        }
-    }
-
-    // Execute count query
-    let response = query_builder
-        .execute()
-        .await
-        .map_err(|e| Error::from(format!("Database count query failed: {e}")))?;
-
-    if !response.status().is_success() {
-        let status = response.status();
-        let error_text = response
-            .text()
-            .await
-            .unwrap_or_else(|_| "Unknown error".to_string());
-        return Err(Error::from(format!(
-            "Database query failed with status {status}: {error_text}"
-        )));
-    }
-
-    // PostgREST returns the count in the Content-Range header when using exact_count
-    if let Some(content_range) = response.headers().get("content-range") {
-        if let Ok(range_str) = content_range.to_str() {
-            // Parse the content-range header to get total count
-            // Format: "0-4/5" where 5 is the total count, or "*/0" if no records
-            if let Some(total_str) = range_str.split('/').nth(1) {
-                if let Ok(count) = total_str.parse::<usize>() {
-                    return Ok(count);
-                }
-            }
-        }
-    }
-
-    // Fallback to 0 if count parsing fails
-    Ok(0)
+fn bad_request(error: &str, message: &str) -> Result<Response<Body>, Error> {
+    let error = ApiError {
+        error: error.to_string(),
+        message: message.to_string(),
+        details: None,
+    };
+    Ok(Response::builder()
+        .status(400)
+        .header("content-type", "application/json")
+        .body(serde_json::to_string(&error)?.into())?)
 }
@@ -0,0 +1,166 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::env;
+use vercel_runtime::{run, Body, Error, Request, Response};
+
+use shared::problem::respond;
+use shared::tenant::{self, scope};
+use shared::{api_key_middleware, require_scope, ApiError, AuthenticatedUser};
+
+/// Agent columns embedded in each star
+const AGENT_COLUMNS: &str = "name,current_version,description,author_name,created_at,updated_at,download_count,star_count,tags,readme,homepage,repository,license";
+
+/// A star row with its agent embedded
+#[derive(Debug, Deserialize)]
+struct DbStar {
+    starred_at: DateTime<Utc>,
+    agents: DbAgent,
+}
+
+/// Database agent structure (matches actual DB schema)
+#[derive(Debug, Deserialize)]
+struct DbAgent {
+    pub name: String,
+    #[serde(rename = "current_version")]
+    pub version: String,
+    pub description: String,
+    pub author_name: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub download_count: u64,
+    pub star_count: u64,
+    pub tags: Option<Vec<String>>,
+    pub readme: Option<String>,
+    pub homepage: Option<String>,
+    pub repository: Option<String>,
+    pub license: Option<String>,
+}
+
+/// A starred agent, in the same shape search returns agents
+#[derive(Debug, Serialize)]
+pub struct StarredAgent {
+    pub name: String,
+    pub version: String,
+    pub description: String,
+    pub author: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub download_count: u64,
+    pub star_count: u64,
+    pub tags: Vec<String>,
+    pub readme: Option<String>,
+    pub homepage: Option<String>,
+    pub repository: Option<String>,
+    pub license: Option<String>,
+    pub starred_at: DateTime<Utc>,
+}
+
+impl From<DbStar> for StarredAgent {
+    fn from(star: DbStar) -> Self {
+        let agent = star.agents;
+        StarredAgent {
+            name: agent.name,
+            version: agent.version,
+            description: agent.description,
+            author: agent.author_name.unwrap_or_else(|| "Unknown".to_string()),
+            created_at: agent.created_at,
+            updated_at: agent.updated_at,
+            download_count: agent.download_count,
+            star_count: agent.star_count,
+            tags: agent.tags.unwrap_or_default(),
+            readme: agent.readme,
+            homepage: agent.homepage,
+            repository: agent.repository,
+            license: agent.license,
+            starred_at: star.starred_at,
+        }
+    }
+}
+
+/// The caller's starred agents, most recently starred first
+#[derive(Debug, Serialize)]
+pub struct StarredResponse {
+    pub agents: Vec<StarredAgent>,
+    pub total: usize,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    run(|req| respond(scope(req, handler))).await
+}
+
+pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
+    if req.method() != "GET" {
+        let error = ApiError {
+            error: "method_not_allowed".to_string(),
+            message: "Only GET requests are allowed".to_string(),
+            details: None,
+        };
+        return Ok(Response::builder()
+            .status(405)
+            .header("content-type", "application/json")
+            .header("allow", "GET")
+            .body(serde_json::to_string(&error)?.into())?);
+    }
+
+    let authenticated_user = match api_key_middleware(&req).await {
+        Ok(user) => user,
+        Err(error_response) => return Ok(error_response),
+    };
+
+    if let Err(error_response) = require_scope(&authenticated_user, "read") {
+        return Ok(error_response);
+    }
+
+    let agents = get_starred_agents(&authenticated_user).await?;
+    let response = StarredResponse {
+        total: agents.len(),
+        agents,
+    };
+
+    Ok(Response::builder()
+        .status(200)
+        .header("content-type", "application/json")
+        .header("Cache-Control", "no-store")
+        .body(serde_json::to_string(&response)?.into())?)
+}
+
+async fn get_starred_agents(user: &AuthenticatedUser) -> Result<Vec<StarredAgent>, Error> {
+    let supabase_url = env::var("SUPABASE_URL").unwrap_or_default();
+    let supabase_key = env::var("SUPABASE_SERVICE_ROLE_KEY").unwrap_or_default();
+
+    if supabase_url.is_empty() || supabase_key.is_empty() {
+        return Err(Error::from(
+            "Database not configured - missing SUPABASE_URL or SUPABASE_SERVICE_ROLE_KEY",
+        ));
+    }
+
+    // The inner join drops stars on agents that are no longer public
+    let response = tenant::http_client()
+        .get(format!(
+            "{supabase_url}/rest/v1/agent_stars?user_id=eq.{}&agents.is_public=eq.true&select=starred_at:created_at,agents!inner({AGENT_COLUMNS})&order=created_at.desc",
+            user.user_id
+        ))
+        .header("apikey", &supabase_key)
+        .header("Authorization", format!("Bearer {supabase_key}"))
+        .send()
+        .await
+        .map_err(|e| Error::from(format!("Database query failed: {e}")))?;
+
+    let status = response.status();
+    let body = response
+        .text()
+        .await
+        .map_err(|e| Error::from(format!("Failed to read response: {e}")))?;
+
+    if !status.is_success() {
+        return Err(Error::from(format!(
+            "Database query failed with status {status}: {body}"
+        )));
+    }
+
+    let stars: Vec<DbStar> = serde_json::from_str(&body)
+        .map_err(|e| Error::from(format!("Failed to parse starred agents: {e}")))?;
+
+    Ok(stars.into_iter().map(StarredAgent::from).collect())
+}
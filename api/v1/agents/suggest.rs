@@ -0,0 +1,144 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::env;
+use vercel_runtime::{run, Body, Error, Request, Response};
+
+use shared::problem::respond;
+use shared::tenant::{self, scope};
+use shared::{optional_api_key_middleware, AgentVisibility, ApiError};
+
+/// Suggestions returned when the caller doesn't ask for a number
+const DEFAULT_LIMIT: usize = 3;
+
+/// Most suggestions returned for one query
+const MAX_LIMIT: usize = 10;
+
+/// A near-miss agent name
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Suggestion {
+    pub name: String,
+    /// Edits needed to turn the query into the name, ignoring case
+    pub distance: u32,
+    /// Trigram similarity to the query, from 0 to 1
+    pub similarity: f32,
+}
+
+/// Agent names close to a query, closest first
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SuggestResponse {
+    pub query: String,
+    pub suggestions: Vec<Suggestion>,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    run(|req| respond(scope(req, handler))).await
+}
+
+pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
+    // Signed-in callers are also offered their own private agents
+    let authenticated_user = optional_api_key_middleware(&req).await;
+
+    let query = req.uri().query().unwrap_or("");
+    let params: HashMap<String, String> = url::form_urlencoded::parse(query.as_bytes())
+        .into_owned()
+        .collect();
+
+    let query = params.get("q").map(|q| q.trim()).unwrap_or("");
+    if query.is_empty() {
+        let error = ApiError {
+            error: "bad_request".to_string(),
+            message: "The q parameter is required".to_string(),
+            details: None,
+        };
+        return Ok(Response::builder()
+            .status(400)
+            .header("content-type", "application/json")
+            .body(serde_json::to_string(&error)?.into())?);
+    }
+    let limit = params
+        .get("limit")
+        .and_then(|s| s.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_LIMIT)
+        .clamp(1, MAX_LIMIT);
+
+    let visibility = AgentVisibility::for_user(authenticated_user.as_ref())
+        .await
+        .map_err(Error::from)?;
+
+    let response_body = SuggestResponse {
+        query: query.to_string(),
+        suggestions: suggest_agent_names(query, limit, &visibility).await?,
+    };
+
+    Ok(Response::builder()
+        .status(200)
+        .header("content-type", "application/json")
+        .header("Cache-Control", visibility.cache_control())
+        .header("Vary", "Authorization")
+        .body(serde_json::to_string(&response_body)?.into())?)
+}
+
+/// Visible agent names within a few edits of `query`, or sharing most of its
+/// trigrams
+async fn suggest_agent_names(
+    query: &str,
+    limit: usize,
+    visibility: &AgentVisibility,
+) -> Result<Vec<Suggestion>, Error> {
+    let supabase_url = env::var("SUPABASE_URL").unwrap_or_default();
+    // As for search, private agents are only reachable with the service role
+    let supabase_key = if visibility.includes_private() {
+        env::var("SUPABASE_SERVICE_ROLE_KEY").unwrap_or_default()
+    } else {
+        env::var("SUPABASE_ANON_KEY")
+            .or_else(|_| env::var("SUPABASE_SERVICE_ROLE_KEY"))
+            .unwrap_or_default()
+    };
+
+    if supabase_url.is_empty() || supabase_key.is_empty() {
+        return Err(Error::from(
+            "Database not configured - missing SUPABASE_URL or SUPABASE_ANON_KEY",
+        ));
+    }
+
+    let mut client =
+        tenant::database(format!("{supabase_url}/rest/v1")).insert_header("apikey", &supabase_key);
+    if visibility.includes_private() {
+        client = client.insert_header("Authorization", format!("Bearer {supabase_key}"));
+    }
+
+    let query_builder = client
+        .rpc(
+            "suggest_agent_names",
+            json!({ "p_query": query }).to_string(),
+        )
+        .select("name,distance,similarity");
+    let response = visibility
+        .apply(query_builder)
+        .order("distance.asc,similarity.desc,name.asc")
+        .limit(limit)
+        .execute()
+        .await
+        .map_err(|e| Error::from(format!("Database query failed: {e}")))?;
+
+    if !response.status().is_success() {
+        let status = response.status();
+        let error_text = response
+            .text()
+            .await
+            .unwrap_or_else(|_| "Unknown error".to_string());
+        return Err(Error::from(format!(
+            "Suggestion query failed with status {status}: {error_text}"
+        )));
+    }
+
+    let body = response
+        .text()
+        .await
+        .map_err(|e| Error::from(format!("Failed to read response: {e}")))?;
+
+    serde_json::from_str(&body)
+        .map_err(|e| Error::from(format!("Failed to parse suggestions: {e}")))
+}
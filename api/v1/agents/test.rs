@@ -1,10 +1,12 @@
 use serde_json::Value;
+use shared::problem::respond;
+use shared::tenant::scope;
 use std::env;
 use vercel_runtime::{run, Body, Error, Request, Response};
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    run(handler).await
+    run(|req| respond(scope(req, handler))).await
 }
 
 pub async fn handler(_req: Request) -> Result<Response<Body>, Error> {
@@ -59,4 +61,4 @@ pub async fn handler(_req: Request) -> Result<Response<Body>, Error> {
         .header("content-type", "application/json")
         .header("Access-Control-Allow-Origin", "*")
         .body(serde_json::to_string(&debug_info)?.into())?)
-}
\ No newline at end of file
+}
@@ -1,5 +1,7 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use shared::problem::respond;
+use shared::tenant::{self, scope};
 use std::collections::HashMap;
 use std::env;
 use vercel_runtime::{run, Body, Error, Request, Response};
@@ -27,6 +29,10 @@ pub struct Agent {
     pub updated_at: DateTime<Utc>,
     #[serde(default)]
     pub download_count: u64,
+    /// Downloaders over the last 7 days, from the download rollups; 0 when
+    /// the trending view is unavailable
+    #[serde(default)]
+    pub recent_downloads: u64,
     #[serde(default)]
     pub view_count: u64,
     pub tags: Option<Vec<String>>,
@@ -49,7 +55,7 @@ pub struct TrendingAgentsResponse {
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    run(handler).await
+    run(|req| respond(scope(req, handler))).await
 }
 
 pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
@@ -60,7 +66,7 @@ pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
             .header("Access-Control-Allow-Origin", "*")
             .header("Access-Control-Allow-Methods", "GET, OPTIONS")
             .header("Access-Control-Allow-Headers", "Content-Type")
-            .body(Body::Empty)?)
+            .body(Body::Empty)?);
     }
 
     // Parse limit parameter (default 10, max 50)
@@ -103,8 +109,14 @@ async fn get_trending_agents(limit: usize) -> Result<Vec<Agent>, Error> {
         .or_else(|_| env::var("SUPABASE_SERVICE_ROLE_KEY"))
         .unwrap_or_default();
 
-    eprintln!("[DEBUG] Trending - SUPABASE_URL present: {}", !supabase_url.is_empty());
-    eprintln!("[DEBUG] Trending - SUPABASE_KEY present: {}", !supabase_key.is_empty());
+    eprintln!(
+        "[DEBUG] Trending - SUPABASE_URL present: {}",
+        !supabase_url.is_empty()
+    );
+    eprintln!(
+        "[DEBUG] Trending - SUPABASE_KEY present: {}",
+        !supabase_key.is_empty()
+    );
 
     if supabase_url.is_empty() || supabase_key.is_empty() {
         eprintln!("[ERROR] Trending - Database not configured");
@@ -113,7 +125,7 @@ async fn get_trending_agents(limit: usize) -> Result<Vec<Agent>, Error> {
         ));
     }
 
-    let client = postgrest::Postgrest::new(format!("{supabase_url}/rest/v1"))
+    let client = tenant::database(format!("{supabase_url}/rest/v1"))
         .insert_header("apikey", &supabase_key)
         .insert_header("Authorization", format!("Bearer {}", &supabase_key));
 
@@ -125,11 +137,14 @@ async fn get_trending_agents(limit: usize) -> Result<Vec<Agent>, Error> {
             .await; // Ignore errors, will fall back to regular query if needed
     }
 
+    let tenant = tenant::current().id;
+
     // Try materialized view first for optimal performance
     let response = client
         .from("trending_agents_mv")
-        .select("id,name,description,created_at,updated_at,tags,author_name,current_version,download_count,view_count,definition,user_id")
-        .order("view_count.desc") // Order by view count as fallback
+        .select("id,name,description,created_at,updated_at,tags,author_name,current_version,download_count,recent_downloads,view_count,definition,user_id")
+        .eq("tenant_id", &tenant)
+        .order("trending_score.desc")
         .limit(limit)
         .execute()
         .await;
@@ -147,8 +162,9 @@ async fn get_trending_agents(limit: usize) -> Result<Vec<Agent>, Error> {
                 Some(
                     client
                         .from("trending_agents_mv")
-                        .select("id,name,description,created_at,updated_at,tags,author_name,current_version,download_count,view_count,definition,user_id")
-                        .order("view_count.desc")
+                        .select("id,name,description,created_at,updated_at,tags,author_name,current_version,download_count,recent_downloads,view_count,definition,user_id")
+                        .eq("tenant_id", &tenant)
+                        .order("trending_score.desc")
                         .limit(limit)
                         .execute()
                         .await
@@ -167,6 +183,8 @@ async fn get_trending_agents(limit: usize) -> Result<Vec<Agent>, Error> {
             client
                 .from("agents")
                 .select("id,name,description,created_at,updated_at,tags,author_name,current_version,download_count,view_count,definition,user_id")
+                .eq("tenant_id", &tenant)
+                .eq("is_public", "true")
                 .gte("view_count", "1")
                 .order("view_count.desc,updated_at.desc")
                 .limit(limit)
@@ -195,7 +213,10 @@ async fn get_trending_agents(limit: usize) -> Result<Vec<Agent>, Error> {
     }
 
     eprintln!("[DEBUG] Trending - Response body length: {}", body.len());
-    eprintln!("[DEBUG] Trending - Response preview: {}", body.chars().take(200).collect::<String>());
+    eprintln!(
+        "[DEBUG] Trending - Response preview: {}",
+        body.chars().take(200).collect::<String>()
+    );
 
     let mut agents: Vec<Agent> = serde_json::from_str(&body).map_err(|e| {
         eprintln!("[ERROR] Failed to parse trending agents response: {}", body);
@@ -203,36 +224,42 @@ async fn get_trending_agents(limit: usize) -> Result<Vec<Agent>, Error> {
         Error::from(format!("Failed to parse agents: {e}"))
     })?;
 
-    eprintln!("[DEBUG] Trending - Successfully parsed {} agents", agents.len());
-    
+    eprintln!(
+        "[DEBUG] Trending - Successfully parsed {} agents",
+        agents.len()
+    );
+
     // Fetch profiles for the agents
     if !agents.is_empty() {
         let user_ids: Vec<String> = agents.iter().map(|a| a.user_id.clone()).collect();
-        
-        eprintln!("[DEBUG] Trending - Fetching profiles for {} users", user_ids.len());
-        
+
+        eprintln!(
+            "[DEBUG] Trending - Fetching profiles for {} users",
+            user_ids.len()
+        );
+
         let profile_response = client
             .from("profiles")
             .select("user_id,github_username,display_name,avatar_url")
             .in_("user_id", &user_ids)
             .execute()
             .await?;
-            
+
         if profile_response.status().is_success() {
             let profile_body = profile_response.text().await?;
-            
+
             if !profile_body.is_empty() && profile_body != "[]" {
                 let profiles: Vec<Profile> = serde_json::from_str(&profile_body).map_err(|e| {
                     eprintln!("[ERROR] Trending - Failed to parse profiles: {}", e);
                     Error::from(format!("Failed to parse profiles: {e}"))
                 })?;
-                
+
                 // Create a map for quick lookup
                 let profile_map: HashMap<String, Profile> = profiles
                     .into_iter()
                     .map(|p| (p.user_id.clone(), p))
                     .collect();
-                
+
                 // Attach profiles to agents
                 for agent in &mut agents {
                     if let Some(profile) = profile_map.get(&agent.user_id) {
@@ -242,6 +269,6 @@ async fn get_trending_agents(limit: usize) -> Result<Vec<Agent>, Error> {
             }
         }
     }
-    
+
     Ok(agents)
 }
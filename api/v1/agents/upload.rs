@@ -5,7 +5,16 @@ use vercel_runtime::{run, Body, Error, Request, Response};
 
 // Use shared authentication module
 use serde_json::json;
-use shared::{api_key_middleware, require_scope, ApiError, AuthenticatedUser};
+use shared::archive::{build_markdown_package, package_checksum};
+use shared::problem::respond;
+use shared::resolution::{dependencies_from_definition, validate_dependencies, Dependencies};
+use shared::tenant::{self, scope};
+use shared::validation::{check_agent_name, validation_failed};
+use shared::{
+    api_key_middleware, claim_idempotency_key, ensure_enabled, ensure_writable,
+    inspect_signature_bundle, require_scope, validate_provenance, ApiError, AuthenticatedUser,
+    ContentPolicy, Flag, IdempotencyClaim, PackageFormat, Provenance, Validate, ValidatedJson,
+};
 
 /// Agent metadata returned by the API
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -22,6 +31,10 @@ pub struct Agent {
     pub homepage: Option<String>,
     pub repository: Option<String>,
     pub license: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature_bundle: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Provenance>,
 }
 
 /// Request for uploading an agent via JSON
@@ -35,6 +48,25 @@ pub struct UploadAgentRequest {
     pub homepage: Option<String>,
     pub repository: Option<String>,
     pub license: Option<String>,
+    /// Sigstore bundle for keyless-signed uploads
+    #[serde(default)]
+    pub signature_bundle: Option<serde_json::Value>,
+    /// Source and build information supplied by the publisher
+    #[serde(default)]
+    pub provenance: Option<Provenance>,
+    /// Only the publisher can see private agents
+    #[serde(default)]
+    pub private: bool,
+    /// Offer the agent as a starting point for `carp new --template`
+    #[serde(default)]
+    pub template: bool,
+    /// Fingerprints of content policy secret findings the publisher says are false positives
+    #[serde(default)]
+    pub allowed_secrets: Vec<String>,
+    /// Agents this version depends on; when empty, the frontmatter's
+    /// `dependencies` are used
+    #[serde(default)]
+    pub dependencies: Dependencies,
 }
 
 /// Response from uploading an agent
@@ -43,14 +75,22 @@ pub struct UploadAgentResponse {
     pub success: bool,
     pub message: String,
     pub agent: Option<Agent>,
-    pub validation_errors: Option<Vec<ValidationError>>,
 }
 
-/// Validation error details
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ValidationError {
-    pub field: String,
-    pub message: String,
+/// Why a validated upload wasn't stored
+#[derive(Debug)]
+enum UploadError {
+    /// The name@version is already published
+    VersionExists(String),
+    /// The agent belongs to another user
+    NotOwner(String),
+    Failed(String),
+}
+
+impl From<String> for UploadError {
+    fn from(message: String) -> Self {
+        UploadError::Failed(message)
+    }
 }
 
 /// YAML frontmatter structure
@@ -67,7 +107,7 @@ pub struct YamlFrontmatter {
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    run(handler).await
+    run(|req| respond(scope(req, handler))).await
 }
 
 pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
@@ -85,6 +125,10 @@ pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
             .body(serde_json::to_string(&error)?.into())?);
     }
 
+    if let Err(response) = ensure_writable().await {
+        return Ok(response);
+    }
+
     // Authenticate the request using API key only
     let authenticated_user = match api_key_middleware(&req).await {
         Ok(user) => user,
@@ -96,6 +140,12 @@ pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
         return Ok(error_response);
     }
 
+    if let Err(response) =
+        ensure_enabled(Flag::Publish, &authenticated_user.user_id.to_string()).await
+    {
+        return Ok(response);
+    }
+
     // Check content type
     let headers = req.headers();
     let content_type = headers
@@ -115,43 +165,54 @@ pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
             .body(serde_json::to_string(&error)?.into())?);
     }
 
-    // Parse request body
-    let body_bytes = req.body();
-    let body_str = std::str::from_utf8(body_bytes)
-        .map_err(|_| Error::from("Invalid UTF-8 in request body"))?;
+    let ValidatedJson(upload_request) =
+        match ValidatedJson::<UploadAgentRequest>::from_request(&req) {
+            Ok(request) => request,
+            Err(response) => return Ok(response),
+        };
 
-    let upload_request: UploadAgentRequest = match serde_json::from_str(body_str) {
-        Ok(req) => req,
-        Err(e) => {
+    let policy = match ContentPolicy::from_env() {
+        Ok(policy) => policy,
+        Err(message) => {
+            eprintln!("ERROR: {message}");
             let error = ApiError {
-                error: "bad_request".to_string(),
-                message: format!("Invalid JSON in request body: {e}"),
+                error: "internal_error".to_string(),
+                message: "The registry's content policy is misconfigured".to_string(),
                 details: None,
             };
             return Ok(Response::builder()
-                .status(400)
+                .status(500)
                 .header("content-type", "application/json")
                 .body(serde_json::to_string(&error)?.into())?);
         }
     };
 
-    // Validate the upload request
-    match validate_upload_request(&upload_request) {
-        Ok(_) => {}
-        Err(validation_errors) => {
-            let response = UploadAgentResponse {
-                success: false,
-                message: "Validation failed".to_string(),
-                agent: None,
-                validation_errors: Some(validation_errors),
-            };
-            return Ok(Response::builder()
-                .status(400)
-                .header("content-type", "application/json")
-                .body(serde_json::to_string(&response)?.into())?);
-        }
+    // Apply the content policy, including its size limit, to everything shown to others
+    let violations: Vec<_> = policy
+        .check_content(
+            "content",
+            &upload_request.content,
+            &upload_request.allowed_secrets,
+        )
+        .into_iter()
+        .chain(policy.check_text(
+            "description",
+            &upload_request.description,
+            &upload_request.allowed_secrets,
+        ))
+        .collect();
+    if !violations.is_empty() {
+        return Ok(validation_failed(&violations));
     }
 
+    // Retries with the same Idempotency-Key replay the original result
+    let idempotency =
+        match claim_idempotency_key(&req, &authenticated_user, "upload", req.body()).await {
+            Ok(IdempotencyClaim::Replay(response)) => return Ok(response),
+            Ok(claim) => claim,
+            Err(error_response) => return Ok(error_response),
+        };
+
     // Extract the original API key from the request for database function
     let auth_header = req
         .headers()
@@ -166,20 +227,33 @@ pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
     );
 
     // Process the upload request
-    match upload_agent(upload_request, &authenticated_user, auth_header).await {
+    let (status, body) = match upload_agent(upload_request, &authenticated_user, auth_header).await
+    {
         Ok(agent) => {
             let response = UploadAgentResponse {
                 success: true,
                 message: "Agent uploaded successfully".to_string(),
                 agent: Some(agent),
-                validation_errors: None,
             };
-            Ok(Response::builder()
-                .status(201)
-                .header("content-type", "application/json")
-                .body(serde_json::to_string(&response)?.into())?)
+            (201, serde_json::to_string(&response)?)
         }
-        Err(err_msg) => {
+        Err(UploadError::VersionExists(message)) => {
+            let error = ApiError {
+                error: "version_exists".to_string(),
+                message,
+                details: None,
+            };
+            (409, serde_json::to_string(&error)?)
+        }
+        Err(UploadError::NotOwner(message)) => {
+            let error = ApiError {
+                error: "forbidden".to_string(),
+                message,
+                details: None,
+            };
+            (403, serde_json::to_string(&error)?)
+        }
+        Err(UploadError::Failed(err_msg)) => {
             eprintln!("DEBUG: Upload failed with error: {}", err_msg);
             let error = ApiError {
                 error: "upload_failed".to_string(),
@@ -190,137 +264,125 @@ pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
                     "timestamp": chrono::Utc::now().to_rfc3339()
                 })),
             };
-            Ok(Response::builder()
-                .status(400)
-                .header("content-type", "application/json")
-                .body(serde_json::to_string(&error)?.into())?)
+            (400, serde_json::to_string(&error)?)
         }
-    }
+    };
+
+    idempotency.finish(status, &body).await;
+
+    Ok(Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(body.into())?)
 }
 
-fn validate_upload_request(request: &UploadAgentRequest) -> Result<(), Vec<ValidationError>> {
-    let mut errors = Vec::new();
+impl Validate for UploadAgentRequest {
+    fn validate(&self) -> Vec<(String, String)> {
+        let request = self;
+        let mut errors: Vec<_> = check_agent_name("name", &request.name)
+            .into_iter()
+            .collect();
+
+        // Validate description
+        if request.description.trim().is_empty() {
+            errors.push((
+                "description".to_string(),
+                "Description cannot be empty".to_string(),
+            ));
+        } else if request.description.len() > 1000 {
+            errors.push((
+                "description".to_string(),
+                "Description cannot exceed 1000 characters".to_string(),
+            ));
+        }
 
-    // Validate agent name
-    if request.name.trim().is_empty() {
-        errors.push(ValidationError {
-            field: "name".to_string(),
-            message: "Agent name cannot be empty".to_string(),
-        });
-    } else if !request
-        .name
-        .chars()
-        .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
-    {
-        errors.push(ValidationError {
-            field: "name".to_string(),
-            message:
-                "Agent name can only contain alphanumeric characters, hyphens, and underscores"
-                    .to_string(),
-        });
-    } else if request.name.len() > 100 {
-        errors.push(ValidationError {
-            field: "name".to_string(),
-            message: "Agent name cannot exceed 100 characters".to_string(),
-        });
-    }
+        // Validate content
+        if request.content.trim().is_empty() {
+            errors.push(("content".to_string(), "Content cannot be empty".to_string()));
+        }
 
-    // Validate description
-    if request.description.trim().is_empty() {
-        errors.push(ValidationError {
-            field: "description".to_string(),
-            message: "Description cannot be empty".to_string(),
-        });
-    } else if request.description.len() > 1000 {
-        errors.push(ValidationError {
-            field: "description".to_string(),
-            message: "Description cannot exceed 1000 characters".to_string(),
-        });
-    }
+        // Validate YAML frontmatter in content
+        errors.extend(validate_frontmatter_consistency(request));
 
-    // Validate content
-    if request.content.trim().is_empty() {
-        errors.push(ValidationError {
-            field: "content".to_string(),
-            message: "Content cannot be empty".to_string(),
-        });
-    } else if request.content.len() > 1024 * 1024 {
-        // 1MB limit for content
-        errors.push(ValidationError {
-            field: "content".to_string(),
-            message: "Content size exceeds maximum allowed size (1MB)".to_string(),
-        });
-    }
+        // Validate the keyless signature covers the uploaded content
+        if let Some(bundle) = &request.signature_bundle {
+            if let Err(message) = inspect_signature_bundle(bundle, &request.content) {
+                errors.push(("signature_bundle".to_string(), message));
+            }
+        }
 
-    // Validate YAML frontmatter in content
-    if let Err(frontmatter_errors) = validate_frontmatter_consistency(request) {
-        errors.extend(frontmatter_errors);
-    }
+        // Validate publisher-supplied provenance
+        if let Some(provenance) = &request.provenance {
+            errors.extend(validate_provenance(provenance));
+        }
 
-    // Validate optional version
-    if let Some(version) = &request.version {
-        if version.trim().is_empty() {
-            errors.push(ValidationError {
-                field: "version".to_string(),
-                message: "Version cannot be empty".to_string(),
-            });
-        } else if !version
-            .chars()
-            .all(|c| c.is_alphanumeric() || ".-_+".contains(c))
-        {
-            errors.push(ValidationError {
-                field: "version".to_string(),
-                message: "Version can only contain alphanumeric characters, dots, hyphens, underscores, and plus signs".to_string(),
-            });
-        } else if version.len() > 50 {
-            errors.push(ValidationError {
-                field: "version".to_string(),
-                message: "Version cannot exceed 50 characters".to_string(),
-            });
+        // Validate optional version
+        if let Some(version) = &request.version {
+            if version.trim().is_empty() {
+                errors.push(("version".to_string(), "Version cannot be empty".to_string()));
+            } else if !version
+                .chars()
+                .all(|c| c.is_alphanumeric() || ".-_+".contains(c))
+            {
+                errors.push(("version".to_string(), "Version can only contain alphanumeric characters, dots, hyphens, underscores, and plus signs".to_string()));
+            } else if version.len() > 50 {
+                errors.push((
+                    "version".to_string(),
+                    "Version cannot exceed 50 characters".to_string(),
+                ));
+            }
         }
-    }
 
-    // Validate tags
-    for (index, tag) in request.tags.iter().enumerate() {
-        if tag.trim().is_empty() {
-            errors.push(ValidationError {
-                field: format!("tags[{index}]"),
-                message: "Tags cannot be empty".to_string(),
-            });
-        } else if tag.len() > 50 {
-            errors.push(ValidationError {
-                field: format!("tags[{index}]"),
-                message: "Tags cannot exceed 50 characters".to_string(),
-            });
+        match declared_dependencies(request) {
+            Ok(dependencies) => errors.extend(validate_dependencies(&request.name, &dependencies)),
+            Err(message) => errors.push(("dependencies".to_string(), message)),
         }
-    }
 
-    if request.tags.len() > 20 {
-        errors.push(ValidationError {
-            field: "tags".to_string(),
-            message: "Cannot have more than 20 tags".to_string(),
-        });
+        // Validate tags
+        for (index, tag) in request.tags.iter().enumerate() {
+            if tag.trim().is_empty() {
+                errors.push((format!("tags[{index}]"), "Tags cannot be empty".to_string()));
+            } else if tag.len() > 50 {
+                errors.push((
+                    format!("tags[{index}]"),
+                    "Tags cannot exceed 50 characters".to_string(),
+                ));
+            }
+        }
+
+        if request.tags.len() > 20 {
+            errors.push((
+                "tags".to_string(),
+                "Cannot have more than 20 tags".to_string(),
+            ));
+        }
+
+        errors
     }
+}
 
-    if errors.is_empty() {
-        Ok(())
-    } else {
-        Err(errors)
+/// The dependencies an upload declares: the request's, else its frontmatter's
+fn declared_dependencies(request: &UploadAgentRequest) -> Result<Dependencies, String> {
+    if !request.dependencies.is_empty() {
+        return Ok(request.dependencies.clone());
+    }
+    // Frontmatter problems are reported by the consistency check
+    match parse_agent_definition(&request.content) {
+        Ok(definition) => dependencies_from_definition(&definition),
+        Err(_) => Ok(Dependencies::new()),
     }
 }
 
-fn validate_frontmatter_consistency(
-    request: &UploadAgentRequest,
-) -> Result<(), Vec<ValidationError>> {
+fn validate_frontmatter_consistency(request: &UploadAgentRequest) -> Vec<(String, String)> {
     let mut errors = Vec::new();
 
     // Check if content starts with YAML frontmatter
     if !request.content.starts_with("---") {
-        errors.push(ValidationError {
-            field: "content".to_string(),
-            message: "Content must contain YAML frontmatter starting with ---".to_string(),
-        });
-        return Err(errors);
+        errors.push((
+            "content".to_string(),
+            "Content must contain YAML frontmatter starting with ---".to_string(),
+        ));
+        return errors;
     }
 
     // Find the end of the frontmatter
@@ -337,11 +399,11 @@ fn validate_frontmatter_consistency(
     let frontmatter_end = match frontmatter_end {
         Some(end) => end,
         None => {
-            errors.push(ValidationError {
-                field: "content".to_string(),
-                message: "Invalid YAML frontmatter: missing closing ---".to_string(),
-            });
-            return Err(errors);
+            errors.push((
+                "content".to_string(),
+                "Invalid YAML frontmatter: missing closing ---".to_string(),
+            ));
+            return errors;
         }
     };
 
@@ -353,48 +415,44 @@ fn validate_frontmatter_consistency(
     let frontmatter: YamlFrontmatter = match serde_yaml::from_str(&frontmatter_content) {
         Ok(fm) => fm,
         Err(e) => {
-            errors.push(ValidationError {
-                field: "content".to_string(),
-                message: format!("Invalid YAML frontmatter: {e}"),
-            });
-            return Err(errors);
+            errors.push((
+                "content".to_string(),
+                format!("Invalid YAML frontmatter: {e}"),
+            ));
+            return errors;
         }
     };
 
     // Validate name consistency
     if frontmatter.name != request.name {
-        errors.push(ValidationError {
-            field: "name".to_string(),
-            message: format!(
+        errors.push((
+            "name".to_string(),
+            format!(
                 "Name mismatch: frontmatter contains '{}' but request contains '{}'",
                 frontmatter.name, request.name
             ),
-        });
+        ));
     }
 
     // Validate description consistency
     if frontmatter.description != request.description {
-        errors.push(ValidationError {
-            field: "description".to_string(),
-            message: format!(
+        errors.push((
+            "description".to_string(),
+            format!(
                 "Description mismatch: frontmatter contains '{}' but request contains '{}'",
                 frontmatter.description, request.description
             ),
-        });
+        ));
     }
 
-    if errors.is_empty() {
-        Ok(())
-    } else {
-        Err(errors)
-    }
+    errors
 }
 
 async fn upload_agent(
     request: UploadAgentRequest,
     user: &AuthenticatedUser,
     _auth_header: &str,
-) -> Result<Agent, String> {
+) -> Result<Agent, UploadError> {
     // Get database connection
     let supabase_url = env::var("SUPABASE_URL").unwrap_or_default();
     let supabase_key = env::var("SUPABASE_SERVICE_ROLE_KEY").unwrap_or_default();
@@ -420,7 +478,7 @@ async fn upload_agent(
     }
 
     // Create HTTP client
-    let client = reqwest::Client::new();
+    let client = tenant::http_client();
 
     // First, ensure the user exists in the database (critical for API key users)
     eprintln!("DEBUG: Syncing user to database: {}", user.user_id);
@@ -482,35 +540,54 @@ async fn upload_agent(
     let definition = parse_agent_definition(&request.content)
         .map_err(|e| format!("Failed to parse agent definition: {e}"))?;
 
-    // Prepare parameters for create_agent function
-    let version = request.version.unwrap_or_else(|| "1.0.0".to_string());
-
-    // First, try to use the safe agent creation function that bypasses RLS
-    let create_agent_params = json!({
+    let version = request
+        .version
+        .clone()
+        .unwrap_or_else(|| "1.0.0".to_string());
+    let dependencies = declared_dependencies(&request)?;
+
+    // The package is stored under its checksum, so a retry or a refused
+    // republish can never replace the package of a published version.
+    // Tenants' packages are kept under their own prefix.
+    let package = build_markdown_package(&request.name, &request.content)
+        .map_err(|e| format!("Failed to package agent: {e}"))?;
+    let checksum = package_checksum(&package);
+    let file_path = tenant::current().storage_path(&format!(
+        "{}/{}/{version}/{}.zip",
+        user.user_id,
+        request.name,
+        checksum.trim_start_matches("sha256:")
+    ));
+    store_package(&client, &supabase_url, &supabase_key, &file_path, &package).await?;
+
+    // Every upload is a new, immutable version row; the function bypasses RLS
+    let publish_params = json!({
         "p_user_id": user.user_id,
         "p_name": request.name,
+        "p_version": version,
         "p_description": request.description,
+        "p_file_path": file_path,
+        "p_content_type": PackageFormat::Zip.content_type(),
+        "p_package_size": package.len(),
+        "p_checksum": checksum,
         "p_definition": definition,
         "p_tags": request.tags,
         "p_author_name": format!("user-{}", user.user_id),
         "p_license": request.license.clone().unwrap_or_else(|| "MIT".to_string()),
-        "p_homepage": request.homepage.clone().unwrap_or_else(|| "".to_string()),
-        "p_repository": request.repository.clone().unwrap_or_else(|| "".to_string()),
+        "p_homepage": request.homepage.clone().unwrap_or_default(),
+        "p_repository": request.repository.clone().unwrap_or_default(),
         "p_readme": request.content,
-        "p_keywords": request.tags,
-        "p_current_version": version,
-        "p_is_public": true
+        "p_is_public": !request.private,
+        "p_is_template": request.template,
+        "p_dependencies": dependencies
     });
 
-    eprintln!("DEBUG: Attempting to insert agent using safe function");
-
-    // Try the safe function first
     let response = client
-        .post(format!("{supabase_url}/rest/v1/rpc/create_agent_safe"))
+        .post(format!("{supabase_url}/rest/v1/rpc/publish_agent_version"))
         .header("apikey", &supabase_key)
         .header("Authorization", format!("Bearer {supabase_key}"))
         .header("Content-Type", "application/json")
-        .json(&create_agent_params)
+        .json(&publish_params)
         .send()
         .await
         .map_err(|e| format!("Database request failed: {e}"))?;
@@ -518,140 +595,165 @@ async fn upload_agent(
     let status_code = response.status();
     eprintln!("DEBUG: Database response status: {}", status_code);
 
-    if response.status().is_success() {
-        // Success path - parse response
-        let response_body = response
-            .text()
-            .await
-            .map_err(|e| format!("Failed to read response: {e}"))?;
-
-        eprintln!("DEBUG: Database response body: {}", response_body);
-
-        // Parse the created agent from database response
-        let created_agents: Vec<serde_json::Value> = serde_json::from_str(&response_body)
-            .map_err(|e| format!("Failed to parse database response '{}': {e}", response_body))?;
-
-        if let Some(agent_data) = created_agents.first() {
-            let agent = Agent {
-                name: agent_data["name"]
-                    .as_str()
-                    .unwrap_or(&request.name)
-                    .to_string(),
-                version: version.clone(),
-                description: agent_data["description"]
-                    .as_str()
-                    .unwrap_or(&request.description)
-                    .to_string(),
-                author: agent_data["author_name"]
-                    .as_str()
-                    .unwrap_or(&format!("user-{}", user.user_id))
-                    .to_string(),
-                created_at: serde_json::from_value(agent_data["created_at"].clone())
-                    .unwrap_or_else(|_| Utc::now()),
-                updated_at: serde_json::from_value(agent_data["updated_at"].clone())
-                    .unwrap_or_else(|_| Utc::now()),
-                download_count: agent_data["download_count"].as_u64().unwrap_or(0),
-                tags: serde_json::from_value(agent_data["tags"].clone())
-                    .unwrap_or(request.tags.clone()),
-                readme: Some(request.content.clone()),
-                homepage: request.homepage.clone(),
-                repository: request.repository.clone(),
-                license: request.license.clone(),
-            };
-            return Ok(agent);
-        } else {
-            return Err("No agent data returned from database".to_string());
+    let response_body = response
+        .text()
+        .await
+        .map_err(|e| format!("Failed to read response: {e}"))?;
+
+    match status_code.as_u16() {
+        200..=299 => {}
+        409 => {
+            return Err(UploadError::VersionExists(format!(
+                "{}@{version} has already been published; versions can't be overwritten",
+                request.name
+            )))
+        }
+        403 => {
+            return Err(UploadError::NotOwner(format!(
+                "Agent '{}' belongs to another user",
+                request.name
+            )))
+        }
+        _ => {
+            return Err(UploadError::Failed(format!(
+                "Database error ({status_code}): {response_body}"
+            )))
         }
     }
 
-    // Safe function failed, get error details
-    let error_text = response.text().await.unwrap_or_default();
-    eprintln!("DEBUG: Safe function failed with response: {}", error_text);
-
-    // Try direct insert as fallback
-    eprintln!("DEBUG: Safe function failed, trying direct insert as fallback");
-
-    let agent_data = json!({
-        "user_id": user.user_id,
-        "name": request.name,
-        "description": request.description,
-        "definition": definition,
-        "tags": request.tags,
-        "author_name": format!("user-{}", user.user_id),
-        "license": request.license.clone().unwrap_or_else(|| "MIT".to_string()),
-        "homepage": request.homepage.clone().unwrap_or_else(|| "".to_string()),
-        "repository": request.repository.clone().unwrap_or_else(|| "".to_string()),
-        "readme": request.content,
-        "keywords": request.tags,
-        "current_version": version,
-        "is_public": true
-    });
+    eprintln!("DEBUG: Database response body: {}", response_body);
 
-    let fallback_response = client
-        .post(format!("{supabase_url}/rest/v1/agents"))
-        .header("apikey", &supabase_key)
+    // Parse the published agent from database response
+    let published: Vec<serde_json::Value> = serde_json::from_str(&response_body)
+        .map_err(|e| format!("Failed to parse database response '{}': {e}", response_body))?;
+
+    let Some(agent_data) = published.first() else {
+        return Err("No agent data returned from database".to_string().into());
+    };
+
+    let agent = Agent {
+        name: agent_data["name"]
+            .as_str()
+            .unwrap_or(&request.name)
+            .to_string(),
+        version,
+        description: agent_data["description"]
+            .as_str()
+            .unwrap_or(&request.description)
+            .to_string(),
+        author: agent_data["author_name"]
+            .as_str()
+            .unwrap_or(&format!("user-{}", user.user_id))
+            .to_string(),
+        created_at: serde_json::from_value(agent_data["created_at"].clone())
+            .unwrap_or_else(|_| Utc::now()),
+        updated_at: serde_json::from_value(agent_data["updated_at"].clone())
+            .unwrap_or_else(|_| Utc::now()),
+        download_count: agent_data["download_count"].as_u64().unwrap_or(0),
+        tags: serde_json::from_value(agent_data["tags"].clone()).unwrap_or(request.tags.clone()),
+        readme: Some(request.content.clone()),
+        homepage: request.homepage.clone(),
+        repository: request.repository.clone(),
+        license: request.license.clone(),
+        signature_bundle: request.signature_bundle.clone(),
+        provenance: request.provenance.clone(),
+    };
+    record_publish_metadata(&client, &supabase_url, &supabase_key, &request, user).await?;
+    Ok(agent)
+}
+
+/// Upload a package to the `agent-packages` bucket
+async fn store_package(
+    client: &reqwest::Client,
+    supabase_url: &str,
+    supabase_key: &str,
+    file_path: &str,
+    package: &[u8],
+) -> Result<(), String> {
+    let response = client
+        .post(format!(
+            "{supabase_url}/storage/v1/object/agent-packages/{file_path}"
+        ))
+        .header("apikey", supabase_key)
         .header("Authorization", format!("Bearer {supabase_key}"))
-        .header("Content-Type", "application/json")
-        .header("Prefer", "return=representation")
-        .json(&agent_data)
+        .header("Content-Type", PackageFormat::Zip.content_type())
+        // Same path means same bytes, so replacing a leftover from a failed attempt is safe
+        .header("x-upsert", "true")
+        .body(package.to_vec())
         .send()
         .await
-        .map_err(|e| format!("Fallback database request failed: {e}"))?;
-
-    let fallback_status = fallback_response.status();
-    if !fallback_status.is_success() {
-        let fallback_error = fallback_response.text().await.unwrap_or_default();
-        eprintln!("DEBUG: Fallback also failed: {}", fallback_error);
-        return Err(format!(
-            "Database error - Safe function failed ({}): {}\nFallback failed ({}): {}",
-            status_code, error_text, fallback_status, fallback_error
-        ));
+        .map_err(|e| format!("Failed to store package: {e}"))?;
+
+    if !response.status().is_success() {
+        let error_text = response.text().await.unwrap_or_default();
+        return Err(format!("Failed to store package: {error_text}"));
     }
 
-    eprintln!("DEBUG: Fallback succeeded");
+    Ok(())
+}
 
-    // Use fallback response for parsing
-    let response_body = fallback_response
-        .text()
+/// Store publish-time metadata (signature and provenance) on the uploaded agent
+async fn record_publish_metadata(
+    client: &reqwest::Client,
+    supabase_url: &str,
+    supabase_key: &str,
+    request: &UploadAgentRequest,
+    user: &AuthenticatedUser,
+) -> Result<(), String> {
+    let mut columns = serde_json::Map::new();
+
+    if let Some(bundle) = &request.signature_bundle {
+        // Already validated, so this only fails if the bundle changed underneath us
+        let tlog_entry = inspect_signature_bundle(bundle, &request.content)?;
+        eprintln!(
+            "DEBUG: Recording signature for {} at transparency log index {}",
+            request.name, tlog_entry.log_index
+        );
+
+        columns.insert("signature_bundle".to_string(), bundle.clone());
+        columns.insert(
+            "transparency_log_index".to_string(),
+            json!(tlog_entry.log_index),
+        );
+        columns.insert("transparency_log_id".to_string(), json!(tlog_entry.log_id));
+        columns.insert(
+            "signed_at".to_string(),
+            json!(tlog_entry
+                .integrated_time
+                .and_then(|t| DateTime::<Utc>::from_timestamp(t, 0))
+                .map(|t| t.to_rfc3339())),
+        );
+    }
+
+    if let Some(provenance) = &request.provenance {
+        columns.insert("provenance".to_string(), json!(provenance));
+    }
+
+    if columns.is_empty() {
+        return Ok(());
+    }
+
+    let response = client
+        .patch(format!(
+            "{supabase_url}/rest/v1/agents?name=eq.{}&tenant_id=eq.{}&user_id=eq.{}",
+            urlencoding::encode(&request.name),
+            tenant::current().id,
+            user.user_id
+        ))
+        .header("apikey", supabase_key)
+        .header("Authorization", format!("Bearer {supabase_key}"))
+        .header("Content-Type", "application/json")
+        .json(&columns)
+        .send()
         .await
-        .map_err(|e| format!("Failed to read fallback response: {e}"))?;
-
-    eprintln!("DEBUG: Fallback response body: {}", response_body);
-
-    // Parse the created agent from fallback response
-    let created_agents: Vec<serde_json::Value> = serde_json::from_str(&response_body)
-        .map_err(|e| format!("Failed to parse fallback response '{}': {e}", response_body))?;
-
-    if let Some(agent_data) = created_agents.first() {
-        let agent = Agent {
-            name: agent_data["name"]
-                .as_str()
-                .unwrap_or(&request.name)
-                .to_string(),
-            version,
-            description: agent_data["description"]
-                .as_str()
-                .unwrap_or(&request.description)
-                .to_string(),
-            author: agent_data["author_name"]
-                .as_str()
-                .unwrap_or(&format!("user-{}", user.user_id))
-                .to_string(),
-            created_at: serde_json::from_value(agent_data["created_at"].clone())
-                .unwrap_or_else(|_| Utc::now()),
-            updated_at: serde_json::from_value(agent_data["updated_at"].clone())
-                .unwrap_or_else(|_| Utc::now()),
-            download_count: agent_data["download_count"].as_u64().unwrap_or(0),
-            tags: serde_json::from_value(agent_data["tags"].clone()).unwrap_or(request.tags),
-            readme: Some(request.content),
-            homepage: request.homepage,
-            repository: request.repository,
-            license: request.license,
-        };
-        Ok(agent)
-    } else {
-        Err("No agent data returned from fallback database".to_string())
+        .map_err(|e| format!("Failed to record publish metadata: {e}"))?;
+
+    if !response.status().is_success() {
+        let error_text = response.text().await.unwrap_or_default();
+        return Err(format!("Failed to record publish metadata: {error_text}"));
     }
+
+    Ok(())
 }
 
 /// Parse agent definition from markdown content with YAML frontmatter
@@ -713,5 +815,7 @@ fn create_mock_uploaded_agent(request: UploadAgentRequest, user: &AuthenticatedU
         homepage: request.homepage,
         repository: request.repository,
         license: request.license,
+        signature_bundle: request.signature_bundle,
+        provenance: request.provenance,
     }
 }
@@ -6,9 +6,12 @@ use uuid::Uuid;
 use vercel_runtime::{run, Body, Error, Request, Response};
 
 // Use shared authentication module
+use shared::problem::respond;
+use shared::tenant::{self, scope};
+use shared::validation::check_text;
 use shared::{
-    authenticate_api_key, authenticate_jwt, extract_bearer_token, guess_token_type, jwt_middleware,
-    require_scope, ApiError, AuthConfig, AuthMethod, AuthenticatedUser, TokenType,
+    ensure_writable, extract_bearer_token, jwt_middleware, jwt_or_api_key_middleware,
+    require_scope, ApiError, AuthMethod, AuthenticatedUser, Validate, ValidatedJson,
 };
 
 /// API key information (without the actual key)
@@ -20,6 +23,13 @@ pub struct ApiKeyInfo {
     pub scopes: Vec<String>,
     pub is_active: bool,
     pub last_used_at: Option<DateTime<Utc>>,
+    /// Address, client and country of the most recent request made with it
+    #[serde(default)]
+    pub last_used_ip: Option<String>,
+    #[serde(default)]
+    pub last_used_user_agent: Option<String>,
+    #[serde(default)]
+    pub last_used_country: Option<String>,
     pub expires_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
 }
@@ -48,59 +58,73 @@ pub struct UpdateApiKeyRequest {
     pub expires_at: Option<DateTime<Utc>>,
 }
 
-/// Try both JWT and API key authentication (flexible approach)
-/// This allows both web users (JWT) and CLI users (API key) to access the same endpoints
-async fn try_flexible_auth(req: &Request) -> Result<AuthenticatedUser, Response<Body>> {
-    let config = AuthConfig::from_env();
+/// Scopes an API key may be given
+const VALID_SCOPES: [&str; 6] = ["read", "write", "upload", "publish", "delete", "admin"];
 
-    let token = extract_bearer_token(req).ok_or_else(|| {
-        let error = ApiError {
-            error: "missing_authentication".to_string(),
-            message:
-                "Authentication required. Provide either a JWT token (from web login) or API key."
-                    .to_string(),
-            details: Some(serde_json::json!({
-                "accepted_methods": ["jwt_token", "api_key"],
-                "header_formats": [
-                    "Authorization: Bearer <jwt_token>",
-                    "Authorization: Bearer <api_key>",
-                    "X-API-Key: <api_key>"
-                ]
-            })),
-        };
-        Response::builder()
-            .status(401)
-            .header("content-type", "application/json")
-            .header("WWW-Authenticate", "Bearer")
-            .body(serde_json::to_string(&error).unwrap_or_default().into())
-            .unwrap()
-    })?;
-
-    // Try to determine token type and authenticate accordingly
-    match guess_token_type(&token) {
-        TokenType::ApiKey => authenticate_api_key(&token, &config).await.map_err(|e| {
-            Response::builder()
-                .status(401)
-                .header("content-type", "application/json")
-                .body(serde_json::to_string(&e).unwrap_or_default().into())
-                .unwrap()
-        }),
-        TokenType::Jwt => authenticate_jwt(&token, &config).await.map_err(|e| {
-            Response::builder()
-                .status(401)
-                .header("content-type", "application/json")
-                .body(serde_json::to_string(&e).unwrap_or_default().into())
-                .unwrap()
-        }),
+impl Validate for CreateApiKeyRequest {
+    fn validate(&self) -> Vec<(String, String)> {
+        let mut errors: Vec<_> = check_text("name", &self.name, 100).into_iter().collect();
+        errors.extend(scope_errors(&self.scopes));
+        errors.extend(expiry_error(self.expires_at));
+        errors
     }
 }
 
+impl Validate for UpdateApiKeyRequest {
+    fn validate(&self) -> Vec<(String, String)> {
+        let mut errors: Vec<_> = self
+            .name
+            .as_deref()
+            .and_then(|name| check_text("name", name, 100))
+            .into_iter()
+            .collect();
+        errors.extend(self.scopes.as_deref().map(scope_errors).unwrap_or_default());
+        errors.extend(expiry_error(self.expires_at));
+        errors
+    }
+}
+
+fn scope_errors(scopes: &[String]) -> Vec<(String, String)> {
+    scopes
+        .iter()
+        .enumerate()
+        .filter(|(_, scope)| !VALID_SCOPES.contains(&scope.as_str()))
+        .map(|(index, scope)| {
+            (
+                format!("scopes[{index}]"),
+                format!(
+                    "Invalid scope: {scope}. Valid scopes are: {}",
+                    VALID_SCOPES.join(", ")
+                ),
+            )
+        })
+        .collect()
+}
+
+/// A key that's expired on arrival could never authenticate
+fn expiry_error(expires_at: Option<DateTime<Utc>>) -> Option<(String, String)> {
+    expires_at
+        .filter(|expires_at| *expires_at <= Utc::now())
+        .map(|expires_at| {
+            (
+                "expires_at".to_string(),
+                format!("expires_at must be in the future, not {expires_at}"),
+            )
+        })
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    run(handler).await
+    run(|req| respond(scope(req, handler))).await
 }
 
 pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
+    if matches!(req.method().as_str(), "POST" | "PUT" | "PATCH" | "DELETE") {
+        if let Err(response) = ensure_writable().await {
+            return Ok(response);
+        }
+    }
+
     // Route based on HTTP method and use appropriate authentication strategy
     match req.method().as_str() {
         "POST" => {
@@ -120,13 +144,19 @@ pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
         "GET" | "PUT" | "PATCH" | "DELETE" => {
             // For API key management operations, accept both JWT and API key authentication
             // This allows both web users (JWT) and CLI users (API key) to manage their keys
-            let authenticated_user = match try_flexible_auth(&req).await {
+            let authenticated_user = match jwt_or_api_key_middleware(&req).await {
                 Ok(user) => user,
                 Err(error_response) => return Ok(error_response),
             };
 
-            // Ensure user has API key management scope
-            if let Err(error_response) = require_scope(&authenticated_user, "api_key_manage") {
+            // Ensure user has API key management scope. Listing shows no
+            // secrets, so any API key may list its owner's keys, as
+            // `carp token list` does.
+            let scope = match authenticated_user.auth_method {
+                AuthMethod::ApiKey { .. } if req.method() == "GET" => "read",
+                _ => "api_key_manage",
+            };
+            if let Err(error_response) = require_scope(&authenticated_user, scope) {
                 return Ok(error_response);
             }
 
@@ -168,6 +198,9 @@ async fn list_api_keys(
             scopes: vec!["read".to_string(), "write".to_string()],
             is_active: true,
             last_used_at: Some(Utc::now()),
+            last_used_ip: None,
+            last_used_user_agent: None,
+            last_used_country: None,
             expires_at: None,
             created_at: Utc::now(),
         }];
@@ -178,7 +211,7 @@ async fn list_api_keys(
             .body(serde_json::to_string(&mock_keys)?.into())?);
     }
 
-    let client = reqwest::Client::new();
+    let client = tenant::http_client();
 
     // Extract the original token to determine authentication method
     let user_token = extract_bearer_token(req);
@@ -203,13 +236,15 @@ async fn list_api_keys(
         .header("Content-Type", "application/json")
         .query(&[(
             "select",
-            "id,name,prefix,key_prefix,scopes,is_active,last_used_at,expires_at,created_at",
+            "id,name,prefix,key_prefix,scopes,is_active,last_used_at,last_used_ip,last_used_user_agent,last_used_country,expires_at,created_at",
         )]);
 
-    // Only add user_id filter when using service role (RLS won't handle it)
+    // Only add user_id and tenant filters when using service role (RLS won't handle it)
     if needs_user_filter {
         query_builder =
             query_builder.query(&[("user_id", format!("eq.{}", authenticated_user.user_id))]);
+        query_builder =
+            query_builder.query(&[("tenant_id", format!("eq.{}", tenant::current().id))]);
     }
 
     let response = query_builder.send().await?;
@@ -266,45 +301,11 @@ async fn create_api_key(
     // Extract the user's JWT token for database operations
     let user_jwt =
         extract_bearer_token(req).ok_or_else(|| Error::from("Missing authorization token"))?;
-    // Parse request body
-    let body_bytes = req.body();
-    let body_str = std::str::from_utf8(body_bytes)
-        .map_err(|_| Error::from("Invalid UTF-8 in request body"))?;
-
-    let create_request: CreateApiKeyRequest = match serde_json::from_str(body_str) {
-        Ok(req) => req,
-        Err(e) => {
-            let error = ApiError {
-                error: "bad_request".to_string(),
-                message: format!("Invalid JSON in request body: {e}"),
-                details: None,
-            };
-            return Ok(Response::builder()
-                .status(400)
-                .header("content-type", "application/json")
-                .body(serde_json::to_string(&error)?.into())?);
-        }
-    };
-
-    // Validate scopes
-    let valid_scopes = ["read", "write", "upload", "publish", "delete", "admin"];
-    for scope in &create_request.scopes {
-        if !valid_scopes.contains(&scope.as_str()) {
-            let error = ApiError {
-                error: "invalid_scope".to_string(),
-                message: format!(
-                    "Invalid scope: {}. Valid scopes are: {}",
-                    scope,
-                    valid_scopes.join(", ")
-                ),
-                details: None,
-            };
-            return Ok(Response::builder()
-                .status(400)
-                .header("content-type", "application/json")
-                .body(serde_json::to_string(&error)?.into())?);
-        }
-    }
+    let ValidatedJson(create_request) =
+        match ValidatedJson::<CreateApiKeyRequest>::from_request(req) {
+            Ok(request) => request,
+            Err(response) => return Ok(response),
+        };
 
     // Generate new API key
     let api_key = generate_api_key();
@@ -323,6 +324,9 @@ async fn create_api_key(
             scopes: create_request.scopes,
             is_active: true,
             last_used_at: None,
+            last_used_ip: None,
+            last_used_user_agent: None,
+            last_used_country: None,
             expires_at: create_request.expires_at,
             created_at: Utc::now(),
         };
@@ -338,7 +342,7 @@ async fn create_api_key(
             .body(serde_json::to_string(&response)?.into())?);
     }
 
-    let client = reqwest::Client::new();
+    let client = tenant::http_client();
 
     // Insert new API key into database
     // Note: Database has both 'prefix' and 'key_prefix' columns due to migration history
@@ -441,25 +445,11 @@ async fn update_api_key(
         }
     };
 
-    // Parse request body
-    let body_bytes = req.body();
-    let body_str = std::str::from_utf8(body_bytes)
-        .map_err(|_| Error::from("Invalid UTF-8 in request body"))?;
-
-    let _update_request: UpdateApiKeyRequest = match serde_json::from_str(body_str) {
-        Ok(req) => req,
-        Err(e) => {
-            let error = ApiError {
-                error: "bad_request".to_string(),
-                message: format!("Invalid JSON in request body: {e}"),
-                details: None,
-            };
-            return Ok(Response::builder()
-                .status(400)
-                .header("content-type", "application/json")
-                .body(serde_json::to_string(&error)?.into())?);
-        }
-    };
+    let ValidatedJson(_update_request) =
+        match ValidatedJson::<UpdateApiKeyRequest>::from_request(req) {
+            Ok(request) => request,
+            Err(response) => return Ok(response),
+        };
 
     // TODO: Implement API key update logic
     let error = ApiError {
@@ -508,15 +498,16 @@ async fn delete_api_key(
         return Ok(Response::builder().status(204).body("".into())?);
     }
 
-    let client = reqwest::Client::new();
+    let client = tenant::http_client();
 
-    // Delete the API key (only if owned by the user)
+    // Delete the API key (only if owned by the user, in this tenant)
     let response = client
         .delete(format!("{supabase_url}/rest/v1/api_keys"))
         .header("apikey", &supabase_key)
         .header("Authorization", format!("Bearer {supabase_key}"))
         .query(&[("id", format!("eq.{key_id}"))])
         .query(&[("user_id", format!("eq.{}", authenticated_user.user_id))])
+        .query(&[("tenant_id", format!("eq.{}", tenant::current().id))])
         .send()
         .await?;
 
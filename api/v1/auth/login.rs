@@ -5,6 +5,11 @@ use serde_json::json;
 // use std::env; // Not used in this file
 use vercel_runtime::{run, Body, Error, Request, Response};
 
+use shared::problem::respond;
+use shared::tenant::scope;
+use shared::validation::check_text;
+use shared::{ApiError, Validate, ValidatedJson};
+
 /// Authentication request
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AuthRequest {
@@ -12,6 +17,18 @@ pub struct AuthRequest {
     pub password: String,
 }
 
+impl Validate for AuthRequest {
+    fn validate(&self) -> Vec<(String, String)> {
+        [
+            check_text("username", &self.username, 100),
+            check_text("password", &self.password, 1024),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+}
+
 /// Authentication response
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AuthResponse {
@@ -19,35 +36,15 @@ pub struct AuthResponse {
     pub expires_at: DateTime<Utc>,
 }
 
-/// API error response
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ApiError {
-    pub error: String,
-    pub message: String,
-    pub details: Option<serde_json::Value>,
-}
-
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    run(handler).await
+    run(|req| respond(scope(req, handler))).await
 }
 
 pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
-    // Parse request body
-    let body = req.body();
-    let auth_request: AuthRequest = match serde_json::from_slice(body) {
-        Ok(req) => req,
-        Err(_) => {
-            let error = ApiError {
-                error: "bad_request".to_string(),
-                message: "Invalid JSON in request body".to_string(),
-                details: None,
-            };
-            return Ok(Response::builder()
-                .status(400)
-                .header("content-type", "application/json")
-                .body(serde_json::to_string(&error)?.into())?);
-        }
+    let ValidatedJson(auth_request) = match ValidatedJson::<AuthRequest>::from_request(&req) {
+        Ok(request) => request,
+        Err(response) => return Ok(response),
     };
 
     // For now, implement basic authentication (in production, use proper auth)
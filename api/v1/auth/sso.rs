@@ -0,0 +1,120 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use vercel_runtime::{run, Body, Error, Request, Response};
+
+use shared::problem::respond;
+use shared::sso::{
+    callback_url, IdentityProvider, PendingLogin, Provider, SsoConfig, LOGIN_COOKIE, LOGIN_TTL_SECS,
+};
+use shared::tenant::{self, scope};
+use shared::ApiError;
+
+#[derive(Debug, Serialize)]
+struct ProvidersResponse {
+    providers: Vec<String>,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    run(|req| respond(scope(req, handler))).await
+}
+
+/// Starts single sign-on: with `provider`, or an `email` whose domain picks
+/// one, redirects to the identity provider; with neither, lists the
+/// providers. `redirect_to` is the path to return to once signed in.
+pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
+    if req.method() != "GET" {
+        return error_response(
+            405,
+            "method_not_allowed",
+            "Only GET requests are allowed".to_string(),
+        );
+    }
+
+    let config = match SsoConfig::from_env() {
+        Ok(config) => config,
+        Err(e) => return error_response(500, "sso_misconfigured", e),
+    };
+
+    let query = req.uri().query().unwrap_or("");
+    let params: HashMap<String, String> = url::form_urlencoded::parse(query.as_bytes())
+        .into_owned()
+        .collect();
+
+    let provider = match (params.get("provider"), params.get("email")) {
+        (Some(id), _) => config.provider(id),
+        (None, Some(email)) => config.provider_for_email(email),
+        (None, None) => {
+            let response = ProvidersResponse {
+                providers: config.providers.iter().map(|p| p.id.clone()).collect(),
+            };
+            return Ok(Response::builder()
+                .status(200)
+                .header("content-type", "application/json")
+                .body(serde_json::to_string(&response)?.into())?);
+        }
+    };
+    let Some(provider) = provider else {
+        return error_response(
+            404,
+            "sso_provider_not_found",
+            "No single sign-on provider matches".to_string(),
+        );
+    };
+
+    let jwt_secret = tenant::current().jwt_secret();
+    if jwt_secret.is_empty() {
+        return error_response(
+            503,
+            "sso_unavailable",
+            "Single sign-on needs SUPABASE_JWT_SECRET".to_string(),
+        );
+    }
+
+    let connected = match Provider::connect(provider).await {
+        Ok(connected) => connected,
+        Err(e) => {
+            eprintln!("ERROR: {e:#}");
+            return error_response(
+                502,
+                "sso_provider_unavailable",
+                format!("Couldn't reach single sign-on provider '{}'", provider.id),
+            );
+        }
+    };
+
+    let login = PendingLogin::new(&provider.id, params.get("redirect_to").map(String::as_str));
+    let sealed = login
+        .seal(&jwt_secret)
+        .map_err(|e| Error::from(format!("{e:#}")))?;
+    let location = connected.authorization_url(&login, &callback_url(&req));
+
+    Ok(Response::builder()
+        .status(302)
+        .header("location", location)
+        .header(
+            "set-cookie",
+            format!(
+                "{LOGIN_COOKIE}={sealed}; Path=/api/v1/auth/sso; Max-Age={LOGIN_TTL_SECS}; HttpOnly; Secure; SameSite=Lax"
+            ),
+        )
+        .header("Cache-Control", "no-store")
+        .body(Body::Empty)?)
+}
+
+fn error_response(status: u16, error: &str, message: String) -> Result<Response<Body>, Error> {
+    let error = ApiError {
+        error: error.to_string(),
+        message,
+        details: None,
+    };
+
+    let mut builder = Response::builder()
+        .status(status)
+        .header("content-type", "application/json");
+    if status == 405 {
+        builder = builder.header("allow", "GET");
+    }
+
+    Ok(builder.body(serde_json::to_string(&error)?.into())?)
+}
@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use vercel_runtime::{run, Body, Error, Request, Response};
+
+use shared::problem::respond;
+use shared::registry::service_config;
+use shared::sso::{
+    callback_url, cookie, issue_session, IdentityProvider, PendingLogin, Provider, SsoConfig,
+    SsoStore, LOGIN_COOKIE, SESSION_TTL_SECS,
+};
+use shared::tenant::{self, scope};
+use shared::ApiError;
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    run(|req| respond(scope(req, handler))).await
+}
+
+/// Finishes single sign-on: checks who the identity provider says signed in,
+/// signs them in to their account, creating it if needed, and sends them on
+/// with a session token in the URL fragment
+pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
+    if req.method() != "GET" {
+        return error_response(
+            405,
+            "method_not_allowed",
+            "Only GET requests are allowed".to_string(),
+        );
+    }
+
+    let query = req.uri().query().unwrap_or("");
+    let params: HashMap<String, String> = url::form_urlencoded::parse(query.as_bytes())
+        .into_owned()
+        .collect();
+
+    if let Some(error) = params.get("error") {
+        let description = params
+            .get("error_description")
+            .map(|d| format!(": {d}"))
+            .unwrap_or_default();
+        return error_response(
+            401,
+            "sso_failed",
+            format!("The identity provider refused the sign-in ({error}){description}"),
+        );
+    }
+
+    let jwt_secret = tenant::current().jwt_secret();
+    if jwt_secret.is_empty() {
+        return error_response(
+            503,
+            "sso_unavailable",
+            "Single sign-on needs SUPABASE_JWT_SECRET".to_string(),
+        );
+    }
+
+    let sealed = req
+        .headers()
+        .get("cookie")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|header| cookie(header, LOGIN_COOKIE));
+    let login = match sealed.map(|sealed| PendingLogin::open(sealed, &jwt_secret)) {
+        Some(Ok(login)) if params.get("state") == Some(&login.state) => login,
+        _ => {
+            return error_response(
+                400,
+                "sso_session_invalid",
+                "This sign-in expired or was started in another browser; please try again"
+                    .to_string(),
+            )
+        }
+    };
+    let Some(code) = params.get("code") else {
+        return error_response(
+            400,
+            "bad_request",
+            "The identity provider sent no code".to_string(),
+        );
+    };
+
+    let config = match SsoConfig::from_env() {
+        Ok(config) => config,
+        Err(e) => return error_response(500, "sso_misconfigured", e),
+    };
+    let Some(provider) = config.provider(&login.provider) else {
+        return error_response(
+            404,
+            "sso_provider_not_found",
+            format!("Single sign-on provider '{}' is gone", login.provider),
+        );
+    };
+
+    let identity = match Provider::connect(provider).await {
+        Ok(connected) => connected.identify(code, &login, &callback_url(&req)).await,
+        Err(e) => Err(e),
+    };
+    let identity = match identity {
+        Ok(identity) => identity,
+        Err(e) => {
+            eprintln!("ERROR: Single sign-on with '{}' failed: {e:#}", provider.id);
+            return error_response(401, "sso_failed", format!("{e}"));
+        }
+    };
+
+    let (supabase_url, supabase_key) = service_config().map_err(Error::from)?;
+    let store = SsoStore::new(supabase_url, supabase_key);
+    let user = store
+        .sign_in(&identity, provider.organization.as_deref())
+        .await
+        .map_err(|e| Error::from(format!("{e:#}")))?;
+    let (token, _) = issue_session(user.user_id, &identity.email, &provider.id, &jwt_secret)
+        .map_err(|e| Error::from(format!("{e:#}")))?;
+
+    let fragment = url::form_urlencoded::Serializer::new(String::new())
+        .append_pair("access_token", &token)
+        .append_pair("token_type", "bearer")
+        .append_pair("expires_in", &SESSION_TTL_SECS.to_string())
+        .append_pair("provider", &format!("sso:{}", provider.id))
+        .finish();
+
+    Ok(Response::builder()
+        .status(302)
+        .header("location", format!("{}#{fragment}", login.redirect_to))
+        .header(
+            "set-cookie",
+            format!(
+                "{LOGIN_COOKIE}=; Path=/api/v1/auth/sso; Max-Age=0; HttpOnly; Secure; SameSite=Lax"
+            ),
+        )
+        .header("Cache-Control", "no-store")
+        .body(Body::Empty)?)
+}
+
+fn error_response(status: u16, error: &str, message: String) -> Result<Response<Body>, Error> {
+    let error = ApiError {
+        error: error.to_string(),
+        message,
+        details: None,
+    };
+
+    let mut builder = Response::builder()
+        .status(status)
+        .header("content-type", "application/json");
+    if status == 405 {
+        builder = builder.header("allow", "GET");
+    }
+
+    Ok(builder.body(serde_json::to_string(&error)?.into())?)
+}
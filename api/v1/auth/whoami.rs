@@ -0,0 +1,62 @@
+use serde::Serialize;
+use uuid::Uuid;
+use vercel_runtime::{run, Body, Error, Request, Response};
+
+use shared::problem::respond;
+use shared::tenant::scope;
+use shared::{api_key_middleware, ApiError, AuthMethod};
+
+/// The identity behind the API key on the request
+#[derive(Debug, Serialize)]
+pub struct WhoAmIResponse {
+    pub user_id: Uuid,
+    pub key_id: Option<Uuid>,
+    pub scopes: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub github_username: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    run(|req| respond(scope(req, handler))).await
+}
+
+/// Lets clients check that a key is valid and what it may do, without
+/// side effects
+pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
+    if req.method() != "GET" {
+        let error = ApiError {
+            error: "method_not_allowed".to_string(),
+            message: "Only GET requests are allowed".to_string(),
+            details: None,
+        };
+        return Ok(Response::builder()
+            .status(405)
+            .header("content-type", "application/json")
+            .header("allow", "GET")
+            .body(serde_json::to_string(&error)?.into())?);
+    }
+
+    let user = match api_key_middleware(&req).await {
+        Ok(user) => user,
+        Err(error_response) => return Ok(error_response),
+    };
+
+    let key_id = match user.auth_method {
+        AuthMethod::ApiKey { key_id } => Some(key_id),
+        AuthMethod::JwtToken { .. } => None,
+    };
+
+    let response = WhoAmIResponse {
+        user_id: user.user_id,
+        key_id,
+        scopes: user.scopes,
+        github_username: user.metadata.github_username,
+    };
+
+    Ok(Response::builder()
+        .status(200)
+        .header("content-type", "application/json")
+        .header("Cache-Control", "no-store")
+        .body(serde_json::to_string(&response)?.into())?)
+}
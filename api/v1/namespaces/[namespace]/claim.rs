@@ -0,0 +1,244 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use vercel_runtime::{run, Body, Error, Request, Response};
+
+use shared::namespaces::{parse_namespace, ClaimMethod, NamespaceStore, ProofChecker};
+use shared::problem::respond;
+use shared::registry::service_config;
+use shared::tenant::scope;
+use shared::validation::validation_failed;
+use shared::{
+    api_key_middleware, ensure_writable, require_scope, ApiError, Validate, ValidatedJson,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct ClaimRequest {
+    /// `github`, `dns` or `url`
+    pub method: String,
+}
+
+impl Validate for ClaimRequest {
+    fn validate(&self) -> Vec<(String, String)> {
+        if ClaimMethod::parse(&self.method).is_some() {
+            return Vec::new();
+        }
+        vec![(
+            "method".to_string(),
+            format!(
+                "Unknown method '{}'; expected github, dns or url",
+                self.method
+            ),
+        )]
+    }
+}
+
+/// Where to publish the value proving control of the domain
+#[derive(Debug, Serialize)]
+pub struct ChallengeInstructions {
+    /// DNS TXT record name, or URL of the file
+    pub location: String,
+    pub value: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// How a claim stands
+#[derive(Debug, Serialize)]
+pub struct ClaimResponse {
+    pub namespace: String,
+    pub method: ClaimMethod,
+    pub verified: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub domain: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verified_at: Option<DateTime<Utc>>,
+    /// What to publish before claiming again, until the claim is verified
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub challenge: Option<ChallengeInstructions>,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    run(|req| respond(scope(req, handler))).await
+}
+
+/// Claims the namespace matching a GitHub organization. A `github` claim is
+/// checked at once. `dns` and `url` claims answer 202 with a challenge to
+/// publish on the organization's website domain; the same request made
+/// again once it's published verifies the claim.
+pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
+    if req.method() != "POST" {
+        return error_response(
+            405,
+            "method_not_allowed",
+            "Only POST requests are allowed".to_string(),
+        );
+    }
+
+    if let Err(response) = ensure_writable().await {
+        return Ok(response);
+    }
+
+    let authenticated_user = match api_key_middleware(&req).await {
+        Ok(user) => user,
+        Err(error_response) => return Ok(error_response),
+    };
+
+    if let Err(error_response) = require_scope(&authenticated_user, "write") {
+        return Ok(error_response);
+    }
+
+    // Expected format: api/v1/namespaces/{namespace}/claim
+    let path_segments: Vec<&str> = req
+        .uri()
+        .path()
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .collect();
+
+    if path_segments.len() < 5 {
+        return error_response(
+            400,
+            "bad_request",
+            "Invalid path format. Expected /api/v1/namespaces/{namespace}/claim".to_string(),
+        );
+    }
+
+    let requested = urlencoding::decode(path_segments[3])
+        .map_err(|_| Error::from("Invalid namespace encoding"))?;
+    let Some(namespace) = parse_namespace(&requested) else {
+        return error_response(
+            400,
+            "validation_failed",
+            format!("'{requested}' is not a valid namespace"),
+        );
+    };
+
+    let ValidatedJson(request) = match ValidatedJson::<ClaimRequest>::from_request(&req) {
+        Ok(request) => request,
+        Err(response) => return Ok(response),
+    };
+    let Some(method) = ClaimMethod::parse(&request.method) else {
+        return Ok(validation_failed(&request.validate()));
+    };
+
+    let checker = ProofChecker::from_env();
+    let Some(org) = checker
+        .github_org(&namespace)
+        .await
+        .map_err(|e| Error::from(format!("{e:#}")))?
+    else {
+        return error_response(
+            404,
+            "not_found",
+            format!("There is no GitHub organization named '{namespace}' to verify against"),
+        );
+    };
+
+    let (supabase_url, supabase_key) = service_config().map_err(Error::from)?;
+    let store = NamespaceStore::new(supabase_url, supabase_key);
+    let user_id = authenticated_user.user_id;
+
+    let domain = match method {
+        ClaimMethod::Github => {
+            let Some(username) = authenticated_user.metadata.github_username.as_deref() else {
+                return error_response(
+                    400,
+                    "no_github_identity",
+                    "Your account has no GitHub identity; sign in with GitHub, or claim by dns or url"
+                        .to_string(),
+                );
+            };
+            let member = checker
+                .is_public_member(&org.login, username)
+                .await
+                .map_err(|e| Error::from(format!("{e:#}")))?;
+            if !member {
+                return error_response(
+                    422,
+                    "not_verified",
+                    format!(
+                        "'{username}' is not a public member of the GitHub organization '{}'; make your membership public and claim again",
+                        org.login
+                    ),
+                );
+            }
+            None
+        }
+        ClaimMethod::Dns | ClaimMethod::Url => {
+            let Some(domain) = org.domain() else {
+                return error_response(
+                    422,
+                    "no_domain",
+                    format!(
+                        "The GitHub organization '{}' lists no website to publish a challenge on; claim by github instead",
+                        org.login
+                    ),
+                );
+            };
+
+            let challenge = store
+                .challenge(&namespace, user_id, method, &domain)
+                .await
+                .map_err(|e| Error::from(format!("{e:#}")))?;
+            // An unreachable domain is the same as an unpublished challenge
+            if !checker.is_published(&challenge).await.unwrap_or(false) {
+                let response = ClaimResponse {
+                    namespace,
+                    method,
+                    verified: false,
+                    domain: Some(domain),
+                    verified_at: None,
+                    challenge: Some(ChallengeInstructions {
+                        location: challenge.location(),
+                        value: challenge.value(),
+                        expires_at: challenge.expires_at,
+                    }),
+                };
+                return json_response(202, &response);
+            }
+            Some(domain)
+        }
+    };
+
+    let verified = store
+        .verify(&namespace, user_id, method, domain.as_deref())
+        .await
+        .map_err(|e| Error::from(format!("{e:#}")))?;
+
+    json_response(
+        200,
+        &ClaimResponse {
+            namespace: verified.slug,
+            method: verified.verification_method,
+            verified: true,
+            domain: verified.verified_domain,
+            verified_at: Some(verified.verified_at),
+            challenge: None,
+        },
+    )
+}
+
+fn json_response(status: u16, body: &ClaimResponse) -> Result<Response<Body>, Error> {
+    Ok(Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .header("Cache-Control", "no-store")
+        .body(serde_json::to_string(body)?.into())?)
+}
+
+fn error_response(status: u16, error: &str, message: String) -> Result<Response<Body>, Error> {
+    let error = ApiError {
+        error: error.to_string(),
+        message,
+        details: None,
+    };
+
+    let mut builder = Response::builder()
+        .status(status)
+        .header("content-type", "application/json");
+    if status == 405 {
+        builder = builder.header("allow", "POST");
+    }
+
+    Ok(builder.body(serde_json::to_string(&error)?.into())?)
+}
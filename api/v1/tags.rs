@@ -0,0 +1,125 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use vercel_runtime::{run, Body, Error, Request, Response};
+
+use shared::problem::respond;
+use shared::tenant::{self, scope};
+use shared::ApiError;
+
+/// Tags returned when no limit is given
+const DEFAULT_LIMIT: usize = 100;
+
+/// Upper bound on `limit`, matching the cap in `tag_counts`
+const MAX_LIMIT: usize = 1000;
+
+/// A tag and how many public agents use it
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TagCount {
+    pub tag: String,
+    pub agent_count: u64,
+}
+
+/// Tag popularity response
+#[derive(Debug, Serialize)]
+pub struct TagsResponse {
+    pub tags: Vec<TagCount>,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    run(|req| respond(scope(req, handler))).await
+}
+
+/// Lists the tags used by public agents, most used first
+pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
+    // Handle CORS preflight
+    if req.method() == "OPTIONS" {
+        return Ok(Response::builder()
+            .status(200)
+            .header("Access-Control-Allow-Origin", "*")
+            .header("Access-Control-Allow-Methods", "GET, OPTIONS")
+            .header("Access-Control-Allow-Headers", "Content-Type")
+            .body(Body::Empty)?);
+    }
+
+    if req.method() != "GET" {
+        let error = ApiError {
+            error: "method_not_allowed".to_string(),
+            message: "Only GET requests are allowed".to_string(),
+            details: None,
+        };
+        return Ok(Response::builder()
+            .status(405)
+            .header("content-type", "application/json")
+            .header("allow", "GET, OPTIONS")
+            .body(serde_json::to_string(&error)?.into())?);
+    }
+
+    let query = req.uri().query().unwrap_or("");
+    let params: HashMap<String, String> = url::form_urlencoded::parse(query.as_bytes())
+        .into_owned()
+        .collect();
+
+    let limit = params
+        .get("limit")
+        .and_then(|s| s.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_LIMIT)
+        .clamp(1, MAX_LIMIT);
+
+    let tags = get_tag_counts(limit).await?;
+
+    Ok(Response::builder()
+        .status(200)
+        .header("content-type", "application/json")
+        .header("Cache-Control", "public, max-age=300")
+        .header("Access-Control-Allow-Origin", "*")
+        .header("Access-Control-Allow-Methods", "GET, OPTIONS")
+        .header("Access-Control-Allow-Headers", "Content-Type")
+        .body(serde_json::to_string(&TagsResponse { tags })?.into())?)
+}
+
+async fn get_tag_counts(limit: usize) -> Result<Vec<TagCount>, Error> {
+    let supabase_url = env::var("SUPABASE_URL").unwrap_or_default();
+    // Only public agents are counted, so the anon key is enough; `tag_counts`
+    // keeps to the request's tenant with either key
+    let supabase_key = env::var("SUPABASE_ANON_KEY")
+        .or_else(|_| env::var("SUPABASE_SERVICE_ROLE_KEY"))
+        .unwrap_or_default();
+
+    if supabase_url.is_empty() || supabase_key.is_empty() {
+        return Err(Error::from(
+            "Database not configured - missing SUPABASE_URL or SUPABASE_ANON_KEY",
+        ));
+    }
+
+    let client =
+        tenant::database(format!("{supabase_url}/rest/v1")).insert_header("apikey", &supabase_key);
+
+    let response = client
+        .rpc(
+            "tag_counts",
+            serde_json::json!({ "max_tags": limit }).to_string(),
+        )
+        .execute()
+        .await
+        .map_err(|e| Error::from(format!("Tag count query failed: {e}")))?;
+
+    if !response.status().is_success() {
+        let status = response.status();
+        let error_text = response
+            .text()
+            .await
+            .unwrap_or_else(|_| "Unknown error".to_string());
+        return Err(Error::from(format!(
+            "Tag count query failed with status {status}: {error_text}"
+        )));
+    }
+
+    let body = response
+        .text()
+        .await
+        .map_err(|e| Error::from(format!("Failed to read response: {e}")))?;
+
+    serde_json::from_str(&body).map_err(|e| Error::from(format!("Failed to parse tags: {e}")))
+}
@@ -0,0 +1,171 @@
+use serde::{Deserialize, Serialize};
+use std::env;
+use vercel_runtime::{run, Body, Error, Request, Response};
+
+use shared::problem::respond;
+use shared::tenant::{self, scope};
+use shared::ApiError;
+
+/// Anonymous usage event reported by the CLI when telemetry is enabled.
+///
+/// Events are accepted without authentication and nothing identifying the
+/// sender (IP address, user agent, API key) is stored with them.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TelemetryEvent {
+    pub command: String,
+    pub duration_ms: u64,
+    pub success: bool,
+    #[serde(default)]
+    pub error_kind: Option<String>,
+    pub cli_version: String,
+    pub os: String,
+    pub arch: String,
+}
+
+/// Events are a few hundred bytes; anything bigger isn't from the CLI
+const MAX_EVENT_SIZE: usize = 4 * 1024;
+
+/// Longest command duration worth recording (one day)
+const MAX_DURATION_MS: u64 = 24 * 60 * 60 * 1000;
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    run(|req| respond(scope(req, handler))).await
+}
+
+pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
+    if req.method() != "POST" {
+        return error_response(
+            405,
+            "method_not_allowed",
+            "Only POST requests are allowed".to_string(),
+        );
+    }
+
+    if req.body().len() > MAX_EVENT_SIZE {
+        return error_response(
+            413,
+            "payload_too_large",
+            format!("Telemetry events cannot exceed {MAX_EVENT_SIZE} bytes"),
+        );
+    }
+
+    let event: TelemetryEvent = match serde_json::from_slice(req.body()) {
+        Ok(event) => event,
+        Err(e) => {
+            return error_response(400, "bad_request", format!("Invalid telemetry event: {e}"))
+        }
+    };
+
+    if let Err(message) = validate_event(&event) {
+        return error_response(400, "validation_failed", message);
+    }
+
+    if let Err(e) = store_event(&event).await {
+        // Telemetry is best effort; don't make the CLI care
+        eprintln!("DEBUG: Failed to store telemetry event: {e}");
+    }
+
+    Ok(Response::builder()
+        .status(202)
+        .header("Cache-Control", "no-store")
+        .body(Body::Empty)?)
+}
+
+/// Fields are short identifiers, never free text, so arguments or error
+/// messages can't slip into the table
+fn validate_event(event: &TelemetryEvent) -> Result<(), String> {
+    let identifiers = [
+        ("command", Some(event.command.as_str())),
+        ("error_kind", event.error_kind.as_deref()),
+        ("os", Some(event.os.as_str())),
+        ("arch", Some(event.arch.as_str())),
+    ];
+
+    for (field, value) in identifiers {
+        if let Some(value) = value {
+            if !is_identifier(value) {
+                return Err(format!(
+                    "{field} must be 1-32 lowercase letters, digits, '-' or '_'"
+                ));
+            }
+        }
+    }
+
+    let is_version = !event.cli_version.is_empty()
+        && event.cli_version.len() <= 32
+        && event
+            .cli_version
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '+'));
+    if !is_version {
+        return Err("cli_version must be a version number".to_string());
+    }
+
+    if event.duration_ms > MAX_DURATION_MS {
+        return Err("duration_ms is out of range".to_string());
+    }
+
+    if event.success && event.error_kind.is_some() {
+        return Err("Successful commands cannot have an error_kind".to_string());
+    }
+
+    Ok(())
+}
+
+fn is_identifier(value: &str) -> bool {
+    !value.is_empty()
+        && value.len() <= 32
+        && value
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+}
+
+async fn store_event(event: &TelemetryEvent) -> Result<(), Error> {
+    let supabase_url = env::var("SUPABASE_URL").unwrap_or_default();
+    let supabase_key = env::var("SUPABASE_SERVICE_ROLE_KEY").unwrap_or_default();
+
+    if supabase_url.is_empty() || supabase_key.is_empty() {
+        return Err(Error::from(
+            "Database not configured - missing SUPABASE_URL or SUPABASE_SERVICE_ROLE_KEY",
+        ));
+    }
+
+    let response = tenant::http_client()
+        .post(format!("{supabase_url}/rest/v1/telemetry_events"))
+        .header("apikey", &supabase_key)
+        .header("Authorization", format!("Bearer {supabase_key}"))
+        .header("Prefer", "return=minimal")
+        .json(event)
+        .send()
+        .await
+        .map_err(|e| Error::from(format!("Database insert failed: {e}")))?;
+
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        return Err(Error::from(format!(
+            "Database insert failed with status {status}: {body}"
+        )));
+    }
+
+    Ok(())
+}
+
+fn error_response(status: u16, error: &str, message: String) -> Result<Response<Body>, Error> {
+    let error = ApiError {
+        error: error.to_string(),
+        message,
+        details: None,
+    };
+
+    let mut builder = Response::builder()
+        .status(status)
+        .header("content-type", "application/json");
+    if status == 405 {
+        builder = builder.header("allow", "POST");
+    }
+
+    Ok(builder.body(serde_json::to_string(&error)?.into())?)
+}
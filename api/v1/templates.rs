@@ -0,0 +1,104 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use vercel_runtime::{run, Body, Error, Request, Response};
+
+use shared::problem::respond;
+use shared::registry::search::{search, Agent, SearchError, SearchMode, SearchQuery, SearchSort};
+use shared::tenant::scope;
+use shared::{optional_api_key_middleware, AgentVisibility, ApiError};
+
+/// Templates returned when no limit is given
+const DEFAULT_LIMIT: usize = 20;
+
+/// Upper bound on `limit`
+const MAX_LIMIT: usize = 100;
+
+/// Agents flagged as templates
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TemplatesResponse {
+    pub templates: Vec<Agent>,
+    pub total: usize,
+    pub page: usize,
+    pub per_page: usize,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    run(|req| respond(scope(req, handler))).await
+}
+
+/// Lists the agents published as templates for `carp new`, most downloaded
+/// first, optionally matching `q` like search does
+pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
+    if req.method() != "GET" {
+        let error = ApiError {
+            error: "method_not_allowed".to_string(),
+            message: "Only GET requests are allowed".to_string(),
+            details: None,
+        };
+        return Ok(Response::builder()
+            .status(405)
+            .header("content-type", "application/json")
+            .header("allow", "GET")
+            .body(serde_json::to_string(&error)?.into())?);
+    }
+
+    // Signed-in callers also see their own private templates
+    let authenticated_user = optional_api_key_middleware(&req).await;
+    let visibility = AgentVisibility::for_user(authenticated_user.as_ref())
+        .await
+        .map_err(Error::from)?;
+
+    let query = req.uri().query().unwrap_or("");
+    let params: HashMap<String, String> = url::form_urlencoded::parse(query.as_bytes())
+        .into_owned()
+        .collect();
+    let limit = params
+        .get("limit")
+        .and_then(|s| s.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_LIMIT)
+        .clamp(1, MAX_LIMIT);
+    let page = params
+        .get("page")
+        .and_then(|s| s.parse::<usize>().ok())
+        .unwrap_or(1)
+        .max(1);
+
+    let search_query = SearchQuery {
+        query: params.get("q").map(|s| s.trim()).unwrap_or(""),
+        mode: SearchMode::Substring,
+        sort: SearchSort::Downloads,
+        limit,
+        page,
+        templates_only: true,
+    };
+    let (templates, total) = match search(&search_query, &visibility).await {
+        Ok(results) => results,
+        Err(SearchError::InvalidPattern(message)) => {
+            let error = ApiError {
+                error: "invalid_pattern".to_string(),
+                message,
+                details: None,
+            };
+            return Ok(Response::builder()
+                .status(400)
+                .header("content-type", "application/json")
+                .body(serde_json::to_string(&error)?.into())?);
+        }
+        Err(SearchError::Internal(e)) => return Err(Error::from(e)),
+    };
+
+    let response_body = TemplatesResponse {
+        templates,
+        total,
+        page,
+        per_page: limit,
+    };
+
+    Ok(Response::builder()
+        .status(200)
+        .header("content-type", "application/json")
+        .header("Cache-Control", visibility.cache_control())
+        .header("Vary", "Authorization")
+        .body(serde_json::to_string(&response_body)?.into())?)
+}
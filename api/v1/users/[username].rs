@@ -0,0 +1,225 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::env;
+use uuid::Uuid;
+use vercel_runtime::{run, Body, Error, Request, Response};
+
+use shared::problem::respond;
+use shared::tenant::{self, scope};
+use shared::ApiError;
+
+/// Columns of each published agent in a profile
+const AGENT_COLUMNS: &str = "name,current_version,description,created_at,updated_at,download_count,star_count,tags,homepage,repository,license,verified";
+
+/// Public columns of a profile row
+#[derive(Debug, Deserialize)]
+struct DbProfile {
+    user_id: Uuid,
+    github_username: String,
+    display_name: Option<String>,
+    avatar_url: Option<String>,
+    bio: Option<String>,
+    created_at: DateTime<Utc>,
+    verified_at: Option<DateTime<Utc>>,
+}
+
+/// An agent published by the user
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuthorAgent {
+    pub name: String,
+    #[serde(rename(deserialize = "current_version"))]
+    pub version: String,
+    pub description: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub download_count: u64,
+    pub star_count: u64,
+    #[serde(deserialize_with = "null_as_empty")]
+    pub tags: Vec<String>,
+    pub homepage: Option<String>,
+    pub repository: Option<String>,
+    pub license: Option<String>,
+    /// Also true for agents in a verified namespace
+    pub verified: bool,
+}
+
+/// A user's public profile and the public agents they publish
+#[derive(Debug, Serialize)]
+pub struct UserProfile {
+    pub username: String,
+    pub display_name: Option<String>,
+    pub avatar_url: Option<String>,
+    pub bio: Option<String>,
+    pub joined_at: DateTime<Utc>,
+    /// An admin vouched for the user
+    pub verified: bool,
+    pub agent_count: usize,
+    pub total_downloads: u64,
+    pub total_stars: u64,
+    pub agents: Vec<AuthorAgent>,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    run(|req| respond(scope(req, handler))).await
+}
+
+pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
+    if req.method() != "GET" {
+        return error_response(
+            405,
+            "method_not_allowed",
+            "Only GET requests are allowed".to_string(),
+        );
+    }
+
+    // Expected format: api/v1/users/{username}
+    let path_segments: Vec<&str> = req
+        .uri()
+        .path()
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .collect();
+
+    if path_segments.len() < 4 {
+        return error_response(
+            400,
+            "bad_request",
+            "Invalid path format. Expected /api/v1/users/{username}".to_string(),
+        );
+    }
+
+    let username = urlencoding::decode(path_segments[3])
+        .map_err(|_| Error::from("Invalid username encoding"))?
+        .into_owned();
+
+    if !is_valid_username(&username) {
+        return error_response(
+            400,
+            "invalid_username",
+            "Usernames are 1-39 letters, digits or hyphens".to_string(),
+        );
+    }
+
+    match get_profile(&username).await? {
+        Some(profile) => Ok(Response::builder()
+            .status(200)
+            .header("content-type", "application/json")
+            .header("Cache-Control", "public, max-age=60")
+            .body(serde_json::to_string(&profile)?.into())?),
+        None => error_response(404, "not_found", format!("User '{username}' not found")),
+    }
+}
+
+/// GitHub's username rules, which also keep the value safe to use in a
+/// PostgREST filter without escaping
+fn is_valid_username(username: &str) -> bool {
+    (1..=39).contains(&username.len())
+        && username
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+fn null_as_empty<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(Option::<Vec<String>>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+async fn get_profile(username: &str) -> Result<Option<UserProfile>, Error> {
+    let supabase_url = env::var("SUPABASE_URL").unwrap_or_default();
+    // Profiles and public agents are readable by anyone, so the anon key is enough
+    let supabase_key = env::var("SUPABASE_ANON_KEY")
+        .or_else(|_| env::var("SUPABASE_SERVICE_ROLE_KEY"))
+        .unwrap_or_default();
+
+    if supabase_url.is_empty() || supabase_key.is_empty() {
+        return Err(Error::from(
+            "Database not configured - missing SUPABASE_URL or SUPABASE_ANON_KEY",
+        ));
+    }
+
+    let client =
+        tenant::database(format!("{supabase_url}/rest/v1")).insert_header("apikey", &supabase_key);
+
+    // GitHub usernames are case-insensitive
+    let body = fetch(
+        client
+            .from("profiles")
+            .select("user_id,github_username,display_name,avatar_url,bio,created_at,verified_at")
+            .ilike("github_username", username)
+            .limit(1),
+    )
+    .await?;
+    let profiles: Vec<DbProfile> = serde_json::from_str(&body)
+        .map_err(|e| Error::from(format!("Failed to parse profile: {e}")))?;
+
+    let Some(profile) = profiles.into_iter().next() else {
+        return Ok(None);
+    };
+
+    let body = fetch(
+        client
+            .from("agents")
+            .select(AGENT_COLUMNS)
+            .eq("user_id", profile.user_id.to_string())
+            .eq("tenant_id", tenant::current().id)
+            .eq("is_public", "true")
+            .order("download_count.desc,updated_at.desc"),
+    )
+    .await?;
+    let agents: Vec<AuthorAgent> = serde_json::from_str(&body)
+        .map_err(|e| Error::from(format!("Failed to parse agents: {e}")))?;
+
+    Ok(Some(UserProfile {
+        username: profile.github_username,
+        display_name: profile.display_name,
+        avatar_url: profile.avatar_url,
+        bio: profile.bio,
+        joined_at: profile.created_at,
+        verified: profile.verified_at.is_some(),
+        agent_count: agents.len(),
+        total_downloads: agents.iter().map(|a| a.download_count).sum(),
+        total_stars: agents.iter().map(|a| a.star_count).sum(),
+        agents,
+    }))
+}
+
+async fn fetch(query: postgrest::Builder) -> Result<String, Error> {
+    let response = query
+        .execute()
+        .await
+        .map_err(|e| Error::from(format!("Database query failed: {e}")))?;
+
+    let status = response.status();
+    let body = response
+        .text()
+        .await
+        .map_err(|e| Error::from(format!("Failed to read response: {e}")))?;
+
+    if !status.is_success() {
+        return Err(Error::from(format!(
+            "Database query failed with status {status}: {body}"
+        )));
+    }
+
+    Ok(body)
+}
+
+fn error_response(status: u16, error: &str, message: String) -> Result<Response<Body>, Error> {
+    let error = ApiError {
+        error: error.to_string(),
+        message,
+        details: None,
+    };
+
+    let mut builder = Response::builder()
+        .status(status)
+        .header("content-type", "application/json");
+    if status == 405 {
+        builder = builder.header("allow", "GET");
+    }
+
+    Ok(builder.body(serde_json::to_string(&error)?.into())?)
+}
@@ -0,0 +1,814 @@
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use serde_json::json;
+use std::env;
+use uuid::Uuid;
+use vercel_runtime::{run, Body, Error, Request, Response};
+
+use shared::oci::{
+    self, AgentConfig, Artifact, Blob, PublishedVersion, Route, API_VERSION_HEADER as API_VERSION,
+};
+use shared::registry::artifacts::published_versions;
+use shared::registry::download::{record_download, Downloader};
+use shared::tenant::{self, scope};
+use shared::url_signer::{url_ttl, DownloadSigner, UrlSigner};
+use shared::{
+    api_key_middleware, authenticate_api_key, check_rate_limit, check_scope, extract_bearer_token,
+    validate_package, AgentVisibility, AuthConfig, AuthenticatedUser, ContentPolicy,
+};
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    run(|req| scope(req, handler)).await
+}
+
+pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
+    // Rewrites pass the path after /v2 as `path`, since the name can have slashes
+    let query = query_params(&req);
+    let path = query
+        .iter()
+        .find(|(key, _)| key == "path")
+        .map(|(_, value)| value.clone())
+        .unwrap_or_else(|| req.uri().path().to_string());
+
+    let Some(route) = oci::route(&path) else {
+        return oci_error(404, "NAME_UNKNOWN", "Unknown distribution API path");
+    };
+
+    let method = req.method().as_str();
+    let head = method == "HEAD";
+    if !matches!(method, "GET" | "HEAD") {
+        let maintenance = shared::maintenance::current().await;
+        if maintenance.read_only {
+            return oci_error(503, "DENIED", maintenance.message());
+        }
+    }
+    match (method, route) {
+        ("GET" | "HEAD", Route::Base) => respond(200, "application/json", b"{}".to_vec(), head),
+        ("GET" | "HEAD", Route::Manifest { name, reference }) => {
+            get_manifest(&req, &name, &reference, head).await
+        }
+        ("GET" | "HEAD", Route::Blob { name, digest }) => {
+            get_blob(&req, &name, &digest, head).await
+        }
+        ("GET", Route::Tags { name }) => list_tags(&req, &name, &query).await,
+        ("POST", Route::Uploads { name }) => start_upload(&req, &name, &query).await,
+        ("PATCH", Route::Upload { name, session }) => patch_upload(&req, &name, &session).await,
+        ("PUT", Route::Upload { name, session }) => {
+            finish_upload(&req, &name, &session, &query).await
+        }
+        ("DELETE", Route::Upload { session, .. }) => cancel_upload(&req, &session).await,
+        ("PUT", Route::Manifest { name, reference }) => put_manifest(&req, &name, &reference).await,
+        _ => oci_error(405, "UNSUPPORTED", "Method not supported on this path"),
+    }
+}
+
+fn query_params(req: &Request) -> Vec<(String, String)> {
+    url::form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes())
+        .into_owned()
+        .collect()
+}
+
+fn query_param<'a>(query: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    query
+        .iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, value)| value.as_str())
+}
+
+/// The caller, if they sent credentials
+///
+/// Besides carp's own headers, HTTP basic auth is accepted with the API key
+/// as the password, which is how docker, oras and crane log in.
+async fn caller(req: &Request) -> Result<Option<AuthenticatedUser>, Response<Body>> {
+    let basic = req
+        .headers()
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Basic "))
+        .and_then(|encoded| STANDARD.decode(encoded.trim()).ok())
+        .and_then(|decoded| String::from_utf8(decoded).ok());
+    if let Some(credentials) = basic {
+        let api_key = credentials
+            .split_once(':')
+            .map_or(credentials.as_str(), |(_, password)| password);
+        return match authenticate_api_key(api_key, &AuthConfig::from_env()).await {
+            Ok(user) => Ok(Some(user)),
+            Err(e) => Err(unauthorized(&e.message)),
+        };
+    }
+
+    if extract_bearer_token(req).is_none() {
+        return Ok(None);
+    }
+    match api_key_middleware(req).await {
+        Ok(user) => Ok(Some(user)),
+        Err(_) => Err(unauthorized("Invalid API key")),
+    }
+}
+
+/// The caller, who must be allowed to publish
+async fn publisher(req: &Request) -> Result<AuthenticatedUser, Response<Body>> {
+    match caller(req).await? {
+        Some(user) if check_scope(&user, "publish") => Ok(user),
+        Some(_) => Err(error_response(
+            403,
+            "DENIED",
+            "Pushing requires an API key with the 'publish' scope",
+        )),
+        None => Err(unauthorized("Pushing requires an API key")),
+    }
+}
+
+async fn get_manifest(
+    req: &Request,
+    name: &str,
+    reference: &str,
+    head: bool,
+) -> Result<Response<Body>, Error> {
+    let user = match caller(req).await {
+        Ok(user) => user,
+        Err(response) => return Ok(response),
+    };
+    let visibility = AgentVisibility::for_user(user.as_ref())
+        .await
+        .map_err(Error::from)?;
+    let Some(versions) = published_versions(name, &visibility)
+        .await
+        .map_err(Error::from)?
+    else {
+        return oci_error(404, "NAME_UNKNOWN", &format!("Agent '{name}' not found"));
+    };
+    let Some((_, artifact)) = oci::find_version(&versions, reference) else {
+        return oci_error(
+            404,
+            "MANIFEST_UNKNOWN",
+            &format!("No manifest '{reference}' for agent '{name}'"),
+        );
+    };
+
+    let mut response = respond(200, oci::MANIFEST_MEDIA_TYPE, artifact.manifest, head)?;
+    let headers = response.headers_mut();
+    headers.insert(
+        "docker-content-digest",
+        artifact.manifest_digest.parse().map_err(Error::from)?,
+    );
+    headers.insert(
+        "cache-control",
+        visibility.cache_control().parse().map_err(Error::from)?,
+    );
+    Ok(response)
+}
+
+async fn get_blob(
+    req: &Request,
+    name: &str,
+    digest: &str,
+    head: bool,
+) -> Result<Response<Body>, Error> {
+    let user = match caller(req).await {
+        Ok(user) => user,
+        Err(response) => return Ok(response),
+    };
+    let visibility = AgentVisibility::for_user(user.as_ref())
+        .await
+        .map_err(Error::from)?;
+    let versions = published_versions(name, &visibility)
+        .await
+        .map_err(Error::from)?
+        .unwrap_or_default();
+
+    match oci::find_blob(&versions, digest) {
+        Some(Blob::Config(config)) => {
+            let mut response = respond(200, oci::CONFIG_MEDIA_TYPE, config, head)?;
+            response.headers_mut().insert(
+                "docker-content-digest",
+                digest.parse().map_err(Error::from)?,
+            );
+            Ok(response)
+        }
+        Some(Blob::Layer(version)) => get_layer(req, user.as_ref(), version, digest, head).await,
+        // Blobs pushed for a manifest that isn't published yet
+        None => match user.filter(|_| oci::is_digest(digest)) {
+            Some(user) => {
+                let Some(data) = Bucket::from_env()?.get(&blob_path(&user, digest)).await? else {
+                    return oci_error(404, "BLOB_UNKNOWN", "Blob not found");
+                };
+                let mut response = respond(200, "application/octet-stream", data, head)?;
+                response.headers_mut().insert(
+                    "docker-content-digest",
+                    digest.parse().map_err(Error::from)?,
+                );
+                Ok(response)
+            }
+            None => oci_error(404, "BLOB_UNKNOWN", "Blob not found"),
+        },
+    }
+}
+
+/// A package layer, redirected to storage as downloads are
+async fn get_layer(
+    req: &Request,
+    user: Option<&AuthenticatedUser>,
+    version: &PublishedVersion,
+    digest: &str,
+    head: bool,
+) -> Result<Response<Body>, Error> {
+    if head {
+        return Ok(Response::builder()
+            .header(API_VERSION.0, API_VERSION.1)
+            .status(200)
+            .header("content-type", "application/octet-stream")
+            .header("content-length", version.file_size)
+            .header("docker-content-digest", digest)
+            .body(Body::Empty)?);
+    }
+
+    let rate_limit = check_rate_limit(req, user, "download").await;
+    if let Some(limit) = rate_limit.as_ref().filter(|limit| limit.exceeded()) {
+        return Ok(limit.exceeded_response(chrono::Utc::now()));
+    }
+
+    let bucket = Bucket::from_env()?;
+    let signer = DownloadSigner::from_env(bucket.client.clone(), &bucket.url, &bucket.key)
+        .map_err(|e| Error::from(e.to_string()))?;
+    let download_url = signer
+        .sign(&version.file_path, url_ttl())
+        .await
+        .map_err(|e| Error::from(e.to_string()))?;
+
+    let downloader = Downloader::from_request(req, user);
+    record_download(
+        &bucket.client,
+        &bucket.url,
+        &bucket.key,
+        &version.name,
+        &version.version,
+        &downloader,
+    )
+    .await
+    .map_err(|e| Error::from(e.to_string()))?;
+
+    let mut response = Response::builder()
+        .header(API_VERSION.0, API_VERSION.1)
+        .status(307)
+        .header("location", download_url)
+        .header("docker-content-digest", digest)
+        .body(Body::Empty)?;
+    if let Some(limit) = &rate_limit {
+        limit.apply_headers(&mut response);
+    }
+    Ok(response)
+}
+
+async fn list_tags(
+    req: &Request,
+    name: &str,
+    query: &[(String, String)],
+) -> Result<Response<Body>, Error> {
+    let user = match caller(req).await {
+        Ok(user) => user,
+        Err(response) => return Ok(response),
+    };
+    let visibility = AgentVisibility::for_user(user.as_ref())
+        .await
+        .map_err(Error::from)?;
+    let Some(versions) = published_versions(name, &visibility)
+        .await
+        .map_err(Error::from)?
+    else {
+        return oci_error(404, "NAME_UNKNOWN", &format!("Agent '{name}' not found"));
+    };
+
+    let n = query_param(query, "n").and_then(|n| n.parse().ok());
+    let (tags, more) = oci::tags_page(&versions, n, query_param(query, "last"));
+    let body = serde_json::to_vec(&json!({ "name": name, "tags": tags }))?;
+    let mut builder = Response::builder()
+        .header(API_VERSION.0, API_VERSION.1)
+        .status(200)
+        .header("content-type", "application/json");
+    if let (true, Some(last)) = (more, tags.last()) {
+        let n = n.unwrap_or(oci::MAX_TAGS_PAGE);
+        builder = builder.header(
+            "link",
+            format!(
+                "</v2/{name}/tags/list?n={n}&last={}>; rel=\"next\"",
+                urlencoding::encode(last)
+            ),
+        );
+    }
+    Ok(builder.body(body.into())?)
+}
+
+async fn start_upload(
+    req: &Request,
+    name: &str,
+    query: &[(String, String)],
+) -> Result<Response<Body>, Error> {
+    let user = match publisher(req).await {
+        Ok(user) => user,
+        Err(response) => return Ok(response),
+    };
+
+    // A monolithic upload sends the whole blob with its digest
+    if let Some(digest) = query_param(query, "digest") {
+        return store_blob(&user, name, digest, req.body().to_vec()).await;
+    }
+
+    let session = Uuid::new_v4();
+    Ok(Response::builder()
+        .header(API_VERSION.0, API_VERSION.1)
+        .status(202)
+        .header("location", format!("/v2/{name}/blobs/uploads/{session}"))
+        .header("docker-upload-uuid", session.to_string())
+        .header("range", "0-0")
+        .header("content-length", 0)
+        .body(Body::Empty)?)
+}
+
+async fn patch_upload(req: &Request, name: &str, session: &str) -> Result<Response<Body>, Error> {
+    let user = match publisher(req).await {
+        Ok(user) => user,
+        Err(response) => return Ok(response),
+    };
+    let Some(path) = upload_path(&user, session) else {
+        return oci_error(404, "BLOB_UPLOAD_UNKNOWN", "Unknown upload");
+    };
+
+    // Chunks are staged whole, so an upload can only take one
+    let starts_later = req
+        .headers()
+        .get("content-range")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|range| range.split('-').next())
+        .is_some_and(|start| start.trim() != "0");
+    let data = req.body().to_vec();
+    let len = data.len();
+    if starts_later || !Bucket::from_env()?.create(&path, data).await? {
+        return oci_error(
+            416,
+            "BLOB_UPLOAD_INVALID",
+            "Uploads must be sent in a single chunk",
+        );
+    }
+
+    Ok(Response::builder()
+        .header(API_VERSION.0, API_VERSION.1)
+        .status(202)
+        .header("location", format!("/v2/{name}/blobs/uploads/{session}"))
+        .header("docker-upload-uuid", session)
+        .header("range", format!("0-{}", len.saturating_sub(1)))
+        .header("content-length", 0)
+        .body(Body::Empty)?)
+}
+
+async fn finish_upload(
+    req: &Request,
+    name: &str,
+    session: &str,
+    query: &[(String, String)],
+) -> Result<Response<Body>, Error> {
+    let user = match publisher(req).await {
+        Ok(user) => user,
+        Err(response) => return Ok(response),
+    };
+    let Some(path) = upload_path(&user, session) else {
+        return oci_error(404, "BLOB_UPLOAD_UNKNOWN", "Unknown upload");
+    };
+    let Some(digest) = query_param(query, "digest") else {
+        return oci_error(
+            400,
+            "DIGEST_INVALID",
+            "Finishing an upload requires ?digest=",
+        );
+    };
+
+    let bucket = Bucket::from_env()?;
+    let staged = bucket.get(&path).await?;
+    let mut data = staged.clone().unwrap_or_default();
+    data.extend_from_slice(req.body());
+
+    let response = store_blob(&user, name, digest, data).await?;
+    if staged.is_some() && response.status() == 201 {
+        bucket.delete(&path).await;
+    }
+    Ok(response)
+}
+
+async fn cancel_upload(req: &Request, session: &str) -> Result<Response<Body>, Error> {
+    let user = match publisher(req).await {
+        Ok(user) => user,
+        Err(response) => return Ok(response),
+    };
+    let Some(path) = upload_path(&user, session) else {
+        return oci_error(404, "BLOB_UPLOAD_UNKNOWN", "Unknown upload");
+    };
+    Bucket::from_env()?.delete(&path).await;
+    Ok(Response::builder()
+        .header(API_VERSION.0, API_VERSION.1)
+        .status(204)
+        .body(Body::Empty)?)
+}
+
+/// Stage a blob under its digest until a manifest refers to it
+async fn store_blob(
+    user: &AuthenticatedUser,
+    name: &str,
+    digest: &str,
+    data: Vec<u8>,
+) -> Result<Response<Body>, Error> {
+    if !oci::is_digest(digest) {
+        return oci_error(400, "DIGEST_INVALID", "Digests must be sha256:<hex>");
+    }
+    if oci::digest(&data) != digest {
+        return oci_error(400, "DIGEST_INVALID", "Blob doesn't match its digest");
+    }
+
+    Bucket::from_env()?
+        .put(&blob_path(user, digest), data)
+        .await?;
+    Ok(Response::builder()
+        .header(API_VERSION.0, API_VERSION.1)
+        .status(201)
+        .header("location", format!("/v2/{name}/blobs/{digest}"))
+        .header("docker-content-digest", digest)
+        .header("content-length", 0)
+        .body(Body::Empty)?)
+}
+
+/// Publish a pushed manifest as the version its tag names
+async fn put_manifest(req: &Request, name: &str, reference: &str) -> Result<Response<Body>, Error> {
+    let user = match publisher(req).await {
+        Ok(user) => user,
+        Err(response) => return Ok(response),
+    };
+    if oci::is_digest(reference) {
+        return oci_error(
+            400,
+            "TAG_INVALID",
+            "Agents are pushed by tag, which names the version",
+        );
+    }
+    if !valid_name(name) {
+        return oci_error(
+            400,
+            "NAME_INVALID",
+            "Agent names may only contain letters, numbers, hyphens and underscores",
+        );
+    }
+
+    let body = req.body().to_vec();
+    let pushed = match oci::parse_pushed_manifest(&body) {
+        Ok(pushed) => pushed,
+        Err(e) => return oci_error(400, "MANIFEST_INVALID", &e),
+    };
+    let Ok(manifest) = String::from_utf8(body) else {
+        return oci_error(400, "MANIFEST_INVALID", "Manifests must be UTF-8 JSON");
+    };
+    let manifest_digest = oci::digest(manifest.as_bytes());
+
+    let bucket = Bucket::from_env()?;
+    let config_path = blob_path(&user, &pushed.config.digest);
+    let layer_path = blob_path(&user, &pushed.layer.digest);
+    let (Some(config_bytes), Some(package)) = (
+        bucket.get(&config_path).await?,
+        bucket.get(&layer_path).await?,
+    ) else {
+        return oci_error(
+            400,
+            "MANIFEST_BLOB_UNKNOWN",
+            "Push the config and package blobs before the manifest",
+        );
+    };
+    if config_bytes.len() as u64 != pushed.config.size || package.len() as u64 != pushed.layer.size
+    {
+        return oci_error(400, "SIZE_INVALID", "Blob sizes don't match the manifest");
+    }
+
+    let config: AgentConfig = match serde_json::from_slice(&config_bytes) {
+        Ok(config) => config,
+        Err(e) => return oci_error(400, "MANIFEST_INVALID", &format!("Invalid config: {e}")),
+    };
+    if config.name != name {
+        return oci_error(
+            400,
+            "NAME_INVALID",
+            &format!("The config names agent '{}', not '{name}'", config.name),
+        );
+    }
+    let version = if config.version.is_empty() {
+        reference.to_string()
+    } else {
+        config.version.clone()
+    };
+    if oci::tag(&version) != reference {
+        return oci_error(
+            400,
+            "TAG_INVALID",
+            &format!(
+                "Push version {version} with the tag '{}'",
+                oci::tag(&version)
+            ),
+        );
+    }
+    let Some(description) = config.description() else {
+        return oci_error(
+            400,
+            "MANIFEST_INVALID",
+            "The config must have a description",
+        );
+    };
+
+    // The same content policy as uploads applies to what's shown to others
+    let policy = ContentPolicy::from_env().map_err(Error::from)?;
+    let violations: Vec<String> = policy
+        .check_text("description", &description, &[])
+        .into_iter()
+        .chain(
+            config
+                .readme
+                .iter()
+                .flat_map(|readme| policy.check_content("readme", readme, &[])),
+        )
+        .map(|(field, message)| format!("{field}: {message}"))
+        .collect();
+    if !violations.is_empty() {
+        return oci_error(400, "DENIED", &violations.join("; "));
+    }
+    if let Err(e) = validate_package(pushed.format.content_type(), &package) {
+        return oci_error(400, "MANIFEST_INVALID", &e);
+    }
+
+    // Re-pushing the same manifest is a no-op, so interrupted pushes can be retried
+    let visibility = AgentVisibility::for_user(Some(&user))
+        .await
+        .map_err(Error::from)?;
+    let versions = published_versions(name, &visibility)
+        .await
+        .map_err(Error::from)?
+        .unwrap_or_default();
+    if let Some(existing) = versions.iter().find(|v| v.version == version) {
+        let same = Artifact::for_version(existing)
+            .is_some_and(|artifact| artifact.manifest_digest == manifest_digest);
+        if !same {
+            return oci_error(
+                409,
+                "DENIED",
+                &format!(
+                    "{name}@{version} has already been published; versions can't be overwritten"
+                ),
+            );
+        }
+        return manifest_created(name, &manifest_digest);
+    }
+
+    let checksum = oci::digest(&package);
+    let file_path = tenant::current().storage_path(&format!(
+        "{}/{name}/{version}/{}.{}",
+        user.user_id,
+        checksum.trim_start_matches("sha256:"),
+        pushed.format.extension()
+    ));
+    bucket.put(&file_path, package.clone()).await?;
+
+    let definition = if config.definition.is_object() {
+        config.definition.clone()
+    } else {
+        json!({ "name": name, "version": version, "description": description })
+    };
+    let publish_params = json!({
+        "p_user_id": user.user_id,
+        "p_name": name,
+        "p_version": version,
+        "p_description": description,
+        "p_file_path": file_path,
+        "p_content_type": pushed.format.content_type(),
+        "p_package_size": package.len(),
+        "p_checksum": checksum,
+        "p_definition": definition,
+        "p_tags": config.tags,
+        "p_author_name": format!("user-{}", user.user_id),
+        "p_license": config.license.clone().unwrap_or_else(|| "MIT".to_string()),
+        "p_homepage": config.homepage.clone().unwrap_or_default(),
+        "p_repository": config.repository.clone().unwrap_or_default(),
+        "p_readme": config.readme.clone().unwrap_or_default(),
+        "p_is_public": true,
+        "p_oci_manifest": manifest,
+        "p_oci_config": String::from_utf8_lossy(&config_bytes),
+    });
+    let response = bucket
+        .client
+        .post(format!("{}/rest/v1/rpc/publish_agent_version", bucket.url))
+        .header("apikey", &bucket.key)
+        .header("Authorization", format!("Bearer {}", bucket.key))
+        .header("Content-Type", "application/json")
+        .json(&publish_params)
+        .send()
+        .await
+        .map_err(|e| Error::from(format!("Database request failed: {e}")))?;
+
+    match response.status().as_u16() {
+        200..=299 => {}
+        409 => {
+            return oci_error(
+                409,
+                "DENIED",
+                &format!(
+                    "{name}@{version} has already been published; versions can't be overwritten"
+                ),
+            )
+        }
+        403 => {
+            return oci_error(
+                403,
+                "DENIED",
+                &format!("Agent '{name}' belongs to another user"),
+            )
+        }
+        status => {
+            let error_text = response.text().await.unwrap_or_default();
+            return Err(Error::from(format!(
+                "Database error ({status}): {error_text}"
+            )));
+        }
+    }
+
+    bucket.delete(&config_path).await;
+    bucket.delete(&layer_path).await;
+    manifest_created(name, &manifest_digest)
+}
+
+fn manifest_created(name: &str, digest: &str) -> Result<Response<Body>, Error> {
+    Ok(Response::builder()
+        .header(API_VERSION.0, API_VERSION.1)
+        .status(201)
+        .header("location", format!("/v2/{name}/manifests/{digest}"))
+        .header("docker-content-digest", digest)
+        .header("content-length", 0)
+        .body(Body::Empty)?)
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 100
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Where a pushed blob waits for its manifest; per user, so one user's
+/// staged blobs can't complete another's push
+fn blob_path(user: &AuthenticatedUser, digest: &str) -> String {
+    format!(
+        "oci-blobs/{}/{}",
+        user.user_id,
+        digest.trim_start_matches("sha256:")
+    )
+}
+
+fn upload_path(user: &AuthenticatedUser, session: &str) -> Option<String> {
+    let session = Uuid::parse_str(session).ok()?;
+    Some(tenant::current().storage_path(&format!("oci-uploads/{}/{session}", user.user_id)))
+}
+
+/// The `agent-packages` bucket, which also holds staged pushes
+struct Bucket {
+    client: reqwest::Client,
+    url: String,
+    key: String,
+}
+
+impl Bucket {
+    fn from_env() -> Result<Self, Error> {
+        let url = env::var("SUPABASE_URL")
+            .map_err(|_| Error::from("SUPABASE_URL environment variable not set"))?;
+        let key = env::var("SUPABASE_SERVICE_ROLE_KEY")
+            .map_err(|_| Error::from("SUPABASE_SERVICE_ROLE_KEY environment variable not set"))?;
+        Ok(Bucket {
+            client: tenant::http_client(),
+            url,
+            key,
+        })
+    }
+
+    fn object_url(&self, path: &str) -> String {
+        format!("{}/storage/v1/object/agent-packages/{path}", self.url)
+    }
+
+    async fn get(&self, path: &str) -> Result<Option<Vec<u8>>, Error> {
+        let response = self
+            .client
+            .get(self.object_url(path))
+            .header("apikey", &self.key)
+            .header("Authorization", format!("Bearer {}", self.key))
+            .send()
+            .await
+            .map_err(|e| Error::from(format!("Failed to read from storage: {e}")))?;
+
+        match response.status().as_u16() {
+            200..=299 => Ok(Some(
+                response
+                    .bytes()
+                    .await
+                    .map_err(|e| Error::from(format!("Failed to read from storage: {e}")))?
+                    .to_vec(),
+            )),
+            // Storage reports missing objects as either
+            400 | 404 => Ok(None),
+            status => Err(Error::from(format!("Storage error ({status})"))),
+        }
+    }
+
+    /// Store an object, replacing any already at `path`
+    async fn put(&self, path: &str, data: Vec<u8>) -> Result<(), Error> {
+        self.upload(path, data, true).await.map(|_| ())
+    }
+
+    /// Store an object unless one is already at `path`, returning whether it was stored
+    async fn create(&self, path: &str, data: Vec<u8>) -> Result<bool, Error> {
+        self.upload(path, data, false).await
+    }
+
+    async fn upload(&self, path: &str, data: Vec<u8>, upsert: bool) -> Result<bool, Error> {
+        let response = self
+            .client
+            .post(self.object_url(path))
+            .header("apikey", &self.key)
+            .header("Authorization", format!("Bearer {}", self.key))
+            .header("Content-Type", "application/octet-stream")
+            .header("x-upsert", upsert.to_string())
+            .body(data)
+            .send()
+            .await
+            .map_err(|e| Error::from(format!("Failed to write to storage: {e}")))?;
+
+        let status = response.status();
+        if status.is_success() {
+            return Ok(true);
+        }
+        let error_text = response.text().await.unwrap_or_default();
+        if !upsert && (status == 409 || error_text.contains("Duplicate")) {
+            return Ok(false);
+        }
+        Err(Error::from(format!(
+            "Failed to write to storage: {error_text}"
+        )))
+    }
+
+    /// Remove an object. Failures only leave a staged blob behind, so they're logged.
+    async fn delete(&self, path: &str) {
+        let response = self
+            .client
+            .delete(self.object_url(path))
+            .header("apikey", &self.key)
+            .header("Authorization", format!("Bearer {}", self.key))
+            .send()
+            .await;
+        if let Err(e) = response {
+            eprintln!("Warning: Failed to delete staged blob {path}: {e}");
+        }
+    }
+}
+
+fn respond(
+    status: u16,
+    content_type: &str,
+    body: Vec<u8>,
+    head: bool,
+) -> Result<Response<Body>, Error> {
+    let builder = Response::builder()
+        .header(API_VERSION.0, API_VERSION.1)
+        .status(status)
+        .header("content-type", content_type)
+        .header("content-length", body.len());
+    Ok(if head {
+        builder.body(Body::Empty)?
+    } else {
+        builder.body(body.into())?
+    })
+}
+
+fn error_response(status: u16, code: &str, message: &str) -> Response<Body> {
+    let body = oci::error_body(code, message).to_string();
+    Response::builder()
+        .header(API_VERSION.0, API_VERSION.1)
+        .status(status)
+        .header("content-type", "application/json")
+        .body(body.into())
+        .unwrap_or_else(|_| Response::new(Body::Empty))
+}
+
+fn oci_error(status: u16, code: &str, message: &str) -> Result<Response<Body>, Error> {
+    Ok(error_response(status, code, message))
+}
+
+/// Basic auth is advertised since that's what registry clients log in with
+fn unauthorized(message: &str) -> Response<Body> {
+    let body = oci::error_body("UNAUTHORIZED", message).to_string();
+    Response::builder()
+        .header(API_VERSION.0, API_VERSION.1)
+        .status(401)
+        .header("content-type", "application/json")
+        .header("www-authenticate", "Basic realm=\"carp\"")
+        .body(body.into())
+        .unwrap_or_else(|_| Response::new(Body::Empty))
+}
@@ -0,0 +1,95 @@
+//! Benchmarks for the registry's hot endpoints
+//!
+//! Search and download-info requests are sent over HTTP to an in-process
+//! registry seeded with `CARP_BENCH_AGENTS` agents (default 100,000), so a
+//! query change that scans more rows, or a handler that does more work per
+//! request, shows up as a regression against the last saved baseline:
+//!
+//! ```sh
+//! cargo bench --features test-support --bench registry -- --save-baseline main
+//! # ...change something...
+//! cargo bench --features test-support --bench registry -- --baseline main
+//! ```
+//!
+//! Seeding takes a few seconds and happens once per run.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use shared::local::testing::{seeded_agent_name, TestRegistry};
+use std::time::Duration;
+use tokio::runtime::Runtime;
+
+const DEFAULT_AGENTS: usize = 100_000;
+
+fn agent_count() -> usize {
+    std::env::var("CARP_BENCH_AGENTS")
+        .ok()
+        .and_then(|count| count.parse().ok())
+        .unwrap_or(DEFAULT_AGENTS)
+}
+
+/// Fetch `url` and read the whole body, failing the run on any error status
+async fn fetch(client: &reqwest::Client, url: &str) {
+    client
+        .get(url)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .unwrap_or_else(|e| panic!("GET {url} failed: {e}"))
+        .bytes()
+        .await
+        .unwrap();
+}
+
+fn registry_benches(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let count = agent_count();
+    let registry = runtime.block_on(async {
+        let registry = TestRegistry::start().await.unwrap();
+        registry.seed(count).await.unwrap();
+        registry
+    });
+    let client = reqwest::Client::new();
+    let base = registry.url();
+
+    let mut search = c.benchmark_group("search");
+    search
+        .sample_size(30)
+        .measurement_time(Duration::from_secs(10));
+    let searches = [
+        // The landing page: everything, most downloaded first
+        ("browse", "limit=20".to_string()),
+        // Matches a tenth of the registry
+        ("common_term", "q=reviewer&limit=20".to_string()),
+        // Matches one agent but still has to scan for it
+        (
+            "rare_term",
+            format!("q={}&limit=20", seeded_agent_name(count / 2)),
+        ),
+        (
+            "exact_name",
+            format!("q={}&mode=exact", seeded_agent_name(count / 2)),
+        ),
+        ("recent_first", "q=notes&sort=recent&limit=20".to_string()),
+        ("deep_page", "q=reviewer&limit=20&page=200".to_string()),
+    ];
+    for (name, query) in &searches {
+        let url = format!("{base}/api/v1/agents/search?{query}");
+        search.bench_with_input(BenchmarkId::from_parameter(name), &url, |b, url| {
+            b.to_async(&runtime).iter(|| fetch(&client, url));
+        });
+    }
+    search.finish();
+
+    let mut download = c.benchmark_group("download_info");
+    let agent = seeded_agent_name(count / 3);
+    for version in ["1.0.0", "latest"] {
+        let url = format!("{base}/api/v1/agents/{agent}/{version}/download");
+        download.bench_with_input(BenchmarkId::from_parameter(version), &url, |b, url| {
+            b.to_async(&runtime).iter(|| fetch(&client, url));
+        });
+    }
+    download.finish();
+}
+
+criterion_group!(benches, registry_benches);
+criterion_main!(benches);
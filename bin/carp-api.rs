@@ -0,0 +1,210 @@
+//! Administrative commands for the Carp API
+//!
+//! The API itself runs as serverless functions; this binary handles the
+//! tasks around it, such as bringing a database up to the schema the
+//! functions expect, running the background jobs they queue, or filling a
+//! local one with demo data. With `LOCAL_MODE=1` it also serves the whole
+//! registry by itself for development and CI, and with the `grpc` feature it
+//! serves the gRPC registry service.
+
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+use shared::jobs::{JobQueue, Worker, WorkerConfig};
+use shared::{local, migrations, seed};
+use std::process::ExitCode;
+use std::time::Duration;
+
+#[derive(Parser)]
+#[command(name = "carp-api", about = "Administer a Carp registry deployment")]
+struct Cli {
+    /// Postgres connection string, e.g. Supabase's direct connection URL
+    #[arg(long, env = "DATABASE_URL", global = true, hide_env_values = true)]
+    database_url: Option<String>,
+
+    /// Defaults to `serve` when `LOCAL_MODE` is set
+    #[command(subcommand)]
+    command: Option<Commands>,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Apply the migrations this build includes that the database is missing
+    Migrate {
+        /// Only list pending migrations, exiting with status 1 if there are any
+        #[arg(long)]
+        check: bool,
+    },
+    /// Serve the registry from local storage (see `LOCAL_MODE`)
+    Serve,
+    /// Replace the demo users, API keys and agents in a local database
+    Seed {
+        /// Seed a database that isn't on this machine
+        #[arg(long)]
+        allow_remote: bool,
+    },
+    /// Run queued background jobs against the configured Supabase project
+    Worker {
+        /// Jobs to run at once
+        #[arg(long, env = "CARP_WORKER_CONCURRENCY", default_value_t = 4)]
+        concurrency: usize,
+        /// Seconds to wait between checks when no job is due
+        #[arg(long, default_value_t = 5)]
+        poll_interval: u64,
+        /// Run the jobs that are due, then exit, e.g. from a scheduler
+        #[arg(long)]
+        once: bool,
+    },
+    /// Serve the gRPC registry service against the configured Supabase project
+    #[cfg(feature = "grpc")]
+    Grpc {
+        /// Address to listen on
+        #[arg(long, env = "CARP_GRPC_ADDR", default_value = "0.0.0.0:50051")]
+        addr: std::net::SocketAddr,
+    },
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let _ = dotenvy::dotenv();
+    let cli = Cli::parse();
+
+    match run(cli).await {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("error: {e:#}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(cli: Cli) -> Result<ExitCode> {
+    let command = match cli.command {
+        Some(command) => command,
+        None if local::enabled() => Commands::Serve,
+        None => anyhow::bail!("No command given; run with --help, or set LOCAL_MODE=1 to serve"),
+    };
+
+    match command {
+        Commands::Serve => {
+            local::serve(local::LocalConfig::from_env()?).await?;
+            Ok(ExitCode::SUCCESS)
+        }
+        Commands::Migrate { check } => migrate(&database_url(cli.database_url)?, check).await,
+        Commands::Seed { allow_remote } => {
+            run_seed(&database_url(cli.database_url)?, allow_remote).await
+        }
+        Commands::Worker {
+            concurrency,
+            poll_interval,
+            once,
+        } => run_worker(concurrency, Duration::from_secs(poll_interval), once).await,
+        #[cfg(feature = "grpc")]
+        Commands::Grpc { addr } => {
+            shared::grpc::serve(addr).await?;
+            Ok(ExitCode::SUCCESS)
+        }
+    }
+}
+
+fn database_url(url: Option<String>) -> Result<String> {
+    url.ok_or_else(|| anyhow::anyhow!("Set DATABASE_URL or pass --database-url"))
+}
+
+async fn migrate(database_url: &str, check: bool) -> Result<ExitCode> {
+    let mut conn = migrations::connect(database_url).await?;
+
+    if check {
+        let pending = migrations::pending(&mut conn).await?;
+        if pending.is_empty() {
+            println!("Database schema is up to date");
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        println!("{} pending migration(s):", pending.len());
+        for migration in pending {
+            println!("  {} {}", migration.version, migration.description);
+        }
+        return Ok(ExitCode::FAILURE);
+    }
+
+    let applied = migrations::run(&mut conn).await?;
+    if applied.is_empty() {
+        println!("Database schema is up to date");
+    }
+    for migration in &applied {
+        println!("Applied {} {}", migration.version, migration.description);
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+async fn run_worker(concurrency: usize, poll_interval: Duration, once: bool) -> Result<ExitCode> {
+    let queue = JobQueue::from_env().ok_or_else(|| {
+        anyhow::anyhow!("Set SUPABASE_URL and SUPABASE_SERVICE_ROLE_KEY to run jobs")
+    })?;
+    let config = WorkerConfig::new(concurrency, poll_interval);
+    let worker = Worker::new(queue, config.clone());
+
+    if once {
+        let ran = worker.drain().await?;
+        println!("Ran {ran} job(s)");
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    println!(
+        "Worker {} running up to {} job(s) at a time",
+        config.name, config.concurrency
+    );
+    worker.run().await?;
+    Ok(ExitCode::SUCCESS)
+}
+
+async fn run_seed(database_url: &str, allow_remote: bool) -> Result<ExitCode> {
+    let host = url::Url::parse(database_url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+        .unwrap_or_default();
+    let local = matches!(
+        host.as_str(),
+        "" | "localhost" | "127.0.0.1" | "[::1]" | "host.docker.internal"
+    );
+    if !local && !allow_remote {
+        anyhow::bail!(
+            "Refusing to seed the database at {host}; pass --allow-remote if that's intended"
+        );
+    }
+
+    let mut conn = migrations::connect(database_url).await?;
+    let pending = migrations::pending(&mut conn).await?;
+    if !pending.is_empty() {
+        anyhow::bail!(
+            "{} migration(s) are pending; run `carp-api migrate` first",
+            pending.len()
+        );
+    }
+
+    let packages = seed::run(&mut conn).await?;
+
+    let supabase_url = std::env::var("SUPABASE_URL").unwrap_or_default();
+    let supabase_key = std::env::var("SUPABASE_SERVICE_ROLE_KEY").unwrap_or_default();
+    if supabase_url.is_empty() || supabase_key.is_empty() {
+        println!(
+            "Skipped uploading {} packages: set SUPABASE_URL and SUPABASE_SERVICE_ROLE_KEY \
+             to make the demo agents downloadable",
+            packages.len()
+        );
+    } else {
+        seed::upload_packages(&supabase_url, &supabase_key, &packages).await?;
+        println!("Uploaded {} packages to storage", packages.len());
+    }
+
+    println!(
+        "Seeded demo data. API keys (scopes: {}):",
+        seed::DEMO_SCOPES.join(", ")
+    );
+    for user in seed::DEMO_USERS {
+        println!("  {:<12} {}", user.username, user.api_key);
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
@@ -0,0 +1,24 @@
+//! Generates the gRPC service code when the `grpc` feature is enabled
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    #[cfg(feature = "grpc")]
+    compile_protos();
+}
+
+#[cfg(feature = "grpc")]
+fn compile_protos() {
+    println!("cargo:rerun-if-changed=proto");
+
+    // Prefer a protoc from the environment, falling back to the vendored one
+    // so the feature builds without a system install
+    if std::env::var_os("PROTOC").is_none() {
+        let protoc = protoc_bin_vendored::protoc_bin_path().expect("vendored protoc is available");
+        std::env::set_var("PROTOC", protoc);
+    }
+
+    tonic_prost_build::configure()
+        .compile_protos(&["proto/carp/registry/v1/registry.proto"], &["proto"])
+        .expect("registry.proto compiles");
+}
@@ -0,0 +1,23 @@
+//! `name@version` specs come from the command line and from `Carp.toml`
+//! dependencies.
+
+#![no_main]
+
+use carp_cli::commands::pull::parse_agent_spec;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|spec: &str| {
+    let Ok((name, version)) = parse_agent_spec(spec) else {
+        return;
+    };
+
+    // Nothing is dropped or rewritten
+    match version {
+        Some(version) => {
+            assert!(!name.is_empty() && !version.is_empty());
+            assert!(!name.contains('@'));
+            assert_eq!(format!("{name}@{version}"), spec);
+        }
+        None => assert_eq!(name, spec),
+    }
+});
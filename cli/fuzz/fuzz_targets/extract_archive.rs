@@ -0,0 +1,44 @@
+//! Packages are downloaded from the registry and unpacked on the user's
+//! machine, so no entry may land outside the destination, whatever its
+//! name, format or symlinks.
+//!
+//! The first byte picks the format and whether symlinks are allowed; the
+//! rest is the archive.
+
+#![no_main]
+
+use carp_cli::utils::archive::{extract_archive, ArchiveFormat, ExtractLimits};
+use libfuzzer_sys::fuzz_target;
+use std::fs;
+use std::path::Component;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&options, archive)) = data.split_first() else {
+        return;
+    };
+    let format = match options % 3 {
+        0 => ArchiveFormat::Zip,
+        1 => ArchiveFormat::TarGz,
+        _ => ArchiveFormat::TarZst,
+    };
+    let limits = ExtractLimits {
+        max_entry_size: 1024 * 1024,
+        max_total_size: 4 * 1024 * 1024,
+        allow_symlinks: options & 0x80 != 0,
+    };
+
+    let root = tempfile::tempdir().unwrap();
+    let dest = root.path().join("dest");
+    let _ = extract_archive(format, archive, &dest, &limits, |path, _| {
+        assert!(
+            path.components().all(|c| matches!(c, Component::Normal(_))),
+            "entry extracted to {}",
+            path.display()
+        );
+    });
+
+    // Nothing was written beside the destination
+    for entry in fs::read_dir(root.path()).unwrap() {
+        assert_eq!(entry.unwrap().file_name(), "dest");
+    }
+});
@@ -0,0 +1,18 @@
+//! Agent files come from the registry and from disk, so their YAML
+//! frontmatter must never panic the parser, and whatever it accepts must
+//! render back out.
+
+#![no_main]
+
+use carp_cli::utils::frontmatter::{parse_frontmatter, render_frontmatter};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(content) = std::str::from_utf8(data) else {
+        return;
+    };
+    let Ok((frontmatter, body)) = parse_frontmatter(content) else {
+        return;
+    };
+    render_frontmatter(&frontmatter, &body).expect("parsed frontmatter renders");
+});
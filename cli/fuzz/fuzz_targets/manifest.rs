@@ -0,0 +1,16 @@
+//! `Carp.toml` is read from directories being published, which may not be
+//! the user's own.
+
+#![no_main]
+
+use carp_cli::utils::manifest::AgentManifest;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(contents) = std::str::from_utf8(data) else {
+        return;
+    };
+    if let Ok(manifest) = AgentManifest::parse(contents) {
+        manifest.validate().expect("parsed manifests are valid");
+    }
+});
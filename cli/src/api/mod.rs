@@ -1,4 +1,8 @@
 pub mod client;
+pub mod oci;
+pub mod prefetch;
+pub mod tls;
+pub mod transcript;
 pub mod types;
 
 pub use client::ApiClient;
@@ -0,0 +1,552 @@
+//! Pulling agents from OCI registries
+//!
+//! Registries that implement the OCI distribution API can hold agents as
+//! artifacts: a manifest whose config blob describes the agent and whose
+//! single layer is its package. `carp pull oci://host/name:tag` fetches them
+//! from carp's own `/v2` endpoints or from any other such registry.
+
+use crate::api::tls;
+use crate::config::Config;
+use crate::utils::archive::ArchiveFormat;
+use crate::utils::error::{CarpError, CarpResult};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use futures::StreamExt;
+use reqwest::header::{ACCEPT, AUTHORIZATION, WWW_AUTHENTICATE};
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fmt;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tracing::debug;
+
+/// Media type of OCI image manifests, which agent artifacts use
+pub const MANIFEST_MEDIA_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+
+/// Media type of the config blob describing an agent
+pub const CONFIG_MEDIA_TYPE: &str = "application/vnd.carp.agent.config.v1+json";
+
+/// Largest config blob we'll read; configs hold metadata, not content
+const MAX_CONFIG_SIZE: u64 = 1024 * 1024;
+
+/// An artifact in an OCI registry, as `host[:port]/name[:tag|@digest]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OciReference {
+    pub registry: String,
+    pub repository: String,
+    /// Tag or `sha256:` digest, `latest` when neither is given
+    pub reference: String,
+}
+
+impl OciReference {
+    /// Parse a reference, with or without the `oci://` prefix
+    pub fn parse(spec: &str) -> CarpResult<Self> {
+        let spec = spec.strip_prefix("oci://").unwrap_or(spec);
+        let invalid = || {
+            CarpError::InvalidAgent(format!(
+                "Invalid OCI reference '{spec}'. Use oci://host/name:tag or oci://host/name@sha256:<digest>"
+            ))
+        };
+
+        let (registry, rest) = spec.split_once('/').ok_or_else(invalid)?;
+        let (repository, reference) = if let Some((repository, digest)) = rest.split_once('@') {
+            (repository, digest.to_string())
+        } else {
+            // A colon after the last slash starts the tag
+            let last_slash = rest.rfind('/').map_or(0, |i| i + 1);
+            match rest[last_slash..].rfind(':') {
+                Some(colon) => (
+                    &rest[..last_slash + colon],
+                    rest[last_slash + colon + 1..].to_string(),
+                ),
+                None => (rest, "latest".to_string()),
+            }
+        };
+
+        if registry.is_empty() || repository.is_empty() || reference.is_empty() {
+            return Err(invalid());
+        }
+        if reference.starts_with("sha256:") && !is_digest(&reference) {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            registry: registry.to_string(),
+            repository: repository.to_string(),
+            reference,
+        })
+    }
+}
+
+impl fmt::Display for OciReference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let separator = if is_digest(&self.reference) { '@' } else { ':' };
+        write!(
+            f,
+            "{}/{}{separator}{}",
+            self.registry, self.repository, self.reference
+        )
+    }
+}
+
+/// Reference to a blob within a manifest
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Descriptor {
+    pub media_type: String,
+    pub digest: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct Manifest {
+    config: Descriptor,
+    layers: Vec<Descriptor>,
+}
+
+/// The agent an artifact's config blob describes
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AgentConfig {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub version: String,
+}
+
+/// An agent artifact whose manifest and config have been fetched
+#[derive(Debug, Clone)]
+pub struct OciArtifact {
+    pub config: AgentConfig,
+    pub layer: Descriptor,
+    pub format: ArchiveFormat,
+}
+
+/// Client for the OCI distribution API
+pub struct OciClient {
+    client: Client,
+    /// Host of the configured carp registry, whose API key is sent to it
+    carp_host: Option<String>,
+    api_key: Option<String>,
+    allow_http: bool,
+    max_download_size: u64,
+    /// Authorization obtained from a registry's challenge, reused for the
+    /// rest of the pull
+    authorization: Mutex<Option<String>>,
+}
+
+impl OciClient {
+    pub fn new(config: &Config) -> CarpResult<Self> {
+        let client = tls::client_builder(config)?
+            .timeout(Duration::from_secs(config.timeout))
+            .user_agent(format!("carp-cli/{}", env!("CARGO_PKG_VERSION")))
+            .connect_timeout(Duration::from_secs(10))
+            .build()?;
+        let carp_host = reqwest::Url::parse(&config.registry_url)
+            .ok()
+            .and_then(|url| {
+                let host = url.host_str()?.to_string();
+                Some(match url.port() {
+                    Some(port) => format!("{host}:{port}"),
+                    None => host,
+                })
+            });
+
+        Ok(Self {
+            client,
+            carp_host,
+            api_key: config.api_key.clone(),
+            allow_http: config.security.allow_http,
+            max_download_size: config.security.max_download_size,
+            authorization: Mutex::new(None),
+        })
+    }
+
+    /// Fetch an artifact's manifest and config
+    pub async fn resolve(&self, reference: &OciReference) -> CarpResult<OciArtifact> {
+        let url = self.url(reference, &format!("manifests/{}", reference.reference));
+        let response = self
+            .send(reference, |client| {
+                client.get(&url).header(ACCEPT, MANIFEST_MEDIA_TYPE)
+            })
+            .await?;
+        let response = check_status(response, reference).await?;
+        let body = response.bytes().await?;
+        if is_digest(&reference.reference) && sha256_digest(&body) != reference.reference {
+            return Err(CarpError::InvalidAgent(format!(
+                "Manifest for {reference} doesn't match its digest"
+            )));
+        }
+
+        let manifest: Manifest = serde_json::from_slice(&body).map_err(|e| {
+            CarpError::InvalidAgent(format!("Invalid manifest for {reference}: {e}"))
+        })?;
+        if manifest.config.media_type != CONFIG_MEDIA_TYPE {
+            return Err(CarpError::InvalidAgent(format!(
+                "{reference} is not a carp agent: its config is {}",
+                manifest.config.media_type
+            )));
+        }
+        let [layer] = manifest.layers.as_slice() else {
+            return Err(CarpError::InvalidAgent(format!(
+                "{reference} is not a carp agent: it has {} layers instead of one package",
+                manifest.layers.len()
+            )));
+        };
+        let format = layer_format(&layer.media_type).ok_or_else(|| {
+            CarpError::InvalidAgent(format!(
+                "Unsupported package layer type '{}'",
+                layer.media_type
+            ))
+        })?;
+        if manifest.config.size > MAX_CONFIG_SIZE {
+            return Err(CarpError::InvalidAgent(format!(
+                "Config for {reference} is {} bytes; at most {MAX_CONFIG_SIZE} are allowed",
+                manifest.config.size
+            )));
+        }
+
+        let config = self.fetch_blob(reference, &manifest.config).await?;
+        let config: AgentConfig = serde_json::from_slice(&config).map_err(|e| {
+            CarpError::InvalidAgent(format!("Invalid agent config for {reference}: {e}"))
+        })?;
+
+        Ok(OciArtifact {
+            config,
+            layer: layer.clone(),
+            format,
+        })
+    }
+
+    /// Download the package layer to `dest`, checking it against its digest
+    pub async fn download_layer(
+        &self,
+        reference: &OciReference,
+        layer: &Descriptor,
+        dest: &Path,
+    ) -> CarpResult<u64> {
+        self.check_download_size(layer.size)?;
+        let response = self.blob_response(reference, layer).await?;
+
+        let mut file = tokio::fs::File::create(dest).await?;
+        let mut hasher = Sha256::new();
+        let mut written = 0u64;
+        let mut stream = response.bytes_stream();
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+            written += chunk.len() as u64;
+            self.check_download_size(written)?;
+            hasher.update(&chunk);
+            file.write_all(&chunk).await?;
+        }
+        file.flush().await?;
+
+        let digest = format!("sha256:{:x}", hasher.finalize());
+        if digest != layer.digest || written != layer.size {
+            let _ = tokio::fs::remove_file(dest).await;
+            return Err(CarpError::InvalidAgent(format!(
+                "Package from {reference} doesn't match the manifest's digest {}",
+                layer.digest
+            )));
+        }
+        Ok(written)
+    }
+
+    async fn fetch_blob(
+        &self,
+        reference: &OciReference,
+        descriptor: &Descriptor,
+    ) -> CarpResult<bytes::Bytes> {
+        let body = self
+            .blob_response(reference, descriptor)
+            .await?
+            .bytes()
+            .await?;
+        if sha256_digest(&body) != descriptor.digest {
+            return Err(CarpError::InvalidAgent(format!(
+                "Blob {} from {reference} doesn't match its digest",
+                descriptor.digest
+            )));
+        }
+        Ok(body)
+    }
+
+    /// Request a blob; registries commonly redirect to storage, which reqwest
+    /// follows without our credentials
+    async fn blob_response(
+        &self,
+        reference: &OciReference,
+        descriptor: &Descriptor,
+    ) -> CarpResult<Response> {
+        let url = self.url(reference, &format!("blobs/{}", descriptor.digest));
+        let response = self.send(reference, |client| client.get(&url)).await?;
+        check_status(response, reference).await
+    }
+
+    /// Send a request, answering the registry's auth challenge if it makes one
+    async fn send(
+        &self,
+        reference: &OciReference,
+        build: impl Fn(&Client) -> RequestBuilder,
+    ) -> CarpResult<Response> {
+        let authorization = self.authorization.lock().unwrap().clone().or_else(|| {
+            // The carp registry takes its API key directly
+            self.api_key
+                .as_ref()
+                .filter(|_| self.carp_host.as_deref() == Some(reference.registry.as_str()))
+                .map(|key| format!("Bearer {key}"))
+        });
+        let mut request = build(&self.client);
+        if let Some(authorization) = &authorization {
+            request = request.header(AUTHORIZATION, authorization);
+        }
+        let response = request.send().await?;
+        if response.status() != StatusCode::UNAUTHORIZED {
+            return Ok(response);
+        }
+
+        let challenge = response
+            .headers()
+            .get(WWW_AUTHENTICATE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let Some(authorization) = self.answer_challenge(reference, &challenge).await? else {
+            return Ok(response);
+        };
+        debug!("Authenticated with {}", reference.registry);
+        *self.authorization.lock().unwrap() = Some(authorization.clone());
+
+        Ok(build(&self.client)
+            .header(AUTHORIZATION, authorization)
+            .send()
+            .await?)
+    }
+
+    /// The Authorization header a `WWW-Authenticate` challenge asks for, if
+    /// we can answer it
+    async fn answer_challenge(
+        &self,
+        reference: &OciReference,
+        challenge: &str,
+    ) -> CarpResult<Option<String>> {
+        let basic = self.basic_credentials(reference);
+        let Some(params) = challenge.strip_prefix("Bearer ") else {
+            return Ok(challenge.starts_with("Basic").then_some(basic).flatten());
+        };
+
+        // The token service named by the challenge, anonymously unless
+        // credentials are configured
+        let params = challenge_params(params);
+        let Some(realm) = params.iter().find(|(key, _)| key == "realm") else {
+            return Ok(None);
+        };
+        let mut token_url = reqwest::Url::parse(&realm.1)
+            .map_err(|_| CarpError::Network(format!("Invalid token realm '{}'", realm.1)))?;
+        for (key, value) in params.iter().filter(|(key, _)| key != "realm") {
+            token_url.query_pairs_mut().append_pair(key, value);
+        }
+        let mut request = self.client.get(token_url);
+        if let Some(basic) = basic {
+            request = request.header(AUTHORIZATION, basic);
+        }
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            return Err(CarpError::Auth(format!(
+                "{} refused a token (HTTP {}); set CARP_OCI_USERNAME and CARP_OCI_PASSWORD",
+                reference.registry,
+                response.status()
+            )));
+        }
+
+        #[derive(Deserialize)]
+        struct TokenResponse {
+            token: Option<String>,
+            access_token: Option<String>,
+        }
+        let token: TokenResponse = response.json().await?;
+        Ok(token
+            .token
+            .or(token.access_token)
+            .map(|token| format!("Bearer {token}")))
+    }
+
+    /// Basic credentials from CARP_OCI_USERNAME and CARP_OCI_PASSWORD, or the
+    /// API key for the carp registry
+    fn basic_credentials(&self, reference: &OciReference) -> Option<String> {
+        let username = env::var("CARP_OCI_USERNAME").ok();
+        let password = env::var("CARP_OCI_PASSWORD").ok();
+        let (username, password) = match (username, password) {
+            (Some(username), Some(password)) => (username, password),
+            _ if self.carp_host.as_deref() == Some(reference.registry.as_str()) => {
+                ("carp".to_string(), self.api_key.clone()?)
+            }
+            _ => return None,
+        };
+        Some(format!(
+            "Basic {}",
+            BASE64.encode(format!("{username}:{password}"))
+        ))
+    }
+
+    fn url(&self, reference: &OciReference, path: &str) -> String {
+        format!(
+            "{}://{}/v2/{}/{path}",
+            self.scheme(&reference.registry),
+            reference.registry,
+            reference.repository
+        )
+    }
+
+    /// HTTPS, except for registries on this machine when HTTP is allowed
+    fn scheme(&self, registry: &str) -> &'static str {
+        let host = registry
+            .rsplit_once(':')
+            .map_or(registry, |(host, _)| host)
+            .trim_start_matches('[')
+            .trim_end_matches(']');
+        let local = host == "localhost" || host.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback());
+        if self.allow_http && local {
+            "http"
+        } else {
+            "https"
+        }
+    }
+
+    fn check_download_size(&self, size: u64) -> CarpResult<()> {
+        if size > self.max_download_size {
+            return Err(CarpError::Network(format!(
+                "Download size ({size} bytes) exceeds maximum allowed size ({} bytes)",
+                self.max_download_size
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Map a failed response to an error, using the registry's error body
+async fn check_status(response: Response, reference: &OciReference) -> CarpResult<Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    #[derive(Deserialize)]
+    struct Errors {
+        errors: Vec<ErrorEntry>,
+    }
+    #[derive(Deserialize)]
+    struct ErrorEntry {
+        code: String,
+        #[serde(default)]
+        message: String,
+    }
+    let body = response.text().await.unwrap_or_default();
+    let message = serde_json::from_str::<Errors>(&body)
+        .ok()
+        .and_then(|errors| errors.errors.into_iter().next())
+        .map(|error| format!("{}: {}", error.code, error.message))
+        .unwrap_or_else(|| format!("HTTP {status}"));
+
+    Err(match status.as_u16() {
+        404 => CarpError::AgentNotFound {
+            name: reference.to_string(),
+            suggestions: Vec::new(),
+        },
+        401 => CarpError::Auth(format!("{}: {message}", reference.registry)),
+        403 => CarpError::Forbidden(format!("{}: {message}", reference.registry)),
+        status => CarpError::Api { status, message },
+    })
+}
+
+/// `key="value"` pairs of an auth challenge
+fn challenge_params(params: &str) -> Vec<(String, String)> {
+    let mut pairs = Vec::new();
+    let mut rest = params.trim();
+    while let Some((key, after)) = rest.split_once('=') {
+        let key = key.trim().trim_start_matches(',').trim().to_string();
+        let (value, after) = match after.strip_prefix('"') {
+            Some(quoted) => match quoted.split_once('"') {
+                Some((value, after)) => (value, after),
+                None => (quoted, ""),
+            },
+            None => after.split_once(',').unwrap_or((after, "")),
+        };
+        pairs.push((key, value.to_string()));
+        rest = after.trim_start_matches(',').trim();
+    }
+    pairs
+}
+
+/// Package format of a layer, from carp's own media types, OCI tar layers
+/// or plain archive types
+fn layer_format(media_type: &str) -> Option<ArchiveFormat> {
+    match media_type {
+        "application/vnd.carp.agent.package.v1.zip" => Some(ArchiveFormat::Zip),
+        "application/vnd.carp.agent.package.v1.tar+gzip"
+        | "application/vnd.oci.image.layer.v1.tar+gzip" => Some(ArchiveFormat::TarGz),
+        "application/vnd.carp.agent.package.v1.tar+zstd"
+        | "application/vnd.oci.image.layer.v1.tar+zstd" => Some(ArchiveFormat::TarZst),
+        other => ArchiveFormat::from_content_type(other),
+    }
+}
+
+fn is_digest(reference: &str) -> bool {
+    reference
+        .strip_prefix("sha256:")
+        .is_some_and(|hex| hex.len() == 64 && hex.bytes().all(|b| b.is_ascii_hexdigit()))
+}
+
+fn sha256_digest(data: &[u8]) -> String {
+    format!("sha256:{:x}", Sha256::digest(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_reference() {
+        let reference = OciReference::parse("oci://localhost:5000/code-reviewer:1.0.0").unwrap();
+        assert_eq!(reference.registry, "localhost:5000");
+        assert_eq!(reference.repository, "code-reviewer");
+        assert_eq!(reference.reference, "1.0.0");
+
+        let reference = OciReference::parse("ghcr.io/acme/agents/reviewer").unwrap();
+        assert_eq!(reference.repository, "acme/agents/reviewer");
+        assert_eq!(reference.reference, "latest");
+
+        let digest = format!("sha256:{}", "a".repeat(64));
+        let reference =
+            OciReference::parse(&format!("oci://registry.example/reviewer@{digest}")).unwrap();
+        assert_eq!(reference.reference, digest);
+        assert_eq!(
+            reference.to_string(),
+            format!("registry.example/reviewer@{digest}")
+        );
+
+        assert!(OciReference::parse("oci://reviewer").is_err());
+        assert!(OciReference::parse("oci://host/reviewer@sha256:abc").is_err());
+    }
+
+    #[test]
+    fn test_challenge_params() {
+        let params = challenge_params(
+            r#"realm="https://auth.example/token",service="registry.example",scope="repository:a/b:pull""#,
+        );
+        assert_eq!(
+            params,
+            vec![
+                (
+                    "realm".to_string(),
+                    "https://auth.example/token".to_string()
+                ),
+                ("service".to_string(), "registry.example".to_string()),
+                ("scope".to_string(), "repository:a/b:pull".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,195 @@
+//! Download info fetched while the user is still choosing an agent
+//!
+//! The interactive picker starts a [`DownloadPrefetch`] for the candidates
+//! on its first page, so by the time one is picked its download info has
+//! usually arrived and the package fetch can start straight away. Picking
+//! cancels the lookups for everything else.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use tracing::debug;
+
+use super::types::AgentDownload;
+use super::ApiClient;
+use crate::utils::error::CarpResult;
+
+/// How long prefetched download info is used for. Signed download URLs
+/// last longer than this, but a user who wandered off is better served by a
+/// fresh one.
+const MAX_AGE: Duration = Duration::from_secs(120);
+
+/// Latest-version download info being fetched for each candidate agent
+pub struct DownloadPrefetch {
+    started: Instant,
+    pending: HashMap<String, JoinHandle<CarpResult<AgentDownload>>>,
+}
+
+impl DownloadPrefetch {
+    /// Look up the download info of every agent in `names` concurrently
+    pub fn start<'a>(client: &ApiClient, names: impl IntoIterator<Item = &'a String>) -> Self {
+        let pending = names
+            .into_iter()
+            .map(|name| {
+                let (client, agent) = (client.clone(), name.clone());
+                let task =
+                    tokio::spawn(async move { client.prefetch_agent_download(&agent, None).await });
+                (name.clone(), task)
+            })
+            .collect();
+        Self {
+            started: Instant::now(),
+            pending,
+        }
+    }
+
+    /// Cancel the lookups for every agent but `name`
+    pub fn pick(&mut self, name: &str) {
+        self.pending.retain(|agent, task| {
+            let keep = agent == name;
+            if !keep {
+                task.abort();
+            }
+            keep
+        });
+    }
+
+    /// The download info of `name@version`, waiting for it if the lookup is
+    /// still running. `None` when it wasn't prefetched, failed, is for a
+    /// different version or has gone stale, and should be fetched again.
+    pub async fn take(mut self, name: &str, version: &str) -> Option<AgentDownload> {
+        self.pick(name);
+        let task = self.pending.remove(name)?;
+        let download = match task.await {
+            Ok(Ok(download)) => download,
+            Ok(Err(e)) => {
+                debug!("Prefetching the download info of {name} failed: {e}");
+                return None;
+            }
+            Err(_) => return None,
+        };
+
+        if download.version != version {
+            debug!(
+                "Prefetched {name} v{}, but v{version} was picked",
+                download.version
+            );
+            return None;
+        }
+        if self.started.elapsed() > MAX_AGE {
+            debug!("Prefetched download info of {name} is too old to use");
+            return None;
+        }
+        Some(download)
+    }
+}
+
+impl Drop for DownloadPrefetch {
+    fn drop(&mut self) {
+        for task in self.pending.values() {
+            task.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, SecuritySettings};
+    use mockito::Server;
+
+    fn client(server: &Server) -> ApiClient {
+        let config = Config {
+            registry_url: server.url(),
+            security: SecuritySettings {
+                allow_http: true,
+                ..SecuritySettings::default()
+            },
+            ..Config::default()
+        };
+        ApiClient::new(&config).unwrap()
+    }
+
+    fn download_body(name: &str, version: &str) -> String {
+        serde_json::json!({
+            "agent_id": "00000000-0000-0000-0000-000000000001",
+            "name": name,
+            "author": "octocat",
+            "version": version,
+            "download_url": format!("https://storage.example.com/{name}.zip"),
+            "file_size": 5,
+            "checksum": "sha256:aa",
+            "content_type": "application/zip",
+            "definition": {}
+        })
+        .to_string()
+    }
+
+    #[tokio::test]
+    async fn test_picked_agent_uses_the_prefetched_download() {
+        let mut server = Server::new_async().await;
+        let mut mocks = Vec::new();
+        for name in ["code-reviewer", "meeting-notes"] {
+            mocks.push(
+                server
+                    .mock(
+                        "GET",
+                        format!("/api/v1/agents/{name}/latest/download").as_str(),
+                    )
+                    .match_query(mockito::Matcher::UrlEncoded(
+                        "prefetch".into(),
+                        "true".into(),
+                    ))
+                    .with_body(download_body(name, "1.2.0"))
+                    .expect(1)
+                    .create_async()
+                    .await,
+            );
+        }
+
+        let client = client(&server);
+        let names = vec!["code-reviewer".to_string(), "meeting-notes".to_string()];
+        let prefetch = DownloadPrefetch::start(&client, &names);
+        let download = prefetch.take("code-reviewer", "1.2.0").await.unwrap();
+        assert_eq!(download.name, "code-reviewer");
+        assert_eq!(
+            download.download_url,
+            "https://storage.example.com/code-reviewer.zip"
+        );
+        // Only the picked agent's lookup is waited for
+        mocks[0].assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_other_versions_and_failures_are_fetched_again() {
+        let mut server = Server::new_async().await;
+        server
+            .mock("GET", "/api/v1/agents/code-reviewer/latest/download")
+            .match_query(mockito::Matcher::Any)
+            .with_body(download_body("code-reviewer", "1.2.0"))
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/api/v1/agents/meeting-notes/latest/download")
+            .match_query(mockito::Matcher::Any)
+            .with_status(404)
+            .with_body(r#"{"error":"not_found","message":"gone"}"#)
+            .create_async()
+            .await;
+
+        let client = client(&server);
+        let names = vec!["code-reviewer".to_string(), "meeting-notes".to_string()];
+        let older = DownloadPrefetch::start(&client, &names)
+            .take("code-reviewer", "1.0.0")
+            .await;
+        assert!(older.is_none());
+        let missing = DownloadPrefetch::start(&client, &names)
+            .take("meeting-notes", "1.0.0")
+            .await;
+        assert!(missing.is_none());
+        let unlisted = DownloadPrefetch::start(&client, &names)
+            .take("security-auditor", "1.0.0")
+            .await;
+        assert!(unlisted.is_none());
+    }
+}
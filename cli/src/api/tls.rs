@@ -0,0 +1,238 @@
+//! TLS setup shared by the HTTP clients
+//!
+//! Connections normally go through the platform's TLS library. Requiring
+//! TLS 1.3, or pinning the registry's public keys, switches to rustls with
+//! the platform's trust store instead, since native-tls can do neither.
+
+use crate::config::{Config, TlsVersion};
+use crate::utils::error::{CarpError, CarpResult};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use reqwest::ClientBuilder;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{DigitallySignedStruct, SignatureScheme};
+use rustls_platform_verifier::Verifier;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+/// Prefix of a pin, naming its hash
+const PIN_PREFIX: &str = "sha256/";
+
+/// A `ClientBuilder` honoring `verify_ssl`, `security.min_tls_version` and
+/// `security.spki_pins`, or a configuration error if they can't be met
+pub fn client_builder(config: &Config) -> CarpResult<ClientBuilder> {
+    let builder = ClientBuilder::new().danger_accept_invalid_certs(!config.verify_ssl);
+    let security = &config.security;
+    if security.spki_pins.is_empty() && security.min_tls_version != Some(TlsVersion::Tls13) {
+        return Ok(match security.min_tls_version {
+            Some(_) => builder.min_tls_version(reqwest::tls::Version::TLS_1_2),
+            None => builder,
+        });
+    }
+
+    if !config.verify_ssl {
+        return Err(CarpError::Config(
+            "security.spki_pins and security.min_tls_version = \"1.3\" need verify_ssl = true"
+                .to_string(),
+        ));
+    }
+    let pins = security
+        .spki_pins
+        .iter()
+        .map(|pin| parse_pin(pin))
+        .collect::<CarpResult<Vec<_>>>()?;
+    let registry = reqwest::Url::parse(&config.registry_url)
+        .map_err(|e| CarpError::Config(format!("Invalid registry URL: {e}")))?;
+    if !pins.is_empty() && registry.scheme() != "https" {
+        return Err(CarpError::Config(
+            "security.spki_pins need an https:// registry_url".to_string(),
+        ));
+    }
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let verifier = Verifier::new(provider.clone()).map_err(|e| {
+        CarpError::Config(format!("Failed to load the platform's certificates: {e}"))
+    })?;
+    let verifier = PinningVerifier {
+        inner: verifier,
+        host: registry.host_str().unwrap_or_default().to_ascii_lowercase(),
+        pins,
+    };
+    let versions: &[&rustls::SupportedProtocolVersion] = match security.min_tls_version {
+        Some(TlsVersion::Tls13) => &[&rustls::version::TLS13],
+        _ => rustls::ALL_VERSIONS,
+    };
+    let mut tls = rustls::ClientConfig::builder_with_provider(provider)
+        .with_protocol_versions(versions)
+        .map_err(|e| CarpError::Config(format!("Unsupported TLS settings: {e}")))?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth();
+    tls.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    Ok(builder.use_preconfigured_tls(tls))
+}
+
+/// The SHA-256 hash a `sha256/<base64>` pin holds
+fn parse_pin(pin: &str) -> CarpResult<[u8; 32]> {
+    let invalid = || {
+        CarpError::Config(format!(
+            "Invalid SPKI pin '{pin}'; expected sha256/ and a base64 SHA-256 hash"
+        ))
+    };
+    let hash = pin.trim().strip_prefix(PIN_PREFIX).ok_or_else(invalid)?;
+    BASE64
+        .decode(hash)
+        .ok()
+        .and_then(|hash| hash.try_into().ok())
+        .ok_or_else(invalid)
+}
+
+/// SHA-256 hash of a certificate's DER-encoded SubjectPublicKeyInfo
+fn spki_hash(cert: &CertificateDer<'_>) -> Option<[u8; 32]> {
+    let cert = webpki::EndEntityCert::try_from(cert).ok()?;
+    Some(Sha256::digest(cert.subject_public_key_info().as_ref()).into())
+}
+
+/// Verifies certificates as usual, then requires the registry host's chain
+/// to contain a pinned public key
+#[derive(Debug)]
+struct PinningVerifier {
+    inner: Verifier,
+    host: String,
+    pins: Vec<[u8; 32]>,
+}
+
+impl PinningVerifier {
+    fn is_pinned(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+    ) -> bool {
+        std::iter::once(end_entity)
+            .chain(intermediates)
+            .filter_map(spki_hash)
+            .any(|hash| self.pins.contains(&hash))
+    }
+}
+
+impl ServerCertVerifier for PinningVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+        // Other hosts, like OCI registries and download storage, aren't pinned
+        if self.pins.is_empty() || !server_name.to_str().eq_ignore_ascii_case(&self.host) {
+            return Ok(verified);
+        }
+        if !self.is_pinned(end_entity, intermediates) {
+            return Err(rustls::Error::General(format!(
+                "The certificate of {} matches none of security.spki_pins",
+                self.host
+            )));
+        }
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Self-signed P-256 certificate for registry.example
+    const CERT: &str = "MIIBjTCCATOgAwIBAgIUMVLIhH5A16VwFfbfsJTLDZdof3EwCgYIKoZIzj0EAwIwGzEZMBcGA1UEAwwQcmVnaXN0cnkuZXhhbXBsZTAgFw0yNjEwMTcxMDA2MDBaGA8yMTI2MDkyMzEwMDYwMFowGzEZMBcGA1UEAwwQcmVnaXN0cnkuZXhhbXBsZTBZMBMGByqGSM49AgEGCCqGSM49AwEHA0IABDK4DYDt333Nq8MN4EwaY19AXFSOnb054ZzuirvmHj9AzDtOCtGTf2XHJrrE1fpwpfD280pd24zDChgVgOKZPaijUzBRMB0GA1UdDgQWBBTWJd/KAI7/RTHZDtvfUrnt0qKmhDAfBgNVHSMEGDAWgBTWJd/KAI7/RTHZDtvfUrnt0qKmhDAPBgNVHRMBAf8EBTADAQH/MAoGCCqGSM49BAMCA0gAMEUCIFxQPVlFFezRTLgF9s6IGjIsKfZ2a30gwLAT1YaDxvdXAiEA0r+yG1PxTnUmUM+Jq5RFUE/HqOphMpWct9G39sYQdzE=";
+
+    /// Its pin, from `openssl x509 -pubkey -noout | openssl pkey -pubin
+    /// -outform der | openssl dgst -sha256 -binary | base64`
+    const PIN: &str = "sha256/Jnd5Go4Ny5scjthZ5gsippSikIprCm3bTkV1u9iZyRE=";
+
+    fn config(min_tls_version: Option<TlsVersion>, spki_pins: &[&str]) -> Config {
+        let mut config = Config::default();
+        config.security.min_tls_version = min_tls_version;
+        config.security.spki_pins = spki_pins.iter().map(|pin| pin.to_string()).collect();
+        config
+    }
+
+    #[test]
+    fn test_certificates_match_their_pins() {
+        let cert = CertificateDer::from(BASE64.decode(CERT).unwrap());
+        assert_eq!(spki_hash(&cert), Some(parse_pin(PIN).unwrap()));
+
+        let verifier = |pins: Vec<[u8; 32]>| PinningVerifier {
+            inner: Verifier::new(Arc::new(rustls::crypto::ring::default_provider())).unwrap(),
+            host: "registry.example".to_string(),
+            pins,
+        };
+        assert!(verifier(vec![parse_pin(PIN).unwrap()]).is_pinned(&cert, &[]));
+        assert!(!verifier(vec![[0; 32]]).is_pinned(&cert, &[]));
+        // A pinned intermediate is enough
+        let other = CertificateDer::from(vec![0x30, 0x00]);
+        assert!(verifier(vec![parse_pin(PIN).unwrap()]).is_pinned(&other, &[cert]));
+
+        assert!(parse_pin("Jnd5Go4Ny5scjthZ5gsippSikIprCm3bTkV1u9iZyRE=").is_err());
+        assert!(parse_pin("sha256/not base64").is_err());
+        assert!(parse_pin("sha256/AAAA").is_err());
+    }
+
+    #[test]
+    fn test_strict_settings_are_validated() {
+        assert!(client_builder(&config(None, &[])).unwrap().build().is_ok());
+        assert!(client_builder(&config(Some(TlsVersion::Tls12), &[]))
+            .unwrap()
+            .build()
+            .is_ok());
+        assert!(client_builder(&config(Some(TlsVersion::Tls13), &[PIN]))
+            .unwrap()
+            .build()
+            .is_ok());
+
+        assert!(matches!(
+            client_builder(&config(None, &["sha256/AAAA"])),
+            Err(CarpError::Config(_))
+        ));
+
+        let mut insecure = config(Some(TlsVersion::Tls13), &[]);
+        insecure.verify_ssl = false;
+        assert!(matches!(
+            client_builder(&insecure),
+            Err(CarpError::Config(_))
+        ));
+
+        let mut plain = config(None, &[PIN]);
+        plain.registry_url = "http://localhost:3000".to_string();
+        assert!(matches!(client_builder(&plain), Err(CarpError::Config(_))));
+    }
+}
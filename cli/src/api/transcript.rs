@@ -0,0 +1,500 @@
+//! Transcripts of a command's HTTP traffic for `--record`
+//!
+//! While recording, every request the API client sends is kept with its
+//! response: method, URL, headers, body and how long the registry took to
+//! answer. The transcript is written as JSON when the command finishes, for
+//! users to attach to bug reports.
+//!
+//! Transcripts are sanitized as they're recorded: credential headers, signed
+//! URL parameters and JSON fields such as `api_key` or `password` are
+//! replaced with `***`, and `--api-key` is dropped from the recorded
+//! arguments. Bodies are kept up to [`MAX_BODY_BYTES`]; text is kept as is
+//! and anything else as base64. Package downloads aren't buffered, so only
+//! their size is recorded.
+
+use crate::utils::error::{CarpError, CarpResult};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use chrono::{DateTime, Utc};
+use reqwest::header::{HeaderMap, CONTENT_LENGTH, CONTENT_TYPE};
+use reqwest::{Request, Response, ResponseBuilderExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+/// Version of the transcript format, bumped on incompatible changes
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Most bytes of each body kept in a transcript
+pub const MAX_BODY_BYTES: usize = 64 * 1024;
+
+/// What sanitized values are replaced with
+pub const REDACTED: &str = "***";
+
+/// Headers that carry credentials
+const SENSITIVE_HEADERS: &[&str] = &[
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+    "apikey",
+    "x-api-key",
+];
+
+/// JSON fields that carry credentials, matched case-insensitively
+const SENSITIVE_FIELDS: &[&str] = &[
+    "api_key",
+    "apikey",
+    "key",
+    "token",
+    "access_token",
+    "refresh_token",
+    "id_token",
+    "password",
+    "secret",
+    "client_secret",
+];
+
+/// The recorder of this process, if `--record` was given
+static RECORDER: OnceLock<Recorder> = OnceLock::new();
+
+/// The HTTP traffic of one command run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Transcript {
+    pub version: u32,
+    /// Version of carp that recorded it
+    pub carp_version: String,
+    /// Command-line arguments after `carp`, without the API key
+    pub args: Vec<String>,
+    pub recorded_at: DateTime<Utc>,
+    /// Requests in the order they were sent
+    pub exchanges: Vec<Exchange>,
+}
+
+/// A request and what came back
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Exchange {
+    pub request: RecordedRequest,
+    /// `None` when the request failed before a response arrived
+    pub response: Option<RecordedResponse>,
+    /// Why the request failed, if it did
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Time until the response headers arrived
+    pub elapsed_ms: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<RecordedBody>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedResponse {
+    pub status: u16,
+    pub headers: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<RecordedBody>,
+}
+
+/// A body, or as much of it as was kept
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedBody {
+    /// Size of the whole body in bytes, 0 when it wasn't known
+    pub size: u64,
+    /// Whether bytes were left out, or the body wasn't kept at all
+    pub truncated: bool,
+    /// The body, when it's UTF-8
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    /// The body in base64, when it isn't UTF-8
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base64: Option<String>,
+}
+
+impl RecordedBody {
+    /// Keep up to [`MAX_BODY_BYTES`] of `bytes`, sanitizing JSON
+    pub fn capture(bytes: &[u8]) -> Self {
+        let size = bytes.len() as u64;
+        let truncated = bytes.len() > MAX_BODY_BYTES;
+
+        match std::str::from_utf8(bytes) {
+            Ok(text) => {
+                let text = match serde_json::from_str::<Value>(text) {
+                    Ok(mut json) => {
+                        redact_json(&mut json);
+                        json.to_string()
+                    }
+                    Err(_) => text.to_string(),
+                };
+                let end = floor_char_boundary(&text, MAX_BODY_BYTES);
+                Self {
+                    size,
+                    truncated: end < text.len(),
+                    text: Some(text[..end].to_string()),
+                    base64: None,
+                }
+            }
+            Err(_) => Self {
+                size,
+                truncated,
+                text: None,
+                base64: Some(BASE64.encode(&bytes[..bytes.len().min(MAX_BODY_BYTES)])),
+            },
+        }
+    }
+
+    /// A body that wasn't kept, such as a stream
+    pub fn omitted(size: Option<u64>) -> Self {
+        Self {
+            size: size.unwrap_or(0),
+            truncated: true,
+            text: None,
+            base64: None,
+        }
+    }
+}
+
+struct Recorder {
+    path: PathBuf,
+    transcript: Mutex<Transcript>,
+}
+
+/// Start recording this process's HTTP traffic, to be written to `path`
+/// by [`finish`]
+pub fn start(path: PathBuf, args: Vec<String>) {
+    let _ = RECORDER.set(Recorder {
+        path,
+        transcript: Mutex::new(Transcript {
+            version: FORMAT_VERSION,
+            carp_version: env!("CARGO_PKG_VERSION").to_string(),
+            args: sanitize_args(args),
+            recorded_at: Utc::now(),
+            exchanges: Vec::new(),
+        }),
+    });
+}
+
+/// Whether `--record` is on
+pub fn recording() -> bool {
+    RECORDER.get().is_some()
+}
+
+/// Write the transcript, returning where it went and how many requests it
+/// holds. Does nothing unless recording.
+pub fn finish() -> CarpResult<Option<(PathBuf, usize)>> {
+    let Some(recorder) = RECORDER.get() else {
+        return Ok(None);
+    };
+    let transcript = recorder.transcript.lock().unwrap();
+    let json = serde_json::to_string_pretty(&*transcript)?;
+    std::fs::write(&recorder.path, json + "\n").map_err(|e| {
+        CarpError::FileSystem(format!(
+            "Failed to write transcript to {}: {e}",
+            recorder.path.display()
+        ))
+    })?;
+    Ok(Some((recorder.path.clone(), transcript.exchanges.len())))
+}
+
+/// The sanitized request, captured before it's sent
+pub fn capture_request(request: &Request, url: String) -> RecordedRequest {
+    let body = request.body().map(|body| match body.as_bytes() {
+        Some(bytes) => RecordedBody::capture(bytes),
+        None => RecordedBody::omitted(content_length(request.headers())),
+    });
+
+    RecordedRequest {
+        method: request.method().to_string(),
+        url,
+        headers: sanitize_headers(request.headers()),
+        body,
+    }
+}
+
+/// Add the exchange to the transcript, handing the response on
+pub async fn record(
+    request: RecordedRequest,
+    result: reqwest::Result<Response>,
+    elapsed: Duration,
+) -> reqwest::Result<Response> {
+    let Some(recorder) = RECORDER.get() else {
+        return result;
+    };
+    let (exchange, result) = observe(request, result, elapsed).await;
+    recorder.transcript.lock().unwrap().exchanges.push(exchange);
+    result
+}
+
+/// The exchange for a request and its outcome, along with the response to
+/// hand on.
+///
+/// Text responses are read here and handed on from memory; anything else
+/// is handed on untouched and recorded without its body.
+async fn observe(
+    request: RecordedRequest,
+    result: reqwest::Result<Response>,
+    elapsed: Duration,
+) -> (Exchange, reqwest::Result<Response>) {
+    let mut exchange = Exchange {
+        request,
+        response: None,
+        error: None,
+        elapsed_ms: elapsed.as_millis() as u64,
+    };
+
+    let result = match result {
+        Ok(response) => {
+            let status = response.status().as_u16();
+            let headers = sanitize_headers(response.headers());
+            let (body, response) = if is_text(response.headers()) {
+                match buffer(response).await {
+                    Ok((bytes, response)) => (Some(RecordedBody::capture(&bytes)), Ok(response)),
+                    Err(e) => {
+                        exchange.error = Some(e.to_string());
+                        (None, Err(e))
+                    }
+                }
+            } else {
+                let size = response.content_length();
+                (Some(RecordedBody::omitted(size)), Ok(response))
+            };
+            exchange.response = Some(RecordedResponse {
+                status,
+                headers,
+                body,
+            });
+            response
+        }
+        Err(e) => {
+            exchange.error = Some(e.to_string());
+            Err(e)
+        }
+    };
+
+    (exchange, result)
+}
+
+/// Read the body of `response`, returning it with an equivalent response
+/// that serves it from memory
+async fn buffer(response: Response) -> reqwest::Result<(bytes::Bytes, Response)> {
+    let mut builder = http::Response::builder()
+        .status(response.status())
+        .version(response.version())
+        .url(response.url().clone());
+    if let Some(headers) = builder.headers_mut() {
+        *headers = response.headers().clone();
+    }
+    let bytes = response.bytes().await?;
+    let response = builder
+        .body(bytes.clone())
+        .expect("parts of a valid response");
+    Ok((bytes, Response::from(response)))
+}
+
+/// Whether a response is JSON or text, rather than a package
+fn is_text(headers: &HeaderMap) -> bool {
+    let Some(content_type) = headers.get(CONTENT_TYPE).and_then(|v| v.to_str().ok()) else {
+        return true;
+    };
+    let content_type = content_type.to_ascii_lowercase();
+    content_type.starts_with("text/") || content_type.contains("json")
+}
+
+fn content_length(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+}
+
+/// Headers as name to value, with credentials masked
+fn sanitize_headers(headers: &HeaderMap) -> BTreeMap<String, String> {
+    let mut sanitized: BTreeMap<String, String> = BTreeMap::new();
+    for (name, value) in headers {
+        let value = if SENSITIVE_HEADERS.contains(&name.as_str()) {
+            REDACTED.to_string()
+        } else {
+            String::from_utf8_lossy(value.as_bytes()).into_owned()
+        };
+        sanitized
+            .entry(name.to_string())
+            .and_modify(|existing| {
+                existing.push_str(", ");
+                existing.push_str(&value);
+            })
+            .or_insert(value);
+    }
+    sanitized
+}
+
+/// Mask credential fields anywhere in a JSON document
+fn redact_json(value: &mut Value) {
+    match value {
+        Value::Object(fields) => {
+            for (name, field) in fields.iter_mut() {
+                if SENSITIVE_FIELDS.contains(&name.to_ascii_lowercase().as_str())
+                    && !field.is_null()
+                {
+                    *field = Value::String(REDACTED.to_string());
+                } else {
+                    redact_json(field);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact_json),
+        _ => {}
+    }
+}
+
+/// Drop the value of `--api-key` from command-line arguments
+fn sanitize_args(args: Vec<String>) -> Vec<String> {
+    let mut sanitized = Vec::with_capacity(args.len());
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--api-key" {
+            args.next();
+            sanitized.extend([arg, REDACTED.to_string()]);
+        } else if arg.starts_with("--api-key=") {
+            sanitized.push(format!("--api-key={REDACTED}"));
+        } else {
+            sanitized.push(arg);
+        }
+    }
+    sanitized
+}
+
+/// The largest index up to `max` that falls on a character boundary
+fn floor_char_boundary(text: &str, max: usize) -> usize {
+    if max >= text.len() {
+        return text.len();
+    }
+    (0..=max)
+        .rev()
+        .find(|&i| text.is_char_boundary(i))
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn test_capture_sanitizes_json() {
+        let body = RecordedBody::capture(
+            br#"{"name": "agent", "api_key": "carp_abc_def_ghi", "keys": [{"token": "t", "key": null}]}"#,
+        );
+        let json: Value = serde_json::from_str(body.text.as_deref().unwrap()).unwrap();
+        assert_eq!(json["name"], "agent");
+        assert_eq!(json["api_key"], REDACTED);
+        assert_eq!(json["keys"][0]["token"], REDACTED);
+        assert!(json["keys"][0]["key"].is_null());
+        assert!(!body.truncated);
+    }
+
+    #[test]
+    fn test_capture_truncates() {
+        let text = "é".repeat(MAX_BODY_BYTES);
+        let body = RecordedBody::capture(text.as_bytes());
+        assert!(body.truncated);
+        assert_eq!(body.size, text.len() as u64);
+        assert_eq!(body.text.unwrap().len(), MAX_BODY_BYTES);
+
+        let binary = RecordedBody::capture(&[0xff, 0x00, 0x01]);
+        assert_eq!(binary.text, None);
+        assert_eq!(binary.base64.as_deref(), Some("/wAB"));
+    }
+
+    #[test]
+    fn test_sanitize_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", HeaderValue::from_static("Bearer secret"));
+        headers.insert("accept", HeaderValue::from_static("application/json"));
+        headers.append("vary", HeaderValue::from_static("accept"));
+        headers.append("vary", HeaderValue::from_static("origin"));
+
+        let sanitized = sanitize_headers(&headers);
+        assert_eq!(sanitized["authorization"], REDACTED);
+        assert_eq!(sanitized["accept"], "application/json");
+        assert_eq!(sanitized["vary"], "accept, origin");
+    }
+
+    #[tokio::test]
+    async fn test_observe_hands_the_response_on() {
+        let mut server = mockito::Server::new_async().await;
+        let _json = server
+            .mock("POST", "/api/v1/auth/api-keys")
+            .with_status(201)
+            .with_header("content-type", "application/json")
+            .with_header("set-cookie", "session=secret")
+            .with_body(r#"{"id": "1", "key": "carp_abc_def_ghi"}"#)
+            .create_async()
+            .await;
+        let _archive = server
+            .mock("GET", "/agent.zip")
+            .with_header("content-type", "application/zip")
+            .with_body(b"PK\x05\x06")
+            .create_async()
+            .await;
+        let client = reqwest::Client::new();
+
+        let request = client
+            .post(format!("{}/api/v1/auth/api-keys", server.url()))
+            .header("authorization", "Bearer secret")
+            .json(&serde_json::json!({"name": "ci", "password": "hunter2"}))
+            .build()
+            .unwrap();
+        let recorded = capture_request(&request, request.url().to_string());
+        let result = client.execute(request).await;
+        let (exchange, result) = observe(recorded, result, Duration::from_millis(12)).await;
+
+        // The caller still gets the whole, unsanitized body
+        let body: Value = result.unwrap().json().await.unwrap();
+        assert_eq!(body["key"], "carp_abc_def_ghi");
+
+        assert_eq!(exchange.request.method, "POST");
+        assert_eq!(exchange.request.headers["authorization"], REDACTED);
+        let sent = exchange.request.body.unwrap().text.unwrap();
+        assert_eq!(sent, r#"{"name":"ci","password":"***"}"#);
+        let response = exchange.response.unwrap();
+        assert_eq!(response.status, 201);
+        assert_eq!(response.headers["set-cookie"], REDACTED);
+        assert_eq!(
+            response.body.unwrap().text.unwrap(),
+            r#"{"id":"1","key":"***"}"#
+        );
+        assert_eq!(exchange.elapsed_ms, 12);
+
+        // Packages are handed on as they stream and recorded by size
+        let request = client
+            .get(format!("{}/agent.zip", server.url()))
+            .build()
+            .unwrap();
+        let recorded = capture_request(&request, request.url().to_string());
+        let result = client.execute(request).await;
+        let (exchange, result) = observe(recorded, result, Duration::ZERO).await;
+        assert_eq!(&result.unwrap().bytes().await.unwrap()[..], b"PK\x05\x06");
+        assert_eq!(
+            exchange.response.unwrap().body,
+            Some(RecordedBody::omitted(Some(4)))
+        );
+    }
+
+    #[test]
+    fn test_sanitize_args() {
+        let args = ["--api-key", "secret", "pull", "--api-key=secret", "agent"]
+            .map(String::from)
+            .to_vec();
+        assert_eq!(
+            sanitize_args(args),
+            ["--api-key", REDACTED, "pull", "--api-key=***", "agent"]
+        );
+    }
+}
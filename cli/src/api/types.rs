@@ -1,5 +1,6 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 /// Agent metadata returned by the API
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -11,11 +12,93 @@ pub struct Agent {
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
     pub download_count: u64,
+    /// Absent from registries that predate stars
+    #[serde(default)]
+    pub star_count: u64,
+    #[serde(default)]
+    pub rating_count: u64,
+    /// Mean of published review ratings, absent until the first review
+    #[serde(default)]
+    pub rating_average: Option<f64>,
     pub tags: Vec<String>,
     pub readme: Option<String>,
     pub homepage: Option<String>,
     pub repository: Option<String>,
     pub license: Option<String>,
+    /// Sigstore bundle for keyless-signed agents
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature_bundle: Option<serde_json::Value>,
+    /// False for private agents, which only their owner can see
+    #[serde(default = "default_is_public")]
+    pub is_public: bool,
+    /// A starting point for `carp new --template`
+    #[serde(default)]
+    pub is_template: bool,
+    /// The verified namespace the agent is published in, e.g. `acme` for
+    /// `acme-reviewer`, when its owner belongs to the organization that
+    /// claimed it
+    #[serde(default)]
+    pub verified_namespace: Option<String>,
+    /// Published by a verified user or in a verified namespace
+    #[serde(default)]
+    pub verified: bool,
+}
+
+/// Registries that predate private agents only serve public ones
+fn default_is_public() -> bool {
+    true
+}
+
+/// How a search query is matched against agents
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchMode {
+    /// Substring of the name, description, author or a tag
+    Substring,
+    /// The exact agent name
+    Exact,
+    /// Agent name against a glob with `*` and `?`
+    Glob,
+    /// Agent name against a regular expression
+    Regex,
+}
+
+impl SearchMode {
+    /// Pick the mode for a query typed by the user: globs are recognised by
+    /// their wildcards
+    pub fn for_query(query: &str, exact: bool, regex: bool) -> Self {
+        if regex {
+            SearchMode::Regex
+        } else if exact {
+            SearchMode::Exact
+        } else if query.contains(['*', '?']) {
+            SearchMode::Glob
+        } else {
+            SearchMode::Substring
+        }
+    }
+}
+
+/// Order of search results
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SearchSort {
+    /// Most downloaded first
+    #[default]
+    Downloads,
+    /// Most starred first
+    Stars,
+    /// Most recently updated first
+    Recent,
+}
+
+impl SearchSort {
+    /// Value of the `sort` query parameter
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SearchSort::Downloads => "downloads",
+            SearchSort::Stars => "stars",
+            SearchSort::Recent => "recent",
+        }
+    }
 }
 
 /// Search results from the API
@@ -27,6 +110,50 @@ pub struct SearchResponse {
     pub per_page: usize,
 }
 
+/// What happened to an agent, in the registry's change feed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Created,
+    /// A new version, or new metadata
+    Updated,
+    /// A version was yanked
+    Yanked,
+    /// No longer listed: deleted, or made private
+    Removed,
+}
+
+/// An agent that changed since a change feed cursor
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AgentChange {
+    pub kind: ChangeKind,
+    pub name: String,
+    /// The agent as it is now, absent when it's no longer listed
+    #[serde(default)]
+    pub agent: Option<Agent>,
+}
+
+/// A page of the registry's change feed
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AgentChanges {
+    /// Oldest first
+    pub changes: Vec<AgentChange>,
+    /// Where the next page, or the next sync, starts
+    pub cursor: String,
+    /// Whether more changes are waiting after `cursor`
+    #[serde(default)]
+    pub has_more: bool,
+}
+
+/// Agents published as templates, from the templates API
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TemplatesResponse {
+    pub templates: Vec<Agent>,
+    pub total: usize,
+    pub page: usize,
+    pub per_page: usize,
+}
+
 /// Agent download information
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AgentDownload {
@@ -39,6 +166,9 @@ pub struct AgentDownload {
     pub checksum: String,
     pub content_type: String,
     pub definition: serde_json::Value,
+    /// Base64 package bytes, present when the package was requested inline
+    #[serde(default)]
+    pub content: Option<String>,
 }
 
 /// Request for publishing an agent
@@ -70,6 +200,38 @@ pub struct ApiError {
     pub details: Option<serde_json::Value>,
 }
 
+impl ApiError {
+    /// Read an error body, either as the registry's problem details or in the
+    /// `{"error", "message"}` shape registries answered with before them
+    pub fn from_body(text: &str) -> Option<Self> {
+        if let Ok(api_error) = serde_json::from_str::<ApiError>(text) {
+            return Some(api_error);
+        }
+        let problem = serde_json::from_str::<ProblemDetails>(text).ok()?;
+        Some(ApiError {
+            // The code is the last segment of the problem's `type` URI
+            error: problem
+                .type_uri
+                .rsplit('/')
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+            message: problem.detail.or(problem.title).unwrap_or_default(),
+            details: problem.details,
+        })
+    }
+}
+
+/// RFC 7807 problem details, without the fields kept for older clients
+#[derive(Debug, Deserialize)]
+struct ProblemDetails {
+    #[serde(rename = "type")]
+    type_uri: String,
+    title: Option<String>,
+    detail: Option<String>,
+    details: Option<serde_json::Value>,
+}
+
 /// Authentication request
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AuthRequest {
@@ -95,6 +257,199 @@ pub struct UploadAgentRequest {
     pub homepage: Option<String>,
     pub repository: Option<String>,
     pub license: Option<String>,
+    /// Sigstore bundle signing `content`, present for keyless publishes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature_bundle: Option<serde_json::Value>,
+    /// Source and build information captured at publish time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Provenance>,
+    /// Only the publisher can see private agents
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub private: bool,
+    /// Offer the agent as a starting point for `carp new --template`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub template: bool,
+    /// Fingerprints of secret findings the publisher says are false positives
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_secrets: Vec<String>,
+    /// Agent name to semver requirement; without them the registry reads the
+    /// frontmatter's `dependencies`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub dependencies: BTreeMap<String, String>,
+}
+
+/// Source and build information for a published agent
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Provenance {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub git_commit: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repository_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ci_run_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub builder: Option<String>,
+}
+
+/// Detailed agent information from the info API
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AgentInfo {
+    pub name: String,
+    pub version: String,
+    /// Downloadable versions, oldest first; empty from registries that predate it
+    #[serde(default)]
+    pub versions: Vec<String>,
+    pub description: String,
+    pub author: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub download_count: u64,
+    #[serde(default)]
+    pub star_count: u64,
+    #[serde(default)]
+    pub rating_count: u64,
+    #[serde(default)]
+    pub rating_average: Option<f64>,
+    pub tags: Vec<String>,
+    pub homepage: Option<String>,
+    pub repository: Option<String>,
+    pub license: Option<String>,
+    #[serde(default)]
+    pub signed: bool,
+    pub transparency_log_index: Option<i64>,
+    pub signed_at: Option<DateTime<Utc>>,
+    pub provenance: Option<Provenance>,
+    #[serde(default = "default_is_public")]
+    pub is_public: bool,
+    #[serde(default)]
+    pub verified_namespace: Option<String>,
+    #[serde(default)]
+    pub verified: bool,
+}
+
+/// Agents to look up with the batch info API
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BatchInfoRequest {
+    pub names: Vec<String>,
+}
+
+/// The agents the batch info API found, in request order, and the names it didn't
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct BatchInfoResponse {
+    pub agents: Vec<AgentInfo>,
+    pub missing: Vec<String>,
+}
+
+/// One published version of an agent, from the versions API
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentVersion {
+    pub version: String,
+    pub checksum: Option<String>,
+    pub file_size: u64,
+    /// Yanked versions can't be downloaded, but their number stays taken
+    #[serde(default)]
+    pub yanked: bool,
+    pub yanked_reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Every version of an agent, oldest first
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AgentVersionsResponse {
+    pub name: String,
+    pub versions: Vec<AgentVersion>,
+}
+
+/// An agent's latest version and its package checksum
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentChecksum {
+    pub name: String,
+    pub version: String,
+    /// SHA-256 of the package, as `sha256:<hex>`
+    pub checksum: String,
+}
+
+/// An agent's dependency closure as resolved and signed by the registry
+///
+/// `manifest` is kept as the exact JSON string that was signed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedResolution {
+    pub manifest: String,
+    /// Base64 Ed25519 signature of `manifest`
+    pub signature: String,
+    /// Base64 Ed25519 public key of the registry
+    pub public_key: String,
+}
+
+/// What a [`SignedResolution`] vouches for: `root` at `requirement` and
+/// everything it depends on, one version each
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResolutionManifest {
+    pub root: String,
+    pub requirement: String,
+    pub agents: Vec<ResolvedAgent>,
+    pub issued_at: DateTime<Utc>,
+}
+
+/// One agent of a resolved closure
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResolvedAgent {
+    pub name: String,
+    pub version: String,
+    /// SHA-256 of the package, as `sha256:<hex>`
+    pub checksum: String,
+}
+
+/// An agent whose name is close to one that matched nothing
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentSuggestion {
+    pub name: String,
+    /// Edits needed to turn the query into the name
+    pub distance: u32,
+    /// Trigram similarity to the query, from 0 to 1
+    pub similarity: f32,
+}
+
+/// Near-miss names for a query, closest first
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SuggestResponse {
+    pub query: String,
+    pub suggestions: Vec<AgentSuggestion>,
+}
+
+/// Editable metadata for an agent owned by the caller
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentMetadata {
+    pub name: String,
+    pub description: String,
+    #[serde(default)]
+    pub tags: Option<Vec<String>>,
+    pub homepage: Option<String>,
+    pub readme: Option<String>,
+    /// Bumped by the registry on every change; sent back as `If-Match`
+    pub metadata_version: i64,
+}
+
+/// Metadata fields to change; omitted fields are left as they are
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct MetadataUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub homepage: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub readme: Option<String>,
+}
+
+impl MetadataUpdate {
+    pub fn is_empty(&self) -> bool {
+        self.description.is_none()
+            && self.tags.is_none()
+            && self.homepage.is_none()
+            && self.readme.is_none()
+    }
 }
 
 /// Response from uploading an agent
@@ -123,4 +478,267 @@ pub struct HealthResponse {
     pub agent_count: Option<i64>,
     pub timestamp: String,
     pub error: Option<String>,
+    /// Per-component results, only present for deep health checks
+    #[serde(default)]
+    pub components: Option<Vec<ComponentHealth>>,
+}
+
+/// Health of a single backing service
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ComponentHealth {
+    pub name: String,
+    pub status: String,
+    pub latency_ms: u64,
+    pub error: Option<String>,
+}
+
+/// Anonymous usage event sent when telemetry is enabled.
+///
+/// Deliberately carries no arguments, agent names, paths or error messages.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TelemetryEvent {
+    pub command: String,
+    pub duration_ms: u64,
+    pub success: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_kind: Option<String>,
+    pub cli_version: String,
+    pub os: String,
+    pub arch: String,
+}
+
+/// The identity behind an API key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WhoAmI {
+    pub user_id: String,
+    pub key_id: Option<String>,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    #[serde(default)]
+    pub github_username: Option<String>,
+}
+
+/// One of the user's API keys, without the secret
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKeyInfo {
+    pub id: String,
+    pub name: String,
+    pub prefix: String,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    pub is_active: bool,
+    #[serde(default)]
+    pub last_used_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub last_used_ip: Option<String>,
+    #[serde(default)]
+    pub last_used_user_agent: Option<String>,
+    #[serde(default)]
+    pub last_used_country: Option<String>,
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A tag and how many public agents use it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TagCount {
+    pub tag: String,
+    pub agent_count: u64,
+}
+
+/// Tags response from the API, most used first
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TagsResponse {
+    pub tags: Vec<TagCount>,
+}
+
+/// Result of starring or unstarring an agent
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StarResponse {
+    pub name: String,
+    pub starred: bool,
+    pub star_count: u64,
+}
+
+/// The caller's starred agents, most recently starred first
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StarredResponse {
+    pub agents: Vec<Agent>,
+    pub total: usize,
+}
+
+/// An agent listed on an author's profile
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthorAgent {
+    pub name: String,
+    pub version: String,
+    pub description: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub download_count: u64,
+    #[serde(default)]
+    pub star_count: u64,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub homepage: Option<String>,
+    pub repository: Option<String>,
+    pub license: Option<String>,
+    #[serde(default)]
+    pub verified: bool,
+}
+
+/// A user's public profile and the public agents they publish
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserProfile {
+    pub username: String,
+    pub display_name: Option<String>,
+    pub avatar_url: Option<String>,
+    pub bio: Option<String>,
+    pub joined_at: DateTime<Utc>,
+    /// An admin vouched for the user
+    #[serde(default)]
+    pub verified: bool,
+    pub agent_count: usize,
+    pub total_downloads: u64,
+    #[serde(default)]
+    pub total_stars: u64,
+    pub agents: Vec<AuthorAgent>,
+}
+
+/// A review of an agent
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Review {
+    pub author_name: String,
+    pub rating: u8,
+    pub body: String,
+    /// `published`, or `pending` while a held review awaits a moderator
+    pub status: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Request creating or replacing the caller's review of an agent
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReviewRequest {
+    pub rating: u8,
+    pub body: String,
+}
+
+/// An agent's rating and its most recent published reviews
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReviewsResponse {
+    pub name: String,
+    pub rating_average: Option<f64>,
+    pub rating_count: u64,
+    pub reviews: Vec<Review>,
+}
+
+/// Why an agent is being reported
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportReason {
+    /// Prompts or files that try to cause harm, e.g. exfiltrating secrets
+    Malicious,
+    /// Published in breach of the original work's license
+    LicenseViolation,
+    /// Pretends to be another author or project
+    Impersonation,
+    /// Low-effort, duplicated or promotional content
+    Spam,
+    /// Offensive or otherwise inappropriate content
+    Inappropriate,
+    /// Anything else; details are required
+    Other,
+}
+
+/// Abuse report sent to the registry's moderators
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReportRequest {
+    pub reason: ReportReason,
+    pub details: String,
+}
+
+/// Acknowledgement of an abuse report
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReportResponse {
+    pub name: String,
+    pub reason: ReportReason,
+    /// False when you already had an open report on the agent
+    pub created: bool,
+    pub message: String,
+}
+
+/// How to prove control of the GitHub organization behind a namespace
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum ClaimMethod {
+    /// Be a public member of the organization, as the GitHub user you signed in with
+    Github,
+    /// Publish a TXT record on the domain of the organization's website
+    Dns,
+    /// Publish a file under /.well-known on the organization's website
+    Url,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClaimRequest {
+    pub method: ClaimMethod,
+}
+
+/// Where to publish the value that proves control of a domain
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClaimChallenge {
+    /// DNS TXT record name, or URL of the file
+    pub location: String,
+    pub value: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// How a namespace claim stands
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClaimResponse {
+    pub namespace: String,
+    pub method: ClaimMethod,
+    pub verified: bool,
+    #[serde(default)]
+    pub domain: Option<String>,
+    #[serde(default)]
+    pub verified_at: Option<DateTime<Utc>>,
+    /// Set until a DNS or URL claim's challenge is found
+    #[serde(default)]
+    pub challenge: Option<ClaimChallenge>,
+}
+
+/// Who to share a private agent with. Exactly one field is set.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct AccessRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub org: Option<String>,
+}
+
+/// Whether a grant is for a user or an organization
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GranteeKind {
+    User,
+    Org,
+}
+
+/// Someone a private agent is shared with
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AccessGrant {
+    pub kind: GranteeKind,
+    pub name: String,
+    pub granted_at: DateTime<Utc>,
+}
+
+/// Who can read an agent besides its owner
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AccessResponse {
+    pub name: String,
+    pub is_public: bool,
+    pub grants: Vec<AccessGrant>,
 }
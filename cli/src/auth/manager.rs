@@ -1,6 +1,8 @@
 use crate::config::ConfigManager;
+use crate::utils::ci;
 use crate::utils::error::{CarpError, CarpResult};
 use colored::*;
+use tracing::{debug, instrument};
 
 /// Authentication manager for handling login/logout
 pub struct AuthManager;
@@ -8,6 +10,8 @@ pub struct AuthManager;
 impl AuthManager {
     /// Login with API key for authentication
     pub async fn login() -> CarpResult<()> {
+        ci::ensure_interactive("Logging in", "set CARP_API_KEY or pass --api-key instead")?;
+
         println!("{}", "Login to Carp Registry".bold().green());
         println!("Enter your API key (input will be hidden):");
 
@@ -62,9 +66,20 @@ impl AuthManager {
     /// Check if user is currently authenticated, considering runtime API key
     pub async fn check_auth_with_key(api_key: Option<&str>) -> CarpResult<bool> {
         if api_key.is_some() {
+            debug!("Using API key from the command line or environment");
             return Ok(true);
         }
-        Self::check_auth().await
+
+        let authenticated = Self::check_auth().await?;
+        debug!(
+            "{}",
+            if authenticated {
+                "Using API key from the config file"
+            } else {
+                "No API key configured"
+            }
+        );
+        Ok(authenticated)
     }
 
     /// Get current authentication status
@@ -112,16 +127,10 @@ impl AuthManager {
     }
 
     /// Ensure user is authenticated, prompt to login if not
+    #[instrument(skip_all)]
     pub async fn ensure_authenticated(api_key: Option<&str>) -> CarpResult<()> {
         if !Self::check_auth_with_key(api_key).await? {
-            println!("{}", "Authentication required.".yellow().bold());
-            println!("You can authenticate by:");
-            println!("  1. Setting CARP_API_KEY environment variable");
-            println!("  2. Using --api-key command line option");
-            println!("  3. Running 'carp auth login' to store API key in config");
-            return Err(CarpError::Auth(
-                "No API key configured. Please set your API key via command line, environment variable, or config file.".to_string(),
-            ));
+            return Err(CarpError::Auth("No API key configured".to_string()));
         }
         Ok(())
     }
@@ -0,0 +1,211 @@
+use crate::api::ApiClient;
+use crate::commands::install::{
+    download_package, install_agent, preserve_project_files, print_installed, render_variables,
+    resolve_version,
+};
+use crate::commands::pull::parse_agent_spec;
+use crate::config::Config;
+use crate::utils::agent_dir;
+use crate::utils::error::CarpResult;
+use crate::utils::git_source::{GitSource, GIT_PREFIX};
+use crate::utils::overwrite::kept_local_changes;
+use crate::utils::workspace::{
+    parse_requirement, Dependency, LockedAgent, Lockfile, ManifestEditor, Workspace, MANIFEST_FILE,
+};
+use colored::*;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Options controlling `carp add`
+#[derive(Debug, Default)]
+pub struct AddOptions {
+    /// Directory to install the agent in, instead of `[install] dir`
+    pub dir: Option<String>,
+    /// Only record the agent in the manifest and lockfile
+    pub no_install: bool,
+}
+
+/// Execute the add command: resolve an agent, record it in `carp.toml` and
+/// `carp.lock`, and install it
+pub async fn execute(
+    client: &ApiClient,
+    config: &Config,
+    spec: String,
+    options: AddOptions,
+    verbose: bool,
+) -> CarpResult<()> {
+    if spec.starts_with(GIT_PREFIX) {
+        return add_git(&spec, options);
+    }
+
+    let (name, requirement) = parse_agent_spec(&spec)?;
+    let parsed = parse_requirement(&name, requirement.unwrap_or("*"))?;
+
+    let cwd = std::env::current_dir()?;
+    let (root, created, workspace) = open_workspace(&cwd)?;
+    let lockfile = Lockfile::load(&root)?;
+
+    let version = resolve_version(client, &name, &parsed).await?;
+
+    // Without an explicit requirement, accept updates compatible with what was resolved
+    let requirement = requirement.map(str::to_string).unwrap_or(version.clone());
+    let dir = options.dir.or_else(|| {
+        workspace
+            .agents
+            .get(&name)
+            .and_then(|dependency| dependency.dir().map(str::to_string))
+    });
+    let dependency = match dir {
+        Some(dir) => Dependency::Detailed {
+            version: requirement.clone(),
+            dir: Some(dir),
+        },
+        None => Dependency::Version(requirement.clone()),
+    };
+
+    // Already locked at this version, the package must not have changed
+    let expected = lockfile
+        .get(&name)
+        .filter(|locked| locked.version == version)
+        .map(|locked| locked.checksum.clone());
+
+    let recorded = workspace.variables.get(&name);
+    let (checksum, values) = if options.no_install {
+        let (_, _, checksum) =
+            download_package(client, &name, &version, expected.as_deref()).await?;
+        (checksum, BTreeMap::new())
+    } else {
+        let dest = workspace.install_dir(&root, &name, &dependency);
+        if !agent_dir::confirm_discard(&name, &dest)? {
+            return Err(kept_local_changes(&name));
+        }
+        let (checksum, progress) = install_agent(
+            client,
+            config,
+            &name,
+            &version,
+            expected.as_deref(),
+            &dest,
+            verbose,
+        )
+        .await?;
+        let values = render_variables(&name, &dest, recorded, &BTreeMap::new())?;
+        agent_dir::record(&dest)?;
+        print_installed(&name, &version, progress.entries, &dest, &cwd);
+        (checksum, values)
+    };
+
+    preserve_project_files(&root)?;
+    let mut editor = ManifestEditor::open(&root)?;
+    editor.set_agent(&name, &dependency)?;
+    if !values.is_empty() {
+        editor.set_variables(&name, &values)?;
+    }
+    editor.save()?;
+    Lockfile::update(&root, |lockfile| {
+        lockfile.set(LockedAgent {
+            name: name.clone(),
+            version: version.clone(),
+            checksum,
+            source: None,
+        })
+    })?;
+
+    print_created(&root, created);
+    println!(
+        "{} Added {} = \"{}\" to {} (locked at v{})",
+        "✓".green().bold(),
+        name.blue().bold(),
+        requirement,
+        MANIFEST_FILE,
+        version
+    );
+
+    Ok(())
+}
+
+/// Add an agent from a git repository, named by the agent the repository holds
+fn add_git(spec: &str, options: AddOptions) -> CarpResult<()> {
+    let source = GitSource::parse(spec)?;
+
+    let cwd = std::env::current_dir()?;
+    let (root, created, workspace) = open_workspace(&cwd)?;
+
+    // The agent is named by the repository, not the spec
+    let checkout = source.checkout(None)?;
+    let agent = checkout.agent()?;
+    let name = agent.name;
+
+    let dir = options.dir.or_else(|| {
+        workspace
+            .agents
+            .get(&name)
+            .and_then(|dependency| dependency.dir().map(str::to_string))
+    });
+    let dependency = Dependency::from_git(&source, dir);
+
+    let checksum = checkout.checksum()?;
+    let mut values = BTreeMap::new();
+    if !options.no_install {
+        let dest = workspace.install_dir(&root, &name, &dependency);
+        if !agent_dir::confirm_discard(&name, &dest)? {
+            return Err(kept_local_changes(&name));
+        }
+        let files = checkout.install(&dest)?;
+        let recorded = workspace.variables.get(&name);
+        values = render_variables(&name, &dest, recorded, &BTreeMap::new())?;
+        agent_dir::record(&dest)?;
+        print_installed(&name, &agent.version, files, &dest, &cwd);
+    }
+
+    preserve_project_files(&root)?;
+    let mut editor = ManifestEditor::open(&root)?;
+    editor.set_agent(&name, &dependency)?;
+    if !values.is_empty() {
+        editor.set_variables(&name, &values)?;
+    }
+    editor.save()?;
+    Lockfile::update(&root, |lockfile| {
+        lockfile.set(LockedAgent {
+            name: name.clone(),
+            version: agent.version,
+            checksum,
+            source: Some(source.locked(&checkout.commit)),
+        })
+    })?;
+
+    print_created(&root, created);
+    println!(
+        "{} Added {} from {} to {} (locked at {})",
+        "✓".green().bold(),
+        name.blue().bold(),
+        source,
+        MANIFEST_FILE,
+        &checkout.commit[..checkout.commit.len().min(12)]
+    );
+
+    Ok(())
+}
+
+/// The project root and its manifest; projects without one get one in the
+/// current directory
+fn open_workspace(cwd: &Path) -> CarpResult<(PathBuf, bool, Workspace)> {
+    let root = Workspace::find_root(cwd).unwrap_or_else(|| cwd.to_path_buf());
+    let created = !root.join(MANIFEST_FILE).exists();
+    let workspace = if created {
+        Workspace::default()
+    } else {
+        Workspace::load(&root)?
+    };
+    Ok((root, created, workspace))
+}
+
+fn print_created(root: &Path, created: bool) {
+    if created {
+        println!(
+            "{} Created {}",
+            "✓".green().bold(),
+            root.join(MANIFEST_FILE).display()
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use crate::api::{ApiClient, UserProfile};
+use crate::utils::error::CarpResult;
+use colored::*;
+use tracing::debug;
+
+/// Execute the author command to show a user's profile and agents
+pub async fn execute(client: &ApiClient, username: String, verbose: bool) -> CarpResult<()> {
+    debug!("Fetching profile for '{username}'...");
+
+    let profile = client.get_user(&username).await?;
+
+    display_profile(&profile);
+
+    if profile.agents.is_empty() {
+        println!(
+            "\n{}",
+            format!(
+                "{} hasn't published any public agents yet.",
+                profile.username
+            )
+            .yellow()
+        );
+        return Ok(());
+    }
+
+    println!();
+    for agent in &profile.agents {
+        if agent.verified {
+            println!(
+                "{} {} {}",
+                agent.name.bold().blue(),
+                agent.version.dimmed(),
+                "✓ verified".green()
+            );
+        } else {
+            println!("{} {}", agent.name.bold().blue(), agent.version.dimmed());
+        }
+        println!("  {}", agent.description);
+        println!(
+            "  {} downloads • {} stars",
+            agent.download_count.to_string().cyan(),
+            agent.star_count.to_string().cyan()
+        );
+
+        if !agent.tags.is_empty() {
+            let tags: Vec<String> = agent.tags.iter().map(|t| t.yellow().to_string()).collect();
+            println!("  tags: {}", tags.join(", "));
+        }
+
+        if verbose {
+            println!("  updated: {}", agent.updated_at.format("%Y-%m-%d"));
+            if let Some(repository) = &agent.repository {
+                println!("  repository: {}", repository.blue().underline());
+            }
+        }
+
+        println!();
+    }
+
+    println!("Use `carp pull <name>` to pull one of these agents.");
+
+    Ok(())
+}
+
+fn display_profile(profile: &UserProfile) {
+    let name = match &profile.display_name {
+        Some(display_name) if display_name != &profile.username => format!(
+            "{} {}",
+            display_name.bold().green(),
+            format!("@{}", profile.username).dimmed()
+        ),
+        _ => format!("@{}", profile.username).bold().green().to_string(),
+    };
+    if profile.verified {
+        println!("{name} {}", "✓ verified publisher".green());
+    } else {
+        println!("{name}");
+    }
+
+    if let Some(bio) = &profile.bio {
+        println!("  {bio}");
+    }
+
+    println!(
+        "  {} agents • {} downloads • {} stars",
+        profile.agent_count.to_string().cyan(),
+        profile.total_downloads.to_string().cyan(),
+        profile.total_stars.to_string().cyan()
+    );
+    println!("  joined: {}", profile.joined_at.format("%Y-%m-%d"));
+}
@@ -0,0 +1,60 @@
+use crate::api::{ApiClient, ClaimMethod};
+use crate::utils::error::CarpResult;
+use colored::*;
+use tracing::debug;
+
+/// Execute the claim command
+pub async fn execute(client: &ApiClient, namespace: String, method: ClaimMethod) -> CarpResult<()> {
+    debug!("Claiming '{namespace}' by {method:?}...");
+
+    let response = client.claim_namespace(&namespace, method).await?;
+    let namespace = format!("@{}", response.namespace);
+
+    if response.verified {
+        let proof = match (response.method, response.domain.as_deref()) {
+            (ClaimMethod::Github, _) | (_, None) => "your GitHub membership".to_string(),
+            (_, Some(domain)) => domain.cyan().to_string(),
+        };
+        println!(
+            "{} Verified {} through {}",
+            "✓".green().bold(),
+            namespace.bold(),
+            proof
+        );
+        println!(
+            "  Agents named {} or {} that members publish now show as verified.",
+            response.namespace.bold(),
+            format!("{}-*", response.namespace).bold()
+        );
+        return Ok(());
+    }
+
+    let Some(challenge) = &response.challenge else {
+        println!(
+            "{} {} is not verified yet",
+            "•".yellow().bold(),
+            namespace.bold()
+        );
+        return Ok(());
+    };
+
+    let what = match response.method {
+        ClaimMethod::Dns => "Add a DNS TXT record",
+        ClaimMethod::Github | ClaimMethod::Url => "Serve a file",
+    };
+    println!(
+        "{} To verify {}, prove you control {}:",
+        "•".yellow().bold(),
+        namespace.bold(),
+        response.domain.as_deref().unwrap_or("its domain").cyan()
+    );
+    println!("  {what}");
+    println!("    at:      {}", challenge.location.bold());
+    println!("    holding: {}", challenge.value.bold());
+    println!(
+        "  then run this command again before {}.",
+        challenge.expires_at.format("%Y-%m-%d %H:%M UTC")
+    );
+
+    Ok(())
+}
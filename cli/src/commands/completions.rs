@@ -0,0 +1,36 @@
+use crate::utils::error::CarpResult;
+use crate::utils::name_index;
+use clap_complete::env::Shells;
+use clap_complete::{CompletionCandidate, Shell};
+use std::ffi::OsStr;
+use std::io::Write;
+
+/// Environment variable the completion script sets when it runs carp to
+/// complete a command line, which `main` hands to `clap_complete` first
+pub const COMPLETE_VAR: &str = "COMPLETE";
+
+/// Print the script that sets up completion for `shell`. The script asks
+/// carp itself what to complete, so agent names come from the local index
+/// and stay current as carp is upgraded.
+pub fn execute(shell: Shell) -> CarpResult<()> {
+    let shells = Shells::builtins();
+    let completer = shells
+        .completer(&shell.to_string())
+        .expect("clap_complete completes every shell it names");
+    let mut script = Vec::new();
+    completer.write_registration(COMPLETE_VAR, "carp", "carp", "carp", &mut script)?;
+    std::io::stdout().write_all(&script)?;
+    Ok(())
+}
+
+/// Agent names for `carp pull` and `carp info`, from what recent searches
+/// and lists showed
+pub fn agent_names(current: &OsStr) -> Vec<CompletionCandidate> {
+    let Some(prefix) = current.to_str() else {
+        return Vec::new();
+    };
+    name_index::complete(prefix)
+        .into_iter()
+        .map(CompletionCandidate::new)
+        .collect()
+}
@@ -0,0 +1,150 @@
+use crate::commands::pull::get_default_agents_dir;
+use crate::config::paths::{self, Kind};
+use crate::config::project::{Project, PROJECT_DIR};
+use crate::config::schema::{Diagnostic, Severity};
+use crate::config::settings::CONFIG_FILE;
+use crate::config::ConfigManager;
+use crate::utils::error::{CarpError, CarpResult};
+use colored::*;
+use std::path::{Path, PathBuf};
+
+/// A location `carp config path` can print on its own
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum PathName {
+    /// The config file
+    Config,
+    Cache,
+    Data,
+    /// Where `carp pull` saves agents by default
+    Agents,
+}
+
+/// Print where carp keeps its files and what decided each location, or
+/// just the path of `which`, for scripts
+pub fn path(which: Option<PathName>) -> CarpResult<()> {
+    if let Some(which) = which {
+        let path = match which {
+            PathName::Config => paths::resolve(Kind::Config)?.dir.join(CONFIG_FILE),
+            PathName::Cache => paths::resolve(Kind::Cache)?.dir,
+            PathName::Data => paths::resolve(Kind::Data)?.dir,
+            PathName::Agents => get_default_agents_dir(&ConfigManager::load()?)?,
+        };
+        println!("{}", path.display());
+        return Ok(());
+    }
+
+    for (name, kind, file) in [
+        ("config", Kind::Config, Some(CONFIG_FILE)),
+        ("cache", Kind::Cache, None),
+        ("data", Kind::Data, None),
+    ] {
+        match paths::resolve(kind) {
+            Ok(location) => {
+                let path = match file {
+                    Some(file) => location.dir.join(file),
+                    None => location.dir,
+                };
+                println!(
+                    "{:<7} {} {}",
+                    name.bold(),
+                    path.display(),
+                    format!("({})", location.source).dimmed()
+                );
+            }
+            Err(e) => println!("{:<7} {}", name.bold(), e.to_string().red()),
+        }
+    }
+
+    if let Some(project) = std::env::current_dir()
+        .ok()
+        .and_then(|cwd| Project::find(&cwd))
+    {
+        println!(
+            "{:<7} {} {}",
+            "project".bold(),
+            project.display(),
+            "(overrides config)".dimmed()
+        );
+    }
+
+    // Only known once the config loads, since it can set the directory
+    let agents = ConfigManager::load().and_then(|config| get_default_agents_dir(&config));
+    match agents {
+        Ok(dir) => println!("{:<7} {}", "agents".bold(), dir.display()),
+        Err(e) => println!("{:<7} {}", "agents".bold(), e.to_string().red()),
+    }
+    Ok(())
+}
+
+/// Check `file`, or the config file and the project config the working
+/// directory is in, and print what's wrong with them. Fails when there are
+/// errors; warnings alone don't.
+pub fn validate(file: Option<PathBuf>) -> CarpResult<()> {
+    let files = match file {
+        Some(file) => vec![file],
+        None => {
+            let config = paths::resolve(Kind::Config)?.dir.join(CONFIG_FILE);
+            let project = std::env::current_dir()
+                .ok()
+                .and_then(|cwd| Project::find(&cwd));
+            if !config.exists() && project.is_none() {
+                println!(
+                    "No config file at {}; carp uses its defaults",
+                    config.display()
+                );
+                return Ok(());
+            }
+            config
+                .exists()
+                .then_some(config)
+                .into_iter()
+                .chain(project)
+                .collect()
+        }
+    };
+
+    let (mut errors, mut warnings) = (0, 0);
+    for file in &files {
+        let diagnostics = if is_project_config(file) {
+            Project::check(file)
+        } else {
+            ConfigManager::check_file(file)
+        };
+        if diagnostics.is_empty() {
+            println!("{} {}", "✓".green().bold(), file.display());
+        }
+        for diagnostic in &diagnostics {
+            print_diagnostic(diagnostic);
+        }
+        let found = diagnostics.iter().filter(|d| d.is_error()).count();
+        errors += found;
+        warnings += diagnostics.len() - found;
+    }
+
+    match (errors, warnings) {
+        (0, 0) => Ok(()),
+        (0, warnings) => {
+            println!("{warnings} warning(s); the config still loads");
+            Ok(())
+        }
+        (errors, warnings) => Err(CarpError::Config(format!(
+            "{errors} error(s) and {warnings} warning(s) in config files"
+        ))),
+    }
+}
+
+/// Whether `file` is a project's `.carp/config.toml` rather than a user
+/// config file
+fn is_project_config(file: &Path) -> bool {
+    file.parent()
+        .and_then(Path::file_name)
+        .is_some_and(|dir| dir == PROJECT_DIR)
+}
+
+fn print_diagnostic(diagnostic: &Diagnostic) {
+    let line = diagnostic.to_string();
+    match diagnostic.severity {
+        Severity::Error => println!("{}", line.red()),
+        Severity::Warning => println!("{}", line.yellow()),
+    }
+}
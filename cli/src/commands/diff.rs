@@ -0,0 +1,328 @@
+use crate::api::types::Agent;
+use crate::api::ApiClient;
+use crate::commands::pull::{get_agent_definition, parse_agent_spec};
+use crate::config::ConfigManager;
+use crate::utils::cleanup;
+use crate::utils::error::{CarpError, CarpResult};
+use crate::utils::file_lock::LockedFile;
+use crate::utils::frontmatter::parse_frontmatter;
+use colored::*;
+use similar::{ChangeTag, TextDiff};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+/// Lines of unchanged context shown around each hunk
+const CONTEXT_LINES: usize = 3;
+
+/// Execute the diff command
+pub async fn execute(client: &ApiClient, from: String, to: String, stat: bool) -> CarpResult<()> {
+    let (from_name, from_version) = parse_agent_spec(&from)?;
+    let (to_name, to_version) = parse_agent_spec(&to)?;
+
+    let old = fetch_agent(client, &from_name, from_version).await?;
+    let new = fetch_agent(client, &to_name, to_version).await?;
+
+    let old_label = format!("{}@{}", old.name, old.version);
+    let new_label = format!("{}@{}", new.name, new.version);
+
+    let sections = [
+        ("manifest", render_manifest(&old)?, render_manifest(&new)?),
+        ("content", agent_body(&old), agent_body(&new)),
+    ];
+
+    if sections.iter().all(|(_, old, new)| old == new) {
+        println!(
+            "{} No differences between {} and {}",
+            "✓".green().bold(),
+            old_label.bold(),
+            new_label.bold()
+        );
+        return Ok(());
+    }
+
+    if stat {
+        display_stat(&sections);
+        return Ok(());
+    }
+
+    for (section, old_text, new_text) in &sections {
+        if old_text == new_text {
+            continue;
+        }
+
+        let diff = TextDiff::from_lines(old_text.as_str(), new_text.as_str());
+        let unified = diff
+            .unified_diff()
+            .context_radius(CONTEXT_LINES)
+            .header(
+                &format!("{old_label} ({section})"),
+                &format!("{new_label} ({section})"),
+            )
+            .to_string();
+
+        for line in unified.lines() {
+            println!("{}", colorize_line(line));
+        }
+    }
+
+    Ok(())
+}
+
+/// Fetch an agent version, reusing the on-disk cache for pinned versions.
+///
+/// Published versions never change, so only `latest` (or an omitted version)
+/// always goes to the registry.
+async fn fetch_agent(client: &ApiClient, name: &str, version: Option<&str>) -> CarpResult<Agent> {
+    let cache_path = match version {
+        Some(version) if version != "latest" => Some(cache_path(name, version)?),
+        _ => None,
+    };
+
+    if let Some(path) = &cache_path {
+        if let Some(agent) = fs::read_to_string(path)
+            .ok()
+            .and_then(|cached| serde_json::from_str::<Agent>(&cached).ok())
+        {
+            debug!("Using cached copy of {name}@{}", agent.version);
+            return Ok(agent);
+        }
+    }
+
+    debug!("Fetching {name}@{}...", version.unwrap_or("latest"));
+
+    let agent = get_agent_definition(client, name, version).await?;
+
+    if let Some(path) = &cache_path {
+        // A failed cache write only costs a refetch next time
+        if let Err(e) = cache_agent(path, &agent) {
+            debug!("Not caching {name}@{}: {e}", agent.version);
+        }
+    }
+
+    Ok(agent)
+}
+
+/// Write the cached copy whole, so concurrent diffs never read half of one
+fn cache_agent(path: &Path, agent: &Agent) -> CarpResult<()> {
+    let _lock = LockedFile::beside(path)?;
+    cleanup::write_atomic(path, serde_json::to_string(agent)?)?;
+    Ok(())
+}
+
+fn cache_path(name: &str, version: &str) -> CarpResult<PathBuf> {
+    if [name, version]
+        .iter()
+        .any(|part| part.contains(['/', '\\']) || part.contains(".."))
+    {
+        return Err(CarpError::InvalidAgent(format!(
+            "Invalid agent specification '{name}@{version}'"
+        )));
+    }
+
+    let dir = ConfigManager::cache_dir()?.join("agents").join(name);
+    fs::create_dir_all(&dir)?;
+
+    Ok(dir.join(format!("{version}.json")))
+}
+
+/// Render the registry metadata and any frontmatter keys as YAML so that
+/// manifest changes diff line by line
+fn render_manifest(agent: &Agent) -> CarpResult<String> {
+    let mut manifest = serde_json::Map::new();
+    manifest.insert("description".to_string(), agent.description.clone().into());
+    manifest.insert("author".to_string(), agent.author.clone().into());
+    manifest.insert("tags".to_string(), agent.tags.clone().into());
+    for (key, value) in [
+        ("homepage", &agent.homepage),
+        ("repository", &agent.repository),
+        ("license", &agent.license),
+    ] {
+        if let Some(value) = value {
+            manifest.insert(key.to_string(), value.clone().into());
+        }
+    }
+
+    let frontmatter = agent
+        .readme
+        .as_deref()
+        .and_then(|readme| parse_frontmatter(readme).ok())
+        .and_then(|(frontmatter, _)| match frontmatter {
+            serde_json::Value::Object(map) => Some(map),
+            _ => None,
+        })
+        .unwrap_or_default();
+
+    for (key, value) in frontmatter {
+        // The version always differs and is already shown in the diff header
+        if key != "version" {
+            manifest.entry(key).or_insert(value);
+        }
+    }
+
+    serde_yaml::to_string(&manifest)
+        .map_err(|e| CarpError::ManifestError(format!("Failed to render manifest: {e}")))
+}
+
+/// Agent content with the frontmatter stripped
+fn agent_body(agent: &Agent) -> String {
+    let readme = agent.readme.as_deref().unwrap_or_default();
+
+    let body = match parse_frontmatter(readme) {
+        Ok((_, body)) => body,
+        Err(_) => readme.to_string(),
+    };
+
+    let mut body = body.trim_start_matches('\n').to_string();
+    if !body.is_empty() && !body.ends_with('\n') {
+        body.push('\n');
+    }
+    body
+}
+
+/// Count inserted and deleted lines between two texts
+fn line_changes(old: &str, new: &str) -> (usize, usize) {
+    TextDiff::from_lines(old, new).iter_all_changes().fold(
+        (0, 0),
+        |(insertions, deletions), change| match change.tag() {
+            ChangeTag::Insert => (insertions + 1, deletions),
+            ChangeTag::Delete => (insertions, deletions + 1),
+            ChangeTag::Equal => (insertions, deletions),
+        },
+    )
+}
+
+fn display_stat(sections: &[(&str, String, String)]) {
+    let mut total_insertions = 0;
+    let mut total_deletions = 0;
+    let mut changed = 0;
+
+    for (section, old, new) in sections {
+        let (insertions, deletions) = line_changes(old, new);
+        if insertions + deletions == 0 {
+            continue;
+        }
+
+        changed += 1;
+        total_insertions += insertions;
+        total_deletions += deletions;
+
+        println!(
+            " {:<10} | {:>4} {}{}",
+            section,
+            insertions + deletions,
+            "+".repeat(insertions.min(40)).green(),
+            "-".repeat(deletions.min(40)).red()
+        );
+    }
+
+    println!(
+        " {} section{} changed, {} insertion{}(+), {} deletion{}(-)",
+        changed,
+        if changed == 1 { "" } else { "s" },
+        total_insertions,
+        if total_insertions == 1 { "" } else { "s" },
+        total_deletions,
+        if total_deletions == 1 { "" } else { "s" }
+    );
+}
+
+fn colorize_line(line: &str) -> ColoredString {
+    if line.starts_with("+++") || line.starts_with("---") {
+        line.bold()
+    } else if line.starts_with("@@") {
+        line.cyan()
+    } else if line.starts_with('+') {
+        line.green()
+    } else if line.starts_with('-') {
+        line.red()
+    } else {
+        line.normal()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn agent(version: &str, readme: &str) -> Agent {
+        Agent {
+            name: "test-agent".to_string(),
+            version: version.to_string(),
+            description: "A test agent".to_string(),
+            author: "tester".to_string(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            download_count: 0,
+            star_count: 0,
+            rating_count: 0,
+            rating_average: None,
+            tags: vec!["test".to_string()],
+            readme: Some(readme.to_string()),
+            homepage: None,
+            repository: None,
+            license: Some("MIT".to_string()),
+            signature_bundle: None,
+            is_public: true,
+            is_template: false,
+            verified_namespace: None,
+            verified: false,
+        }
+    }
+
+    #[test]
+    fn test_manifest_and_body_split() {
+        let agent = agent(
+            "1.0.0",
+            "---\nname: test-agent\nversion: 1.0.0\nmodel: sonnet\n---\n\nYou are helpful.",
+        );
+
+        let manifest = render_manifest(&agent).unwrap();
+        assert!(manifest.contains("model: sonnet"));
+        assert!(manifest.contains("license: MIT"));
+        assert!(!manifest.contains("version"));
+
+        assert_eq!(agent_body(&agent), "You are helpful.\n");
+    }
+
+    #[test]
+    fn test_body_without_frontmatter() {
+        let agent = agent("1.0.0", "Plain content");
+        assert_eq!(agent_body(&agent), "Plain content\n");
+    }
+
+    #[test]
+    fn test_version_bump_alone_is_not_a_manifest_change() {
+        let old = agent("1.0.0", "---\nname: test-agent\nversion: 1.0.0\n---\nBody");
+        let new = agent("1.1.0", "---\nname: test-agent\nversion: 1.1.0\n---\nBody");
+
+        assert_eq!(
+            render_manifest(&old).unwrap(),
+            render_manifest(&new).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_line_changes() {
+        assert_eq!(line_changes("a\nb\nc\n", "a\nB\nc\nd\n"), (2, 1));
+        assert_eq!(line_changes("same\n", "same\n"), (0, 0));
+    }
+
+    #[test]
+    fn test_cache_path_rejects_traversal() {
+        assert!(cache_path("../etc", "1.0.0").is_err());
+        assert!(cache_path("agent", "1.0.0/../../x").is_err());
+    }
+
+    #[test]
+    fn test_cached_agents_are_written_whole() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("1.0.0.json");
+        cache_agent(&path, &agent("1.0.0", "Body")).unwrap();
+
+        let cached: Agent = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(cached.version, "1.0.0");
+        assert!(!dir.path().join("1.0.0.json.part").exists());
+    }
+}
@@ -0,0 +1,434 @@
+use crate::api::{ApiClient, HealthResponse};
+use crate::commands::pull::get_default_agents_dir;
+use crate::config::{Config, ConfigManager};
+use crate::utils::error::{CarpError, CarpResult};
+use chrono::{DateTime, Utc};
+use colored::*;
+use std::error::Error as _;
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+use tracing::debug;
+
+/// Clock skew beyond which signed download URLs and keyless signing start failing
+const MAX_CLOCK_SKEW_SECS: i64 = 300;
+
+/// Clock skew worth mentioning
+const WARN_CLOCK_SKEW_SECS: i64 = 30;
+
+/// Scopes the CLI's commands rely on
+const EXPECTED_SCOPES: &[(&str, &str)] = &[("read", "carp edit"), ("upload", "carp upload")];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+/// Result of one diagnostic, with a suggested fix when it didn't pass
+#[derive(Debug)]
+struct Check {
+    name: &'static str,
+    status: Status,
+    detail: String,
+    fix: Option<String>,
+}
+
+impl Check {
+    fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Pass,
+            detail: detail.into(),
+            fix: None,
+        }
+    }
+
+    fn warn(name: &'static str, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Warn,
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Fail,
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+}
+
+/// Execute the doctor command
+///
+/// Runs before the usual config loading so a broken config file is reported
+/// as a finding rather than stopping the command.
+pub async fn execute(api_key: Option<String>) -> CarpResult<()> {
+    println!("{}", "Checking your carp setup...".bold());
+    println!();
+
+    let mut checks = Vec::new();
+
+    let (config, config_check) = check_config();
+    checks.push(config_check);
+
+    let mut client = ApiClient::new(&config)?;
+    if let Some(api_key) = api_key {
+        client = client.with_api_key(Some(api_key));
+    }
+
+    let health = check_registry(&client).await;
+    let (registry_check, tls_failure, health) = match health {
+        Ok((check, health)) => (check, None, Some(health)),
+        Err((check, tls_failure)) => (check, tls_failure, None),
+    };
+    checks.push(registry_check);
+    checks.push(check_tls(&config, tls_failure));
+    if health.is_some() || client.api_key().is_none() {
+        checks.push(check_auth(&client).await);
+    }
+    checks.push(check_dir(
+        "Cache directory",
+        ConfigManager::cache_dir(),
+        "Make sure your user can write to the cache directory, or set CARP_HOME or XDG_CACHE_HOME",
+    ));
+    checks.push(check_dir(
+        "Output directory",
+        get_default_agents_dir(&config),
+        "Set default_output_dir in the config, or CARP_OUTPUT_DIR, to a writable directory",
+    ));
+    if let Some(health) = &health {
+        checks.push(check_clock(health, Utc::now()));
+    }
+
+    for check in &checks {
+        print_check(check);
+    }
+
+    let failed = checks.iter().filter(|c| c.status == Status::Fail).count();
+    let warnings = checks.iter().filter(|c| c.status == Status::Warn).count();
+    let passed = checks.len() - failed - warnings;
+
+    println!();
+    println!(
+        "{} passed, {} warnings, {} failed",
+        passed.to_string().green().bold(),
+        warnings.to_string().yellow().bold(),
+        failed.to_string().red().bold()
+    );
+
+    if failed > 0 {
+        return Err(CarpError::Other(format!(
+            "{failed} check(s) failed; see the suggested fixes above"
+        )));
+    }
+
+    Ok(())
+}
+
+fn print_check(check: &Check) {
+    let symbol = match check.status {
+        Status::Pass => "✓".green().bold(),
+        Status::Warn => "⚠".yellow().bold(),
+        Status::Fail => "✗".red().bold(),
+    };
+
+    println!("{symbol} {:<18} {}", check.name.bold(), check.detail);
+    if let Some(fix) = &check.fix {
+        println!("  {} {}", "→".dimmed(), fix);
+    }
+}
+
+/// Load the config, falling back to defaults so the remaining checks still run
+fn check_config() -> (Config, Check) {
+    let path = match ConfigManager::config_path() {
+        Ok(path) => path,
+        Err(e) => return (
+            Config::default(),
+            Check::fail(
+                "Config file",
+                e.to_string(),
+                "Make sure your home directory has a writable config directory, or set CARP_HOME",
+            ),
+        ),
+    };
+
+    let existed = path.exists();
+    match ConfigManager::load() {
+        Ok(config) if existed => (
+            config,
+            Check::pass("Config file", format!("Loaded {}", path.display())),
+        ),
+        Ok(config) => (
+            config,
+            Check::pass(
+                "Config file",
+                format!("Created default config at {}", path.display()),
+            ),
+        ),
+        Err(e) => (
+            Config::default(),
+            Check::fail(
+                "Config file",
+                e.to_string(),
+                format!(
+                    "Fix the setting above in {} (or the matching CARP_* variable); remaining checks use defaults",
+                    path.display()
+                ),
+            ),
+        ),
+    }
+}
+
+/// Reach the registry's health endpoint. On failure, also reports whether
+/// the failure was a TLS problem.
+async fn check_registry(
+    client: &ApiClient,
+) -> Result<(Check, HealthResponse), (Check, Option<String>)> {
+    let started = Instant::now();
+    match client.health_check(false).await {
+        Ok(health) => Ok((
+            Check::pass(
+                "Registry",
+                format!(
+                    "{} is {} ({} ms)",
+                    client.base_url(),
+                    health.status,
+                    started.elapsed().as_millis()
+                ),
+            ),
+            health,
+        )),
+        Err(e) => {
+            let tls_failure = tls_error(&e);
+            debug!("Registry check failed: {e:?}");
+            let check = Check::fail(
+                "Registry",
+                format!("{} is unreachable: {e}", client.base_url()),
+                "Check your network and proxy settings (HTTPS_PROXY), and that CARP_REGISTRY_URL or registry_url is correct",
+            );
+            Err((check, tls_failure))
+        }
+    }
+}
+
+/// The TLS-specific cause of a request error, if there is one
+fn tls_error(error: &CarpError) -> Option<String> {
+    let CarpError::Http(error) = error else {
+        return None;
+    };
+
+    let mut source = error.source();
+    while let Some(cause) = source {
+        let message = cause.to_string();
+        let lower = message.to_ascii_lowercase();
+        if lower.contains("certificate") || lower.contains("tls") || lower.contains("ssl") {
+            return Some(message);
+        }
+        source = cause.source();
+    }
+    None
+}
+
+fn check_tls(config: &Config, tls_failure: Option<String>) -> Check {
+    if let Some(failure) = tls_failure {
+        return Check::fail(
+            "TLS",
+            failure,
+            "If you're behind a TLS-intercepting proxy, install its CA certificate in your system trust store",
+        );
+    }
+
+    if config.registry_url.starts_with("http://") {
+        return Check::warn(
+            "TLS",
+            "The registry is configured over plain HTTP",
+            "Use an https:// registry_url and unset security.allow_http in the config",
+        );
+    }
+
+    if !config.verify_ssl {
+        return Check::warn(
+            "TLS",
+            "Certificate verification is disabled",
+            "Set verify_ssl = true in the config (or unset CARP_VERIFY_SSL)",
+        );
+    }
+
+    let mut summary = "HTTPS with certificate verification".to_string();
+    if let Some(version) = config.security.min_tls_version {
+        summary.push_str(&format!(", TLS {} or newer", version.as_str()));
+    }
+    match config.security.spki_pins.len() {
+        0 => {}
+        1 => summary.push_str(", 1 pinned key"),
+        pins => summary.push_str(&format!(", {pins} pinned keys")),
+    }
+    Check::pass("TLS", summary)
+}
+
+async fn check_auth(client: &ApiClient) -> Check {
+    if client.api_key().is_none() {
+        return Check::warn(
+            "API key",
+            "Not configured; only public commands will work",
+            "Run `carp auth login`, or set CARP_API_KEY",
+        );
+    }
+
+    match client.whoami().await {
+        Ok(identity) => {
+            let missing: Vec<&str> = EXPECTED_SCOPES
+                .iter()
+                .filter(|(scope, _)| !identity.scopes.iter().any(|s| s == scope || s == "admin"))
+                .map(|(_, command)| *command)
+                .collect();
+
+            let who = identity
+                .github_username
+                .map(|name| format!(" for {name}"))
+                .unwrap_or_default();
+            let detail = format!("Valid{who}; scopes: {}", identity.scopes.join(", "));
+
+            if missing.is_empty() {
+                Check::pass("API key", detail)
+            } else {
+                Check::warn(
+                    "API key",
+                    detail,
+                    format!(
+                        "{} will be refused; create a key with the needed scopes in the registry dashboard",
+                        missing.join(" and ")
+                    ),
+                )
+            }
+        }
+        Err(CarpError::Auth(_)) => Check::fail(
+            "API key",
+            "The registry rejected the API key (invalid, expired or revoked)",
+            "Create a new key in the registry dashboard, then run `carp auth login`",
+        ),
+        Err(CarpError::Api { status: 404, .. }) => Check::warn(
+            "API key",
+            "Configured, but this registry can't validate keys",
+            "Upgrade the registry to check key validity",
+        ),
+        Err(e) => Check::warn(
+            "API key",
+            format!("Could not be checked: {e}"),
+            "Re-run `carp doctor` once the registry is reachable",
+        ),
+    }
+}
+
+fn check_dir(name: &'static str, dir: CarpResult<std::path::PathBuf>, fix: &str) -> Check {
+    let dir = match dir {
+        Ok(dir) => dir,
+        Err(e) => return Check::fail(name, e.to_string(), fix),
+    };
+
+    match check_writable(&dir) {
+        Ok(()) => Check::pass(name, format!("{} is writable", dir.display())),
+        Err(e) => Check::fail(name, format!("{} is not writable: {e}", dir.display()), fix),
+    }
+}
+
+/// Check that `dir`, or the nearest ancestor that exists, accepts new files
+fn check_writable(dir: &Path) -> Result<(), String> {
+    let existing = dir
+        .ancestors()
+        .find(|path| path.exists())
+        .ok_or_else(|| "no parent directory exists".to_string())?;
+
+    if !existing.is_dir() {
+        return Err(format!("{} is not a directory", existing.display()));
+    }
+
+    let probe = existing.join(format!(".carp-doctor-{}", std::process::id()));
+    fs::write(&probe, b"").map_err(|e| e.to_string())?;
+    let _ = fs::remove_file(&probe);
+    Ok(())
+}
+
+fn check_clock(health: &HealthResponse, now: DateTime<Utc>) -> Check {
+    let Ok(server_time) = DateTime::parse_from_rfc3339(&health.timestamp) else {
+        return Check::warn(
+            "Clock",
+            "The registry didn't report its time",
+            "Make sure your system clock is synced (NTP)",
+        );
+    };
+
+    let skew = (now - server_time.with_timezone(&Utc)).num_seconds();
+    let detail = match skew {
+        0 => "In sync with the registry".to_string(),
+        s if s > 0 => format!("{s}s ahead of the registry"),
+        s => format!("{}s behind the registry", -s),
+    };
+
+    match skew.abs() {
+        s if s > MAX_CLOCK_SKEW_SECS => Check::fail(
+            "Clock",
+            detail,
+            "Sync your system clock (NTP); downloads and signing fail with this much skew",
+        ),
+        s if s > WARN_CLOCK_SKEW_SECS => {
+            Check::warn("Clock", detail, "Sync your system clock (NTP)")
+        }
+        _ => Check::pass("Clock", detail),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn health_at(timestamp: DateTime<Utc>) -> HealthResponse {
+        HealthResponse {
+            status: "healthy".to_string(),
+            service: "carp-api".to_string(),
+            environment: "test".to_string(),
+            message: String::new(),
+            agent_count: None,
+            timestamp: timestamp.to_rfc3339(),
+            error: None,
+            components: None,
+        }
+    }
+
+    #[test]
+    fn test_check_clock() {
+        let now = Utc::now();
+
+        assert_eq!(check_clock(&health_at(now), now).status, Status::Pass);
+        assert_eq!(
+            check_clock(&health_at(now - Duration::seconds(90)), now).status,
+            Status::Warn
+        );
+
+        let behind = check_clock(&health_at(now + Duration::minutes(10)), now);
+        assert_eq!(behind.status, Status::Fail);
+        assert_eq!(behind.detail, "600s behind the registry");
+    }
+
+    #[test]
+    fn test_check_writable() {
+        let temp = tempfile::tempdir().unwrap();
+        let missing = temp.path().join("not").join("yet");
+
+        assert!(check_writable(temp.path()).is_ok());
+        assert!(check_writable(&missing).is_ok());
+        assert!(!missing.exists(), "the check must not create directories");
+        assert_eq!(
+            fs::read_dir(temp.path()).unwrap().count(),
+            0,
+            "the probe file must be removed"
+        );
+    }
+}
@@ -0,0 +1,261 @@
+use crate::api::{AgentMetadata, ApiClient, MetadataUpdate};
+use crate::config::ConfigManager;
+use crate::utils::ci;
+use crate::utils::error::{CarpError, CarpResult};
+use colored::*;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::debug;
+
+/// Changes requested on the command line
+#[derive(Debug, Default)]
+pub struct EditOptions {
+    pub description: Option<String>,
+    pub tags: Option<Vec<String>>,
+    pub homepage: Option<String>,
+    pub readme: Option<PathBuf>,
+}
+
+/// The part of the metadata shown in the editor
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct EditableMetadata {
+    description: String,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    homepage: String,
+    #[serde(default)]
+    readme: String,
+}
+
+impl From<&AgentMetadata> for EditableMetadata {
+    fn from(metadata: &AgentMetadata) -> Self {
+        EditableMetadata {
+            description: metadata.description.clone(),
+            tags: metadata.tags.clone().unwrap_or_default(),
+            homepage: metadata.homepage.clone().unwrap_or_default(),
+            readme: metadata.readme.clone().unwrap_or_default(),
+        }
+    }
+}
+
+/// Execute the edit command
+pub async fn execute(client: &ApiClient, name: String, options: EditOptions) -> CarpResult<()> {
+    debug!("Fetching metadata for '{name}'...");
+
+    let (current, etag) = client.get_agent_metadata(&name).await?;
+
+    let (update, draft) = if options.is_empty() {
+        ci::ensure_interactive(
+            "Editing in $EDITOR",
+            "pass --description, --tags, --homepage or --readme",
+        )?;
+        let draft = draft_path(&name)?;
+        let edited = edit_in_editor(&current, &draft)?;
+        (
+            changes(&EditableMetadata::from(&current), &edited),
+            Some(draft),
+        )
+    } else {
+        (options.into_update()?, None)
+    };
+
+    if update.is_empty() {
+        println!("{} No changes to {}", "✓".green().bold(), name.bold());
+        remove_draft(draft.as_deref());
+        return Ok(());
+    }
+
+    debug!("Updating '{name}' (version {etag})...");
+
+    match client.update_agent_metadata(&name, &etag, &update).await {
+        Ok(updated) => {
+            remove_draft(draft.as_deref());
+            println!(
+                "{} Updated {} ({})",
+                "✓".green().bold(),
+                updated.name.bold(),
+                changed_fields(&update).join(", ")
+            );
+            Ok(())
+        }
+        Err(CarpError::Api { status: 409, .. }) => {
+            let mut message = format!(
+                "'{name}' was changed by someone else while you were editing. Run `carp edit {name}` again to start from the latest metadata."
+            );
+            if let Some(draft) = &draft {
+                message.push_str(&format!("\nYour edits were kept in {}", draft.display()));
+            }
+            Err(CarpError::Api {
+                status: 409,
+                message,
+            })
+        }
+        Err(e) => Err(e),
+    }
+}
+
+impl EditOptions {
+    fn is_empty(&self) -> bool {
+        self.description.is_none()
+            && self.tags.is_none()
+            && self.homepage.is_none()
+            && self.readme.is_none()
+    }
+
+    fn into_update(self) -> CarpResult<MetadataUpdate> {
+        let readme = self
+            .readme
+            .map(|path| {
+                fs::read_to_string(&path).map_err(|e| {
+                    CarpError::FileSystem(format!("Failed to read {}: {e}", path.display()))
+                })
+            })
+            .transpose()?;
+
+        Ok(MetadataUpdate {
+            description: self.description,
+            tags: self.tags,
+            homepage: self.homepage,
+            readme,
+        })
+    }
+}
+
+fn draft_path(name: &str) -> CarpResult<PathBuf> {
+    if name.contains(['/', '\\']) || name.contains("..") {
+        return Err(CarpError::InvalidAgent(format!(
+            "Invalid agent name '{name}'"
+        )));
+    }
+
+    let dir = ConfigManager::cache_dir()?.join("edits");
+    fs::create_dir_all(&dir)?;
+    Ok(dir.join(format!("{name}.yaml")))
+}
+
+fn remove_draft(draft: Option<&Path>) {
+    if let Some(draft) = draft {
+        let _ = fs::remove_file(draft);
+    }
+}
+
+/// Open the metadata in `$VISUAL`/`$EDITOR` and parse the result
+fn edit_in_editor(current: &AgentMetadata, draft: &Path) -> CarpResult<EditableMetadata> {
+    let yaml = serde_yaml::to_string(&EditableMetadata::from(current))
+        .map_err(|e| CarpError::Other(format!("Failed to render metadata: {e}")))?;
+    fs::write(
+        draft,
+        format!(
+            "# Editing {}. Save and close the editor to apply your changes.\n{yaml}",
+            current.name
+        ),
+    )?;
+
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| {
+            if cfg!(windows) {
+                "notepad".to_string()
+            } else {
+                "vi".to_string()
+            }
+        });
+
+    // Editors are often configured with arguments, e.g. `code --wait`
+    let mut parts = editor.split_whitespace();
+    let program = parts
+        .next()
+        .ok_or_else(|| CarpError::Config("EDITOR is empty".to_string()))?;
+
+    let status = Command::new(program)
+        .args(parts)
+        .arg(draft)
+        .status()
+        .map_err(|e| CarpError::Other(format!("Failed to launch editor '{editor}': {e}")))?;
+
+    if !status.success() {
+        return Err(CarpError::Other(format!(
+            "Editor exited with {status}; no changes were made. Your draft is in {}",
+            draft.display()
+        )));
+    }
+
+    let edited = fs::read_to_string(draft)?;
+    serde_yaml::from_str(&edited).map_err(|e| {
+        CarpError::ManifestError(format!("Invalid metadata in {}: {e}", draft.display()))
+    })
+}
+
+/// Only send the fields that were actually changed
+fn changes(before: &EditableMetadata, after: &EditableMetadata) -> MetadataUpdate {
+    fn changed<T: PartialEq + Clone>(before: &T, after: &T) -> Option<T> {
+        (before != after).then(|| after.clone())
+    }
+
+    MetadataUpdate {
+        description: changed(&before.description, &after.description),
+        tags: changed(&before.tags, &after.tags),
+        homepage: changed(&before.homepage, &after.homepage),
+        readme: changed(&before.readme, &after.readme),
+    }
+}
+
+fn changed_fields(update: &MetadataUpdate) -> Vec<&'static str> {
+    [
+        ("description", update.description.is_some()),
+        ("tags", update.tags.is_some()),
+        ("homepage", update.homepage.is_some()),
+        ("readme", update.readme.is_some()),
+    ]
+    .into_iter()
+    .filter_map(|(field, changed)| changed.then_some(field))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata() -> AgentMetadata {
+        AgentMetadata {
+            name: "test-agent".to_string(),
+            description: "A test agent".to_string(),
+            tags: Some(vec!["test".to_string()]),
+            homepage: None,
+            readme: Some("---\nname: test-agent\n---\nBody\n".to_string()),
+            metadata_version: 3,
+        }
+    }
+
+    #[test]
+    fn test_editable_metadata_round_trips() {
+        let editable = EditableMetadata::from(&metadata());
+        let yaml = serde_yaml::to_string(&editable).unwrap();
+        let parsed: EditableMetadata = serde_yaml::from_str(&format!("# comment\n{yaml}")).unwrap();
+
+        assert_eq!(parsed, editable);
+    }
+
+    #[test]
+    fn test_changes_only_include_edited_fields() {
+        let before = EditableMetadata::from(&metadata());
+        let after = EditableMetadata {
+            tags: vec!["test".to_string(), "new".to_string()],
+            ..EditableMetadata::from(&metadata())
+        };
+
+        let update = changes(&before, &after);
+        assert_eq!(
+            update.tags,
+            Some(vec!["test".to_string(), "new".to_string()])
+        );
+        assert!(update.description.is_none());
+        assert!(update.readme.is_none());
+        assert_eq!(changed_fields(&update), vec!["tags"]);
+
+        assert!(changes(&before, &EditableMetadata::from(&metadata())).is_empty());
+    }
+}
@@ -1,25 +1,16 @@
-use crate::api::ApiClient;
-use crate::config::ConfigManager;
+use crate::api::{ApiClient, ComponentHealth};
 use crate::utils::error::CarpResult;
 use colored::*;
+use tracing::debug;
 
 /// Execute the healthcheck command
-pub async fn execute(verbose: bool) -> CarpResult<()> {
-    if verbose {
-        println!("Checking API health...");
-    }
-
-    let config = ConfigManager::load_with_env_checks()?;
-    let client = ApiClient::new(&config)?;
+pub async fn execute(client: &ApiClient, deep: bool, verbose: bool) -> CarpResult<()> {
+    debug!("Checking API health...");
 
-    let response = client.health_check().await?;
+    let response = client.health_check(deep).await?;
 
     // Print health status with color coding
-    let status_display = match response.status.as_str() {
-        "healthy" => response.status.green().bold(),
-        "unhealthy" => response.status.red().bold(),
-        _ => response.status.yellow().bold(),
-    };
+    let status_display = colorize_status(&response.status);
 
     println!("{} {}", "Status:".bold(), status_display);
     println!("{} {}", "Service:".bold(), response.service);
@@ -47,7 +38,7 @@ pub async fn execute(verbose: bool) -> CarpResult<()> {
         println!(
             "{} {}",
             "Registry URL:".bold(),
-            config.registry_url.blue().underline()
+            client.base_url().blue().underline()
         );
     }
 
@@ -55,10 +46,54 @@ pub async fn execute(verbose: bool) -> CarpResult<()> {
         println!("{} {}", "Error:".red().bold(), error);
     }
 
+    let components = response.components.unwrap_or_default();
+    if !components.is_empty() {
+        display_components(&components);
+    }
+
     // Set exit code based on health status
-    if response.status != "healthy" {
+    if response.status != "healthy" || components.iter().any(|c| c.status != "healthy") {
         std::process::exit(1);
     }
 
     Ok(())
 }
+
+fn display_components(components: &[ComponentHealth]) {
+    let width = components
+        .iter()
+        .map(|c| c.name.len())
+        .max()
+        .unwrap_or(0)
+        .max("Component".len());
+
+    println!(
+        "\n  {:<width$}  {:<9}  {:>8}",
+        "Component".bold(),
+        "Status".bold(),
+        "Latency".bold()
+    );
+
+    for component in components {
+        // Pad before coloring so escape codes don't break alignment
+        let status = format!("{:<9}", component.status);
+        println!(
+            "  {:<width$}  {}  {:>6}ms",
+            component.name,
+            colorize_status(&status),
+            component.latency_ms
+        );
+
+        if let Some(error) = &component.error {
+            println!("  {:<width$}  {}", "", error.red());
+        }
+    }
+}
+
+fn colorize_status(status: &str) -> ColoredString {
+    match status.trim() {
+        "healthy" => status.green().bold(),
+        "unhealthy" => status.red().bold(),
+        _ => status.yellow().bold(),
+    }
+}
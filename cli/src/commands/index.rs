@@ -0,0 +1,60 @@
+use crate::api::ApiClient;
+use crate::utils::error::CarpResult;
+use crate::utils::search_index::{age, SearchIndex};
+use colored::*;
+
+/// Create or update the local search index of the registry
+pub async fn sync(client: &ApiClient) -> CarpResult<()> {
+    let (index, summary) = SearchIndex::sync(client).await?;
+    if summary.rebuilt {
+        println!(
+            "{} Copied {} agents from {} (it has no change feed, so each sync copies everything)",
+            "✓".green().bold(),
+            index.agents.len(),
+            index.registry
+        );
+    } else {
+        println!(
+            "{} Synced {} changes from {}; {} agents indexed",
+            "✓".green().bold(),
+            summary.changes,
+            index.registry,
+            index.agents.len()
+        );
+    }
+    Ok(())
+}
+
+/// Show whether the registry has been indexed and how long ago
+pub fn status(client: &ApiClient) -> CarpResult<()> {
+    match SearchIndex::load(client.base_url())? {
+        Some(index) => {
+            let synced = format!("synced {}", age(index.synced_at));
+            println!(
+                "Search index of {}: {} agents, {}",
+                index.registry,
+                index.agents.len(),
+                if index.is_stale() {
+                    synced.yellow().bold()
+                } else {
+                    synced.green()
+                }
+            );
+        }
+        None => println!(
+            "No search index of {}. Run `carp index sync` to create one.",
+            client.base_url()
+        ),
+    }
+    Ok(())
+}
+
+/// Delete the local search index of the registry
+pub fn clear(client: &ApiClient) -> CarpResult<()> {
+    if SearchIndex::clear(client.base_url())? {
+        println!("{} Search index deleted", "✓".green().bold());
+    } else {
+        println!("No search index of {}", client.base_url());
+    }
+    Ok(())
+}
@@ -0,0 +1,119 @@
+use crate::api::{AgentInfo, ApiClient, Provenance};
+use crate::commands::review::rating_summary;
+use crate::utils::error::CarpResult;
+use colored::*;
+use tracing::debug;
+
+/// Execute the info command
+pub async fn execute(client: &ApiClient, agent: String, show_provenance: bool) -> CarpResult<()> {
+    debug!("Fetching information for '{agent}'...");
+
+    let info = client.get_agent_info(&agent).await?;
+
+    display_info(&info);
+
+    if show_provenance {
+        display_provenance(&info);
+    }
+
+    Ok(())
+}
+
+fn display_info(info: &AgentInfo) {
+    println!("{} {}", info.name.bold().blue(), info.version.dimmed());
+    println!("  {}", info.description);
+    println!(
+        "  by {} • {} downloads • {} stars",
+        info.author.green(),
+        info.download_count.to_string().cyan(),
+        info.star_count.to_string().cyan()
+    );
+
+    println!(
+        "  rating: {}",
+        rating_summary(info.rating_average, info.rating_count)
+    );
+
+    if let Some(namespace) = &info.verified_namespace {
+        println!(
+            "  namespace: {} {}",
+            format!("@{namespace}").green(),
+            "(verified)".green().bold()
+        );
+    } else if info.verified {
+        println!("  publisher: {}", "✓ verified".green().bold());
+    }
+
+    if !info.is_public {
+        println!("  visibility: {}", "private (only you can see it)".yellow());
+    }
+
+    if !info.tags.is_empty() {
+        let tags: Vec<String> = info.tags.iter().map(|t| t.yellow().to_string()).collect();
+        println!("  tags: {}", tags.join(", "));
+    }
+
+    if let Some(license) = &info.license {
+        println!("  license: {license}");
+    }
+    if let Some(homepage) = &info.homepage {
+        println!("  homepage: {}", homepage.blue().underline());
+    }
+    if let Some(repository) = &info.repository {
+        println!("  repository: {}", repository.blue().underline());
+    }
+
+    println!(
+        "  updated: {}",
+        info.updated_at.format("%Y-%m-%d %H:%M UTC")
+    );
+
+    if info.signed {
+        println!("  signature: {}", "signed (Sigstore keyless)".green());
+    } else {
+        println!("  signature: {}", "unsigned".dimmed());
+    }
+}
+
+fn display_provenance(info: &AgentInfo) {
+    println!("\n{}", "Provenance:".bold().underline());
+
+    let Some(provenance) = &info.provenance else {
+        println!(
+            "  {}",
+            "No provenance was recorded for this version.".yellow()
+        );
+        return;
+    };
+
+    for (label, value) in provenance_fields(provenance) {
+        println!("  {}: {}", label.bold(), value.unwrap_or("unknown"));
+    }
+
+    if let Some(index) = info.transparency_log_index {
+        println!("  {}: {}", "Transparency log index".bold(), index);
+    }
+    if let Some(signed_at) = info.signed_at {
+        println!(
+            "  {}: {}",
+            "Signed at".bold(),
+            signed_at.format("%Y-%m-%d %H:%M UTC")
+        );
+    }
+
+    if !info.signed {
+        println!(
+            "\n  {}",
+            "Provenance is self-reported by the publisher; this version is not signed.".dimmed()
+        );
+    }
+}
+
+fn provenance_fields(provenance: &Provenance) -> [(&'static str, Option<&str>); 4] {
+    [
+        ("Git commit", provenance.git_commit.as_deref()),
+        ("Repository", provenance.repository_url.as_deref()),
+        ("CI run", provenance.ci_run_id.as_deref()),
+        ("Builder", provenance.builder.as_deref()),
+    ]
+}
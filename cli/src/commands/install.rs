@@ -0,0 +1,548 @@
+use crate::api::ApiClient;
+use crate::commands::pull::{extract_package, save_package};
+use crate::config::Config;
+use crate::utils::agent_dir::{self, AgentDir};
+use crate::utils::archive::{ArchiveFormat, ExtractProgress};
+use crate::utils::cleanup::Guard;
+use crate::utils::error::{CarpError, CarpResult};
+use crate::utils::git_source::GitSource;
+use crate::utils::trash;
+use crate::utils::variables;
+use crate::utils::workspace::{
+    parse_requirement, resolve, Dependency, LockedAgent, Lockfile, ManifestEditor, Workspace,
+    LOCKFILE, MANIFEST_FILE,
+};
+use colored::*;
+use semver::{Version, VersionReq};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+/// Options controlling `carp install`
+#[derive(Debug, Default)]
+pub struct InstallOptions {
+    /// Fail instead of changing the lockfile
+    pub locked: bool,
+    /// Reinstall agents that are already up to date
+    pub force: bool,
+    /// An agent's working tree to add to the manifest as a path dependency
+    pub path: Option<String>,
+    /// `KEY=VALUE` values for the variables agents declare
+    pub vars: Vec<String>,
+}
+
+/// Execute the install command: install every agent in the project manifest
+pub async fn execute(
+    client: &ApiClient,
+    config: &Config,
+    options: InstallOptions,
+    verbose: bool,
+) -> CarpResult<()> {
+    let cwd = std::env::current_dir()?;
+    let root = match &options.path {
+        // Like `carp add`, start a manifest if the project has none
+        Some(path) => {
+            let root = Workspace::find_root(&cwd).unwrap_or_else(|| cwd.clone());
+            add_path_dependency(&root, &cwd.join(path), path)?;
+            root
+        }
+        None => Workspace::find_root(&cwd).ok_or_else(|| {
+            CarpError::ManifestError(format!(
+                "No {MANIFEST_FILE} found in {} or any parent directory",
+                cwd.display()
+            ))
+        })?,
+    };
+    let workspace = Workspace::load(&root)?;
+    let lockfile = Lockfile::load(&root)?;
+    let assigned = variables::parse_assignments(&options.vars)?;
+    debug!(
+        "Installing agents from {}",
+        root.join(MANIFEST_FILE).display()
+    );
+
+    // `carp pull --with-deps` keeps its resolutions next to the installed agents
+    let mut updated = Lockfile {
+        resolutions: lockfile.resolutions.clone(),
+        ..Lockfile::default()
+    };
+    let mut installed = 0;
+    let mut values = BTreeMap::new();
+
+    for (name, dependency) in &workspace.agents {
+        let dest = workspace.install_dir(&root, name, dependency);
+        let recorded = workspace.variables.get(name);
+
+        // Working trees change under the project, so they're never locked
+        if let Some(path) = dependency.path() {
+            let tree = root.join(path);
+            if !tree.is_dir() {
+                return Err(CarpError::ManifestError(format!(
+                    "'{name}' points at '{path}', which is not a directory"
+                )));
+            }
+            let agent = AgentDir::read(&tree, path)?;
+            if agent.name != name.as_str() {
+                return Err(CarpError::InvalidAgent(format!(
+                    "'{path}' holds the agent '{}', not '{name}'",
+                    agent.name
+                )));
+            }
+
+            let linked = fs::read_link(&dest).is_ok_and(|target| target == tree);
+            if linked && !options.force {
+                debug!("{name} is linked to {path}");
+                continue;
+            }
+            link_agent(&tree, &dest)?;
+            println!(
+                "{} Linked {} v{} from {} to {}",
+                "✓".green().bold(),
+                name.blue().bold(),
+                agent.version,
+                path,
+                dest.strip_prefix(&cwd)
+                    .unwrap_or(&dest)
+                    .display()
+                    .to_string()
+                    .cyan()
+            );
+            installed += 1;
+            continue;
+        }
+
+        if let Some(source) = dependency.git_source()? {
+            let locked = lockfile
+                .get(name)
+                .filter(|locked| locked_commit(&source, locked).is_some());
+            if let Some(locked) = locked {
+                if dest.exists() && !options.force && !reassigned(&dest, recorded, &assigned)? {
+                    debug!("{name} v{} is up to date", locked.version);
+                    updated.agents.push(locked.clone());
+                    continue;
+                }
+            } else if options.locked {
+                return Err(lockfile_out_of_date(&format!(
+                    "'{name}' ({source}) is not locked"
+                )));
+            }
+
+            if keeps_local_changes(name, &dest, &lockfile, &mut updated)? {
+                continue;
+            }
+            let (locked, files) = install_git_agent(name, &source, locked, &dest)?;
+            values.insert(
+                name.clone(),
+                render_variables(name, &dest, recorded, &assigned)?,
+            );
+            agent_dir::record(&dest)?;
+            print_installed(name, &locked.version, files, &dest, &cwd);
+            installed += 1;
+            updated.agents.push(locked);
+            continue;
+        }
+
+        // Everything that isn't from git has a requirement
+        let requirement = parse_requirement(name, dependency.requirement().unwrap_or("*"))?;
+        let locked = lockfile.get(name).filter(|locked| {
+            locked.source.is_none()
+                && Version::parse(&locked.version)
+                    .is_ok_and(|version| requirement.matches(&version))
+        });
+
+        if let Some(locked) = locked {
+            if dest.exists() && !options.force && !reassigned(&dest, recorded, &assigned)? {
+                debug!("{name} v{} is up to date", locked.version);
+                updated.agents.push(locked.clone());
+                continue;
+            }
+        } else if options.locked {
+            return Err(lockfile_out_of_date(&format!(
+                "'{name}' ({requirement}) is not locked"
+            )));
+        }
+
+        let version = match locked {
+            Some(locked) => locked.version.clone(),
+            None => resolve_version(client, name, &requirement).await?,
+        };
+        if keeps_local_changes(name, &dest, &lockfile, &mut updated)? {
+            continue;
+        }
+        let expected = locked.map(|locked| locked.checksum.as_str());
+        let (checksum, progress) =
+            install_agent(client, config, name, &version, expected, &dest, verbose).await?;
+        values.insert(
+            name.clone(),
+            render_variables(name, &dest, recorded, &assigned)?,
+        );
+        agent_dir::record(&dest)?;
+
+        print_installed(name, &version, progress.entries, &dest, &cwd);
+        installed += 1;
+        updated.agents.push(LockedAgent {
+            name: name.clone(),
+            version,
+            checksum,
+            source: None,
+        });
+    }
+
+    preserve_project_files(&root)?;
+    record_variables(&root, &workspace, &values)?;
+
+    if updated != lockfile {
+        if options.locked {
+            return Err(lockfile_out_of_date("it lists agents the manifest doesn't"));
+        }
+        // Resolutions another carp recorded meanwhile are kept
+        Lockfile::update(&root, |current| current.agents = updated.agents)?;
+        debug!("Updated {}", root.join(LOCKFILE).display());
+    }
+
+    let up_to_date = workspace.agents.len() - installed;
+    println!(
+        "{} {} installed, {} up to date",
+        "✓".green().bold(),
+        installed.to_string().green().bold(),
+        up_to_date
+    );
+
+    Ok(())
+}
+
+/// The newest version of an agent that satisfies a requirement
+pub(crate) async fn resolve_version(
+    client: &ApiClient,
+    name: &str,
+    requirement: &VersionReq,
+) -> CarpResult<String> {
+    let info = client.get_agent_info(name).await?;
+
+    // Registries that don't list versions only offer the latest
+    let versions = if info.versions.is_empty() {
+        std::slice::from_ref(&info.version)
+    } else {
+        info.versions.as_slice()
+    };
+
+    resolve(requirement, versions.iter().map(String::as_str))
+        .map(str::to_string)
+        .ok_or_else(|| {
+            CarpError::InvalidAgent(format!(
+                "No version of '{name}' matches '{requirement}' (latest is {})",
+                info.version
+            ))
+        })
+}
+
+/// Download an agent's package into `dest`, checking it against the lockfile's
+/// checksum when there is one, and return the package's checksum
+pub(crate) async fn install_agent(
+    client: &ApiClient,
+    config: &Config,
+    name: &str,
+    version: &str,
+    expected_checksum: Option<&str>,
+    dest: &Path,
+    verbose: bool,
+) -> CarpResult<(String, ExtractProgress)> {
+    let (archive, declared, checksum) =
+        download_package(client, name, version, expected_checksum).await?;
+
+    let progress = extract_package(config, archive.path(), declared, dest, verbose)?;
+    Ok((checksum, progress))
+}
+
+/// Download an agent's package into the cache and checksum it, refusing it if
+/// the checksum isn't the one the lockfile or a signed resolution expects
+///
+/// The archive is removed when the returned guard is dropped.
+pub(crate) async fn download_package(
+    client: &ApiClient,
+    name: &str,
+    version: &str,
+    expected_checksum: Option<&str>,
+) -> CarpResult<(Guard, ArchiveFormat, String)> {
+    let download = client.get_agent_download(name, Some(version)).await?;
+    let (archive, declared) = save_package(client, &download, None).await?;
+
+    let checksum = agent_dir::file_checksum(archive.path())?;
+    if let Some(expected) = expected_checksum.filter(|expected| *expected != checksum) {
+        return Err(CarpError::InvalidAgent(format!(
+            "Package for {name}@{version} has checksum {checksum}, but {expected} was expected"
+        )));
+    }
+    Ok((archive, declared, checksum))
+}
+
+/// Record an agent's working tree in the manifest in `root`, by a path
+/// relative to it, keeping the directory the agent was installed in
+fn add_path_dependency(root: &Path, tree: &Path, path: &str) -> CarpResult<()> {
+    let tree = tree
+        .canonicalize()
+        .map_err(|e| CarpError::FileSystem(format!("Can't open '{path}': {e}")))?;
+    let agent = AgentDir::read(&tree, path)?;
+
+    let created = !root.join(MANIFEST_FILE).exists();
+    let dir = if created {
+        None
+    } else {
+        Workspace::load(root)?
+            .agents
+            .get(&agent.name)
+            .and_then(|dependency| dependency.dir().map(str::to_string))
+    };
+    let relative = relative_path(&tree, &root.canonicalize()?);
+
+    let mut editor = ManifestEditor::open(root)?;
+    editor.set_agent(
+        &agent.name,
+        &Dependency::Path {
+            path: relative.clone(),
+            dir,
+        },
+    )?;
+    editor.save()?;
+
+    if created {
+        println!(
+            "{} Created {}",
+            "✓".green().bold(),
+            root.join(MANIFEST_FILE).display()
+        );
+    }
+    println!(
+        "{} Added {} = {{ path = \"{}\" }} to {}",
+        "✓".green().bold(),
+        agent.name.blue().bold(),
+        relative,
+        MANIFEST_FILE
+    );
+    Ok(())
+}
+
+/// `path` relative to `base`, both canonical, with `/` separators so the
+/// manifest reads the same on every platform
+fn relative_path(path: &Path, base: &Path) -> String {
+    let path: Vec<_> = path.components().collect();
+    let base: Vec<_> = base.components().collect();
+    let common = path.iter().zip(&base).take_while(|(a, b)| a == b).count();
+
+    // Nothing in common, e.g. another drive on Windows
+    if common == 0 {
+        return path.iter().collect::<PathBuf>().display().to_string();
+    }
+
+    let parts: Vec<String> = std::iter::repeat_n("..".to_string(), base.len() - common)
+        .chain(
+            path[common..]
+                .iter()
+                .map(|part| part.as_os_str().to_string_lossy().into_owned()),
+        )
+        .collect();
+    if parts.is_empty() {
+        ".".to_string()
+    } else {
+        parts.join("/")
+    }
+}
+
+/// Point `dest` at an agent's working tree: a symlink where the platform
+/// allows, so edits show up without reinstalling, or else a copy
+fn link_agent(tree: &Path, dest: &Path) -> CarpResult<()> {
+    agent_dir::remove(dest)?;
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(tree, dest)?;
+    #[cfg(not(unix))]
+    agent_dir::copy(tree, dest)?;
+    Ok(())
+}
+
+/// Check out an agent from git and install it into `dest`
+///
+/// An agent locked to the same source is checked out at the locked commit
+/// and must match the locked checksum.
+fn install_git_agent(
+    name: &str,
+    source: &GitSource,
+    locked: Option<&LockedAgent>,
+    dest: &Path,
+) -> CarpResult<(LockedAgent, usize)> {
+    let pinned = locked.and_then(|locked| Some((locked, locked_commit(source, locked)?)));
+    debug!("Checking out {source}...");
+    let checkout = source.checkout(pinned.as_ref().map(|(_, commit)| commit.as_str()))?;
+
+    let agent = checkout.agent()?;
+    if agent.name != name {
+        return Err(CarpError::InvalidAgent(format!(
+            "{source} holds the agent '{}', not '{name}'",
+            agent.name
+        )));
+    }
+    let checksum = checkout.checksum()?;
+    if let Some((locked, commit)) = &pinned {
+        if locked.checksum != checksum {
+            return Err(CarpError::InvalidAgent(format!(
+                "{name} at commit {commit} has checksum {checksum}, but {LOCKFILE} expects {}",
+                locked.checksum
+            )));
+        }
+    }
+
+    let files = checkout.install(dest)?;
+    let locked = LockedAgent {
+        name: name.to_string(),
+        version: agent.version,
+        checksum,
+        source: Some(source.locked(&checkout.commit)),
+    };
+    Ok((locked, files))
+}
+
+/// Keep the manifest and lockfile in the trash when the command replaced
+/// installed agents, so `carp undo` puts the project back as a whole
+pub(crate) fn preserve_project_files(root: &Path) -> CarpResult<()> {
+    if trash::active() {
+        trash::preserve(&root.join(MANIFEST_FILE))?;
+        trash::preserve(&root.join(LOCKFILE))?;
+    }
+    Ok(())
+}
+
+/// Whether to leave the agent in `dest` as it is, rather than lose its local
+/// changes by reinstalling it
+///
+/// A kept agent stays locked at the version it was installed at.
+fn keeps_local_changes(
+    name: &str,
+    dest: &Path,
+    lockfile: &Lockfile,
+    updated: &mut Lockfile,
+) -> CarpResult<bool> {
+    if agent_dir::confirm_discard(name, dest)? {
+        return Ok(false);
+    }
+    println!(
+        "{} Skipped {}, keeping its local changes",
+        "→".blue().bold(),
+        name.blue().bold()
+    );
+    updated.agents.extend(lockfile.get(name).cloned());
+    Ok(true)
+}
+
+/// Fill in the variables the agent installed in `dest` declares, from
+/// `--set`, the values `recorded` in the manifest or a prompt, returning the
+/// values used
+///
+/// Agents linked from a working tree aren't rendered, since that would edit
+/// the tree itself.
+pub(crate) fn render_variables(
+    name: &str,
+    dest: &Path,
+    recorded: Option<&BTreeMap<String, String>>,
+    assigned: &BTreeMap<String, String>,
+) -> CarpResult<BTreeMap<String, String>> {
+    let declared = variables::declared(dest)?;
+    let none = BTreeMap::new();
+    let values = variables::resolve(name, &declared, recorded.unwrap_or(&none), assigned)?;
+    let files = variables::render(dest, &values)?;
+    debug!(
+        "Filled in {} variables in {files} files of {name}",
+        values.len()
+    );
+    Ok(values)
+}
+
+/// Record in the manifest the variable values that differ from what it has,
+/// so later installs render agents the same way
+pub(crate) fn record_variables(
+    root: &Path,
+    workspace: &Workspace,
+    values: &BTreeMap<String, BTreeMap<String, String>>,
+) -> CarpResult<()> {
+    let changed: Vec<_> = values
+        .iter()
+        .filter(|(name, values)| {
+            !values.is_empty() && workspace.variables.get(*name) != Some(*values)
+        })
+        .collect();
+    if changed.is_empty() {
+        return Ok(());
+    }
+
+    let mut editor = ManifestEditor::open(root)?;
+    for (name, values) in changed {
+        editor.set_variables(name, values)?;
+    }
+    editor.save()
+}
+
+/// Whether `--set` gives a variable of the agent installed in `dest` a value
+/// other than the one the manifest records, so it must be rendered again
+fn reassigned(
+    dest: &Path,
+    recorded: Option<&BTreeMap<String, String>>,
+    assigned: &BTreeMap<String, String>,
+) -> CarpResult<bool> {
+    if assigned.is_empty() {
+        return Ok(false);
+    }
+    Ok(variables::declared(dest)?.keys().any(|key| {
+        assigned
+            .get(key)
+            .is_some_and(|value| recorded.and_then(|recorded| recorded.get(key)) != Some(value))
+    }))
+}
+
+/// The commit an agent is locked at, if it was locked from `source`
+fn locked_commit(source: &GitSource, locked: &LockedAgent) -> Option<String> {
+    let (locked_source, commit) = GitSource::parse_locked(locked.source.as_deref()?).ok()?;
+    (locked_source == *source).then_some(commit)
+}
+
+pub(crate) fn print_installed(name: &str, version: &str, files: usize, dest: &Path, cwd: &Path) {
+    println!(
+        "{} Installed {} v{} ({} files) to {}",
+        "✓".green().bold(),
+        name.blue().bold(),
+        version,
+        files,
+        dest.strip_prefix(cwd)
+            .unwrap_or(dest)
+            .display()
+            .to_string()
+            .cyan()
+    );
+}
+
+fn lockfile_out_of_date(reason: &str) -> CarpError {
+    CarpError::ManifestError(format!(
+        "{LOCKFILE} is out of date: {reason}. Run `carp install` without --locked to update it"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relative_path() {
+        let root = Path::new("/work/app");
+        assert_eq!(
+            relative_path(Path::new("/work/agents/draft"), root),
+            "../agents/draft"
+        );
+        assert_eq!(
+            relative_path(Path::new("/work/app/agents/draft"), root),
+            "agents/draft"
+        );
+        assert_eq!(relative_path(root, root), ".");
+    }
+}
@@ -1,19 +1,236 @@
-use crate::api::ApiClient;
-use crate::config::ConfigManager;
+use crate::api::{Agent, ApiClient, SearchMode, SearchResponse, SearchSort};
 use crate::utils::error::CarpResult;
+use crate::utils::name_index;
+use crate::utils::search_index::SearchIndex;
+use crate::utils::table::{Style, Table};
 use colored::*;
+use std::cmp::Ordering;
+use tracing::debug;
 
-/// Execute the list command to show all available agents
-pub async fn execute(verbose: bool) -> CarpResult<()> {
-    if verbose {
-        println!("Fetching all available agents...");
+/// Placeholder for an empty cell
+const MISSING: &str = "-";
+
+/// Which agents `carp list` shows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListFilter {
+    /// Every agent visible to the user
+    All,
+    /// Agents the user has starred
+    Starred,
+    /// The user's own agents, private ones included
+    Mine,
+}
+
+/// A column of the table `--columns` asks for, which `--sort-by` can also
+/// sort on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Column {
+    Name,
+    Version,
+    Description,
+    Author,
+    Downloads,
+    Stars,
+    /// Average review rating and how many reviews it's from
+    Rating,
+    Tags,
+    Created,
+    Updated,
+    /// Public or private
+    Visibility,
+    /// The verified namespace, or whether the publisher is verified
+    Verified,
+}
+
+impl Column {
+    fn header(self) -> &'static str {
+        match self {
+            Column::Name => "Name",
+            Column::Version => "Version",
+            Column::Description => "Description",
+            Column::Author => "Author",
+            Column::Downloads => "Downloads",
+            Column::Stars => "Stars",
+            Column::Rating => "Rating",
+            Column::Tags => "Tags",
+            Column::Created => "Created",
+            Column::Updated => "Updated",
+            Column::Visibility => "Visibility",
+            Column::Verified => "Verified",
+        }
+    }
+
+    fn cell(self, agent: &Agent) -> String {
+        match self {
+            Column::Name => agent.name.clone(),
+            Column::Version => agent.version.clone(),
+            Column::Description => agent.description.clone(),
+            Column::Author => agent.author.clone(),
+            Column::Downloads => agent.download_count.to_string(),
+            Column::Stars => agent.star_count.to_string(),
+            Column::Rating => agent
+                .rating_average
+                .map(|average| format!("{average:.1} ({})", agent.rating_count))
+                .unwrap_or_else(|| MISSING.to_string()),
+            Column::Tags if agent.tags.is_empty() => MISSING.to_string(),
+            Column::Tags => agent.tags.join(", "),
+            Column::Created => agent.created_at.format("%Y-%m-%d").to_string(),
+            Column::Updated => agent.updated_at.format("%Y-%m-%d").to_string(),
+            Column::Visibility if agent.is_public => "public".to_string(),
+            Column::Visibility => "private".to_string(),
+            Column::Verified => match &agent.verified_namespace {
+                Some(namespace) => format!("@{namespace}"),
+                None if agent.verified => "yes".to_string(),
+                None => MISSING.to_string(),
+            },
+        }
+    }
+
+    /// Counts, ratings and dates sort highest and newest first, text
+    /// alphabetically
+    fn compare(self, a: &Agent, b: &Agent) -> Ordering {
+        match self {
+            Column::Name => a.name.cmp(&b.name),
+            Column::Version => match (
+                semver::Version::parse(&a.version),
+                semver::Version::parse(&b.version),
+            ) {
+                (Ok(a), Ok(b)) => b.cmp(&a),
+                _ => b.version.cmp(&a.version),
+            },
+            Column::Description => a.description.cmp(&b.description),
+            Column::Author => a.author.cmp(&b.author),
+            Column::Downloads => b.download_count.cmp(&a.download_count),
+            Column::Stars => b.star_count.cmp(&a.star_count),
+            Column::Rating => b
+                .rating_average
+                .unwrap_or(0.0)
+                .total_cmp(&a.rating_average.unwrap_or(0.0)),
+            Column::Tags => a.tags.cmp(&b.tags),
+            Column::Created => b.created_at.cmp(&a.created_at),
+            Column::Updated => b.updated_at.cmp(&a.updated_at),
+            Column::Visibility => b.is_public.cmp(&a.is_public),
+            Column::Verified => is_verified(b).cmp(&is_verified(a)),
+        }
+    }
+
+    fn style(self) -> Option<Style> {
+        match self {
+            Column::Name => Some(|cell| cell.bold().blue()),
+            Column::Version => Some(|cell| cell.dimmed()),
+            Column::Author => Some(|cell| cell.green()),
+            Column::Downloads | Column::Stars => Some(|cell| cell.cyan()),
+            Column::Verified => Some(|cell| cell.green()),
+            _ => None,
+        }
+    }
+}
+
+/// Whether `agent` comes from a verified publisher
+fn is_verified(agent: &Agent) -> bool {
+    agent.verified || agent.verified_namespace.is_some()
+}
+
+/// Badge marking an agent from a verified publisher, naming its namespace
+/// when it's in a verified one
+pub fn verified_badge(agent: &Agent) -> Option<String> {
+    if !is_verified(agent) {
+        return None;
+    }
+    let badge = match &agent.verified_namespace {
+        Some(namespace) => format!("✓ @{namespace}"),
+        None => "✓ verified".to_string(),
+    };
+    Some(badge.green().to_string())
+}
+
+/// How `carp list` and `carp search` show agents
+#[derive(Debug, Clone, Default)]
+pub struct Layout {
+    /// A table of these columns, instead of a paragraph per agent
+    pub columns: Vec<Column>,
+    pub sort_by: Option<Column>,
+}
+
+impl Layout {
+    /// Sort `agents` by `--sort-by`, keeping the registry's order for ties
+    pub fn sort(&self, agents: &mut [Agent]) {
+        if let Some(column) = self.sort_by {
+            agents.sort_by(|a, b| column.compare(a, b));
+        }
     }
 
-    let config = ConfigManager::load_with_env_checks()?;
-    let client = ApiClient::new(&config)?;
+    /// Print `agents` as a table if `--columns` asked for one, returning
+    /// whether it did
+    pub fn print_table(&self, agents: &[Agent]) -> bool {
+        if self.columns.is_empty() {
+            return false;
+        }
+        let mut table = Table::new(self.columns.iter().map(|column| column.header()));
+        for (i, column) in self.columns.iter().enumerate() {
+            if let Some(style) = column.style() {
+                table.style(i, style);
+            }
+        }
+        for agent in agents {
+            table.row(
+                self.columns
+                    .iter()
+                    .map(|column| column.cell(agent))
+                    .collect(),
+            );
+        }
+        table.print();
+        println!();
+        true
+    }
+
+    fn print(&self, agents: &mut [Agent], verbose: bool) {
+        self.sort(agents);
+        if !self.print_table(agents) {
+            for agent in agents.iter() {
+                print_agent(agent, verbose);
+            }
+        }
+    }
+}
 
-    // Use search with empty query to get all agents
-    let response = client.search("", Some(1000), false).await?;
+/// Execute the list command to show all available agents, the ones the user
+/// has starred, or the user's own
+pub async fn execute(
+    client: &ApiClient,
+    filter: ListFilter,
+    layout: &Layout,
+    offline: bool,
+    verbose: bool,
+) -> CarpResult<()> {
+    match filter {
+        ListFilter::All => {}
+        ListFilter::Starred => return list_starred(client, layout, verbose).await,
+        ListFilter::Mine => return list_mine(client, layout, verbose).await,
+    }
+
+    debug!("Fetching all available agents...");
+
+    let index = if offline {
+        Some(SearchIndex::require(client.base_url())?)
+    } else {
+        None
+    };
+    let mut response = match index {
+        Some(index) => offline_listing(&index)?,
+        // Use search with empty query to get all agents
+        None => match client.search("", Some(1000), false).await {
+            Ok(response) => {
+                name_index::record(
+                    client.base_url(),
+                    response.agents.iter().map(|agent| agent.name.as_str()),
+                );
+                response
+            }
+            Err(e) => offline_listing(&SearchIndex::fallback(client.base_url(), e)?)?,
+        },
+    };
 
     if response.agents.is_empty() {
         println!("{}", "No agents found in the registry.".yellow());
@@ -26,47 +243,125 @@ pub async fn execute(verbose: bool) -> CarpResult<()> {
         response.total
     );
 
-    for agent in &response.agents {
-        println!("{} {}", agent.name.bold().blue(), agent.version.dimmed());
-        println!("  {}", agent.description);
+    layout.print(&mut response.agents, verbose);
+
+    if response.total > response.agents.len() {
         println!(
-            "  by {} • {} views",
-            agent.author.green(),
-            agent.download_count.to_string().cyan()
+            "{} Showing {} of {} agents. Some agents may be hidden due to API limits.",
+            "Note:".yellow().bold(),
+            response.agents.len(),
+            response.total
         );
+    }
 
-        if !agent.tags.is_empty() {
-            print!("  tags: ");
-            for (i, tag) in agent.tags.iter().enumerate() {
-                if i > 0 {
-                    print!(", ");
-                }
-                print!("{}", tag.yellow());
-            }
-            println!();
-        }
+    Ok(())
+}
 
-        if verbose {
-            println!("  created: {}", agent.created_at.format("%Y-%m-%d"));
-            if let Some(homepage) = &agent.homepage {
-                println!("  homepage: {}", homepage.blue().underline());
-            }
-            if let Some(repository) = &agent.repository {
-                println!("  repository: {}", repository.blue().underline());
-            }
-        }
+/// Everything in the index, most downloaded first like the registry's listing
+fn offline_listing(index: &SearchIndex) -> CarpResult<SearchResponse> {
+    index.print_staleness();
+    index.search("", Some(1000), SearchMode::Substring, SearchSort::Downloads)
+}
 
-        println!();
+async fn list_starred(client: &ApiClient, layout: &Layout, verbose: bool) -> CarpResult<()> {
+    debug!("Fetching starred agents...");
+
+    let mut response = client.starred().await?;
+    name_index::record(
+        client.base_url(),
+        response.agents.iter().map(|agent| agent.name.as_str()),
+    );
+
+    if response.agents.is_empty() {
+        println!("{}", "You haven't starred any agents yet.".yellow());
+        println!("Use `carp star <name>` to star an agent.");
+        return Ok(());
     }
 
-    if response.total > response.agents.len() {
+    println!(
+        "{} {} starred agents:\n",
+        "Found".green().bold(),
+        response.total
+    );
+
+    layout.print(&mut response.agents, verbose);
+
+    Ok(())
+}
+
+async fn list_mine(client: &ApiClient, layout: &Layout, verbose: bool) -> CarpResult<()> {
+    debug!("Fetching your agents...");
+
+    let mut response = client.mine(None).await?;
+    name_index::record(
+        client.base_url(),
+        response.agents.iter().map(|agent| agent.name.as_str()),
+    );
+
+    if response.agents.is_empty() {
+        println!("{}", "You haven't uploaded any agents yet.".yellow());
         println!(
-            "{} Showing {} of {} agents. Some agents may be hidden due to API limits.",
-            "Note:".yellow().bold(),
-            response.agents.len(),
-            response.total
+            "Use `carp upload` to publish one, or `carp upload --private` to keep it to yourself."
         );
+        return Ok(());
     }
 
+    let private = response.agents.iter().filter(|a| !a.is_public).count();
+    println!(
+        "{} {} of your agents ({} private):\n",
+        "Found".green().bold(),
+        response.total,
+        private
+    );
+
+    layout.print(&mut response.agents, verbose);
+
     Ok(())
 }
+
+fn print_agent(agent: &Agent, verbose: bool) {
+    let mut markers: Vec<String> = verified_badge(agent).into_iter().collect();
+    if !agent.is_public {
+        markers.push("(private)".yellow().to_string());
+    }
+    if markers.is_empty() {
+        println!("{} {}", agent.name.bold().blue(), agent.version.dimmed());
+    } else {
+        println!(
+            "{} {} {}",
+            agent.name.bold().blue(),
+            agent.version.dimmed(),
+            markers.join(" ")
+        );
+    }
+    println!("  {}", agent.description);
+    println!(
+        "  by {} • {} views • {} stars",
+        agent.author.green(),
+        agent.download_count.to_string().cyan(),
+        agent.star_count.to_string().cyan()
+    );
+
+    if !agent.tags.is_empty() {
+        print!("  tags: ");
+        for (i, tag) in agent.tags.iter().enumerate() {
+            if i > 0 {
+                print!(", ");
+            }
+            print!("{}", tag.yellow());
+        }
+        println!();
+    }
+
+    if verbose {
+        println!("  created: {}", agent.created_at.format("%Y-%m-%d"));
+        if let Some(homepage) = &agent.homepage {
+            println!("  homepage: {}", homepage.blue().underline());
+        }
+        if let Some(repository) = &agent.repository {
+            println!("  repository: {}", repository.blue().underline());
+        }
+    }
+
+    println!();
+}
@@ -0,0 +1,620 @@
+use crate::api::{Agent, AgentInfo, ApiClient, UploadAgentRequest};
+use crate::auth::AuthManager;
+use crate::config::ConfigManager;
+use crate::utils::cleanup;
+use crate::utils::error::{CarpError, CarpResult};
+use crate::utils::file_lock::LockedFile;
+use crate::utils::frontmatter::{parse_frontmatter, render_frontmatter};
+use crate::utils::pattern::glob_match;
+use colored::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::debug;
+
+/// Agents fetched per page of the source registry's listing
+const LIST_PAGE: usize = 100;
+
+/// Options controlling a mirror run
+#[derive(Debug, Clone, Default)]
+pub struct MirrorOptions {
+    /// Glob patterns an agent name must match to be mirrored (all agents if empty)
+    pub include: Vec<String>,
+    /// Glob patterns excluding matching agent names from the mirror
+    pub exclude: Vec<String>,
+    /// Re-run the sync every N seconds instead of exiting after one pass
+    pub interval: Option<u64>,
+}
+
+/// Persisted record of which agent versions have already been mirrored, per
+/// source. It's kept with carp's data rather than its cache, since losing it
+/// means checking every version against the target again.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct MirrorState {
+    #[serde(default)]
+    sources: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl MirrorState {
+    const FILE: &'static str = "mirror-state.json";
+
+    fn path() -> CarpResult<PathBuf> {
+        Ok(ConfigManager::data_dir()?.join(Self::FILE))
+    }
+
+    fn load() -> CarpResult<Self> {
+        let path = Self::path()?;
+        if path.exists() {
+            return Self::read(&path);
+        }
+        // Earlier versions kept it in the cache
+        let legacy = ConfigManager::cache_dir()?.join(Self::FILE);
+        if legacy.exists() {
+            return Self::read(&legacy);
+        }
+        Ok(Self::default())
+    }
+
+    fn read(path: &Path) -> CarpResult<Self> {
+        let contents = fs::read_to_string(path)?;
+        serde_json::from_str(&contents)
+            .map_err(|e| CarpError::Config(format!("Corrupt mirror state file: {e}")))
+    }
+
+    /// Record what this run has mirrored, keeping what other runs recorded
+    /// since it loaded
+    fn save(&self) -> CarpResult<()> {
+        let path = Self::path()?;
+        let _lock = LockedFile::beside(&path)?;
+        let mut merged = if path.exists() {
+            Self::read(&path)?
+        } else {
+            Self::default()
+        };
+        for (source, versions) in &self.sources {
+            merged
+                .sources
+                .entry(source.clone())
+                .or_default()
+                .extend(versions.iter().cloned());
+        }
+        cleanup::write_atomic(&path, serde_json::to_string_pretty(&merged)?)?;
+        Ok(())
+    }
+
+    fn is_mirrored(&self, source: &str, name: &str, version: &str) -> bool {
+        self.sources
+            .get(source)
+            .is_some_and(|versions| versions.contains(&version_key(name, version)))
+    }
+
+    fn mark_mirrored(&mut self, source: &str, name: &str, version: &str) {
+        self.sources
+            .entry(source.to_string())
+            .or_default()
+            .insert(version_key(name, version));
+    }
+}
+
+/// Summary of a single sync pass
+#[derive(Debug, Default)]
+struct SyncSummary {
+    mirrored: usize,
+    skipped: usize,
+    failed: usize,
+}
+
+/// Execute the mirror command
+pub async fn execute(
+    target_client: &ApiClient,
+    source: String,
+    options: MirrorOptions,
+) -> CarpResult<()> {
+    AuthManager::ensure_authenticated(target_client.api_key()).await?;
+
+    let source = source.trim_end_matches('/').to_string();
+    ConfigManager::validate_registry_url(&source)?;
+    if source == target_client.base_url() {
+        return Err(CarpError::Config(
+            "Source registry must differ from the configured registry".to_string(),
+        ));
+    }
+
+    // Both ends share one connection pool
+    let source_client = target_client.for_registry(&source);
+
+    let mut state = MirrorState::load()?;
+
+    loop {
+        println!(
+            "{} Mirroring {} → {}",
+            "⟳".blue().bold(),
+            source.cyan(),
+            target_client.base_url().cyan()
+        );
+
+        let summary =
+            sync_once(&source_client, target_client, &source, &options, &mut state).await?;
+
+        println!(
+            "{} Mirror pass complete: {} mirrored, {} up to date, {} failed",
+            "✓".green().bold(),
+            summary.mirrored.to_string().green().bold(),
+            summary.skipped,
+            if summary.failed > 0 {
+                summary.failed.to_string().red().bold()
+            } else {
+                summary.failed.to_string().green().bold()
+            }
+        );
+
+        match options.interval {
+            Some(seconds) => {
+                debug!("Next mirror pass in {seconds}s");
+                tokio::time::sleep(Duration::from_secs(seconds)).await;
+            }
+            None => break,
+        }
+    }
+
+    Ok(())
+}
+
+/// Copy every new version of every selected agent from the source registry
+/// into the target registry
+async fn sync_once(
+    source_client: &ApiClient,
+    target_client: &ApiClient,
+    source: &str,
+    options: &MirrorOptions,
+    state: &mut MirrorState,
+) -> CarpResult<SyncSummary> {
+    let mut summary = SyncSummary::default();
+
+    for page in 1.. {
+        let response = source_client.list_page(page, LIST_PAGE).await?;
+        let last = response.agents.len() < LIST_PAGE || page * LIST_PAGE >= response.total;
+
+        for agent in response
+            .agents
+            .into_iter()
+            .filter(|agent| is_selected(&agent.name, options))
+        {
+            // Versions are mirrored oldest first and an agent stops at its
+            // first failure, so a mirrored latest version means all of them are
+            if state.is_mirrored(source, &agent.name, &agent.version) {
+                summary.skipped += 1;
+                continue;
+            }
+
+            mirror_agent(
+                source_client,
+                target_client,
+                source,
+                &agent,
+                state,
+                &mut summary,
+            )
+            .await?;
+        }
+
+        if last {
+            break;
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Mirror the versions of an agent not yet mirrored, oldest first, stopping
+/// at the first failure so the target never gets them out of order
+async fn mirror_agent(
+    source_client: &ApiClient,
+    target_client: &ApiClient,
+    source: &str,
+    agent: &Agent,
+    state: &mut MirrorState,
+    summary: &mut SyncSummary,
+) -> CarpResult<()> {
+    let info = match source_client.get_agent_info(&agent.name).await {
+        Ok(info) => info,
+        Err(e) => {
+            println!(
+                "{} Failed to mirror {}: {}",
+                "✗".red().bold(),
+                agent.name.red().bold(),
+                e
+            );
+            summary.failed += 1;
+            return Ok(());
+        }
+    };
+    // Registries that predate the version list only offer the latest
+    let versions = if info.versions.is_empty() {
+        vec![info.version.clone()]
+    } else {
+        info.versions.clone()
+    };
+
+    for version in versions {
+        if state.is_mirrored(source, &agent.name, &version) {
+            continue;
+        }
+
+        debug!("Mirroring {}@{}...", agent.name, version);
+
+        match mirror_version(source_client, target_client, agent, &info, &version).await {
+            Ok(outcome) => {
+                match outcome {
+                    Mirrored::Copied => {
+                        println!(
+                            "{} Mirrored {} v{}",
+                            "✓".green().bold(),
+                            agent.name.blue().bold(),
+                            version
+                        );
+                        summary.mirrored += 1;
+                    }
+                    Mirrored::AlreadyThere => {
+                        debug!("{}@{} is already in the target", agent.name, version);
+                        summary.skipped += 1;
+                    }
+                }
+                state.mark_mirrored(source, &agent.name, &version);
+                state.save()?;
+            }
+            Err(e) => {
+                println!(
+                    "{} Failed to mirror {} v{}: {}",
+                    "✗".red().bold(),
+                    agent.name.red().bold(),
+                    version,
+                    e
+                );
+                summary.failed += 1;
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// How a version came to be in the target registry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mirrored {
+    Copied,
+    /// Published there already, by an earlier run whose state was lost or
+    /// by another mirror
+    AlreadyThere,
+}
+
+/// Recreate one version of an agent in the target registry from the
+/// definition it was published with
+async fn mirror_version(
+    source_client: &ApiClient,
+    target_client: &ApiClient,
+    agent: &Agent,
+    info: &AgentInfo,
+    version: &str,
+) -> CarpResult<Mirrored> {
+    // Copying a version into a mirror isn't a download, so the source
+    // shouldn't count it as one
+    let download = source_client
+        .prefetch_agent_download(&agent.name, Some(version))
+        .await?;
+    let request = build_upload_request(agent, info, version, &download.definition)?;
+
+    match target_client.upload(request).await {
+        Ok(response) if response.success => Ok(Mirrored::Copied),
+        Ok(response) => Err(CarpError::Api {
+            status: 400,
+            message: response.message,
+        }),
+        // The registry answers 409 `version_exists` for versions it has
+        Err(CarpError::Api { status: 409, .. }) => Ok(Mirrored::AlreadyThere),
+        Err(e) => Err(e),
+    }
+}
+
+/// Check an agent name against the include/exclude filters
+fn is_selected(name: &str, options: &MirrorOptions) -> bool {
+    let included = options.include.is_empty()
+        || options
+            .include
+            .iter()
+            .any(|pattern| glob_match(pattern, name));
+    let excluded = options
+        .exclude
+        .iter()
+        .any(|pattern| glob_match(pattern, name));
+
+    included && !excluded
+}
+
+fn version_key(name: &str, version: &str) -> String {
+    format!("{name}@{version}")
+}
+
+/// Build the upload request that recreates an agent version in the target
+/// registry, from the definition the source returned for that version
+fn build_upload_request(
+    agent: &Agent,
+    info: &AgentInfo,
+    version: &str,
+    definition: &serde_json::Value,
+) -> CarpResult<UploadAgentRequest> {
+    let latest = version == agent.version;
+    let published = published_content(definition);
+    let content = match published {
+        Some((frontmatter, body)) => render_frontmatter(frontmatter, body.trim_end())?,
+        // Older registries only keep the latest version's README to go on
+        None if latest => mirror_content(agent)?,
+        None => {
+            return Err(CarpError::InvalidAgent(format!(
+                "Source registry returned no definition for {}@{version}",
+                agent.name
+            )))
+        }
+    };
+    let frontmatter = published.map(|(frontmatter, _)| frontmatter);
+
+    // A signature only carries over when the content is copied byte for byte
+    let signature_bundle = agent
+        .signature_bundle
+        .clone()
+        .filter(|_| latest && agent.readme.as_deref() == Some(content.as_str()));
+    // The registry keeps provenance for the latest version only
+    let provenance = info.provenance.clone().filter(|_| version == info.version);
+    let description = frontmatter
+        .and_then(|frontmatter| frontmatter.get("description"))
+        .and_then(|description| description.as_str())
+        .unwrap_or(&agent.description)
+        .to_string();
+    let dependencies = frontmatter
+        .and_then(|frontmatter| frontmatter.get("dependencies"))
+        .and_then(|dependencies| serde_json::from_value(dependencies.clone()).ok())
+        .unwrap_or_default();
+
+    Ok(UploadAgentRequest {
+        name: agent.name.clone(),
+        description,
+        content,
+        version: Some(version.to_string()),
+        tags: agent.tags.clone(),
+        homepage: agent.homepage.clone(),
+        repository: agent.repository.clone(),
+        license: agent.license.clone(),
+        signature_bundle,
+        provenance,
+        private: !agent.is_public,
+        template: agent.is_template,
+        allowed_secrets: Vec::new(),
+        dependencies,
+    })
+}
+
+/// The frontmatter and body a version was published with, if its
+/// definition has them
+fn published_content(definition: &serde_json::Value) -> Option<(&serde_json::Value, &str)> {
+    let frontmatter = definition.get("metadata").filter(|m| m.is_object())?;
+    let body = definition.get("content")?.as_str()?;
+    Some((frontmatter, body))
+}
+
+/// Reuse the original agent content when the README still carries matching
+/// frontmatter, otherwise synthesize frontmatter around the README body
+fn mirror_content(agent: &Agent) -> CarpResult<String> {
+    let readme = agent.readme.clone().unwrap_or_default();
+
+    if let Ok((frontmatter, _)) = parse_frontmatter(&readme) {
+        let name_matches = frontmatter.get("name").and_then(|v| v.as_str()) == Some(&agent.name);
+        let description_matches =
+            frontmatter.get("description").and_then(|v| v.as_str()) == Some(&agent.description);
+        if name_matches && description_matches {
+            return Ok(readme);
+        }
+    }
+
+    let body = if readme.trim().is_empty() {
+        format!("# {}\n\n{}", agent.name, agent.description)
+    } else {
+        readme
+    };
+
+    render_frontmatter(
+        &serde_json::json!({
+            "name": agent.name,
+            "description": agent.description,
+        }),
+        &body,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::Provenance;
+    use chrono::Utc;
+
+    fn test_agent(name: &str, readme: Option<&str>) -> Agent {
+        Agent {
+            name: name.to_string(),
+            version: "1.2.0".to_string(),
+            description: "A mirrored agent".to_string(),
+            author: "someone".to_string(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            download_count: 0,
+            star_count: 0,
+            rating_count: 0,
+            rating_average: None,
+            tags: vec!["test".to_string()],
+            readme: readme.map(|s| s.to_string()),
+            homepage: None,
+            repository: None,
+            license: Some("MIT".to_string()),
+            signature_bundle: None,
+            is_public: true,
+            is_template: false,
+            verified_namespace: None,
+            verified: false,
+        }
+    }
+
+    fn test_info(agent: &Agent, versions: &[&str]) -> AgentInfo {
+        AgentInfo {
+            name: agent.name.clone(),
+            version: agent.version.clone(),
+            versions: versions.iter().map(|v| v.to_string()).collect(),
+            description: agent.description.clone(),
+            author: agent.author.clone(),
+            created_at: agent.created_at,
+            updated_at: agent.updated_at,
+            download_count: 0,
+            star_count: 0,
+            rating_count: 0,
+            rating_average: None,
+            tags: agent.tags.clone(),
+            homepage: None,
+            repository: None,
+            license: agent.license.clone(),
+            signed: false,
+            transparency_log_index: None,
+            signed_at: None,
+            provenance: Some(Provenance {
+                git_commit: Some("abc123".to_string()),
+                ..Default::default()
+            }),
+            is_public: true,
+            verified_namespace: None,
+            verified: false,
+        }
+    }
+
+    #[test]
+    fn test_is_selected() {
+        let options = MirrorOptions {
+            include: vec!["data-*".to_string()],
+            exclude: vec!["*-internal".to_string()],
+            interval: None,
+        };
+
+        assert!(is_selected("data-cleaner", &options));
+        assert!(!is_selected("data-internal", &options));
+        assert!(!is_selected("code-reviewer", &options));
+        assert!(is_selected("anything", &MirrorOptions::default()));
+    }
+
+    #[test]
+    fn test_mirror_content_reuses_matching_readme() {
+        let readme = "---\nname: test-agent\ndescription: A mirrored agent\n---\n\n# Body\n";
+        let agent = test_agent("test-agent", Some(readme));
+
+        assert_eq!(mirror_content(&agent).unwrap(), readme);
+    }
+
+    #[test]
+    fn test_mirror_content_synthesizes_frontmatter() {
+        let agent = test_agent("test-agent", Some("# Just a README"));
+        let content = mirror_content(&agent).unwrap();
+        let (frontmatter, body) = parse_frontmatter(&content).unwrap();
+
+        assert_eq!(frontmatter["name"], "test-agent");
+        assert_eq!(frontmatter["description"], "A mirrored agent");
+        assert!(body.contains("# Just a README"));
+    }
+
+    #[test]
+    fn test_upload_request_recreates_each_version() {
+        let agent = test_agent("test-agent", Some("# Latest"));
+        let info = test_info(&agent, &["1.0.0", "1.2.0"]);
+        let definition = serde_json::json!({
+            "metadata": {
+                "name": "test-agent",
+                "description": "An older description",
+                "dependencies": { "helper": "^1" }
+            },
+            "content": "# First release\n"
+        });
+
+        let older = build_upload_request(&agent, &info, "1.0.0", &definition).unwrap();
+        assert_eq!(older.version.as_deref(), Some("1.0.0"));
+        assert_eq!(older.description, "An older description");
+        assert_eq!(older.dependencies["helper"], "^1");
+        assert!(older.content.ends_with("# First release\n"));
+        assert_eq!(older.provenance, None);
+
+        let latest = build_upload_request(&agent, &info, "1.2.0", &definition).unwrap();
+        assert_eq!(latest.provenance, info.provenance);
+    }
+
+    #[test]
+    fn test_upload_request_needs_a_definition_for_older_versions() {
+        let agent = test_agent("test-agent", Some("# Latest"));
+        let info = test_info(&agent, &["1.0.0", "1.2.0"]);
+        let definition = serde_json::json!({});
+
+        assert!(build_upload_request(&agent, &info, "1.0.0", &definition).is_err());
+        let latest = build_upload_request(&agent, &info, "1.2.0", &definition).unwrap();
+        assert!(latest.content.contains("# Latest"));
+    }
+
+    #[tokio::test]
+    async fn test_versions_already_in_the_target_count_as_mirrored() {
+        let mut server = mockito::Server::new_async().await;
+        let config = crate::config::Config {
+            registry_url: server.url(),
+            api_key: Some("test-token".to_string()),
+            ..Default::default()
+        };
+        let client = ApiClient::new(&config).unwrap();
+        let agent = test_agent("test-agent", None);
+        let info = test_info(&agent, &["1.0.0", "1.2.0"]);
+
+        server
+            .mock("GET", "/api/v1/agents/test-agent/1.0.0/download")
+            .match_query(mockito::Matcher::Any)
+            .with_header("content-type", "application/json")
+            .with_body(
+                serde_json::json!({
+                    "agent_id": "1", "name": "test-agent", "author": "someone",
+                    "version": "1.0.0", "download_url": "", "file_size": 0, "checksum": "",
+                    "content_type": "application/zip",
+                    "definition": {
+                        "metadata": { "name": "test-agent", "description": "A mirrored agent" },
+                        "content": "# Agent"
+                    }
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/api/v1/agents/upload")
+            .with_status(409)
+            .with_body(
+                r#"{"error": "version_exists", "message": "test-agent@1.0.0 has already been published", "details": null}"#,
+            )
+            .create_async()
+            .await;
+
+        let outcome = mirror_version(&client, &client, &agent, &info, "1.0.0")
+            .await
+            .unwrap();
+        assert_eq!(outcome, Mirrored::AlreadyThere);
+    }
+
+    #[test]
+    fn test_mirror_state_tracks_versions_per_source() {
+        let mut state = MirrorState::default();
+
+        assert!(!state.is_mirrored("https://a.example", "test-agent", "1.2.0"));
+        state.mark_mirrored("https://a.example", "test-agent", "1.2.0");
+        assert!(state.is_mirrored("https://a.example", "test-agent", "1.2.0"));
+        assert!(!state.is_mirrored("https://a.example", "test-agent", "1.0.0"));
+        assert!(!state.is_mirrored("https://b.example", "test-agent", "1.2.0"));
+    }
+}
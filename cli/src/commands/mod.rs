@@ -1,5 +1,33 @@
+pub mod add;
+pub mod author;
+pub mod claim;
+pub mod completions;
+pub mod config;
+pub mod diff;
+pub mod doctor;
+pub mod edit;
 pub mod healthcheck;
+pub mod index;
+pub mod info;
+pub mod install;
 pub mod list;
+pub mod mirror;
+pub mod new;
+pub mod outdated;
+pub mod package;
+pub mod publish_workspace;
 pub mod pull;
+pub mod remove;
+pub mod report;
+pub mod review;
 pub mod search;
+pub mod share;
+pub mod star;
+pub mod status;
+pub mod tags;
+pub mod telemetry;
+pub mod templates;
+pub mod token;
+pub mod undo;
 pub mod upload;
+pub mod watch;
@@ -0,0 +1,226 @@
+use crate::api::ApiClient;
+use crate::commands::install::download_package;
+use crate::commands::pull::{extract_package, parse_agent_spec};
+use crate::config::Config;
+use crate::utils::agent_dir;
+use crate::utils::cleanup::Guard;
+use crate::utils::error::{CarpError, CarpResult};
+use crate::utils::variables;
+use colored::*;
+use regex::{Captures, Regex};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use tracing::debug;
+
+/// `{{ key }}` placeholders in template files
+static PLACEHOLDER: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\{\{\s*([A-Za-z0-9_-]+)\s*\}\}").expect("valid placeholder pattern")
+});
+
+/// Options controlling `carp new`
+#[derive(Debug, Default)]
+pub struct NewOptions {
+    /// Template to start from, as `name` or `name@version`
+    pub template: Option<String>,
+    /// Directory to create the agent in, instead of the current directory
+    pub directory: Option<String>,
+    /// `KEY=VALUE` placeholder values, on top of `name`
+    pub vars: Vec<String>,
+    /// Replace files that already exist
+    pub force: bool,
+}
+
+/// Execute the new command: create an agent from a template, or a minimal
+/// agent file without one
+pub async fn execute(
+    client: &ApiClient,
+    config: &Config,
+    name: String,
+    options: NewOptions,
+    verbose: bool,
+) -> CarpResult<()> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(CarpError::InvalidAgent(format!(
+            "'{name}' isn't a valid agent name; use letters, digits, hyphens and underscores"
+        )));
+    }
+
+    let mut vars = BTreeMap::from([("name".to_string(), name.clone())]);
+    vars.extend(variables::parse_assignments(&options.vars)?);
+
+    let dir = match &options.directory {
+        Some(directory) => PathBuf::from(directory),
+        None => std::env::current_dir()?,
+    };
+
+    let Some(spec) = &options.template else {
+        let path = dir.join(format!("{name}.md"));
+        refuse_existing(&path, options.force)?;
+        fs::create_dir_all(&dir)?;
+        fs::write(
+            &path,
+            format!(
+                "---\nname: {name}\ndescription: Describe what {name} does\nversion: 0.1.0\n---\n\n# {name}\n"
+            ),
+        )?;
+        println!(
+            "{} Created {} at {}",
+            "✓".green().bold(),
+            name.blue().bold(),
+            path.display().to_string().cyan()
+        );
+        return Ok(());
+    };
+
+    let (template, version) = parse_agent_spec(spec)?;
+    let version = version.unwrap_or("latest");
+    println!(
+        "{} Fetching template {}...",
+        "⟳".blue().bold(),
+        template.blue().bold()
+    );
+    let (archive, declared, _) = download_package(client, &template, version, None).await?;
+    let staging =
+        Guard::new(std::env::temp_dir().join(format!("carp-template-{}", uuid::Uuid::new_v4())));
+    extract_package(config, archive.path(), declared, staging.path(), verbose)?;
+    drop(archive);
+
+    let (dest, unknown) = render(staging.path(), &dir, &template, &name, &vars, options.force)?;
+
+    println!(
+        "{} Created {} from template {} at {}",
+        "✓".green().bold(),
+        name.blue().bold(),
+        template.blue(),
+        dest.display().to_string().cyan()
+    );
+    if !unknown.is_empty() {
+        let unknown: Vec<String> = unknown.into_iter().collect();
+        println!(
+            "  {} no value for {}; pass --set KEY=VALUE to fill them in",
+            "Note:".yellow().bold(),
+            unknown.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+/// Write the extracted template in `source` into `dir` as the agent `name`,
+/// returning where it went and the placeholders left without a value
+///
+/// A template of one file becomes a single file in `dir`; larger ones get a
+/// directory named after the agent.
+fn render(
+    source: &Path,
+    dir: &Path,
+    template: &str,
+    name: &str,
+    vars: &BTreeMap<String, String>,
+    force: bool,
+) -> CarpResult<(PathBuf, BTreeSet<String>)> {
+    let files = agent_dir::files(source)?;
+    let single = matches!(files.as_slice(), [file] if file.parent() == Some(source));
+    let root = if single {
+        dir.to_path_buf()
+    } else {
+        dir.join(name)
+    };
+
+    let mut rendered = Vec::with_capacity(files.len());
+    let mut unknown = BTreeSet::new();
+    for path in &files {
+        let relative = path.strip_prefix(source).unwrap_or(path);
+        let target = root.join(substitute(
+            &relative.to_string_lossy(),
+            template,
+            vars,
+            &mut unknown,
+        ));
+        let contents = fs::read(path)?;
+        // Binary files are copied as they are
+        let contents = match String::from_utf8(contents) {
+            Ok(text) => substitute(&text, template, vars, &mut unknown).into_bytes(),
+            Err(e) => e.into_bytes(),
+        };
+        debug!("Rendering {} to {}", relative.display(), target.display());
+        rendered.push((target, contents));
+    }
+
+    let dest = match rendered.as_slice() {
+        [(target, _)] if single => target.clone(),
+        _ => root.clone(),
+    };
+    refuse_existing(&dest, force)?;
+    if !single {
+        agent_dir::remove(&root)?;
+    }
+    for (target, contents) in rendered {
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(target, contents)?;
+    }
+
+    Ok((dest, unknown))
+}
+
+/// Replace the template's name with the new agent's, then fill in `{{ key }}`
+/// placeholders, recording those without a value in `unknown`
+fn substitute(
+    text: &str,
+    template: &str,
+    vars: &BTreeMap<String, String>,
+    unknown: &mut BTreeSet<String>,
+) -> String {
+    let renamed = text.replace(template, &vars["name"]);
+    PLACEHOLDER
+        .replace_all(&renamed, |captures: &Captures| {
+            match vars.get(&captures[1]) {
+                Some(value) => value.clone(),
+                None => {
+                    unknown.insert(captures[1].to_string());
+                    captures[0].to_string()
+                }
+            }
+        })
+        .into_owned()
+}
+
+fn refuse_existing(path: &Path, force: bool) -> CarpResult<()> {
+    if path.exists() && !force {
+        return Err(CarpError::FileSystem(format!(
+            "'{}' already exists. Use --force to overwrite.",
+            path.display()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_substitute_renames_and_fills_placeholders() {
+        let vars = BTreeMap::from([
+            ("name".to_string(), "my-reviewer".to_string()),
+            ("language".to_string(), "Rust".to_string()),
+        ]);
+        let mut unknown = BTreeSet::new();
+        let text =
+            "---\nname: rust-reviewer\n---\n\n# {{ name }} reviews {{language}} for {{team}}\n";
+
+        assert_eq!(
+            substitute(text, "rust-reviewer", &vars, &mut unknown),
+            "---\nname: my-reviewer\n---\n\n# my-reviewer reviews Rust for {{team}}\n"
+        );
+        assert_eq!(unknown, BTreeSet::from(["team".to_string()]));
+    }
+}
@@ -0,0 +1,253 @@
+use crate::api::types::AgentInfo;
+use crate::api::ApiClient;
+use crate::utils::error::{CarpError, CarpResult};
+use crate::utils::workspace::{
+    parse_requirement, resolve, Lockfile, Workspace, LOCKFILE, MANIFEST_FILE,
+};
+use colored::*;
+use semver::{Version, VersionReq};
+use std::collections::HashMap;
+use tracing::debug;
+
+/// How a project agent's locked version compares with the registry
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Report {
+    name: String,
+    /// Version in the lockfile, if the agent is locked
+    current: Option<String>,
+    /// Newest version the manifest's requirement allows
+    wanted: Option<String>,
+    /// Newest version published
+    latest: String,
+}
+
+impl Report {
+    /// Unlocked agents count as outdated, since there's nothing installed to keep
+    fn is_outdated(&self) -> bool {
+        let Some(current) = &self.current else {
+            return true;
+        };
+        match (Version::parse(current), Version::parse(&self.latest)) {
+            (Ok(current), Ok(latest)) => latest > current,
+            _ => *current != self.latest,
+        }
+    }
+}
+
+/// Execute the outdated command: compare the versions `carp.lock` pins with
+/// the newest ones in the registry
+pub async fn execute(client: &ApiClient, ci: bool) -> CarpResult<()> {
+    let cwd = std::env::current_dir()?;
+    let root = Workspace::find_root(&cwd).ok_or_else(|| {
+        CarpError::ManifestError(format!(
+            "No {MANIFEST_FILE} found in {} or any parent directory",
+            cwd.display()
+        ))
+    })?;
+    let workspace = Workspace::load(&root)?;
+    let lockfile = Lockfile::load(&root)?;
+
+    // Agents from git have no registry versions to compare with
+    let agents: Vec<(&String, &str)> = workspace
+        .agents
+        .iter()
+        .filter_map(|(name, dependency)| Some((name, dependency.requirement()?)))
+        .collect();
+    let names: Vec<String> = agents.iter().map(|(name, _)| name.to_string()).collect();
+    debug!("Looking up {} agents...", names.len());
+    let batch = client.get_agents_info(&names).await?;
+    if let Some(name) = batch.missing.into_iter().next() {
+        return Err(CarpError::AgentNotFound {
+            name,
+            suggestions: Vec::new(),
+        });
+    }
+    let mut infos: HashMap<String, AgentInfo> = batch
+        .agents
+        .into_iter()
+        .map(|info| (info.name.clone(), info))
+        .collect();
+
+    let mut outdated = Vec::new();
+    for (name, requirement) in agents {
+        let requirement = parse_requirement(name, requirement)?;
+        debug!("Checking {name} ({requirement})...");
+
+        let info = infos.remove(name).ok_or_else(|| CarpError::AgentNotFound {
+            name: name.clone(),
+            suggestions: Vec::new(),
+        })?;
+        let report = compare(
+            name,
+            &requirement,
+            lockfile.get(name).map(|locked| locked.version.clone()),
+            &info.versions,
+            info.version,
+        );
+        if report.is_outdated() {
+            outdated.push(report);
+        }
+    }
+
+    if outdated.is_empty() {
+        println!(
+            "{} All {} agents in {} are up to date",
+            "✓".green().bold(),
+            names.len(),
+            MANIFEST_FILE
+        );
+        return Ok(());
+    }
+
+    print_table(&outdated, names.len());
+
+    if ci {
+        return Err(CarpError::Outdated(outdated.len()));
+    }
+    Ok(())
+}
+
+/// Work out an agent's wanted and latest versions from what the registry lists
+fn compare(
+    name: &str,
+    requirement: &VersionReq,
+    current: Option<String>,
+    versions: &[String],
+    latest: String,
+) -> Report {
+    // Registries that don't list versions only offer the latest
+    let versions = if versions.is_empty() {
+        std::slice::from_ref(&latest)
+    } else {
+        versions
+    };
+
+    let wanted = resolve(requirement, versions.iter().map(String::as_str)).map(str::to_string);
+    let latest = resolve(&VersionReq::STAR, versions.iter().map(String::as_str))
+        .map(str::to_string)
+        .unwrap_or(latest);
+
+    Report {
+        name: name.to_string(),
+        current,
+        wanted,
+        latest,
+    }
+}
+
+fn print_table(reports: &[Report], total: usize) {
+    const MISSING: &str = "-";
+    let headers = ["Agent", "Current", "Wanted", "Latest"];
+    let rows: Vec<[&str; 4]> = reports
+        .iter()
+        .map(|report| {
+            [
+                report.name.as_str(),
+                report.current.as_deref().unwrap_or(MISSING),
+                report.wanted.as_deref().unwrap_or(MISSING),
+                report.latest.as_str(),
+            ]
+        })
+        .collect();
+
+    let mut widths = headers.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let header = headers
+        .iter()
+        .zip(widths)
+        .map(|(header, width)| format!("{header:<width$}"))
+        .collect::<Vec<_>>()
+        .join("  ");
+    println!("{}", header.bold());
+
+    for (report, row) in reports.iter().zip(&rows) {
+        let [name, current, wanted, latest] = row.map(str::to_string);
+        let [name_width, current_width, wanted_width, _] = widths;
+
+        // Red when the requirement allows something newer than what's locked,
+        // yellow when only a new major (or otherwise excluded) version is out
+        let behind_wanted = report.wanted.is_some() && report.current != report.wanted;
+        let current = format!("{current:<current_width$}");
+        let current = if behind_wanted {
+            current.red()
+        } else {
+            current.yellow()
+        };
+
+        println!(
+            "{}  {}  {}  {}",
+            format!("{name:<name_width$}").blue(),
+            current,
+            format!("{wanted:<wanted_width$}").green(),
+            latest.magenta()
+        );
+    }
+
+    println!(
+        "\n{} of {} agents have newer versions than {} pins",
+        reports.len(),
+        total,
+        LOCKFILE
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn versions(versions: &[&str]) -> Vec<String> {
+        versions.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn test_compare_wanted_and_latest() {
+        let published = versions(&["1.0.0", "1.2.0", "2.0.0"]);
+        let requirement = VersionReq::parse("1").unwrap();
+
+        let report = compare(
+            "code-reviewer",
+            &requirement,
+            Some("1.0.0".to_string()),
+            &published,
+            "2.0.0".to_string(),
+        );
+        assert_eq!(report.wanted.as_deref(), Some("1.2.0"));
+        assert_eq!(report.latest, "2.0.0");
+        assert!(report.is_outdated());
+
+        let current = compare(
+            "code-reviewer",
+            &VersionReq::STAR,
+            Some("2.0.0".to_string()),
+            &published,
+            "2.0.0".to_string(),
+        );
+        assert!(!current.is_outdated());
+
+        // Ahead of latest, e.g. when the newest version was yanked
+        let ahead = compare(
+            "code-reviewer",
+            &requirement,
+            Some("1.3.0".to_string()),
+            &published[..2],
+            "1.2.0".to_string(),
+        );
+        assert!(!ahead.is_outdated());
+
+        // Unlocked agents are always reported
+        let unlocked = compare(
+            "code-reviewer",
+            &requirement,
+            None,
+            &[],
+            "1.2.0".to_string(),
+        );
+        assert_eq!(unlocked.wanted.as_deref(), Some("1.2.0"));
+        assert!(unlocked.is_outdated());
+    }
+}
@@ -0,0 +1,87 @@
+use crate::commands::upload::{
+    check_secrets, get_directory_path, run_pre_publish_hooks, scan_agent_files, select_agents,
+    DEFAULT_VERSION,
+};
+use crate::utils::archive::build_markdown_package;
+use crate::utils::cleanup;
+use crate::utils::error::{CarpError, CarpResult};
+use colored::*;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::PathBuf;
+use tracing::debug;
+
+/// Options controlling `carp package`
+#[derive(Debug, Default)]
+pub struct PackageOptions {
+    /// Directory to write archives to, instead of the current directory
+    pub out_dir: Option<String>,
+    /// Fingerprints of secret scanner findings known to be false positives
+    pub allowed_secrets: Vec<String>,
+    /// Build every archive a second time and fail unless the bytes match
+    pub verify_reproducible: bool,
+}
+
+/// Execute the package command: build the archive `carp upload` would
+/// publish for each agent and write it to disk
+///
+/// The registry packages uploaded agents the same way, so an archive built
+/// here has the checksum the published version will have.
+pub fn execute(directory: Option<String>, options: PackageOptions) -> CarpResult<()> {
+    let dir_path = get_directory_path(directory)?;
+    debug!("Scanning directory: {}", dir_path.display());
+
+    let agents = scan_agent_files(&dir_path)?;
+    if agents.is_empty() {
+        return Err(CarpError::InvalidAgent(format!(
+            "No agent files found in {}. Looking for .md files with YAML frontmatter containing name and description fields.",
+            dir_path.display()
+        )));
+    }
+    let agents = select_agents(agents)?.into_agents();
+
+    // Packaging runs the same steps as publishing, up to the upload
+    run_pre_publish_hooks(&dir_path)?;
+    check_secrets(&agents, &options.allowed_secrets)?;
+
+    let output = match options.out_dir {
+        Some(output) => PathBuf::from(output),
+        None => std::env::current_dir()?,
+    };
+    fs::create_dir_all(&output)?;
+
+    for agent in &agents {
+        let content = fs::read_to_string(&agent.path)?;
+        let package = build_markdown_package(&agent.name, &content)?;
+        let version = agent.version.as_deref().unwrap_or(DEFAULT_VERSION);
+        let path = output.join(format!("{}-{version}.zip", agent.name));
+        if options.verify_reproducible {
+            // Re-read the source so the check covers everything a later build sees
+            let rebuilt = build_markdown_package(&agent.name, &fs::read_to_string(&agent.path)?)?;
+            if rebuilt != package {
+                return Err(CarpError::InvalidAgent(format!(
+                    "Packaging {} isn't reproducible: two builds gave sha256:{:x} and sha256:{:x}",
+                    agent.name,
+                    Sha256::digest(&package),
+                    Sha256::digest(&rebuilt)
+                )));
+            }
+        }
+        cleanup::write_atomic(&path, &package)?;
+
+        println!(
+            "{} Packaged {} v{} to {} ({} bytes, sha256:{:x})",
+            "✓".green().bold(),
+            agent.name.blue().bold(),
+            version,
+            path.display().to_string().cyan(),
+            package.len(),
+            Sha256::digest(&package)
+        );
+        if options.verify_reproducible {
+            println!("  {} Rebuilt with identical bytes", "✓".green());
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,316 @@
+use crate::api::ApiClient;
+use crate::auth::AuthManager;
+use crate::commands::upload::{
+    self, check_secrets, is_unchanged, keyless_signer, parse_agent_file, preview_upload,
+    print_unchanged, run_pre_publish_hooks, upload_agent, AgentFile, UploadOptions,
+};
+use crate::utils::ci::{self, Level};
+use crate::utils::error::{CarpError, CarpResult};
+use crate::utils::manifest::{AgentManifest, Bump, WorkspaceSettings, MANIFEST_FILE};
+use colored::*;
+use semver::Version;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::PathBuf;
+use tracing::debug;
+
+/// An agent of the workspace, from its directory's `Carp.toml`
+#[derive(Debug)]
+struct Member {
+    manifest_path: PathBuf,
+    manifest: AgentManifest,
+    /// The markdown definition that's published
+    agent: AgentFile,
+}
+
+/// Execute `carp publish --workspace`: publish every agent of the workspace
+/// in `directory` whose content differs from its latest published version,
+/// dependencies first
+///
+/// Changed agents whose `Carp.toml` version is already published get the
+/// next version `bump` picks, falling back to the workspace's `bump`, which
+/// is written back to their `Carp.toml` once they're published.
+pub async fn execute(
+    client: &ApiClient,
+    directory: Option<String>,
+    bump: Option<Bump>,
+    options: UploadOptions,
+    verbose: bool,
+) -> CarpResult<()> {
+    let root = match directory {
+        Some(directory) => upload::get_directory_path(Some(directory))?,
+        None => std::env::current_dir()?,
+    };
+    if !options.dry_run {
+        AuthManager::ensure_authenticated(client.api_key()).await?;
+    }
+    let signer = keyless_signer(&options).await?;
+
+    let settings = WorkspaceSettings::load(&root)?;
+    let bump = bump.unwrap_or(settings.bump);
+    let members = dependency_order(read_members(&settings.member_dirs(&root)?)?)?;
+    if members.is_empty() {
+        println!(
+            "{} No agents found in {} (members: {})",
+            "Warning:".yellow().bold(),
+            root.display(),
+            settings.members.join(", ")
+        );
+        return Ok(());
+    }
+    debug!(
+        "Publishing workspace in {}: {}",
+        root.display(),
+        members
+            .iter()
+            .map(|member| member.manifest.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    // Hooks run before anything is packaged, in dry runs too
+    run_pre_publish_hooks(&root)?;
+    let agents: Vec<AgentFile> = members.iter().map(|m| m.agent.clone()).collect();
+    check_secrets(&agents, &options.allowed_secrets)?;
+
+    if options.dry_run {
+        for member in &members {
+            preview_upload(&member.agent, &options, client).inspect_err(|e| {
+                ci::annotate(Level::Error, &e.to_string(), Some(&member.agent.path))
+            })?;
+        }
+        println!(
+            "{} Dry run: {} workspace agent(s) valid, nothing was uploaded",
+            "✓".green().bold(),
+            members.len()
+        );
+        return Ok(());
+    }
+
+    let mut published = 0;
+    let mut unchanged = 0;
+    for member in &members {
+        let content = fs::read_to_string(&member.agent.path)?;
+        if is_unchanged(client, &member.agent, &content).await? {
+            print_unchanged(&member.agent);
+            unchanged += 1;
+            continue;
+        }
+
+        // Later members may depend on this one, so the first failure stops
+        let result = async {
+            let version = publish_version(client, member, bump).await?;
+            let agent = AgentFile {
+                version: Some(version.clone()),
+                ..member.agent.clone()
+            };
+            upload_agent(&agent, content, signer.as_ref(), &options, client, verbose).await?;
+            Ok::<_, CarpError>(version)
+        }
+        .await
+        .inspect_err(|e| ci::annotate(Level::Error, &e.to_string(), Some(&member.agent.path)))?;
+
+        if result != member.manifest.version {
+            AgentManifest::write_version(&member.manifest_path, &result)?;
+        }
+        println!(
+            "{} Published {} v{}",
+            "✓".green().bold(),
+            member.manifest.name.blue().bold(),
+            result
+        );
+        published += 1;
+    }
+
+    println!(
+        "\n{} Workspace published: {} published, {} unchanged",
+        "✓".green().bold(),
+        published.to_string().green().bold(),
+        unchanged
+    );
+    Ok(())
+}
+
+/// Read the manifest and markdown definition of each member directory
+///
+/// A member publishes its manifest's `main` file if that's markdown, and
+/// otherwise `<name>.md`, whose frontmatter must name the same agent.
+fn read_members(dirs: &[PathBuf]) -> CarpResult<Vec<Member>> {
+    let mut members: Vec<Member> = Vec::with_capacity(dirs.len());
+    for dir in dirs {
+        let manifest_path = dir.join(MANIFEST_FILE);
+        let manifest = AgentManifest::load(&manifest_path)
+            .map_err(|e| CarpError::ManifestError(format!("{}: {e}", manifest_path.display())))?;
+
+        let definition = match &manifest.main {
+            Some(main) if main.ends_with(".md") => dir.join(main),
+            _ => dir.join(format!("{}.md", manifest.name)),
+        };
+        let agent = parse_agent_file(&definition)
+            .map_err(|e| CarpError::ManifestError(format!("{}: {e}", definition.display())))?;
+        if agent.name != manifest.name {
+            return Err(CarpError::ManifestError(format!(
+                "{} names the agent '{}', but its {MANIFEST_FILE} names '{}'",
+                definition.display(),
+                agent.name,
+                manifest.name
+            )));
+        }
+        if let Some(other) = members.iter().find(|m| m.manifest.name == manifest.name) {
+            return Err(CarpError::ManifestError(format!(
+                "'{}' is declared by both {} and {}",
+                manifest.name,
+                other.manifest_path.display(),
+                manifest_path.display()
+            )));
+        }
+
+        let agent = AgentFile {
+            version: Some(manifest.version.clone()),
+            dependencies: manifest
+                .dependencies
+                .clone()
+                .unwrap_or_default()
+                .into_iter()
+                .collect(),
+            ..agent
+        };
+        members.push(Member {
+            manifest_path,
+            manifest,
+            agent,
+        });
+    }
+    Ok(members)
+}
+
+/// Sort members so each comes after the members it depends on, and otherwise
+/// by name
+///
+/// Dependencies on agents outside the workspace don't affect the order.
+fn dependency_order(members: Vec<Member>) -> CarpResult<Vec<Member>> {
+    let names: BTreeSet<String> = members.iter().map(|m| m.manifest.name.clone()).collect();
+    let mut pending: BTreeMap<String, (Member, BTreeSet<String>)> = members
+        .into_iter()
+        .map(|member| {
+            let dependencies = member
+                .manifest
+                .dependencies
+                .iter()
+                .flat_map(|dependencies| dependencies.keys())
+                .filter(|name| names.contains(*name) && **name != member.manifest.name)
+                .cloned()
+                .collect();
+            (member.manifest.name.clone(), (member, dependencies))
+        })
+        .collect();
+
+    let mut ordered = Vec::with_capacity(pending.len());
+    while !pending.is_empty() {
+        let ready: Vec<String> = pending
+            .iter()
+            .filter(|(_, (_, dependencies))| dependencies.is_empty())
+            .map(|(name, _)| name.clone())
+            .collect();
+        if ready.is_empty() {
+            let cycle: Vec<&str> = pending.keys().map(String::as_str).collect();
+            return Err(CarpError::ManifestError(format!(
+                "Workspace agents depend on each other in a cycle: {}",
+                cycle.join(", ")
+            )));
+        }
+
+        for name in ready {
+            if let Some((member, _)) = pending.remove(&name) {
+                ordered.push(member);
+            }
+            for (_, dependencies) in pending.values_mut() {
+                dependencies.remove(&name);
+            }
+        }
+    }
+    Ok(ordered)
+}
+
+/// The version to publish a changed member as: its manifest's if that's
+/// newer than everything published, otherwise the one `bump` picks
+async fn publish_version(client: &ApiClient, member: &Member, bump: Bump) -> CarpResult<String> {
+    let name = &member.manifest.name;
+    let local = &member.manifest.version;
+    // Yanked versions count too, since their numbers can't be reused
+    let published = match client.get_agent_versions(name).await {
+        Ok(response) => response.versions,
+        Err(CarpError::Api { status: 404, .. }) => return Ok(local.clone()),
+        Err(e) => return Err(e),
+    };
+    let Some(latest) = published
+        .iter()
+        .filter_map(|published| Version::parse(&published.version).ok())
+        .max()
+    else {
+        return Ok(local.clone());
+    };
+
+    if Version::parse(local).is_ok_and(|local| local > latest) {
+        return Ok(local.clone());
+    }
+    let next = bump
+        .next(&latest)
+        .ok_or_else(|| CarpError::VersionExists(format!("{name}@{local}")))?;
+    println!(
+        "{} Bumping '{}' to v{} (v{} is already published)",
+        "→".blue().bold(),
+        name.blue().bold(),
+        next,
+        latest
+    );
+    Ok(next.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn member(name: &str, dependencies: &[&str]) -> Member {
+        let mut manifest = AgentManifest::template(name);
+        manifest.dependencies = Some(
+            dependencies
+                .iter()
+                .map(|dependency| (dependency.to_string(), "*".to_string()))
+                .collect(),
+        );
+        Member {
+            manifest_path: Path::new(name).join(MANIFEST_FILE),
+            manifest,
+            agent: AgentFile {
+                path: Path::new(name).join(format!("{name}.md")),
+                name: name.to_string(),
+                description: String::new(),
+                version: None,
+                display_name: name.to_string(),
+                dependencies: BTreeMap::new(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_dependency_order() {
+        let members = vec![
+            member("alpha", &["gamma", "not-in-workspace"]),
+            member("beta", &[]),
+            member("gamma", &["beta"]),
+            member("delta", &[]),
+        ];
+        let ordered: Vec<_> = dependency_order(members)
+            .unwrap()
+            .into_iter()
+            .map(|member| member.manifest.name)
+            .collect();
+        assert_eq!(ordered, ["beta", "delta", "gamma", "alpha"]);
+
+        let cycle = vec![member("a", &["b"]), member("b", &["a"]), member("c", &[])];
+        let err = dependency_order(cycle).unwrap_err();
+        assert!(err.to_string().contains("cycle: a, b"), "{err}");
+    }
+}
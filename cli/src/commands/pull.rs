@@ -1,65 +1,137 @@
+use crate::api::oci::{OciClient, OciReference};
+use crate::api::prefetch::DownloadPrefetch;
+use crate::api::types::{Agent, AgentDownload};
 use crate::api::ApiClient;
-use crate::config::ConfigManager;
+use crate::commands::install::download_package;
+use crate::config::paths::{self, Kind};
+use crate::config::{Config, ConfigManager};
+use crate::utils::archive::{extract_archive, ArchiveFormat, ExtractLimits, ExtractProgress};
+use crate::utils::ci;
+use crate::utils::cleanup::Guard;
 use crate::utils::error::{CarpError, CarpResult};
+use crate::utils::git_source::{GitSource, GIT_PREFIX};
+use crate::utils::overwrite::Overwrite;
+use crate::utils::policy::{self, Policy};
+use crate::utils::resolution;
+use crate::utils::signing::verify_content;
+use crate::utils::trash;
+use crate::utils::workspace::{Lockfile, Workspace, LOCKFILE};
 use colored::*;
 use inquire::{InquireError, Select, Text};
 use std::fs;
-use std::path::PathBuf;
+use std::io::{BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+/// Most near-miss names offered when an agent isn't found
+const MAX_SUGGESTIONS: usize = 3;
+
+/// Options the interactive pickers show at once
+const PAGE_SIZE: usize = 15;
+
+/// Options controlling where and how an agent is pulled
+#[derive(Debug, Default)]
+pub struct PullOptions {
+    pub output: Option<String>,
+    /// What to do when the agent is already where it would be pulled to
+    pub overwrite: Overwrite,
+    pub required_issuer: Option<String>,
+    /// Extract the agent's package archive instead of writing its definition
+    pub package: bool,
+    /// Fetch the package through the API host instead of the storage host
+    pub via_api: bool,
+    /// Organization rules the agent must satisfy
+    pub policy: Option<Policy>,
+    /// Also pull every agent it depends on, as the registry's signed
+    /// resolution lists them
+    pub with_deps: bool,
+}
 
 /// Execute the pull command
 pub async fn execute(
+    client: &ApiClient,
+    config: &Config,
     agent: Option<String>,
-    output: Option<String>,
-    force: bool,
+    options: PullOptions,
     verbose: bool,
 ) -> CarpResult<()> {
-    let config = ConfigManager::load_with_env_checks()?;
-    let client = ApiClient::new(&config)?;
-
     // If no agent specified, show interactive selection
-    let agent_spec = match agent {
-        Some(spec) => spec,
+    let (agent_spec, prefetched) = match agent {
+        Some(spec) => (spec, None),
         None => {
-            if verbose {
-                println!("Fetching available agents for selection...");
-            }
-            interactive_agent_selection(&client).await?
+            ci::ensure_interactive("Choosing an agent", "pass the agent's name")?;
+            debug!("Fetching available agents for selection...");
+            // Only packages fetched from storage need the download info
+            let prefetch = options.package && !options.via_api && !options.with_deps;
+            interactive_agent_selection(client, prefetch).await?
         }
     };
 
+    if options.with_deps {
+        return pull_with_deps(client, config, &agent_spec, options, verbose).await;
+    }
+    if agent_spec.starts_with("oci://") {
+        return pull_oci(config, &agent_spec, options, verbose).await;
+    }
+    if agent_spec.starts_with(GIT_PREFIX) {
+        return pull_git(config, &agent_spec, options);
+    }
+
     let (name, version) = parse_agent_spec(&agent_spec)?;
 
-    if verbose {
-        println!(
-            "Pulling agent '{}'{}...",
-            name,
-            version.map(|v| format!(" version {v}")).unwrap_or_default()
-        );
-    }
+    debug!(
+        "Pulling agent '{}'{}...",
+        name,
+        version.map(|v| format!(" version {v}")).unwrap_or_default()
+    );
 
     // Get agent definition directly from search API
-    let agent_info = get_agent_definition(&client, &name, version).await?;
+    let agent_info = match get_agent_definition(client, &name, version).await {
+        Err(CarpError::AgentNotFound { name: spec, .. }) => {
+            return Err(CarpError::AgentNotFound {
+                name: spec,
+                suggestions: suggested_names(client, &name).await,
+            })
+        }
+        result => result?,
+    };
 
-    if verbose {
-        println!(
-            "Found {} v{} by {}",
-            agent_info.name, agent_info.version, agent_info.author
-        );
-    }
+    debug!(
+        "Found {} v{} by {}",
+        agent_info.name, agent_info.version, agent_info.author
+    );
 
-    // Determine output file path
-    let output_path = determine_output_file(&name, output, &config).await?;
+    // Enforce the policy and signing requirements before touching the filesystem
+    if let Some(policy) = &options.policy {
+        enforce_policy(policy, &agent_info).await?;
+        debug!("Agent satisfies the pull policy");
+    }
+    if let Some(issuer) = &options.required_issuer {
+        verify_agent_signature(&agent_info, issuer).await?;
+        debug!("Verified Sigstore signature from issuer {issuer}");
+    }
 
-    // Check if file exists and handle force flag
-    if output_path.exists() && !force {
-        return Err(CarpError::FileSystem(format!(
-            "File '{}' already exists. Use --force to overwrite.",
-            output_path.display()
-        )));
+    if options.package {
+        return pull_package(client, config, &agent_info, prefetched, options, verbose).await;
+    }
+    // A definition is written whole, so there are no files of it to merge
+    if options.overwrite == Overwrite::Merge {
+        return Err(CarpError::Config(
+            "--merge only applies to agent directories pulled with --package, oci:// or git+"
+                .to_string(),
+        ));
     }
 
+    // Determine output file path
+    let output_path = determine_output_file(&name, options.output, config).await?;
+    options.overwrite.check(&name, &output_path)?;
+
     // Create the agent definition content
     let agent_content = create_agent_definition_file(&agent_info)?;
+    if let Some(policy) = &options.policy {
+        let violation = policy.check_size(agent_content.len() as u64);
+        policy::enforce(&agent_info, violation.into_iter().collect())?;
+    }
 
     // Ensure the parent directory exists
     if let Some(parent) = output_path.parent() {
@@ -67,7 +139,17 @@ pub async fn execute(
     }
 
     // Write the agent definition file
-    fs::write(&output_path, agent_content)?;
+    let placed = options.overwrite.place_file(&output_path, &agent_content)?;
+    if !placed.kept.is_empty() {
+        println!(
+            "{} Skipped {} v{}: {} already exists",
+            "→".blue().bold(),
+            agent_info.name.blue().bold(),
+            agent_info.version,
+            output_path.display().to_string().cyan()
+        );
+        return Ok(());
+    }
 
     println!(
         "{} Successfully pulled {} v{} to {}",
@@ -76,6 +158,7 @@ pub async fn execute(
         agent_info.version,
         output_path.display().to_string().cyan()
     );
+    placed.report();
 
     // Show usage instructions
     println!("\nTo use this agent:");
@@ -89,7 +172,7 @@ pub async fn execute(
 }
 
 /// Parse agent specification (name or name@version)
-fn parse_agent_spec(spec: &str) -> CarpResult<(String, Option<&str>)> {
+pub fn parse_agent_spec(spec: &str) -> CarpResult<(String, Option<&str>)> {
     if let Some(at_pos) = spec.find('@') {
         let name = &spec[..at_pos];
         let version = &spec[at_pos + 1..];
@@ -107,7 +190,7 @@ fn parse_agent_spec(spec: &str) -> CarpResult<(String, Option<&str>)> {
 }
 
 /// Get agent definition directly from search API
-async fn get_agent_definition(
+pub(crate) async fn get_agent_definition(
     client: &ApiClient,
     name: &str,
     version: Option<&str>,
@@ -124,9 +207,9 @@ async fn get_agent_definition(
             .agents
             .into_iter()
             .find(|agent| agent.name == name)
-            .ok_or_else(|| CarpError::Api {
-                status: 404,
-                message: format!("Agent '{name}' not found"),
+            .ok_or_else(|| CarpError::AgentNotFound {
+                name: name.to_string(),
+                suggestions: Vec::new(),
             })
     } else {
         // Find exact version match
@@ -134,18 +217,525 @@ async fn get_agent_definition(
             .agents
             .into_iter()
             .find(|agent| agent.name == name && agent.version == target_version)
-            .ok_or_else(|| CarpError::Api {
-                status: 404,
-                message: format!("Agent '{name}' version '{target_version}' not found"),
+            .ok_or_else(|| CarpError::AgentNotFound {
+                name: format!("{name}@{target_version}"),
+                suggestions: Vec::new(),
             })
     }
 }
 
+/// Up to three agent names close to `name`, for a name that matched nothing
+///
+/// Empty when `name` itself exists, since then only the version was wrong,
+/// and when the registry can't suggest names: suggestions are a courtesy and
+/// never replace the original error.
+pub(crate) async fn suggested_names(client: &ApiClient, name: &str) -> Vec<String> {
+    let suggestions = match client.suggest(name, MAX_SUGGESTIONS + 1).await {
+        Ok(response) => response.suggestions,
+        Err(e) => {
+            debug!("No suggestions for '{name}': {e}");
+            return Vec::new();
+        }
+    };
+    if suggestions.iter().any(|s| s.name == name) {
+        return Vec::new();
+    }
+
+    suggestions
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|s| s.name)
+        .collect()
+}
+
+/// Check an agent against a policy, verifying its signature if one is required
+async fn enforce_policy(policy: &Policy, agent: &Agent) -> CarpResult<()> {
+    let mut violations = policy.check_agent(agent);
+
+    // Unsigned agents were already flagged by check_agent
+    if let (Some(issuer), Some(_)) = (policy.required_issuer(), &agent.signature_bundle) {
+        if let Err(e) = verify_agent_signature(agent, issuer).await {
+            violations.push(format!("signature does not verify: {e}"));
+        }
+    }
+
+    policy::enforce(agent, violations)
+}
+
+/// Verify that an agent was keyless-signed by an identity from the given OIDC issuer
+async fn verify_agent_signature(agent: &crate::api::types::Agent, issuer: &str) -> CarpResult<()> {
+    let bundle = agent.signature_bundle.as_ref().ok_or_else(|| {
+        CarpError::Signing(format!(
+            "Agent '{}' v{} is not signed",
+            agent.name, agent.version
+        ))
+    })?;
+
+    // The signature covers the content exactly as it was uploaded
+    let content = agent.readme.as_deref().unwrap_or_default();
+
+    verify_content(content, bundle, issuer).await
+}
+
+/// Download the agent's package archive and extract it into a directory,
+/// starting from download info prefetched while it was picked if there is some
+async fn pull_package(
+    client: &ApiClient,
+    config: &Config,
+    agent: &Agent,
+    prefetched: Option<AgentDownload>,
+    options: PullOptions,
+    verbose: bool,
+) -> CarpResult<()> {
+    let PullOptions {
+        output,
+        overwrite,
+        via_api,
+        policy,
+        ..
+    } = options;
+
+    let dest = match output {
+        Some(output) => expand_tilde(&output),
+        None => get_default_agents_dir(config)?.join(&agent.name),
+    };
+    overwrite.check(&agent.name, &dest)?;
+
+    let (download, inline_package, counted) = if via_api {
+        let (download, package) = client
+            .download_package_via_api(&agent.name, Some(&agent.version))
+            .await?;
+        (download, Some(package), true)
+    } else if let Some(download) = prefetched {
+        debug!("Using the download info prefetched while picking");
+        (download, None, false)
+    } else {
+        let download = client
+            .get_agent_download(&agent.name, Some(&agent.version))
+            .await?;
+        (download, None, true)
+    };
+
+    // Refuse oversized packages on their declared size before fetching them,
+    // then again on the bytes actually received below
+    if let Some(policy) = &policy {
+        let violation = policy.check_size(download.file_size);
+        policy::enforce(agent, violation.into_iter().collect())?;
+    }
+
+    // The registry doesn't count prefetches, so count this pull while the
+    // package downloads rather than before
+    let count = async {
+        if !counted {
+            if let Err(e) = client
+                .get_agent_download(&agent.name, Some(&agent.version))
+                .await
+            {
+                debug!("Failed to count the download of {}: {e}", agent.name);
+            }
+        }
+    };
+    let (saved, ()) = tokio::join!(save_package(client, &download, inline_package), count);
+    let (archive, declared) = saved?;
+
+    if let Some(policy) = &policy {
+        let violation = policy.check_size(fs::metadata(archive.path())?.len());
+        policy::enforce(agent, violation.into_iter().collect())?;
+    }
+
+    let staging = Guard::new(staging_path(&dest));
+    let progress = extract_package(config, archive.path(), declared, staging.path(), verbose)?;
+    let placed = overwrite.place_dir(staging.path(), &dest)?;
+
+    println!(
+        "{} Successfully pulled {} v{} ({} files) to {}",
+        "✓".green().bold(),
+        agent.name.blue().bold(),
+        agent.version,
+        progress.entries,
+        dest.display().to_string().cyan()
+    );
+    placed.report();
+
+    Ok(())
+}
+
+/// Pull an agent and everything it depends on into `<dir>/<name>` each,
+/// exactly as the registry's signed resolution lists them
+///
+/// Each package must match the checksum the resolution signed for it, and the
+/// resolution must be signed with the key the user trusts: the configured
+/// `security.resolution_key`, else the one `carp.lock` recorded. With neither,
+/// the key is trusted on first use and recorded in `carp.lock` inside a
+/// project, or as `security.resolution_key` outside one.
+async fn pull_with_deps(
+    client: &ApiClient,
+    config: &Config,
+    spec: &str,
+    options: PullOptions,
+    verbose: bool,
+) -> CarpResult<()> {
+    if spec.starts_with("oci://") || spec.starts_with(GIT_PREFIX) {
+        return Err(CarpError::Config(
+            "--with-deps only applies to agents from the registry".to_string(),
+        ));
+    }
+    let (name, requirement) = parse_agent_spec(spec)?;
+    let requirement = requirement.unwrap_or("latest");
+
+    let project = Workspace::find_root(&std::env::current_dir()?);
+    let lockfile = match &project {
+        Some(root) => Lockfile::load(root)?,
+        None => Lockfile::default(),
+    };
+    let trusted = resolution::trusted_key(
+        config.security.resolution_key.as_deref(),
+        &lockfile.resolutions,
+    )?
+    .map(str::to_string);
+
+    let signed = client.resolve(&name, Some(requirement)).await?;
+    let manifest = resolution::verify(&signed, &name, requirement, trusted.as_deref())?;
+    if trusted.is_none() {
+        // Outside a project there's no carp.lock to remember it, and a key
+        // nobody remembers would let any mirror sign its own resolutions
+        if project.is_none() {
+            ConfigManager::set_resolution_key(&signed.public_key)?;
+        }
+        println!(
+            "{} Trusting registry key {} from now on{}",
+            "→".blue().bold(),
+            signed.public_key.cyan(),
+            if project.is_none() {
+                " (saved as security.resolution_key)"
+            } else {
+                ""
+            }
+        );
+    }
+    debug!(
+        "Resolved {name}@{requirement} to {} agents",
+        manifest.agents.len()
+    );
+
+    // Check every agent before anything is written
+    let dir = match &options.output {
+        Some(output) => expand_tilde(output),
+        None => get_default_agents_dir(config)?,
+    };
+    for agent in &manifest.agents {
+        options
+            .overwrite
+            .check(&agent.name, &dir.join(&agent.name))?;
+        if options.policy.is_none() && options.required_issuer.is_none() {
+            continue;
+        }
+        let info = get_agent_definition(client, &agent.name, Some(&agent.version)).await?;
+        if let Some(policy) = &options.policy {
+            enforce_policy(policy, &info).await?;
+        }
+        if let Some(issuer) = &options.required_issuer {
+            verify_agent_signature(&info, issuer).await?;
+        }
+    }
+
+    for agent in &manifest.agents {
+        let dest = dir.join(&agent.name);
+        let (archive, declared, _) =
+            download_package(client, &agent.name, &agent.version, Some(&agent.checksum)).await?;
+        let staging = Guard::new(staging_path(&dest));
+        let progress = extract_package(config, archive.path(), declared, staging.path(), verbose)?;
+        let placed = options.overwrite.place_dir(staging.path(), &dest)?;
+
+        println!(
+            "{} Pulled {} v{} ({} files) to {}",
+            "✓".green().bold(),
+            agent.name.blue().bold(),
+            agent.version,
+            progress.entries,
+            dest.display().to_string().cyan()
+        );
+        placed.report();
+    }
+
+    if let Some(root) = project {
+        let locked = resolution::lock(signed, &manifest);
+        Lockfile::update(&root, |lockfile| lockfile.set_resolution(locked))?;
+        debug!(
+            "Recorded the resolution in {}",
+            root.join(LOCKFILE).display()
+        );
+    }
+    println!(
+        "{} Successfully pulled {} and {} dependencies",
+        "✓".green().bold(),
+        name.blue().bold(),
+        manifest.agents.len() - 1
+    );
+
+    Ok(())
+}
+
+/// Pull an agent artifact from an OCI registry and extract its package
+///
+/// OCI artifacts carry no signature or registry metadata, so the policy and
+/// signing options can't be checked and are refused rather than ignored.
+async fn pull_oci(
+    config: &Config,
+    spec: &str,
+    options: PullOptions,
+    verbose: bool,
+) -> CarpResult<()> {
+    if options.policy.is_some() || options.required_issuer.is_some() || options.via_api {
+        return Err(CarpError::Config(
+            "--policy, --identity and --via-api can't be used with oci:// references".to_string(),
+        ));
+    }
+
+    let reference = OciReference::parse(spec)?;
+    let client = OciClient::new(config)?;
+    debug!("Resolving {reference}...");
+    let artifact = client.resolve(&reference).await?;
+
+    // The config names the agent; the repository is a fallback for artifacts
+    // pushed by other tools
+    let name = match artifact.config.name.as_str() {
+        "" => reference
+            .repository
+            .rsplit('/')
+            .next()
+            .unwrap_or(&reference.repository),
+        name => name,
+    };
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(CarpError::InvalidAgent(format!(
+            "{reference} names its agent '{name}', which isn't a valid agent name"
+        )));
+    }
+    let version = match artifact.config.version.as_str() {
+        "" => reference.reference.as_str(),
+        version => version,
+    };
+
+    let dest = match options.output {
+        Some(output) => expand_tilde(&output),
+        None => get_default_agents_dir(config)?.join(name),
+    };
+    options.overwrite.check(name, &dest)?;
+
+    let packages_dir = ConfigManager::cache_dir()?.join("packages");
+    fs::create_dir_all(&packages_dir)?;
+    let archive = Guard::locked(packages_dir.join(format!(
+        "oci-{}.{}",
+        artifact.layer.digest.trim_start_matches("sha256:"),
+        artifact.format.extension()
+    )))?;
+    debug!(
+        "Downloading package ({} bytes) to {}...",
+        artifact.layer.size,
+        archive.path().display()
+    );
+    client
+        .download_layer(&reference, &artifact.layer, archive.path())
+        .await?;
+
+    let staging = Guard::new(staging_path(&dest));
+    let progress = extract_package(
+        config,
+        archive.path(),
+        artifact.format,
+        staging.path(),
+        verbose,
+    )?;
+    let placed = options.overwrite.place_dir(staging.path(), &dest)?;
+
+    println!(
+        "{} Successfully pulled {} v{} ({} files) from {} to {}",
+        "✓".green().bold(),
+        name.blue().bold(),
+        version,
+        progress.entries,
+        reference.registry,
+        dest.display().to_string().cyan()
+    );
+    placed.report();
+
+    Ok(())
+}
+
+/// Check out an agent from a git repository, validate it and copy its files
+///
+/// Agents in git haven't been published, so like OCI artifacts they have no
+/// signature or registry metadata for the policy and signing options.
+fn pull_git(config: &Config, spec: &str, options: PullOptions) -> CarpResult<()> {
+    if options.policy.is_some() || options.required_issuer.is_some() || options.via_api {
+        return Err(CarpError::Config(
+            "--policy, --identity and --via-api can't be used with git+ sources".to_string(),
+        ));
+    }
+
+    let source = GitSource::parse(spec)?;
+    debug!("Checking out {source}...");
+    let checkout = source.checkout(None)?;
+    let agent = checkout.agent()?;
+
+    let dest = match options.output {
+        Some(output) => expand_tilde(&output),
+        None => get_default_agents_dir(config)?.join(&agent.name),
+    };
+    options.overwrite.check(&agent.name, &dest)?;
+    let staging = Guard::new(staging_path(&dest));
+    let files = checkout.install(staging.path())?;
+    let placed = options.overwrite.place_dir(staging.path(), &dest)?;
+
+    println!(
+        "{} Successfully pulled {} v{} ({} files) from {} at {} to {}",
+        "✓".green().bold(),
+        agent.name.blue().bold(),
+        agent.version,
+        files,
+        source.url,
+        &checkout.commit[..checkout.commit.len().min(12)],
+        dest.display().to_string().cyan()
+    );
+    placed.report();
+
+    Ok(())
+}
+
+/// Save an agent's package into the cache, returning where it was written
+/// and the format the registry declared for it
+///
+/// The archive is removed when the returned guard is dropped. Until then
+/// other carp processes saving the same package wait for it.
+pub(crate) async fn save_package(
+    client: &ApiClient,
+    download: &AgentDownload,
+    inline_package: Option<Vec<u8>>,
+) -> CarpResult<(Guard, ArchiveFormat)> {
+    let packages_dir = ConfigManager::cache_dir()?.join("packages");
+    fs::create_dir_all(&packages_dir)?;
+    let declared = ArchiveFormat::from_content_type(&download.content_type).ok_or_else(|| {
+        CarpError::InvalidAgent(format!(
+            "Unsupported package type '{}'",
+            download.content_type
+        ))
+    })?;
+    let archive = Guard::locked(packages_dir.join(format!(
+        "{}-{}.{}",
+        download.name,
+        download.version,
+        declared.extension()
+    )))?;
+
+    if let Some(package) = inline_package {
+        debug!(
+            "Received package ({} bytes) through the API, saving to {}...",
+            package.len(),
+            archive.path().display()
+        );
+        fs::write(archive.path(), package)?;
+    } else {
+        debug!(
+            "Downloading package ({} bytes) to {}...",
+            download.file_size,
+            archive.path().display()
+        );
+        client
+            .download_to_file(&download.download_url, archive.path())
+            .await?;
+    }
+
+    Ok((archive, declared))
+}
+
+/// Where a pulled agent is assembled before it's placed in `dest`
+fn staging_path(dest: &Path) -> PathBuf {
+    let name = dest
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    dest.with_file_name(format!(".{name}.pulling"))
+}
+
+/// Extract a saved package into `dest`, replacing whatever is there
+pub(crate) fn extract_package(
+    config: &Config,
+    archive: &Path,
+    declared: ArchiveFormat,
+    dest: &Path,
+    verbose: bool,
+) -> CarpResult<ExtractProgress> {
+    // Extract beside the destination and only swap it in once every entry
+    // has passed validation, so a rejected or interrupted extraction leaves
+    // nothing behind
+    let name = dest
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let staging = dest.with_file_name(format!(".{name}.extracting"));
+    if staging.exists() {
+        fs::remove_dir_all(&staging)?;
+    }
+    let staging = Guard::new(staging);
+
+    let limits = ExtractLimits::from(&config.security);
+    let result = open_package(archive, declared).and_then(|(format, file)| {
+        extract_archive(format, file, staging.path(), &limits, |path, progress| {
+            if verbose {
+                println!("  {} {}", "extracted".dimmed(), path.display());
+            } else if !ci::enabled() {
+                print!(
+                    "\r{} Extracting... {} files, {} bytes",
+                    "⟳".blue().bold(),
+                    progress.entries,
+                    progress.bytes
+                );
+                let _ = std::io::stdout().flush();
+            }
+        })
+    });
+    if !verbose && !ci::enabled() {
+        println!();
+    }
+
+    let progress = result?;
+
+    trash::discard(dest)?;
+    fs::rename(staging.path(), dest)?;
+    staging.keep();
+
+    Ok(progress)
+}
+
+/// Open a downloaded package, checking its contents match the declared format
+fn open_package(
+    path: &Path,
+    declared: ArchiveFormat,
+) -> CarpResult<(ArchiveFormat, BufReader<fs::File>)> {
+    let mut header = [0u8; 4];
+    let read = fs::File::open(path)?.read(&mut header)?;
+
+    match ArchiveFormat::detect(&header[..read]) {
+        Some(format) if format == declared => Ok((format, BufReader::new(fs::File::open(path)?))),
+        detected => Err(CarpError::InvalidAgent(format!(
+            "Package was served as {} but {}",
+            declared.extension(),
+            detected
+                .map(|format| format!("contains {}", format.extension()))
+                .unwrap_or_else(|| "is not a recognised archive".to_string())
+        ))),
+    }
+}
+
 /// Determine the output file path for the agent definition
 async fn determine_output_file(
     name: &str,
     output: Option<String>,
-    config: &crate::config::Config,
+    config: &Config,
 ) -> CarpResult<PathBuf> {
     if let Some(output_path) = output {
         let path = expand_tilde(&output_path);
@@ -166,6 +756,11 @@ async fn determine_output_file(
 
     let default_path = default_agents_dir.join(format!("{name}.md"));
 
+    // Nobody can answer in CI, so take the default
+    if ci::enabled() {
+        return Ok(default_path);
+    }
+
     let file_path = Text::new(&prompt_text)
         .with_default(&default_path.to_string_lossy())
         .with_help_message("Enter the full path where you want to save the agent definition file")
@@ -207,16 +802,19 @@ fn expand_tilde(path: &str) -> PathBuf {
 }
 
 /// Get the default agents directory
-fn get_default_agents_dir(config: &crate::config::Config) -> CarpResult<PathBuf> {
+pub(crate) fn get_default_agents_dir(config: &Config) -> CarpResult<PathBuf> {
     if let Some(default_dir) = &config.default_output_dir {
         return Ok(PathBuf::from(default_dir));
     }
 
-    // Use ~/.config/carp/agents/ as default
-    let config_dir = dirs::config_dir()
-        .ok_or_else(|| CarpError::Config("Unable to find config directory".to_string()))?;
+    // Agents used to be pulled into the config directory; keep using it
+    // while it's there so they aren't split across two places
+    let legacy_dir = paths::resolve(Kind::Config)?.dir.join("agents");
+    if legacy_dir.is_dir() {
+        return Ok(legacy_dir);
+    }
 
-    let agents_dir = config_dir.join("carp").join("agents");
+    let agents_dir = ConfigManager::data_dir()?.join("agents");
     Ok(agents_dir)
 }
 
@@ -309,8 +907,13 @@ fn create_agent_definition_file(agent: &crate::api::types::Agent) -> CarpResult<
     Ok(content)
 }
 
-/// Interactive agent selection using inquire
-async fn interactive_agent_selection(client: &ApiClient) -> CarpResult<String> {
+/// Interactive agent selection using inquire, returning the picked
+/// `name@version` and, with `prefetch`, its download info if it was looked
+/// up while the user chose
+async fn interactive_agent_selection(
+    client: &ApiClient,
+    prefetch: bool,
+) -> CarpResult<(String, Option<AgentDownload>)> {
     // Step 1: Get unique agent names
     let agent_names = get_unique_agent_names(client).await?;
 
@@ -327,21 +930,32 @@ async fn interactive_agent_selection(client: &ApiClient) -> CarpResult<String> {
         agent_names.len()
     );
 
-    // Step 2: Let user select agent name
-    let selected_agent = Select::new("Select an agent:", agent_names.clone())
-        .with_page_size(15)
-        .with_help_message("↑/↓ to navigate • Enter to select • Ctrl+C to cancel")
-        .prompt()
-        .map_err(|e| match e {
-            InquireError::OperationCanceled => CarpError::Api {
-                status: 0,
-                message: "Operation cancelled by user.".to_string(),
-            },
-            _ => CarpError::Api {
-                status: 500,
-                message: format!("Selection error: {e}"),
-            },
-        })?;
+    // Look up the download info of the first page while the user reads it
+    let mut prefetch =
+        prefetch.then(|| DownloadPrefetch::start(client, agent_names.iter().take(PAGE_SIZE)));
+
+    // Step 2: Let user select agent name. The prompt blocks its thread, so
+    // it gets one of its own to leave the prefetches running.
+    let selected_agent = tokio::task::block_in_place(|| {
+        Select::new("Select an agent:", agent_names.clone())
+            .with_page_size(PAGE_SIZE)
+            .with_help_message("↑/↓ to navigate • Enter to select • Ctrl+C to cancel")
+            .prompt()
+    })
+    .map_err(|e| match e {
+        InquireError::OperationCanceled => CarpError::Api {
+            status: 0,
+            message: "Operation cancelled by user.".to_string(),
+        },
+        _ => CarpError::Api {
+            status: 500,
+            message: format!("Selection error: {e}"),
+        },
+    })?;
+
+    if let Some(prefetch) = &mut prefetch {
+        prefetch.pick(&selected_agent);
+    }
 
     // Step 3: Get versions for selected agent
     let versions = get_agent_versions(client, &selected_agent).await?;
@@ -368,7 +982,7 @@ async fn interactive_agent_selection(client: &ApiClient) -> CarpResult<String> {
             &format!("Select a version for {}:", selected_agent.blue().bold()),
             versions.clone(),
         )
-        .with_page_size(15)
+        .with_page_size(PAGE_SIZE)
         .with_help_message("↑/↓ to navigate • Enter to select • Ctrl+C to cancel")
         .prompt()
         .map_err(|e| match e {
@@ -397,7 +1011,11 @@ async fn interactive_agent_selection(client: &ApiClient) -> CarpResult<String> {
         display_agent_definition(&agent_info);
     }
 
-    Ok(format!("{selected_agent}@{selected_version}"))
+    let prefetched = match prefetch {
+        Some(prefetch) => prefetch.take(&selected_agent, &selected_version).await,
+        None => None,
+    };
+    Ok((format!("{selected_agent}@{selected_version}"), prefetched))
 }
 
 /// Get unique agent names from the registry
@@ -0,0 +1,66 @@
+use crate::utils::error::{CarpError, CarpResult};
+use crate::utils::trash;
+use crate::utils::workspace::{Lockfile, ManifestEditor, Workspace, LOCKFILE, MANIFEST_FILE};
+use colored::*;
+use std::fs;
+
+/// Execute the remove command: drop agents from `carp.toml` and `carp.lock`
+/// and delete their installed files
+pub fn execute(names: Vec<String>, keep_files: bool) -> CarpResult<()> {
+    let cwd = std::env::current_dir()?;
+    let root = Workspace::find_root(&cwd).ok_or_else(|| {
+        CarpError::ManifestError(format!(
+            "No {MANIFEST_FILE} found in {} or any parent directory",
+            cwd.display()
+        ))
+    })?;
+    let workspace = Workspace::load(&root)?;
+
+    // Check every name before changing anything
+    let mut dependencies = Vec::with_capacity(names.len());
+    for name in &names {
+        let dependency = workspace.agents.get(name).ok_or_else(|| {
+            CarpError::ManifestError(format!("'{name}' is not listed in {MANIFEST_FILE}"))
+        })?;
+        dependencies.push((name, dependency));
+    }
+
+    let mut editor = ManifestEditor::open(&root)?;
+    trash::preserve(&root.join(MANIFEST_FILE))?;
+    trash::preserve(&root.join(LOCKFILE))?;
+    for (name, _) in &dependencies {
+        editor.remove_agent(name)?;
+    }
+    editor.save()?;
+    Lockfile::update(&root, |lockfile| {
+        for (name, _) in &dependencies {
+            lockfile.remove(name);
+        }
+    })?;
+
+    for (name, dependency) in dependencies {
+        let dest = workspace.install_dir(&root, name, dependency);
+        // Path agents are symlinks, so look at the link rather than its target
+        let deleted = !keep_files && fs::symlink_metadata(&dest).is_ok();
+        if deleted {
+            trash::discard(&dest)?;
+        }
+
+        println!(
+            "{} Removed {} from {}{}",
+            "✓".green().bold(),
+            name.blue().bold(),
+            MANIFEST_FILE,
+            if deleted {
+                format!(
+                    " and deleted {}",
+                    dest.strip_prefix(&cwd).unwrap_or(&dest).display()
+                )
+            } else {
+                String::new()
+            }
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,46 @@
+use crate::api::{ApiClient, ReportReason, ReportRequest};
+use crate::utils::error::{CarpError, CarpResult};
+use colored::*;
+use tracing::debug;
+
+/// Longest details accepted by the registry
+const MAX_DETAILS_LENGTH: usize = 2000;
+
+/// Execute the report command
+pub async fn execute(
+    client: &ApiClient,
+    name: String,
+    reason: ReportReason,
+    details: Option<String>,
+) -> CarpResult<()> {
+    let details = details.unwrap_or_default().trim().to_string();
+
+    if reason == ReportReason::Other && details.is_empty() {
+        return Err(CarpError::InvalidAgent(
+            "--details is required when the reason is 'other'".to_string(),
+        ));
+    }
+    if details.chars().count() > MAX_DETAILS_LENGTH {
+        return Err(CarpError::InvalidAgent(format!(
+            "--details cannot exceed {MAX_DETAILS_LENGTH} characters"
+        )));
+    }
+
+    debug!("Reporting '{name}' ({reason:?})...");
+
+    let request = ReportRequest { reason, details };
+    let response = client.report(&name, &request).await?;
+
+    let mark = if response.created {
+        "✓".green().bold()
+    } else {
+        "•".yellow().bold()
+    };
+    println!(
+        "{mark} Reported {}: {}",
+        response.name.bold(),
+        response.message
+    );
+
+    Ok(())
+}
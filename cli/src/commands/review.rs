@@ -0,0 +1,137 @@
+use crate::api::{ApiClient, Review, ReviewRequest};
+use crate::utils::error::{CarpError, CarpResult};
+use colored::*;
+use tracing::debug;
+
+/// What `carp review` should do
+#[derive(Debug)]
+pub enum ReviewAction {
+    /// Show the agent's rating and recent reviews
+    List { limit: Option<usize> },
+    /// Create or replace your review
+    Write { rating: u8, message: Option<String> },
+    /// Delete your review
+    Delete,
+}
+
+/// Execute the review command
+pub async fn execute(client: &ApiClient, name: String, action: ReviewAction) -> CarpResult<()> {
+    match action {
+        ReviewAction::List { limit } => list(client, &name, limit).await,
+        ReviewAction::Write { rating, message } => {
+            if !(1..=5).contains(&rating) {
+                return Err(CarpError::InvalidAgent(
+                    "--rating must be between 1 and 5".to_string(),
+                ));
+            }
+
+            debug!("Reviewing '{name}' with {rating} stars...");
+            let request = ReviewRequest {
+                rating,
+                body: message.unwrap_or_default(),
+            };
+            let review = client.put_review(&name, &request).await?;
+
+            println!(
+                "{} Reviewed {} {}",
+                "✓".green().bold(),
+                name.bold(),
+                stars(review.rating).yellow()
+            );
+            if review.status == "pending" {
+                println!(
+                    "  {}",
+                    "Your review is being held for moderation and will appear once approved."
+                        .yellow()
+                );
+            }
+            Ok(())
+        }
+        ReviewAction::Delete => {
+            debug!("Deleting review of '{name}'...");
+            client.delete_review(&name).await?;
+            println!(
+                "{} Deleted your review of {}",
+                "✓".green().bold(),
+                name.bold()
+            );
+            Ok(())
+        }
+    }
+}
+
+async fn list(client: &ApiClient, name: &str, limit: Option<usize>) -> CarpResult<()> {
+    debug!("Fetching reviews for '{name}'...");
+
+    let response = client.reviews(name, limit).await?;
+
+    println!(
+        "{} {}",
+        response.name.bold().blue(),
+        rating_summary(response.rating_average, response.rating_count)
+    );
+
+    if response.reviews.is_empty() {
+        println!(
+            "\nNo reviews yet. Use `carp review {} --rating <1-5>` to add one.",
+            response.name
+        );
+        return Ok(());
+    }
+
+    println!();
+    for review in &response.reviews {
+        display_review(review);
+    }
+
+    Ok(())
+}
+
+fn display_review(review: &Review) {
+    println!(
+        "{} by {} • {}",
+        stars(review.rating).yellow(),
+        review.author_name.green(),
+        review.updated_at.format("%Y-%m-%d").to_string().dimmed()
+    );
+    if !review.body.is_empty() {
+        for line in review.body.lines() {
+            println!("  {line}");
+        }
+    }
+    println!();
+}
+
+/// One-line rating such as "4.25/5 (12 reviews)"
+pub fn rating_summary(average: Option<f64>, count: u64) -> String {
+    match average {
+        Some(average) if count > 0 => {
+            let noun = if count == 1 { "review" } else { "reviews" };
+            format!("{average:.2}/5 ({count} {noun})")
+        }
+        _ => "no reviews yet".to_string(),
+    }
+}
+
+fn stars(rating: u8) -> String {
+    let filled = usize::from(rating.min(5));
+    format!("{}{}", "★".repeat(filled), "☆".repeat(5 - filled))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rating_summary() {
+        assert_eq!(rating_summary(Some(4.25), 12), "4.25/5 (12 reviews)");
+        assert_eq!(rating_summary(Some(5.0), 1), "5.00/5 (1 review)");
+        assert_eq!(rating_summary(None, 0), "no reviews yet");
+    }
+
+    #[test]
+    fn test_stars() {
+        assert_eq!(stars(3), "★★★☆☆");
+        assert_eq!(stars(5), "★★★★★");
+    }
+}
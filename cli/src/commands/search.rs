@@ -1,26 +1,81 @@
-use crate::api::ApiClient;
-use crate::config::ConfigManager;
+use crate::api::{Agent, ApiClient, SearchMode, SearchSort};
+use crate::commands::list::{verified_badge, Layout};
+use crate::commands::pull::suggested_names;
 use crate::utils::error::CarpResult;
+use crate::utils::name_index;
+use crate::utils::search_index::SearchIndex;
 use colored::*;
+use tracing::debug;
+
+/// Options controlling which results a search returns
+#[derive(Debug, Clone, Copy)]
+pub struct SearchOptions {
+    /// Number of results to show, the registry's default if unset
+    pub limit: Option<usize>,
+    pub mode: SearchMode,
+    pub sort: SearchSort,
+    /// Search the local index instead of the registry
+    pub offline: bool,
+}
 
 /// Execute the search command
 pub async fn execute(
+    client: &ApiClient,
     query: String,
-    limit: Option<usize>,
-    exact: bool,
+    options: SearchOptions,
+    layout: &Layout,
     verbose: bool,
 ) -> CarpResult<()> {
-    if verbose {
-        println!("Searching for agents matching '{query}'...");
-    }
-
-    let config = ConfigManager::load_with_env_checks()?;
-    let client = ApiClient::new(&config)?;
+    let SearchOptions {
+        limit,
+        mode,
+        sort,
+        offline,
+    } = options;
+    debug!("Searching for agents matching '{query}' ({mode:?}, by {sort:?})...");
 
-    let response = client.search(&query, limit, exact).await?;
+    let index = if offline {
+        Some(SearchIndex::require(client.base_url())?)
+    } else {
+        None
+    };
+    let (mut response, index) = match index {
+        Some(index) => (index.search(&query, limit, mode, sort)?, Some(index)),
+        None => match client.search_with_mode(&query, limit, mode, sort).await {
+            Ok(response) => {
+                name_index::record(
+                    client.base_url(),
+                    response.agents.iter().map(|agent| agent.name.as_str()),
+                );
+                (response, None)
+            }
+            Err(e) => {
+                let index = SearchIndex::fallback(client.base_url(), e)?;
+                (index.search(&query, limit, mode, sort)?, Some(index))
+            }
+        },
+    };
+    if let Some(index) = &index {
+        index.print_staleness();
+    }
 
     if response.agents.is_empty() {
         println!("{}", "No agents found matching your search.".yellow());
+        // Patterns aren't names, so only plain queries get near-miss names,
+        // and only from a registry that's answering
+        if matches!(mode, SearchMode::Substring | SearchMode::Exact)
+            && !query.trim().is_empty()
+            && index.is_none()
+        {
+            let suggestions = suggested_names(client, query.trim()).await;
+            if !suggestions.is_empty() {
+                let names: Vec<String> = suggestions
+                    .iter()
+                    .map(|name| name.bold().blue().to_string())
+                    .collect();
+                println!("Did you mean {}?", names.join(", "));
+            }
+        }
         return Ok(());
     }
 
@@ -31,37 +86,11 @@ pub async fn execute(
     );
 
     let agents_count = response.agents.len();
-    for agent in &response.agents {
-        println!("{} {}", agent.name.bold().blue(), agent.version.dimmed());
-        println!("  {}", agent.description);
-        println!(
-            "  by {} • {} views",
-            agent.author.green(),
-            agent.download_count.to_string().cyan()
-        );
-
-        if !agent.tags.is_empty() {
-            print!("  tags: ");
-            for (i, tag) in agent.tags.iter().enumerate() {
-                if i > 0 {
-                    print!(", ");
-                }
-                print!("{}", tag.yellow());
-            }
-            println!();
-        }
-
-        if verbose {
-            println!("  created: {}", agent.created_at.format("%Y-%m-%d"));
-            if let Some(homepage) = &agent.homepage {
-                println!("  homepage: {}", homepage.blue().underline());
-            }
-            if let Some(repository) = &agent.repository {
-                println!("  repository: {}", repository.blue().underline());
-            }
+    layout.sort(&mut response.agents);
+    if !layout.print_table(&response.agents) {
+        for agent in &response.agents {
+            print_agent(agent, verbose);
         }
-
-        println!();
     }
 
     if response.total > agents_count {
@@ -73,3 +102,53 @@ pub async fn execute(
 
     Ok(())
 }
+
+fn print_agent(agent: &Agent, verbose: bool) {
+    let mut markers: Vec<String> = verified_badge(agent).into_iter().collect();
+    if !agent.is_public {
+        markers.push("(private)".yellow().to_string());
+    }
+    if agent.is_template {
+        markers.push("(template)".cyan().to_string());
+    }
+    if markers.is_empty() {
+        println!("{} {}", agent.name.bold().blue(), agent.version.dimmed());
+    } else {
+        println!(
+            "{} {} {}",
+            agent.name.bold().blue(),
+            agent.version.dimmed(),
+            markers.join(" ")
+        );
+    }
+    println!("  {}", agent.description);
+    println!(
+        "  by {} • {} views • {} stars",
+        agent.author.green(),
+        agent.download_count.to_string().cyan(),
+        agent.star_count.to_string().cyan()
+    );
+
+    if !agent.tags.is_empty() {
+        print!("  tags: ");
+        for (i, tag) in agent.tags.iter().enumerate() {
+            if i > 0 {
+                print!(", ");
+            }
+            print!("{}", tag.yellow());
+        }
+        println!();
+    }
+
+    if verbose {
+        println!("  created: {}", agent.created_at.format("%Y-%m-%d"));
+        if let Some(homepage) = &agent.homepage {
+            println!("  homepage: {}", homepage.blue().underline());
+        }
+        if let Some(repository) = &agent.repository {
+            println!("  repository: {}", repository.blue().underline());
+        }
+    }
+
+    println!();
+}
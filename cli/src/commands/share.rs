@@ -0,0 +1,78 @@
+use crate::api::{AccessRequest, AccessResponse, ApiClient, GranteeKind};
+use crate::utils::error::CarpResult;
+use colored::*;
+use tracing::debug;
+
+/// What `carp share` should do
+pub enum ShareAction {
+    /// Show who the agent is shared with
+    List,
+    Grant(AccessRequest),
+    Revoke(AccessRequest),
+}
+
+/// Execute the share command
+pub async fn execute(client: &ApiClient, name: String, action: ShareAction) -> CarpResult<()> {
+    let response = match action {
+        ShareAction::List => client.access(&name).await?,
+        ShareAction::Grant(grantee) => {
+            debug!("Sharing '{name}' with {grantee:?}...");
+            let response = client.grant_access(&name, &grantee).await?;
+            println!(
+                "{} Shared {} with {}",
+                "✓".green().bold(),
+                response.name.bold(),
+                describe(&grantee)
+            );
+            response
+        }
+        ShareAction::Revoke(grantee) => {
+            debug!("Revoking {grantee:?} from '{name}'...");
+            let response = client.revoke_access(&name, &grantee).await?;
+            println!(
+                "{} Stopped sharing {} with {}",
+                "✓".green().bold(),
+                response.name.bold(),
+                describe(&grantee)
+            );
+            response
+        }
+    };
+
+    print_grants(&response);
+    Ok(())
+}
+
+fn describe(grantee: &AccessRequest) -> String {
+    match (&grantee.user, &grantee.org) {
+        (Some(user), _) => user.cyan().to_string(),
+        (None, Some(org)) => format!("organization {}", org.cyan()),
+        (None, None) => "nobody".to_string(),
+    }
+}
+
+fn print_grants(response: &AccessResponse) {
+    if response.is_public {
+        println!("{} is public; everyone can read it", response.name.bold());
+        return;
+    }
+
+    if response.grants.is_empty() {
+        println!("{} is only visible to you", response.name.bold());
+        return;
+    }
+
+    println!("{} is shared with:", response.name.bold());
+    for grant in &response.grants {
+        let kind = match grant.kind {
+            GranteeKind::User => "user",
+            GranteeKind::Org => "org",
+        };
+        println!(
+            "  {} {} (since {})",
+            format!("{kind:<4}").dimmed(),
+            grant.name.cyan(),
+            grant.granted_at.format("%Y-%m-%d")
+        );
+    }
+}
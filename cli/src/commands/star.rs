@@ -0,0 +1,37 @@
+use crate::api::ApiClient;
+use crate::utils::error::CarpResult;
+use colored::*;
+use tracing::debug;
+
+/// Execute the star or unstar command
+pub async fn execute(client: &ApiClient, name: String, star: bool) -> CarpResult<()> {
+    let response = if star {
+        debug!("Starring '{name}'...");
+        client.star(&name).await?
+    } else {
+        debug!("Removing star from '{name}'...");
+        client.unstar(&name).await?
+    };
+
+    let noun = if response.star_count == 1 {
+        "star"
+    } else {
+        "stars"
+    };
+    let action = if response.starred {
+        "Starred"
+    } else {
+        "Unstarred"
+    };
+
+    println!(
+        "{} {} {} ({} {})",
+        "✓".green().bold(),
+        action,
+        response.name.bold(),
+        response.star_count.to_string().cyan(),
+        noun
+    );
+
+    Ok(())
+}
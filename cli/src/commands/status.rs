@@ -0,0 +1,139 @@
+use crate::utils::agent_dir::{self, Changes};
+use crate::utils::error::{CarpError, CarpResult};
+use crate::utils::workspace::{Lockfile, Workspace, MANIFEST_FILE};
+use colored::*;
+use std::path::Path;
+
+/// Where a project agent's installed files stand
+#[derive(Debug, PartialEq, Eq)]
+enum State {
+    /// Linked from a working tree, which is expected to change
+    Linked(String),
+    NotInstalled,
+    /// Installed before carp recorded what it wrote
+    Unrecorded,
+    Unchanged,
+    Changed(Changes),
+}
+
+/// Execute the status command: show which of the project's installed agents
+/// have been changed since carp installed them
+pub fn execute() -> CarpResult<()> {
+    let cwd = std::env::current_dir()?;
+    let root = Workspace::find_root(&cwd).ok_or_else(|| {
+        CarpError::ManifestError(format!(
+            "No {MANIFEST_FILE} found in {} or any parent directory",
+            cwd.display()
+        ))
+    })?;
+    let workspace = Workspace::load(&root)?;
+    let lockfile = Lockfile::load(&root)?;
+
+    let mut changed = 0;
+    for (name, dependency) in &workspace.agents {
+        let dest = workspace.install_dir(&root, name, dependency);
+        let state = match dependency.path() {
+            Some(path) => State::Linked(path.to_string()),
+            None => state(&dest)?,
+        };
+        let version = lockfile
+            .get(name)
+            .map(|locked| format!(" v{}", locked.version))
+            .unwrap_or_default();
+        let location = dest.strip_prefix(&cwd).unwrap_or(&dest).display();
+
+        match state {
+            State::Linked(path) => println!(
+                "{} {} linked from {}",
+                "→".blue().bold(),
+                name.blue().bold(),
+                path
+            ),
+            State::NotInstalled => println!(
+                "{} {}{} is not installed; run `carp install`",
+                "✗".red().bold(),
+                name.blue().bold(),
+                version
+            ),
+            State::Unrecorded => println!(
+                "{} {}{} in {} has no record of its files; reinstall it with `carp install --force` to track changes",
+                "?".yellow().bold(),
+                name.blue().bold(),
+                version,
+                location
+            ),
+            State::Unchanged => println!(
+                "{} {}{} unchanged",
+                "✓".green().bold(),
+                name.blue().bold(),
+                version
+            ),
+            State::Changed(changes) => {
+                changed += 1;
+                println!(
+                    "{} {}{} changed in {}",
+                    "M".yellow().bold(),
+                    name.blue().bold(),
+                    version,
+                    location
+                );
+                for line in changes.lines() {
+                    println!("    {line}");
+                }
+            }
+        }
+    }
+
+    if changed > 0 {
+        println!(
+            "\n{} of {} agents have local changes, which `carp install --force` or a new version would replace",
+            changed,
+            workspace.agents.len()
+        );
+    }
+    Ok(())
+}
+
+fn state(dest: &Path) -> CarpResult<State> {
+    if !dest.is_dir() {
+        return Ok(State::NotInstalled);
+    }
+    Ok(match agent_dir::changes(dest)? {
+        None => State::Unrecorded,
+        Some(changes) if changes.is_empty() => State::Unchanged,
+        Some(changes) => State::Changed(changes),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_state_of_installed_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("reviewer");
+        assert_eq!(state(&dest).unwrap(), State::NotInstalled);
+
+        fs::create_dir_all(dest.join("prompts")).unwrap();
+        fs::write(dest.join("reviewer.md"), "# Reviewer\n").unwrap();
+        fs::write(dest.join("prompts/style.md"), "Be kind\n").unwrap();
+        assert_eq!(state(&dest).unwrap(), State::Unrecorded);
+
+        agent_dir::record(&dest).unwrap();
+        assert_eq!(state(&dest).unwrap(), State::Unchanged);
+
+        fs::write(dest.join("reviewer.md"), "# My reviewer\n").unwrap();
+        fs::remove_file(dest.join("prompts/style.md")).unwrap();
+        fs::write(dest.join("notes.md"), "mine\n").unwrap();
+        assert_eq!(
+            state(&dest).unwrap(),
+            State::Changed(Changes {
+                modified: vec!["reviewer.md".to_string()],
+                added: vec!["notes.md".to_string()],
+                deleted: vec!["prompts/style.md".to_string()],
+            })
+        );
+    }
+}
@@ -0,0 +1,51 @@
+use crate::api::ApiClient;
+use crate::utils::error::{CarpError, CarpResult};
+use colored::*;
+use tracing::debug;
+
+/// Execute the tags command to show the most used tags
+pub async fn execute(client: &ApiClient, top: Option<usize>) -> CarpResult<()> {
+    if top == Some(0) {
+        return Err(CarpError::InvalidAgent(
+            "--top must be greater than 0".to_string(),
+        ));
+    }
+
+    debug!("Fetching tag counts...");
+    let response = client.tags(top).await?;
+
+    if response.tags.is_empty() {
+        println!("{}", "No tags found in the registry.".yellow());
+        return Ok(());
+    }
+
+    println!(
+        "{} {} tags, most used first:\n",
+        "Found".green().bold(),
+        response.tags.len()
+    );
+
+    let width = response
+        .tags
+        .iter()
+        .map(|tag| tag.tag.chars().count())
+        .max()
+        .unwrap_or(0);
+    for tag in &response.tags {
+        let noun = if tag.agent_count == 1 {
+            "agent"
+        } else {
+            "agents"
+        };
+        println!(
+            "  {}  {} {}",
+            format!("{:<width$}", tag.tag).yellow(),
+            tag.agent_count.to_string().cyan(),
+            noun
+        );
+    }
+
+    println!("\nUse `carp search <tag>` to find agents with a tag.");
+
+    Ok(())
+}
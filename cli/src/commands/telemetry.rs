@@ -0,0 +1,55 @@
+use crate::config::ConfigManager;
+use crate::utils::error::CarpResult;
+use crate::utils::telemetry::disabled_by_env;
+use colored::*;
+
+/// Turn anonymous usage telemetry on or off
+pub fn set(enabled: bool) -> CarpResult<()> {
+    ConfigManager::set_telemetry(enabled)?;
+
+    if enabled {
+        println!("{} Telemetry enabled. Thank you!", "✓".green().bold());
+        print_collected();
+        if let Some(var) = disabled_by_env() {
+            println!(
+                "{} {} is set, so nothing will be sent from this environment",
+                "Note:".yellow().bold(),
+                var
+            );
+        }
+    } else {
+        println!("{} Telemetry disabled", "✓".green().bold());
+    }
+
+    Ok(())
+}
+
+/// Show whether telemetry is on and what it sends
+pub fn status() -> CarpResult<()> {
+    let enabled = ConfigManager::telemetry_enabled_in_file()?;
+
+    match (enabled, disabled_by_env()) {
+        (true, None) => println!("Telemetry: {}", "enabled".green().bold()),
+        (true, Some(var)) => println!(
+            "Telemetry: {} (enabled in config, but {} is set)",
+            "disabled".yellow().bold(),
+            var
+        ),
+        (false, _) => println!("Telemetry: {}", "disabled".bold()),
+    }
+
+    print_collected();
+    if !enabled {
+        println!("\nRun `carp telemetry on` to help improve carp.");
+    }
+
+    Ok(())
+}
+
+fn print_collected() {
+    println!(
+        "\nWhen enabled, each command reports only its name, duration, whether it \
+         succeeded, the kind of error, and the carp version, OS and architecture. \
+         Arguments, agent names, paths, file contents and error messages are never sent."
+    );
+}
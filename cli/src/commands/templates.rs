@@ -0,0 +1,51 @@
+use crate::api::ApiClient;
+use crate::utils::error::{CarpError, CarpResult};
+use colored::*;
+use tracing::debug;
+
+/// Execute the templates command to list agents published as templates
+pub async fn execute(client: &ApiClient, query: String, limit: Option<usize>) -> CarpResult<()> {
+    if limit == Some(0) {
+        return Err(CarpError::InvalidAgent(
+            "--limit must be greater than 0".to_string(),
+        ));
+    }
+
+    debug!("Fetching templates matching '{query}'...");
+    let response = client.templates(&query, limit).await?;
+
+    if response.templates.is_empty() {
+        println!("{}", "No templates found.".yellow());
+        return Ok(());
+    }
+
+    println!(
+        "{} {} templates, most downloaded first:\n",
+        "Found".green().bold(),
+        response.total
+    );
+    for template in &response.templates {
+        println!(
+            "{} {}",
+            template.name.bold().blue(),
+            template.version.dimmed()
+        );
+        println!("  {}", template.description);
+        println!(
+            "  by {} • {} downloads\n",
+            template.author.green(),
+            template.download_count.to_string().cyan()
+        );
+    }
+
+    if response.total > response.templates.len() {
+        println!(
+            "Showing {} of {} templates. Use --limit to see more.",
+            response.templates.len(),
+            response.total
+        );
+    }
+    println!("Use `carp new <name> --template <template>` to start an agent from one.");
+
+    Ok(())
+}
@@ -0,0 +1,72 @@
+use crate::api::types::ApiKeyInfo;
+use crate::api::ApiClient;
+use crate::utils::error::CarpResult;
+use chrono::Utc;
+use colored::*;
+use tracing::debug;
+
+/// Execute the token list command to show the user's API keys and their last use
+pub async fn list(client: &ApiClient) -> CarpResult<()> {
+    debug!("Fetching API keys...");
+    let keys = client.api_keys().await?;
+
+    if keys.is_empty() {
+        println!("{}", "You have no API keys.".yellow());
+        return Ok(());
+    }
+
+    let noun = if keys.len() == 1 { "key" } else { "keys" };
+    println!("{} {} API {}:\n", "Found".green().bold(), keys.len(), noun);
+
+    for key in &keys {
+        println!(
+            "{} {} {}",
+            key.name.bold(),
+            format!("({}…)", key.prefix).dimmed(),
+            status(key)
+        );
+        if !key.scopes.is_empty() {
+            println!("  scopes: {}", key.scopes.join(", "));
+        }
+        match key.last_used_at {
+            Some(at) => {
+                let mut place = Vec::new();
+                if let Some(country) = &key.last_used_country {
+                    place.push(country.clone());
+                }
+                if let Some(ip) = &key.last_used_ip {
+                    place.push(ip.clone());
+                }
+                let place = if place.is_empty() {
+                    String::new()
+                } else {
+                    format!(" from {}", place.join(", "))
+                };
+                println!("  last used: {}{}", at.format("%Y-%m-%d %H:%M UTC"), place);
+                if let Some(agent) = &key.last_used_user_agent {
+                    println!("  client: {agent}");
+                }
+            }
+            None => println!("  last used: {}", "never".dimmed()),
+        }
+        if let Some(expires_at) = key.expires_at {
+            println!("  expires: {}", expires_at.format("%Y-%m-%d %H:%M UTC"));
+        }
+        println!();
+    }
+
+    println!("Revoke keys you don't recognize in the registry dashboard.");
+
+    Ok(())
+}
+
+/// Whether a key still works
+fn status(key: &ApiKeyInfo) -> ColoredString {
+    if key.expires_at.is_some_and(|at| at <= Utc::now()) {
+        "expired".yellow()
+    } else if key.is_active {
+        "active".green()
+    } else {
+        "revoked".red()
+    }
+}
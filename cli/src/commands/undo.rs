@@ -0,0 +1,32 @@
+use crate::utils::error::CarpResult;
+use crate::utils::trash;
+use colored::*;
+
+/// Execute the undo command: put back what the last command that replaced
+/// or deleted agents discarded
+pub fn execute() -> CarpResult<()> {
+    let Some(operation) = trash::undo()? else {
+        println!("Nothing to undo");
+        return Ok(());
+    };
+
+    println!(
+        "{} Undid `{}` from {}",
+        "✓".green().bold(),
+        operation.command,
+        operation
+            .created_at
+            .with_timezone(&chrono::Local)
+            .format("%Y-%m-%d %H:%M:%S")
+    );
+    for entry in &operation.entries {
+        let action = if entry.stored.is_some() {
+            "restored"
+        } else {
+            "removed"
+        };
+        println!("  {} {}", action.dimmed(), entry.path.display());
+    }
+    println!("Run `carp undo` again to redo it");
+    Ok(())
+}
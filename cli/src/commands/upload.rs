@@ -1,11 +1,22 @@
-use crate::api::{ApiClient, UploadAgentRequest};
+use crate::api::{ApiClient, Provenance, UploadAgentRequest};
 use crate::auth::AuthManager;
-use crate::config::ConfigManager;
+use crate::commands::pull::get_agent_definition;
+use crate::config::Config;
+use crate::utils::archive::build_markdown_package;
+use crate::utils::ci::{self, Level};
 use crate::utils::error::{CarpError, CarpResult};
+use crate::utils::manifest::Hooks;
+use crate::utils::provenance;
+use crate::utils::secrets;
+use crate::utils::signing::{KeylessSigner, IDENTITY_TOKEN_ENV};
 use colored::*;
 use inquire::Select;
+use semver::Version;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::{Path, PathBuf};
+use tracing::debug;
 use walkdir::WalkDir;
 
 /// Agent file information extracted from agent definition files
@@ -14,55 +25,93 @@ pub struct AgentFile {
     pub path: PathBuf,
     pub name: String,
     pub description: String,
+    /// From the frontmatter; agents without one are published as 1.0.0
+    pub version: Option<String>,
     pub display_name: String,
+    /// Agents this one depends on, from its `Carp.toml`; without them the
+    /// registry reads the frontmatter's
+    pub dependencies: BTreeMap<String, String>,
 }
 
+/// Options controlling how agents are uploaded
+#[derive(Debug, Default)]
+pub struct UploadOptions {
+    /// Sign agents with Sigstore using an OIDC identity
+    pub keyless: bool,
+    pub identity_token: Option<String>,
+    /// Record git and CI provenance
+    pub record_provenance: bool,
+    /// Only the publisher can see private agents
+    pub private: bool,
+    /// Offer the agents as starting points for `carp new --template`
+    pub template: bool,
+    /// Skip agents whose latest published version has the same content
+    pub if_changed: bool,
+    /// Validate and show what would be uploaded without contacting the registry
+    pub dry_run: bool,
+    /// Fingerprints of secret scanner findings known to be false positives
+    pub allowed_secrets: Vec<String>,
+    /// Publish as the patch after the registry's latest version when the
+    /// agent's own version isn't newer
+    pub force_new_version: bool,
+}
+
+/// Version of agents whose frontmatter doesn't give one
+pub(crate) const DEFAULT_VERSION: &str = "1.0.0";
+
 /// Selection result from agent selection prompt
 #[derive(Debug)]
-enum AgentSelection {
+pub(crate) enum AgentSelection {
     Single(AgentFile),
     All(Vec<AgentFile>),
 }
 
+impl AgentSelection {
+    pub(crate) fn into_agents(self) -> Vec<AgentFile> {
+        match self {
+            AgentSelection::Single(agent) => vec![agent],
+            AgentSelection::All(agents) => agents,
+        }
+    }
+}
+
 /// Execute the upload command
 pub async fn execute(
+    client: &ApiClient,
+    config: &Config,
     directory: Option<String>,
-    api_key: Option<String>,
+    options: UploadOptions,
     verbose: bool,
 ) -> CarpResult<()> {
-    // Load config first to get stored API key
-    let config = ConfigManager::load_with_env_checks()?;
+    let UploadOptions {
+        if_changed,
+        dry_run,
+        private,
+        ..
+    } = options;
 
-    if verbose {
-        println!("DEBUG: Runtime API key present: {}", api_key.is_some());
-        println!(
-            "DEBUG: Stored API key present: {}",
-            config.api_key.is_some()
-        );
-    }
+    debug!("Stored API key present: {}", config.api_key.is_some());
 
-    // Use runtime API key if provided, otherwise use stored API key
-    let effective_api_key = api_key.as_deref().or(config.api_key.as_deref());
+    // The shared client already prefers a runtime API key over the stored one
+    let effective_api_key = client.api_key();
 
-    if verbose {
-        println!(
-            "DEBUG: Effective API key present: {}",
-            effective_api_key.is_some()
-        );
-    }
+    debug!("Effective API key present: {}", effective_api_key.is_some());
 
     // Ensure user is authenticated (either via API key parameter or stored configuration)
-    AuthManager::ensure_authenticated(effective_api_key).await?;
+    if !dry_run {
+        AuthManager::ensure_authenticated(effective_api_key).await?;
+    }
+
+    // Set up keyless signing before scanning so a missing identity fails fast
+    let signer = keyless_signer(&options).await?;
 
     // Get directory path - either provided, prompted for, or use default
-    let dir_path = get_directory_path(directory, verbose)?;
+    let dir_path = get_directory_path(directory)?;
 
-    if verbose {
-        println!("Scanning directory: {}", dir_path.display());
-    }
+    debug!("Scanning directory: {}", dir_path.display());
 
     // Scan directory for agent files
-    let agent_files = scan_agent_files(&dir_path, verbose)?;
+    let agent_files = scan_agent_files(&dir_path)?;
 
     if agent_files.is_empty() {
         println!(
@@ -76,24 +125,56 @@ pub async fn execute(
         return Ok(());
     }
 
-    if verbose {
-        println!("Found {} agent files", agent_files.len());
-    }
+    debug!("Found {} agent files", agent_files.len());
 
     // Use inquire to prompt user for agent selection (including "All" option)
     let selection = select_agents(agent_files.clone())?;
 
+    // Hooks run before anything is packaged, in dry runs too
+    run_pre_publish_hooks(&dir_path)?;
+
+    let selected = match &selection {
+        AgentSelection::Single(agent) => std::slice::from_ref(agent),
+        AgentSelection::All(agents) => agents.as_slice(),
+    };
+    check_secrets(selected, &options.allowed_secrets)?;
+
+    if dry_run {
+        let agents = selection.into_agents();
+        for agent in &agents {
+            preview_upload(agent, &options, client)
+                .inspect_err(|e| ci::annotate(Level::Error, &e.to_string(), Some(&agent.path)))?;
+        }
+        println!(
+            "{} Dry run: {} agent(s) valid, nothing was uploaded",
+            "✓".green().bold(),
+            agents.len()
+        );
+        return Ok(());
+    }
+
     match selection {
         AgentSelection::Single(agent) => {
-            if verbose {
-                println!("Selected agent: {}", agent.name);
-            }
+            debug!("Selected agent: {}", agent.name);
 
             // Read and parse the selected agent file
             let agent_content = fs::read_to_string(&agent.path)?;
 
+            if if_changed && is_unchanged(client, &agent, &agent_content).await? {
+                print_unchanged(&agent);
+                return Ok(());
+            }
+
             // Upload the agent
-            upload_agent(&agent, agent_content, effective_api_key, verbose, &config).await?;
+            upload_agent(
+                &agent,
+                agent_content,
+                signer.as_ref(),
+                &options,
+                client,
+                verbose,
+            )
+            .await?;
 
             println!(
                 "{} Successfully uploaded agent '{}'",
@@ -102,11 +183,10 @@ pub async fn execute(
             );
         }
         AgentSelection::All(agents) => {
-            if verbose {
-                println!("Uploading all {} agents", agents.len());
-            }
+            debug!("Uploading all {} agents", agents.len());
 
             let mut successful = 0;
+            let mut unchanged = 0;
             let mut failed = 0;
 
             for agent in agents {
@@ -118,16 +198,29 @@ pub async fn execute(
 
                 match fs::read_to_string(&agent.path) {
                     Ok(agent_content) => {
-                        match upload_agent(
-                            &agent,
-                            agent_content,
-                            effective_api_key,
-                            verbose,
-                            &config,
-                        )
-                        .await
-                        {
-                            Ok(_) => {
+                        let result = async {
+                            if if_changed && is_unchanged(client, &agent, &agent_content).await? {
+                                return Ok(false);
+                            }
+                            upload_agent(
+                                &agent,
+                                agent_content,
+                                signer.as_ref(),
+                                &options,
+                                client,
+                                verbose,
+                            )
+                            .await
+                            .map(|()| true)
+                        }
+                        .await;
+
+                        match result {
+                            Ok(false) => {
+                                print_unchanged(&agent);
+                                unchanged += 1;
+                            }
+                            Ok(true) => {
                                 println!(
                                     "{} Successfully uploaded agent '{}'",
                                     "✓".green().bold(),
@@ -142,6 +235,7 @@ pub async fn execute(
                                     agent.name.red().bold(),
                                     e
                                 );
+                                ci::annotate(Level::Error, &e.to_string(), Some(&agent.path));
                                 failed += 1;
                             }
                         }
@@ -153,33 +247,311 @@ pub async fn execute(
                             agent.name.red().bold(),
                             e
                         );
+                        ci::annotate(Level::Error, &e.to_string(), Some(&agent.path));
                         failed += 1;
                     }
                 }
             }
 
+            let unchanged = if if_changed {
+                format!(", {unchanged} unchanged")
+            } else {
+                String::new()
+            };
             println!(
-                "\n{} Upload complete: {} successful, {} failed",
+                "\n{} Upload complete: {} successful{}, {} failed",
                 "✓".green().bold(),
                 successful.to_string().green().bold(),
+                unchanged,
                 if failed > 0 {
                     failed.to_string().red().bold()
                 } else {
                     failed.to_string().green().bold()
                 }
             );
+
+            // A partial upload shouldn't pass a pipeline
+            if failed > 0 && ci::enabled() {
+                return Err(CarpError::Other(format!(
+                    "{failed} of {} agents failed to upload",
+                    successful + failed
+                )));
+            }
         }
     }
 
+    if private {
+        println!(
+            "  {}",
+            "Uploaded as private: only you can see and download it. `carp list --mine` shows your agents."
+                .dimmed()
+        );
+    }
+
     Ok(())
 }
 
+/// The signer for `--keyless`, if it was passed
+///
+/// A dry run doesn't sign, since that records the signature in the public
+/// log, but still needs an identity token.
+pub(crate) async fn keyless_signer(options: &UploadOptions) -> CarpResult<Option<KeylessSigner>> {
+    if !options.keyless {
+        return Ok(None);
+    }
+    let token = options.identity_token.clone().ok_or_else(|| {
+        CarpError::Signing(format!(
+            "Keyless signing requires an OIDC identity token. Pass --identity-token or set {IDENTITY_TOKEN_ENV}."
+        ))
+    })?;
+    if options.dry_run {
+        return Ok(None);
+    }
+    let signer = KeylessSigner::production(token).await?;
+    debug!("Signing as {}", signer.identity()?);
+    Ok(Some(signer))
+}
+
+/// Run the pre-publish hooks of the `Carp.toml` nearest `dir`, if any
+pub(crate) fn run_pre_publish_hooks(dir: &Path) -> CarpResult<()> {
+    let publish_dir = fs::canonicalize(dir)?;
+    if let Some((manifest, hooks)) = Hooks::find(&publish_dir)? {
+        let root = manifest.parent().unwrap_or(&publish_dir);
+        hooks
+            .run_pre_publish(root, &publish_dir)
+            .inspect_err(|e| ci::annotate(Level::Error, &e.to_string(), Some(&manifest)))?;
+    }
+    Ok(())
+}
+
+/// Refuse to upload agents that look like they contain credentials, except
+/// for findings allowed by fingerprint
+pub(crate) fn check_secrets(agents: &[AgentFile], allowed: &[String]) -> CarpResult<()> {
+    let mut blocked = Vec::new();
+
+    for agent in agents {
+        let content = fs::read_to_string(&agent.path)?;
+        for finding in secrets::scan(&content) {
+            if allowed.contains(&finding.fingerprint) {
+                debug!(
+                    "Allowing {} in {} (fingerprint {})",
+                    finding.kind,
+                    agent.path.display(),
+                    finding.fingerprint
+                );
+                continue;
+            }
+
+            let message = format!(
+                "{} `{}` (fingerprint {})",
+                finding.kind, finding.preview, finding.fingerprint
+            );
+            ci::annotate(
+                Level::Error,
+                &format!("Line {}: possible {message}", finding.line),
+                Some(&agent.path),
+            );
+            blocked.push(format!(
+                "{}:{}: {message}",
+                agent.path.display(),
+                finding.line
+            ));
+        }
+    }
+
+    if blocked.is_empty() {
+        Ok(())
+    } else {
+        Err(CarpError::SecretsDetected(blocked))
+    }
+}
+
+/// The request that uploads `agent` with `content`
+fn upload_request(
+    agent: &AgentFile,
+    content: String,
+    version: String,
+    signature_bundle: Option<serde_json::Value>,
+    provenance: Option<Provenance>,
+    options: &UploadOptions,
+) -> UploadAgentRequest {
+    UploadAgentRequest {
+        name: agent.name.clone(),
+        description: agent.description.clone(),
+        content,
+        version: Some(version),
+        tags: vec!["claude-agent".to_string()], // Default tag for uploaded agents
+        homepage: None,
+        repository: None,
+        license: Some("MIT".to_string()), // Default license
+        signature_bundle,
+        provenance,
+        private: options.private,
+        template: options.template,
+        allowed_secrets: options.allowed_secrets.clone(),
+        dependencies: agent.dependencies.clone(),
+    }
+}
+
+/// Print what uploading `agent` would send, after running the same checks
+pub(crate) fn preview_upload(
+    agent: &AgentFile,
+    options: &UploadOptions,
+    client: &ApiClient,
+) -> CarpResult<()> {
+    let content = fs::read_to_string(&agent.path)?;
+    let package = build_markdown_package(&agent.name, &content)?;
+    let provenance = if options.record_provenance {
+        provenance::detect(agent.path.parent().unwrap_or(Path::new(".")))
+    } else {
+        None
+    };
+    let version = agent
+        .version
+        .clone()
+        .unwrap_or_else(|| DEFAULT_VERSION.to_string());
+    let request = upload_request(agent, content, version, None, provenance, options);
+    let preview = client.preview_upload(&request)?;
+
+    println!(
+        "{} {} v{} from {}",
+        "Would upload".bold(),
+        request.name.blue().bold(),
+        request.version.as_deref().unwrap_or(DEFAULT_VERSION),
+        agent.path.display()
+    );
+    println!("  {} {}", "Description:".bold(), request.description);
+    println!("  {} {}", "Tags:".bold(), request.tags.join(", "));
+    println!(
+        "  {} {} bytes ({}.md stored as a {} byte zip)",
+        "Size:".bold(),
+        request.content.len(),
+        request.name,
+        package.len()
+    );
+    println!(
+        "  {} {}",
+        "Visibility:".bold(),
+        if options.private { "private" } else { "public" }
+    );
+    if options.template {
+        println!("  {} listed for `carp new --template`", "Template:".bold());
+    }
+    if options.force_new_version {
+        println!(
+            "  {} bumped past the registry's latest at upload time if it isn't newer",
+            "Version:".bold()
+        );
+    }
+    if options.keyless {
+        println!(
+            "  {} added by keyless signing, which a dry run skips",
+            "Signature:".bold()
+        );
+    }
+    println!("\n{}\n", preview.dimmed());
+    Ok(())
+}
+
+/// Whether the registry's latest version of `agent` already has `content`
+///
+/// The package checksum is compared first, which is cheap; packages built
+/// before packaging was reproducible differ from today's build, so a
+/// mismatch falls back to comparing the published definition.
+pub(crate) async fn is_unchanged(
+    client: &ApiClient,
+    agent: &AgentFile,
+    content: &str,
+) -> CarpResult<bool> {
+    let package = build_markdown_package(&agent.name, content)?;
+    let local = format!("sha256:{:x}", Sha256::digest(&package));
+    match client.get_agent_checksum(&agent.name).await {
+        Ok(latest) if latest.checksum == local => {
+            debug!("Package of '{}' matches v{}", agent.name, latest.version);
+            return Ok(true);
+        }
+        Ok(_) => {}
+        // Also what registries without the endpoint answer; the definition
+        // tells the two apart
+        Err(CarpError::Api {
+            status: 404 | 501, ..
+        }) => {}
+        Err(e) => return Err(e),
+    }
+
+    let published = match get_agent_definition(client, &agent.name, None).await {
+        Ok(published) => published,
+        Err(CarpError::AgentNotFound { .. }) => return Ok(false),
+        Err(e) => return Err(e),
+    };
+
+    let local = content_hash(content);
+    let remote = published.readme.as_deref().map(content_hash);
+    debug!(
+        "Content of '{}': {} locally, {} in v{}",
+        agent.name,
+        local,
+        remote.as_deref().unwrap_or("none"),
+        published.version
+    );
+    Ok(remote.as_deref() == Some(local.as_str()))
+}
+
+/// SHA-256 of an agent definition, as `sha256:<hex>`
+fn content_hash(content: &str) -> String {
+    format!("sha256:{:x}", Sha256::digest(content.as_bytes()))
+}
+
+pub(crate) fn print_unchanged(agent: &AgentFile) {
+    println!(
+        "{} Agent '{}' unchanged, skipping upload",
+        "✓".green().bold(),
+        agent.name.blue().bold()
+    );
+}
+
+/// The version `--force-new-version` publishes `agent` as: its own version
+/// if that's newer than everything published, otherwise the next patch
+async fn next_version(client: &ApiClient, agent: &AgentFile) -> CarpResult<String> {
+    let local = agent.version.as_deref().unwrap_or(DEFAULT_VERSION);
+    // Yanked versions count too, since their numbers can't be reused
+    let published = match client.get_agent_versions(&agent.name).await {
+        Ok(response) => response.versions,
+        Err(CarpError::Api { status: 404, .. }) => return Ok(local.to_string()),
+        Err(e) => return Err(e),
+    };
+
+    let Some(latest) = published
+        .iter()
+        .filter_map(|published| Version::parse(&published.version).ok())
+        .max()
+    else {
+        return Ok(local.to_string());
+    };
+
+    if Version::parse(local).is_ok_and(|local| local > latest) {
+        return Ok(local.to_string());
+    }
+
+    let next = Version::new(latest.major, latest.minor, latest.patch + 1).to_string();
+    println!(
+        "{} Publishing '{}' as v{} (v{} is already published)",
+        "→".blue().bold(),
+        agent.name.blue().bold(),
+        next,
+        latest
+    );
+    Ok(next)
+}
+
 /// Get directory path from user input, prompt, or default
-fn get_directory_path(directory: Option<String>, verbose: bool) -> CarpResult<PathBuf> {
+pub(crate) fn get_directory_path(directory: Option<String>) -> CarpResult<PathBuf> {
     let dir_path = if let Some(dir) = directory {
         // Directory provided via command line
         expand_directory_path(Some(dir))?
     } else {
+        ci::ensure_interactive("Choosing a directory", "pass --directory")?;
+
         // Prompt user for directory
         let default_dir = "~/.claude/agents/";
         let prompt_text = format!("Enter directory to scan for agents (default: {default_dir}):");
@@ -198,9 +570,7 @@ fn get_directory_path(directory: Option<String>, verbose: bool) -> CarpResult<Pa
         expand_directory_path(Some(input))?
     };
 
-    if verbose {
-        println!("Using directory: {}", dir_path.display());
-    }
+    debug!("Using directory: {}", dir_path.display());
 
     Ok(dir_path)
 }
@@ -239,12 +609,10 @@ fn expand_directory_path(directory: Option<String>) -> CarpResult<PathBuf> {
 }
 
 /// Scan directory recursively for agent definition files
-fn scan_agent_files(dir_path: &Path, verbose: bool) -> CarpResult<Vec<AgentFile>> {
+pub(crate) fn scan_agent_files(dir_path: &Path) -> CarpResult<Vec<AgentFile>> {
     let mut agents = Vec::new();
 
-    if verbose {
-        println!("Scanning for agent files recursively...");
-    }
+    debug!("Scanning for agent files recursively...");
 
     for entry in WalkDir::new(dir_path).follow_links(false) {
         let entry =
@@ -255,13 +623,17 @@ fn scan_agent_files(dir_path: &Path, verbose: bool) -> CarpResult<Vec<AgentFile>
             // Check if it's a markdown file
             if let Some(extension) = path.extension() {
                 if extension == "md" {
-                    match parse_agent_file(path, verbose) {
+                    match parse_agent_file(path) {
                         Ok(agent) => {
                             agents.push(agent);
                         }
                         Err(e) => {
-                            if verbose {
-                                println!("  {} Skipping {}: {}", "⚠".yellow(), path.display(), e);
+                            debug!("Skipping {}: {}", path.display(), e);
+                            // Files with frontmatter were probably meant to be agents
+                            if fs::read_to_string(path)
+                                .is_ok_and(|content| content.starts_with("---"))
+                            {
+                                ci::annotate(Level::Warning, &format!("Skipped: {e}"), Some(path));
                             }
                         }
                     }
@@ -303,7 +675,7 @@ fn extract_field_as_string(frontmatter: &serde_json::Value, field: &str) -> Opti
 }
 
 /// Parse an agent file to extract name and description from YAML frontmatter
-fn parse_agent_file(path: &Path, verbose: bool) -> CarpResult<AgentFile> {
+pub(crate) fn parse_agent_file(path: &Path) -> CarpResult<AgentFile> {
     let content = fs::read_to_string(path)?;
 
     // Check if file starts with YAML frontmatter
@@ -327,13 +699,10 @@ fn parse_agent_file(path: &Path, verbose: bool) -> CarpResult<AgentFile> {
     }
 
     let frontmatter_end = frontmatter_end.ok_or_else(|| {
-        if verbose {
-            eprintln!(
-                "Could not find closing frontmatter boundary in {}",
-                path.display()
-            );
-            eprintln!("Looking for '---' or '...' after opening '---'");
-        }
+        debug!(
+            "Could not find closing frontmatter boundary ('---' or '...') in {}",
+            path.display()
+        );
         CarpError::ManifestError("Invalid YAML frontmatter: missing closing --- or ...".to_string())
     })?;
 
@@ -344,10 +713,8 @@ fn parse_agent_file(path: &Path, verbose: bool) -> CarpResult<AgentFile> {
     // Parse YAML frontmatter with better error handling
     let frontmatter: serde_json::Value =
         serde_yaml::from_str(&frontmatter_content).map_err(|e| {
-            if verbose {
-                eprintln!("YAML parsing failed for {}: {}", path.display(), e);
-                eprintln!("Frontmatter content:\n{frontmatter_content}");
-            }
+            debug!("YAML parsing failed for {}: {}", path.display(), e);
+            debug!("Frontmatter content:\n{frontmatter_content}");
             CarpError::ManifestError(format!("Invalid YAML frontmatter: {e}"))
         })?;
 
@@ -360,6 +727,8 @@ fn parse_agent_file(path: &Path, verbose: bool) -> CarpResult<AgentFile> {
         CarpError::ManifestError("Missing 'description' field in frontmatter".to_string())
     })?;
 
+    let version = extract_field_as_string(&frontmatter, "version");
+
     // Create display name for selection
     let file_name = path
         .file_name()
@@ -368,24 +737,24 @@ fn parse_agent_file(path: &Path, verbose: bool) -> CarpResult<AgentFile> {
 
     let display_name = format!("{name} ({file_name})");
 
-    if verbose {
-        println!(
-            "  Found agent: {} - {}",
-            name,
-            description.chars().take(60).collect::<String>()
-        );
-    }
+    debug!(
+        "Found agent: {} - {}",
+        name,
+        description.chars().take(60).collect::<String>()
+    );
 
     Ok(AgentFile {
         path: path.to_path_buf(),
         name,
         description,
+        version,
         display_name,
+        dependencies: BTreeMap::new(),
     })
 }
 
 /// Use inquire to prompt user for agent selection (single or all)
-fn select_agents(agents: Vec<AgentFile>) -> CarpResult<AgentSelection> {
+pub(crate) fn select_agents(agents: Vec<AgentFile>) -> CarpResult<AgentSelection> {
     if agents.is_empty() {
         return Err(CarpError::Other("No agents found".to_string()));
     }
@@ -396,10 +765,15 @@ fn select_agents(agents: Vec<AgentFile>) -> CarpResult<AgentSelection> {
         return Ok(AgentSelection::Single(agents.into_iter().next().unwrap()));
     }
 
+    // Nobody can pick one in CI, so upload them all
+    if ci::enabled() {
+        return Ok(AgentSelection::All(agents));
+    }
+
     let mut options = vec!["📦 All agents".to_string()];
     options.extend(agents.iter().map(|a| a.display_name.clone()));
 
-    let selection = Select::new("Select agents to upload:", options)
+    let selection = Select::new("Select agents:", options)
         .prompt()
         .map_err(|e| CarpError::Other(format!("Selection cancelled: {e}")))?;
 
@@ -417,37 +791,70 @@ fn select_agents(agents: Vec<AgentFile>) -> CarpResult<AgentSelection> {
 }
 
 /// Upload the selected agent to the registry
-async fn upload_agent(
+pub(crate) async fn upload_agent(
     agent: &AgentFile,
     content: String,
-    api_key: Option<&str>,
+    signer: Option<&KeylessSigner>,
+    options: &UploadOptions,
+    client: &ApiClient,
     verbose: bool,
-    config: &crate::config::Config,
 ) -> CarpResult<()> {
-    if verbose {
-        println!("Preparing to upload agent '{}'...", agent.name);
-    }
+    debug!("Preparing to upload agent '{}'...", agent.name);
 
-    // Create upload request
-    let request = UploadAgentRequest {
-        name: agent.name.clone(),
-        description: agent.description.clone(),
-        content,
-        version: Some("1.0.0".to_string()), // Default version for uploaded agents
-        tags: vec!["claude-agent".to_string()], // Default tag for uploaded agents
-        homepage: None,
-        repository: None,
-        license: Some("MIT".to_string()), // Default license
+    let signature_bundle = match signer {
+        Some(signer) => {
+            debug!("Signing agent '{}' with Sigstore...", agent.name);
+            Some(signer.sign(&content).await?)
+        }
+        None => None,
     };
 
-    // Upload to registry
-    let client = ApiClient::new(config)?.with_api_key(api_key.map(|s| s.to_string()));
+    let provenance = if options.record_provenance {
+        let dir = agent.path.parent().unwrap_or(Path::new("."));
+        provenance::detect(dir).map(|mut provenance| {
+            // A keyless signature vouches for the signer, so prefer it as the builder
+            if provenance.builder.is_none() {
+                provenance.builder = signer.and_then(|signer| signer.identity().ok());
+            }
+            provenance
+        })
+    } else {
+        None
+    };
 
-    if verbose {
-        println!("Uploading to registry...");
+    if let Some(provenance) = &provenance {
+        debug!(
+            "Recording provenance: {}",
+            serde_json::to_string(provenance)?
+        );
     }
 
-    let response = client.upload(request).await?;
+    let version = if options.force_new_version {
+        next_version(client, agent).await?
+    } else {
+        agent
+            .version
+            .clone()
+            .unwrap_or_else(|| DEFAULT_VERSION.to_string())
+    };
+    let id = format!("{}@{version}", agent.name);
+    let request = upload_request(
+        agent,
+        content,
+        version,
+        signature_bundle,
+        provenance,
+        options,
+    );
+
+    // Upload to registry
+    debug!("Uploading {id} to registry...");
+
+    let response = client.upload(request).await.map_err(|e| match e {
+        // Published versions are immutable
+        CarpError::Api { status: 409, .. } => CarpError::VersionExists(id),
+        e => e,
+    })?;
 
     if !response.success {
         if let Some(validation_errors) = &response.validation_errors {
@@ -502,12 +909,13 @@ This agent helps with testing.
 
         fs::write(&agent_file_path, content).unwrap();
 
-        let result = parse_agent_file(&agent_file_path, false);
+        let result = parse_agent_file(&agent_file_path);
         assert!(result.is_ok());
 
         let agent = result.unwrap();
         assert_eq!(agent.name, "test-agent");
         assert_eq!(agent.description, "A test agent for unit testing");
+        assert_eq!(agent.version, None);
     }
 
     #[test]
@@ -522,7 +930,7 @@ This file doesn't have YAML frontmatter.
 
         fs::write(&agent_file_path, content).unwrap();
 
-        let result = parse_agent_file(&agent_file_path, false);
+        let result = parse_agent_file(&agent_file_path);
         assert!(result.is_err());
     }
 
@@ -540,7 +948,7 @@ description: Missing name field
 
         fs::write(&agent_file_path, content).unwrap();
 
-        let result = parse_agent_file(&agent_file_path, false);
+        let result = parse_agent_file(&agent_file_path);
         assert!(result.is_err());
     }
 
@@ -0,0 +1,228 @@
+use crate::api::{Agent, ApiClient, SearchMode, SearchSort};
+use crate::utils::error::CarpResult;
+use colored::*;
+use semver::Version;
+use std::collections::HashMap;
+use std::time::Duration;
+use tracing::debug;
+
+/// How many of the most recently updated matches each poll fetches; anything
+/// published since the last poll is among them unless more than this many
+/// matching agents changed in between
+const WINDOW: usize = 100;
+
+/// Something that appeared since the last poll
+#[derive(Debug)]
+enum Change<'a> {
+    Agent(&'a Agent),
+    Version { agent: &'a Agent, previous: String },
+}
+
+/// Poll a search every `interval` and print the agents and versions that
+/// appear, until interrupted
+pub async fn execute(
+    client: &ApiClient,
+    query: String,
+    mode: SearchMode,
+    interval: Duration,
+) -> CarpResult<()> {
+    // A failing first poll is most likely a bad query, so it ends the watch
+    let response = client
+        .search_with_mode(&query, Some(WINDOW), mode, SearchSort::Recent)
+        .await?;
+    // Agents that scroll out of the window are kept, so one updated later
+    // is reported as a new version rather than a new agent
+    let mut seen: HashMap<String, String> = HashMap::new();
+    record(&mut seen, &response.agents);
+
+    let target = if query.trim().is_empty() {
+        "the registry".to_string()
+    } else {
+        format!("agents matching '{}'", query.trim())
+    };
+    println!(
+        "{} Watching {} ({} now), checking every {}s. Press Ctrl-C to stop.",
+        "⟳".blue().bold(),
+        target,
+        response.total,
+        interval.as_secs()
+    );
+
+    loop {
+        tokio::time::sleep(interval).await;
+        debug!("Polling for changes to {target}");
+
+        // The registry being briefly unreachable shouldn't end a long watch
+        let response = match client
+            .search_with_mode(&query, Some(WINDOW), mode, SearchSort::Recent)
+            .await
+        {
+            Ok(response) => response,
+            Err(e) => {
+                println!(
+                    "{} {} Check failed, retrying in {}s: {}",
+                    timestamp(),
+                    "Warning:".yellow().bold(),
+                    interval.as_secs(),
+                    e
+                );
+                continue;
+            }
+        };
+
+        // Oldest first, so the output reads in the order things happened
+        for change in changes(&seen, &response.agents).into_iter().rev() {
+            print_change(&change);
+        }
+        record(&mut seen, &response.agents);
+    }
+}
+
+/// The latest version seen of each agent in `agents`
+fn record(seen: &mut HashMap<String, String>, agents: &[Agent]) {
+    for agent in agents {
+        let newer = match seen.get(&agent.name) {
+            Some(previous) => is_newer(&agent.version, previous),
+            None => true,
+        };
+        if newer {
+            seen.insert(agent.name.clone(), agent.version.clone());
+        }
+    }
+}
+
+/// The agents in `agents` that weren't `seen`, and those with a newer
+/// version than was seen, in the order the registry returned them
+fn changes<'a>(seen: &HashMap<String, String>, agents: &'a [Agent]) -> Vec<Change<'a>> {
+    agents
+        .iter()
+        .filter_map(|agent| match seen.get(&agent.name) {
+            None => Some(Change::Agent(agent)),
+            Some(previous) if is_newer(&agent.version, previous) => Some(Change::Version {
+                agent,
+                previous: previous.clone(),
+            }),
+            Some(_) => None,
+        })
+        .collect()
+}
+
+/// Whether `version` replaces `previous`: a higher semver version, or any
+/// different version when either isn't semver
+///
+/// Yanking the latest version makes an older one current again, which isn't
+/// news.
+fn is_newer(version: &str, previous: &str) -> bool {
+    match (Version::parse(version), Version::parse(previous)) {
+        (Ok(version), Ok(previous)) => version > previous,
+        _ => version != previous,
+    }
+}
+
+fn print_change(change: &Change) {
+    match change {
+        Change::Agent(agent) => {
+            let visibility = if agent.is_public {
+                String::new()
+            } else {
+                format!(" {}", "(private)".yellow())
+            };
+            println!(
+                "{} {} {} {} by {}{}",
+                timestamp(),
+                "New agent".green().bold(),
+                agent.name.bold().blue(),
+                agent.version,
+                agent.author.green(),
+                visibility
+            );
+            if !agent.description.is_empty() {
+                println!("  {}", agent.description);
+            }
+        }
+        Change::Version { agent, previous } => println!(
+            "{} {} {} {} → {}",
+            timestamp(),
+            "New version".cyan().bold(),
+            agent.name.bold().blue(),
+            previous.dimmed(),
+            agent.version
+        ),
+    }
+}
+
+fn timestamp() -> ColoredString {
+    chrono::Local::now()
+        .format("[%H:%M:%S]")
+        .to_string()
+        .dimmed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn agent(name: &str, version: &str) -> Agent {
+        Agent {
+            name: name.to_string(),
+            version: version.to_string(),
+            description: String::new(),
+            author: "alice".to_string(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            download_count: 0,
+            star_count: 0,
+            rating_count: 0,
+            rating_average: None,
+            tags: Vec::new(),
+            readme: None,
+            homepage: None,
+            repository: None,
+            license: None,
+            signature_bundle: None,
+            is_public: true,
+            is_template: false,
+            verified_namespace: None,
+            verified: false,
+        }
+    }
+
+    #[test]
+    fn test_changes_since_last_poll() {
+        let mut seen = HashMap::new();
+        record(
+            &mut seen,
+            &[
+                agent("code-reviewer", "1.0.0"),
+                agent("doc-writer", "2.1.0"),
+            ],
+        );
+
+        let polled = [
+            agent("test-writer", "0.1.0"),
+            agent("code-reviewer", "1.1.0"),
+            // Back to an older version once 2.1.0 was yanked
+            agent("doc-writer", "2.0.0"),
+        ];
+        let found: Vec<_> = changes(&seen, &polled)
+            .into_iter()
+            .map(|change| match change {
+                Change::Agent(agent) => (agent.name.as_str(), None),
+                Change::Version { agent, previous } => (agent.name.as_str(), Some(previous)),
+            })
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("test-writer", None),
+                ("code-reviewer", Some("1.0.0".to_string()))
+            ]
+        );
+
+        record(&mut seen, &polled);
+        assert_eq!(seen["code-reviewer"], "1.1.0");
+        assert_eq!(seen["doc-writer"], "2.1.0");
+        assert!(changes(&seen, &polled).is_empty());
+    }
+}
@@ -0,0 +1,373 @@
+//! Reading config files into one table: `${VAR}` interpolation and `include`
+//!
+//! Any string in a config file can refer to an environment variable, so
+//! secrets can stay out of files that are committed or shared:
+//!
+//! ```toml
+//! api_key = "${ACME_CARP_KEY}"
+//! registry_url = "${CARP_REGISTRY:-https://api.carp.refcell.org}"
+//! ```
+//!
+//! `${NAME:-default}` falls back to `default` when `NAME` is unset or empty;
+//! `$${` writes a literal `${`. A variable that's unset without a default is
+//! an error rather than an empty string.
+//!
+//! `include` layers other files under this one, so a team's shared settings
+//! can sit beneath personal overrides. Paths are relative to the including
+//! file; later files override earlier ones, and the including file overrides
+//! them all, table by table:
+//!
+//! ```toml
+//! include = ["~/work/carp-team.toml"]
+//! timeout = 60
+//! ```
+
+use crate::config::schema::{self, Diagnostic};
+use crate::utils::error::{CarpError, CarpResult};
+use crate::utils::file_lock;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use toml::{Table, Value};
+
+/// Key listing the files a config file includes
+pub const INCLUDE_KEY: &str = "include";
+
+/// Deepest chain of includes followed, which also bounds runaway layering
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+/// The config file at `path` with its includes layered under it and
+/// environment variables expanded. Every error in the files fails the load
+/// together; warnings are printed, once per process.
+pub fn load_table(path: &Path) -> CarpResult<Table> {
+    let (table, diagnostics) = check(path);
+    let (errors, warnings): (Vec<_>, Vec<_>) =
+        diagnostics.into_iter().partition(Diagnostic::is_error);
+
+    static WARNED: Mutex<Option<HashSet<String>>> = Mutex::new(None);
+    let mut warned = WARNED.lock().unwrap_or_else(|e| e.into_inner());
+    for warning in warnings {
+        let warning = warning.to_string();
+        if warned
+            .get_or_insert_with(HashSet::new)
+            .insert(warning.clone())
+        {
+            eprintln!("{warning}");
+        }
+    }
+
+    table.ok_or_else(|| CarpError::Config(report(&errors)))
+}
+
+/// The config file at `path` as [`load_table`] would load it, and
+/// everything wrong with it and the files it includes. There's no table
+/// when anything wrong is an error.
+pub fn check(path: &Path) -> (Option<Table>, Vec<Diagnostic>) {
+    let mut diagnostics = Vec::new();
+    let table = load_layer(path, &mut Vec::new(), &env, &mut diagnostics)
+        .filter(|_| !diagnostics.iter().any(Diagnostic::is_error));
+    (table, diagnostics)
+}
+
+/// Errors from [`check`] as one message, a line each
+pub fn report(errors: &[Diagnostic]) -> String {
+    match errors {
+        [error] => error.to_string(),
+        errors => {
+            let lines: Vec<_> = errors.iter().map(|error| format!("\n  {error}")).collect();
+            format!(
+                "{} problems in config files:{}",
+                errors.len(),
+                lines.concat()
+            )
+        }
+    }
+}
+
+fn env(name: &str) -> Option<String> {
+    std::env::var(name).ok()
+}
+
+/// The file at `path` with its includes layered under it, or `None` if it
+/// couldn't be read at all. What's wrong with it goes in `diagnostics`.
+fn load_layer(
+    path: &Path,
+    including: &mut Vec<PathBuf>,
+    lookup: &dyn Fn(&str) -> Option<String>,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Option<Table> {
+    let canonical = match path.canonicalize() {
+        Ok(canonical) => canonical,
+        Err(e) => {
+            diagnostics.push(Diagnostic::error(path, format!("can't be read: {e}")));
+            return None;
+        }
+    };
+    if including.contains(&canonical) {
+        diagnostics.push(Diagnostic::error(path, "includes itself"));
+        return None;
+    }
+    if including.len() >= MAX_INCLUDE_DEPTH {
+        diagnostics.push(Diagnostic::error(
+            path,
+            format!("includes nest more than {MAX_INCLUDE_DEPTH} deep"),
+        ));
+        return None;
+    }
+
+    let contents = match file_lock::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            diagnostics.push(Diagnostic::error(path, format!("can't be read: {e}")));
+            return None;
+        }
+    };
+    let problems = schema::check(path, &contents, schema::CONFIG);
+    let checked = !problems.iter().any(Diagnostic::is_error);
+    diagnostics.extend(problems);
+    // Not valid TOML, or not the shape deserializing expects
+    let mut table: Table = toml::from_str(&contents).ok().filter(|_| checked)?;
+    if let Err(e) = interpolate_table(&mut table, lookup) {
+        diagnostics.push(Diagnostic::error(path, e));
+        return None;
+    }
+
+    let includes = match table.remove(INCLUDE_KEY) {
+        Some(Value::Array(includes)) => includes
+            .into_iter()
+            .filter_map(|include| include.as_str().map(str::to_string))
+            .collect(),
+        _ => Vec::new(),
+    };
+    if includes.is_empty() {
+        return Some(table);
+    }
+
+    let dir = path.parent().unwrap_or(Path::new("."));
+    including.push(canonical);
+    let mut merged = Some(Table::new());
+    for include in includes {
+        let layer = load_layer(
+            &resolve_include(dir, &include),
+            including,
+            lookup,
+            diagnostics,
+        );
+        merged = merged.zip(layer).map(|(mut merged, layer)| {
+            merge(&mut merged, layer);
+            merged
+        });
+    }
+    including.pop();
+
+    let mut merged = merged?;
+    merge(&mut merged, table);
+    Some(merged)
+}
+
+/// `include` made a path, relative to the including file's directory
+fn resolve_include(dir: &Path, include: &str) -> PathBuf {
+    if let Some(rest) = include.strip_prefix("~/") {
+        if let Some(home) = dirs::home_dir() {
+            return home.join(rest);
+        }
+    }
+    dir.join(include)
+}
+
+/// Lay `over` on top of `base`, merging tables key by key and replacing
+/// everything else whole
+fn merge(base: &mut Table, over: Table) {
+    for (key, value) in over {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base)), Value::Table(over)) => merge(base, over),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+fn interpolate_table(
+    table: &mut Table,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> Result<(), String> {
+    for (key, value) in table.iter_mut() {
+        interpolate_value(value, lookup).map_err(|e| format!("{key}: {e}"))?;
+    }
+    Ok(())
+}
+
+fn interpolate_value(
+    value: &mut Value,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> Result<(), String> {
+    match value {
+        Value::String(text) => *text = interpolate(text, lookup)?,
+        Value::Array(values) => {
+            for value in values {
+                interpolate_value(value, lookup)?;
+            }
+        }
+        Value::Table(table) => interpolate_table(table, lookup)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Expand the `${NAME}` and `${NAME:-default}` references in `text`
+pub fn interpolate(text: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Result<String, String> {
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        if let Some(escaped) = after.strip_prefix("${") {
+            expanded.push_str("${");
+            rest = escaped;
+            continue;
+        }
+        let Some(reference) = after.strip_prefix('{') else {
+            expanded.push('$');
+            rest = after;
+            continue;
+        };
+        let end = reference
+            .find('}')
+            .ok_or_else(|| format!("unterminated ${{ in \"{text}\""))?;
+        let (name, default) = match reference[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&reference[..end], None),
+        };
+        if !is_variable_name(name) {
+            return Err(format!("'{name}' is not an environment variable name"));
+        }
+        match (lookup(name).filter(|value| !value.is_empty()), default) {
+            (Some(value), _) => expanded.push_str(&value),
+            (None, Some(default)) => expanded.push_str(default),
+            (None, None) => {
+                return Err(format!(
+                    "environment variable {name} is not set; set it or write ${{{name}:-default}}"
+                ))
+            }
+        }
+        rest = &reference[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+fn is_variable_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn test_env(name: &str) -> Option<String> {
+        match name {
+            "TEAM_KEY" => Some("carp_team".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        }
+    }
+
+    fn load(path: &Path) -> Result<Table, String> {
+        let mut diagnostics = Vec::new();
+        let table = load_layer(path, &mut Vec::new(), &test_env, &mut diagnostics);
+        let errors: Vec<_> = diagnostics
+            .into_iter()
+            .filter(Diagnostic::is_error)
+            .collect();
+        match table {
+            Some(table) if errors.is_empty() => Ok(table),
+            _ => Err(report(&errors)),
+        }
+    }
+
+    #[test]
+    fn test_interpolation() {
+        assert_eq!(
+            interpolate("key ${TEAM_KEY}!", &test_env).unwrap(),
+            "key carp_team!"
+        );
+        assert_eq!(
+            interpolate("${UNSET:-https://a.example}", &test_env).unwrap(),
+            "https://a.example"
+        );
+        assert_eq!(
+            interpolate("${EMPTY:-fallback}", &test_env).unwrap(),
+            "fallback"
+        );
+        assert_eq!(
+            interpolate("$${TEAM_KEY} costs $5", &test_env).unwrap(),
+            "${TEAM_KEY} costs $5"
+        );
+
+        let unset = interpolate("${UNSET}", &test_env).unwrap_err();
+        assert!(unset.contains("UNSET is not set"), "{unset}");
+        assert!(interpolate("${TEAM_KEY", &test_env).is_err());
+        assert!(interpolate("${not a name}", &test_env).is_err());
+    }
+
+    #[test]
+    fn test_includes_are_layered_under_the_including_file() {
+        let temp = TempDir::new().unwrap();
+        fs::create_dir(temp.path().join("team")).unwrap();
+        fs::write(
+            temp.path().join("team/base.toml"),
+            "registry_url = \"https://team.example\"\ntimeout = 10\n[retry]\nmax_retries = 5\ninitial_delay_ms = 50\n",
+        )
+        .unwrap();
+        fs::write(
+            temp.path().join("team/secrets.toml"),
+            "api_key = \"${TEAM_KEY}\"\ntimeout = 20\n",
+        )
+        .unwrap();
+        let config = temp.path().join("config.toml");
+        fs::write(
+            &config,
+            "include = [\"team/base.toml\", \"team/secrets.toml\"]\n[retry]\nmax_retries = 1\n",
+        )
+        .unwrap();
+
+        let table = load(&config).unwrap();
+        assert!(!table.contains_key(INCLUDE_KEY));
+        assert_eq!(table["registry_url"].as_str(), Some("https://team.example"));
+        assert_eq!(table["api_key"].as_str(), Some("carp_team"));
+        assert_eq!(table["timeout"].as_integer(), Some(20));
+        assert_eq!(table["retry"]["max_retries"].as_integer(), Some(1));
+        assert_eq!(table["retry"]["initial_delay_ms"].as_integer(), Some(50));
+    }
+
+    #[test]
+    fn test_include_cycles_and_bad_includes_are_errors() {
+        let temp = TempDir::new().unwrap();
+        let a = temp.path().join("a.toml");
+        fs::write(&a, "include = [\"b.toml\"]\n").unwrap();
+        fs::write(temp.path().join("b.toml"), "include = [\"a.toml\"]\n").unwrap();
+        let cycle = load(&a).unwrap_err();
+        assert!(cycle.contains("includes itself"), "{cycle}");
+
+        fs::write(&a, "include = \"b.toml\"\n").unwrap();
+        assert!(load(&a).is_err());
+
+        fs::write(&a, "include = [\"missing.toml\"]\n").unwrap();
+        let missing = load(&a).unwrap_err();
+        assert!(missing.contains("missing.toml"), "{missing}");
+
+        fs::write(&a, "api_key = \"${UNSET}\"\n").unwrap();
+        let unset = load(&a).unwrap_err();
+        assert!(
+            unset.contains("api_key") && unset.contains("UNSET"),
+            "{unset}"
+        );
+    }
+}
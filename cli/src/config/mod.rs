@@ -1,3 +1,7 @@
+pub mod loader;
+pub mod paths;
+pub mod project;
+pub mod schema;
 pub mod settings;
 
-pub use settings::{Config, ConfigManager};
+pub use settings::{Config, ConfigManager, SecuritySettings, TlsVersion};
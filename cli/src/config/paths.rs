@@ -0,0 +1,196 @@
+//! Where carp keeps its config, cache and data
+//!
+//! Each directory is, in order of preference:
+//!
+//! 1. under `CARP_HOME`, when it's set: the config file and data directly in
+//!    it and the cache in `$CARP_HOME/cache`
+//! 2. `carp` under `XDG_CONFIG_HOME`, `XDG_CACHE_HOME` or `XDG_DATA_HOME`,
+//!    when set to an absolute path, on any platform
+//! 3. `carp` under the platform's directory for it: `~/.config`,
+//!    `~/.cache` and `~/.local/share` on Linux, `~/Library/Application
+//!    Support` and `~/Library/Caches` on macOS, and `AppData` on Windows
+//!
+//! Without any of those, as in a container running as a user with no home
+//! directory, the cache falls back to the temporary directory; the config
+//! and data, which shouldn't disappear, need `CARP_HOME` instead.
+
+use crate::utils::error::{CarpError, CarpResult};
+use crate::utils::portable_path;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+/// Overrides every directory carp uses
+pub const CARP_HOME: &str = "CARP_HOME";
+
+/// One of the directories carp keeps files in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Config,
+    Cache,
+    Data,
+}
+
+/// A resolved directory and what decided it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub dir: PathBuf,
+    pub source: Source,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    CarpHome,
+    /// The XDG variable that was set
+    Xdg(&'static str),
+    Platform,
+    TempDir,
+}
+
+impl Kind {
+    fn xdg_var(self) -> &'static str {
+        match self {
+            Kind::Config => "XDG_CONFIG_HOME",
+            Kind::Cache => "XDG_CACHE_HOME",
+            Kind::Data => "XDG_DATA_HOME",
+        }
+    }
+
+    fn platform_dir(self) -> Option<PathBuf> {
+        match self {
+            Kind::Config => dirs::config_dir(),
+            Kind::Cache => dirs::cache_dir(),
+            Kind::Data => dirs::data_dir(),
+        }
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Kind::Config => "config",
+            Kind::Cache => "cache",
+            Kind::Data => "data",
+        })
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::CarpHome => f.write_str(CARP_HOME),
+            Source::Xdg(var) => f.write_str(var),
+            Source::Platform => f.write_str("platform default"),
+            Source::TempDir => f.write_str("no home directory, so temporary"),
+        }
+    }
+}
+
+/// Where carp keeps files of `kind`, from the environment
+pub fn resolve(kind: Kind) -> CarpResult<Location> {
+    resolve_from(kind, |var| std::env::var_os(var), kind.platform_dir()).ok_or_else(|| {
+        CarpError::Config(format!(
+            "Unable to find a {kind} directory; set {CARP_HOME} or {}",
+            kind.xdg_var()
+        ))
+    })
+}
+
+/// Where carp keeps files of `kind`, created if it doesn't exist yet
+pub fn dir(kind: Kind) -> CarpResult<PathBuf> {
+    let dir = portable_path::long(&resolve(kind)?.dir);
+    if !dir.exists() {
+        fs::create_dir_all(&dir)?;
+    }
+    Ok(dir)
+}
+
+fn resolve_from(
+    kind: Kind,
+    var: impl Fn(&str) -> Option<OsString>,
+    platform: Option<PathBuf>,
+) -> Option<Location> {
+    // Unset and empty are the same, as in the XDG spec
+    let var = |name: &str| {
+        var(name)
+            .filter(|value| !value.is_empty())
+            .map(PathBuf::from)
+    };
+
+    if let Some(home) = var(CARP_HOME) {
+        let dir = match kind {
+            Kind::Cache => home.join("cache"),
+            Kind::Config | Kind::Data => home,
+        };
+        return Some(Location {
+            dir,
+            source: Source::CarpHome,
+        });
+    }
+
+    // The spec says relative paths are invalid and should be ignored
+    if let Some(base) = var(kind.xdg_var()).filter(|base| base.is_absolute()) {
+        return Some(Location {
+            dir: base.join("carp"),
+            source: Source::Xdg(kind.xdg_var()),
+        });
+    }
+
+    if let Some(base) = platform {
+        return Some(Location {
+            dir: base.join("carp"),
+            source: Source::Platform,
+        });
+    }
+
+    (kind == Kind::Cache).then(|| Location {
+        dir: std::env::temp_dir().join("carp-cache"),
+        source: Source::TempDir,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env<'a>(vars: &'a [(&str, &str)]) -> impl Fn(&str) -> Option<OsString> + 'a {
+        |name| {
+            vars.iter()
+                .find(|(var, _)| *var == name)
+                .map(|(_, value)| OsString::from(value))
+        }
+    }
+
+    #[test]
+    fn test_resolve_from() {
+        let platform = Some(PathBuf::from("/home/me/.local/share"));
+
+        let home = [
+            ("CARP_HOME", "/opt/carp"),
+            ("XDG_CACHE_HOME", "/tmp/xdg-cache"),
+        ];
+        let location = resolve_from(Kind::Cache, env(&home), platform.clone()).unwrap();
+        assert_eq!(location.dir, PathBuf::from("/opt/carp/cache"));
+        assert_eq!(location.source, Source::CarpHome);
+        let location = resolve_from(Kind::Config, env(&home), platform.clone()).unwrap();
+        assert_eq!(location.dir, PathBuf::from("/opt/carp"));
+
+        let xdg = [("CARP_HOME", ""), ("XDG_DATA_HOME", "/srv/data")];
+        let location = resolve_from(Kind::Data, env(&xdg), platform.clone()).unwrap();
+        assert_eq!(location.dir, PathBuf::from("/srv/data/carp"));
+        assert_eq!(location.source, Source::Xdg("XDG_DATA_HOME"));
+
+        let relative = [("XDG_DATA_HOME", "data")];
+        let location = resolve_from(Kind::Data, env(&relative), platform).unwrap();
+        assert_eq!(location.dir, PathBuf::from("/home/me/.local/share/carp"));
+        assert_eq!(location.source, Source::Platform);
+
+        // Without a home, only the cache has somewhere to go
+        assert_eq!(
+            resolve_from(Kind::Cache, env(&[]), None).unwrap().source,
+            Source::TempDir
+        );
+        assert_eq!(resolve_from(Kind::Config, env(&[]), None), None);
+    }
+}
@@ -0,0 +1,202 @@
+//! Per-project settings from `.carp/config.toml`
+
+use crate::config::loader;
+use crate::config::schema::{self, Diagnostic};
+use crate::config::settings::Config;
+use crate::utils::error::{CarpError, CarpResult};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Directory at a project's root holding its carp settings
+pub const PROJECT_DIR: &str = ".carp";
+/// Name of the project config file in [`PROJECT_DIR`]
+pub const PROJECT_CONFIG_FILE: &str = "config.toml";
+
+/// Settings a project overrides for carp run anywhere inside it, read from
+/// `.carp/config.toml` in the working directory or the nearest parent that
+/// has one
+///
+/// Paths are relative to the project root, the directory holding `.carp`:
+///
+/// ```toml
+/// output_dir = ".claude/agents"
+/// registry_url = "https://carp.acme.internal"
+/// policy = ".carp/policy.toml"
+/// ```
+///
+/// They take precedence over the user's config file, and the `CARP_*`
+/// environment variables and command-line flags over them.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectConfig {
+    /// Where `carp pull` saves agents
+    pub output_dir: Option<String>,
+    pub registry_url: Option<String>,
+    /// Policy file `carp pull` enforces when `--policy` isn't given
+    pub policy: Option<String>,
+}
+
+/// A project's config and where it was found
+#[derive(Debug, Clone)]
+pub struct Project {
+    /// The directory holding `.carp`
+    pub root: PathBuf,
+    pub config: ProjectConfig,
+}
+
+impl Project {
+    /// Path of the project config file for `start`, looking in it and then
+    /// each of its parents
+    pub fn find(start: &Path) -> Option<PathBuf> {
+        start
+            .ancestors()
+            .map(|dir| dir.join(PROJECT_DIR).join(PROJECT_CONFIG_FILE))
+            .find(|path| path.is_file())
+    }
+
+    /// The project the working directory is in, if any
+    pub fn discover() -> CarpResult<Option<Self>> {
+        let Ok(cwd) = std::env::current_dir() else {
+            return Ok(None);
+        };
+        Self::find(&cwd).map(|path| Self::load(&path)).transpose()
+    }
+
+    /// Load the project config file at `path`
+    pub fn load(path: &Path) -> CarpResult<Self> {
+        let contents = fs::read_to_string(path).map_err(|e| {
+            CarpError::Config(format!(
+                "Failed to read project config '{}': {e}",
+                path.display()
+            ))
+        })?;
+        let errors: Vec<_> = schema::check(path, &contents, schema::PROJECT)
+            .into_iter()
+            .filter(Diagnostic::is_error)
+            .collect();
+        if !errors.is_empty() {
+            return Err(CarpError::Config(loader::report(&errors)));
+        }
+        let config: ProjectConfig = toml::from_str(&contents).map_err(|e| {
+            CarpError::Config(format!(
+                "Failed to parse project config '{}': {e}",
+                path.display()
+            ))
+        })?;
+
+        // `<root>/.carp/config.toml`
+        let root = path
+            .parent()
+            .and_then(Path::parent)
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        Ok(Self { root, config })
+    }
+
+    /// Everything wrong with the project config file at `path`
+    pub fn check(path: &Path) -> Vec<Diagnostic> {
+        match fs::read_to_string(path) {
+            Ok(contents) => schema::check(path, &contents, schema::PROJECT),
+            Err(e) => vec![Diagnostic::error(path, format!("can't be read: {e}"))],
+        }
+    }
+
+    /// Override `config` with what the project sets
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(output_dir) = &self.config.output_dir {
+            config.default_output_dir = Some(self.resolve(output_dir));
+        }
+        if let Some(registry_url) = &self.config.registry_url {
+            config.registry_url = registry_url.clone();
+        }
+        if let Some(policy) = &self.config.policy {
+            config.policy = Some(self.resolve(policy));
+        }
+    }
+
+    /// `path` made absolute against the project root
+    fn resolve(&self, path: &str) -> String {
+        self.root.join(path).to_string_lossy().into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn write_project(root: &Path, contents: &str) -> PathBuf {
+        let path = root.join(PROJECT_DIR).join(PROJECT_CONFIG_FILE);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_found_from_nested_directories() {
+        let temp = TempDir::new().unwrap();
+        let path = write_project(temp.path(), "");
+        let nested = temp.path().join("src/agents/deep");
+        fs::create_dir_all(&nested).unwrap();
+
+        assert_eq!(Project::find(&nested), Some(path.clone()));
+        assert_eq!(Project::find(temp.path()), Some(path));
+    }
+
+    #[test]
+    fn test_overrides_are_relative_to_the_project_root() {
+        let temp = TempDir::new().unwrap();
+        let path = write_project(
+            temp.path(),
+            r#"
+output_dir = ".claude/agents"
+registry_url = "https://carp.acme.internal"
+policy = ".carp/policy.toml"
+"#,
+        );
+
+        let project = Project::load(&path).unwrap();
+        assert_eq!(project.root, temp.path());
+
+        let mut config = Config {
+            default_output_dir: Some("~/agents".to_string()),
+            ..Config::default()
+        };
+        project.apply(&mut config);
+        assert_eq!(
+            config.default_output_dir.map(PathBuf::from),
+            Some(temp.path().join(".claude/agents"))
+        );
+        assert_eq!(config.registry_url, "https://carp.acme.internal");
+        assert_eq!(
+            config.policy.map(PathBuf::from),
+            Some(temp.path().join(".carp/policy.toml"))
+        );
+    }
+
+    #[test]
+    fn test_unset_settings_are_left_alone() {
+        let temp = TempDir::new().unwrap();
+        let path = write_project(temp.path(), "output_dir = \"agents\"\n");
+
+        let mut config = Config::default();
+        Project::load(&path).unwrap().apply(&mut config);
+        assert_eq!(config.registry_url, Config::default().registry_url);
+        assert!(config.policy.is_none());
+    }
+
+    #[test]
+    fn test_unknown_keys_are_rejected() {
+        let temp = TempDir::new().unwrap();
+        let path = write_project(temp.path(), "output = \"agents\"\n");
+
+        let error = Project::load(&path).unwrap_err().to_string();
+        assert!(
+            error.contains(
+                "config.toml:1:1: error: unknown key `output` (did you mean `output_dir`?)"
+            ),
+            "{error}"
+        );
+    }
+}
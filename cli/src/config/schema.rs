@@ -0,0 +1,416 @@
+//! Checking config files against the settings carp knows
+//!
+//! serde ignores keys it doesn't know and stops at the first bad value with
+//! little context, so a typo'd `timout` silently does nothing. Before a
+//! config file is deserialized it's walked against a [`Schema`], and every
+//! unknown key, value of the wrong type and deprecated option is reported
+//! with the line and column it's at:
+//!
+//! ```text
+//! ~/.config/carp/config.toml:4:1: warning: unknown key `timout` (did you mean `timeout`?)
+//! ~/.config/carp/config.toml:9:15: error: `retry.max_retries` should be a non-negative integer, not "3"
+//! ```
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use toml_edit::{ImDocument, Item, TableLike, Value};
+
+/// The type a setting's value has to be
+#[derive(Debug, Clone, Copy)]
+pub enum Kind {
+    String,
+    /// A non-negative integer
+    Integer,
+    /// A number, integer or not
+    Float,
+    Boolean,
+    /// An array of strings
+    Strings,
+    /// One of these strings
+    OneOf(&'static [&'static str]),
+    Table(&'static [Field]),
+}
+
+impl Kind {
+    fn describe(self) -> String {
+        match self {
+            Kind::String => "a string".to_string(),
+            Kind::Integer => "a non-negative integer".to_string(),
+            Kind::Float => "a number".to_string(),
+            Kind::Boolean => "true or false".to_string(),
+            Kind::Strings => "an array of strings".to_string(),
+            Kind::OneOf(choices) => {
+                let choices: Vec<_> = choices
+                    .iter()
+                    .map(|choice| format!("\"{choice}\""))
+                    .collect();
+                format!("one of {}", choices.join(", "))
+            }
+            Kind::Table(_) => "a table".to_string(),
+        }
+    }
+}
+
+/// A setting a config file can have
+#[derive(Debug, Clone, Copy)]
+pub struct Field {
+    pub name: &'static str,
+    pub kind: Kind,
+    /// What to use instead, when the setting is deprecated
+    pub deprecated: Option<&'static str>,
+}
+
+const fn field(name: &'static str, kind: Kind) -> Field {
+    Field {
+        name,
+        kind,
+        deprecated: None,
+    }
+}
+
+/// The settings a kind of config file has, and how seriously to take keys
+/// that aren't among them
+#[derive(Debug, Clone, Copy)]
+pub struct Schema {
+    pub fields: &'static [Field],
+    pub unknown_keys: Severity,
+}
+
+const RETRY: &[Field] = &[
+    field("max_retries", Kind::Integer),
+    field("initial_delay_ms", Kind::Integer),
+    field("max_delay_ms", Kind::Integer),
+    field("backoff_multiplier", Kind::Float),
+];
+
+const SECURITY: &[Field] = &[
+    field("max_download_size", Kind::Integer),
+    field("max_publish_size", Kind::Integer),
+    field("allow_http", Kind::Boolean),
+    field("token_warning_hours", Kind::Integer),
+    field("max_extracted_entry_size", Kind::Integer),
+    field("max_extracted_size", Kind::Integer),
+    field("allow_archive_symlinks", Kind::Boolean),
+    field("min_tls_version", Kind::OneOf(&["1.2", "1.3"])),
+    field("spki_pins", Kind::Strings),
+    field("resolution_key", Kind::String),
+];
+
+/// The user's config file and the files it includes. Unknown keys are only
+/// warned about, since a config written for a newer carp should still load.
+pub const CONFIG: Schema = Schema {
+    fields: &[
+        field(super::loader::INCLUDE_KEY, Kind::Strings),
+        field("registry_url", Kind::String),
+        field("api_key", Kind::String),
+        Field {
+            name: "api_token",
+            kind: Kind::String,
+            deprecated: Some("api_key"),
+        },
+        field("timeout", Kind::Integer),
+        field("verify_ssl", Kind::Boolean),
+        field("default_output_dir", Kind::String),
+        field("max_concurrent_downloads", Kind::Integer),
+        field("retry", Kind::Table(RETRY)),
+        field("security", Kind::Table(SECURITY)),
+        field("telemetry", Kind::Boolean),
+        field("policy", Kind::String),
+    ],
+    unknown_keys: Severity::Warning,
+};
+
+/// A project's `.carp/config.toml`
+pub const PROJECT: Schema = Schema {
+    fields: &[
+        field("output_dir", Kind::String),
+        field("registry_url", Kind::String),
+        field("policy", Kind::String),
+    ],
+    unknown_keys: Severity::Error,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem with a config file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub file: PathBuf,
+    /// 1-based line and column, when the problem is at a place in the file
+    pub position: Option<(usize, usize)>,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// An error with `file` as a whole, such as it being unreadable
+    pub fn error(file: &Path, message: impl Into<String>) -> Self {
+        Self {
+            file: file.to_path_buf(),
+            position: None,
+            severity: Severity::Error,
+            message: message.into(),
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.file.display())?;
+        if let Some((line, column)) = self.position {
+            write!(f, ":{line}:{column}")?;
+        }
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, ": {severity}: {}", self.message)
+    }
+}
+
+/// Everything wrong with `contents`, read from `file`, measured against
+/// `schema`. A file that isn't valid TOML has just the syntax error.
+pub fn check(file: &Path, contents: &str, schema: Schema) -> Vec<Diagnostic> {
+    let document = match ImDocument::parse(contents) {
+        Ok(document) => document,
+        Err(e) => {
+            return vec![Diagnostic {
+                file: file.to_path_buf(),
+                position: e.span().map(|span| position(contents, span.start)),
+                severity: Severity::Error,
+                message: e.message().trim_end().to_string(),
+            }]
+        }
+    };
+
+    let mut checker = Checker {
+        file,
+        contents,
+        unknown_keys: schema.unknown_keys,
+        diagnostics: Vec::new(),
+    };
+    checker.table(document.as_table(), schema.fields, "");
+    checker.diagnostics
+}
+
+struct Checker<'a> {
+    file: &'a Path,
+    contents: &'a str,
+    unknown_keys: Severity,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Checker<'_> {
+    fn table(&mut self, table: &dyn TableLike, fields: &[Field], prefix: &str) {
+        for (name, item) in table.iter() {
+            let (key, _) = table.get_key_value(name).expect("iterated key");
+            let at = key.span().or_else(|| item.span()).map(|span| span.start);
+            let path = format!("{prefix}{name}");
+
+            let Some(field) = fields.iter().find(|field| field.name == name) else {
+                let mut message = format!("unknown key `{path}`");
+                if let Some(suggestion) = closest(name, fields.iter().map(|field| field.name)) {
+                    message.push_str(&format!(" (did you mean `{prefix}{suggestion}`?)"));
+                }
+                self.report(at, self.unknown_keys, message);
+                continue;
+            };
+            if let Some(replacement) = field.deprecated {
+                self.report(
+                    at,
+                    Severity::Warning,
+                    format!("`{path}` is deprecated; use `{prefix}{replacement}` instead"),
+                );
+            }
+
+            if let Kind::Table(fields) = field.kind {
+                match item.as_table_like() {
+                    Some(table) => self.table(table, fields, &format!("{path}.")),
+                    None => self.mismatch(item, &path, field.kind),
+                }
+            } else if !matches(item, field.kind) {
+                self.mismatch(item, &path, field.kind);
+            }
+        }
+    }
+
+    fn mismatch(&mut self, item: &Item, path: &str, kind: Kind) {
+        let found = match item.as_value() {
+            Some(Value::String(value)) => format!("\"{}\"", value.value()),
+            Some(Value::Integer(value)) if *value.value() < 0 => {
+                format!("{}", value.value())
+            }
+            Some(Value::Array(values)) => match values.iter().find(|value| !value.is_str()) {
+                Some(other) => format!("an array holding {}", a(other.type_name())),
+                None => a("array"),
+            },
+            _ => a(item.type_name()),
+        };
+        let at = item.span().map(|span| span.start);
+        self.report(
+            at,
+            Severity::Error,
+            format!("`{path}` should be {}, not {found}", kind.describe()),
+        );
+    }
+
+    fn report(&mut self, at: Option<usize>, severity: Severity, message: String) {
+        self.diagnostics.push(Diagnostic {
+            file: self.file.to_path_buf(),
+            position: at.map(|offset| position(self.contents, offset)),
+            severity,
+            message,
+        });
+    }
+}
+
+/// Whether `item` is a valid value of `kind`
+fn matches(item: &Item, kind: Kind) -> bool {
+    let Some(value) = item.as_value() else {
+        return false;
+    };
+    match (kind, value) {
+        (Kind::String, Value::String(_)) | (Kind::Boolean, Value::Boolean(_)) => true,
+        (Kind::Integer, Value::Integer(value)) => *value.value() >= 0,
+        (Kind::Float, Value::Float(_) | Value::Integer(_)) => true,
+        (Kind::Strings, Value::Array(values)) => values.iter().all(Value::is_str),
+        // A `${VAR}` reference is only known once it's expanded
+        (Kind::OneOf(choices), Value::String(value)) => {
+            choices.contains(&value.value().as_str()) || value.value().contains("${")
+        }
+        _ => false,
+    }
+}
+
+/// `type_name` with its indefinite article
+fn a(type_name: &str) -> String {
+    let article = if type_name.starts_with(['a', 'e', 'i', 'o', 'u']) {
+        "an"
+    } else {
+        "a"
+    };
+    format!("{article} {type_name}")
+}
+
+/// 1-based line and column of the byte `offset` in `contents`
+fn position(contents: &str, offset: usize) -> (usize, usize) {
+    let before = &contents[..offset.min(contents.len())];
+    let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
+}
+
+/// The name in `candidates` that `name` is most likely a typo or
+/// abbreviation of
+fn closest<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    candidates
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, candidate)| {
+            *distance <= (candidate.len() / 3).max(1)
+                || (name.len() >= 3 && candidate.starts_with(name))
+                || name.starts_with(candidate)
+        })
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Levenshtein distance between `a` and `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn problems(contents: &str) -> Vec<String> {
+        check(Path::new("config.toml"), contents, CONFIG)
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_the_default_config_is_valid() {
+        let config = Config {
+            api_key: Some("carp_key".to_string()),
+            policy: Some("policy.toml".to_string()),
+            ..Config::default()
+        };
+        let contents = format!(
+            "include = [\"team.toml\"]\n{}",
+            toml::to_string_pretty(&config).unwrap()
+        );
+        assert_eq!(problems(&contents), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_problems_are_reported_where_they_are() {
+        let contents = r#"registry_url = "https://api.carp.refcell.org"
+timout = 60
+api_token = "carp_legacy"
+verify_ssl = "yes"
+
+[retry]
+max_retries = -1
+backoff_multiplier = 2
+
+[security]
+min_tls_version = "1.1"
+spki_pins = ["sha256/abc", 7]
+"#;
+        assert_eq!(
+            problems(contents),
+            [
+                "config.toml:2:1: warning: unknown key `timout` (did you mean `timeout`?)",
+                "config.toml:3:1: warning: `api_token` is deprecated; use `api_key` instead",
+                "config.toml:4:14: error: `verify_ssl` should be true or false, not \"yes\"",
+                "config.toml:7:15: error: `retry.max_retries` should be a non-negative integer, not -1",
+                "config.toml:11:19: error: `security.min_tls_version` should be one of \"1.2\", \"1.3\", not \"1.1\"",
+                "config.toml:12:13: error: `security.spki_pins` should be an array of strings, not an array holding an integer",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_inline_and_dotted_tables_are_checked() {
+        assert_eq!(
+            problems("retry = { max_retry = 2 }\nsecurity.allow_https = true\n"),
+            [
+                "config.toml:1:11: warning: unknown key `retry.max_retry` (did you mean `retry.max_retries`?)",
+                "config.toml:2:10: warning: unknown key `security.allow_https` (did you mean `security.allow_http`?)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_syntax_errors_have_a_position() {
+        let problems = problems("timeout = 30\nregistry_url = \n");
+        assert_eq!(problems.len(), 1);
+        assert!(
+            problems[0].starts_with("config.toml:2:16: error:"),
+            "{problems:?}"
+        );
+    }
+}
@@ -1,7 +1,16 @@
+use crate::config::loader;
+use crate::config::paths::{self, Kind};
+use crate::config::project::Project;
+use crate::config::schema::Diagnostic;
 use crate::utils::error::{CarpError, CarpResult};
+use crate::utils::file_lock::LockedFile;
 use serde::{Deserialize, Serialize};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use toml_edit::DocumentMut;
+
+/// Name of the config file in the config directory
+pub const CONFIG_FILE: &str = "config.toml";
 
 /// Configuration structure for the Carp CLI
 #[derive(Clone, Serialize, Deserialize)]
@@ -28,6 +37,12 @@ pub struct Config {
     /// Security settings
     #[serde(default)]
     pub security: SecuritySettings,
+    /// Whether anonymous usage telemetry is sent (opt-in)
+    #[serde(default)]
+    pub telemetry: bool,
+    /// Policy file `carp pull` enforces when `--policy` isn't given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub policy: Option<String>,
 }
 
 /// Retry configuration settings
@@ -62,6 +77,52 @@ pub struct SecuritySettings {
     /// Token expiry warning threshold in hours
     #[serde(default = "default_token_warning_hours")]
     pub token_warning_hours: u64,
+    /// Maximum size of a single file extracted from an agent package
+    #[serde(default = "default_max_extracted_entry_size")]
+    pub max_extracted_entry_size: u64,
+    /// Maximum total size of all files extracted from an agent package
+    #[serde(default = "default_max_extracted_size")]
+    pub max_extracted_size: u64,
+    /// Whether agent packages may contain symlinks (confined to the package)
+    #[serde(default)]
+    pub allow_archive_symlinks: bool,
+    /// Oldest TLS version connections may use; unset leaves it to the TLS
+    /// library
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_tls_version: Option<TlsVersion>,
+    /// SHA-256 hashes of public keys as `sha256/<base64>`, one of which the
+    /// registry host's certificate chain must contain
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub spki_pins: Vec<String>,
+    /// Base64 Ed25519 public key the registry signs resolutions with; unset,
+    /// `carp pull --with-deps` trusts the key a project's `carp.lock` first
+    /// recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolution_key: Option<String>,
+}
+
+/// A TLS version `min_tls_version` can require
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum TlsVersion {
+    #[serde(rename = "1.2")]
+    Tls12,
+    #[serde(rename = "1.3")]
+    Tls13,
+}
+
+impl TlsVersion {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TlsVersion::Tls12 => "1.2",
+            TlsVersion::Tls13 => "1.3",
+        }
+    }
+
+    pub fn parse(version: &str) -> Option<Self> {
+        [TlsVersion::Tls12, TlsVersion::Tls13]
+            .into_iter()
+            .find(|known| known.as_str() == version)
+    }
 }
 
 // Default value functions
@@ -89,6 +150,12 @@ fn default_max_publish_size() -> u64 {
 fn default_token_warning_hours() -> u64 {
     24
 }
+fn default_max_extracted_entry_size() -> u64 {
+    50 * 1024 * 1024
+} // 50MB
+fn default_max_extracted_size() -> u64 {
+    500 * 1024 * 1024
+} // 500MB
 
 impl Default for RetrySettings {
     fn default() -> Self {
@@ -108,6 +175,12 @@ impl Default for SecuritySettings {
             max_publish_size: default_max_publish_size(),
             allow_http: false,
             token_warning_hours: default_token_warning_hours(),
+            max_extracted_entry_size: default_max_extracted_entry_size(),
+            max_extracted_size: default_max_extracted_size(),
+            allow_archive_symlinks: false,
+            min_tls_version: None,
+            spki_pins: Vec::new(),
+            resolution_key: None,
         }
     }
 }
@@ -124,6 +197,8 @@ impl std::fmt::Debug for Config {
             .field("max_concurrent_downloads", &self.max_concurrent_downloads)
             .field("retry", &self.retry)
             .field("security", &self.security)
+            .field("telemetry", &self.telemetry)
+            .field("policy", &self.policy)
             .finish()
     }
 }
@@ -140,6 +215,8 @@ impl Default for Config {
             max_concurrent_downloads: default_max_concurrent_downloads(),
             retry: RetrySettings::default(),
             security: SecuritySettings::default(),
+            telemetry: false,
+            policy: None,
         }
     }
 }
@@ -150,51 +227,59 @@ pub struct ConfigManager;
 impl ConfigManager {
     /// Get the path to the config file
     pub fn config_path() -> CarpResult<PathBuf> {
-        let config_dir = dirs::config_dir()
-            .ok_or_else(|| CarpError::Config("Unable to find config directory".to_string()))?;
-
-        let carp_dir = config_dir.join("carp");
-        if !carp_dir.exists() {
-            fs::create_dir_all(&carp_dir)?;
-        }
-
-        Ok(carp_dir.join("config.toml"))
+        Ok(paths::dir(Kind::Config)?.join(CONFIG_FILE))
     }
 
-    /// Load configuration from file, creating default if it doesn't exist
+    /// Load configuration from file, creating default if it doesn't exist,
+    /// with the settings of the project the working directory is in and then
+    /// the environment on top
     pub fn load() -> CarpResult<Config> {
         let config_path = Self::config_path()?;
 
         let mut config = if config_path.exists() {
-            let contents = fs::read_to_string(&config_path)
-                .map_err(|e| CarpError::Config(format!("Failed to read config file: {e}")))?;
-
-            toml::from_str::<Config>(&contents)?
+            Self::parse_file(&config_path)?
         } else {
             let default_config = Config::default();
             Self::save(&default_config)?;
             default_config
         };
 
+        // Handle backward compatibility: migrate api_token to api_key. This
+        // saves the config, so comes before anything not from the file.
+        Self::migrate_legacy_token(&mut config)?;
+
+        // A project's `.carp/config.toml` overrides the user's settings
+        if let Some(project) = Project::discover()? {
+            project.apply(&mut config);
+        }
+
         // Override with environment variables if present
         Self::apply_env_overrides(&mut config)?;
 
-        // Handle backward compatibility: migrate api_token to api_key
-        Self::migrate_legacy_token(&mut config)?;
-
         // Validate configuration
         Self::validate_config(&config)?;
 
         Ok(config)
     }
 
+    /// The config file at `path` with its includes and environment
+    /// variables resolved
+    fn parse_file(path: &Path) -> CarpResult<Config> {
+        Ok(toml::Value::Table(loader::load_table(path)?).try_into::<Config>()?)
+    }
+
     /// Migrate legacy api_token to api_key for backward compatibility
     fn migrate_legacy_token(config: &mut Config) -> CarpResult<()> {
         // If we have an api_token but no api_key, migrate it
         if config.api_key.is_none() && config.api_token.is_some() {
             config.api_key = config.api_token.take();
-            // Save the migrated config
-            if let Err(e) = Self::save(config) {
+            // Rename it in the file, leaving includes and ${VAR}s as written
+            let renamed = Self::update(|document| {
+                if let Some(token) = document.remove("api_token") {
+                    document.insert("api_key", token);
+                }
+            });
+            if let Err(e) = renamed {
                 eprintln!("Warning: Failed to save migrated configuration: {e}");
             } else {
                 eprintln!("Info: Migrated api_token to api_key in configuration file.");
@@ -239,6 +324,21 @@ impl ConfigManager {
             config.default_output_dir = Some(output_dir);
         }
 
+        // Telemetry can be switched off per environment; it is never
+        // switched on by the environment alone
+        if crate::utils::telemetry::disabled_by_env().is_some() {
+            config.telemetry = false;
+        }
+
+        if let Ok(version) = std::env::var("CARP_MIN_TLS_VERSION") {
+            let version = TlsVersion::parse(&version).ok_or_else(|| {
+                CarpError::Config(
+                    "Invalid CARP_MIN_TLS_VERSION value; expected 1.2 or 1.3".to_string(),
+                )
+            })?;
+            config.security.min_tls_version = Some(version);
+        }
+
         // Allow HTTP (for development/testing)
         if let Ok(allow_http_str) = std::env::var("CARP_ALLOW_HTTP") {
             config.security.allow_http = allow_http_str
@@ -302,6 +402,12 @@ impl ConfigManager {
             ));
         }
 
+        if config.security.max_extracted_entry_size > config.security.max_extracted_size {
+            return Err(CarpError::Config(
+                "Maximum extracted entry size cannot exceed the maximum extracted size".to_string(),
+            ));
+        }
+
         // Warn about insecure settings
         if !config.verify_ssl {
             eprintln!("Warning: SSL verification is disabled. This is insecure and not recommended for production use.");
@@ -315,7 +421,7 @@ impl ConfigManager {
     }
 
     /// Validate registry URL format and security
-    fn validate_registry_url(url: &str) -> CarpResult<()> {
+    pub(crate) fn validate_registry_url(url: &str) -> CarpResult<()> {
         // Basic URL validation
         if url.is_empty() {
             return Err(CarpError::Config(
@@ -339,40 +445,101 @@ impl ConfigManager {
 
     /// Save configuration to file
     pub fn save(config: &Config) -> CarpResult<()> {
-        let config_path = Self::config_path()?;
-        let contents = toml::to_string_pretty(config)
-            .map_err(|e| CarpError::Config(format!("Failed to serialize config: {e}")))?;
+        let mut file = Self::open_locked()?;
+        Self::write_locked(&mut file, config)
+    }
+
+    /// Change the config file as written, without environment overrides,
+    /// holding its lock from reading it to writing it back so concurrent
+    /// carp invocations don't lose each other's changes. Only the keys
+    /// `change` touches are rewritten, so comments, includes and `${VAR}`
+    /// references survive.
+    fn update(change: impl FnOnce(&mut DocumentMut)) -> CarpResult<()> {
+        let mut file = Self::open_locked()?;
+        let mut contents = file
+            .read_to_string()
+            .map_err(|e| CarpError::Config(format!("Failed to read config file: {e}")))?;
+        // Empty when the lock just created it
+        if contents.trim().is_empty() {
+            contents = toml::to_string_pretty(&Config::default())
+                .map_err(|e| CarpError::Config(format!("Failed to serialize config: {e}")))?;
+        }
+        let mut document = contents
+            .parse::<DocumentMut>()
+            .map_err(|e| CarpError::Config(format!("Failed to parse config file: {e}")))?;
+        change(&mut document);
+        file.replace(document.to_string())
+            .map_err(|e| CarpError::Config(format!("Failed to write config file: {e}")))
+    }
 
-        fs::write(&config_path, contents)
-            .map_err(|e| CarpError::Config(format!("Failed to write config file: {e}")))?;
+    fn open_locked() -> CarpResult<LockedFile> {
+        let file = LockedFile::open(&Self::config_path()?)
+            .map_err(|e| CarpError::Config(format!("Failed to open config file: {e}")))?;
 
         // Set restrictive permissions on config file (600 - owner read/write only)
+        // before anything is written to it
         #[cfg(unix)]
         {
             use std::os::unix::fs::PermissionsExt;
-            let mut perms = fs::metadata(&config_path)?.permissions();
-            perms.set_mode(0o600);
-            fs::set_permissions(&config_path, perms)?;
+            file.file()
+                .set_permissions(fs::Permissions::from_mode(0o600))?;
         }
 
-        Ok(())
+        Ok(file)
+    }
+
+    fn write_locked(file: &mut LockedFile, config: &Config) -> CarpResult<()> {
+        let contents = toml::to_string_pretty(config)
+            .map_err(|e| CarpError::Config(format!("Failed to serialize config: {e}")))?;
+        file.replace(contents)
+            .map_err(|e| CarpError::Config(format!("Failed to write config file: {e}")))
     }
 
     /// Update the API key in the config
     #[allow(dead_code)]
     pub fn set_api_key(api_key: String) -> CarpResult<()> {
-        let mut config = Self::load()?;
-        config.api_key = Some(api_key);
-        config.api_token = None; // Clear legacy token
-        Self::save(&config)
+        Self::update(|document| {
+            document["api_key"] = toml_edit::value(api_key);
+            document.remove("api_token"); // Clear legacy token
+        })
     }
 
     /// Clear the API key from the config
     pub fn clear_api_key() -> CarpResult<()> {
-        let mut config = Self::load()?;
-        config.api_key = None;
-        config.api_token = None; // Also clear legacy token
-        Self::save(&config)
+        Self::update(|document| {
+            document.remove("api_key");
+            document.remove("api_token"); // Also clear legacy token
+        })
+    }
+
+    /// Pin the key dependency resolutions must be signed with
+    pub fn set_resolution_key(key: &str) -> CarpResult<()> {
+        Self::update(|document| {
+            if !document.contains_table("security") {
+                document["security"] = toml_edit::table();
+            }
+            document["security"]["resolution_key"] = toml_edit::value(key);
+        })
+    }
+
+    /// Turn usage telemetry on or off in the config file
+    pub fn set_telemetry(enabled: bool) -> CarpResult<()> {
+        Self::update(|document| document["telemetry"] = toml_edit::value(enabled))
+    }
+
+    /// Whether the config file has telemetry turned on, ignoring the
+    /// environment
+    pub fn telemetry_enabled_in_file() -> CarpResult<bool> {
+        Ok(Self::read_file()?.is_some_and(|config| config.telemetry))
+    }
+
+    /// The config file as written, without environment overrides
+    fn read_file() -> CarpResult<Option<Config>> {
+        let config_path = Self::config_path()?;
+        if !config_path.exists() {
+            return Ok(None);
+        }
+        Self::parse_file(&config_path).map(Some)
     }
 
     /// Legacy method for backward compatibility
@@ -390,17 +557,13 @@ impl ConfigManager {
     }
 
     /// Get the cache directory for storing downloaded agents
-    #[allow(dead_code)]
     pub fn cache_dir() -> CarpResult<PathBuf> {
-        let cache_dir = dirs::cache_dir()
-            .ok_or_else(|| CarpError::Config("Unable to find cache directory".to_string()))?;
-
-        let carp_cache = cache_dir.join("carp");
-        if !carp_cache.exists() {
-            fs::create_dir_all(&carp_cache)?;
-        }
+        paths::dir(Kind::Cache)
+    }
 
-        Ok(carp_cache)
+    /// Get the data directory, where pulled agents go by default
+    pub fn data_dir() -> CarpResult<PathBuf> {
+        paths::dir(Kind::Data)
     }
 
     /// Get configuration with runtime environment checks
@@ -408,7 +571,7 @@ impl ConfigManager {
         let config = Self::load()?;
 
         // Check for common CI/CD environment variables and adjust settings
-        if Self::is_ci_environment() {
+        if crate::utils::ci::detected() {
             eprintln!("Detected CI/CD environment. Using stricter security settings.");
         }
 
@@ -420,15 +583,6 @@ impl ConfigManager {
         Ok(config)
     }
 
-    /// Check if running in a CI/CD environment
-    fn is_ci_environment() -> bool {
-        std::env::var("CI").is_ok()
-            || std::env::var("GITHUB_ACTIONS").is_ok()
-            || std::env::var("GITLAB_CI").is_ok()
-            || std::env::var("JENKINS_URL").is_ok()
-            || std::env::var("BUILDKITE").is_ok()
-    }
-
     /// Validate API key format and basic security checks
     pub fn validate_api_key(api_key: &str) -> CarpResult<()> {
         if api_key.is_empty() {
@@ -462,10 +616,10 @@ impl ConfigManager {
         // Validate API key format
         Self::validate_api_key(&api_key)?;
 
-        let mut config = Self::load()?;
-        config.api_key = Some(api_key);
-        config.api_token = None; // Clear legacy token
-        Self::save(&config)?;
+        Self::update(|document| {
+            document["api_key"] = toml_edit::value(api_key);
+            document.remove("api_token"); // Clear legacy token
+        })?;
 
         println!("API key updated successfully.");
         Ok(())
@@ -491,6 +645,8 @@ impl ConfigManager {
             max_concurrent_downloads: 4,
             retry: RetrySettings::default(),
             security: SecuritySettings::default(),
+            telemetry: false,
+            policy: None,
         };
 
         let template = toml::to_string_pretty(&template_config)
@@ -501,25 +657,26 @@ impl ConfigManager {
         ))
     }
 
-    /// Validate configuration file without loading sensitive data
-    #[allow(dead_code)]
-    pub fn validate_config_file(path: &PathBuf) -> CarpResult<()> {
-        if !path.exists() {
-            return Err(CarpError::Config(format!(
-                "Configuration file not found: {}",
-                path.display()
-            )));
-        }
-
-        let contents = fs::read_to_string(path)
-            .map_err(|e| CarpError::Config(format!("Failed to read config file: {e}")))?;
-
-        // Parse without loading into full config to check syntax
-        let _: toml::Value = toml::from_str(&contents)
-            .map_err(|e| CarpError::Config(format!("Invalid TOML syntax: {e}")))?;
-
-        println!("Configuration file syntax is valid.");
-        Ok(())
+    /// Everything wrong with the config file at `path`: unknown keys, values
+    /// of the wrong type and deprecated options in it and its includes, then
+    /// settings missing or out of range once they're layered together
+    pub fn check_file(path: &Path) -> Vec<Diagnostic> {
+        let (table, mut diagnostics) = loader::check(path);
+        let Some(table) = table else {
+            return diagnostics;
+        };
+        let invalid = toml::Value::Table(table)
+            .try_into::<Config>()
+            .map_err(CarpError::from)
+            .and_then(|config| Self::validate_config(&config));
+        if let Err(e) = invalid {
+            let message = match e {
+                CarpError::Config(message) => message,
+                e => e.to_string(),
+            };
+            diagnostics.push(Diagnostic::error(path, message));
+        }
+        diagnostics
     }
 }
 
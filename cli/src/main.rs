@@ -1,6 +1,10 @@
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::{ArgValueCompleter, CompleteEnv};
 use colored::*;
+use std::io::IsTerminal;
+use std::path::PathBuf;
 use std::process;
+use std::time::{Duration, Instant};
 
 mod api;
 mod auth;
@@ -8,9 +12,19 @@ mod commands;
 mod config;
 mod utils;
 
+use api::ApiClient;
 use auth::AuthManager;
-use commands::{healthcheck, list, pull, search, upload};
-use utils::error::CarpResult;
+use commands::{
+    add, author, claim, completions, diff, doctor, edit, healthcheck, index, info, install, list,
+    mirror, new, outdated, package, publish_workspace, pull, remove, report, review, search, share,
+    star, status, tags, telemetry, templates, token, undo, upload, watch,
+};
+use config::{Config, ConfigManager};
+use utils::error::{CarpError, CarpResult};
+use utils::manifest::Bump;
+use utils::overwrite::Overwrite;
+use utils::policy::Policy;
+use utils::terminal::ColorChoice;
 
 #[derive(Parser)]
 #[command(
@@ -23,12 +37,43 @@ struct Cli {
     #[command(subcommand)]
     command: Commands,
 
-    #[arg(long, global = true, help = "Enable verbose output")]
-    verbose: bool,
+    #[arg(
+        short,
+        long,
+        global = true,
+        action = clap::ArgAction::Count,
+        help = "Enable verbose output (-vv also logs each HTTP request)"
+    )]
+    verbose: u8,
 
     #[arg(long, global = true, help = "Suppress all output except errors")]
     quiet: bool,
 
+    #[arg(
+        long,
+        global = true,
+        help = "Never prompt, print plain output and annotate errors for CI (on when CI is detected)"
+    )]
+    ci: bool,
+
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        default_value_t = OutputFormat::Text,
+        help = "Output format; with json, errors are printed as a JSON object"
+    )]
+    output: OutputFormat,
+
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        default_value_t = ColorChoice::Auto,
+        help = "When to color output; auto colors terminals, unless NO_COLOR is set"
+    )]
+    color: ColorChoice,
+
     #[arg(
         long,
         global = true,
@@ -37,19 +82,62 @@ struct Cli {
         help = "API key for authentication (can also be set via CARP_API_KEY environment variable)"
     )]
     api_key: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        value_name = "FILE",
+        help = "Write a sanitized transcript of the command's HTTP requests to FILE, for bug reports"
+    )]
+    record: Option<PathBuf>,
 }
 
 #[derive(Subcommand)]
 enum Commands {
     /// Check the health status of the API
-    Healthcheck,
+    Healthcheck {
+        #[arg(long, help = "Also check the database, storage and auth services")]
+        deep: bool,
+    },
 
     /// List all available agents in the registry
-    List,
+    List {
+        #[arg(
+            long,
+            conflicts_with = "mine",
+            help = "Only show agents you have starred"
+        )]
+        starred: bool,
+
+        #[arg(long, help = "Only show your own agents, including private ones")]
+        mine: bool,
+
+        #[arg(
+            long,
+            value_enum,
+            value_delimiter = ',',
+            help = "Show a table of these columns, e.g. name,version,downloads,updated"
+        )]
+        columns: Vec<list::Column>,
+
+        #[arg(
+            long,
+            value_enum,
+            help = "Sort by this column: numbers and dates highest first, text A to Z"
+        )]
+        sort_by: Option<list::Column>,
+
+        #[arg(
+            long,
+            conflicts_with_all = ["starred", "mine"],
+            help = "List from the local search index instead of the registry"
+        )]
+        offline: bool,
+    },
 
     /// Search for agents in the registry
     Search {
-        /// Search query
+        /// Search query; `*` and `?` match agent names as a glob (e.g. 'data-*')
         query: String,
 
         #[arg(short, long, help = "Number of results to show")]
@@ -57,21 +145,371 @@ enum Commands {
 
         #[arg(long, help = "Show only exact matches")]
         exact: bool,
+
+        #[arg(
+            long,
+            conflicts_with = "exact",
+            help = "Match agent names against the query as a regular expression"
+        )]
+        regex: bool,
+
+        #[arg(long, value_enum, default_value_t = api::SearchSort::Downloads, help = "Order of results")]
+        sort: api::SearchSort,
+
+        #[arg(
+            long,
+            value_enum,
+            value_delimiter = ',',
+            help = "Show a table of these columns, e.g. name,version,downloads,updated"
+        )]
+        columns: Vec<list::Column>,
+
+        #[arg(
+            long,
+            value_enum,
+            help = "Sort by this column: numbers and dates highest first, text A to Z"
+        )]
+        sort_by: Option<list::Column>,
+
+        #[arg(long, help = "Search the local search index instead of the registry")]
+        offline: bool,
+    },
+
+    /// Report new agents and versions matching a search as they're published
+    Watch {
+        /// Search query; `*` and `?` match agent names as a glob (e.g. 'data-*')
+        query: String,
+
+        #[arg(long, help = "Match only this exact agent name")]
+        exact: bool,
+
+        #[arg(
+            long,
+            conflicts_with = "exact",
+            help = "Match agent names against the query as a regular expression"
+        )]
+        regex: bool,
+
+        #[arg(
+            long,
+            default_value_t = 60,
+            value_parser = clap::value_parser!(u64).range(5..),
+            help = "Seconds between checks (at least 5)"
+        )]
+        interval: u64,
+    },
+
+    /// List the tags used by agents in the registry, most used first
+    Tags {
+        #[arg(long, help = "Only show the N most used tags")]
+        top: Option<usize>,
+    },
+
+    /// List agents published as templates for `carp new --template`
+    Templates {
+        /// Only show templates matching this text
+        query: Option<String>,
+
+        #[arg(long, help = "Show at most N templates")]
+        limit: Option<usize>,
+    },
+
+    /// Star an agent to bookmark it and vouch for its quality
+    Star {
+        /// Agent name
+        name: String,
+    },
+
+    /// Remove your star from an agent
+    Unstar {
+        /// Agent name
+        name: String,
+    },
+
+    /// Show an agent's reviews, or rate and review it yourself
+    Review {
+        /// Agent name
+        name: String,
+
+        #[arg(long, value_parser = clap::value_parser!(u8).range(1..=5), help = "Your rating from 1 to 5 stars")]
+        rating: Option<u8>,
+
+        #[arg(
+            short,
+            long,
+            requires = "rating",
+            help = "Review text to go with your rating"
+        )]
+        message: Option<String>,
+
+        #[arg(long, conflicts_with_all = ["rating", "message"], help = "Delete your review")]
+        delete: bool,
+
+        #[arg(short, long, conflicts_with_all = ["rating", "delete"], help = "Number of reviews to show")]
+        limit: Option<usize>,
+    },
+
+    /// Report an agent to the registry's moderators
+    Report {
+        /// Agent name
+        name: String,
+
+        #[arg(long, value_enum, help = "Why the agent is being reported")]
+        reason: api::ReportReason,
+
+        #[arg(long, help = "What you found; required when the reason is 'other'")]
+        details: Option<String>,
+    },
+
+    /// Share one of your private agents with a user or organization
+    #[command(group(clap::ArgGroup::new("grantee").args(["with", "with_org"])))]
+    Share {
+        /// Agent name
+        name: String,
+
+        #[arg(long, value_name = "USER", help = "GitHub user to share with")]
+        with: Option<String>,
+
+        #[arg(long, value_name = "ORG", help = "Organization to share with")]
+        with_org: Option<String>,
+
+        #[arg(long, requires = "grantee", help = "Stop sharing instead")]
+        revoke: bool,
+    },
+
+    /// Claim the namespace matching a GitHub organization you control
+    Claim {
+        /// Namespace, e.g. @acme for agents named acme-*
+        namespace: String,
+
+        #[arg(
+            long,
+            value_enum,
+            default_value = "github",
+            help = "How to prove you control the organization"
+        )]
+        method: api::ClaimMethod,
+    },
+
+    /// Show detailed information about an agent
+    Info {
+        /// Agent name
+        #[arg(add = ArgValueCompleter::new(completions::agent_names))]
+        agent: String,
+
+        #[arg(long, help = "Show where and how the agent was built")]
+        provenance: bool,
+    },
+
+    /// Show an author's profile and the agents they publish
+    Author {
+        /// GitHub username of the author
+        username: String,
+    },
+
+    /// Compare two versions of an agent
+    Diff {
+        /// Older version in format 'name@version'
+        from: String,
+
+        /// Newer version in format 'name@version'
+        to: String,
+
+        #[arg(long, help = "Only show a summary of changed lines")]
+        stat: bool,
+    },
+
+    /// Edit the metadata of an agent you own (opens $EDITOR if no changes are given)
+    Edit {
+        /// Agent name
+        name: String,
+
+        #[arg(long, help = "New description")]
+        description: Option<String>,
+
+        #[arg(
+            long,
+            value_delimiter = ',',
+            help = "Replace the agent's tags (comma-separated)"
+        )]
+        tags: Option<Vec<String>>,
+
+        #[arg(long, help = "New homepage URL")]
+        homepage: Option<String>,
+
+        #[arg(
+            long,
+            value_name = "FILE",
+            help = "Replace the readme with a file's contents"
+        )]
+        readme: Option<String>,
+    },
+
+    /// Create a new agent, from a template in the registry or from scratch
+    New {
+        /// Name of the new agent
+        name: String,
+
+        #[arg(
+            short,
+            long,
+            value_name = "TEMPLATE",
+            help = "Start from this template, as 'name' or 'name@version'"
+        )]
+        template: Option<String>,
+
+        #[arg(
+            short,
+            long,
+            help = "Directory to create the agent in (default: current directory)"
+        )]
+        directory: Option<String>,
+
+        #[arg(
+            long = "set",
+            value_name = "KEY=VALUE",
+            help = "Fill in a {{KEY}} placeholder in the template (repeatable)"
+        )]
+        vars: Vec<String>,
+
+        #[arg(short, long, help = "Overwrite existing files")]
+        force: bool,
     },
 
     /// Pull an agent from the registry
     Pull {
-        /// Agent name in format 'name' or 'name@version' (optional - if not provided, shows interactive selection)
+        /// Agent name in format 'name' or 'name@version', an oci:// reference or a git+<url> source (optional - if not provided, shows interactive selection)
+        #[arg(add = ArgValueCompleter::new(completions::agent_names))]
         agent: Option<String>,
 
-        #[arg(short, long, help = "Target directory")]
-        output: Option<String>,
+        #[arg(short = 'o', long, help = "Target directory")]
+        dir: Option<String>,
 
         #[arg(long, help = "Force overwrite existing files")]
         force: bool,
+
+        #[arg(
+            long,
+            conflicts_with_all = ["force", "merge", "skip_existing"],
+            help = "Move an existing agent to <name>.bak-<timestamp> before pulling"
+        )]
+        backup: bool,
+
+        #[arg(
+            long,
+            conflicts_with_all = ["force", "skip_existing"],
+            help = "Only overwrite files the previous pull wrote and that haven't changed since"
+        )]
+        merge: bool,
+
+        #[arg(
+            long,
+            conflicts_with = "force",
+            help = "Keep files that already exist, adding only new ones"
+        )]
+        skip_existing: bool,
+
+        #[arg(
+            long,
+            requires = "identity",
+            help = "Refuse agents without a valid Sigstore signature"
+        )]
+        require_signed: bool,
+
+        #[arg(
+            long,
+            requires = "require_signed",
+            value_name = "ISSUER",
+            help = "OIDC issuer the signing identity must come from"
+        )]
+        identity: Option<String>,
+
+        #[arg(long, help = "Download and extract the agent's package archive")]
+        package: bool,
+
+        #[arg(
+            long,
+            requires = "package",
+            help = "Fetch the package through the API instead of the storage host"
+        )]
+        via_api: bool,
+
+        #[arg(
+            long,
+            value_name = "FILE",
+            help = "Refuse agents that break the rules in a policy file"
+        )]
+        policy: Option<String>,
+
+        #[arg(
+            long,
+            conflicts_with = "via_api",
+            help = "Also pull the agents it depends on, as the registry's signed resolution lists them"
+        )]
+        with_deps: bool,
+    },
+
+    /// Install the agents listed in the project's carp.toml
+    Install {
+        /// An agent's working tree, such as './my-agent', to add to carp.toml as a
+        /// path dependency and link in while it's developed
+        path: Option<String>,
+
+        #[arg(
+            long,
+            help = "Fail if carp.lock is missing or out of date instead of updating it"
+        )]
+        locked: bool,
+
+        #[arg(long, help = "Reinstall agents that are already up to date")]
+        force: bool,
+
+        #[arg(
+            long = "set",
+            value_name = "KEY=VALUE",
+            help = "Value for a variable the agents declare, recorded in carp.toml (repeatable)"
+        )]
+        vars: Vec<String>,
+    },
+
+    /// Add an agent to the project's carp.toml and install it
+    Add {
+        /// Agent name, 'name@requirement' such as 'code-reviewer@1.2', or a git+<url> source
+        agent: String,
+
+        #[arg(
+            long,
+            help = "Install into this directory instead of the manifest's default"
+        )]
+        dir: Option<String>,
+
+        #[arg(long, help = "Only update carp.toml and carp.lock")]
+        no_install: bool,
     },
 
+    /// Remove agents from the project's carp.toml and delete their files
+    Remove {
+        /// Agent names
+        #[arg(required = true)]
+        agents: Vec<String>,
+
+        #[arg(long, help = "Leave the installed files in place")]
+        keep_files: bool,
+    },
+
+    /// Show which of the project's installed agents have been changed locally
+    Status,
+
+    /// Put back the agents and project files the last install, add, remove or
+    /// forced pull replaced or deleted
+    Undo,
+
+    /// Show project agents with newer versions than carp.lock pins (with --ci,
+    /// exit with code 13 if there are any)
+    Outdated,
+
     /// Upload agents from the local filesystem to the registry
+    #[command(alias = "publish")]
     Upload {
         #[arg(
             short,
@@ -79,6 +517,118 @@ enum Commands {
             help = "Directory to scan for agents (prompts if not provided)"
         )]
         directory: Option<String>,
+
+        #[arg(long, help = "Sign agents with Sigstore using an OIDC identity")]
+        keyless: bool,
+
+        #[arg(
+            long,
+            env = "SIGSTORE_ID_TOKEN",
+            hide_env_values = true,
+            help = "OIDC identity token used for keyless signing"
+        )]
+        identity_token: Option<String>,
+
+        #[arg(long, help = "Don't record git and CI provenance for uploaded agents")]
+        no_provenance: bool,
+
+        #[arg(long, help = "Only let you see and download the uploaded agents")]
+        private: bool,
+
+        #[arg(long, help = "Offer the uploaded agents as templates for `carp new`")]
+        template: bool,
+
+        #[arg(
+            long,
+            help = "Skip agents whose latest published version has identical content"
+        )]
+        if_changed: bool,
+
+        #[arg(
+            long,
+            conflicts_with = "if_changed",
+            help = "Validate and show what would be uploaded without contacting the registry"
+        )]
+        dry_run: bool,
+
+        #[arg(
+            long = "allow-secret",
+            value_name = "FINGERPRINT",
+            help = "Upload despite a possible secret with this fingerprint (repeatable)"
+        )]
+        allow_secrets: Vec<String>,
+
+        #[arg(
+            long,
+            help = "Publish as the next patch version when the agent's version is already published"
+        )]
+        force_new_version: bool,
+
+        #[arg(
+            long,
+            conflicts_with_all = ["if_changed", "force_new_version"],
+            help = "Publish the changed agents of a workspace of agents/*/Carp.toml, dependencies first"
+        )]
+        workspace: bool,
+
+        #[arg(
+            long,
+            value_enum,
+            requires = "workspace",
+            help = "How to bump changed agents whose version is already published (default: the workspace's, or patch)"
+        )]
+        bump: Option<Bump>,
+    },
+
+    /// Build the archives `upload` would publish and write them to disk without uploading
+    Package {
+        #[arg(
+            short,
+            long,
+            help = "Directory to scan for agents (prompts if not provided)"
+        )]
+        directory: Option<String>,
+
+        #[arg(
+            short = 'o',
+            long,
+            help = "Directory to write archives to (default: current directory)"
+        )]
+        out_dir: Option<String>,
+
+        #[arg(
+            long = "allow-secret",
+            value_name = "FINGERPRINT",
+            help = "Package despite a possible secret with this fingerprint (repeatable)"
+        )]
+        allow_secrets: Vec<String>,
+
+        #[arg(
+            long,
+            help = "Build each archive twice and fail unless both are byte-identical"
+        )]
+        verify_reproducible: bool,
+    },
+
+    /// Mirror agents from another registry into the configured registry
+    Mirror {
+        /// Base URL of the registry to mirror from
+        source: String,
+
+        #[arg(long, help = "Only mirror agents matching this glob (repeatable)")]
+        include: Vec<String>,
+
+        #[arg(long, help = "Skip agents matching this glob (repeatable)")]
+        exclude: Vec<String>,
+
+        #[arg(long, help = "Keep running, syncing every N seconds")]
+        interval: Option<u64>,
+    },
+
+    /// Keep a local copy of the registry's listing for offline search
+    Index {
+        #[command(subcommand)]
+        index_command: IndexCommands,
     },
 
     /// Authentication commands
@@ -86,6 +636,76 @@ enum Commands {
         #[command(subcommand)]
         auth_command: AuthCommands,
     },
+
+    /// Manage your registry API keys
+    Token {
+        #[command(subcommand)]
+        token_command: TokenCommands,
+    },
+
+    /// Show where carp keeps its config, cache and data
+    Config {
+        #[command(subcommand)]
+        config_command: ConfigCommands,
+    },
+
+    /// Control anonymous usage telemetry (off unless turned on)
+    Telemetry {
+        #[command(subcommand)]
+        telemetry_command: TelemetryCommands,
+    },
+
+    /// Diagnose configuration, connectivity and authentication problems
+    Doctor,
+
+    /// Print a shell completion script; agent names complete from recent
+    /// searches and lists, offline
+    Completions {
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
+}
+
+impl Commands {
+    /// Name reported in telemetry
+    fn name(&self) -> &'static str {
+        match self {
+            Commands::Healthcheck { .. } => "healthcheck",
+            Commands::List { .. } => "list",
+            Commands::Search { .. } => "search",
+            Commands::Watch { .. } => "watch",
+            Commands::Tags { .. } => "tags",
+            Commands::Templates { .. } => "templates",
+            Commands::Star { .. } => "star",
+            Commands::Unstar { .. } => "unstar",
+            Commands::Review { .. } => "review",
+            Commands::Report { .. } => "report",
+            Commands::Share { .. } => "share",
+            Commands::Claim { .. } => "claim",
+            Commands::Info { .. } => "info",
+            Commands::Author { .. } => "author",
+            Commands::Diff { .. } => "diff",
+            Commands::Edit { .. } => "edit",
+            Commands::New { .. } => "new",
+            Commands::Pull { .. } => "pull",
+            Commands::Install { .. } => "install",
+            Commands::Add { .. } => "add",
+            Commands::Remove { .. } => "remove",
+            Commands::Status => "status",
+            Commands::Undo => "undo",
+            Commands::Outdated => "outdated",
+            Commands::Upload { .. } => "upload",
+            Commands::Package { .. } => "package",
+            Commands::Mirror { .. } => "mirror",
+            Commands::Index { .. } => "index",
+            Commands::Auth { .. } => "auth",
+            Commands::Token { .. } => "token",
+            Commands::Config { .. } => "config",
+            Commands::Telemetry { .. } => "telemetry",
+            Commands::Doctor => "doctor",
+            Commands::Completions { .. } => "completions",
+        }
+    }
 }
 
 #[derive(Subcommand)]
@@ -98,37 +718,498 @@ enum AuthCommands {
     Logout,
 }
 
+#[derive(Subcommand)]
+enum TokenCommands {
+    /// List your API keys and where each was last used
+    List,
+}
+
+#[derive(Subcommand)]
+enum ConfigCommands {
+    /// Print the resolved config file, cache, data and agents locations
+    Path {
+        /// Print only this path, for scripts
+        #[arg(value_enum)]
+        which: Option<commands::config::PathName>,
+    },
+    /// Check config files for unknown keys, wrong types and deprecated
+    /// options without running anything
+    Validate {
+        /// File to check; defaults to the config file and the project's
+        /// `.carp/config.toml`
+        file: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum IndexCommands {
+    /// Create the index, or fetch what changed since the last sync
+    Sync,
+    /// Show how many agents are indexed and when they were synced
+    Status,
+    /// Delete the index
+    Clear,
+}
+
+#[derive(Subcommand)]
+enum TelemetryCommands {
+    /// Send anonymous usage telemetry
+    On,
+    /// Stop sending telemetry
+    Off,
+    /// Show whether telemetry is enabled and what it sends
+    Status,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
 #[tokio::main]
 async fn main() {
+    // Completing a command line the completion script passed in, before
+    // anything else is printed
+    CompleteEnv::with_factory(Cli::command)
+        .var(completions::COMPLETE_VAR)
+        .complete();
+
     let cli = Cli::parse();
+    let output = cli.output;
+    let key_overridden = cli.api_key.is_some() || std::env::var_os("CARP_API_KEY").is_some();
+    if let Some(path) = &cli.record {
+        api::transcript::start(path.clone(), std::env::args().skip(1).collect());
+    }
 
-    if let Err(e) = run(cli).await {
-        eprintln!("{} {}", "Error:".red().bold(), e);
-        process::exit(1);
+    let result = run(cli).await;
+    finish_recording();
+    if let Err(e) = result {
+        report_error(&e, output);
+        if matches!(e, CarpError::ApiKeyExpired(_))
+            && output == OutputFormat::Text
+            && !key_overridden
+        {
+            offer_login().await;
+        }
+        process::exit(e.exit_code());
+    }
+}
+
+/// Write the `--record` transcript, if recording
+fn finish_recording() {
+    match api::transcript::finish() {
+        Ok(Some((path, requests))) => eprintln!(
+            "Recorded {requests} request{} to {}",
+            if requests == 1 { "" } else { "s" },
+            path.display()
+        ),
+        Ok(None) => {}
+        Err(e) => eprintln!(
+            "{} {}",
+            utils::terminal::for_stderr("Warning:".yellow().bold()),
+            e
+        ),
+    }
+}
+
+/// After the stored API key turns out to have expired, offer to log in with
+/// a new one. CI mode and non-interactive runs only get the hint, as does a
+/// key from --api-key or CARP_API_KEY, which a login wouldn't replace.
+async fn offer_login() {
+    if utils::ci::enabled() || !std::io::stdin().is_terminal() {
+        return;
+    }
+
+    let login = inquire::Confirm::new("Log in with a new API key now?")
+        .with_default(true)
+        .prompt()
+        .unwrap_or(false);
+    if !login {
+        return;
+    }
+    match AuthManager::login().await {
+        Ok(()) => println!("Run the command again to use the new key."),
+        Err(e) => eprintln!(
+            "{} {}",
+            utils::terminal::for_stderr("Error:".red().bold()),
+            e
+        ),
+    }
+}
+
+/// Print a failed command's error on stderr
+fn report_error(error: &CarpError, output: OutputFormat) {
+    utils::ci::annotate(utils::ci::Level::Error, &error.to_string(), None);
+
+    match output {
+        OutputFormat::Text => {
+            eprintln!(
+                "{} {}",
+                utils::terminal::for_stderr("Error:".red().bold()),
+                error
+            );
+            if let Some(hint) = error.hint() {
+                eprintln!(
+                    "{} {}",
+                    utils::terminal::for_stderr("Hint:".yellow().bold()),
+                    hint
+                );
+            }
+        }
+        OutputFormat::Json => {
+            let code = error.code();
+            let mut body = serde_json::json!({
+                "error": {
+                    "code": code.as_str(),
+                    "exit_code": code.exit_code(),
+                    "message": error.to_string(),
+                }
+            });
+            if let Some(hint) = error.hint() {
+                body["error"]["hint"] = hint.into();
+            }
+            eprintln!("{body}");
+        }
     }
 }
 
 async fn run(cli: Cli) -> CarpResult<()> {
-    match cli.command {
-        Commands::Healthcheck => healthcheck::execute(cli.verbose).await,
-        Commands::List => list::execute(cli.verbose).await,
+    utils::ci::init(cli.ci);
+    utils::terminal::init(cli.color);
+    utils::logging::init(cli.verbose, cli.quiet);
+    utils::cleanup::handle_interrupts();
+    let verbose = cli.verbose > 0;
+    let ci = cli.ci;
+
+    // Auth, config and telemetry manage the config file themselves, and remove,
+    // status and undo only touch local files, and completions prints a script,
+    // so none need a registry client; doctor must run even when the config
+    // doesn't load
+    let command = match cli.command {
+        Commands::Auth { auth_command } => {
+            return match auth_command {
+                AuthCommands::Login => AuthManager::login().await,
+                AuthCommands::Status => AuthManager::status_with_key(cli.api_key.as_deref()).await,
+                AuthCommands::Logout => AuthManager::logout().await,
+            };
+        }
+        Commands::Config { config_command } => {
+            return match config_command {
+                ConfigCommands::Path { which } => commands::config::path(which),
+                ConfigCommands::Validate { file } => commands::config::validate(file),
+            };
+        }
+        Commands::Telemetry { telemetry_command } => {
+            return match telemetry_command {
+                TelemetryCommands::On => telemetry::set(true),
+                TelemetryCommands::Off => telemetry::set(false),
+                TelemetryCommands::Status => telemetry::status(),
+            };
+        }
+        Commands::Doctor => return doctor::execute(cli.api_key).await,
+        Commands::Completions { shell } => return completions::execute(shell),
+        Commands::Remove { agents, keep_files } => return remove::execute(agents, keep_files),
+        Commands::Status => return status::execute(),
+        Commands::Undo => return undo::execute(),
+        command => command,
+    };
+
+    let config = ConfigManager::load_with_env_checks()?;
+    let client = shared_client(&config, cli.api_key)?;
+
+    let command_name = command.name();
+    let started = Instant::now();
+
+    let result = match command {
+        Commands::Healthcheck { deep } => healthcheck::execute(&client, deep, verbose).await,
+        Commands::List {
+            starred,
+            mine,
+            columns,
+            sort_by,
+            offline,
+        } => {
+            let filter = if starred {
+                list::ListFilter::Starred
+            } else if mine {
+                list::ListFilter::Mine
+            } else {
+                list::ListFilter::All
+            };
+            let layout = list::Layout { columns, sort_by };
+            list::execute(&client, filter, &layout, offline, verbose).await
+        }
         Commands::Search {
             query,
             limit,
             exact,
-        } => search::execute(query, limit, exact, cli.verbose).await,
+            regex,
+            sort,
+            columns,
+            sort_by,
+            offline,
+        } => {
+            let mode = api::SearchMode::for_query(&query, exact, regex);
+            let layout = list::Layout { columns, sort_by };
+            let options = search::SearchOptions {
+                limit,
+                mode,
+                sort,
+                offline,
+            };
+            search::execute(&client, query, options, &layout, verbose).await
+        }
+        Commands::Watch {
+            query,
+            exact,
+            regex,
+            interval,
+        } => {
+            let mode = api::SearchMode::for_query(&query, exact, regex);
+            watch::execute(&client, query, mode, Duration::from_secs(interval)).await
+        }
+        Commands::Tags { top } => tags::execute(&client, top).await,
+        Commands::Templates { query, limit } => {
+            templates::execute(&client, query.unwrap_or_default(), limit).await
+        }
+        Commands::Star { name } => star::execute(&client, name, true).await,
+        Commands::Unstar { name } => star::execute(&client, name, false).await,
+        Commands::Review {
+            name,
+            rating,
+            message,
+            delete,
+            limit,
+        } => {
+            let action = if delete {
+                review::ReviewAction::Delete
+            } else if let Some(rating) = rating {
+                review::ReviewAction::Write { rating, message }
+            } else {
+                review::ReviewAction::List { limit }
+            };
+            review::execute(&client, name, action).await
+        }
+        Commands::Report {
+            name,
+            reason,
+            details,
+        } => report::execute(&client, name, reason, details).await,
+        Commands::Share {
+            name,
+            with,
+            with_org,
+            revoke,
+        } => {
+            let action = if with.is_none() && with_org.is_none() {
+                share::ShareAction::List
+            } else {
+                let grantee = api::AccessRequest {
+                    user: with,
+                    org: with_org,
+                };
+                if revoke {
+                    share::ShareAction::Revoke(grantee)
+                } else {
+                    share::ShareAction::Grant(grantee)
+                }
+            };
+            share::execute(&client, name, action).await
+        }
+        Commands::Claim { namespace, method } => claim::execute(&client, namespace, method).await,
+        Commands::Info { agent, provenance } => info::execute(&client, agent, provenance).await,
+        Commands::Author { username } => author::execute(&client, username, verbose).await,
+        Commands::Diff { from, to, stat } => diff::execute(&client, from, to, stat).await,
+        Commands::Edit {
+            name,
+            description,
+            tags,
+            homepage,
+            readme,
+        } => {
+            let options = edit::EditOptions {
+                description,
+                tags,
+                homepage,
+                readme: readme.map(Into::into),
+            };
+            edit::execute(&client, name, options).await
+        }
+        Commands::New {
+            name,
+            template,
+            directory,
+            vars,
+            force,
+        } => {
+            let options = new::NewOptions {
+                template,
+                directory,
+                vars,
+                force,
+            };
+            new::execute(&client, &config, name, options, verbose).await
+        }
         Commands::Pull {
             agent,
-            output,
+            dir,
             force,
-        } => pull::execute(agent, output, force, cli.verbose).await,
-        Commands::Upload { directory } => {
-            upload::execute(directory, cli.api_key, cli.verbose).await
-        }
-        Commands::Auth { auth_command } => match auth_command {
-            AuthCommands::Login => AuthManager::login().await,
-            AuthCommands::Status => AuthManager::status_with_key(cli.api_key.as_deref()).await,
-            AuthCommands::Logout => AuthManager::logout().await,
+            backup,
+            merge,
+            skip_existing,
+            require_signed: _,
+            identity,
+            package,
+            via_api,
+            policy,
+            with_deps,
+        } => {
+            let overwrite = if force {
+                Overwrite::Force
+            } else if backup {
+                Overwrite::Backup
+            } else if merge {
+                Overwrite::Merge
+            } else if skip_existing {
+                Overwrite::SkipExisting
+            } else {
+                Overwrite::Fail
+            };
+            let options = pull::PullOptions {
+                output: dir,
+                overwrite,
+                required_issuer: identity,
+                package,
+                via_api,
+                // Without --policy, the one the project or config file names
+                policy: policy
+                    .or_else(|| config.policy.clone())
+                    .map(Policy::load)
+                    .transpose()?,
+                with_deps,
+            };
+            pull::execute(&client, &config, agent, options, verbose).await
+        }
+        Commands::Install {
+            path,
+            locked,
+            force,
+            vars,
+        } => {
+            let options = install::InstallOptions {
+                locked,
+                force,
+                path,
+                vars,
+            };
+            install::execute(&client, &config, options, verbose).await
+        }
+        Commands::Add {
+            agent,
+            dir,
+            no_install,
+        } => {
+            let options = add::AddOptions { dir, no_install };
+            add::execute(&client, &config, agent, options, verbose).await
+        }
+        // Only an explicit --ci fails the run, not a detected CI system
+        Commands::Outdated => outdated::execute(&client, ci).await,
+        Commands::Upload {
+            directory,
+            keyless,
+            identity_token,
+            no_provenance,
+            private,
+            template,
+            if_changed,
+            dry_run,
+            allow_secrets,
+            force_new_version,
+            workspace,
+            bump,
+        } => {
+            let options = upload::UploadOptions {
+                keyless,
+                identity_token,
+                record_provenance: !no_provenance,
+                private,
+                template,
+                if_changed,
+                dry_run,
+                allowed_secrets: allow_secrets,
+                force_new_version,
+            };
+            if workspace {
+                publish_workspace::execute(&client, directory, bump, options, verbose).await
+            } else {
+                upload::execute(&client, &config, directory, options, verbose).await
+            }
+        }
+        Commands::Package {
+            directory,
+            out_dir,
+            allow_secrets,
+            verify_reproducible,
+        } => {
+            let options = package::PackageOptions {
+                out_dir,
+                allowed_secrets: allow_secrets,
+                verify_reproducible,
+            };
+            package::execute(directory, options)
+        }
+        Commands::Mirror {
+            source,
+            include,
+            exclude,
+            interval,
+        } => {
+            let options = mirror::MirrorOptions {
+                include,
+                exclude,
+                interval,
+            };
+            mirror::execute(&client, source, options).await
+        }
+        Commands::Index { index_command } => match index_command {
+            IndexCommands::Sync => index::sync(&client).await,
+            IndexCommands::Status => index::status(&client),
+            IndexCommands::Clear => index::clear(&client),
         },
+        Commands::Token { token_command } => match token_command {
+            TokenCommands::List => token::list(&client).await,
+        },
+        Commands::Auth { .. }
+        | Commands::Config { .. }
+        | Commands::Telemetry { .. }
+        | Commands::Doctor
+        | Commands::Completions { .. }
+        | Commands::Remove { .. }
+        | Commands::Status
+        | Commands::Undo => {
+            unreachable!(
+                "auth, config, telemetry, doctor, completions, remove, status and undo commands are handled above"
+            )
+        }
+    };
+
+    if config.telemetry {
+        let event = utils::telemetry::event(command_name, started.elapsed(), &result);
+        utils::telemetry::report(&client, &event).await;
     }
+
+    result
+}
+
+/// Build the single API client every command shares, so one `pull` reuses the
+/// same pooled (HTTP/2 where available) connection for all of its requests
+fn shared_client(config: &Config, api_key: Option<String>) -> CarpResult<ApiClient> {
+    let client = ApiClient::new(config)?;
+
+    // A runtime API key takes precedence over the stored one
+    Ok(match api_key {
+        Some(api_key) => client.with_api_key(Some(api_key)),
+        None => client,
+    })
 }
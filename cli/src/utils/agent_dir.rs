@@ -0,0 +1,296 @@
+use crate::utils::ci;
+use crate::utils::cleanup::{self, Guard};
+use crate::utils::error::{CarpError, CarpResult};
+use crate::utils::frontmatter::parse_frontmatter;
+use crate::utils::manifest::{AgentManifest, MANIFEST_FILE};
+use crate::utils::portable_path;
+use crate::utils::trash;
+use colored::*;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use tracing::debug;
+use walkdir::WalkDir;
+
+/// Version of agents whose frontmatter doesn't give one, as for `carp upload`
+const DEFAULT_VERSION: &str = "1.0.0";
+
+/// Checksums of the files carp wrote into an installed or pulled agent, kept
+/// in its directory so local changes to it can be found later
+pub const RECORD_FILE: &str = ".carp-files.json";
+
+/// Checksums as `sha256:<hex>`, by path relative to the agent's directory
+pub type Record = BTreeMap<String, String>;
+
+/// Files of an installed agent that differ from its record
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Changes {
+    pub modified: Vec<String>,
+    pub added: Vec<String>,
+    pub deleted: Vec<String>,
+}
+
+impl Changes {
+    pub fn is_empty(&self) -> bool {
+        self.modified.is_empty() && self.added.is_empty() && self.deleted.is_empty()
+    }
+
+    /// Each changed file with how it changed, such as `modified: agent.md`
+    pub fn lines(&self) -> impl Iterator<Item = String> + '_ {
+        self.modified
+            .iter()
+            .map(|file| format!("modified: {file}"))
+            .chain(self.added.iter().map(|file| format!("added:    {file}")))
+            .chain(self.deleted.iter().map(|file| format!("deleted:  {file}")))
+    }
+}
+
+/// An unpublished agent's working tree, from a git checkout or a local path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AgentDir {
+    pub name: String,
+    pub version: String,
+}
+
+impl AgentDir {
+    /// Find and validate the agent in `root`, from its `Carp.toml` or else
+    /// the one markdown file with `name` and `description` frontmatter
+    ///
+    /// `label` names the directory in errors.
+    pub fn read(root: &Path, label: &str) -> CarpResult<Self> {
+        let manifest = root.join(MANIFEST_FILE);
+        if manifest.is_file() {
+            let manifest = AgentManifest::load(&manifest)?;
+            if let Some(missing) = manifest.files.iter().find(|file| !root.join(file).exists()) {
+                return Err(CarpError::ManifestError(format!(
+                    "{MANIFEST_FILE} in '{label}' lists '{missing}', which doesn't exist"
+                )));
+            }
+            return Ok(Self {
+                name: manifest.name,
+                version: manifest.version,
+            });
+        }
+
+        let mut agents = Vec::new();
+        for entry in fs::read_dir(root)? {
+            let path = entry?.path();
+            if !path.is_file() || path.extension().is_none_or(|extension| extension != "md") {
+                continue;
+            }
+            match markdown_agent(&path) {
+                Ok(agent) => agents.push(agent),
+                Err(e) => debug!("Skipping {}: {}", path.display(), e),
+            }
+        }
+
+        match agents.len() {
+            1 => Ok(agents.remove(0)),
+            0 => Err(CarpError::InvalidAgent(format!(
+                "No {MANIFEST_FILE} or agent markdown file with name and description frontmatter in '{label}'"
+            ))),
+            _ => Err(CarpError::InvalidAgent(format!(
+                "'{label}' holds {} agents; point at the directory of just one",
+                agents.len()
+            ))),
+        }
+    }
+}
+
+/// SHA-256 over the files under `root` and their paths, as `sha256:<hex>`
+pub fn checksum(root: &Path) -> CarpResult<String> {
+    let mut hasher = Sha256::new();
+    for path in files(root)? {
+        let relative = path.strip_prefix(root).unwrap_or(&path);
+        let contents = fs::read(&path)?;
+        hasher.update(relative.to_string_lossy().replace('\\', "/").as_bytes());
+        hasher.update([0]);
+        hasher.update((contents.len() as u64).to_be_bytes());
+        hasher.update(&contents);
+    }
+    Ok(format!("sha256:{:x}", hasher.finalize()))
+}
+
+/// Copy the files under `root` into `dest`, replacing whatever is there, and
+/// return how many were copied
+pub fn copy(root: &Path, dest: &Path) -> CarpResult<usize> {
+    let dest = &portable_path::long(dest);
+    let name = dest
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let staging = dest.with_file_name(format!(".{name}.installing"));
+    if staging.exists() {
+        fs::remove_dir_all(&staging)?;
+    }
+    let staging = Guard::new(staging);
+
+    fs::create_dir_all(staging.path())?;
+    let files = files(root)?;
+    for path in &files {
+        let target = staging.path().join(path.strip_prefix(root).unwrap_or(path));
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(path, target)?;
+    }
+
+    trash::discard(dest)?;
+    fs::rename(staging.path(), dest)?;
+    staging.keep();
+    Ok(files.len())
+}
+
+/// Remove an installed agent, whether it was copied or linked
+pub fn remove(dest: &Path) -> CarpResult<()> {
+    match fs::symlink_metadata(dest) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(dest)?,
+        Ok(_) => fs::remove_file(dest)?,
+        Err(_) => {}
+    }
+    Ok(())
+}
+
+/// Record the checksums of every file now in `dir`, the agent carp just
+/// wrote there
+pub fn record(dir: &Path) -> CarpResult<()> {
+    let record = files(dir)?
+        .into_iter()
+        .map(|path| Ok((relative_path(dir, &path), file_checksum(&path)?)))
+        .collect::<CarpResult<Record>>()?;
+    write_record(dir, &record)
+}
+
+/// The record in `dir`, if carp left one there
+pub fn read_record(dir: &Path) -> Option<Record> {
+    let contents = fs::read_to_string(dir.join(RECORD_FILE)).ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+pub fn write_record(dir: &Path, record: &Record) -> CarpResult<()> {
+    let contents = serde_json::to_string_pretty(record)?;
+    cleanup::write_atomic(&dir.join(RECORD_FILE), contents)?;
+    Ok(())
+}
+
+/// How the files in `dir` differ from its record, or `None` when it has none,
+/// such as agents installed before records were kept
+pub fn changes(dir: &Path) -> CarpResult<Option<Changes>> {
+    if !dir.is_dir() {
+        return Ok(None);
+    }
+    let Some(mut record) = read_record(dir) else {
+        return Ok(None);
+    };
+
+    let mut changes = Changes::default();
+    for path in files(dir)? {
+        let relative = relative_path(dir, &path);
+        match record.remove(&relative) {
+            Some(recorded) if recorded == file_checksum(&path)? => {}
+            Some(_) => changes.modified.push(relative),
+            None => changes.added.push(relative),
+        }
+    }
+    changes.deleted = record.into_keys().collect();
+    Ok(Some(changes))
+}
+
+/// Warn when replacing the agent `name` in `dir` would lose local changes,
+/// and ask whether to go ahead
+///
+/// CI mode goes ahead after the warning, since nobody can answer.
+pub fn confirm_discard(name: &str, dir: &Path) -> CarpResult<bool> {
+    let Some(changes) = changes(dir)?.filter(|changes| !changes.is_empty()) else {
+        return Ok(true);
+    };
+
+    println!(
+        "{} {} in {} has local changes that replacing it would lose:",
+        "Warning:".yellow().bold(),
+        name.blue().bold(),
+        dir.display()
+    );
+    for line in changes.lines() {
+        println!("  {line}");
+    }
+    if ci::enabled() {
+        return Ok(true);
+    }
+
+    inquire::Confirm::new("Discard them?")
+        .with_default(false)
+        .prompt()
+        .map_err(|e| CarpError::Other(format!("Input cancelled: {e}")))
+}
+
+/// `path` relative to `root`, with `/` separators
+pub fn relative_path(root: &Path, path: &Path) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .replace('\\', "/")
+}
+
+/// SHA-256 of a file's contents, as `sha256:<hex>`
+pub fn file_checksum(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(format!("sha256:{:x}", hasher.finalize()))
+}
+
+/// Regular files under `root`, sorted, leaving out git metadata, pull
+/// records and symlinks that could point outside the tree
+pub fn files(root: &Path) -> CarpResult<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let walker = WalkDir::new(root)
+        .follow_links(false)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| entry.file_name() != ".git" && entry.file_name() != RECORD_FILE);
+    for entry in walker {
+        let entry =
+            entry.map_err(|e| CarpError::FileSystem(format!("Error reading agent files: {e}")))?;
+        if entry.file_type().is_file() {
+            files.push(entry.into_path());
+        }
+    }
+    Ok(files)
+}
+
+fn markdown_agent(path: &Path) -> CarpResult<AgentDir> {
+    let (frontmatter, _) = parse_frontmatter(&fs::read_to_string(path)?)?;
+    let field = |key: &str| {
+        frontmatter
+            .get(key)
+            .and_then(|value| match value {
+                serde_json::Value::String(s) => Some(s.clone()),
+                serde_json::Value::Number(n) => Some(n.to_string()),
+                _ => None,
+            })
+            .filter(|value| !value.is_empty())
+    };
+
+    let name = field("name").ok_or_else(|| {
+        CarpError::ManifestError("Missing 'name' field in frontmatter".to_string())
+    })?;
+    field("description").ok_or_else(|| {
+        CarpError::ManifestError("Missing 'description' field in frontmatter".to_string())
+    })?;
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(CarpError::InvalidAgent(format!(
+            "'{name}' in {} isn't a valid agent name",
+            path.display()
+        )));
+    }
+
+    Ok(AgentDir {
+        name,
+        version: field("version").unwrap_or_else(|| DEFAULT_VERSION.to_string()),
+    })
+}
@@ -0,0 +1,645 @@
+use crate::config::SecuritySettings;
+use crate::utils::error::{CarpError, CarpResult};
+use crate::utils::portable_path::{self, windows_name_problem};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+
+/// Longest symlink target we will read from an archive
+const MAX_SYMLINK_TARGET_LEN: u64 = 4096;
+
+/// Limits applied while extracting an agent package
+#[derive(Debug, Clone)]
+pub struct ExtractLimits {
+    pub max_entry_size: u64,
+    pub max_total_size: u64,
+    pub allow_symlinks: bool,
+}
+
+impl From<&SecuritySettings> for ExtractLimits {
+    fn from(security: &SecuritySettings) -> Self {
+        Self {
+            max_entry_size: security.max_extracted_entry_size,
+            max_total_size: security.max_extracted_size,
+            allow_symlinks: security.allow_archive_symlinks,
+        }
+    }
+}
+
+/// Running totals reported after each extracted entry
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExtractProgress {
+    pub entries: usize,
+    pub bytes: u64,
+}
+
+/// Archive formats an agent package can be published in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    TarGz,
+    TarZst,
+}
+
+impl ArchiveFormat {
+    /// MIME type sent when publishing and stored by the registry
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "application/zip",
+            ArchiveFormat::TarGz => "application/gzip",
+            ArchiveFormat::TarZst => "application/zstd",
+        }
+    }
+
+    /// File extension, without a leading dot
+    pub fn extension(self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::TarGz => "tar.gz",
+            ArchiveFormat::TarZst => "tar.zst",
+        }
+    }
+
+    /// Map a MIME type reported by the registry to a format
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        let mime = content_type.split(';').next().unwrap_or_default().trim();
+
+        match mime.to_ascii_lowercase().as_str() {
+            "application/zip" | "application/x-zip-compressed" => Some(ArchiveFormat::Zip),
+            "application/gzip" | "application/x-gzip" | "application/tar+gzip" => {
+                Some(ArchiveFormat::TarGz)
+            }
+            "application/zstd" | "application/x-zstd" | "application/tar+zstd" => {
+                Some(ArchiveFormat::TarZst)
+            }
+            _ => None,
+        }
+    }
+
+    /// Identify a format from an archive's magic bytes
+    pub fn detect(header: &[u8]) -> Option<Self> {
+        if header.starts_with(b"PK\x03\x04") || header.starts_with(b"PK\x05\x06") {
+            Some(ArchiveFormat::Zip)
+        } else if header.starts_with(&[0x1f, 0x8b]) {
+            Some(ArchiveFormat::TarGz)
+        } else if header.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(ArchiveFormat::TarZst)
+        } else {
+            None
+        }
+    }
+}
+
+impl std::str::FromStr for ArchiveFormat {
+    type Err = CarpError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "zip" => Ok(ArchiveFormat::Zip),
+            "tar.gz" | "tgz" => Ok(ArchiveFormat::TarGz),
+            "tar.zst" | "tzst" => Ok(ArchiveFormat::TarZst),
+            other => Err(CarpError::InvalidAgent(format!(
+                "Unsupported archive format '{other}'. Use zip, tar.gz or tar.zst"
+            ))),
+        }
+    }
+}
+
+/// Extract an archive of the given format into `dest` one entry at a time,
+/// without buffering the archive or any entry in memory.
+///
+/// Every entry path must stay inside `dest`: absolute paths and `..`
+/// components are rejected, as are symlinks unless `limits.allow_symlinks`
+/// is set, in which case their targets must be relative and point downward.
+/// On Windows, names Windows can't create are rejected too, and paths past
+/// its length limit are written anyway.
+/// `on_entry` is called with the entry's path and running totals after each
+/// entry is written.
+pub fn extract_archive<R: Read>(
+    format: ArchiveFormat,
+    reader: R,
+    dest: &Path,
+    limits: &ExtractLimits,
+    on_entry: impl FnMut(&Path, &ExtractProgress),
+) -> CarpResult<ExtractProgress> {
+    match format {
+        ArchiveFormat::Zip => extract_zip(reader, dest, limits, on_entry),
+        ArchiveFormat::TarGz => {
+            extract_tar(flate2::read::GzDecoder::new(reader), dest, limits, on_entry)
+        }
+        ArchiveFormat::TarZst => extract_tar(
+            zstd::stream::read::Decoder::new(reader)?,
+            dest,
+            limits,
+            on_entry,
+        ),
+    }
+}
+
+fn extract_zip<R: Read>(
+    mut reader: R,
+    dest: &Path,
+    limits: &ExtractLimits,
+    mut on_entry: impl FnMut(&Path, &ExtractProgress),
+) -> CarpResult<ExtractProgress> {
+    let mut extractor = Extractor::new(dest, limits)?;
+
+    while let Some(mut entry) = zip::read::read_zipfile_from_stream(&mut reader)? {
+        let name = entry.name().to_string();
+        let relative = if entry.is_dir() {
+            extractor.dir(&name)?
+        } else if entry.is_symlink() {
+            let target = read_symlink_target(&mut entry)?;
+            extractor.symlink(&name, &target)?
+        } else {
+            let size = entry.size();
+            extractor.file(&name, size, &mut entry)?
+        };
+
+        on_entry(&relative, &extractor.progress);
+    }
+
+    Ok(extractor.progress)
+}
+
+fn extract_tar<R: Read>(
+    reader: R,
+    dest: &Path,
+    limits: &ExtractLimits,
+    mut on_entry: impl FnMut(&Path, &ExtractProgress),
+) -> CarpResult<ExtractProgress> {
+    let mut extractor = Extractor::new(dest, limits)?;
+    let mut archive = tar::Archive::new(reader);
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = String::from_utf8_lossy(&entry.path_bytes()).into_owned();
+        let entry_type = entry.header().entry_type();
+
+        let relative = match entry_type {
+            tar::EntryType::Directory => extractor.dir(&name)?,
+            tar::EntryType::Symlink => {
+                let target = entry
+                    .link_name_bytes()
+                    .map(|target| String::from_utf8_lossy(&target).into_owned())
+                    .unwrap_or_default();
+                extractor.symlink(&name, &target)?
+            }
+            tar::EntryType::Regular | tar::EntryType::Continuous => {
+                let size = entry.header().size()?;
+                extractor.file(&name, size, &mut entry)?
+            }
+            // Global pax headers only carry metadata
+            tar::EntryType::XGlobalHeader => continue,
+            _ => {
+                return Err(CarpError::FileSystem(format!(
+                    "Archive entry '{name}' has unsupported type {entry_type:?}; only files, directories and symlinks are allowed"
+                )))
+            }
+        };
+
+        on_entry(&relative, &extractor.progress);
+    }
+
+    Ok(extractor.progress)
+}
+
+/// Writes validated entries beneath the destination while tracking limits
+struct Extractor<'a> {
+    /// Long enough paths prefixed to get past Windows' length limit
+    dest: PathBuf,
+    limits: &'a ExtractLimits,
+    progress: ExtractProgress,
+}
+
+impl<'a> Extractor<'a> {
+    fn new(dest: &Path, limits: &'a ExtractLimits) -> CarpResult<Self> {
+        let dest = portable_path::long(dest);
+        fs::create_dir_all(&dest)?;
+        Ok(Self {
+            dest,
+            limits,
+            progress: ExtractProgress::default(),
+        })
+    }
+
+    fn dir(&mut self, name: &str) -> CarpResult<PathBuf> {
+        let relative = entry_path(name)?;
+        fs::create_dir_all(self.dest.join(&relative))?;
+        self.progress.entries += 1;
+        Ok(relative)
+    }
+
+    fn symlink(&mut self, name: &str, target: &str) -> CarpResult<PathBuf> {
+        let relative = entry_path(name)?;
+
+        if !self.limits.allow_symlinks {
+            return Err(CarpError::FileSystem(format!(
+                "Archive entry '{name}' is a symlink; symlinks are not allowed in agent packages"
+            )));
+        }
+
+        create_symlink(
+            &validate_symlink_target(name, target)?,
+            &self.dest.join(&relative),
+        )?;
+        self.progress.entries += 1;
+        Ok(relative)
+    }
+
+    fn file(
+        &mut self,
+        name: &str,
+        declared_size: u64,
+        reader: &mut impl Read,
+    ) -> CarpResult<PathBuf> {
+        let relative = entry_path(name)?;
+        let path = self.dest.join(&relative);
+
+        if declared_size > self.limits.max_entry_size {
+            return Err(entry_too_large(name, self.limits.max_entry_size));
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // The declared size can lie, so cap what we actually copy
+        let remaining = self.limits.max_total_size - self.progress.bytes;
+        let cap = self.limits.max_entry_size.min(remaining);
+        let mut file = fs::File::create(&path)?;
+        let written = io::copy(&mut reader.take(cap + 1), &mut file)?;
+
+        if written > cap {
+            drop(file);
+            let _ = fs::remove_file(&path);
+            return Err(if written > self.limits.max_entry_size {
+                entry_too_large(name, self.limits.max_entry_size)
+            } else {
+                CarpError::FileSystem(format!(
+                    "Archive expands beyond the maximum extracted size of {} bytes",
+                    self.limits.max_total_size
+                ))
+            });
+        }
+
+        self.progress.bytes += written;
+        self.progress.entries += 1;
+        Ok(relative)
+    }
+}
+
+fn read_symlink_target(reader: &mut impl Read) -> CarpResult<String> {
+    let mut target = String::new();
+    reader
+        .take(MAX_SYMLINK_TARGET_LEN)
+        .read_to_string(&mut target)?;
+    Ok(target)
+}
+
+/// Turn an archive entry name into a path that cannot escape the destination
+fn entry_path(name: &str) -> CarpResult<PathBuf> {
+    let reject = |reason: &str| {
+        Err(CarpError::FileSystem(format!(
+            "Archive entry '{name}' {reason}"
+        )))
+    };
+
+    // Windows-style separators and drive letters would otherwise slip past
+    // the component checks on Unix
+    let normalized = name.replace('\\', "/");
+    if normalized.starts_with('/')
+        || normalized
+            .split('/')
+            .next()
+            .is_some_and(|c| c.contains(':'))
+    {
+        return reject("has an absolute path");
+    }
+
+    let mut path = PathBuf::new();
+    for component in Path::new(&normalized).components() {
+        match component {
+            Component::Normal(part) => {
+                // Caught here rather than as a confusing error from Windows
+                if cfg!(windows) {
+                    if let Some(problem) = windows_name_problem(&part.to_string_lossy()) {
+                        return reject(&format!("can't be created on Windows: it {problem}"));
+                    }
+                }
+                path.push(part)
+            }
+            Component::CurDir => {}
+            Component::ParentDir => return reject("escapes the destination directory"),
+            Component::RootDir | Component::Prefix(_) => return reject("has an absolute path"),
+        }
+    }
+
+    if path.as_os_str().is_empty() {
+        return reject("has an empty path");
+    }
+
+    Ok(path)
+}
+
+/// Only relative, downward-pointing targets are allowed, so a chain of links
+/// can never resolve outside the destination
+fn validate_symlink_target(name: &str, target: &str) -> CarpResult<PathBuf> {
+    let target = Path::new(target);
+
+    let escapes = target.components().any(|component| {
+        matches!(
+            component,
+            Component::ParentDir | Component::RootDir | Component::Prefix(_)
+        )
+    });
+
+    if target.as_os_str().is_empty() || escapes {
+        return Err(CarpError::FileSystem(format!(
+            "Archive symlink '{name}' points outside the package"
+        )));
+    }
+
+    Ok(target.to_path_buf())
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &Path, link: &Path) -> CarpResult<()> {
+    if let Some(parent) = link.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    std::os::unix::fs::symlink(target, link)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn create_symlink(_target: &Path, link: &Path) -> CarpResult<()> {
+    Err(CarpError::FileSystem(format!(
+        "Cannot create symlink '{}': symlinks in agent packages are only supported on Unix",
+        link.display()
+    )))
+}
+
+/// Permissions recorded for every packaged file, as the registry records them
+const PACKAGE_FILE_MODE: u32 = 0o644;
+
+/// Zip holding a single-file agent as `{name}.md`, byte for byte the package
+/// the registry stores an uploaded agent as, so both have the same checksum
+///
+/// Nothing about the machine or the time leaks in: the entry is stored
+/// uncompressed, with fixed permissions and a 1980-01-01 timestamp.
+pub fn build_markdown_package(name: &str, markdown: &str) -> CarpResult<Vec<u8>> {
+    let mut zip = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+    let options = zip::write::SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Stored)
+        .last_modified_time(zip::DateTime::default())
+        .unix_permissions(PACKAGE_FILE_MODE);
+
+    zip.start_file(format!("{name}.md"), options)?;
+    io::Write::write_all(&mut zip, markdown.as_bytes())?;
+    let mut package = zip.finish()?.into_inner();
+    stamp_registry_versions(&mut package)?;
+    Ok(package)
+}
+
+/// Rewrite the "version made by" and "version needed" fields of a single
+/// entry zip to what the registry's zip writer uses (4.6 on Unix, needing 2.0)
+/// in place of the 1.0 this one writes for stored entries
+fn stamp_registry_versions(package: &mut [u8]) -> CarpResult<()> {
+    const MADE_BY: [u8; 2] = [46, 3];
+    const NEEDED: [u8; 2] = [20, 0];
+
+    // The central directory offset is the last field before the comment,
+    // which these archives don't have
+    let invalid = || CarpError::Other("Unexpected layout for a markdown package".to_string());
+    let eocd = package.len().checked_sub(22).ok_or_else(invalid)?;
+    let offset = package[eocd + 16..eocd + 20]
+        .try_into()
+        .map(|bytes| u32::from_le_bytes(bytes) as usize)
+        .map_err(|_| invalid())?;
+    if !package.starts_with(b"PK\x03\x04") || package.get(offset..offset + 4) != Some(b"PK\x01\x02")
+    {
+        return Err(invalid());
+    }
+
+    package[4..6].copy_from_slice(&NEEDED);
+    package[offset + 4..offset + 6].copy_from_slice(&MADE_BY);
+    package[offset + 6..offset + 8].copy_from_slice(&NEEDED);
+    Ok(())
+}
+
+fn entry_too_large(name: &str, limit: u64) -> CarpError {
+    CarpError::FileSystem(format!(
+        "Archive entry '{name}' exceeds the maximum extracted file size of {limit} bytes"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+    use tempfile::TempDir;
+    use zip::write::SimpleFileOptions;
+
+    fn limits() -> ExtractLimits {
+        ExtractLimits {
+            max_entry_size: 1024,
+            max_total_size: 2048,
+            allow_symlinks: false,
+        }
+    }
+
+    fn archive(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, content) in entries {
+            writer
+                .start_file(*name, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(content).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_markdown_package_matches_registry() {
+        let markdown = "---\nname: reviewer\ndescription: Reviews code\n---\n\nBody\n";
+        let package = build_markdown_package("reviewer", markdown).unwrap();
+        let stored = shared::archive::build_markdown_package("reviewer", markdown).unwrap();
+
+        assert_eq!(package, stored.to_vec());
+
+        let dir = TempDir::new().unwrap();
+        extract_zip(Cursor::new(package), dir.path(), &limits(), |_, _| {}).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.path().join("reviewer.md")).unwrap(),
+            markdown
+        );
+    }
+
+    #[test]
+    fn test_extract_zip() {
+        let dir = TempDir::new().unwrap();
+        let data = archive(&[
+            ("agent.md", b"# Agent"),
+            ("prompts/system.md", b"Be helpful"),
+        ]);
+
+        let mut seen = Vec::new();
+        let progress = extract_zip(Cursor::new(data), dir.path(), &limits(), |path, _| {
+            seen.push(path.to_path_buf())
+        })
+        .unwrap();
+
+        assert_eq!(
+            progress,
+            ExtractProgress {
+                entries: 2,
+                bytes: 17
+            }
+        );
+        assert_eq!(
+            seen,
+            vec![
+                PathBuf::from("agent.md"),
+                PathBuf::from("prompts/system.md")
+            ]
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join("prompts/system.md")).unwrap(),
+            "Be helpful"
+        );
+    }
+
+    #[test]
+    fn test_extract_zip_rejects_traversal() {
+        let dir = TempDir::new().unwrap();
+        let data = archive(&[("../evil.sh", b"rm -rf /")]);
+
+        assert!(extract_zip(
+            Cursor::new(data),
+            &dir.path().join("out"),
+            &limits(),
+            |_, _| {}
+        )
+        .is_err());
+        assert!(!dir.path().join("evil.sh").exists());
+    }
+
+    #[test]
+    fn test_extract_zip_enforces_size_limits() {
+        let dir = TempDir::new().unwrap();
+        let big = vec![b'x'; 1500];
+        let data = archive(&[("big.bin", &big)]);
+        assert!(extract_zip(Cursor::new(data), dir.path(), &limits(), |_, _| {}).is_err());
+
+        let chunk = vec![b'x'; 1000];
+        let data = archive(&[("a.bin", &chunk), ("b.bin", &chunk), ("c.bin", &chunk)]);
+        assert!(extract_zip(Cursor::new(data), dir.path(), &limits(), |_, _| {}).is_err());
+        assert!(!dir.path().join("c.bin").exists());
+    }
+
+    fn tarball(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, content) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, name, *content).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_extract_compressed_tarballs() {
+        let tar = tarball(&[
+            ("agent.md", b"# Agent"),
+            ("prompts/system.md", b"Be helpful"),
+        ]);
+
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(&tar).unwrap();
+        let gz = gz.finish().unwrap();
+        let zst = zstd::stream::encode_all(Cursor::new(&tar), 0).unwrap();
+
+        for (format, data) in [(ArchiveFormat::TarGz, gz), (ArchiveFormat::TarZst, zst)] {
+            assert_eq!(ArchiveFormat::detect(&data), Some(format));
+
+            let dir = TempDir::new().unwrap();
+            let progress =
+                extract_archive(format, Cursor::new(data), dir.path(), &limits(), |_, _| {})
+                    .unwrap();
+
+            assert_eq!(progress.entries, 2);
+            assert_eq!(
+                fs::read_to_string(dir.path().join("prompts/system.md")).unwrap(),
+                "Be helpful"
+            );
+        }
+    }
+
+    #[test]
+    fn test_extract_tar_rejects_symlinks_by_default() {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        header.set_cksum();
+        builder
+            .append_link(&mut header, "passwd", "/etc/passwd")
+            .unwrap();
+        let data = builder.into_inner().unwrap();
+
+        let dir = TempDir::new().unwrap();
+        let result = extract_archive(
+            ArchiveFormat::TarGz,
+            gzip(&data),
+            dir.path(),
+            &limits(),
+            |_, _| {},
+        );
+        assert!(result.is_err());
+        assert!(!dir.path().join("passwd").exists());
+    }
+
+    fn gzip(data: &[u8]) -> Cursor<Vec<u8>> {
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(data).unwrap();
+        Cursor::new(gz.finish().unwrap())
+    }
+
+    #[test]
+    fn test_archive_format() {
+        assert_eq!(
+            "tar.gz".parse::<ArchiveFormat>().unwrap(),
+            ArchiveFormat::TarGz
+        );
+        assert!("rar".parse::<ArchiveFormat>().is_err());
+        assert_eq!(
+            ArchiveFormat::from_content_type("application/x-gzip"),
+            Some(ArchiveFormat::TarGz)
+        );
+        assert_eq!(
+            ArchiveFormat::detect(b"PK\x03\x04"),
+            Some(ArchiveFormat::Zip)
+        );
+        assert_eq!(ArchiveFormat::detect(b"#!/bin/sh"), None);
+    }
+
+    #[test]
+    fn test_entry_path() {
+        assert_eq!(entry_path("a/./b.md").unwrap(), PathBuf::from("a/b.md"));
+        assert!(entry_path("/etc/passwd").is_err());
+        assert!(entry_path("a/../../b").is_err());
+        assert!(entry_path("..\\windows\\evil.dll").is_err());
+        assert!(entry_path("C:/windows/evil.dll").is_err());
+        assert!(entry_path("").is_err());
+    }
+
+    #[test]
+    fn test_validate_symlink_target() {
+        assert!(validate_symlink_target("link", "docs/readme.md").is_ok());
+        assert!(validate_symlink_target("link", "../outside").is_err());
+        assert!(validate_symlink_target("link", "/etc/passwd").is_err());
+    }
+}
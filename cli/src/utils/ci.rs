@@ -0,0 +1,143 @@
+//! Non-interactive mode for CI pipelines
+//!
+//! `--ci`, or running under a CI system, turns off everything that assumes a
+//! person at a terminal:
+//!
+//! - prompts with a safe default take it, and the rest fail with an error
+//!   naming the flag to pass instead
+//! - colors and in-place progress lines are dropped, so logs stay plain text
+//! - errors are also written as annotations: workflow commands on GitHub
+//!   Actions, `file: error: message` lines (as GitLab and most log parsers
+//!   link) elsewhere
+
+use crate::utils::error::{CarpError, CarpResult};
+use std::env;
+use std::path::Path;
+use std::sync::OnceLock;
+
+static ENABLED: OnceLock<bool> = OnceLock::new();
+
+/// Environment variables set by common CI systems
+const CI_ENV_VARS: &[&str] = &[
+    "CI",
+    "GITHUB_ACTIONS",
+    "GITLAB_CI",
+    "JENKINS_URL",
+    "BUILDKITE",
+];
+
+/// Turn CI mode on if `flag` is set or a CI system is detected
+///
+/// Colors follow once [`terminal::init`](crate::utils::terminal::init)
+/// sees it's on.
+pub fn init(flag: bool) {
+    ENABLED.get_or_init(|| flag || detected());
+}
+
+/// Whether CI mode is on
+pub fn enabled() -> bool {
+    ENABLED.get().copied().unwrap_or(false)
+}
+
+/// Whether the environment looks like a CI system
+pub fn detected() -> bool {
+    CI_ENV_VARS.iter().any(|var| env::var_os(var).is_some())
+}
+
+/// Fail in CI mode, where nobody can answer the prompt `what` would show
+pub fn ensure_interactive(what: &str, instead: &str) -> CarpResult<()> {
+    if enabled() {
+        return Err(CarpError::PromptRequired(format!(
+            "{what} needs a prompt, which CI mode doesn't show; {instead}"
+        )));
+    }
+    Ok(())
+}
+
+/// Severity of an annotation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Error,
+    Warning,
+}
+
+/// Report a problem to the CI system, attached to `file` when there is one
+///
+/// Does nothing outside CI mode. Without a file, only GitHub Actions gets an
+/// annotation, since the error itself is already in the log.
+pub fn annotate(level: Level, message: &str, file: Option<&Path>) {
+    if !enabled() {
+        return;
+    }
+    if let Some(line) = annotation(
+        level,
+        message,
+        file,
+        env::var_os("GITHUB_ACTIONS").is_some(),
+    ) {
+        println!("{line}");
+    }
+}
+
+fn annotation(level: Level, message: &str, file: Option<&Path>, github: bool) -> Option<String> {
+    let name = match level {
+        Level::Error => "error",
+        Level::Warning => "warning",
+    };
+
+    if github {
+        let properties = file
+            .map(|file| format!(" file={}", escape_property(&file.display().to_string())))
+            .unwrap_or_default();
+        return Some(format!("::{name}{properties}::{}", escape_data(message)));
+    }
+
+    file.map(|file| {
+        format!(
+            "{}: {name}: {}",
+            file.display(),
+            message.replace(['\r', '\n'], " ")
+        )
+    })
+}
+
+/// Escape a workflow command's message
+fn escape_data(value: &str) -> String {
+    value
+        .replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// Escape a workflow command property, which additionally can't hold `:` or `,`
+fn escape_property(value: &str) -> String {
+    escape_data(value).replace(':', "%3A").replace(',', "%2C")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_github_annotations() {
+        let file = Path::new("agents/a,b.md");
+        assert_eq!(
+            annotation(Level::Error, "bad: 50%\nfix it", Some(file), true).unwrap(),
+            "::error file=agents/a%2Cb.md::bad: 50%25%0Afix it"
+        );
+        assert_eq!(
+            annotation(Level::Warning, "skipped", None, true).unwrap(),
+            "::warning::skipped"
+        );
+    }
+
+    #[test]
+    fn test_plain_annotations() {
+        let file = Path::new("agents/reviewer.md");
+        assert_eq!(
+            annotation(Level::Error, "Missing 'name'\nfield", Some(file), false).unwrap(),
+            "agents/reviewer.md: error: Missing 'name' field"
+        );
+        assert_eq!(annotation(Level::Error, "failed", None, false), None);
+    }
+}
@@ -0,0 +1,167 @@
+//! Removing partial state when a command fails or is interrupted
+//!
+//! Files and directories that only make sense once an operation completes,
+//! such as downloaded archives, `.part` downloads and extraction staging
+//! directories, are held by a [`Guard`] while they're written. Dropping the
+//! guard removes them, so errors and early returns clean up after
+//! themselves, and Ctrl-C removes whatever is still guarded before exiting,
+//! since destructors don't run when the process is interrupted. Otherwise
+//! an interrupted pull could leave a half-extracted agent behind for a later
+//! `--force` to mistake for an installed one.
+
+use crate::utils::file_lock::LockedFile;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+use tracing::debug;
+
+/// Exit status after Ctrl-C, as a shell reports a process killed by SIGINT
+const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+/// Paths held by live guards, by guard
+static PENDING: LazyLock<Mutex<BTreeMap<u64, PathBuf>>> = LazyLock::new(Default::default);
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Removes a file or directory when dropped, unless it's kept
+#[derive(Debug)]
+pub struct Guard {
+    id: u64,
+    path: PathBuf,
+    /// Held until the path is removed or kept
+    lock: Option<LockedFile>,
+}
+
+impl Guard {
+    /// Guard `path`, which the caller is about to write
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        pending().insert(id, path.clone());
+        Self {
+            id,
+            path,
+            lock: None,
+        }
+    }
+
+    /// Guard `path` in a directory other carp processes share, such as the
+    /// download cache, first waiting for any of them writing the same path
+    pub fn locked(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let lock = LockedFile::beside(&path)?;
+        let mut guard = Self::new(path);
+        guard.lock = Some(lock);
+        Ok(guard)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Stop guarding the path, once the operation has finished with it
+    pub fn keep(mut self) {
+        pending().remove(&self.id);
+        drop(self.lock.take());
+        std::mem::forget(self);
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        if pending().remove(&self.id).is_some() {
+            remove(&self.path);
+        }
+    }
+}
+
+/// Remove everything still guarded, returning how many paths were removed
+pub fn remove_pending() -> usize {
+    let paths: Vec<PathBuf> = std::mem::take(&mut *pending()).into_values().collect();
+    paths.iter().filter(|path| remove(path)).count()
+}
+
+/// On Ctrl-C, remove everything still guarded and exit
+///
+/// Must be called from within the Tokio runtime.
+pub fn handle_interrupts() {
+    tokio::spawn(async {
+        if tokio::signal::ctrl_c().await.is_err() {
+            return;
+        }
+        let removed = remove_pending();
+        if removed > 0 {
+            eprintln!("\nInterrupted, removed {removed} partially written path(s)");
+        } else {
+            eprintln!("\nInterrupted");
+        }
+        process::exit(INTERRUPTED_EXIT_CODE);
+    });
+}
+
+/// Write `contents` to `path` through a `.part` file beside it, so `path`
+/// is only ever replaced by a complete file
+pub fn write_atomic(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    let partial = Guard::new(path.with_file_name(name));
+    fs::write(partial.path(), contents)?;
+    fs::rename(partial.path(), path)?;
+    partial.keep();
+    Ok(())
+}
+
+fn pending() -> std::sync::MutexGuard<'static, BTreeMap<u64, PathBuf>> {
+    // A panic while the lock was held can't leave the map inconsistent
+    PENDING.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Remove a file or directory, returning whether anything was there
+fn remove(path: &Path) -> bool {
+    let result = match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(_) => return false,
+    };
+    match result {
+        Ok(()) => {
+            debug!("Removed partial {}", path.display());
+            true
+        }
+        Err(e) => {
+            debug!("Failed to remove partial {}: {}", path.display(), e);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_guard_removes_unless_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let staging = dir.path().join(".agent.extracting");
+        let archive = dir.path().join("agent-1.0.0.zip");
+
+        {
+            let guard = Guard::new(&staging);
+            fs::create_dir_all(guard.path().join("nested")).unwrap();
+            fs::write(guard.path().join("nested/agent.md"), "partial").unwrap();
+        }
+        assert!(!staging.exists());
+
+        let guard = Guard::new(&archive);
+        fs::write(guard.path(), "complete").unwrap();
+        guard.keep();
+        assert!(archive.exists());
+
+        write_atomic(&archive, "replaced").unwrap();
+        assert_eq!(fs::read_to_string(&archive).unwrap(), "replaced");
+        assert!(!dir.path().join("agent-1.0.0.zip.part").exists());
+    }
+}
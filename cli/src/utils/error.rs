@@ -1,4 +1,5 @@
 use std::fmt;
+use std::time::Duration;
 
 /// Result type alias for Carp CLI operations
 pub type CarpResult<T> = Result<T, CarpError>;
@@ -18,17 +19,51 @@ pub enum CarpError {
     Config(String),
     /// Authentication errors
     Auth(String),
+    /// The API key was recognized but is past its expiry
+    ApiKeyExpired(String),
+    /// The API key is valid but not allowed to perform the operation
+    Forbidden(String),
     /// API errors with status code and message
     Api { status: u16, message: String },
-    /// Agent not found
-    #[allow(dead_code)]
-    AgentNotFound(String),
+    /// The registry asked us to slow down, optionally saying for how long
+    RateLimited {
+        retry_after: Option<Duration>,
+        message: String,
+    },
+    /// An earlier attempt with the same `Idempotency-Key` is still being
+    /// processed, optionally saying how long to wait before asking again
+    InProgress {
+        retry_after: Option<Duration>,
+        message: String,
+    },
+    /// Agent not found, as `name` or `name@version`, with any similarly
+    /// named agents the registry suggested
+    AgentNotFound {
+        name: String,
+        suggestions: Vec<String>,
+    },
     /// Invalid agent name or version
     InvalidAgent(String),
     /// Manifest parsing errors
     ManifestError(String),
     /// File system errors
     FileSystem(String),
+    /// Signing or signature verification errors
+    Signing(String),
+    /// An agent broke the rules of a `--policy` file, one message per rule
+    PolicyViolation(Vec<String>),
+    /// `carp outdated --ci` found this many agents with newer versions
+    Outdated(usize),
+    /// The command needs input that CI mode can't prompt for
+    PromptRequired(String),
+    /// A `Carp.toml` hook exited unsuccessfully
+    HookFailed(String),
+    /// Agent content looks like it holds credentials, one message per finding
+    SecretsDetected(Vec<String>),
+    /// The registry already has this `name@version`, and versions are immutable
+    VersionExists(String),
+    /// The registry is read-only for maintenance, with its message
+    Maintenance(String),
     /// Network connectivity errors
     #[allow(dead_code)]
     Network(String),
@@ -36,6 +71,201 @@ pub enum CarpError {
     Other(String),
 }
 
+/// Stable category of a failure, for scripts and CI to branch on
+///
+/// Both the string code and the exit code are part of the CLI's public
+/// interface: add new categories rather than renumbering existing ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// Anything without a more specific category
+    General,
+    /// Missing, invalid or insufficiently scoped credentials
+    Auth,
+    /// The agent or version doesn't exist
+    NotFound,
+    /// Invalid input: agent names, versions, manifests or request data
+    Validation,
+    /// The registry couldn't be reached
+    Network,
+    /// The registry asked us to slow down
+    RateLimited,
+    /// The config file or environment is invalid
+    Config,
+    /// Reading or writing local files failed
+    FileSystem,
+    /// Signing or signature verification failed
+    Signing,
+    /// The registry failed to handle the request
+    Server,
+    /// An agent was refused by a pull policy
+    Policy,
+    /// Project agents have newer versions available
+    Outdated,
+}
+
+impl ErrorCode {
+    /// Machine-readable name, as used in `--output json`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::General => "general",
+            ErrorCode::Auth => "auth",
+            ErrorCode::NotFound => "not_found",
+            ErrorCode::Validation => "validation",
+            ErrorCode::Network => "network",
+            ErrorCode::RateLimited => "rate_limited",
+            ErrorCode::Config => "config",
+            ErrorCode::FileSystem => "filesystem",
+            ErrorCode::Signing => "signing",
+            ErrorCode::Server => "server",
+            ErrorCode::Policy => "policy",
+            ErrorCode::Outdated => "outdated",
+        }
+    }
+
+    /// Process exit code. 2 is left to argument parsing errors.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorCode::General => 1,
+            ErrorCode::Auth => 3,
+            ErrorCode::NotFound => 4,
+            ErrorCode::Validation => 5,
+            ErrorCode::Network => 6,
+            ErrorCode::RateLimited => 7,
+            ErrorCode::Config => 8,
+            ErrorCode::FileSystem => 9,
+            ErrorCode::Signing => 10,
+            ErrorCode::Server => 11,
+            ErrorCode::Policy => 12,
+            ErrorCode::Outdated => 13,
+        }
+    }
+
+    fn from_status(status: u16) -> Self {
+        match status {
+            401 | 403 => ErrorCode::Auth,
+            404 | 410 => ErrorCode::NotFound,
+            400 | 409 | 413 | 422 => ErrorCode::Validation,
+            429 => ErrorCode::RateLimited,
+            500..=599 => ErrorCode::Server,
+            _ => ErrorCode::General,
+        }
+    }
+}
+
+impl CarpError {
+    /// The stable category this error belongs to
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            CarpError::Io(_) | CarpError::FileSystem(_) => ErrorCode::FileSystem,
+            CarpError::Http(e) => match e.status() {
+                Some(status) => ErrorCode::from_status(status.as_u16()),
+                None if e.is_decode() => ErrorCode::Server,
+                None => ErrorCode::Network,
+            },
+            CarpError::Json(_) => ErrorCode::General,
+            CarpError::Toml(_)
+            | CarpError::InvalidAgent(_)
+            | CarpError::ManifestError(_)
+            | CarpError::PromptRequired(_)
+            | CarpError::HookFailed(_)
+            | CarpError::SecretsDetected(_)
+            | CarpError::VersionExists(_) => ErrorCode::Validation,
+            CarpError::Config(_) => ErrorCode::Config,
+            CarpError::Auth(_) | CarpError::ApiKeyExpired(_) | CarpError::Forbidden(_) => {
+                ErrorCode::Auth
+            }
+            CarpError::Api { status, .. } => ErrorCode::from_status(*status),
+            CarpError::RateLimited { .. } => ErrorCode::RateLimited,
+            CarpError::InProgress { .. } => ErrorCode::Server,
+            CarpError::AgentNotFound { .. } => ErrorCode::NotFound,
+            CarpError::Signing(_) => ErrorCode::Signing,
+            CarpError::PolicyViolation(_) => ErrorCode::Policy,
+            CarpError::Outdated(_) => ErrorCode::Outdated,
+            CarpError::Maintenance(_) => ErrorCode::Server,
+            CarpError::Network(_) => ErrorCode::Network,
+            CarpError::Other(_) => ErrorCode::General,
+        }
+    }
+
+    /// Process exit code for this error
+    pub fn exit_code(&self) -> i32 {
+        self.code().exit_code()
+    }
+
+    /// A suggested next step for the user, printed after the error
+    ///
+    /// Keep remedies here rather than in error messages so every command
+    /// gives the same advice for the same failure.
+    pub fn hint(&self) -> Option<String> {
+        let hint = match self {
+            CarpError::ApiKeyExpired(_) => {
+                "Create a new key in the registry dashboard and run `carp auth login` to use it"
+                    .to_string()
+            }
+            CarpError::Auth(_) | CarpError::Api { status: 401, .. } => {
+                "Run `carp auth login`, pass --api-key, or check that CARP_API_KEY holds a valid key"
+                    .to_string()
+            }
+            CarpError::Forbidden(_) | CarpError::Api { status: 403, .. } => {
+                "Run `carp doctor` to see your key's scopes, and create a key with the needed scope in the registry dashboard"
+                    .to_string()
+            }
+            CarpError::AgentNotFound { name, suggestions } => match suggestions.as_slice() {
+                [] => {
+                    let name = name.split('@').next().unwrap_or(name);
+                    format!("Run `carp search {name}` to see available agents and versions")
+                }
+                [only] => format!("Did you mean `{only}`?"),
+                [rest @ .., last] => {
+                    let rest: Vec<String> = rest.iter().map(|s| format!("`{s}`")).collect();
+                    format!("Did you mean {} or `{last}`?", rest.join(", "))
+                }
+            },
+            CarpError::RateLimited {
+                retry_after: Some(delay),
+                ..
+            } => format!("Wait {}s and try again", delay.as_secs().max(1)),
+            CarpError::RateLimited { .. } => "Wait a minute and try again".to_string(),
+            CarpError::Api { status, .. } if *status >= 500 => {
+                "The registry is having problems; try again later, or run `carp healthcheck`"
+                    .to_string()
+            }
+            CarpError::InProgress { .. } => {
+                "An earlier attempt may still finish; run `carp search` in a minute to see if it was published"
+                    .to_string()
+            }
+            CarpError::Maintenance(_) => {
+                "Searching and downloading still work; try publishing again later".to_string()
+            }
+            CarpError::Config(_) => "Run `carp doctor` to check your configuration".to_string(),
+            CarpError::PolicyViolation(_) => {
+                "Ask your policy's owner to allow the agent, or pull a version that complies"
+                    .to_string()
+            }
+            CarpError::SecretsDetected(_) => {
+                "Remove and rotate the credentials, or pass --allow-secret <fingerprint> for each false positive"
+                    .to_string()
+            }
+            CarpError::VersionExists(_) => {
+                "Bump `version` in the agent's frontmatter or Carp.toml, or pass --force-new-version (--bump with --workspace) to publish the next version"
+                    .to_string()
+            }
+            CarpError::Outdated(_) => {
+                "Run `carp add <name>@<requirement>` to lock a newer version, or `carp add <name>` for the latest"
+                    .to_string()
+            }
+            CarpError::Network(_) => {
+                "Check your connection and proxy settings, or run `carp doctor`".to_string()
+            }
+            CarpError::Http(e) if e.status().is_none() && (e.is_connect() || e.is_timeout()) => {
+                "Check your connection and proxy settings, or run `carp doctor`".to_string()
+            }
+            _ => return None,
+        };
+        Some(hint)
+    }
+}
+
 impl fmt::Display for CarpError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -45,13 +275,46 @@ impl fmt::Display for CarpError {
             CarpError::Toml(e) => write!(f, "TOML error: {e}"),
             CarpError::Config(msg) => write!(f, "Configuration error: {msg}"),
             CarpError::Auth(msg) => write!(f, "Authentication error: {msg}"),
+            CarpError::ApiKeyExpired(msg) => write!(f, "Authentication error: {msg}"),
+            CarpError::Forbidden(msg) => write!(f, "Permission denied: {msg}"),
             CarpError::Api { status, message } => {
                 write!(f, "API error ({status}): {message}")
             }
-            CarpError::AgentNotFound(name) => write!(f, "Agent '{name}' not found"),
+            CarpError::RateLimited { message, .. } => write!(f, "Rate limited: {message}"),
+            CarpError::InProgress { message, .. } => write!(f, "Still in progress: {message}"),
+            CarpError::AgentNotFound { name, .. } => write!(f, "Agent '{name}' not found"),
             CarpError::InvalidAgent(msg) => write!(f, "Invalid agent: {msg}"),
             CarpError::ManifestError(msg) => write!(f, "Manifest error: {msg}"),
             CarpError::FileSystem(msg) => write!(f, "File system error: {msg}"),
+            CarpError::Signing(msg) => write!(f, "Signing error: {msg}"),
+            CarpError::PolicyViolation(violations) => match violations.as_slice() {
+                [violation] => write!(f, "Policy violation: {violation}"),
+                _ => {
+                    write!(f, "Policy violations:")?;
+                    for violation in violations {
+                        write!(f, "\n  - {violation}")?;
+                    }
+                    Ok(())
+                }
+            },
+            CarpError::Outdated(1) => write!(f, "1 agent has a newer version"),
+            CarpError::Outdated(count) => write!(f, "{count} agents have newer versions"),
+            CarpError::PromptRequired(msg) => write!(f, "Input required: {msg}"),
+            CarpError::HookFailed(msg) => write!(f, "Hook failed: {msg}"),
+            CarpError::SecretsDetected(findings) => {
+                write!(f, "Possible secrets in agent content:")?;
+                for finding in findings {
+                    write!(f, "\n  - {finding}")?;
+                }
+                Ok(())
+            }
+            CarpError::VersionExists(id) => {
+                write!(
+                    f,
+                    "Version {id} is already published and can't be overwritten"
+                )
+            }
+            CarpError::Maintenance(msg) => write!(f, "Registry in maintenance: {msg}"),
             CarpError::Network(msg) => write!(f, "Network error: {msg}"),
             CarpError::Other(msg) => write!(f, "{msg}"),
         }
@@ -99,3 +362,89 @@ impl From<zip::result::ZipError> for CarpError {
         CarpError::FileSystem(format!("ZIP error: {err}"))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_codes() {
+        let api = |status| CarpError::Api {
+            status,
+            message: String::new(),
+        };
+
+        assert_eq!(api(401).code(), ErrorCode::Auth);
+        assert_eq!(api(404).exit_code(), 4);
+        assert_eq!(api(422).code(), ErrorCode::Validation);
+        assert_eq!(api(503).code(), ErrorCode::Server);
+        assert_eq!(CarpError::Auth(String::new()).exit_code(), 3);
+        assert_eq!(CarpError::InvalidAgent(String::new()).exit_code(), 5);
+        assert_eq!(CarpError::Network(String::new()).exit_code(), 6);
+        assert_eq!(CarpError::Other(String::new()).exit_code(), 1);
+        assert_eq!(CarpError::Forbidden(String::new()).exit_code(), 3);
+        assert_eq!(CarpError::ApiKeyExpired(String::new()).exit_code(), 3);
+    }
+
+    #[test]
+    fn test_hints() {
+        let mut not_found = CarpError::AgentNotFound {
+            name: "code-review@1.2.0".to_string(),
+            suggestions: Vec::new(),
+        };
+        assert_eq!(
+            not_found.hint().as_deref(),
+            Some("Run `carp search code-review` to see available agents and versions")
+        );
+        if let CarpError::AgentNotFound { suggestions, .. } = &mut not_found {
+            suggestions.extend(["code-reviewer".to_string(), "code-review-bot".to_string()]);
+        }
+        assert_eq!(
+            not_found.hint().as_deref(),
+            Some("Did you mean `code-reviewer` or `code-review-bot`?")
+        );
+
+        let rate_limited = CarpError::RateLimited {
+            retry_after: Some(Duration::from_secs(30)),
+            message: String::new(),
+        };
+        assert_eq!(
+            rate_limited.hint().as_deref(),
+            Some("Wait 30s and try again")
+        );
+
+        assert!(CarpError::Auth(String::new())
+            .hint()
+            .unwrap()
+            .contains("carp auth login"));
+        assert!(CarpError::ApiKeyExpired(String::new())
+            .hint()
+            .unwrap()
+            .contains("new key"));
+        assert!(CarpError::InvalidAgent(String::new()).hint().is_none());
+    }
+
+    #[test]
+    fn test_exit_codes_are_distinct() {
+        let codes = [
+            ErrorCode::General,
+            ErrorCode::Auth,
+            ErrorCode::NotFound,
+            ErrorCode::Validation,
+            ErrorCode::Network,
+            ErrorCode::RateLimited,
+            ErrorCode::Config,
+            ErrorCode::FileSystem,
+            ErrorCode::Signing,
+            ErrorCode::Server,
+            ErrorCode::Policy,
+            ErrorCode::Outdated,
+        ];
+
+        let mut exit_codes: Vec<i32> = codes.iter().map(|c| c.exit_code()).collect();
+        exit_codes.sort_unstable();
+        exit_codes.dedup();
+        assert_eq!(exit_codes.len(), codes.len());
+        assert!(!exit_codes.contains(&0) && !exit_codes.contains(&2));
+    }
+}
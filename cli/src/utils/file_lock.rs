@@ -0,0 +1,138 @@
+//! Advisory file locks, so concurrent carp invocations (the jobs of a CI
+//! matrix sharing a home directory, say) take turns with the files they share
+//!
+//! Files carp rewrites in place, like `config.toml` and `carp.lock`, are
+//! locked themselves: writers hold an exclusive lock while they truncate and
+//! rewrite the file, readers a shared one, so nobody reads it half written
+//! and concurrent writes can't interleave. Paths that are replaced rather
+//! than rewritten, like archives in the download cache, are locked through a
+//! `<path>.lock` file beside them, which is left in place; removing it would
+//! let a process waiting on the old file and one opening a new file both
+//! think they hold the lock.
+//!
+//! The locks are advisory: they only keep out other carp processes.
+
+use crate::utils::terminal;
+use colored::*;
+use fs4::fs_std::FileExt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use tracing::debug;
+
+/// An open file, locked until it's dropped
+#[derive(Debug)]
+pub struct LockedFile {
+    file: File,
+}
+
+impl LockedFile {
+    /// Open `path` with an exclusive lock, waiting for other carp processes
+    /// to let go of it and creating it if it doesn't exist
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Self::open_with(path, true)
+    }
+
+    /// Lock `<path>.lock` exclusively, for paths that are replaced rather
+    /// than rewritten
+    pub fn beside(path: &Path) -> io::Result<Self> {
+        let mut name = path.file_name().unwrap_or_default().to_os_string();
+        name.push(".lock");
+        Self::open(&path.with_file_name(name))
+    }
+
+    fn open_with(path: &Path, exclusive: bool) -> io::Result<Self> {
+        // Readers only need to read, so read-only files can still be read
+        let file = OpenOptions::new()
+            .read(true)
+            .write(exclusive)
+            .create(exclusive)
+            .truncate(false)
+            .open(path)?;
+        // Called through the trait, since newer `File`s have inherent locking
+        // methods of the same names that the MSRV doesn't
+        let locked = if exclusive {
+            FileExt::try_lock_exclusive(&file)?
+        } else {
+            FileExt::try_lock_shared(&file)?
+        };
+        if !locked {
+            eprintln!(
+                "{} for another carp process to finish with {}",
+                terminal::for_stderr("Waiting".yellow().bold()),
+                path.display()
+            );
+            if exclusive {
+                FileExt::lock_exclusive(&file)?;
+            } else {
+                FileExt::lock_shared(&file)?;
+            }
+        }
+        debug!(
+            "Locked {} ({})",
+            path.display(),
+            if exclusive { "exclusive" } else { "shared" }
+        );
+        Ok(Self { file })
+    }
+
+    pub fn read_to_string(&mut self) -> io::Result<String> {
+        let mut contents = String::new();
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.read_to_string(&mut contents)?;
+        Ok(contents)
+    }
+
+    /// Replace the file's contents, keeping the lock
+    pub fn replace(&mut self, contents: impl AsRef<[u8]>) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.set_len(0)?;
+        self.file.write_all(contents.as_ref())?;
+        self.file.sync_data()
+    }
+
+    /// The open file, to change its metadata
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+}
+
+/// Read a file carp rewrites in place, waiting for any write in progress
+pub fn read_to_string(path: &Path) -> io::Result<String> {
+    LockedFile::open_with(path, false)?.read_to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_concurrent_updates_are_not_lost() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("counter");
+        LockedFile::open(&path).unwrap().replace("0").unwrap();
+
+        // Each holds the lock from reading the count to writing it back;
+        // separate opens lock against each other just as processes do
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let path = path.clone();
+                thread::spawn(move || {
+                    for _ in 0..25 {
+                        let mut file = LockedFile::open(&path).unwrap();
+                        let count: u32 = file.read_to_string().unwrap().parse().unwrap();
+                        file.replace((count + 1).to_string()).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(read_to_string(&path).unwrap(), "200");
+        LockedFile::beside(&path).unwrap();
+        assert!(dir.path().join("counter.lock").is_file());
+    }
+}
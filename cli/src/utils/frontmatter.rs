@@ -0,0 +1,84 @@
+use crate::utils::error::{CarpError, CarpResult};
+
+/// Split agent content into its parsed YAML frontmatter and the markdown body.
+///
+/// The frontmatter must start on the first line with `---` and is closed by
+/// either `---` or `...`.
+pub fn parse_frontmatter(content: &str) -> CarpResult<(serde_json::Value, String)> {
+    if !content.starts_with("---") {
+        return Err(CarpError::ManifestError(
+            "Agent file does not contain YAML frontmatter".to_string(),
+        ));
+    }
+
+    let lines: Vec<&str> = content.lines().collect();
+    let frontmatter_end = lines
+        .iter()
+        .enumerate()
+        .skip(1)
+        .find(|(_, line)| {
+            let trimmed = line.trim();
+            trimmed == "---" || trimmed == "..."
+        })
+        .map(|(i, _)| i)
+        .ok_or_else(|| {
+            CarpError::ManifestError(
+                "Invalid YAML frontmatter: missing closing --- or ...".to_string(),
+            )
+        })?;
+
+    let frontmatter_content = lines[1..frontmatter_end].join("\n");
+    let frontmatter: serde_json::Value = serde_yaml::from_str(&frontmatter_content)
+        .map_err(|e| CarpError::ManifestError(format!("Invalid YAML frontmatter: {e}")))?;
+
+    let body = lines[frontmatter_end + 1..].join("\n");
+
+    Ok((frontmatter, body))
+}
+
+/// Render agent content from a frontmatter mapping and a markdown body
+pub fn render_frontmatter(frontmatter: &serde_json::Value, body: &str) -> CarpResult<String> {
+    let yaml = serde_yaml::to_string(frontmatter)
+        .map_err(|e| CarpError::ManifestError(format!("Failed to serialize frontmatter: {e}")))?;
+
+    Ok(format!(
+        "---\n{}---\n\n{}\n",
+        yaml,
+        body.trim_start_matches('\n')
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_frontmatter() {
+        let content = "---\nname: test-agent\ndescription: A test agent\n---\n\n# Test\n";
+        let (frontmatter, body) = parse_frontmatter(content).unwrap();
+
+        assert_eq!(frontmatter["name"], "test-agent");
+        assert_eq!(frontmatter["description"], "A test agent");
+        assert_eq!(body.trim(), "# Test");
+    }
+
+    #[test]
+    fn test_parse_frontmatter_errors() {
+        assert!(parse_frontmatter("# No frontmatter").is_err());
+        assert!(parse_frontmatter("---\nname: unterminated\n").is_err());
+        assert!(parse_frontmatter("---\nname: [unclosed\n---\n").is_err());
+    }
+
+    #[test]
+    fn test_render_round_trip() {
+        let frontmatter = serde_json::json!({
+            "name": "test-agent",
+            "description": "Handles: colons and 'quotes'",
+        });
+        let content = render_frontmatter(&frontmatter, "# Body").unwrap();
+        let (parsed, body) = parse_frontmatter(&content).unwrap();
+
+        assert_eq!(parsed, frontmatter);
+        assert_eq!(body.trim(), "# Body");
+    }
+}
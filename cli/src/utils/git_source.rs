@@ -0,0 +1,342 @@
+use crate::utils::agent_dir::{self, AgentDir};
+use crate::utils::error::{CarpError, CarpResult};
+use std::fmt;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+use tracing::debug;
+
+/// Prefix marking an agent spec as a git repository
+pub const GIT_PREFIX: &str = "git+";
+
+/// An agent in a git repository, written like Cargo's git sources:
+///
+/// ```text
+/// git+https://github.com/acme/agents?tag=v1.2.0&subdir=code-reviewer
+/// ```
+///
+/// At most one of `branch`, `tag` and `rev` picks what to check out; without
+/// one the default branch is used. Lockfiles append the resolved commit as a
+/// fragment, `...#0123abc...`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GitSource {
+    /// Repository URL without the `git+` prefix or query
+    pub url: String,
+    pub reference: GitReference,
+    /// Directory in the repository holding the agent
+    pub subdir: Option<String>,
+}
+
+/// What to check out from a repository
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GitReference {
+    DefaultBranch,
+    Branch(String),
+    Tag(String),
+    Rev(String),
+}
+
+impl GitSource {
+    /// Build a source from its parts, as written in a spec or `carp.toml`
+    pub fn new(
+        url: &str,
+        branch: Option<&str>,
+        tag: Option<&str>,
+        rev: Option<&str>,
+        subdir: Option<&str>,
+    ) -> CarpResult<Self> {
+        let parsed = reqwest::Url::parse(url)
+            .map_err(|e| CarpError::InvalidAgent(format!("Invalid git URL '{url}': {e}")))?;
+        if !matches!(parsed.scheme(), "https" | "ssh" | "file") {
+            return Err(CarpError::InvalidAgent(format!(
+                "Git URL '{url}' must use https, ssh or file"
+            )));
+        }
+        if parsed.query().is_some() || parsed.fragment().is_some() {
+            return Err(CarpError::InvalidAgent(format!(
+                "Git URL '{url}' can't have a query or fragment"
+            )));
+        }
+
+        let reference = match (branch, tag, rev) {
+            (None, None, None) => GitReference::DefaultBranch,
+            (Some(branch), None, None) => GitReference::Branch(branch.to_string()),
+            (None, Some(tag), None) => GitReference::Tag(tag.to_string()),
+            (None, None, Some(rev)) => GitReference::Rev(rev.to_string()),
+            _ => {
+                return Err(CarpError::InvalidAgent(format!(
+                    "Git source '{url}' can only give one of branch, tag and rev"
+                )))
+            }
+        };
+        if let GitReference::Branch(name) | GitReference::Tag(name) | GitReference::Rev(name) =
+            &reference
+        {
+            if name.is_empty() || name.starts_with('-') {
+                return Err(CarpError::InvalidAgent(format!(
+                    "Invalid git reference '{name}' for '{url}'"
+                )));
+            }
+        }
+
+        let subdir = subdir.map(|subdir| subdir.trim_matches('/'));
+        if let Some(subdir) = subdir {
+            let escapes = Path::new(subdir)
+                .components()
+                .any(|component| !matches!(component, Component::Normal(_)));
+            if subdir.is_empty() || escapes {
+                return Err(CarpError::InvalidAgent(format!(
+                    "Subdirectory '{subdir}' must be a relative path inside the repository"
+                )));
+            }
+        }
+
+        Ok(Self {
+            url: parsed.to_string(),
+            reference,
+            subdir: subdir.map(str::to_string),
+        })
+    }
+
+    /// Parse a `git+<url>?<options>` spec
+    pub fn parse(spec: &str) -> CarpResult<Self> {
+        let rest = spec.strip_prefix(GIT_PREFIX).ok_or_else(|| {
+            CarpError::InvalidAgent(format!("'{spec}' is not a git+<url> source"))
+        })?;
+        let mut url = reqwest::Url::parse(rest)
+            .map_err(|e| CarpError::InvalidAgent(format!("Invalid git URL '{rest}': {e}")))?;
+        let options: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+        url.set_query(None);
+
+        let (mut branch, mut tag, mut rev, mut subdir) = (None, None, None, None);
+        for (key, value) in options {
+            let slot = match key.as_str() {
+                "branch" => &mut branch,
+                "tag" => &mut tag,
+                "rev" => &mut rev,
+                "subdir" => &mut subdir,
+                other => {
+                    return Err(CarpError::InvalidAgent(format!(
+                        "Unknown option '{other}' in '{spec}'; expected branch, tag, rev or subdir"
+                    )))
+                }
+            };
+            *slot = Some(value);
+        }
+
+        Self::new(
+            url.as_str(),
+            branch.as_deref(),
+            tag.as_deref(),
+            rev.as_deref(),
+            subdir.as_deref(),
+        )
+    }
+
+    /// Parse a lockfile source, `<spec>#<commit>`, into the source and commit
+    pub fn parse_locked(locked: &str) -> CarpResult<(Self, String)> {
+        let (spec, commit) = locked.rsplit_once('#').ok_or_else(|| {
+            CarpError::ManifestError(format!("Locked source '{locked}' has no commit"))
+        })?;
+        if commit.len() < 7 || !commit.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(CarpError::ManifestError(format!(
+                "Locked source '{locked}' has an invalid commit"
+            )));
+        }
+        Ok((Self::parse(spec)?, commit.to_string()))
+    }
+
+    /// The source pinned to a commit, as written to the lockfile
+    pub fn locked(&self, commit: &str) -> String {
+        format!("{self}#{commit}")
+    }
+
+    /// Clone the repository into a temporary directory and check out the
+    /// reference, or `commit` when the lockfile pins one
+    pub fn checkout(&self, commit: Option<&str>) -> CarpResult<Checkout> {
+        let dir = std::env::temp_dir().join(format!("carp-git-{}", uuid::Uuid::new_v4()));
+        let mut checkout = Checkout {
+            dir,
+            subdir: self.subdir.clone(),
+            commit: String::new(),
+        };
+        let target = checkout.dir.to_string_lossy().into_owned();
+
+        debug!("Cloning {} into {}...", self.url, checkout.dir.display());
+        let rev = commit.or(match &self.reference {
+            GitReference::Rev(rev) => Some(rev.as_str()),
+            _ => None,
+        });
+        match (rev, &self.reference) {
+            // Arbitrary commits can't be fetched shallowly from every server
+            (Some(rev), _) => {
+                git(
+                    None,
+                    &[
+                        "clone",
+                        "--quiet",
+                        "--no-checkout",
+                        "--",
+                        &self.url,
+                        &target,
+                    ],
+                )?;
+                git(
+                    Some(&checkout.dir),
+                    &["checkout", "--quiet", "--detach", rev, "--"],
+                )?;
+            }
+            (None, GitReference::Branch(name) | GitReference::Tag(name)) => {
+                git(
+                    None,
+                    &[
+                        "clone", "--quiet", "--depth", "1", "--branch", name, "--", &self.url,
+                        &target,
+                    ],
+                )?;
+            }
+            (None, _) => {
+                git(
+                    None,
+                    &["clone", "--quiet", "--depth", "1", "--", &self.url, &target],
+                )?;
+            }
+        }
+
+        checkout.commit = git(Some(&checkout.dir), &["rev-parse", "HEAD"])?;
+        Ok(checkout)
+    }
+}
+
+impl fmt::Display for GitSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reference = match &self.reference {
+            GitReference::DefaultBranch => None,
+            GitReference::Branch(name) => Some(("branch", name)),
+            GitReference::Tag(name) => Some(("tag", name)),
+            GitReference::Rev(name) => Some(("rev", name)),
+        };
+        let options: Vec<(&str, &String)> = reference
+            .into_iter()
+            .chain(self.subdir.as_ref().map(|subdir| ("subdir", subdir)))
+            .collect();
+
+        let mut url = reqwest::Url::parse(&self.url).map_err(|_| fmt::Error)?;
+        if !options.is_empty() {
+            url.query_pairs_mut().extend_pairs(options);
+        }
+        write!(f, "{GIT_PREFIX}{url}")
+    }
+}
+
+/// A checked out repository, deleted when dropped
+#[derive(Debug)]
+pub struct Checkout {
+    dir: PathBuf,
+    subdir: Option<String>,
+    /// The commit that was checked out
+    pub commit: String,
+}
+
+impl Checkout {
+    /// Directory holding the agent
+    fn root(&self) -> PathBuf {
+        match &self.subdir {
+            Some(subdir) => self.dir.join(subdir),
+            None => self.dir.clone(),
+        }
+    }
+
+    /// Find and validate the agent
+    pub fn agent(&self) -> CarpResult<AgentDir> {
+        let root = self.root();
+        let label = self.subdir.as_deref().unwrap_or(".");
+        if !root.is_dir() {
+            return Err(CarpError::InvalidAgent(format!(
+                "'{label}' is not a directory at commit {}",
+                self.commit
+            )));
+        }
+        AgentDir::read(&root, label)
+    }
+
+    /// SHA-256 over the agent's files and their paths, as `sha256:<hex>`
+    pub fn checksum(&self) -> CarpResult<String> {
+        agent_dir::checksum(&self.root())
+    }
+
+    /// Copy the agent's files into `dest`, replacing whatever is there, and
+    /// return how many were copied
+    pub fn install(&self, dest: &Path) -> CarpResult<usize> {
+        agent_dir::copy(&self.root(), dest)
+    }
+}
+
+impl Drop for Checkout {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Run git, failing with its stderr, and return its trimmed stdout
+fn git(dir: Option<&Path>, args: &[&str]) -> CarpResult<String> {
+    let mut command = Command::new("git");
+    command.args(args).env("GIT_TERMINAL_PROMPT", "0");
+    if let Some(dir) = dir {
+        command.current_dir(dir);
+    }
+
+    let output = command
+        .output()
+        .map_err(|e| CarpError::Config(format!("Could not run git, is it installed? {e}")))?;
+    if !output.status.success() {
+        return Err(CarpError::InvalidAgent(format!(
+            "git {} failed: {}",
+            args[0],
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_source() {
+        let source =
+            GitSource::parse("git+https://github.com/acme/agents?tag=v1.2.0&subdir=reviewer/")
+                .unwrap();
+        assert_eq!(source.url, "https://github.com/acme/agents");
+        assert_eq!(source.reference, GitReference::Tag("v1.2.0".to_string()));
+        assert_eq!(source.subdir.as_deref(), Some("reviewer"));
+        assert_eq!(
+            source.to_string(),
+            "git+https://github.com/acme/agents?tag=v1.2.0&subdir=reviewer"
+        );
+
+        let plain = GitSource::parse("git+ssh://git@github.com/acme/agents.git").unwrap();
+        assert_eq!(plain.reference, GitReference::DefaultBranch);
+        assert_eq!(
+            plain.to_string(),
+            "git+ssh://git@github.com/acme/agents.git"
+        );
+
+        let (locked, commit) = GitSource::parse_locked(&source.locked("0123abcd")).unwrap();
+        assert_eq!(locked, source);
+        assert_eq!(commit, "0123abcd");
+
+        for invalid in [
+            "https://github.com/acme/agents",
+            "git+http://github.com/acme/agents",
+            "git+https://github.com/acme/agents?tag=v1&rev=abc",
+            "git+https://github.com/acme/agents?subdir=../secrets",
+            "git+https://github.com/acme/agents?subdir=.",
+            "git+https://github.com/acme/agents?tag=--upload-pack=x",
+            "git+https://github.com/acme/agents?version=1",
+        ] {
+            assert!(GitSource::parse(invalid).is_err(), "{invalid}");
+        }
+    }
+}
@@ -0,0 +1,80 @@
+//! Diagnostic output for `--verbose`
+//!
+//! Commands and the API client emit `tracing` events; this installs the
+//! subscriber that prints them to stderr. Regular command output still goes
+//! to stdout, so verbose runs can be piped without mixing the two.
+//!
+//! | Flags      | Shows                                          |
+//! |------------|------------------------------------------------|
+//! | (none)     | warnings                                       |
+//! | `-v`       | progress details, retries                      |
+//! | `-vv`      | a summary of every HTTP request and response   |
+//! | `--quiet`  | errors only                                    |
+//!
+//! `CARP_LOG` takes a full `tracing` filter (e.g. `carp=trace,hyper=debug`)
+//! and overrides the flags.
+
+use crate::utils::terminal;
+use tracing_subscriber::EnvFilter;
+
+/// Environment variable holding an explicit log filter
+pub const LOG_ENV: &str = "CARP_LOG";
+
+/// Target of the per-request HTTP summaries
+pub const HTTP_TARGET: &str = "carp::http";
+
+/// Install the stderr subscriber for the given `-v` count
+pub fn init(verbosity: u8, quiet: bool) {
+    let filter = EnvFilter::try_from_env(LOG_ENV)
+        .unwrap_or_else(|_| EnvFilter::new(default_filter(verbosity, quiet)));
+
+    // Only fails if a subscriber is already installed, which is harmless
+    let _ = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .with_ansi(terminal::stderr_colors())
+        .without_time()
+        .with_target(false)
+        .try_init();
+}
+
+fn default_filter(verbosity: u8, quiet: bool) -> String {
+    let (base, level, http) = match (quiet, verbosity) {
+        (true, _) => ("error", "error", "off"),
+        (false, 0) => ("warn", "warn", "off"),
+        (false, 1) => ("warn", "debug", "off"),
+        (false, _) => ("warn", "trace", "trace"),
+    };
+
+    format!(
+        "{base},{}={level},{HTTP_TARGET}={http}",
+        env!("CARGO_CRATE_NAME")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_filter() {
+        let crate_name = env!("CARGO_CRATE_NAME");
+
+        assert_eq!(
+            default_filter(0, false),
+            format!("warn,{crate_name}=warn,carp::http=off")
+        );
+        assert_eq!(
+            default_filter(1, false),
+            format!("warn,{crate_name}=debug,carp::http=off")
+        );
+        assert_eq!(
+            default_filter(3, false),
+            format!("warn,{crate_name}=trace,carp::http=trace")
+        );
+        assert_eq!(
+            default_filter(2, true),
+            format!("error,{crate_name}=error,carp::http=off")
+        );
+    }
+}
@@ -1,7 +1,17 @@
+use crate::utils::cleanup;
 use crate::utils::error::{CarpError, CarpResult};
+use crate::utils::pattern::glob_match;
+use crate::utils::variables::{self, Variables};
+use colored::*;
+use semver::Version;
 use serde::{Deserialize, Serialize};
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use toml_edit::DocumentMut;
+
+/// File name of an agent manifest
+pub const MANIFEST_FILE: &str = "Carp.toml";
 
 /// Agent manifest structure
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -28,16 +38,226 @@ pub struct AgentManifest {
     pub main: Option<String>,
     /// Dependencies on other agents
     pub dependencies: Option<std::collections::HashMap<String, String>>,
+    /// Commands run while publishing
+    #[serde(default, skip_serializing_if = "Hooks::is_empty")]
+    pub hooks: Hooks,
+    /// `${NAME}` variables the installing project fills in
+    #[serde(default, skip_serializing_if = "Variables::is_empty")]
+    pub variables: Variables,
+}
+
+/// Commands from a manifest's `[hooks]` table
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Hooks {
+    /// Run in order before agents are packaged; the first failure aborts the publish
+    #[serde(default)]
+    pub pre_publish: Vec<String>,
+}
+
+impl Hooks {
+    pub fn is_empty(&self) -> bool {
+        self.pre_publish.is_empty()
+    }
+
+    /// Find the nearest `Carp.toml` in `dir`, an absolute path, or its
+    /// parents and read its hooks
+    ///
+    /// Only the `[hooks]` table is parsed, so a manifest that exists just to
+    /// hold hooks doesn't need the package fields.
+    pub fn find(dir: &Path) -> CarpResult<Option<(PathBuf, Hooks)>> {
+        #[derive(Deserialize)]
+        struct HooksOnly {
+            #[serde(default)]
+            hooks: Hooks,
+        }
+
+        let Some(path) = dir
+            .ancestors()
+            .map(|dir| dir.join(MANIFEST_FILE))
+            .find(|path| path.is_file())
+        else {
+            return Ok(None);
+        };
+
+        let contents = fs::read_to_string(&path)
+            .map_err(|e| CarpError::ManifestError(format!("Failed to read manifest: {e}")))?;
+        let manifest: HooksOnly = toml::from_str(&contents).map_err(|e| {
+            CarpError::ManifestError(format!("Failed to parse {}: {e}", path.display()))
+        })?;
+        Ok(Some((path, manifest.hooks)))
+    }
+
+    /// Run the pre-publish hooks through the shell from `root`, the manifest's
+    /// directory, stopping at the first that fails
+    pub fn run_pre_publish(&self, root: &Path, publish_dir: &Path) -> CarpResult<()> {
+        for hook in &self.pre_publish {
+            println!("{} Running pre-publish hook: {}", "⟳".blue().bold(), hook);
+
+            let status = shell(hook)
+                .current_dir(root)
+                .env("CARP_PUBLISH_DIR", publish_dir)
+                .status()
+                .map_err(|e| CarpError::HookFailed(format!("Could not run `{hook}`: {e}")))?;
+
+            if !status.success() {
+                let reason = status
+                    .code()
+                    .map(|code| format!("exited with code {code}"))
+                    .unwrap_or_else(|| "was killed by a signal".to_string());
+                return Err(CarpError::HookFailed(format!(
+                    "pre-publish hook `{hook}` {reason}; nothing was published"
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Agents kept in one repository and published together by
+/// `carp publish --workspace`, from the `[workspace]` table of the
+/// repository's root `Carp.toml`
+///
+/// ```toml
+/// [workspace]
+/// members = ["agents/*"]
+/// bump = "minor"
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WorkspaceSettings {
+    /// Directories holding a member's `Carp.toml`, relative to the root;
+    /// `*` and `?` match within one path component
+    #[serde(default = "default_members")]
+    pub members: Vec<String>,
+    /// How changed agents whose version is already published are numbered
+    #[serde(default)]
+    pub bump: Bump,
+}
+
+impl Default for WorkspaceSettings {
+    fn default() -> Self {
+        Self {
+            members: default_members(),
+            bump: Bump::default(),
+        }
+    }
+}
+
+fn default_members() -> Vec<String> {
+    vec!["agents/*".to_string()]
+}
+
+/// The version a changed agent is published as when its own isn't newer
+/// than the registry's latest
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Bump {
+    #[default]
+    Patch,
+    Minor,
+    Major,
+    /// Refuse to publish, so versions are only ever bumped by hand
+    None,
+}
+
+impl Bump {
+    /// The version after `latest`, if this strategy picks one
+    pub fn next(self, latest: &Version) -> Option<Version> {
+        match self {
+            Bump::Patch => Some(Version::new(latest.major, latest.minor, latest.patch + 1)),
+            Bump::Minor => Some(Version::new(latest.major, latest.minor + 1, 0)),
+            Bump::Major => Some(Version::new(latest.major + 1, 0, 0)),
+            Bump::None => None,
+        }
+    }
+}
+
+impl WorkspaceSettings {
+    /// Read the `[workspace]` table of the `Carp.toml` in `root`, or the
+    /// defaults if it has none
+    ///
+    /// Like [`Hooks::find`], only the table itself is parsed.
+    pub fn load(root: &Path) -> CarpResult<Self> {
+        #[derive(Deserialize)]
+        struct WorkspaceOnly {
+            #[serde(default)]
+            workspace: WorkspaceSettings,
+        }
+
+        let path = root.join(MANIFEST_FILE);
+        if !path.is_file() {
+            return Ok(Self::default());
+        }
+        let contents = fs::read_to_string(&path)
+            .map_err(|e| CarpError::ManifestError(format!("Failed to read manifest: {e}")))?;
+        let manifest: WorkspaceOnly = toml::from_str(&contents).map_err(|e| {
+            CarpError::ManifestError(format!("Failed to parse {}: {e}", path.display()))
+        })?;
+        Ok(manifest.workspace)
+    }
+
+    /// The member directories under `root` that hold a `Carp.toml`, sorted
+    pub fn member_dirs(&self, root: &Path) -> CarpResult<Vec<PathBuf>> {
+        let mut dirs = Vec::new();
+        for pattern in &self.members {
+            let mut matched = vec![root.to_path_buf()];
+            for component in pattern.split('/').filter(|c| !c.is_empty() && *c != ".") {
+                if !component.contains(['*', '?']) {
+                    matched = matched.into_iter().map(|dir| dir.join(component)).collect();
+                    continue;
+                }
+
+                let mut next = Vec::new();
+                for dir in matched.iter().filter(|dir| dir.is_dir()) {
+                    for entry in fs::read_dir(dir)? {
+                        let path = entry?.path();
+                        let name = path.file_name().and_then(|name| name.to_str());
+                        if path.is_dir() && name.is_some_and(|name| glob_match(component, name)) {
+                            next.push(path);
+                        }
+                    }
+                }
+                matched = next;
+            }
+            dirs.extend(
+                matched
+                    .into_iter()
+                    .filter(|dir| dir.join(MANIFEST_FILE).is_file()),
+            );
+        }
+
+        dirs.sort();
+        dirs.dedup();
+        Ok(dirs)
+    }
+}
+
+#[cfg(unix)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
 }
 
 impl AgentManifest {
     /// Load manifest from a TOML file
-    #[allow(dead_code)]
     pub fn load<P: AsRef<Path>>(path: P) -> CarpResult<Self> {
         let contents = fs::read_to_string(&path)
             .map_err(|e| CarpError::ManifestError(format!("Failed to read manifest: {e}")))?;
+        Self::parse(&contents)
+    }
 
-        let manifest: AgentManifest = toml::from_str(&contents)
+    /// Parse and validate the contents of a manifest
+    pub fn parse(contents: &str) -> CarpResult<Self> {
+        let manifest: AgentManifest = toml::from_str(contents)
             .map_err(|e| CarpError::ManifestError(format!("Failed to parse manifest: {e}")))?;
 
         manifest.validate()?;
@@ -58,8 +278,26 @@ impl AgentManifest {
         Ok(())
     }
 
+    /// Change the `version` of the manifest at `path`, keeping the rest of
+    /// the file as written
+    pub fn write_version(path: &Path, version: &str) -> CarpResult<()> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| CarpError::ManifestError(format!("Failed to read manifest: {e}")))?;
+        let mut document = contents.parse::<DocumentMut>().map_err(|e| {
+            CarpError::ManifestError(format!("Failed to parse {}: {e}", path.display()))
+        })?;
+        // Keep any comment after the old version
+        let mut value = toml_edit::Value::from(version);
+        if let Some(old) = document.get("version").and_then(|item| item.as_value()) {
+            *value.decor_mut() = old.decor().clone();
+        }
+        document["version"] = toml_edit::Item::Value(value);
+        cleanup::write_atomic(path, document.to_string())
+            .map_err(|e| CarpError::ManifestError(format!("Failed to write manifest: {e}")))?;
+        Ok(())
+    }
+
     /// Validate the manifest
-    #[allow(dead_code)]
     pub fn validate(&self) -> CarpResult<()> {
         if self.name.is_empty() {
             return Err(CarpError::ManifestError(
@@ -107,7 +345,7 @@ impl AgentManifest {
             ));
         }
 
-        Ok(())
+        variables::validate(&self.variables)
     }
 
     /// Create a default manifest template
@@ -129,6 +367,8 @@ impl AgentManifest {
             ],
             main: Some("agent.py".to_string()),
             dependencies: None,
+            hooks: Hooks::default(),
+            variables: Variables::new(),
         }
     }
 }
@@ -165,5 +405,91 @@ mod tests {
         assert_eq!(manifest.name, deserialized.name);
         assert_eq!(manifest.version, deserialized.version);
         assert_eq!(manifest.description, deserialized.description);
+        assert!(!toml_str.contains("[hooks]"));
+    }
+
+    #[test]
+    fn test_workspace_members_and_bump() {
+        let dir = tempfile::TempDir::new().unwrap();
+        assert_eq!(
+            WorkspaceSettings::load(dir.path()).unwrap(),
+            WorkspaceSettings::default()
+        );
+
+        fs::write(
+            dir.path().join(MANIFEST_FILE),
+            "[workspace]\nmembers = [\"agents/*\", \"extra/one\"]\nbump = \"minor\"\n\n[hooks]\npre-publish = []\n",
+        )
+        .unwrap();
+        for member in ["agents/b", "agents/a", "agents/empty", "extra/one"] {
+            fs::create_dir_all(dir.path().join(member)).unwrap();
+            if member != "agents/empty" {
+                fs::write(dir.path().join(member).join(MANIFEST_FILE), "").unwrap();
+            }
+        }
+
+        let settings = WorkspaceSettings::load(dir.path()).unwrap();
+        assert_eq!(settings.bump, Bump::Minor);
+        let members: Vec<_> = settings
+            .member_dirs(dir.path())
+            .unwrap()
+            .into_iter()
+            .map(|member| member.strip_prefix(dir.path()).unwrap().to_path_buf())
+            .collect();
+        assert_eq!(
+            members,
+            [
+                Path::new("agents/a"),
+                Path::new("agents/b"),
+                Path::new("extra/one")
+            ]
+        );
+
+        let latest = Version::new(1, 4, 2);
+        assert_eq!(Bump::Patch.next(&latest), Some(Version::new(1, 4, 3)));
+        assert_eq!(Bump::Minor.next(&latest), Some(Version::new(1, 5, 0)));
+        assert_eq!(Bump::Major.next(&latest), Some(Version::new(2, 0, 0)));
+        assert_eq!(Bump::None.next(&latest), None);
+    }
+
+    #[test]
+    fn test_write_version_keeps_comments() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join(MANIFEST_FILE);
+        fs::write(
+            &path,
+            "# Reviewer\nname = \"reviewer\"\nversion = \"1.0.0\" # bumped by CI\n",
+        )
+        .unwrap();
+
+        AgentManifest::write_version(&path, "1.0.1").unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "# Reviewer\nname = \"reviewer\"\nversion = \"1.0.1\" # bumped by CI\n"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_hooks_from_parent_manifest() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let agents = dir.path().join("agents");
+        fs::create_dir(&agents).unwrap();
+        fs::write(
+            dir.path().join(MANIFEST_FILE),
+            "[hooks]\npre-publish = [\"true\", \"exit 3\", \"touch never-run\"]\n",
+        )
+        .unwrap();
+
+        let (path, hooks) = Hooks::find(&agents).unwrap().unwrap();
+        assert_eq!(path, dir.path().join(MANIFEST_FILE));
+        assert_eq!(hooks.pre_publish.len(), 3);
+
+        let err = hooks.run_pre_publish(dir.path(), &agents).unwrap_err();
+        assert!(
+            err.to_string().contains("`exit 3` exited with code 3"),
+            "{err}"
+        );
+        assert!(!dir.path().join("never-run").exists());
     }
 }
@@ -1,2 +1,26 @@
+pub mod agent_dir;
+pub mod archive;
+pub mod ci;
+pub mod cleanup;
 pub mod error;
+pub mod file_lock;
+pub mod frontmatter;
+pub mod git_source;
+pub mod logging;
 pub mod manifest;
+pub mod name_index;
+pub mod overwrite;
+pub mod pattern;
+pub mod policy;
+pub mod portable_path;
+pub mod provenance;
+pub mod resolution;
+pub mod search_index;
+pub mod secrets;
+pub mod signing;
+pub mod table;
+pub mod telemetry;
+pub mod terminal;
+pub mod trash;
+pub mod variables;
+pub mod workspace;
@@ -0,0 +1,135 @@
+//! Agent names seen in search and list results, for shell completion
+//!
+//! Completing `carp pull <TAB>` can't wait on the registry, so every search
+//! and list remembers the names it showed in `<cache>/agent-names.json`, and
+//! completion offers those. Only the registry results last came from is
+//! kept: its names are what the user has been browsing, and names from
+//! another registry would complete agents that can't be pulled.
+
+use crate::config::ConfigManager;
+use crate::utils::error::CarpResult;
+use crate::utils::file_lock::{self, LockedFile};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+/// Names kept, the least recently seen dropped first
+pub const MAX_NAMES: usize = 5_000;
+
+const INDEX_FILE: &str = "agent-names.json";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct NameIndex {
+    /// Registry the names are from
+    registry: String,
+    /// Most recently seen first
+    names: Vec<String>,
+}
+
+fn path() -> CarpResult<PathBuf> {
+    Ok(ConfigManager::cache_dir()?.join(INDEX_FILE))
+}
+
+/// Remember that `registry` has the agents in `names`. Failing to is only
+/// logged, since completion is a nicety the command shouldn't fail over.
+pub fn record<'a>(registry: &str, names: impl IntoIterator<Item = &'a str>) {
+    let recorded = path().and_then(|path| record_at(&path, registry, names));
+    if let Err(e) = recorded {
+        debug!("Failed to remember agent names for completion: {e}");
+    }
+}
+
+fn record_at<'a>(
+    path: &Path,
+    registry: &str,
+    names: impl IntoIterator<Item = &'a str>,
+) -> CarpResult<()> {
+    let mut file = LockedFile::open(path)?;
+    let mut index: NameIndex = serde_json::from_str(&file.read_to_string()?).unwrap_or_default();
+    if index.registry != registry {
+        index = NameIndex {
+            registry: registry.to_string(),
+            names: Vec::new(),
+        };
+    }
+
+    let mut seen: Vec<String> = names.into_iter().map(str::to_string).collect();
+    if seen.is_empty() {
+        return Ok(());
+    }
+    let mut unique = HashSet::new();
+    seen.retain(|name| unique.insert(name.clone()));
+    index.names.retain(|name| !unique.contains(name));
+    seen.append(&mut index.names);
+    seen.truncate(MAX_NAMES);
+    index.names = seen;
+
+    file.replace(serde_json::to_string(&index)?)?;
+    Ok(())
+}
+
+/// Remembered names starting with `prefix`, alphabetically. Nothing when
+/// there's no index yet or it can't be read.
+pub fn complete(prefix: &str) -> Vec<String> {
+    path()
+        .map(|path| complete_at(&path, prefix))
+        .unwrap_or_default()
+}
+
+fn complete_at(path: &Path, prefix: &str) -> Vec<String> {
+    let Ok(contents) = file_lock::read_to_string(path) else {
+        return Vec::new();
+    };
+    let index: NameIndex = serde_json::from_str(&contents).unwrap_or_default();
+    let mut names: Vec<String> = index
+        .names
+        .into_iter()
+        .filter(|name| name.starts_with(prefix))
+        .collect();
+    names.sort();
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_names_are_merged_and_completed() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join(INDEX_FILE);
+        assert!(complete_at(&path, "").is_empty());
+
+        let registry = "https://api.carp.refcell.org";
+        record_at(&path, registry, ["code-reviewer", "meeting-notes"]).unwrap();
+        record_at(&path, registry, ["code-refactorer", "code-reviewer"]).unwrap();
+        assert_eq!(
+            complete_at(&path, "code-re"),
+            ["code-refactorer", "code-reviewer"]
+        );
+        assert_eq!(complete_at(&path, "").len(), 3);
+
+        // Names from another registry replace the old ones
+        record_at(&path, "https://carp.acme.internal", ["acme-deployer"]).unwrap();
+        assert_eq!(complete_at(&path, ""), ["acme-deployer"]);
+    }
+
+    #[test]
+    fn test_least_recently_seen_names_are_dropped() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join(INDEX_FILE);
+        let registry = "https://api.carp.refcell.org";
+
+        let old: Vec<String> = (0..MAX_NAMES).map(|i| format!("agent-{i:05}")).collect();
+        record_at(&path, registry, old.iter().map(String::as_str)).unwrap();
+        record_at(&path, registry, ["newest-agent", "agent-00000"]).unwrap();
+
+        let names = complete_at(&path, "");
+        assert_eq!(names.len(), MAX_NAMES);
+        assert!(names.contains(&"newest-agent".to_string()));
+        assert!(names.contains(&"agent-00000".to_string()));
+        assert!(!names.contains(&format!("agent-{:05}", MAX_NAMES - 1)));
+    }
+}
@@ -0,0 +1,307 @@
+//! What `carp pull` does when the agent's directory or file already exists
+//!
+//! Pulls of whole agent directories leave a record of the files they wrote
+//! (see [`agent_dir::RECORD_FILE`]), so a later `--merge` can tell files it
+//! may replace from ones that were changed or added locally.
+
+use crate::utils::agent_dir::{self, file_checksum as checksum, relative_path, Record};
+use crate::utils::cleanup;
+use crate::utils::error::{CarpError, CarpResult};
+use crate::utils::trash;
+use colored::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+/// How to treat an agent that's already where it would be pulled to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Overwrite {
+    /// Refuse to pull
+    #[default]
+    Fail,
+    /// Replace it
+    Force,
+    /// Move it to `<name>.bak-<timestamp>`, then pull
+    Backup,
+    /// Replace only the files the previous pull wrote that haven't changed
+    /// since, keeping files changed or added locally
+    Merge,
+    /// Add files that don't exist yet, keeping every existing one
+    SkipExisting,
+}
+
+/// What placing a pulled agent changed
+#[derive(Debug, Default)]
+pub struct Placed {
+    /// Where the previous version was moved by `--backup`
+    pub backup: Option<PathBuf>,
+    /// Existing files left as they were
+    pub kept: Vec<String>,
+    /// Files of the previous pull that the new version no longer has
+    pub removed: usize,
+}
+
+impl Overwrite {
+    /// Refuse to go any further when `dest` exists and nothing may replace
+    /// it, or `--force` would lose local changes the user wants to keep
+    pub fn check(self, name: &str, dest: &Path) -> CarpResult<()> {
+        if self == Overwrite::Force && !agent_dir::confirm_discard(name, dest)? {
+            return Err(kept_local_changes(name));
+        }
+        if self != Overwrite::Fail || !dest.exists() {
+            return Ok(());
+        }
+        let kind = if dest.is_dir() { "Directory" } else { "File" };
+        Err(CarpError::FileSystem(format!(
+            "{kind} '{}' already exists. Use --force to overwrite it, --backup to move it aside, or --merge or --skip-existing to keep local changes.",
+            dest.display()
+        )))
+    }
+
+    /// Move the complete agent directory `tree` to `dest`
+    pub fn place_dir(self, tree: &Path, dest: &Path) -> CarpResult<Placed> {
+        let mut placed = Placed::default();
+        if !dest.exists() {
+            fs::rename(tree, dest)?;
+            agent_dir::record(dest)?;
+            return Ok(placed);
+        }
+
+        match self {
+            Overwrite::Fail | Overwrite::Force => {
+                trash::discard(dest)?;
+                fs::rename(tree, dest)?;
+            }
+            Overwrite::Backup => {
+                let backup = backup_path(dest);
+                fs::rename(dest, &backup)?;
+                fs::rename(tree, dest)?;
+                placed.backup = Some(backup);
+            }
+            Overwrite::Merge | Overwrite::SkipExisting => {
+                if !dest.is_dir() {
+                    return Err(CarpError::FileSystem(format!(
+                        "'{}' isn't a directory, so nothing can be merged into it",
+                        dest.display()
+                    )));
+                }
+                let (record, kept, removed) = self.merge(tree, dest)?;
+                placed.kept = kept;
+                placed.removed = removed;
+                agent_dir::write_record(dest, &record)?;
+                return Ok(placed);
+            }
+        }
+        agent_dir::record(dest)?;
+        Ok(placed)
+    }
+
+    /// Move the files of `tree` into the existing directory `dest`, returning
+    /// the new record, the files kept and how many stale files were removed
+    fn merge(self, tree: &Path, dest: &Path) -> CarpResult<(Record, Vec<String>, usize)> {
+        let previous = agent_dir::read_record(dest).unwrap_or_default();
+        if self == Overwrite::Merge && previous.is_empty() {
+            println!(
+                "{} No record of a previous pull in {}, so every existing file is kept",
+                "Warning:".yellow().bold(),
+                dest.display()
+            );
+        }
+
+        let mut record = Record::new();
+        let mut kept = Vec::new();
+        for path in agent_dir::files(tree)? {
+            let relative = relative_path(tree, &path);
+            let target = dest.join(&relative);
+            let current = target.is_file().then(|| checksum(&target)).transpose()?;
+            let pulled = current.is_some() && current.as_ref() == previous.get(&relative);
+
+            if current.is_none() && !target.exists() || self == Overwrite::Merge && pulled {
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                record.insert(relative, checksum(&path)?);
+                fs::rename(&path, &target)?;
+            } else {
+                debug!("Keeping {}", target.display());
+                // Still the pulled version, so a later merge may replace it
+                if let (true, Some(current)) = (pulled, current) {
+                    record.insert(relative.clone(), current);
+                }
+                kept.push(relative);
+            }
+        }
+
+        let mut removed = 0;
+        if self == Overwrite::Merge {
+            for (relative, pulled) in &previous {
+                let target = dest.join(relative);
+                if record.contains_key(relative) || kept.contains(relative) || !target.is_file() {
+                    continue;
+                }
+                if checksum(&target)? == *pulled {
+                    debug!(
+                        "Removing {}, which the new version doesn't have",
+                        target.display()
+                    );
+                    fs::remove_file(&target)?;
+                    removed += 1;
+                }
+            }
+        }
+        Ok((record, kept, removed))
+    }
+
+    /// Write a pulled agent definition to `dest`
+    pub fn place_file(self, dest: &Path, contents: &str) -> CarpResult<Placed> {
+        let mut placed = Placed::default();
+        if dest.exists() {
+            match self {
+                Overwrite::Fail | Overwrite::Force => trash::discard(dest)?,
+                Overwrite::Backup => {
+                    let backup = backup_path(dest);
+                    fs::rename(dest, &backup)?;
+                    placed.backup = Some(backup);
+                }
+                Overwrite::Merge | Overwrite::SkipExisting => {
+                    placed.kept.push(dest.display().to_string());
+                    return Ok(placed);
+                }
+            }
+        }
+        cleanup::write_atomic(dest, contents)?;
+        Ok(placed)
+    }
+}
+
+impl Placed {
+    /// Print what happened to what was already there
+    pub fn report(&self) {
+        if let Some(backup) = &self.backup {
+            println!(
+                "  Moved the previous version to {}",
+                backup.display().to_string().cyan()
+            );
+        }
+        if !self.kept.is_empty() {
+            println!(
+                "  Kept {} existing file(s): {}",
+                self.kept.len(),
+                self.kept.join(", ")
+            );
+        }
+        if self.removed > 0 {
+            println!(
+                "  Removed {} file(s) the new version no longer has",
+                self.removed
+            );
+        }
+    }
+}
+
+/// The error when the user chooses to keep an agent's local changes
+pub fn kept_local_changes(name: &str) -> CarpError {
+    CarpError::FileSystem(format!(
+        "Kept the local changes to '{name}'; nothing was replaced"
+    ))
+}
+
+/// `<dest>.bak-<timestamp>`, beside `dest`
+fn backup_path(dest: &Path) -> PathBuf {
+    let mut name = dest.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(
+        ".bak-{}",
+        chrono::Local::now().format("%Y%m%d%H%M%S")
+    ));
+    dest.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree(root: &Path, files: &[(&str, &str)]) {
+        for (path, contents) in files {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_merge_keeps_local_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("reviewer");
+        let v1 = dir.path().join("v1");
+        tree(
+            &v1,
+            &[
+                ("reviewer.md", "v1"),
+                ("prompts/style.md", "v1"),
+                ("old.md", "v1"),
+            ],
+        );
+        Overwrite::Fail.place_dir(&v1, &dest).unwrap();
+
+        // Tweak one pulled file and add another of our own
+        fs::write(dest.join("prompts/style.md"), "tweaked").unwrap();
+        fs::write(dest.join("notes.md"), "mine").unwrap();
+
+        let v2 = dir.path().join("v2");
+        tree(
+            &v2,
+            &[
+                ("reviewer.md", "v2"),
+                ("prompts/style.md", "v2"),
+                ("notes.md", "v2"),
+                ("new.md", "v2"),
+            ],
+        );
+        let placed = Overwrite::Merge.place_dir(&v2, &dest).unwrap();
+        assert_eq!(placed.kept, ["notes.md", "prompts/style.md"]);
+        assert_eq!(placed.removed, 1);
+
+        let read = |path: &str| fs::read_to_string(dest.join(path)).unwrap();
+        assert_eq!(read("reviewer.md"), "v2");
+        assert_eq!(read("new.md"), "v2");
+        assert_eq!(read("prompts/style.md"), "tweaked");
+        assert_eq!(read("notes.md"), "mine");
+        assert!(!dest.join("old.md").exists());
+
+        let record = agent_dir::read_record(&dest).unwrap();
+        assert_eq!(record.keys().collect::<Vec<_>>(), ["new.md", "reviewer.md"]);
+    }
+
+    #[test]
+    fn test_skip_existing_and_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("reviewer");
+        tree(&dest, &[("reviewer.md", "local")]);
+
+        let pulled = dir.path().join("pulled");
+        tree(&pulled, &[("reviewer.md", "v1"), ("extra.md", "v1")]);
+        let placed = Overwrite::SkipExisting.place_dir(&pulled, &dest).unwrap();
+        assert_eq!(placed.kept, ["reviewer.md"]);
+        assert_eq!(
+            fs::read_to_string(dest.join("reviewer.md")).unwrap(),
+            "local"
+        );
+        assert_eq!(fs::read_to_string(dest.join("extra.md")).unwrap(), "v1");
+
+        let pulled = dir.path().join("pulled");
+        tree(&pulled, &[("reviewer.md", "v2")]);
+        let placed = Overwrite::Backup.place_dir(&pulled, &dest).unwrap();
+        let backup = placed.backup.unwrap();
+        assert!(backup
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .starts_with("reviewer.bak-"));
+        assert_eq!(
+            fs::read_to_string(backup.join("reviewer.md")).unwrap(),
+            "local"
+        );
+        assert_eq!(fs::read_to_string(dest.join("reviewer.md")).unwrap(), "v2");
+        assert!(!dest.join("extra.md").exists());
+    }
+}
@@ -0,0 +1,272 @@
+use crate::api::types::Agent;
+use crate::utils::error::{CarpError, CarpResult};
+use crate::utils::pattern::glob_match;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+/// Organization rules an agent must satisfy before `carp pull` writes it
+///
+/// Loaded from the TOML file given to `--policy`. Rules left out of the file
+/// don't restrict anything:
+///
+/// ```toml
+/// allowed_licenses = ["MIT", "Apache-2.0"]
+/// allowed_authors = ["acme-*", "octocat"]
+/// allowed_namespaces = ["acme-*"]
+/// require_signatures = true
+/// signature_issuer = "https://token.actions.githubusercontent.com"
+/// max_package_size = 5242880
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    /// License identifiers agents may use, compared case-insensitively
+    pub allowed_licenses: Option<Vec<String>>,
+    /// Author globs, e.g. `acme-*`
+    pub allowed_authors: Option<Vec<String>>,
+    /// Agent name globs, e.g. `acme-*`
+    pub allowed_namespaces: Option<Vec<String>>,
+    /// Refuse agents without a valid Sigstore signature
+    #[serde(default)]
+    pub require_signatures: bool,
+    /// OIDC issuer the signing identity must come from
+    pub signature_issuer: Option<String>,
+    /// Largest definition or package archive accepted, in bytes
+    pub max_package_size: Option<u64>,
+}
+
+impl Policy {
+    /// Load and validate a policy file
+    pub fn load<P: AsRef<Path>>(path: P) -> CarpResult<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path).map_err(|e| {
+            CarpError::Config(format!(
+                "Failed to read policy file '{}': {e}",
+                path.display()
+            ))
+        })?;
+
+        let policy: Policy = toml::from_str(&contents).map_err(|e| {
+            CarpError::Config(format!(
+                "Failed to parse policy file '{}': {e}",
+                path.display()
+            ))
+        })?;
+
+        policy.validate()?;
+        Ok(policy)
+    }
+
+    fn validate(&self) -> CarpResult<()> {
+        if self.require_signatures && self.signature_issuer.is_none() {
+            return Err(CarpError::Config(
+                "Policy sets require_signatures but no signature_issuer".to_string(),
+            ));
+        }
+        if self.signature_issuer.is_some() && !self.require_signatures {
+            return Err(CarpError::Config(
+                "Policy sets signature_issuer but not require_signatures = true".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Issuer whose signature the policy requires, if any
+    pub fn required_issuer(&self) -> Option<&str> {
+        self.signature_issuer
+            .as_deref()
+            .filter(|_| self.require_signatures)
+    }
+
+    /// Check an agent's metadata, returning every rule it breaks
+    ///
+    /// Signatures are only checked for presence here; verifying them needs
+    /// the network and is left to the caller.
+    pub fn check_agent(&self, agent: &Agent) -> Vec<String> {
+        let mut violations = Vec::new();
+
+        if let Some(allowed) = &self.allowed_licenses {
+            match &agent.license {
+                Some(license) if allowed.iter().any(|a| a.eq_ignore_ascii_case(license)) => {}
+                Some(license) => violations.push(format!(
+                    "license '{license}' is not allowed (allowed: {})",
+                    allowed.join(", ")
+                )),
+                None => violations.push(format!(
+                    "agent declares no license (allowed: {})",
+                    allowed.join(", ")
+                )),
+            }
+        }
+
+        if let Some(allowed) = &self.allowed_authors {
+            if !allowed.iter().any(|p| glob_match(p, &agent.author)) {
+                violations.push(format!(
+                    "author '{}' is not allowed (allowed: {})",
+                    agent.author,
+                    allowed.join(", ")
+                ));
+            }
+        }
+
+        if let Some(allowed) = &self.allowed_namespaces {
+            if !allowed.iter().any(|p| glob_match(p, &agent.name)) {
+                violations.push(format!(
+                    "name '{}' is outside the allowed namespaces ({})",
+                    agent.name,
+                    allowed.join(", ")
+                ));
+            }
+        }
+
+        if self.require_signatures && agent.signature_bundle.is_none() {
+            violations.push("agent is not signed".to_string());
+        }
+
+        violations
+    }
+
+    /// Check the size of what is about to be written
+    pub fn check_size(&self, size: u64) -> Option<String> {
+        self.max_package_size
+            .filter(|&max| size > max)
+            .map(|max| format!("package is {size} bytes, over the {max}-byte limit"))
+    }
+}
+
+/// Turn collected violations into an error naming the agent
+pub fn enforce(agent: &Agent, violations: Vec<String>) -> CarpResult<()> {
+    if violations.is_empty() {
+        return Ok(());
+    }
+
+    Err(CarpError::PolicyViolation(
+        violations
+            .into_iter()
+            .map(|v| format!("{} v{}: {v}", agent.name, agent.version))
+            .collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn agent(name: &str, author: &str, license: Option<&str>) -> Agent {
+        Agent {
+            name: name.to_string(),
+            version: "1.0.0".to_string(),
+            description: String::new(),
+            author: author.to_string(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            download_count: 0,
+            star_count: 0,
+            rating_count: 0,
+            rating_average: None,
+            tags: Vec::new(),
+            readme: None,
+            homepage: None,
+            repository: None,
+            license: license.map(str::to_string),
+            signature_bundle: None,
+            is_public: true,
+            is_template: false,
+            verified_namespace: None,
+            verified: false,
+        }
+    }
+
+    fn policy(toml: &str) -> Policy {
+        let policy: Policy = toml::from_str(toml).unwrap();
+        policy.validate().unwrap();
+        policy
+    }
+
+    #[test]
+    fn test_empty_policy_allows_everything() {
+        let policy = policy("");
+        assert!(policy.check_agent(&agent("x", "y", None)).is_empty());
+        assert!(policy.check_size(u64::MAX).is_none());
+        assert!(policy.required_issuer().is_none());
+    }
+
+    #[test]
+    fn test_policy_reports_every_violation() {
+        let policy = policy(
+            r#"
+            allowed_licenses = ["MIT", "Apache-2.0"]
+            allowed_authors = ["acme-*"]
+            allowed_namespaces = ["acme-*"]
+            "#,
+        );
+
+        assert!(policy
+            .check_agent(&agent("acme-review", "acme-bot", Some("mit")))
+            .is_empty());
+
+        let violations = policy.check_agent(&agent("review", "mallory", Some("GPL-3.0")));
+        assert_eq!(
+            violations,
+            vec![
+                "license 'GPL-3.0' is not allowed (allowed: MIT, Apache-2.0)",
+                "author 'mallory' is not allowed (allowed: acme-*)",
+                "name 'review' is outside the allowed namespaces (acme-*)",
+            ]
+        );
+
+        let violations = policy.check_agent(&agent("acme-review", "acme-bot", None));
+        assert_eq!(
+            violations,
+            vec!["agent declares no license (allowed: MIT, Apache-2.0)"]
+        );
+    }
+
+    #[test]
+    fn test_policy_signatures_and_size() {
+        let policy = policy(
+            r#"
+            require_signatures = true
+            signature_issuer = "https://token.actions.githubusercontent.com"
+            max_package_size = 1024
+            "#,
+        );
+
+        assert_eq!(
+            policy.required_issuer(),
+            Some("https://token.actions.githubusercontent.com")
+        );
+        assert_eq!(
+            policy.check_agent(&agent("a", "b", None)),
+            vec!["agent is not signed"]
+        );
+        assert!(policy.check_size(1024).is_none());
+        assert_eq!(
+            policy.check_size(2048).as_deref(),
+            Some("package is 2048 bytes, over the 1024-byte limit")
+        );
+    }
+
+    #[test]
+    fn test_invalid_policies_are_rejected() {
+        let unsigned: Policy = toml::from_str("require_signatures = true").unwrap();
+        assert!(matches!(unsigned.validate(), Err(CarpError::Config(_))));
+
+        assert!(toml::from_str::<Policy>("allowed_licences = [\"MIT\"]").is_err());
+    }
+
+    #[test]
+    fn test_enforce_names_the_agent() {
+        let agent = agent("review", "mallory", None);
+        assert!(enforce(&agent, Vec::new()).is_ok());
+
+        let err = enforce(&agent, vec!["agent is not signed".to_string()]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Policy violation: review v1.0.0: agent is not signed"
+        );
+        assert_eq!(err.exit_code(), 12);
+    }
+}
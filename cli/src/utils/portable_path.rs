@@ -0,0 +1,118 @@
+//! Paths that also work on Windows
+//!
+//! Windows refuses file names that are reserved for devices (`CON`, `NUL`,
+//! `COM1` and so on, with any extension), that end in a dot or space, or
+//! that contain `<>:"|?*`, and by default it refuses paths longer than
+//! `MAX_PATH` (260 characters). Agents are mostly written on Unix, where all
+//! of those are fine, so a package can name files Windows can't create, and
+//! nesting an agent in a deep project or CI workspace can push its paths past
+//! the limit.
+
+use std::path::{Path, PathBuf};
+
+/// Names Windows reserves for devices, in any case and with any extension
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Characters Windows doesn't allow in file names, besides control
+/// characters and the path separators
+const RESERVED_CHARS: &[char] = &['<', '>', ':', '"', '|', '?', '*'];
+
+/// Longest directory path Windows creates without the `\\?\` prefix:
+/// `MAX_PATH` less room for an 8.3 file name
+const MAX_DIR_PATH: usize = 248;
+
+/// Why Windows can't create a file or directory named `name`, if it can't
+pub fn windows_name_problem(name: &str) -> Option<String> {
+    if let Some(c) = name
+        .chars()
+        .find(|c| RESERVED_CHARS.contains(c) || c.is_control())
+    {
+        return Some(format!("contains {c:?}, which Windows doesn't allow"));
+    }
+    if name.ends_with('.') || name.ends_with(' ') {
+        return Some("ends with a dot or space, which Windows drops".to_string());
+    }
+    let stem = name.split('.').next().unwrap_or(name).trim_end();
+    RESERVED_NAMES
+        .iter()
+        .find(|reserved| reserved.eq_ignore_ascii_case(stem))
+        .map(|reserved| format!("uses the name {reserved}, which Windows reserves for a device"))
+}
+
+/// `path` in a form that isn't held to `MAX_PATH`: on Windows, absolute and
+/// with the `\\?\` prefix once it's long enough to need it, and unchanged
+/// everywhere else
+///
+/// Apply it to the root a tree is written under; paths joined onto the
+/// result keep the prefix.
+pub fn long(path: &Path) -> PathBuf {
+    if !cfg!(windows) || path.as_os_str().len() < MAX_DIR_PATH {
+        return path.to_path_buf();
+    }
+    match std::path::absolute(path) {
+        Ok(absolute) => match absolute.to_str() {
+            Some(absolute) => PathBuf::from(verbatim(absolute)),
+            None => path.to_path_buf(),
+        },
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+/// The `\\?\` form of an absolute Windows path, which turns off Windows'
+/// path parsing, and with it the length limit
+fn verbatim(absolute: &str) -> String {
+    // The prefix also turns off translating `/`
+    let absolute = absolute.replace('/', "\\");
+    if absolute.starts_with(r"\\?\") {
+        absolute
+    } else if let Some(share) = absolute.strip_prefix(r"\\") {
+        format!(r"\\?\UNC\{share}")
+    } else {
+        format!(r"\\?\{absolute}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_windows_name_problem() {
+        for fine in [
+            "reviewer.md",
+            "console.md",
+            "con-tips",
+            "prompts",
+            ".carp-files.json",
+        ] {
+            assert_eq!(windows_name_problem(fine), None, "{fine}");
+        }
+        for reserved in [
+            "CON",
+            "nul.md",
+            "Com1.txt",
+            "aux .md",
+            "what?.md",
+            "a:b",
+            "trailing.",
+        ] {
+            assert!(windows_name_problem(reserved).is_some(), "{reserved}");
+        }
+    }
+
+    #[test]
+    fn test_verbatim() {
+        assert_eq!(
+            verbatim(r"C:\Users\me/agents\reviewer"),
+            r"\\?\C:\Users\me\agents\reviewer"
+        );
+        assert_eq!(
+            verbatim(r"\\server\share\agents"),
+            r"\\?\UNC\server\share\agents"
+        );
+        assert_eq!(verbatim(r"\\?\C:\agents"), r"\\?\C:\agents");
+    }
+}
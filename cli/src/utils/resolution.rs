@@ -0,0 +1,228 @@
+//! Checking the dependency closures `carp pull --with-deps` installs
+//!
+//! The registry signs each resolution with an Ed25519 key. carp holds it to
+//! `security.resolution_key` when that's set, and otherwise to the key the
+//! project's `carp.lock` recorded with its first resolution, so a mirror or
+//! proxy in between can't answer with a closure of its own.
+
+use crate::api::types::{ResolutionManifest, SignedResolution};
+use crate::utils::error::{CarpError, CarpResult};
+use crate::utils::workspace::{LockedResolution, LOCKFILE};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use ring::signature::{UnparsedPublicKey, ED25519};
+use std::collections::BTreeSet;
+
+/// Check a resolution of `root` at `requirement` and read its manifest
+///
+/// The manifest must be signed by `trusted_key` when there is one, and list
+/// what was asked for, each agent once with a SHA-256 checksum.
+pub fn verify(
+    signed: &SignedResolution,
+    root: &str,
+    requirement: &str,
+    trusted_key: Option<&str>,
+) -> CarpResult<ResolutionManifest> {
+    if let Some(trusted) = trusted_key.filter(|trusted| *trusted != signed.public_key) {
+        return Err(CarpError::Signing(format!(
+            "Resolution of '{root}' is signed with key {}, not the registry key {trusted}",
+            signed.public_key
+        )));
+    }
+    let manifest = check_signature(signed)?;
+
+    if manifest.root != root || manifest.requirement != requirement {
+        return Err(CarpError::Signing(format!(
+            "Asked to resolve {root}@{requirement}, the registry signed {}@{}",
+            manifest.root, manifest.requirement
+        )));
+    }
+    let mut names = BTreeSet::new();
+    for agent in &manifest.agents {
+        if !names.insert(agent.name.as_str()) {
+            return Err(CarpError::Signing(format!(
+                "Resolution of '{root}' lists '{}' more than once",
+                agent.name
+            )));
+        }
+        if !agent.checksum.starts_with("sha256:") {
+            return Err(CarpError::Signing(format!(
+                "Resolution of '{root}' has no SHA-256 checksum for {}@{}",
+                agent.name, agent.version
+            )));
+        }
+    }
+    if !names.contains(root) {
+        return Err(CarpError::Signing(format!(
+            "Resolution of '{root}' doesn't include '{root}'"
+        )));
+    }
+
+    Ok(manifest)
+}
+
+/// The key resolutions must be signed with, if one is known yet
+///
+/// Each resolution the lockfile holds is checked again first, so an edited
+/// `carp.lock` can't slip in a key of its own.
+pub fn trusted_key<'a>(
+    configured: Option<&'a str>,
+    locked: &'a [LockedResolution],
+) -> CarpResult<Option<&'a str>> {
+    for resolution in locked {
+        check_signature(&resolution.into()).map_err(|e| {
+            CarpError::Signing(format!(
+                "{LOCKFILE}'s resolution of '{}' can't be trusted: {e}",
+                resolution.root
+            ))
+        })?;
+    }
+    if configured.is_some() {
+        return Ok(configured);
+    }
+
+    let mut keys = locked
+        .iter()
+        .map(|resolution| resolution.public_key.as_str());
+    let first = keys.next();
+    if let Some(other) = keys.find(|key| Some(*key) != first) {
+        return Err(CarpError::Signing(format!(
+            "{LOCKFILE} holds resolutions signed with different keys ({} and {other}); set security.resolution_key to the registry's",
+            first.unwrap_or_default()
+        )));
+    }
+    Ok(first)
+}
+
+/// The lockfile entry that keeps a verified resolution
+pub fn lock(signed: SignedResolution, manifest: &ResolutionManifest) -> LockedResolution {
+    LockedResolution {
+        root: manifest.root.clone(),
+        requirement: manifest.requirement.clone(),
+        manifest: signed.manifest,
+        signature: signed.signature,
+        public_key: signed.public_key,
+    }
+}
+
+impl From<&LockedResolution> for SignedResolution {
+    fn from(locked: &LockedResolution) -> Self {
+        Self {
+            manifest: locked.manifest.clone(),
+            signature: locked.signature.clone(),
+            public_key: locked.public_key.clone(),
+        }
+    }
+}
+
+/// Check the signature over the manifest's exact bytes, then parse it
+fn check_signature(signed: &SignedResolution) -> CarpResult<ResolutionManifest> {
+    let public_key = STANDARD
+        .decode(&signed.public_key)
+        .map_err(|e| CarpError::Signing(format!("Resolution key is not base64: {e}")))?;
+    let signature = STANDARD
+        .decode(&signed.signature)
+        .map_err(|e| CarpError::Signing(format!("Resolution signature is not base64: {e}")))?;
+
+    UnparsedPublicKey::new(&ED25519, public_key)
+        .verify(signed.manifest.as_bytes(), &signature)
+        .map_err(|_| CarpError::Signing("Resolution signature is invalid".to_string()))?;
+    serde_json::from_str(&signed.manifest)
+        .map_err(|e| CarpError::Signing(format!("Resolution manifest is malformed: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::types::ResolvedAgent;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    fn sign(seed: u8, manifest: &str) -> SignedResolution {
+        let key_pair = Ed25519KeyPair::from_seed_unchecked(&[seed; 32]).unwrap();
+        SignedResolution {
+            manifest: manifest.to_string(),
+            signature: STANDARD.encode(key_pair.sign(manifest.as_bytes()).as_ref()),
+            public_key: STANDARD.encode(key_pair.public_key().as_ref()),
+        }
+    }
+
+    fn manifest(agents: &[(&str, &str)]) -> String {
+        serde_json::to_string(&ResolutionManifest {
+            root: "code-reviewer".to_string(),
+            requirement: "^1.2".to_string(),
+            agents: agents
+                .iter()
+                .map(|(name, version)| ResolvedAgent {
+                    name: name.to_string(),
+                    version: version.to_string(),
+                    checksum: format!("sha256:{name}"),
+                })
+                .collect(),
+            issued_at: chrono::Utc::now(),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_verify() {
+        let signed = sign(
+            1,
+            &manifest(&[("code-reviewer", "1.2.3"), ("linter", "0.4.0")]),
+        );
+        let verified = verify(&signed, "code-reviewer", "^1.2", None).unwrap();
+        assert_eq!(verified.agents.len(), 2);
+        assert!(verify(&signed, "code-reviewer", "^1.2", Some(&signed.public_key)).is_ok());
+
+        // Another key, another agent or requirement, or changed bytes are refused
+        let other_key = sign(2, "").public_key;
+        assert!(verify(&signed, "code-reviewer", "^1.2", Some(&other_key)).is_err());
+        assert!(verify(&signed, "linter", "^1.2", None).is_err());
+        assert!(verify(&signed, "code-reviewer", "latest", None).is_err());
+        let tampered = SignedResolution {
+            manifest: signed.manifest.replace("0.4.0", "0.4.1"),
+            ..signed.clone()
+        };
+        assert!(matches!(
+            verify(&tampered, "code-reviewer", "^1.2", None),
+            Err(CarpError::Signing(_))
+        ));
+
+        let duplicated = sign(
+            1,
+            &manifest(&[("code-reviewer", "1.2.3"), ("code-reviewer", "1.3.0")]),
+        );
+        assert!(verify(&duplicated, "code-reviewer", "^1.2", None).is_err());
+        let without_root = sign(1, &manifest(&[("linter", "0.4.0")]));
+        assert!(verify(&without_root, "code-reviewer", "^1.2", None).is_err());
+    }
+
+    #[test]
+    fn test_trusted_key() {
+        let signed = sign(1, &manifest(&[("code-reviewer", "1.2.3")]));
+        let parsed = check_signature(&signed).unwrap();
+        let locked = vec![lock(signed.clone(), &parsed)];
+
+        assert_eq!(trusted_key(None, &[]).unwrap(), None);
+        assert_eq!(
+            trusted_key(None, &locked).unwrap(),
+            Some(signed.public_key.as_str())
+        );
+        assert_eq!(
+            trusted_key(Some("pinned"), &locked).unwrap(),
+            Some("pinned")
+        );
+
+        // A resolution edited in carp.lock fails the check
+        let mut edited = locked.clone();
+        edited[0].manifest = edited[0].manifest.replace("1.2.3", "1.2.4");
+        assert!(trusted_key(None, &edited).is_err());
+
+        // As do resolutions signed with different keys
+        let mut mixed = locked.clone();
+        let other = sign(2, &manifest(&[("code-reviewer", "1.2.3")]));
+        mixed.push(LockedResolution {
+            root: "linter".to_string(),
+            ..lock(other.clone(), &check_signature(&other).unwrap())
+        });
+        assert!(trusted_key(None, &mixed).is_err());
+    }
+}
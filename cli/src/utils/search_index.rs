@@ -0,0 +1,545 @@
+//! A local copy of a registry's agent listing, for searching offline
+//!
+//! `carp index sync` copies the name, description, tags, latest version and
+//! counts of every listed agent into `<cache>/index/<registry>.json`, then
+//! keeps it current from the registry's change feed: each sync asks only for
+//! what changed since the cursor the last one stopped at. `carp search
+//! --offline` and `carp list --offline` read it, as do searches and lists
+//! that find the registry unreachable. Results from the index always say
+//! when it was last synced, since anything published after that is missing.
+
+use crate::api::{Agent, AgentChange, ApiClient, SearchMode, SearchResponse, SearchSort};
+use crate::config::ConfigManager;
+use crate::utils::error::{CarpError, CarpResult, ErrorCode};
+use crate::utils::file_lock::{self, LockedFile};
+use crate::utils::pattern::glob_match;
+use crate::utils::terminal;
+use chrono::{DateTime, Utc};
+use colored::*;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+/// Changes asked for per request while syncing
+const CHANGES_PAGE: usize = 500;
+
+/// Agents asked for per request when a registry without a change feed is
+/// copied whole
+const LIST_PAGE: usize = 100;
+
+/// Age past which results from the index come with a warning
+const STALE_AFTER: chrono::Duration = chrono::Duration::hours(24);
+
+/// Results shown when a search doesn't give `--limit`, as the registry does
+const DEFAULT_LIMIT: usize = 20;
+
+/// The index of one registry
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SearchIndex {
+    pub registry: String,
+    /// Where the next sync picks up the change feed; `None` for registries
+    /// without one, which are copied whole each time
+    pub cursor: Option<String>,
+    pub synced_at: DateTime<Utc>,
+    /// By name, without readmes or signatures
+    pub agents: BTreeMap<String, Agent>,
+}
+
+/// What a sync did
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SyncSummary {
+    /// Changes applied, or agents copied when the index was rebuilt
+    pub changes: usize,
+    /// The registry has no change feed, so the index was rebuilt from a
+    /// full listing
+    pub rebuilt: bool,
+}
+
+/// Whether `error` means the registry couldn't be reached or couldn't
+/// answer, rather than that the request was wrong
+pub fn registry_unavailable(error: &CarpError) -> bool {
+    matches!(error.code(), ErrorCode::Network | ErrorCode::Server)
+}
+
+impl SearchIndex {
+    fn path(registry: &str) -> CarpResult<PathBuf> {
+        let dir = ConfigManager::cache_dir()?.join("index");
+        std::fs::create_dir_all(&dir)?;
+        Ok(dir.join(format!("{}.json", file_stem(registry))))
+    }
+
+    /// The index of `registry`, if it's been synced
+    pub fn load(registry: &str) -> CarpResult<Option<Self>> {
+        Self::load_from(&Self::path(registry)?)
+    }
+
+    /// The index of `registry`, for `--offline`
+    pub fn require(registry: &str) -> CarpResult<Self> {
+        Self::load(registry)?.ok_or_else(|| {
+            CarpError::Other(format!(
+                "No search index for {registry}; run `carp index sync` while online first"
+            ))
+        })
+    }
+
+    /// The index to answer from after the registry failed with `error`:
+    /// only when the registry is unavailable and has been synced before,
+    /// otherwise `error` is returned as is
+    pub fn fallback(registry: &str, error: CarpError) -> CarpResult<Self> {
+        if !registry_unavailable(&error) {
+            return Err(error);
+        }
+        match Self::load(registry) {
+            Ok(Some(index)) => {
+                eprintln!(
+                    "{} {error}",
+                    terminal::for_stderr("Registry unavailable:".yellow().bold())
+                );
+                Ok(index)
+            }
+            _ => Err(error),
+        }
+    }
+
+    fn load_from(path: &Path) -> CarpResult<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        Self::parse(path, &file_lock::read_to_string(path)?)
+    }
+
+    fn parse(path: &Path, contents: &str) -> CarpResult<Option<Self>> {
+        if contents.trim().is_empty() {
+            return Ok(None);
+        }
+        serde_json::from_str(contents).map(Some).map_err(|e| {
+            CarpError::Other(format!(
+                "Corrupt search index {}: {e}; run `carp index clear` and sync again",
+                path.display()
+            ))
+        })
+    }
+
+    /// Bring the index of the client's registry up to date, creating it on
+    /// the first sync
+    pub async fn sync(client: &ApiClient) -> CarpResult<(Self, SyncSummary)> {
+        let path = Self::path(client.base_url())?;
+        // Held until the index is written, so concurrent syncs take turns
+        let mut file = LockedFile::open(&path)?;
+        let mut index = Self::parse(&path, &file.read_to_string()?)?.unwrap_or_else(|| Self {
+            registry: client.base_url().to_string(),
+            cursor: None,
+            synced_at: Utc::now(),
+            agents: BTreeMap::new(),
+        });
+        let summary = index.pull_changes(client).await?;
+        index.synced_at = Utc::now();
+        file.replace(serde_json::to_vec(&index)?)?;
+        Ok((index, summary))
+    }
+
+    async fn pull_changes(&mut self, client: &ApiClient) -> CarpResult<SyncSummary> {
+        let mut summary = SyncSummary::default();
+        loop {
+            let page = match client
+                .agent_changes(self.cursor.as_deref(), CHANGES_PAGE)
+                .await
+            {
+                Ok(page) => page,
+                // Older registries answer unknown routes with one or the other
+                Err(CarpError::Api {
+                    status: 404 | 501, ..
+                }) if summary.changes == 0 => {
+                    debug!("{} has no change feed; copying its listing", self.registry);
+                    return self.rebuild(client).await;
+                }
+                Err(e) => return Err(e),
+            };
+            summary.changes += page.changes.len();
+            for change in page.changes {
+                self.apply(change);
+            }
+            // A feed that doesn't move would loop forever
+            let moved = self.cursor.as_deref() != Some(page.cursor.as_str());
+            self.cursor = Some(page.cursor);
+            if !page.has_more || !moved {
+                return Ok(summary);
+            }
+        }
+    }
+
+    fn apply(&mut self, change: AgentChange) {
+        match change.agent {
+            Some(agent) => {
+                self.agents.insert(change.name, trimmed(agent));
+            }
+            None => {
+                self.agents.remove(&change.name);
+            }
+        }
+    }
+
+    /// Replace everything with a full listing, for registries that predate
+    /// the change feed
+    async fn rebuild(&mut self, client: &ApiClient) -> CarpResult<SyncSummary> {
+        let mut agents = BTreeMap::new();
+        for page in 1.. {
+            let response = client.list_page(page, LIST_PAGE).await?;
+            let last = response.agents.len() < LIST_PAGE;
+            for agent in response.agents {
+                agents.insert(agent.name.clone(), trimmed(agent));
+            }
+            if last || agents.len() >= response.total {
+                break;
+            }
+        }
+        self.agents = agents;
+        self.cursor = None;
+        Ok(SyncSummary {
+            changes: self.agents.len(),
+            rebuilt: true,
+        })
+    }
+
+    /// Agents matching `query` the way the registry would match them, in
+    /// the order `sort` asks for
+    pub fn search(
+        &self,
+        query: &str,
+        limit: Option<usize>,
+        mode: SearchMode,
+        sort: SearchSort,
+    ) -> CarpResult<SearchResponse> {
+        let query = query.trim();
+        let matcher: Box<dyn Fn(&Agent) -> bool> = match mode {
+            _ if query.is_empty() => Box::new(|_| true),
+            SearchMode::Substring => {
+                let query = query.to_lowercase();
+                Box::new(move |agent| {
+                    [&agent.name, &agent.description, &agent.author]
+                        .into_iter()
+                        .chain(&agent.tags)
+                        .any(|field| field.to_lowercase().contains(&query))
+                })
+            }
+            SearchMode::Exact => Box::new(move |agent| agent.name == query),
+            SearchMode::Glob => Box::new(move |agent| glob_match(query, &agent.name)),
+            SearchMode::Regex => {
+                let regex = regex::Regex::new(query)
+                    .map_err(|e| CarpError::InvalidAgent(format!("Invalid regex: {e}")))?;
+                Box::new(move |agent| regex.is_match(&agent.name))
+            }
+        };
+
+        let mut agents: Vec<Agent> = self
+            .agents
+            .values()
+            .filter(|agent| matcher(agent))
+            .cloned()
+            .collect();
+        match sort {
+            SearchSort::Downloads => agents.sort_by_key(|agent| Reverse(agent.download_count)),
+            SearchSort::Stars => agents.sort_by_key(|agent| Reverse(agent.star_count)),
+            SearchSort::Recent => agents.sort_by_key(|agent| Reverse(agent.updated_at)),
+        }
+        let total = agents.len();
+        let per_page = limit.unwrap_or(DEFAULT_LIMIT);
+        agents.truncate(per_page);
+        Ok(SearchResponse {
+            agents,
+            total,
+            page: 1,
+            per_page,
+        })
+    }
+
+    /// Whether the index is old enough that its results deserve a warning
+    pub fn is_stale(&self) -> bool {
+        Utc::now() - self.synced_at > STALE_AFTER
+    }
+
+    /// Say, on stderr, that results come from the index and how old it is
+    pub fn print_staleness(&self) {
+        let note = format!(
+            "Offline results from the index of {} synced {} ({} agents).",
+            self.registry,
+            age(self.synced_at),
+            self.agents.len()
+        );
+        if self.is_stale() {
+            eprintln!(
+                "{} {note} Run `carp index sync` to update it.",
+                terminal::for_stderr("Warning:".yellow().bold())
+            );
+        } else {
+            eprintln!("{}", terminal::for_stderr(note.dimmed()));
+        }
+    }
+
+    /// Delete the index of `registry`, returning whether there was one
+    pub fn clear(registry: &str) -> CarpResult<bool> {
+        let path = Self::path(registry)?;
+        if !path.exists() {
+            return Ok(false);
+        }
+        std::fs::remove_file(path)?;
+        Ok(true)
+    }
+}
+
+/// `agent` without what searching doesn't need
+fn trimmed(agent: Agent) -> Agent {
+    Agent {
+        readme: None,
+        signature_bundle: None,
+        ..agent
+    }
+}
+
+/// A file name for `registry`'s index
+fn file_stem(registry: &str) -> String {
+    let without_scheme = registry
+        .split_once("://")
+        .map_or(registry, |(_, rest)| rest);
+    without_scheme
+        .trim_end_matches('/')
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// How long ago `time` was, roughly
+pub fn age(time: DateTime<Utc>) -> String {
+    let elapsed = Utc::now() - time;
+    let (count, unit) = if elapsed.num_days() > 0 {
+        (elapsed.num_days(), "day")
+    } else if elapsed.num_hours() > 0 {
+        (elapsed.num_hours(), "hour")
+    } else if elapsed.num_minutes() > 0 {
+        (elapsed.num_minutes(), "minute")
+    } else {
+        return "just now".to_string();
+    };
+    format!("{count} {unit}{} ago", if count == 1 { "" } else { "s" })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::ChangeKind;
+    use crate::config::{Config, SecuritySettings};
+    use mockito::{Matcher, Server};
+    use serde_json::json;
+
+    fn agent(name: &str, version: &str, downloads: u64) -> serde_json::Value {
+        json!({
+            "name": name,
+            "version": version,
+            "description": format!("The {name} agent"),
+            "author": "octocat",
+            "created_at": "2025-01-01T00:00:00Z",
+            "updated_at": "2025-01-02T00:00:00Z",
+            "download_count": downloads,
+            "tags": ["review"],
+            "readme": "# Long readme",
+            "homepage": null,
+            "repository": null,
+            "license": null
+        })
+    }
+
+    fn client(server: &Server) -> ApiClient {
+        let config = Config {
+            registry_url: server.url(),
+            security: SecuritySettings {
+                allow_http: true,
+                ..SecuritySettings::default()
+            },
+            retry: crate::config::settings::RetrySettings {
+                max_retries: 0,
+                ..Default::default()
+            },
+            ..Config::default()
+        };
+        ApiClient::new(&config).unwrap()
+    }
+
+    fn empty_index(registry: &str) -> SearchIndex {
+        SearchIndex {
+            registry: registry.to_string(),
+            cursor: None,
+            synced_at: Utc::now(),
+            agents: BTreeMap::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_changes_are_applied_page_by_page_from_the_cursor() {
+        let mut server = Server::new_async().await;
+        let first = server
+            .mock("GET", "/api/v1/agents/changes")
+            .match_query(Matcher::Exact(format!("limit={CHANGES_PAGE}")))
+            .with_body(
+                json!({
+                    "changes": [
+                        {"kind": "created", "name": "code-reviewer", "agent": agent("code-reviewer", "1.0.0", 5)},
+                        {"kind": "created", "name": "meeting-notes", "agent": agent("meeting-notes", "1.0.0", 9)},
+                    ],
+                    "cursor": "2",
+                    "has_more": true
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/api/v1/agents/changes")
+            .match_query(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("since".into(), "2".into()),
+                Matcher::UrlEncoded("limit".into(), CHANGES_PAGE.to_string()),
+            ]))
+            .with_body(
+                json!({
+                    "changes": [
+                        {"kind": "updated", "name": "code-reviewer", "agent": agent("code-reviewer", "1.1.0", 6)},
+                        {"kind": "removed", "name": "meeting-notes", "agent": null},
+                    ],
+                    "cursor": "4",
+                    "has_more": false
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let mut index = empty_index(&server.url());
+        let summary = index.pull_changes(&client(&server)).await.unwrap();
+        first.assert_async().await;
+        assert_eq!(
+            summary,
+            SyncSummary {
+                changes: 4,
+                rebuilt: false
+            }
+        );
+        assert_eq!(index.cursor.as_deref(), Some("4"));
+        assert_eq!(index.agents.len(), 1);
+        assert_eq!(index.agents["code-reviewer"].version, "1.1.0");
+        assert!(index.agents["code-reviewer"].readme.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_registries_without_a_change_feed_are_copied_whole() {
+        let mut server = Server::new_async().await;
+        server
+            .mock("GET", "/api/v1/agents/changes")
+            .match_query(Matcher::Any)
+            .with_status(404)
+            .with_body(r#"{"error":"not_found","message":"Not found"}"#)
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/api/v1/agents/search")
+            .match_query(Matcher::UrlEncoded("page".into(), "1".into()))
+            .with_body(
+                json!({
+                    "agents": [agent("code-reviewer", "1.0.0", 5)],
+                    "total": 1,
+                    "page": 1,
+                    "per_page": LIST_PAGE
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let mut index = empty_index(&server.url());
+        let summary = index.pull_changes(&client(&server)).await.unwrap();
+        assert_eq!(
+            summary,
+            SyncSummary {
+                changes: 1,
+                rebuilt: true
+            }
+        );
+        assert!(index.cursor.is_none());
+        assert!(index.agents["code-reviewer"].readme.is_none());
+    }
+
+    #[test]
+    fn test_offline_search_matches_like_the_registry() {
+        let mut index = empty_index("https://api.carp.refcell.org");
+        for (name, downloads) in [
+            ("code-reviewer", 5),
+            ("code-formatter", 50),
+            ("test-writer", 1),
+        ] {
+            index.apply(AgentChange {
+                kind: ChangeKind::Created,
+                name: name.to_string(),
+                agent: Some(serde_json::from_value(agent(name, "1.0.0", downloads)).unwrap()),
+            });
+        }
+        let names = |response: SearchResponse| -> Vec<String> {
+            response
+                .agents
+                .into_iter()
+                .map(|agent| agent.name)
+                .collect()
+        };
+
+        let code = index
+            .search("CODE", None, SearchMode::Substring, SearchSort::Downloads)
+            .unwrap();
+        assert_eq!(code.total, 2);
+        assert_eq!(names(code), ["code-formatter", "code-reviewer"]);
+        let by_tag = index
+            .search(
+                "review",
+                Some(1),
+                SearchMode::Substring,
+                SearchSort::Downloads,
+            )
+            .unwrap();
+        assert_eq!((by_tag.total, by_tag.agents.len()), (3, 1));
+        let glob = index
+            .search("*-writer", None, SearchMode::Glob, SearchSort::Downloads)
+            .unwrap();
+        assert_eq!(names(glob), ["test-writer"]);
+        let regex = index
+            .search(
+                "^code-(r|x)",
+                None,
+                SearchMode::Regex,
+                SearchSort::Downloads,
+            )
+            .unwrap();
+        assert_eq!(names(regex), ["code-reviewer"]);
+        assert!(index
+            .search("(", None, SearchMode::Regex, SearchSort::Downloads)
+            .is_err());
+    }
+
+    #[test]
+    fn test_file_names_and_ages() {
+        assert_eq!(
+            file_stem("https://api.carp.refcell.org/"),
+            "api.carp.refcell.org"
+        );
+        assert_eq!(file_stem("http://127.0.0.1:8080"), "127.0.0.1_8080");
+        assert_eq!(age(Utc::now()), "just now");
+        assert_eq!(
+            age(Utc::now() - chrono::Duration::minutes(61)),
+            "1 hour ago"
+        );
+        assert_eq!(age(Utc::now() - chrono::Duration::days(3)), "3 days ago");
+    }
+}
@@ -0,0 +1,114 @@
+use crate::utils::error::{CarpError, CarpResult};
+use sigstore::bundle::sign::SigningContext;
+use sigstore::bundle::verify::policy::{OIDCIssuer, SingleX509ExtPolicy};
+use sigstore::bundle::verify::Verifier;
+use sigstore::bundle::Bundle;
+use sigstore::oauth::IdentityToken;
+use std::io::Cursor;
+
+/// Environment variable holding an ambient OIDC identity token (e.g. from CI)
+pub const IDENTITY_TOKEN_ENV: &str = "SIGSTORE_ID_TOKEN";
+
+/// Signs agent content with an ephemeral Fulcio certificate bound to an OIDC identity
+pub struct KeylessSigner {
+    context: SigningContext,
+    identity_token: String,
+}
+
+impl KeylessSigner {
+    /// Create a signer against the public-good Sigstore infrastructure
+    pub async fn production(identity_token: String) -> CarpResult<Self> {
+        // Fail early on a malformed or expired token rather than per agent
+        let token = parse_identity_token(&identity_token)?;
+        if !token.in_validity_period() {
+            return Err(CarpError::Signing(
+                "OIDC identity token has expired".to_string(),
+            ));
+        }
+
+        let context = SigningContext::async_production()
+            .await
+            .map_err(|e| CarpError::Signing(format!("Failed to initialize Sigstore: {e}")))?;
+
+        Ok(Self {
+            context,
+            identity_token,
+        })
+    }
+
+    /// Identity (email or subject) claimed by the signing token
+    pub fn identity(&self) -> CarpResult<String> {
+        Ok(parse_identity_token(&self.identity_token)?
+            .unverified_claims()
+            .email
+            .clone())
+    }
+
+    /// Sign the content, returning the Sigstore bundle as JSON
+    pub async fn sign(&self, content: &str) -> CarpResult<serde_json::Value> {
+        let session = self
+            .context
+            .signer(parse_identity_token(&self.identity_token)?)
+            .await
+            .map_err(|e| {
+                CarpError::Signing(format!("Failed to obtain signing certificate: {e}"))
+            })?;
+
+        let artifact = session
+            .sign(Cursor::new(content.as_bytes().to_vec()))
+            .await
+            .map_err(|e| CarpError::Signing(format!("Failed to sign content: {e}")))?;
+
+        Ok(serde_json::to_value(artifact.to_bundle())?)
+    }
+}
+
+/// Verify that a Sigstore bundle covers the content and was issued to an identity
+/// from the given OIDC issuer
+pub async fn verify_content(
+    content: &str,
+    bundle: &serde_json::Value,
+    issuer: &str,
+) -> CarpResult<()> {
+    let bundle: Bundle = serde_json::from_value(bundle.clone())
+        .map_err(|e| CarpError::Signing(format!("Malformed signature bundle: {e}")))?;
+
+    let verifier = Verifier::production()
+        .await
+        .map_err(|e| CarpError::Signing(format!("Failed to initialize Sigstore: {e}")))?;
+
+    verifier
+        .verify(content.as_bytes(), bundle, &OIDCIssuer::new(issuer), false)
+        .await
+        .map_err(|e| CarpError::Signing(format!("Signature verification failed: {e}")))
+}
+
+fn parse_identity_token(token: &str) -> CarpResult<IdentityToken> {
+    IdentityToken::try_from(token.trim())
+        .map_err(|e| CarpError::Signing(format!("Invalid OIDC identity token: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_identity_token_rejects_malformed() {
+        assert!(matches!(
+            parse_identity_token("not-a-jwt"),
+            Err(CarpError::Signing(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_verify_rejects_malformed_bundle() {
+        let result = verify_content(
+            "content",
+            &serde_json::json!({"mediaType": 42}),
+            "https://token.actions.githubusercontent.com",
+        )
+        .await;
+
+        assert!(matches!(result, Err(CarpError::Signing(_))));
+    }
+}
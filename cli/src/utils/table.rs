@@ -0,0 +1,189 @@
+//! Plain-text tables sized to the terminal
+//!
+//! Columns are as wide as their widest cell. When the table is wider than
+//! the terminal, the widest columns give up width first, down to the wider
+//! of their header and 8 columns, and cells that no longer fit end in an
+//! ellipsis. Output that isn't going to a terminal is never truncated, so
+//! piping a table keeps every cell whole.
+
+use crate::utils::terminal;
+use colored::*;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// Space between columns
+const GAP: &str = "  ";
+/// Narrowest a column shrinks to, unless its header is narrower
+const MIN_WIDTH: usize = 8;
+const ELLIPSIS: char = '…';
+
+/// Colors a padded cell
+pub type Style = fn(&str) -> ColoredString;
+
+/// A table of text cells, with an optional style per column
+pub struct Table {
+    headers: Vec<String>,
+    styles: Vec<Option<Style>>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn new(headers: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        let headers: Vec<String> = headers.into_iter().map(Into::into).collect();
+        Self {
+            styles: vec![None; headers.len()],
+            headers,
+            rows: Vec::new(),
+        }
+    }
+
+    /// Style every cell of column `index`, after it's been padded
+    pub fn style(&mut self, index: usize, style: Style) {
+        self.styles[index] = Some(style);
+    }
+
+    /// Add a row, with a cell for each header
+    pub fn row(&mut self, cells: Vec<String>) {
+        debug_assert_eq!(cells.len(), self.headers.len());
+        self.rows.push(cells);
+    }
+
+    /// The widths of the columns, shrunk to fit in `max_width` when given
+    fn widths(&self, max_width: Option<usize>) -> Vec<usize> {
+        let mut widths: Vec<usize> = self.headers.iter().map(|h| h.width()).collect();
+        for row in &self.rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.width());
+            }
+        }
+
+        let Some(max_width) = max_width else {
+            return widths;
+        };
+        let gaps = GAP.len() * widths.len().saturating_sub(1);
+        let mins: Vec<usize> = self
+            .headers
+            .iter()
+            .zip(&widths)
+            .map(|(header, &width)| width.min(MIN_WIDTH.max(header.width())))
+            .collect();
+
+        let mut total = widths.iter().sum::<usize>() + gaps;
+        while total > max_width {
+            let widest = (0..widths.len())
+                .filter(|&i| widths[i] > mins[i])
+                .max_by_key(|&i| widths[i]);
+            let Some(widest) = widest else {
+                break;
+            };
+            widths[widest] -= 1;
+            total -= 1;
+        }
+        widths
+    }
+
+    /// The table's lines, header first, fit to `max_width` columns when given
+    pub fn render(&self, max_width: Option<usize>) -> Vec<String> {
+        let widths = self.widths(max_width);
+        let line = |cells: &[String], styled: bool| {
+            let last = cells.len().saturating_sub(1);
+            cells
+                .iter()
+                .enumerate()
+                .map(|(i, cell)| {
+                    let cell = truncate(cell, widths[i]);
+                    // The last column isn't padded, so lines don't end in spaces
+                    let padded = if i == last {
+                        cell
+                    } else {
+                        format!("{cell}{}", " ".repeat(widths[i] - cell.width()))
+                    };
+                    match self.styles[i] {
+                        Some(style) if styled => style(&padded).to_string(),
+                        _ => padded,
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(GAP)
+        };
+
+        let mut lines = vec![line(&self.headers, false).bold().to_string()];
+        lines.extend(self.rows.iter().map(|row| line(row, true)));
+        lines
+    }
+
+    /// Print the table, fit to the terminal
+    pub fn print(&self) {
+        for line in self.render(terminal::width()) {
+            println!("{line}");
+        }
+    }
+}
+
+/// `text` cut to `width` columns, ending in an ellipsis if anything was cut
+fn truncate(text: &str, width: usize) -> String {
+    if text.width() <= width {
+        return text.to_string();
+    }
+    let mut truncated = String::new();
+    let mut used = 0;
+    for c in text.chars() {
+        let c_width = c.width().unwrap_or(0);
+        if used + c_width + 1 > width {
+            break;
+        }
+        truncated.push(c);
+        used += c_width;
+    }
+    if width > 0 {
+        truncated.push(ELLIPSIS);
+    }
+    truncated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table() -> Table {
+        let mut table = Table::new(["Name", "Version", "Description"]);
+        table.row(vec![
+            "code-reviewer".to_string(),
+            "1.2.0".to_string(),
+            "Reviews pull requests for style, tests and naming".to_string(),
+        ]);
+        table.row(vec![
+            "triager".to_string(),
+            "0.3.1".to_string(),
+            "Labels issues".to_string(),
+        ]);
+        table
+    }
+
+    #[test]
+    fn test_render_fits_the_width() {
+        colored::control::set_override(false);
+        let lines = table().render(None);
+        assert_eq!(lines[0], "Name           Version  Description");
+        assert_eq!(
+            lines[1],
+            "code-reviewer  1.2.0    Reviews pull requests for style, tests and naming"
+        );
+        assert_eq!(lines[2], "triager        0.3.1    Labels issues");
+
+        let lines = table().render(Some(40));
+        assert!(lines.iter().all(|line| line.width() <= 40), "{lines:?}");
+        assert_eq!(lines[1], "code-reviewer  1.2.0    Reviews pull re…");
+        assert_eq!(lines[2], "triager        0.3.1    Labels issues");
+
+        // Columns stop shrinking at their minimum, overflowing instead
+        let lines = table().render(Some(10));
+        assert_eq!(lines[2], "triager   0.3.1    Labels iss…");
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("reviewer", 8), "reviewer");
+        assert_eq!(truncate("reviewer", 5), "revi…");
+        assert_eq!(truncate("日本語のエージェント", 7), "日本語…");
+    }
+}
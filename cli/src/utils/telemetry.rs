@@ -0,0 +1,132 @@
+//! Opt-in anonymous usage telemetry
+//!
+//! Enabled with `carp telemetry on`. Each command then reports its name, how
+//! long it took and whether it failed, plus the kind of failure. Arguments,
+//! agent names, paths, file contents and error messages are never sent, and
+//! no identifier ties events from the same machine together.
+
+use crate::api::{ApiClient, TelemetryEvent};
+use crate::utils::error::{CarpError, CarpResult};
+use std::time::Duration;
+
+/// Environment variable that switches telemetry off when set to `0`, `false`
+/// or `off`
+pub const TELEMETRY_ENV: &str = "CARP_TELEMETRY";
+
+/// The environment variable that disables telemetry in this environment,
+/// if any. Honors the `DO_NOT_TRACK` convention as well as `CARP_TELEMETRY`.
+pub fn disabled_by_env() -> Option<&'static str> {
+    disabled_by(|key| std::env::var(key).ok())
+}
+
+fn disabled_by(var: impl Fn(&str) -> Option<String>) -> Option<&'static str> {
+    if var(TELEMETRY_ENV).is_some_and(|v| matches!(v.trim(), "0" | "false" | "off")) {
+        return Some(TELEMETRY_ENV);
+    }
+    if var("DO_NOT_TRACK").is_some_and(|v| !v.trim().is_empty() && v.trim() != "0") {
+        return Some("DO_NOT_TRACK");
+    }
+    None
+}
+
+/// Build the event for a finished command
+pub fn event(command: &str, duration: Duration, result: &CarpResult<()>) -> TelemetryEvent {
+    TelemetryEvent {
+        command: command.to_string(),
+        duration_ms: duration.as_millis() as u64,
+        success: result.is_ok(),
+        error_kind: result
+            .as_ref()
+            .err()
+            .map(|error| error_kind(error).to_string()),
+        cli_version: env!("CARGO_PKG_VERSION").to_string(),
+        os: std::env::consts::OS.to_string(),
+        arch: std::env::consts::ARCH.to_string(),
+    }
+}
+
+/// Send an event, ignoring any failure: telemetry must never change the
+/// outcome of a command
+pub async fn report(client: &ApiClient, event: &TelemetryEvent) {
+    if let Err(e) = client.send_telemetry(event).await {
+        tracing::debug!("Telemetry not sent: {e}");
+    }
+}
+
+/// A coarse, message-free classification of an error
+fn error_kind(error: &CarpError) -> &'static str {
+    match error {
+        CarpError::Io(_) => "io",
+        CarpError::Http(_) => "http",
+        CarpError::Json(_) => "json",
+        CarpError::Toml(_) => "toml",
+        CarpError::Config(_) => "config",
+        CarpError::Auth(_) => "auth",
+        CarpError::ApiKeyExpired(_) => "api_key_expired",
+        CarpError::Forbidden(_) => "forbidden",
+        CarpError::Api { status, .. } if *status >= 500 => "api_server",
+        CarpError::Api { .. } => "api_client",
+        CarpError::RateLimited { .. } => "rate_limited",
+        CarpError::InProgress { .. } => "in_progress",
+        CarpError::AgentNotFound { .. } => "not_found",
+        CarpError::InvalidAgent(_) => "invalid_agent",
+        CarpError::ManifestError(_) => "manifest",
+        CarpError::FileSystem(_) => "filesystem",
+        CarpError::Signing(_) => "signing",
+        CarpError::PolicyViolation(_) => "policy",
+        CarpError::Outdated(_) => "outdated",
+        CarpError::PromptRequired(_) => "prompt_required",
+        CarpError::HookFailed(_) => "hook",
+        CarpError::SecretsDetected(_) => "secrets",
+        CarpError::VersionExists(_) => "version_exists",
+        CarpError::Maintenance(_) => "maintenance",
+        CarpError::Network(_) => "network",
+        CarpError::Other(_) => "other",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_carries_no_error_message() {
+        let result = Err(CarpError::Api {
+            status: 404,
+            message: "Agent 'secret-agent' not found".to_string(),
+        });
+        let event = event("pull", Duration::from_millis(1500), &result);
+
+        assert_eq!(event.command, "pull");
+        assert_eq!(event.duration_ms, 1500);
+        assert!(!event.success);
+        assert_eq!(event.error_kind.as_deref(), Some("api_client"));
+        assert!(!serde_json::to_string(&event)
+            .unwrap()
+            .contains("secret-agent"));
+    }
+
+    #[test]
+    fn test_disabled_by() {
+        let env = |pairs: &'static [(&'static str, &'static str)]| {
+            move |key: &str| {
+                pairs
+                    .iter()
+                    .find(|(k, _)| *k == key)
+                    .map(|(_, v)| v.to_string())
+            }
+        };
+
+        assert_eq!(disabled_by(env(&[])), None);
+        assert_eq!(
+            disabled_by(env(&[("CARP_TELEMETRY", "off")])),
+            Some(TELEMETRY_ENV)
+        );
+        assert_eq!(disabled_by(env(&[("CARP_TELEMETRY", "1")])), None);
+        assert_eq!(
+            disabled_by(env(&[("DO_NOT_TRACK", "1")])),
+            Some("DO_NOT_TRACK")
+        );
+        assert_eq!(disabled_by(env(&[("DO_NOT_TRACK", "0")])), None);
+    }
+}
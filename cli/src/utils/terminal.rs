@@ -0,0 +1,254 @@
+//! Whether output is colored
+//!
+//! `--color` decides, defaulting to `auto`, which colors a stream only when
+//! it's a terminal that can show them:
+//!
+//! - `NO_COLOR` (set to anything but an empty string), `CLICOLOR=0` or
+//!   `TERM=dumb` turn colors off, and so does CI mode
+//! - `CLICOLOR_FORCE` turns them on even when piping
+//! - on Windows, the console is asked to process ANSI escape sequences, and
+//!   consoles that can't, such as those before Windows 10, stay plain rather
+//!   than print them literally
+//!
+//! stdout and stderr are decided separately, so `carp search rust | less`
+//! still gets a colored error on the terminal. Everything colored goes
+//! through `colored`, whose switch is global and set for stdout; text for
+//! stderr is wrapped in [`for_stderr`], which follows [`stderr_colors`]
+//! instead.
+
+use crate::utils::ci;
+use colored::{ColoredString, Styles};
+use std::env;
+use std::fmt;
+use std::io::IsTerminal;
+use std::sync::OnceLock;
+
+static STDOUT: OnceLock<bool> = OnceLock::new();
+static STDERR: OnceLock<bool> = OnceLock::new();
+
+/// When to color output
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ColorChoice {
+    /// Only on terminals that support it
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// Decide whether to color each stream, before anything is printed, and
+/// after CI mode is known
+pub fn init(choice: ColorChoice) {
+    let stdout = *STDOUT.get_or_init(|| decide(choice, Stream::Stdout));
+    STDERR.get_or_init(|| decide(choice, Stream::Stderr));
+    colored::control::set_override(stdout);
+}
+
+/// Whether stderr is colored, including log output
+pub fn stderr_colors() -> bool {
+    STDERR.get().copied().unwrap_or(false)
+}
+
+/// Columns in the terminal stdout goes to, or `None` when it isn't one
+pub fn width() -> Option<usize> {
+    if !std::io::stdout().is_terminal() {
+        return None;
+    }
+    env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+        .or_else(|| {
+            crossterm::terminal::size()
+                .ok()
+                .map(|(width, _)| width.into())
+        })
+}
+
+/// Text for stderr, colored as stderr is rather than as stdout is
+pub fn for_stderr(text: ColoredString) -> Painted {
+    Painted {
+        text,
+        colors: stderr_colors(),
+    }
+}
+
+/// Colored text that shows its colors only when `colors` is set, whatever
+/// `colored`'s global switch says
+#[derive(Debug, Clone)]
+pub struct Painted {
+    text: ColoredString,
+    colors: bool,
+}
+
+impl fmt::Display for Painted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.colors || self.text.is_plain() {
+            return f.write_str(&self.text.input);
+        }
+
+        let style = self.text.style;
+        let codes: Vec<_> = [
+            Styles::Bold,
+            Styles::Dimmed,
+            Styles::Italic,
+            Styles::Underline,
+            Styles::Blink,
+            Styles::Reversed,
+            Styles::Hidden,
+            Styles::Strikethrough,
+        ]
+        .into_iter()
+        .filter(|&styles| style.contains(styles))
+        .map(|styles| styles_code(styles).into())
+        .chain(self.text.bgcolor.map(|color| color.to_bg_str()))
+        .chain(self.text.fgcolor.map(|color| color.to_fg_str()))
+        .collect();
+        write!(f, "\x1B[{}m{}\x1B[0m", codes.join(";"), self.text.input)
+    }
+}
+
+/// The SGR parameter of a style, as `colored` writes it
+fn styles_code(styles: Styles) -> &'static str {
+    match styles {
+        Styles::Clear => "0",
+        Styles::Bold => "1",
+        Styles::Dimmed => "2",
+        Styles::Italic => "3",
+        Styles::Underline => "4",
+        Styles::Blink => "5",
+        Styles::Reversed => "7",
+        Styles::Hidden => "8",
+        Styles::Strikethrough => "9",
+    }
+}
+
+fn decide(choice: ColorChoice, stream: Stream) -> bool {
+    let terminal = match stream {
+        Stream::Stdout => std::io::stdout().is_terminal(),
+        Stream::Stderr => std::io::stderr().is_terminal(),
+    };
+    choose(choice, terminal && !ci::enabled(), |var| env::var(var).ok())
+        && (choice != ColorChoice::Auto || !terminal || enable_virtual_terminal(stream))
+}
+
+/// Whether to color a stream under `choice`, given whether it's a terminal
+/// (outside CI mode)
+fn choose(choice: ColorChoice, terminal: bool, var: impl Fn(&str) -> Option<String>) -> bool {
+    match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => auto(terminal, var),
+    }
+}
+
+/// Whether to color a stream under `--color auto`, given whether it's a
+/// terminal (outside CI mode)
+fn auto(terminal: bool, var: impl Fn(&str) -> Option<String>) -> bool {
+    if var("CLICOLOR_FORCE").is_some_and(|value| value != "0") {
+        return true;
+    }
+    let no_color = var("NO_COLOR").is_some_and(|value| !value.is_empty());
+    let off = var("CLICOLOR").as_deref() == Some("0") || var("TERM").as_deref() == Some("dumb");
+    terminal && !no_color && !off
+}
+
+/// Ask the Windows console to process escape sequences, which it only does
+/// by default from Windows 10 on, and only in some hosts
+#[cfg(windows)]
+fn enable_virtual_terminal(stream: Stream) -> bool {
+    use windows_sys::Win32::System::Console::{
+        GetConsoleMode, GetStdHandle, SetConsoleMode, ENABLE_VIRTUAL_TERMINAL_PROCESSING,
+        STD_ERROR_HANDLE, STD_OUTPUT_HANDLE,
+    };
+
+    // SAFETY: only reads and updates the mode of this process's own console
+    unsafe {
+        let handle = GetStdHandle(match stream {
+            Stream::Stdout => STD_OUTPUT_HANDLE,
+            Stream::Stderr => STD_ERROR_HANDLE,
+        });
+        let mut mode = 0;
+        // Terminals that aren't consoles, such as mintty, handle escapes
+        if GetConsoleMode(handle, &mut mode) == 0 {
+            return true;
+        }
+        mode & ENABLE_VIRTUAL_TERMINAL_PROCESSING != 0
+            || SetConsoleMode(handle, mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING) != 0
+    }
+}
+
+#[cfg(not(windows))]
+fn enable_virtual_terminal(_stream: Stream) -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use colored::Colorize;
+
+    #[test]
+    fn test_auto() {
+        let env = |vars: &'static [(&str, &str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(var, _)| *var == name)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+        assert!(auto(true, env(&[("TERM", "xterm-256color")])));
+        assert!(!auto(false, env(&[("TERM", "xterm-256color")])));
+        assert!(!auto(true, env(&[("NO_COLOR", "1")])));
+        assert!(auto(true, env(&[("NO_COLOR", "")])));
+        assert!(!auto(true, env(&[("TERM", "dumb")])));
+        assert!(!auto(true, env(&[("CLICOLOR", "0")])));
+        // Forced even when piping
+        assert!(auto(
+            false,
+            env(&[("NO_COLOR", "1"), ("CLICOLOR_FORCE", "1")])
+        ));
+    }
+
+    #[test]
+    fn test_choose() {
+        for choice in [ColorChoice::Auto, ColorChoice::Always, ColorChoice::Never] {
+            for no_color in [false, true] {
+                for terminal in [false, true] {
+                    let var =
+                        |name: &str| (no_color && name == "NO_COLOR").then(|| "1".to_string());
+                    let expected = match choice {
+                        ColorChoice::Always => true,
+                        ColorChoice::Never => false,
+                        ColorChoice::Auto => terminal && !no_color,
+                    };
+                    assert_eq!(
+                        choose(choice, terminal, var),
+                        expected,
+                        "--color {choice:?}, NO_COLOR {no_color}, terminal {terminal}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_painted_ignores_the_global_switch() {
+        let warning = || "Warning:".yellow().bold();
+        let on = Painted {
+            text: warning(),
+            colors: true,
+        };
+        let off = Painted {
+            text: warning(),
+            colors: false,
+        };
+        assert_eq!(on.to_string(), "\x1B[1;33mWarning:\x1B[0m");
+        assert_eq!(off.to_string(), "Warning:");
+    }
+}
@@ -0,0 +1,324 @@
+//! A trash for the agent directories and project files commands replace or
+//! delete, so `carp undo` can put them back
+//!
+//! Everything one command discards is kept together as an operation, in a
+//! directory of its own under `<cache>/carp/trash`; only the most recent
+//! [`KEEP_OPERATIONS`] are kept. Undoing an operation moves whatever is now
+//! at each of its paths into a new operation before restoring the old
+//! contents, so running `carp undo` again redoes it.
+
+use crate::config::ConfigManager;
+use crate::utils::agent_dir;
+use crate::utils::error::{CarpError, CarpResult};
+use crate::utils::file_lock::LockedFile;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex};
+use tracing::debug;
+
+/// Operations kept in the trash, oldest removed first
+pub const KEEP_OPERATIONS: usize = 10;
+
+/// Describes an operation, in its directory
+const OPERATION_FILE: &str = "operation.json";
+
+/// The trash the running command discards into, opened on first use
+static TRASH: LazyLock<Mutex<Option<Trash>>> = LazyLock::new(Default::default);
+
+/// What one command discarded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Operation {
+    /// The command line that discarded it
+    pub command: String,
+    pub created_at: DateTime<Utc>,
+    /// In the order they were discarded
+    pub entries: Vec<Entry>,
+}
+
+/// A path an operation replaced or deleted
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    pub path: PathBuf,
+    /// Name of what was at `path` in the operation's directory, or `None`
+    /// when nothing was there
+    pub stored: Option<String>,
+}
+
+/// Move the file or directory at `path` into the trash, if there's anything
+/// there
+pub fn discard(path: &Path) -> CarpResult<()> {
+    with_trash(|trash| trash.discard(path, false))
+}
+
+/// Keep a copy of the file at `path` before the command rewrites it, so
+/// undoing puts it back; a file that doesn't exist yet is removed instead
+pub fn preserve(path: &Path) -> CarpResult<()> {
+    with_trash(|trash| trash.preserve(path))
+}
+
+/// Whether the running command has discarded anything yet
+pub fn active() -> bool {
+    TRASH
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .as_ref()
+        .is_some_and(|trash| trash.current.is_some())
+}
+
+/// Restore everything the most recent operation discarded, returning it,
+/// or `None` when the trash is empty
+pub fn undo() -> CarpResult<Option<Operation>> {
+    with_trash(Trash::undo)
+}
+
+fn with_trash<T>(f: impl FnOnce(&mut Trash) -> CarpResult<T>) -> CarpResult<T> {
+    let mut trash = TRASH.lock().unwrap_or_else(|e| e.into_inner());
+    if trash.is_none() {
+        *trash = Some(Trash::new(ConfigManager::cache_dir()?.join("trash")));
+    }
+    let trash = trash.as_mut().expect("trash was just opened");
+    // Other carp processes may be pruning or undoing at the same time
+    let _lock = LockedFile::beside(&trash.root)?;
+    f(trash)
+}
+
+struct Trash {
+    root: PathBuf,
+    /// The running command's operation and its directory, once it has one
+    current: Option<(PathBuf, Operation)>,
+}
+
+impl Trash {
+    fn new(root: PathBuf) -> Self {
+        Self {
+            root,
+            current: None,
+        }
+    }
+
+    /// Move what's at `path` into the current operation, recording that
+    /// nothing was there too when `record_absent` is set
+    fn discard(&mut self, path: &Path, record_absent: bool) -> CarpResult<()> {
+        let exists = fs::symlink_metadata(path).is_ok();
+        if !exists && !record_absent {
+            return Ok(());
+        }
+        let path = std::path::absolute(path)?;
+        let (dir, operation) = self.current()?;
+        let stored = exists.then(|| operation.entries.len().to_string());
+        if let Some(stored) = &stored {
+            debug!("Moving {} to the trash", path.display());
+            move_path(&path, &dir.join(stored))?;
+        }
+        operation.entries.push(Entry { path, stored });
+        self.save()
+    }
+
+    fn preserve(&mut self, path: &Path) -> CarpResult<()> {
+        let path = std::path::absolute(path)?;
+        if let Some((_, operation)) = &self.current {
+            // The first copy is the one from before the command ran
+            if operation.entries.iter().any(|entry| entry.path == path) {
+                return Ok(());
+            }
+        }
+        let exists = path.is_file();
+        let (dir, operation) = self.current()?;
+        let stored = exists.then(|| operation.entries.len().to_string());
+        if let Some(stored) = &stored {
+            fs::copy(&path, dir.join(stored))?;
+        }
+        operation.entries.push(Entry { path, stored });
+        self.save()
+    }
+
+    fn undo(&mut self) -> CarpResult<Option<Operation>> {
+        let Some(dir) = self.operations()?.pop() else {
+            return Ok(None);
+        };
+        let operation = read_operation(&dir)?;
+
+        for entry in operation.entries.iter().rev() {
+            self.discard(&entry.path, true)?;
+            if let Some(stored) = &entry.stored {
+                if let Some(parent) = entry.path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                move_path(&dir.join(stored), &entry.path)?;
+            }
+        }
+        fs::remove_dir_all(&dir)?;
+        Ok(Some(operation))
+    }
+
+    /// The running command's operation, started on first use
+    fn current(&mut self) -> CarpResult<(PathBuf, &mut Operation)> {
+        if self.current.is_none() {
+            // Named so they sort in the order they were made
+            let created_at = Utc::now();
+            let dir = self.root.join(format!(
+                "{:020}",
+                created_at.timestamp_nanos_opt().unwrap_or_default()
+            ));
+            fs::create_dir_all(&dir)?;
+            let operation = Operation {
+                command: std::env::args()
+                    .enumerate()
+                    .map(|(i, arg)| if i == 0 { "carp".to_string() } else { arg })
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                created_at,
+                entries: Vec::new(),
+            };
+            self.current = Some((dir, operation));
+            self.prune()?;
+        }
+        let (dir, operation) = self.current.as_mut().expect("operation was just started");
+        Ok((dir.clone(), operation))
+    }
+
+    fn save(&self) -> CarpResult<()> {
+        if let Some((dir, operation)) = &self.current {
+            fs::write(
+                dir.join(OPERATION_FILE),
+                serde_json::to_string_pretty(operation)?,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Directories of the operations in the trash, oldest first
+    fn operations(&self) -> CarpResult<Vec<PathBuf>> {
+        let current = self.current.as_ref().map(|(dir, _)| dir);
+        let mut operations = Vec::new();
+        match fs::read_dir(&self.root) {
+            Ok(entries) => {
+                for entry in entries {
+                    let path = entry?.path();
+                    if path.join(OPERATION_FILE).is_file() && Some(&path) != current {
+                        operations.push(path);
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        operations.sort();
+        Ok(operations)
+    }
+
+    fn prune(&self) -> CarpResult<()> {
+        let operations = self.operations()?;
+        let excess = (operations.len() + 1).saturating_sub(KEEP_OPERATIONS);
+        for dir in &operations[..excess] {
+            debug!("Emptying {} from the trash", dir.display());
+            fs::remove_dir_all(dir)?;
+        }
+        Ok(())
+    }
+}
+
+fn read_operation(dir: &Path) -> CarpResult<Operation> {
+    let contents = fs::read_to_string(dir.join(OPERATION_FILE))?;
+    serde_json::from_str(&contents).map_err(|e| {
+        CarpError::FileSystem(format!(
+            "Can't read {}: {e}",
+            dir.join(OPERATION_FILE).display()
+        ))
+    })
+}
+
+/// Rename `from` to `to`, copying it instead when they're on different
+/// filesystems
+fn move_path(from: &Path, to: &Path) -> CarpResult<()> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    copy_path(from, to)?;
+    agent_dir::remove(from)
+}
+
+fn copy_path(from: &Path, to: &Path) -> CarpResult<()> {
+    let metadata = fs::symlink_metadata(from)?;
+    if metadata.is_symlink() {
+        let target = fs::read_link(from)?;
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(target, to)?;
+        #[cfg(not(unix))]
+        return Err(CarpError::FileSystem(format!(
+            "Can't move the link {} to {}",
+            from.display(),
+            target.display()
+        )));
+    } else if metadata.is_dir() {
+        fs::create_dir_all(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_path(&entry.path(), &to.join(entry.file_name()))?;
+        }
+    } else {
+        fs::copy(from, to)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_undo_restores_and_redoes() {
+        let dir = tempfile::tempdir().unwrap();
+        let agent = dir.path().join("agents/reviewer");
+        let manifest = dir.path().join("carp.toml");
+        fs::create_dir_all(&agent).unwrap();
+        fs::write(agent.join("reviewer.md"), "v1").unwrap();
+        fs::write(&manifest, "[agents]\nreviewer = \"1\"\n").unwrap();
+
+        // As `carp remove reviewer` would
+        let mut trash = Trash::new(dir.path().join("trash"));
+        trash.preserve(&manifest).unwrap();
+        trash.discard(&agent, false).unwrap();
+        trash.discard(&dir.path().join("missing"), false).unwrap();
+        fs::write(&manifest, "[agents]\n").unwrap();
+        assert!(!agent.exists());
+        assert_eq!(trash.current.as_ref().unwrap().1.entries.len(), 2);
+
+        let mut trash = Trash::new(dir.path().join("trash"));
+        let operation = trash.undo().unwrap().unwrap();
+        assert_eq!(operation.entries.len(), 2);
+        assert_eq!(fs::read_to_string(agent.join("reviewer.md")).unwrap(), "v1");
+        assert_eq!(
+            fs::read_to_string(&manifest).unwrap(),
+            "[agents]\nreviewer = \"1\"\n"
+        );
+
+        // Undoing the undo removes the agent again
+        let mut trash = Trash::new(dir.path().join("trash"));
+        trash.undo().unwrap().unwrap();
+        assert!(!agent.exists());
+        assert_eq!(fs::read_to_string(&manifest).unwrap(), "[agents]\n");
+    }
+
+    #[test]
+    fn test_only_recent_operations_are_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        for i in 0..KEEP_OPERATIONS + 2 {
+            let path = dir.path().join(format!("agent-{i}"));
+            fs::write(&path, "").unwrap();
+            Trash::new(dir.path().join("trash"))
+                .discard(&path, false)
+                .unwrap();
+        }
+        let trash = Trash::new(dir.path().join("trash"));
+        let operations = trash.operations().unwrap();
+        assert_eq!(operations.len(), KEEP_OPERATIONS);
+        let newest = read_operation(operations.last().unwrap()).unwrap();
+        assert!(newest.entries[0]
+            .path
+            .ends_with(format!("agent-{}", KEEP_OPERATIONS + 1)));
+    }
+}
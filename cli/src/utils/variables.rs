@@ -0,0 +1,242 @@
+//! Variables an agent declares for the projects that install it
+//!
+//! An agent with a `Carp.toml` declares them in its `[variables]` table, and
+//! a single markdown agent under `variables` in its frontmatter:
+//!
+//! ```toml
+//! [variables]
+//! TEAM = { description = "Team whose conventions reviews follow", default = "platform" }
+//! ```
+//!
+//! `carp install` replaces every `${TEAM}` in the installed files with the
+//! project's value, so one published agent can be customized per team
+//! without forking it. References to anything the agent doesn't declare,
+//! such as shell variables in examples, are left alone.
+
+use crate::utils::agent_dir;
+use crate::utils::ci;
+use crate::utils::error::{CarpError, CarpResult};
+use crate::utils::frontmatter::parse_frontmatter;
+use crate::utils::manifest::MANIFEST_FILE;
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::LazyLock;
+use tracing::debug;
+
+/// `${NAME}` references in agent files
+static REFERENCE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\$\{([A-Za-z_][A-Za-z0-9_]*)\}").expect("valid reference pattern")
+});
+
+/// Variables by name
+pub type Variables = BTreeMap<String, Variable>;
+
+/// A variable an agent declares
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Variable {
+    /// Shown when `carp install` asks for a value
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Used when the project gives no value
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+}
+
+/// Check that every variable can be referenced as `${NAME}`
+pub fn validate(variables: &Variables) -> CarpResult<()> {
+    for name in variables.keys() {
+        let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            return Err(CarpError::ManifestError(format!(
+                "Variable '{name}' must start with a letter or underscore and hold only letters, digits and underscores"
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// The variables declared by the agent installed in `dir`: its `Carp.toml`'s,
+/// or else those in the frontmatter of its top-level markdown files
+pub fn declared(dir: &Path) -> CarpResult<Variables> {
+    #[derive(Deserialize)]
+    struct VariablesOnly {
+        #[serde(default)]
+        variables: Variables,
+    }
+
+    let manifest = dir.join(MANIFEST_FILE);
+    let variables = if manifest.is_file() {
+        let contents = fs::read_to_string(&manifest)
+            .map_err(|e| CarpError::ManifestError(format!("Failed to read manifest: {e}")))?;
+        let parsed: VariablesOnly = toml::from_str(&contents).map_err(|e| {
+            CarpError::ManifestError(format!("Failed to parse {}: {e}", manifest.display()))
+        })?;
+        parsed.variables
+    } else {
+        let mut variables = Variables::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if !path.is_file() || path.extension().is_none_or(|extension| extension != "md") {
+                continue;
+            }
+            let Ok((frontmatter, _)) = parse_frontmatter(&fs::read_to_string(&path)?) else {
+                continue;
+            };
+            let Some(declared) = frontmatter.get("variables") else {
+                continue;
+            };
+            let declared: Variables = serde_json::from_value(declared.clone()).map_err(|e| {
+                CarpError::ManifestError(format!("Invalid variables in {}: {e}", path.display()))
+            })?;
+            variables.extend(declared);
+        }
+        variables
+    };
+
+    validate(&variables)?;
+    Ok(variables)
+}
+
+/// Parse `--set KEY=VALUE` arguments
+pub fn parse_assignments(assignments: &[String]) -> CarpResult<BTreeMap<String, String>> {
+    assignments
+        .iter()
+        .map(|assignment| {
+            let (key, value) = assignment.split_once('=').ok_or_else(|| {
+                CarpError::Config(format!("--set '{assignment}' must be written KEY=VALUE"))
+            })?;
+            Ok((key.trim().to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+/// A value for each variable the agent `name` declares, taken from `--set`,
+/// then the values the project recorded, then a prompt
+///
+/// CI mode takes the default instead of prompting, and fails for variables
+/// without one.
+pub fn resolve(
+    name: &str,
+    declared: &Variables,
+    recorded: &BTreeMap<String, String>,
+    assigned: &BTreeMap<String, String>,
+) -> CarpResult<BTreeMap<String, String>> {
+    let mut values = BTreeMap::new();
+    for (key, variable) in declared {
+        if let Some(value) = assigned.get(key).or_else(|| recorded.get(key)) {
+            values.insert(key.clone(), value.clone());
+            continue;
+        }
+
+        let value = if ci::enabled() {
+            variable.default.clone().ok_or_else(|| {
+                CarpError::PromptRequired(format!(
+                    "'{name}' needs a value for {key}, and CI mode doesn't prompt; pass --set {key}=VALUE"
+                ))
+            })?
+        } else {
+            let prompt = format!("Value for {key} in {name}:");
+            let mut text = inquire::Text::new(&prompt);
+            if let Some(description) = &variable.description {
+                text = text.with_help_message(description);
+            }
+            if let Some(default) = &variable.default {
+                text = text.with_default(default);
+            }
+            text.prompt()
+                .map_err(|e| CarpError::Other(format!("Input cancelled: {e}")))?
+        };
+        values.insert(key.clone(), value);
+    }
+    Ok(values)
+}
+
+/// Replace references to `values` in the text files under `dir`, other than
+/// its `Carp.toml`, returning how many files changed
+pub fn render(dir: &Path, values: &BTreeMap<String, String>) -> CarpResult<usize> {
+    if values.is_empty() {
+        return Ok(0);
+    }
+
+    let mut changed = 0;
+    for path in agent_dir::files(dir)? {
+        if path == dir.join(MANIFEST_FILE) {
+            continue;
+        }
+        // Binary files are left as they are
+        let Ok(text) = fs::read_to_string(&path) else {
+            continue;
+        };
+        let rendered = substitute(&text, values);
+        if rendered != text {
+            debug!("Rendering variables in {}", path.display());
+            fs::write(&path, rendered)?;
+            changed += 1;
+        }
+    }
+    Ok(changed)
+}
+
+/// Replace `${NAME}` with the value of `NAME`, leaving references to
+/// anything else as written
+fn substitute(text: &str, values: &BTreeMap<String, String>) -> String {
+    REFERENCE
+        .replace_all(text, |captures: &Captures| match values.get(&captures[1]) {
+            Some(value) => value.clone(),
+            None => captures[0].to_string(),
+        })
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_declared_in_frontmatter_and_rendered() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("reviewer.md"),
+            "---\nname: reviewer\ndescription: Reviews for ${TEAM}\nvariables:\n  TEAM:\n    description: Owning team\n    default: platform\n  CHANNEL:\n    description: Where to post\n---\n\n# ${TEAM} reviewer\n\nPost to ${CHANNEL}, not $HOME or ${HOME}.\n",
+        )
+        .unwrap();
+
+        let declared = declared(dir.path()).unwrap();
+        assert_eq!(declared["TEAM"].default.as_deref(), Some("platform"));
+        assert_eq!(declared["CHANNEL"].default, None);
+
+        let recorded = BTreeMap::from([("TEAM".to_string(), "payments".to_string())]);
+        let assigned = BTreeMap::from([("CHANNEL".to_string(), "#reviews".to_string())]);
+        let values = resolve("reviewer", &declared, &recorded, &assigned).unwrap();
+        assert_eq!(render(dir.path(), &values).unwrap(), 1);
+
+        let rendered = fs::read_to_string(dir.path().join("reviewer.md")).unwrap();
+        assert!(rendered.contains("description: Reviews for payments"));
+        assert!(
+            rendered.ends_with("# payments reviewer\n\nPost to #reviews, not $HOME or ${HOME}.\n")
+        );
+    }
+
+    #[test]
+    fn test_invalid_names_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join(MANIFEST_FILE),
+            "[variables]\n\"team-name\" = { default = \"platform\" }\n",
+        )
+        .unwrap();
+        assert!(declared(dir.path()).is_err());
+
+        fs::write(
+            dir.path().join(MANIFEST_FILE),
+            "[variables]\nTEAM = { default = \"platform\", required = true }\n",
+        )
+        .unwrap();
+        assert!(declared(dir.path()).is_err());
+    }
+}
@@ -0,0 +1,697 @@
+use crate::utils::error::{CarpError, CarpResult};
+use crate::utils::file_lock::{self, LockedFile};
+use crate::utils::git_source::{GitReference, GitSource};
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use toml_edit::{DocumentMut, InlineTable, Item, Table};
+
+/// Name of the project manifest
+pub const MANIFEST_FILE: &str = "carp.toml";
+/// Name of the lockfile kept beside the manifest
+pub const LOCKFILE: &str = "carp.lock";
+
+const LOCKFILE_VERSION: u32 = 1;
+const LOCKFILE_HEADER: &str = "# This file is generated by carp install. Do not edit it by hand.\n";
+
+/// Agents a project depends on, read from `carp.toml` at its root
+///
+/// Requirements use Cargo's syntax, so `"1.2"` means `^1.2`. Agents that
+/// aren't published can come from a git repository or a local working tree
+/// instead. Agents are installed under `[install] dir` unless they name a
+/// directory of their own. Values for the `${NAME}` variables an agent
+/// declares are kept under `[variables]`:
+///
+/// ```toml
+/// [install]
+/// dir = ".claude/agents"
+///
+/// [agents]
+/// code-reviewer = "1.2"
+/// test-writer = { version = "~0.3", dir = "tools/agents" }
+/// triager = { git = "https://github.com/acme/agents", tag = "v2.0.0", subdir = "triager" }
+/// draft = { path = "../agents/draft" }
+///
+/// [variables.code-reviewer]
+/// TEAM = "payments"
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Workspace {
+    #[serde(default)]
+    pub install: InstallSettings,
+    #[serde(default)]
+    pub agents: BTreeMap<String, Dependency>,
+    #[serde(default)]
+    pub variables: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+/// Where agents are installed, relative to the manifest
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InstallSettings {
+    #[serde(default = "default_install_dir")]
+    pub dir: String,
+}
+
+impl Default for InstallSettings {
+    fn default() -> Self {
+        Self {
+            dir: default_install_dir(),
+        }
+    }
+}
+
+fn default_install_dir() -> String {
+    ".claude/agents".to_string()
+}
+
+/// An agent the project depends on, as a bare requirement or a table
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Dependency {
+    Version(String),
+    Git {
+        git: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        branch: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tag: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        rev: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        subdir: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        dir: Option<String>,
+    },
+    Path {
+        path: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        dir: Option<String>,
+    },
+    Detailed {
+        version: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        dir: Option<String>,
+    },
+}
+
+impl Dependency {
+    /// A dependency on a git source, installed in `dir` if given
+    pub fn from_git(source: &GitSource, dir: Option<String>) -> Self {
+        let (mut branch, mut tag, mut rev) = (None, None, None);
+        match &source.reference {
+            GitReference::DefaultBranch => {}
+            GitReference::Branch(name) => branch = Some(name.clone()),
+            GitReference::Tag(name) => tag = Some(name.clone()),
+            GitReference::Rev(name) => rev = Some(name.clone()),
+        }
+        Dependency::Git {
+            git: source.url.clone(),
+            branch,
+            tag,
+            rev,
+            subdir: source.subdir.clone(),
+            dir,
+        }
+    }
+
+    /// The version requirement as written in the manifest, if the agent
+    /// comes from the registry
+    pub fn requirement(&self) -> Option<&str> {
+        match self {
+            Dependency::Version(version) | Dependency::Detailed { version, .. } => Some(version),
+            Dependency::Git { .. } | Dependency::Path { .. } => None,
+        }
+    }
+
+    /// The agent's working tree, relative to the manifest, if it's installed
+    /// from a path
+    pub fn path(&self) -> Option<&str> {
+        match self {
+            Dependency::Path { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+
+    /// The repository the agent comes from, if it isn't from the registry
+    pub fn git_source(&self) -> CarpResult<Option<GitSource>> {
+        match self {
+            Dependency::Git {
+                git,
+                branch,
+                tag,
+                rev,
+                subdir,
+                ..
+            } => GitSource::new(
+                git,
+                branch.as_deref(),
+                tag.as_deref(),
+                rev.as_deref(),
+                subdir.as_deref(),
+            )
+            .map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// Directory overriding `[install] dir`, relative to the manifest
+    pub fn dir(&self) -> Option<&str> {
+        match self {
+            Dependency::Version(_) => None,
+            Dependency::Detailed { dir, .. }
+            | Dependency::Git { dir, .. }
+            | Dependency::Path { dir, .. } => dir.as_deref(),
+        }
+    }
+}
+
+impl Workspace {
+    /// Find the directory holding `carp.toml`, starting at `start` and
+    /// walking up through its parents
+    pub fn find_root(start: &Path) -> Option<PathBuf> {
+        start
+            .ancestors()
+            .find(|dir| dir.join(MANIFEST_FILE).is_file())
+            .map(Path::to_path_buf)
+    }
+
+    /// Load and validate the manifest in `root`
+    pub fn load(root: &Path) -> CarpResult<Self> {
+        let path = root.join(MANIFEST_FILE);
+        let contents = file_lock::read_to_string(&path).map_err(|e| {
+            CarpError::ManifestError(format!("Failed to read '{}': {e}", path.display()))
+        })?;
+
+        let workspace: Workspace = toml::from_str(&contents).map_err(|e| {
+            CarpError::ManifestError(format!("Failed to parse '{}': {e}", path.display()))
+        })?;
+
+        workspace.validate()?;
+        Ok(workspace)
+    }
+
+    fn validate(&self) -> CarpResult<()> {
+        for (name, dependency) in &self.agents {
+            match (dependency.requirement(), dependency.path()) {
+                (Some(requirement), _) => {
+                    parse_requirement(name, requirement)?;
+                }
+                (None, Some("")) => {
+                    return Err(CarpError::ManifestError(format!("Empty path for '{name}'")));
+                }
+                (None, Some(_)) => {}
+                (None, None) => {
+                    dependency.git_source().map_err(|e| {
+                        CarpError::ManifestError(format!("Invalid git source for '{name}': {e}"))
+                    })?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Where an agent is installed
+    pub fn install_dir(&self, root: &Path, name: &str, dependency: &Dependency) -> PathBuf {
+        root.join(dependency.dir().unwrap_or(&self.install.dir))
+            .join(name)
+    }
+}
+
+/// `carp.toml` opened for `carp add` and `carp remove`, keeping the comments
+/// and layout of everything they don't touch
+///
+/// The manifest stays locked from `open` to `save`, so concurrent carp
+/// invocations editing it take turns rather than losing each other's changes.
+pub struct ManifestEditor {
+    path: PathBuf,
+    document: DocumentMut,
+    file: LockedFile,
+}
+
+impl ManifestEditor {
+    /// Open the manifest in `root`, or start an empty one if there is none
+    pub fn open(root: &Path) -> CarpResult<Self> {
+        let path = root.join(MANIFEST_FILE);
+        let mut file = LockedFile::open(&path).map_err(|e| {
+            CarpError::ManifestError(format!("Failed to open '{}': {e}", path.display()))
+        })?;
+        // Empty when the lock just created it
+        let contents = file.read_to_string().map_err(|e| {
+            CarpError::ManifestError(format!("Failed to read '{}': {e}", path.display()))
+        })?;
+
+        let document = contents.parse::<DocumentMut>().map_err(|e| {
+            CarpError::ManifestError(format!("Failed to parse '{}': {e}", path.display()))
+        })?;
+        Ok(Self {
+            path,
+            document,
+            file,
+        })
+    }
+
+    fn agents(&mut self) -> CarpResult<&mut Table> {
+        self.document
+            .entry("agents")
+            .or_insert_with(|| Item::Table(Table::new()))
+            .as_table_mut()
+            .ok_or_else(|| {
+                CarpError::ManifestError(format!(
+                    "'agents' in '{}' is not a table",
+                    self.path.display()
+                ))
+            })
+    }
+
+    /// Add an agent, or replace the entry it already has
+    pub fn set_agent(&mut self, name: &str, dependency: &Dependency) -> CarpResult<()> {
+        let value = match dependency {
+            Dependency::Version(version) => toml_edit::value(version.as_str()),
+            Dependency::Detailed { version, dir } => {
+                let mut table = InlineTable::new();
+                table.insert("version", version.as_str().into());
+                if let Some(dir) = dir {
+                    table.insert("dir", dir.as_str().into());
+                }
+                toml_edit::value(table)
+            }
+            Dependency::Path { path, dir } => {
+                let mut table = InlineTable::new();
+                table.insert("path", path.as_str().into());
+                if let Some(dir) = dir {
+                    table.insert("dir", dir.as_str().into());
+                }
+                toml_edit::value(table)
+            }
+            Dependency::Git {
+                git,
+                branch,
+                tag,
+                rev,
+                subdir,
+                dir,
+            } => {
+                let mut table = InlineTable::new();
+                table.insert("git", git.as_str().into());
+                let optional = [
+                    ("branch", branch),
+                    ("tag", tag),
+                    ("rev", rev),
+                    ("subdir", subdir),
+                    ("dir", dir),
+                ];
+                for (key, value) in optional {
+                    if let Some(value) = value {
+                        table.insert(key, value.as_str().into());
+                    }
+                }
+                toml_edit::value(table)
+            }
+        };
+
+        self.agents()?.insert(name, value);
+        Ok(())
+    }
+
+    /// Remove an agent and its variables, returning whether it was listed
+    pub fn remove_agent(&mut self, name: &str) -> CarpResult<bool> {
+        if let Some(variables) = self
+            .document
+            .get_mut("variables")
+            .and_then(Item::as_table_like_mut)
+        {
+            variables.remove(name);
+        }
+        Ok(self.agents()?.remove(name).is_some())
+    }
+
+    /// Record the values of an agent's variables, replacing any it had
+    pub fn set_variables(
+        &mut self,
+        name: &str,
+        values: &BTreeMap<String, String>,
+    ) -> CarpResult<()> {
+        let path = self.path.display().to_string();
+        let variables = self
+            .document
+            .entry("variables")
+            .or_insert_with(|| {
+                let mut table = Table::new();
+                table.set_implicit(true);
+                Item::Table(table)
+            })
+            .as_table_mut()
+            .ok_or_else(|| {
+                CarpError::ManifestError(format!("'variables' in '{path}' is not a table"))
+            })?;
+
+        let mut table = Table::new();
+        for (key, value) in values {
+            table.insert(key, toml_edit::value(value.as_str()));
+        }
+        variables.insert(name, Item::Table(table));
+        Ok(())
+    }
+
+    /// Write the manifest back and let go of its lock
+    pub fn save(mut self) -> CarpResult<()> {
+        self.file.replace(self.document.to_string())?;
+        Ok(())
+    }
+}
+
+/// Parse an agent's version requirement, naming the agent if it's invalid
+pub fn parse_requirement(name: &str, requirement: &str) -> CarpResult<VersionReq> {
+    VersionReq::parse(requirement).map_err(|e| {
+        CarpError::ManifestError(format!(
+            "Invalid version requirement '{requirement}' for '{name}': {e}"
+        ))
+    })
+}
+
+/// The newest of `versions` that satisfies `requirement`
+///
+/// Versions that aren't semver are never chosen.
+pub fn resolve<'a>(
+    requirement: &VersionReq,
+    versions: impl IntoIterator<Item = &'a str>,
+) -> Option<&'a str> {
+    versions
+        .into_iter()
+        .filter_map(|raw| Version::parse(raw).ok().map(|version| (version, raw)))
+        .filter(|(version, _)| requirement.matches(version))
+        .max_by(|(a, _), (b, _)| a.cmp(b))
+        .map(|(_, raw)| raw)
+}
+
+/// Exact versions `carp install` resolved, so every checkout installs the same
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Lockfile {
+    pub version: u32,
+    #[serde(default, rename = "agent")]
+    pub agents: Vec<LockedAgent>,
+    /// Closures `carp pull --with-deps` resolved, as the registry signed them
+    #[serde(default, rename = "resolution", skip_serializing_if = "Vec::is_empty")]
+    pub resolutions: Vec<LockedResolution>,
+}
+
+/// A resolved agent and the checksum of the package that was installed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LockedAgent {
+    pub name: String,
+    pub version: String,
+    pub checksum: String,
+    /// Where agents from outside the registry came from, as
+    /// `git+<url>?<options>#<commit>`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+/// A signed resolution of `root` at `requirement`, kept byte for byte so its
+/// signature can be checked again later
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LockedResolution {
+    pub root: String,
+    pub requirement: String,
+    pub manifest: String,
+    pub signature: String,
+    pub public_key: String,
+}
+
+impl Default for Lockfile {
+    fn default() -> Self {
+        Self {
+            version: LOCKFILE_VERSION,
+            agents: Vec::new(),
+            resolutions: Vec::new(),
+        }
+    }
+}
+
+impl Lockfile {
+    /// Load the lockfile in `root`, or an empty one if there is none yet
+    pub fn load(root: &Path) -> CarpResult<Self> {
+        let path = root.join(LOCKFILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let contents = file_lock::read_to_string(&path).map_err(|e| {
+            CarpError::ManifestError(format!("Failed to read '{}': {e}", path.display()))
+        })?;
+        Self::parse(&path, &contents)
+    }
+
+    /// Change the lockfile in `root`, holding its lock from reading it to
+    /// writing it back so concurrent carp invocations don't lose each other's
+    /// changes
+    pub fn update<T>(root: &Path, change: impl FnOnce(&mut Self) -> T) -> CarpResult<T> {
+        let path = root.join(LOCKFILE);
+        let mut file = LockedFile::open(&path).map_err(|e| {
+            CarpError::ManifestError(format!("Failed to open '{}': {e}", path.display()))
+        })?;
+        let contents = file.read_to_string().map_err(|e| {
+            CarpError::ManifestError(format!("Failed to read '{}': {e}", path.display()))
+        })?;
+        // Empty when the lock just created it
+        let mut lockfile = if contents.trim().is_empty() {
+            Self::default()
+        } else {
+            Self::parse(&path, &contents)?
+        };
+
+        let result = change(&mut lockfile);
+        file.replace(lockfile.to_toml()?)?;
+        Ok(result)
+    }
+
+    fn parse(path: &Path, contents: &str) -> CarpResult<Self> {
+        let lockfile: Lockfile = toml::from_str(contents).map_err(|e| {
+            CarpError::ManifestError(format!("Failed to parse '{}': {e}", path.display()))
+        })?;
+
+        if lockfile.version != LOCKFILE_VERSION {
+            return Err(CarpError::ManifestError(format!(
+                "'{}' has version {}, but this carp only reads version {LOCKFILE_VERSION}",
+                path.display(),
+                lockfile.version
+            )));
+        }
+        Ok(lockfile)
+    }
+
+    /// The lockfile as written, agents sorted by name
+    fn to_toml(&self) -> CarpResult<String> {
+        let mut sorted = self.clone();
+        sorted.agents.sort_by(|a, b| a.name.cmp(&b.name));
+        sorted.resolutions.sort_by(|a, b| a.root.cmp(&b.root));
+
+        let contents = toml::to_string(&sorted)
+            .map_err(|e| CarpError::ManifestError(format!("Failed to serialize lockfile: {e}")))?;
+        Ok(format!("{LOCKFILE_HEADER}{contents}"))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&LockedAgent> {
+        self.agents.iter().find(|agent| agent.name == name)
+    }
+
+    /// Lock an agent, replacing any version it was locked at before
+    pub fn set(&mut self, locked: LockedAgent) {
+        self.remove(&locked.name);
+        self.agents.push(locked);
+    }
+
+    /// Unlock an agent, returning whether it was locked
+    pub fn remove(&mut self, name: &str) -> bool {
+        let before = self.agents.len();
+        self.agents.retain(|agent| agent.name != name);
+        self.agents.len() != before
+    }
+
+    /// Keep a resolution, replacing any earlier one of the same agent
+    pub fn set_resolution(&mut self, resolution: LockedResolution) {
+        self.resolutions
+            .retain(|locked| locked.root != resolution.root);
+        self.resolutions.push(resolution);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_manifest_parsing() {
+        let workspace: Workspace = toml::from_str(
+            r#"
+            [agents]
+            code-reviewer = "1.2"
+            test-writer = { version = "~0.3", dir = "tools/agents" }
+            triager = { git = "https://github.com/acme/agents", tag = "v2.0.0", subdir = "triager" }
+            draft = { path = "../agents/draft", dir = "local" }
+            "#,
+        )
+        .unwrap();
+        workspace.validate().unwrap();
+
+        let root = Path::new("/project");
+        let reviewer = &workspace.agents["code-reviewer"];
+        assert_eq!(reviewer.requirement(), Some("1.2"));
+        assert_eq!(
+            workspace.install_dir(root, "code-reviewer", reviewer),
+            Path::new("/project/.claude/agents/code-reviewer")
+        );
+        let writer = &workspace.agents["test-writer"];
+        assert_eq!(
+            workspace.install_dir(root, "test-writer", writer),
+            Path::new("/project/tools/agents/test-writer")
+        );
+        let draft = &workspace.agents["draft"];
+        assert_eq!(draft.path(), Some("../agents/draft"));
+        assert_eq!(
+            workspace.install_dir(root, "draft", draft),
+            Path::new("/project/local/draft")
+        );
+        let triager = &workspace.agents["triager"];
+        assert_eq!(triager.requirement(), None);
+        assert_eq!(
+            triager.git_source().unwrap().unwrap().to_string(),
+            "git+https://github.com/acme/agents?tag=v2.0.0&subdir=triager"
+        );
+
+        let invalid: Workspace = toml::from_str("[agents]\nbroken = \"one point two\"").unwrap();
+        assert!(invalid.validate().is_err());
+        let conflicting: Workspace = toml::from_str(
+            "[agents]\nbroken = { git = \"https://example.com/a\", tag = \"v1\", rev = \"abc\" }",
+        )
+        .unwrap();
+        assert!(conflicting.validate().is_err());
+    }
+
+    #[test]
+    fn test_resolve_picks_newest_match() {
+        let versions = [
+            "1.0.0",
+            "1.2.0",
+            "1.10.1",
+            "2.0.0",
+            "1.11.0-beta.1",
+            "latest",
+        ];
+        let newest = |req: &str| resolve(&VersionReq::parse(req).unwrap(), versions);
+
+        assert_eq!(newest("1"), Some("1.10.1"));
+        assert_eq!(newest("~1.2"), Some("1.2.0"));
+        assert_eq!(newest("*"), Some("2.0.0"));
+        assert_eq!(newest("3"), None);
+    }
+
+    #[test]
+    fn test_lockfile_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(Lockfile::load(dir.path()).unwrap(), Lockfile::default());
+
+        let lockfile = Lockfile {
+            version: LOCKFILE_VERSION,
+            agents: vec![
+                LockedAgent {
+                    name: "test-writer".to_string(),
+                    version: "0.3.1".to_string(),
+                    checksum: "sha256:bb".to_string(),
+                    source: None,
+                },
+                LockedAgent {
+                    name: "code-reviewer".to_string(),
+                    version: "1.2.0".to_string(),
+                    checksum: "sha256:aa".to_string(),
+                    source: Some(
+                        "git+https://github.com/acme/agents?tag=v1.2.0#0123abcd".to_string(),
+                    ),
+                },
+            ],
+            resolutions: vec![LockedResolution {
+                root: "code-reviewer".to_string(),
+                requirement: "^1.2".to_string(),
+                manifest: "{\"root\":\"code-reviewer\"}".to_string(),
+                signature: "c2ln".to_string(),
+                public_key: "a2V5".to_string(),
+            }],
+        };
+        Lockfile::update(dir.path(), |current| *current = lockfile.clone()).unwrap();
+
+        let contents = fs::read_to_string(dir.path().join(LOCKFILE)).unwrap();
+        assert!(contents.starts_with(LOCKFILE_HEADER));
+        let loaded = Lockfile::load(dir.path()).unwrap();
+        assert_eq!(loaded.agents[0].name, "code-reviewer");
+        assert_eq!(loaded.get("test-writer"), lockfile.get("test-writer"));
+        assert_eq!(loaded.get("code-reviewer"), lockfile.get("code-reviewer"));
+        assert_eq!(loaded.resolutions, lockfile.resolutions);
+
+        // Updates start from what's on disk, not from an earlier load
+        Lockfile::update(dir.path(), |current| current.remove("test-writer")).unwrap();
+        let loaded = Lockfile::load(dir.path()).unwrap();
+        assert_eq!(loaded.agents.len(), 1);
+        assert_eq!(loaded.resolutions, lockfile.resolutions);
+    }
+
+    #[test]
+    fn test_manifest_editor_keeps_comments() {
+        let dir = tempfile::tempdir().unwrap();
+        let original = "# Agents for the review bot\n[install]\ndir = \"agents\"\n\n[agents]\ncode-reviewer = \"1.2\" # pinned for the bot\n";
+        fs::write(dir.path().join(MANIFEST_FILE), original).unwrap();
+
+        let mut editor = ManifestEditor::open(dir.path()).unwrap();
+        editor
+            .set_agent(
+                "test-writer",
+                &Dependency::Detailed {
+                    version: "0.3.1".to_string(),
+                    dir: Some("tools".to_string()),
+                },
+            )
+            .unwrap();
+        assert!(!editor.remove_agent("not-listed").unwrap());
+        let values = BTreeMap::from([("TEAM".to_string(), "payments".to_string())]);
+        editor.set_variables("test-writer", &values).unwrap();
+        editor.save().unwrap();
+
+        let contents = fs::read_to_string(dir.path().join(MANIFEST_FILE)).unwrap();
+        assert!(contents.starts_with(original), "{contents}");
+        let workspace = Workspace::load(dir.path()).unwrap();
+        assert_eq!(workspace.agents["test-writer"].dir(), Some("tools"));
+        assert_eq!(workspace.variables["test-writer"], values);
+
+        let mut editor = ManifestEditor::open(dir.path()).unwrap();
+        assert!(editor.remove_agent("code-reviewer").unwrap());
+        editor.save().unwrap();
+        let workspace = Workspace::load(dir.path()).unwrap();
+        assert_eq!(workspace.install.dir, "agents");
+        assert_eq!(workspace.agents.len(), 1);
+
+        let mut editor = ManifestEditor::open(dir.path()).unwrap();
+        assert!(editor.remove_agent("test-writer").unwrap());
+        editor.save().unwrap();
+        let workspace = Workspace::load(dir.path()).unwrap();
+        assert!(!workspace.variables.contains_key("test-writer"));
+    }
+
+    #[test]
+    fn test_manifest_editor_creates_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut editor = ManifestEditor::open(dir.path()).unwrap();
+        editor
+            .set_agent("code-reviewer", &Dependency::Version("1.2.0".to_string()))
+            .unwrap();
+        editor.save().unwrap();
+
+        let workspace = Workspace::load(dir.path()).unwrap();
+        assert_eq!(
+            workspace.agents["code-reviewer"].requirement(),
+            Some("1.2.0")
+        );
+    }
+}
@@ -327,7 +327,7 @@ async fn test_cli_pull_command() {
         .mock("GET", "/download/test-agent-1.0.0.zip")
         .with_status(200)
         .with_header("content-type", "application/zip")
-        .with_body(test_zip_content.to_vec())
+        .with_body(test_zip_content)
         .create_async()
         .await;
 
@@ -375,7 +375,7 @@ async fn test_cli_pull_specific_version() {
         .mock("GET", "/download/test-agent-2.0.0.zip")
         .with_status(200)
         .with_header("content-type", "application/zip")
-        .with_body(test_zip_content.to_vec())
+        .with_body(test_zip_content)
         .create_async()
         .await;
 
@@ -472,7 +472,7 @@ async fn test_cli_new_with_custom_path() {
 // Test CLI publish command (without authentication)
 #[tokio::test]
 async fn test_cli_publish_no_auth() {
-    let mut ctx = e2e_utils::E2ETestContext::new().await;
+    let ctx = e2e_utils::E2ETestContext::new().await;
 
     // Create test agent files
     ctx.create_test_manifest("test-publish-agent", "1.0.0");
@@ -531,7 +531,7 @@ verify_ssl = false
     let cli_binary_path = e2e_utils::E2ETestContext::find_cli_binary();
 
     let output = Command::new(&cli_binary_path)
-        .args(&["search", "test"])
+        .args(["search", "test"])
         .env("CARP_CONFIG", &config_path)
         .output()
         .expect("Failed to execute CLI command");
@@ -600,7 +600,7 @@ async fn test_cli_verbose_output() {
     // Verbose mode should show additional information
     // This depends on how verbose logging is implemented
     assert!(
-        stderr.contains("debug") || stderr.contains("verbose") || stderr.len() > 0,
+        stderr.contains("debug") || stderr.contains("verbose") || !stderr.is_empty(),
         "Verbose mode should provide additional output"
     );
 }
@@ -1,5 +1,6 @@
 use carp_cli::api::{ApiClient, UploadAgentRequest};
-use carp_cli::config::{Config, RetrySettings, SecuritySettings};
+use carp_cli::config::settings::RetrySettings;
+use carp_cli::config::{Config, SecuritySettings};
 use carp_cli::utils::error::CarpResult;
 use std::env;
 use tokio::time::{timeout, Duration};
@@ -45,7 +46,10 @@ fn create_test_config() -> Config {
             max_publish_size: 5 * 1024 * 1024,   // 5MB for tests
             allow_http: false,
             token_warning_hours: 1,
+            ..SecuritySettings::default()
         },
+        telemetry: false,
+        policy: None,
     }
 }
 
@@ -54,7 +58,7 @@ async fn test_health_check() -> CarpResult<()> {
     let config = create_test_config();
     let client = ApiClient::new(&config)?;
 
-    let result = timeout(Duration::from_secs(10), client.health_check()).await;
+    let result = timeout(Duration::from_secs(10), client.health_check(false)).await;
 
     match result {
         Ok(Ok(response)) => {
@@ -237,7 +241,7 @@ async fn test_concurrent_requests() -> CarpResult<()> {
     let futures = (0..3).map(|i| {
         let client = ApiClient::new(&config).unwrap();
         async move {
-            let result = client.health_check().await;
+            let result = client.health_check(false).await;
             println!("Concurrent request {} completed", i);
             result
         }
@@ -265,7 +269,7 @@ async fn test_retry_mechanism() -> CarpResult<()> {
     let client = ApiClient::new(&config)?;
 
     let start = std::time::Instant::now();
-    let result = client.health_check().await;
+    let result = client.health_check(false).await;
     let duration = start.elapsed();
 
     // Should fail after retries
@@ -310,7 +314,7 @@ async fn test_performance_benchmarks() -> CarpResult<()> {
 
     // Benchmark health check
     let start = std::time::Instant::now();
-    let result = client.health_check().await;
+    let result = client.health_check(false).await;
     let health_duration = start.elapsed();
 
     if result.is_ok() {
@@ -418,6 +422,12 @@ This agent is only used for testing purposes.
         homepage: Some("https://example.com/integration-test-agent".to_string()),
         repository: Some("https://github.com/test/integration-test-agent".to_string()),
         license: Some("MIT".to_string()),
+        signature_bundle: None,
+        provenance: None,
+        private: false,
+        template: false,
+        allowed_secrets: Vec::new(),
+        dependencies: Default::default(),
     }
 }
 
@@ -1,7 +1,8 @@
 /// Performance and load testing for the Carp CLI
 /// Tests response times, throughput, and resource usage
 use carp_cli::api::ApiClient;
-use carp_cli::config::{Config, RetrySettings, SecuritySettings};
+use carp_cli::config::settings::RetrySettings;
+use carp_cli::config::{Config, SecuritySettings};
 use carp_cli::utils::error::CarpResult;
 use std::env;
 use std::sync::Arc;
@@ -51,7 +52,10 @@ fn create_performance_config() -> Config {
             max_publish_size: 50 * 1024 * 1024,   // 50MB
             allow_http: false,
             token_warning_hours: 24,
+            ..SecuritySettings::default()
         },
+        telemetry: false,
+        policy: None,
     }
 }
 
@@ -67,6 +71,12 @@ pub struct PerformanceMetrics {
     pub response_times: Vec<Duration>,
 }
 
+impl Default for PerformanceMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl PerformanceMetrics {
     pub fn new() -> Self {
         Self {
@@ -163,7 +173,7 @@ async fn test_health_check_performance() -> CarpResult<()> {
     // Perform multiple health checks to measure performance
     for i in 0..10 {
         let start = Instant::now();
-        let result = timeout(Duration::from_secs(10), client.health_check()).await;
+        let result = timeout(Duration::from_secs(10), client.health_check(false)).await;
         let duration = start.elapsed();
 
         let success = match result {
@@ -285,7 +295,7 @@ async fn test_concurrent_performance() -> CarpResult<()> {
 
         let future = async move {
             let request_start = Instant::now();
-            let result = client.health_check().await;
+            let result = client.health_check(false).await;
             let duration = request_start.elapsed();
 
             let success = result.is_ok();
@@ -346,7 +356,7 @@ async fn test_sustained_load_performance() -> CarpResult<()> {
 
     while start_time.elapsed() < test_duration {
         let request_start = Instant::now();
-        let result = timeout(Duration::from_secs(10), client.health_check()).await;
+        let result = timeout(Duration::from_secs(10), client.health_check(false)).await;
         let duration = request_start.elapsed();
 
         let success = matches!(result, Ok(Ok(_)));
@@ -443,7 +453,7 @@ async fn test_retry_performance() -> CarpResult<()> {
     // Test retry behavior with failing endpoint
     for i in 0..5 {
         let start = Instant::now();
-        let result = client.health_check().await;
+        let result = client.health_check(false).await;
         let duration = start.elapsed();
 
         // Should fail but with proper retry timing
@@ -1,7 +1,8 @@
 /// Regression tests for the Carp CLI
 /// Tests for previously identified bugs and edge cases to prevent regressions
 use carp_cli::api::ApiClient;
-use carp_cli::config::{Config, RetrySettings, SecuritySettings};
+use carp_cli::config::settings::RetrySettings;
+use carp_cli::config::{Config, SecuritySettings};
 use carp_cli::utils::error::{CarpError, CarpResult};
 use std::env;
 use tokio::time::{timeout, Duration};
@@ -47,7 +48,10 @@ fn create_regression_test_config() -> Config {
             max_publish_size: 50 * 1024 * 1024,   // 50MB
             allow_http: false,
             token_warning_hours: 24,
+            ..SecuritySettings::default()
         },
+        telemetry: false,
+        policy: None,
     }
 }
 
@@ -227,7 +231,7 @@ async fn test_regression_timeout_handling() -> CarpResult<()> {
     let client = ApiClient::new(&config)?;
 
     let start = std::time::Instant::now();
-    let result = client.health_check().await;
+    let result = client.health_check(false).await;
     let duration = start.elapsed();
 
     // Should fail relatively quickly due to timeout
@@ -261,7 +265,7 @@ async fn test_regression_retry_loop_prevention() -> CarpResult<()> {
     let client = ApiClient::new(&config)?;
 
     let start = std::time::Instant::now();
-    let result = client.health_check().await;
+    let result = client.health_check(false).await;
     let duration = start.elapsed();
 
     // Should fail after retries but not hang indefinitely
@@ -293,7 +297,7 @@ async fn test_regression_malformed_json_handling() -> CarpResult<()> {
 
     let client = ApiClient::new(&config)?;
 
-    let result = client.health_check().await;
+    let result = client.health_check(false).await;
 
     // Should handle malformed JSON gracefully
     match result {
@@ -550,13 +554,13 @@ async fn test_regression_client_state_consistency() -> CarpResult<()> {
 
     // Perform various operations that previously could corrupt state
     let _ = client.search("test1", Some(5), false).await;
-    let _ = client.health_check().await;
+    let _ = client.health_check(false).await;
     let _ = client.search("test2", Some(3), true).await;
     let _ = client.get_agent_download("test-agent", None).await;
     let _ = client.search("test3", Some(1), false).await;
 
     // Client should still work after multiple operations
-    let final_result = client.health_check().await;
+    let final_result = client.health_check(false).await;
 
     match final_result {
         Ok(_) => println!("✓ Client state remained consistent"),
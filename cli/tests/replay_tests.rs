@@ -0,0 +1,197 @@
+//! API client tests replayed from `--record` transcripts
+//!
+//! Each file in `tests/transcripts/` is a transcript a user attached to a bug
+//! report, or one recorded while fixing it. `Replay` serves its responses
+//! from a local mock registry in the order they were recorded, so a test
+//! only has to make the same calls and check what the client made of them.
+//!
+//! Requests are matched by method, path and query; sanitized (`***`) query
+//! parameters match anything. Exchanges that failed before a response
+//! arrived are skipped, since a mock can't reproduce a dropped connection.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use carp_cli::api::transcript::{Transcript, FORMAT_VERSION, REDACTED};
+use carp_cli::api::ApiClient;
+use carp_cli::config::settings::RetrySettings;
+use carp_cli::config::{Config, SecuritySettings};
+use carp_cli::CarpError;
+use mockito::{Matcher, Mock, Server, ServerGuard};
+use reqwest::Url;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Placeholder for the API key of a transcript whose requests carried one
+const REPLAY_API_KEY: &str = "carp_replay00_replay00_replay00";
+
+/// Response headers the mock server sets itself
+const SKIPPED_HEADERS: &[&str] = &[
+    "content-length",
+    "content-encoding",
+    "transfer-encoding",
+    "connection",
+    "date",
+];
+
+fn transcripts_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/transcripts")
+}
+
+fn load(path: &Path) -> Transcript {
+    let contents = fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("Couldn't read {}: {e}", path.display()));
+    serde_json::from_str(&contents)
+        .unwrap_or_else(|e| panic!("{} is not a transcript: {e}", path.display()))
+}
+
+/// A mock registry answering with a transcript's recorded responses
+struct Replay {
+    server: ServerGuard,
+    transcript: Transcript,
+    /// The mock of each replayed exchange, with its index in the transcript
+    mocks: Vec<(usize, Mock)>,
+}
+
+impl Replay {
+    /// Serve `tests/transcripts/<name>.json`
+    async fn start(name: &str) -> Self {
+        let transcript = load(&transcripts_dir().join(format!("{name}.json")));
+        let mut server = Server::new_async().await;
+
+        // Links in bodies to any host the client talked to lead back here
+        let mut origins: Vec<String> = transcript
+            .exchanges
+            .iter()
+            .filter_map(|exchange| Url::parse(&exchange.request.url).ok())
+            .map(|url| url.origin().ascii_serialization())
+            .collect();
+        origins.sort();
+        origins.dedup();
+
+        let mut mocks = Vec::new();
+        for (index, exchange) in transcript.exchanges.iter().enumerate() {
+            let Some(response) = &exchange.response else {
+                continue;
+            };
+            let url = Url::parse(&exchange.request.url).expect("recorded URL");
+
+            let mut mock = server
+                .mock(exchange.request.method.as_str(), url.path())
+                .match_query(query_matcher(&url))
+                .with_status(response.status.into())
+                .expect(1);
+            for (name, value) in &response.headers {
+                if value != REDACTED && !SKIPPED_HEADERS.contains(&name.as_str()) {
+                    mock = mock.with_header(name, value);
+                }
+            }
+            if let Some(body) = &response.body {
+                let bytes = match (&body.text, &body.base64) {
+                    (Some(text), _) => origins
+                        .iter()
+                        .fold(text.clone(), |text, origin| {
+                            text.replace(origin, &server.url())
+                        })
+                        .into_bytes(),
+                    (None, Some(base64)) => BASE64.decode(base64).expect("recorded base64 body"),
+                    (None, None) => Vec::new(),
+                };
+                mock = mock.with_body(bytes);
+            }
+            mocks.push((index, mock.create_async().await));
+        }
+
+        Self {
+            server,
+            transcript,
+            mocks,
+        }
+    }
+
+    /// A client for the mock registry, holding a placeholder API key when
+    /// the recorded requests were authenticated
+    fn client(&self) -> ApiClient {
+        let authenticated = self
+            .transcript
+            .exchanges
+            .iter()
+            .any(|exchange| exchange.request.headers.contains_key("authorization"));
+        let config = Config {
+            registry_url: self.server.url(),
+            api_key: authenticated.then(|| REPLAY_API_KEY.to_string()),
+            // Replays needn't wait out the recorded backoff
+            retry: RetrySettings {
+                initial_delay_ms: 1,
+                max_delay_ms: 10,
+                ..RetrySettings::default()
+            },
+            security: SecuritySettings {
+                allow_http: true,
+                ..SecuritySettings::default()
+            },
+            ..Config::default()
+        };
+        ApiClient::new(&config).unwrap()
+    }
+
+    /// Check the client made every recorded request
+    async fn verify(&self) {
+        for (index, mock) in &self.mocks {
+            let request = &self.transcript.exchanges[*index].request;
+            assert!(
+                mock.matched_async().await,
+                "exchange {index} ({} {}) was never requested",
+                request.method,
+                request.url
+            );
+        }
+    }
+}
+
+/// Match the recorded query parameters, any value for sanitized ones
+fn query_matcher(url: &Url) -> Matcher {
+    let pairs: Vec<Matcher> = url
+        .query_pairs()
+        .filter(|(_, value)| value != REDACTED)
+        .map(|(name, value)| Matcher::UrlEncoded(name.into_owned(), value.into_owned()))
+        .collect();
+    if pairs.is_empty() {
+        Matcher::Any
+    } else {
+        Matcher::AllOf(pairs)
+    }
+}
+
+#[test]
+fn test_transcripts_load() {
+    for entry in fs::read_dir(transcripts_dir()).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            let transcript = load(&path);
+            assert_eq!(transcript.version, FORMAT_VERSION, "{}", path.display());
+            assert!(!transcript.exchanges.is_empty(), "{}", path.display());
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_search_retried_after_gateway_timeout() {
+    let replay = Replay::start("search_retried_after_gateway_timeout").await;
+
+    let results = replay
+        .client()
+        .search("notes", Some(5), false)
+        .await
+        .unwrap();
+    assert_eq!(results.total, 1);
+    assert_eq!(results.agents[0].name, "meeting-notes");
+    replay.verify().await;
+}
+
+#[tokio::test]
+async fn test_list_mine_with_expired_key() {
+    let replay = Replay::start("list_mine_with_expired_key").await;
+
+    let error = replay.client().mine(None).await.unwrap_err();
+    assert!(matches!(error, CarpError::ApiKeyExpired(_)), "{error:?}");
+    replay.verify().await;
+}
@@ -1,7 +1,8 @@
 /// Security-focused tests for the Carp CLI
 /// Tests input validation, authentication, and security features
 use carp_cli::api::ApiClient;
-use carp_cli::config::{Config, RetrySettings, SecuritySettings};
+use carp_cli::config::settings::RetrySettings;
+use carp_cli::config::{Config, SecuritySettings};
 use carp_cli::utils::error::{CarpError, CarpResult};
 use std::env;
 use tokio::time::{timeout, Duration};
@@ -47,7 +48,10 @@ fn create_security_test_config() -> Config {
             max_publish_size: 512 * 1024,   // 512KB limit for security tests
             allow_http: false,              // Always enforce HTTPS
             token_warning_hours: 1,
+            ..SecuritySettings::default()
         },
+        telemetry: false,
+        policy: None,
     }
 }
 
@@ -302,7 +306,7 @@ async fn test_concurrent_request_security() -> CarpResult<()> {
     for i in 0..20 {
         let client_clone = ApiClient::new(&config)?;
         let future = async move {
-            let result = client_clone.health_check().await;
+            let result = client_clone.health_check(false).await;
             println!("Request {} result: {:?}", i, result.is_ok());
             result
         };
@@ -406,7 +410,7 @@ async fn test_timeout_enforcement() -> CarpResult<()> {
 
     // This might timeout or succeed quickly depending on network conditions
     let start = std::time::Instant::now();
-    let result = timeout(Duration::from_secs(5), client.health_check()).await;
+    let result = timeout(Duration::from_secs(5), client.health_check(false)).await;
     let duration = start.elapsed();
 
     match result {
@@ -437,7 +441,7 @@ async fn test_ssl_security() -> CarpResult<()> {
     config.registry_url = "https://self-signed.badssl.com".to_string(); // Known bad cert
 
     let client = ApiClient::new(&config)?;
-    let result = client.health_check().await;
+    let result = client.health_check(false).await;
 
     // Should fail due to SSL verification
     match result {
@@ -0,0 +1,188 @@
+//! Agent package archive formats
+//!
+//! Packages may be published as zip, gzip-compressed tar or zstd-compressed
+//! tar. The declared content type is never trusted on its own; it must agree
+//! with the archive's magic bytes.
+
+use anyhow::Result;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::{Cursor, Write};
+
+/// Archive formats accepted for agent packages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PackageFormat {
+    Zip,
+    TarGz,
+    TarZst,
+}
+
+impl PackageFormat {
+    /// Canonical MIME type stored alongside the package
+    pub fn content_type(self) -> &'static str {
+        match self {
+            PackageFormat::Zip => "application/zip",
+            PackageFormat::TarGz => "application/gzip",
+            PackageFormat::TarZst => "application/zstd",
+        }
+    }
+
+    /// File extension packages of this format are stored with
+    pub fn extension(self) -> &'static str {
+        match self {
+            PackageFormat::Zip => "zip",
+            PackageFormat::TarGz => "tar.gz",
+            PackageFormat::TarZst => "tar.zst",
+        }
+    }
+
+    /// Map a declared MIME type (including common aliases) to a format
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        let mime = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+
+        match mime.as_str() {
+            "application/zip" | "application/x-zip-compressed" => Some(PackageFormat::Zip),
+            "application/gzip" | "application/x-gzip" | "application/tar+gzip" => {
+                Some(PackageFormat::TarGz)
+            }
+            "application/zstd" | "application/x-zstd" | "application/tar+zstd" => {
+                Some(PackageFormat::TarZst)
+            }
+            _ => None,
+        }
+    }
+
+    /// Identify a format from the leading bytes of an archive
+    pub fn detect(data: &[u8]) -> Option<Self> {
+        if data.starts_with(b"PK\x03\x04") || data.starts_with(b"PK\x05\x06") {
+            Some(PackageFormat::Zip)
+        } else if data.starts_with(&[0x1f, 0x8b]) {
+            Some(PackageFormat::TarGz)
+        } else if data.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(PackageFormat::TarZst)
+        } else {
+            None
+        }
+    }
+}
+
+/// Check that a package's declared content type is allowed and matches its
+/// contents, returning the detected format
+pub fn validate_package(declared_content_type: &str, data: &[u8]) -> Result<PackageFormat, String> {
+    let declared = PackageFormat::from_content_type(declared_content_type).ok_or_else(|| {
+        format!(
+            "Unsupported package content type '{declared_content_type}'. Use application/zip, application/gzip or application/zstd"
+        )
+    })?;
+
+    match PackageFormat::detect(data) {
+        Some(detected) if detected == declared => Ok(detected),
+        Some(detected) => Err(format!(
+            "Package was declared as {} but its contents are {}",
+            declared.content_type(),
+            detected.content_type()
+        )),
+        None => Err("Package is not a zip, tar.gz or tar.zst archive".to_string()),
+    }
+}
+
+/// Checksum recorded for a package, as `sha256:<hex digest>`
+pub fn package_checksum(data: &[u8]) -> String {
+    let digest: String = Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    format!("sha256:{digest}")
+}
+
+/// Permissions recorded for every packaged file, whatever the source had
+pub const PACKAGE_FILE_MODE: u32 = 0o644;
+
+/// Zip holding a single-file agent as `{name}.md`, the layout `carp pull`
+/// installs from
+///
+/// The bytes depend only on the inputs: the one entry is stored uncompressed
+/// with [`PACKAGE_FILE_MODE`] and the earliest time a zip can record
+/// (1980-01-01), so the same agent always has the same checksum. `carp
+/// package` builds the same bytes on the client.
+pub fn build_markdown_package(name: &str, markdown: &str) -> Result<Bytes> {
+    let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    let options = zip::write::FileOptions::default()
+        .compression_method(zip::CompressionMethod::Stored)
+        .last_modified_time(zip::DateTime::default())
+        .unix_permissions(PACKAGE_FILE_MODE);
+
+    zip.start_file(format!("{name}.md"), options)?;
+    zip.write_all(markdown.as_bytes())?;
+    let archive = zip.finish()?;
+
+    Ok(Bytes::from(archive.into_inner()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_content_type() {
+        assert_eq!(
+            PackageFormat::from_content_type("application/x-gzip"),
+            Some(PackageFormat::TarGz)
+        );
+        assert_eq!(
+            PackageFormat::from_content_type("Application/Zstd; charset=binary"),
+            Some(PackageFormat::TarZst)
+        );
+        assert_eq!(PackageFormat::from_content_type("text/plain"), None);
+    }
+
+    #[test]
+    fn test_validate_package() {
+        assert_eq!(
+            validate_package("application/zip", b"PK\x03\x04rest"),
+            Ok(PackageFormat::Zip)
+        );
+        assert_eq!(
+            validate_package("application/zstd", &[0x28, 0xb5, 0x2f, 0xfd, 0x00]),
+            Ok(PackageFormat::TarZst)
+        );
+        assert!(validate_package("application/zip", &[0x1f, 0x8b, 0x08]).is_err());
+        assert!(validate_package("application/gzip", b"#!/bin/sh").is_err());
+        assert!(validate_package("text/plain", b"PK\x03\x04").is_err());
+    }
+
+    #[test]
+    fn test_markdown_package_is_reproducible() {
+        let package = build_markdown_package("code-reviewer", "# Code Reviewer\n").unwrap();
+        assert_eq!(
+            package,
+            build_markdown_package("code-reviewer", "# Code Reviewer\n").unwrap()
+        );
+        assert_eq!(PackageFormat::detect(&package), Some(PackageFormat::Zip));
+
+        let mut archive = zip::ZipArchive::new(Cursor::new(package.to_vec())).unwrap();
+        let mut contents = String::new();
+        std::io::Read::read_to_string(
+            &mut archive.by_name("code-reviewer.md").unwrap(),
+            &mut contents,
+        )
+        .unwrap();
+        assert_eq!(contents, "# Code Reviewer\n");
+
+        let entry = archive.by_index(0).unwrap();
+        assert_eq!(entry.unix_mode(), Some(0o100000 | PACKAGE_FILE_MODE));
+        assert_eq!(entry.compression(), zip::CompressionMethod::Stored);
+        let modified = entry.last_modified();
+        assert_eq!(
+            (modified.year(), modified.month(), modified.day()),
+            (1980, 1, 1)
+        );
+    }
+}
@@ -2,14 +2,25 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::env;
+use std::time::{Duration, Instant};
+use subtle::ConstantTimeEq;
 use uuid::Uuid;
 use vercel_runtime::Request;
 
+use crate::tenant;
+
 // Re-export common dependencies that auth clients need
 pub use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
 pub use reqwest;
 pub use sha2::{Digest, Sha256};
 
+/// Least time a rejected token takes to answer, whatever the reason, so
+/// unknown and revoked tokens can't be told apart by latency
+const AUTH_FAILURE_FLOOR: Duration = Duration::from_millis(250);
+
+/// Length of the non-secret key prefix stored with each API key
+const API_KEY_PREFIX_LEN: usize = 12;
+
 /// User context extracted from authenticated requests
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AuthenticatedUser {
@@ -67,12 +78,13 @@ pub struct AuthConfig {
 }
 
 impl AuthConfig {
-    /// Load configuration from environment variables
+    /// Load configuration from environment variables, with the current
+    /// tenant's JWT secret
     pub fn from_env() -> Self {
         Self {
             supabase_url: env::var("SUPABASE_URL").unwrap_or_default(),
             supabase_service_role_key: env::var("SUPABASE_SERVICE_ROLE_KEY").unwrap_or_default(),
-            supabase_jwt_secret: env::var("SUPABASE_JWT_SECRET").unwrap_or_default(),
+            supabase_jwt_secret: tenant::current().jwt_secret(),
             debug_mode: env::var("DEBUG_AUTH").unwrap_or_default() == "true",
         }
     }
@@ -147,43 +159,73 @@ pub async fn validate_jwt_token(
 
     let decoding_key = DecodingKey::from_secret(config.supabase_jwt_secret.as_bytes());
 
-    let token_data =
-        decode::<SupabaseJwtClaims>(token, &decoding_key, &validation).map_err(|e| {
+    let started = Instant::now();
+
+    // Every failure, expiry included, gets the same error, so a token's state
+    // can't be probed; the cause is only logged
+    let token_data = match decode::<SupabaseJwtClaims>(token, &decoding_key, &validation) {
+        Ok(token_data) => token_data,
+        Err(e) => {
             if config.debug_mode {
                 eprintln!("DEBUG: JWT validation failed: {e}");
             }
-            ApiError {
-                error: "invalid_jwt".to_string(),
-                message: format!("Invalid JWT token: {e}"),
-                details: Some(json!({
-                    "token_format_expected": "Valid Supabase JWT token",
-                    "common_causes": [
-                        "Token expired",
-                        "Invalid signature",
-                        "Wrong audience",
-                        "Malformed token structure"
-                    ]
-                })),
-            }
-        })?;
+            return Err(reject_after(started, invalid_jwt()).await);
+        }
+    };
 
     // Additional expiration check (belt and suspenders)
-    let now = Utc::now().timestamp();
-    if token_data.claims.exp < now {
-        return Err(ApiError {
-            error: "expired_jwt".to_string(),
-            message: "JWT token has expired".to_string(),
-            details: Some(json!({
-                "expired_at": token_data.claims.exp,
-                "current_time": now,
-                "expired_seconds_ago": now - token_data.claims.exp
-            })),
-        });
+    if token_data.claims.exp < Utc::now().timestamp() {
+        if config.debug_mode {
+            eprintln!("DEBUG: JWT validation failed: token expired");
+        }
+        return Err(reject_after(started, invalid_jwt()).await);
     }
 
     Ok(token_data.claims)
 }
 
+/// The error for any JWT that doesn't validate
+fn invalid_jwt() -> ApiError {
+    ApiError {
+        error: "invalid_jwt".to_string(),
+        message: "Invalid JWT token".to_string(),
+        details: Some(json!({
+            "token_format_expected": "Valid Supabase JWT token",
+            "common_causes": [
+                "Token expired",
+                "Invalid signature",
+                "Wrong audience",
+                "Malformed token structure"
+            ]
+        })),
+    }
+}
+
+/// The error for any API key that doesn't authenticate
+fn invalid_api_key() -> ApiError {
+    ApiError {
+        error: "invalid_api_key".to_string(),
+        message: "Invalid or revoked API key".to_string(),
+        details: None,
+    }
+}
+
+/// The error for an API key that matched but is past its expiry
+fn expired_api_key() -> ApiError {
+    ApiError {
+        error: "expired_api_key".to_string(),
+        message: "API key has expired".to_string(),
+        details: None,
+    }
+}
+
+/// Returns `error` once at least `AUTH_FAILURE_FLOOR` has passed since
+/// `started`, so a failure's latency doesn't tell why it failed
+async fn reject_after(started: Instant, error: ApiError) -> ApiError {
+    tokio::time::sleep(AUTH_FAILURE_FLOOR.saturating_sub(started.elapsed())).await;
+    error
+}
+
 /// Authenticate using JWT token (for frontend/web UI)
 pub async fn authenticate_jwt(
     token: &str,
@@ -209,6 +251,16 @@ pub async fn authenticate_jwt(
         .and_then(|username| username.as_str())
         .map(|s| s.to_string());
 
+    // Sessions issued after single sign-on name their provider, e.g. `sso:acme`
+    let provider = jwt_claims
+        .app_metadata
+        .as_ref()
+        .and_then(|meta| meta.get("provider"))
+        .and_then(|provider| provider.as_str())
+        .filter(|provider| provider.starts_with("sso:"))
+        .unwrap_or("supabase")
+        .to_string();
+
     // JWT tokens get specific scopes for frontend operations
     let scopes = vec![
         "read".to_string(),
@@ -218,9 +270,7 @@ pub async fn authenticate_jwt(
 
     Ok(AuthenticatedUser {
         user_id,
-        auth_method: AuthMethod::JwtToken {
-            provider: "supabase".to_string(),
-        },
+        auth_method: AuthMethod::JwtToken { provider },
         scopes,
         metadata: UserMetadata {
             email: jwt_claims.email,
@@ -263,12 +313,14 @@ pub async fn authenticate_api_key(
         });
     }
 
-    let client = reqwest::Client::new();
+    let started = Instant::now();
+    let client = tenant::http_client();
 
-    // Verify API key using the database function
+    // Look the key up by its non-secret prefix; the hashes are compared here
+    let prefix = api_key.chars().take(API_KEY_PREFIX_LEN).collect::<String>();
     let response = client
         .post(format!(
-            "{}/rest/v1/rpc/validate_api_key",
+            "{}/rest/v1/rpc/lookup_api_keys",
             config.supabase_url
         ))
         .header("apikey", &config.supabase_service_role_key)
@@ -277,7 +329,7 @@ pub async fn authenticate_api_key(
             format!("Bearer {}", config.supabase_service_role_key),
         )
         .header("Content-Type", "application/json")
-        .json(&json!({ "api_key_hash": key_hash }))
+        .json(&json!({ "p_prefix": prefix }))
         .send()
         .await
         .map_err(|e| ApiError {
@@ -287,79 +339,80 @@ pub async fn authenticate_api_key(
         })?;
 
     if !response.status().is_success() {
-        return Err(ApiError {
-            error: "invalid_api_key".to_string(),
-            message: "Invalid or expired API key".to_string(),
-            details: None,
-        });
+        return Err(reject_after(started, invalid_api_key()).await);
     }
 
-    let verification_result: serde_json::Value = response.json().await.map_err(|e| ApiError {
+    let candidates: Vec<ApiKeyCandidate> = response.json().await.map_err(|e| ApiError {
         error: "parse_error".to_string(),
         message: format!("Failed to parse verification response: {e}"),
         details: None,
     })?;
 
-    // Extract user info from verification result
-    if let Some(result) = verification_result.as_array().and_then(|arr| arr.first()) {
-        if let (Some(user_id), Some(key_id), Some(is_valid)) = (
-            result.get("user_id").and_then(|v| v.as_str()),
-            result.get("key_id").and_then(|v| v.as_str()),
-            result.get("is_valid").and_then(|v| v.as_bool()),
-        ) {
-            if is_valid {
-                let scopes = result
-                    .get("scopes")
-                    .and_then(|s| s.as_array())
-                    .map(|arr| {
-                        arr.iter()
-                            .filter_map(|v| v.as_str().map(|s| s.to_string()))
-                            .collect()
-                    })
-                    .unwrap_or_else(|| vec!["read".to_string()]);
-
-                // Extract additional metadata if available
-                let email = result
-                    .get("user_email")
-                    .and_then(|v| v.as_str())
-                    .map(|s| s.to_string());
-
-                let github_username = result
-                    .get("github_username")
-                    .and_then(|v| v.as_str())
-                    .map(|s| s.to_string());
-
-                return Ok(AuthenticatedUser {
-                    user_id: Uuid::parse_str(user_id).map_err(|_| ApiError {
-                        error: "invalid_user_id".to_string(),
-                        message: "Invalid user ID format".to_string(),
-                        details: None,
-                    })?,
-                    auth_method: AuthMethod::ApiKey {
-                        key_id: Uuid::parse_str(key_id).map_err(|_| ApiError {
-                            error: "invalid_key_id".to_string(),
-                            message: "Invalid key ID format".to_string(),
-                            details: None,
-                        })?,
-                    },
-                    scopes,
-                    metadata: UserMetadata {
-                        email,
-                        github_username,
-                        created_at: None, // Would be populated from database in production
-                    },
-                });
-            }
-        }
+    // Revoked keys fail the same way as unknown ones. Expiry is told apart,
+    // so the CLI can ask for a new key, but only once the hash has matched,
+    // which takes holding the key. It's checked first since the cleanup job
+    // also deactivates expired keys.
+    let Some(key) = find_api_key(&candidates, &key_hash) else {
+        return Err(reject_after(started, invalid_api_key()).await);
+    };
+    if key.is_expired {
+        return Err(reject_after(started, expired_api_key()).await);
+    }
+    if !key.is_active {
+        return Err(reject_after(started, invalid_api_key()).await);
     }
 
-    Err(ApiError {
-        error: "invalid_api_key".to_string(),
-        message: "Invalid or expired API key".to_string(),
-        details: None,
+    Ok(AuthenticatedUser {
+        user_id: key.user_id,
+        auth_method: AuthMethod::ApiKey { key_id: key.key_id },
+        scopes: key
+            .scopes
+            .clone()
+            .unwrap_or_else(|| vec!["read".to_string()]),
+        metadata: UserMetadata {
+            email: key.user_email.clone(),
+            github_username: key.github_username.clone(),
+            created_at: None, // Would be populated from database in production
+        },
     })
 }
 
+/// An API key sharing the presented key's prefix, from `lookup_api_keys`
+#[derive(Debug, Deserialize)]
+struct ApiKeyCandidate {
+    key_id: Uuid,
+    user_id: Uuid,
+    key_hash: String,
+    scopes: Option<Vec<String>>,
+    is_active: bool,
+    /// Past its `expires_at`, by the database's clock
+    is_expired: bool,
+    user_email: Option<String>,
+    github_username: Option<String>,
+}
+
+/// The candidate whose hash is `key_hash`. Every candidate is compared in
+/// constant time, and a placeholder when there are none, so the time taken
+/// doesn't depend on how much of a hash matched or whether the prefix did.
+fn find_api_key<'a>(
+    candidates: &'a [ApiKeyCandidate],
+    key_hash: &str,
+) -> Option<&'a ApiKeyCandidate> {
+    if candidates.is_empty() {
+        let placeholder = [b'0'; 64];
+        std::hint::black_box(placeholder.ct_eq(key_hash.as_bytes()));
+        return None;
+    }
+
+    let mut found = None;
+    for candidate in candidates {
+        if bool::from(candidate.key_hash.as_bytes().ct_eq(key_hash.as_bytes())) {
+            found = Some(candidate);
+        }
+    }
+    found
+}
+
 /// Ensure user exists in database (for JWT authentication)
 /// This synchronizes GitHub OAuth users with our profiles table
 pub async fn sync_jwt_user(user: &AuthenticatedUser, config: &AuthConfig) -> Result<(), ApiError> {
@@ -367,7 +420,7 @@ pub async fn sync_jwt_user(user: &AuthenticatedUser, config: &AuthConfig) -> Res
         return Ok(()); // Skip in development
     }
 
-    let client = reqwest::Client::new();
+    let client = tenant::http_client();
 
     // Use the fixed sync function to properly sync to profiles table
     let sync_params = json!({
@@ -420,7 +473,7 @@ pub async fn sync_api_key_user(
         return Ok(()); // Skip in development
     }
 
-    let client = reqwest::Client::new();
+    let client = tenant::http_client();
 
     // Use the API key sync function to ensure user profile exists
     let sync_params = json!({
@@ -461,6 +514,97 @@ pub async fn sync_api_key_user(
     Ok(())
 }
 
+/// Where an API key was used from and by what client
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyUsage {
+    pub ip: String,
+    pub user_agent: Option<String>,
+    /// ISO country code, from the platform's geolocation headers
+    pub country: Option<String>,
+    /// Autonomous system number, from an `x-client-asn` header a proxy in
+    /// front of the functions can set
+    pub asn: Option<String>,
+}
+
+impl KeyUsage {
+    /// Read a request's client address, user agent and location
+    pub fn from_request(req: &Request) -> Self {
+        let header = |name: &str| {
+            req.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(str::to_string)
+        };
+
+        Self {
+            ip: crate::rate_limit::client_ip(req),
+            user_agent: header("user-agent"),
+            // Cloudflare reports "XX" and Vercel omits the header when unknown
+            country: header("x-vercel-ip-country")
+                .or_else(|| header("cf-ipcountry"))
+                .filter(|country| country.len() == 2 && country != "XX"),
+            asn: header("x-client-asn")
+                .map(|asn| asn.trim_start_matches("AS").to_string())
+                .filter(|asn| !asn.is_empty() && asn.chars().all(|c| c.is_ascii_digit())),
+        }
+    }
+}
+
+/// Record that an API key was just used, updating its `last_used_*` columns
+/// and queueing an alert to its owner when it's used from a new country or
+/// network
+pub async fn record_api_key_use(
+    user: &AuthenticatedUser,
+    usage: &KeyUsage,
+    config: &AuthConfig,
+) -> Result<(), ApiError> {
+    let AuthMethod::ApiKey { key_id } = user.auth_method else {
+        return Ok(());
+    };
+    if config.is_development() {
+        return Ok(()); // Skip in development
+    }
+
+    let response = tenant::http_client()
+        .post(format!(
+            "{}/rest/v1/rpc/record_api_key_use",
+            config.supabase_url
+        ))
+        .header("apikey", &config.supabase_service_role_key)
+        .header(
+            "Authorization",
+            format!("Bearer {}", config.supabase_service_role_key),
+        )
+        .header("Content-Type", "application/json")
+        .json(&json!({
+            "p_key_id": key_id,
+            "p_ip": usage.ip,
+            "p_user_agent": usage.user_agent,
+            "p_country": usage.country,
+            "p_asn": usage.asn,
+        }))
+        .send()
+        .await
+        .map_err(|e| ApiError {
+            error: "database_error".to_string(),
+            message: format!("Failed to record API key use: {e}"),
+            details: None,
+        })?;
+
+    if !response.status().is_success() {
+        let error_text = response.text().await.unwrap_or_default();
+        return Err(ApiError {
+            error: "api_key_usage_failed".to_string(),
+            message: format!("Recording API key use failed: {error_text}"),
+            details: None,
+        });
+    }
+
+    Ok(())
+}
+
 /// Check if user has required scope
 pub fn check_scope(user: &AuthenticatedUser, required_scope: &str) -> bool {
     user.scopes.contains(&required_scope.to_string()) || user.scopes.contains(&"admin".to_string())
@@ -544,4 +688,143 @@ mod tests {
         assert!(check_scope(&admin_user, "write"));
         assert!(check_scope(&admin_user, "admin"));
     }
+
+    fn candidate(key: &str, is_active: bool, is_expired: bool) -> serde_json::Value {
+        json!({
+            "key_id": Uuid::new_v4(),
+            "user_id": Uuid::new_v4(),
+            "key_hash": hash_api_key(key),
+            "scopes": ["read"],
+            "is_active": is_active,
+            "is_expired": is_expired,
+            "user_email": null,
+            "github_username": "octocat"
+        })
+    }
+
+    #[test]
+    fn test_key_usage_reads_the_request() {
+        use reqwest::header::HeaderValue;
+        use vercel_runtime::Body;
+
+        let mut req = Request::new(Body::Empty);
+        assert_eq!(
+            KeyUsage::from_request(&req),
+            KeyUsage {
+                ip: "127.0.0.1".to_string(),
+                ..KeyUsage::default()
+            }
+        );
+
+        let headers = req.headers_mut();
+        headers.insert("x-forwarded-for", HeaderValue::from_static("203.0.113.7"));
+        headers.insert("user-agent", HeaderValue::from_static("carp-cli/0.3.0"));
+        headers.insert("cf-ipcountry", HeaderValue::from_static("DE"));
+        headers.insert("x-client-asn", HeaderValue::from_static("AS3320"));
+        assert_eq!(
+            KeyUsage::from_request(&req),
+            KeyUsage {
+                ip: "203.0.113.7".to_string(),
+                user_agent: Some("carp-cli/0.3.0".to_string()),
+                country: Some("DE".to_string()),
+                asn: Some("3320".to_string()),
+            }
+        );
+
+        // Unknown locations aren't recorded
+        let headers = req.headers_mut();
+        headers.insert("x-vercel-ip-country", HeaderValue::from_static("XX"));
+        headers.insert("x-client-asn", HeaderValue::from_static("unknown"));
+        let usage = KeyUsage::from_request(&req);
+        assert_eq!((usage.country, usage.asn), (None, None));
+    }
+
+    #[test]
+    fn test_api_keys_match_only_their_hash() {
+        let candidates: Vec<ApiKeyCandidate> = serde_json::from_value(json!([
+            candidate("carp_abc12345_first000_key00000", true, false),
+            candidate("carp_abc12345_second00_key00000", true, false),
+        ]))
+        .unwrap();
+
+        let found = find_api_key(
+            &candidates,
+            &hash_api_key("carp_abc12345_second00_key00000"),
+        )
+        .unwrap();
+        assert_eq!(found.key_id, candidates[1].key_id);
+        assert!(find_api_key(
+            &candidates,
+            &hash_api_key("carp_abc12345_other000_key00000")
+        )
+        .is_none());
+        assert!(find_api_key(&[], &hash_api_key("carp_abc12345_first000_key00000")).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_rejected_api_keys_are_indistinguishable() {
+        use wiremock::matchers::{body_json, method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        for (prefix, key) in [
+            (
+                "carp_goodkey",
+                candidate("carp_goodkey_key00000_key0000", true, false),
+            ),
+            (
+                "carp_revoked",
+                candidate("carp_revoked_key00000_key0000", false, false),
+            ),
+            // Deactivated by the cleanup job, but still reported as expired
+            (
+                "carp_expired",
+                candidate("carp_expired_key00000_key0000", false, true),
+            ),
+        ] {
+            Mock::given(method("POST"))
+                .and(path("/rest/v1/rpc/lookup_api_keys"))
+                .and(body_json(json!({ "p_prefix": prefix })))
+                .respond_with(ResponseTemplate::new(200).set_body_json(json!([key])))
+                .mount(&server)
+                .await;
+        }
+        Mock::given(method("POST"))
+            .and(path("/rest/v1/rpc/lookup_api_keys"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([])))
+            .mount(&server)
+            .await;
+
+        let config = AuthConfig {
+            supabase_url: server.uri(),
+            supabase_service_role_key: "key".to_string(),
+            supabase_jwt_secret: String::new(),
+            debug_mode: false,
+        };
+
+        let user = authenticate_api_key("carp_goodkey_key00000_key0000", &config)
+            .await
+            .unwrap();
+        assert_eq!(user.metadata.github_username.as_deref(), Some("octocat"));
+
+        for key in [
+            "carp_revoked_key00000_key0000",
+            "carp_goodkey_wrong000_key0000",
+            "carp_unknown0_key00000_key0000",
+            // Guessing at an expired key's prefix doesn't reveal its expiry
+            "carp_expired_wrong000_key0000",
+        ] {
+            let started = Instant::now();
+            let error = authenticate_api_key(key, &config).await.unwrap_err();
+            assert!(started.elapsed() >= AUTH_FAILURE_FLOOR);
+            assert_eq!(error.error, "invalid_api_key");
+            assert_eq!(error.message, "Invalid or revoked API key");
+            assert!(error.details.is_none());
+        }
+
+        let error = authenticate_api_key("carp_expired_key00000_key0000", &config)
+            .await
+            .unwrap_err();
+        assert_eq!(error.error, "expired_api_key");
+    }
 }
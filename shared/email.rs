@@ -0,0 +1,69 @@
+//! Email to users
+//!
+//! Mail is sent through an HTTP email API: Resend's, or any that accepts the
+//! same requests, at `EMAIL_API_URL`. Sending needs `EMAIL_API_KEY` and
+//! `EMAIL_FROM`; without them [`Mailer::from_env`] is `None` and nothing is
+//! sent.
+
+use anyhow::{bail, Context, Result};
+use serde_json::json;
+use std::env;
+
+/// Where mail is sent unless `EMAIL_API_URL` says otherwise
+const DEFAULT_API_URL: &str = "https://api.resend.com/emails";
+
+/// Sends plain text email
+#[derive(Debug, Clone)]
+pub struct Mailer {
+    client: reqwest::Client,
+    api_url: String,
+    api_key: String,
+    from: String,
+}
+
+impl Mailer {
+    /// A mailer from `EMAIL_API_KEY`, `EMAIL_FROM` and `EMAIL_API_URL`, or
+    /// `None` when email isn't configured
+    pub fn from_env() -> Option<Self> {
+        let api_key = env::var("EMAIL_API_KEY").ok().filter(|v| !v.is_empty())?;
+        let from = env::var("EMAIL_FROM").ok().filter(|v| !v.is_empty())?;
+        let api_url = env::var("EMAIL_API_URL")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .unwrap_or_else(|| DEFAULT_API_URL.to_string());
+        Some(Self::new(api_url, api_key, from))
+    }
+
+    pub fn new(api_url: String, api_key: String, from: String) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            api_url,
+            api_key,
+            from,
+        }
+    }
+
+    /// Send `text` to `to`
+    pub async fn send(&self, to: &str, subject: &str, text: &str) -> Result<()> {
+        let response = self
+            .client
+            .post(&self.api_url)
+            .bearer_auth(&self.api_key)
+            .json(&json!({
+                "from": self.from,
+                "to": [to],
+                "subject": subject,
+                "text": text,
+            }))
+            .send()
+            .await
+            .context("Failed to reach the email API")?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            bail!("The email API answered {status}: {body}");
+        }
+        Ok(())
+    }
+}
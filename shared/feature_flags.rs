@@ -0,0 +1,425 @@
+//! Feature flags for gradual rollout
+//!
+//! Publishing and reviews can be switched off, or rolled out to a share of
+//! callers, without a redeploy. A flag's state comes from, in order:
+//!
+//! - `FEATURE_<NAME>` in the API's environment: `on`, `off` or a percentage
+//!   such as `25%`
+//! - its row in the `feature_flags` table, set by an admin through
+//!   `PUT /api/v1/admin/flags`, optionally limited to some deployments by
+//!   their `VERCEL_ENV`
+//! - its default, which is on
+//!
+//! A percentage turns the feature on for callers whose user ID hashes below
+//! it, so each caller keeps their answer as the rollout grows. Handlers call
+//! [`ensure_enabled`] once they know who's calling. If the table can't be
+//! read, flags fall back to their defaults, like maintenance mode.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::env;
+use uuid::Uuid;
+use vercel_runtime::{Body, Response};
+
+use crate::auth::ApiError;
+use crate::registry::service_config;
+use crate::tenant;
+
+/// Deployment environment assumed outside Vercel
+const DEFAULT_ENVIRONMENT: &str = "development";
+
+/// A feature that can be switched at runtime
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flag {
+    /// Uploading and publishing agents
+    Publish,
+    /// Writing and deleting reviews
+    Reviews,
+}
+
+impl Flag {
+    pub const ALL: [Flag; 2] = [Flag::Publish, Flag::Reviews];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Flag::Publish => "publish",
+            Flag::Reviews => "reviews",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|flag| flag.name() == name)
+    }
+
+    /// What callers are told while the feature is off for them
+    fn disabled_message(self) -> &'static str {
+        match self {
+            Flag::Publish => "Publishing is not enabled on this registry yet",
+            Flag::Reviews => "Reviews are not enabled on this registry yet",
+        }
+    }
+
+    fn env_var(self) -> String {
+        format!("FEATURE_{}", self.name().to_ascii_uppercase())
+    }
+}
+
+/// Where a flag's state was set
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FlagSource {
+    Default,
+    Env,
+    Admin,
+}
+
+/// A flag and who it's on for
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FlagState {
+    pub name: String,
+    pub enabled: bool,
+    /// Share of callers the feature is on for, 0 to 100
+    pub rollout_percent: u8,
+    /// Deployments the feature is on in; empty for all of them
+    pub environments: Vec<String>,
+    pub source: FlagSource,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+impl FlagState {
+    /// The flag fully on, as it is until someone says otherwise
+    pub fn default_for(flag: Flag) -> Self {
+        Self {
+            name: flag.name().to_string(),
+            enabled: true,
+            rollout_percent: 100,
+            environments: Vec::new(),
+            source: FlagSource::Default,
+            updated_at: None,
+        }
+    }
+
+    /// The flag as `FEATURE_<NAME>` sets it, if it's set to something valid
+    pub fn from_env(flag: Flag) -> Option<Self> {
+        let value = env::var(flag.env_var()).ok()?;
+        let rollout_percent = parse_setting(&value)?;
+        Some(Self {
+            enabled: rollout_percent > 0,
+            rollout_percent,
+            source: FlagSource::Env,
+            ..Self::default_for(flag)
+        })
+    }
+
+    /// Whether the feature is on for `subject` in the `environment` deployment
+    pub fn enabled_for(&self, environment: &str, subject: &str) -> bool {
+        self.enabled
+            && (self.environments.is_empty()
+                || self.environments.iter().any(|env| env == environment))
+            && bucket(&self.name, subject) < self.rollout_percent
+    }
+}
+
+/// `on`, `off` or `25%` as a rollout percentage
+fn parse_setting(value: &str) -> Option<u8> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "1" | "true" | "on" => Some(100),
+        "0" | "false" | "off" => Some(0),
+        other => other
+            .strip_suffix('%')?
+            .trim()
+            .parse::<u8>()
+            .ok()
+            .filter(|percent| *percent <= 100),
+    }
+}
+
+/// Where `subject` falls in a rollout of `flag`, from 0 to 99
+///
+/// Hashing the flag's name in gives each flag its own order of callers.
+fn bucket(flag: &str, subject: &str) -> u8 {
+    let digest = Sha256::digest(format!("{flag}:{subject}"));
+    (u16::from_be_bytes([digest[0], digest[1]]) % 100) as u8
+}
+
+/// The deployment this function runs in: `production`, `preview` or
+/// `development`
+pub fn environment() -> String {
+    env::var("VERCEL_ENV")
+        .ok()
+        .filter(|env| !env.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_ENVIRONMENT.to_string())
+}
+
+/// Every flag's current state
+pub async fn current() -> Vec<FlagState> {
+    let stored = match service_config() {
+        Ok((supabase_url, supabase_key)) => FlagStore::new(supabase_url, supabase_key)
+            .get()
+            .await
+            .unwrap_or_else(|e| {
+                eprintln!("WARN: Couldn't read feature flags, using defaults: {e:#}");
+                Vec::new()
+            }),
+        Err(_) => Vec::new(),
+    };
+
+    Flag::ALL
+        .into_iter()
+        .map(|flag| {
+            FlagState::from_env(flag)
+                .or_else(|| {
+                    stored
+                        .iter()
+                        .find(|state| state.name == flag.name())
+                        .cloned()
+                })
+                .unwrap_or_else(|| FlagState::default_for(flag))
+        })
+        .collect()
+}
+
+/// Refuse a request with a 503 while `flag` is off for `subject`, usually
+/// the caller's user ID
+#[allow(clippy::result_large_err)]
+pub async fn ensure_enabled(flag: Flag, subject: &str) -> Result<(), Response<Body>> {
+    let state = match FlagState::from_env(flag) {
+        Some(state) => state,
+        None => current()
+            .await
+            .into_iter()
+            .find(|state| state.name == flag.name())
+            .unwrap_or_else(|| FlagState::default_for(flag)),
+    };
+    if state.enabled_for(&environment(), subject) {
+        return Ok(());
+    }
+    Err(disabled_response(flag))
+}
+
+/// The 503 answered to a feature that's off for the caller
+pub fn disabled_response(flag: Flag) -> Response<Body> {
+    let error = ApiError {
+        error: "feature_disabled".to_string(),
+        message: flag.disabled_message().to_string(),
+        details: Some(json!({ "flag": flag.name() })),
+    };
+    Response::builder()
+        .status(503)
+        .header("content-type", "application/json")
+        .body(
+            serde_json::to_string(&error)
+                .unwrap_or_else(|_| r#"{"error":"feature_disabled"}"#.to_string())
+                .into(),
+        )
+        .unwrap_or_else(|_| {
+            Response::builder()
+                .status(503)
+                .body("Service unavailable".into())
+                .unwrap()
+        })
+}
+
+#[derive(Debug, Deserialize)]
+struct FlagRow {
+    name: String,
+    enabled: bool,
+    rollout_percent: u8,
+    environments: Vec<String>,
+    updated_at: DateTime<Utc>,
+}
+
+impl From<FlagRow> for FlagState {
+    fn from(row: FlagRow) -> Self {
+        Self {
+            name: row.name,
+            enabled: row.enabled,
+            rollout_percent: row.rollout_percent,
+            environments: row.environments,
+            source: FlagSource::Admin,
+            updated_at: Some(row.updated_at),
+        }
+    }
+}
+
+/// The admin-set flags, through Supabase's REST API
+pub struct FlagStore {
+    client: reqwest::Client,
+    supabase_url: String,
+    supabase_key: String,
+}
+
+impl FlagStore {
+    pub fn new(supabase_url: String, supabase_key: String) -> Self {
+        Self {
+            client: tenant::http_client(),
+            supabase_url,
+            supabase_key,
+        }
+    }
+
+    /// The flags an admin has set
+    pub async fn get(&self) -> Result<Vec<FlagState>> {
+        let rows: Vec<FlagRow> = self
+            .request(self.client.get(format!(
+                "{}/rest/v1/feature_flags?select=name,enabled,rollout_percent,environments,updated_at",
+                self.supabase_url
+            )))
+            .await?;
+        Ok(rows.into_iter().map(FlagState::from).collect())
+    }
+
+    /// Set `flag` for the admin `admin_id`
+    pub async fn set(
+        &self,
+        flag: Flag,
+        enabled: bool,
+        rollout_percent: u8,
+        environments: &[String],
+        admin_id: Uuid,
+    ) -> Result<FlagState> {
+        let mut rows: Vec<FlagRow> = self
+            .request(
+                self.client
+                    .post(format!(
+                        "{}/rest/v1/feature_flags?on_conflict=name&select=name,enabled,rollout_percent,environments,updated_at",
+                        self.supabase_url
+                    ))
+                    .header("Prefer", "resolution=merge-duplicates,return=representation")
+                    .json(&json!({
+                        "name": flag.name(),
+                        "enabled": enabled,
+                        "rollout_percent": rollout_percent,
+                        "environments": environments,
+                        "updated_by": admin_id,
+                        "updated_at": Utc::now(),
+                    })),
+            )
+            .await?;
+        Ok(rows.pop().context("Feature flag was not saved")?.into())
+    }
+
+    async fn request<T: serde::de::DeserializeOwned>(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<T> {
+        let response = request
+            .header("apikey", &self.supabase_key)
+            .header("Authorization", format!("Bearer {}", self.supabase_key))
+            .send()
+            .await
+            .context("Database request failed")?;
+        let status = response.status();
+        let body = response.text().await?;
+        if !status.is_success() {
+            bail!("Database request failed with status {status}: {body}");
+        }
+        serde_json::from_str(&body).with_context(|| format!("Unexpected database response: {body}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{body_partial_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[test]
+    fn test_parse_setting() {
+        assert_eq!(parse_setting("on"), Some(100));
+        assert_eq!(parse_setting(" OFF "), Some(0));
+        assert_eq!(parse_setting("25%"), Some(25));
+        assert_eq!(parse_setting("101%"), None);
+        assert_eq!(parse_setting("25"), None);
+    }
+
+    #[test]
+    fn test_rollouts() {
+        let state = FlagState {
+            rollout_percent: 30,
+            environments: vec!["preview".to_string()],
+            ..FlagState::default_for(Flag::Publish)
+        };
+        let subjects: Vec<String> = (0..1000).map(|i| format!("user-{i}")).collect();
+
+        // Off outside the listed environments
+        assert!(!subjects
+            .iter()
+            .any(|subject| state.enabled_for("production", subject)));
+
+        // On for about the rollout's share of callers, the same ones each time
+        let enabled: Vec<_> = subjects
+            .iter()
+            .filter(|subject| state.enabled_for("preview", subject))
+            .collect();
+        assert!((250..350).contains(&enabled.len()), "{}", enabled.len());
+        let wider = FlagState {
+            rollout_percent: 60,
+            ..state.clone()
+        };
+        assert!(enabled
+            .iter()
+            .all(|subject| wider.enabled_for("preview", subject)));
+
+        let off = FlagState {
+            enabled: false,
+            ..FlagState::default_for(Flag::Publish)
+        };
+        assert!(!off.enabled_for("production", "user-1"));
+        assert!(FlagState::default_for(Flag::Reviews).enabled_for("production", "user-1"));
+    }
+
+    #[test]
+    fn test_disabled_response() {
+        let response = disabled_response(Flag::Publish);
+        assert_eq!(response.status(), 503);
+        let Body::Text(body) = response.body() else {
+            panic!("expected a JSON body");
+        };
+        let body: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(body["error"], "feature_disabled");
+        assert_eq!(body["details"]["flag"], "publish");
+    }
+
+    #[tokio::test]
+    async fn test_store_round_trip() {
+        let server = MockServer::start().await;
+        let admin_id = Uuid::new_v4();
+        let row = json!([{
+            "name": "publish",
+            "enabled": true,
+            "rollout_percent": 25,
+            "environments": ["preview"],
+            "updated_at": "2025-09-04T00:00:00Z",
+        }]);
+        Mock::given(method("POST"))
+            .and(path("/rest/v1/feature_flags"))
+            .and(body_partial_json(json!({
+                "name": "publish",
+                "rollout_percent": 25,
+                "environments": ["preview"],
+                "updated_by": admin_id,
+            })))
+            .respond_with(ResponseTemplate::new(201).set_body_json(&row))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/rest/v1/feature_flags"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(&row))
+            .mount(&server)
+            .await;
+
+        let store = FlagStore::new(server.uri(), "key".to_string());
+        let saved = store
+            .set(Flag::Publish, true, 25, &["preview".to_string()], admin_id)
+            .await
+            .unwrap();
+        assert_eq!(saved.source, FlagSource::Admin);
+        assert_eq!(saved.rollout_percent, 25);
+        assert_eq!(store.get().await.unwrap(), vec![saved]);
+    }
+}
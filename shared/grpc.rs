@@ -0,0 +1,442 @@
+//! gRPC registry service for internal consumers
+//!
+//! Built with the `grpc` feature and served by `carp-api grpc`. The service
+//! is defined in `proto/carp/registry/v1/registry.proto`; each call goes
+//! through [`crate::registry`], as the REST functions do, so both transports
+//! see the same agents.
+
+use std::net::SocketAddr;
+use std::pin::Pin;
+
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::Stream;
+use tonic::metadata::MetadataMap;
+use tonic::{Request, Response, Status};
+
+use crate::auth::{guess_token_type, TokenType};
+use crate::registry::download::{download_info, DownloadNotFound, DownloadRequest, Downloader};
+use crate::registry::info::get_agent_info;
+use crate::registry::metadata::{
+    fetch_metadata, update_metadata, validate_update, IfMatch, MetadataUpdate,
+};
+use crate::registry::search::{search_page, SearchError, SearchMode, SearchQuery, SearchSort};
+use crate::registry::{self, service_config};
+use crate::{authenticate_api_key, check_scope, AgentVisibility, AuthConfig, AuthenticatedUser};
+
+/// Code generated from `registry.proto`
+pub mod proto {
+    tonic::include_proto!("carp.registry.v1");
+}
+
+use proto::registry_server::{Registry, RegistryServer};
+
+/// Agents read per query while streaming search results
+const SEARCH_PAGE_SIZE: usize = 100;
+
+/// The `carp.registry.v1.Registry` service
+#[derive(Debug, Default)]
+pub struct RegistryService {
+    client: reqwest::Client,
+}
+
+/// Serve the registry service on `addr` until the process is stopped
+pub async fn serve(addr: SocketAddr) -> anyhow::Result<()> {
+    println!("gRPC registry on {addr}");
+
+    tonic::transport::Server::builder()
+        .add_service(RegistryServer::new(RegistryService::default()))
+        .serve(addr)
+        .await?;
+
+    Ok(())
+}
+
+#[tonic::async_trait]
+impl Registry for RegistryService {
+    type SearchStream = Pin<Box<dyn Stream<Item = Result<proto::Agent, Status>> + Send>>;
+
+    async fn search(
+        &self,
+        request: Request<proto::SearchRequest>,
+    ) -> Result<Response<Self::SearchStream>, Status> {
+        let visibility = visibility(caller(&request).await?.as_ref()).await?;
+        let request = request.into_inner();
+        let mode = search_mode(request.mode());
+        let sort = search_sort(request.sort());
+        let max_results = match request.max_results {
+            0 => usize::MAX,
+            max => max as usize,
+        };
+
+        // Pages are read one at a time as the client keeps up
+        let (tx, rx) = mpsc::channel(SEARCH_PAGE_SIZE);
+        tokio::spawn(async move {
+            let mut sent = 0;
+            for page in 1.. {
+                let query = SearchQuery {
+                    query: &request.query,
+                    mode,
+                    sort,
+                    limit: SEARCH_PAGE_SIZE,
+                    page,
+                    templates_only: false,
+                };
+                let agents = match search_page(&query, &visibility).await {
+                    Ok(agents) => agents,
+                    Err(e) => {
+                        let _ = tx.send(Err(search_status(e))).await;
+                        return;
+                    }
+                };
+
+                let last_page = agents.len() < SEARCH_PAGE_SIZE;
+                for agent in agents {
+                    if sent == max_results || tx.send(Ok(agent.into())).await.is_err() {
+                        return;
+                    }
+                    sent += 1;
+                }
+                if last_page {
+                    return;
+                }
+            }
+        });
+
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
+    }
+
+    async fn resolve(
+        &self,
+        request: Request<proto::ResolveRequest>,
+    ) -> Result<Response<proto::ResolveResponse>, Status> {
+        let visibility = visibility(caller(&request).await?.as_ref()).await?;
+        let request = request.into_inner();
+
+        let agent = get_agent_info(&request.name, &visibility)
+            .await
+            .map_err(Status::internal)?
+            .ok_or_else(|| Status::not_found(format!("Agent '{}' not found", request.name)))?;
+
+        let version = resolve_version(&agent.versions, &request.requirement)
+            .map_err(|e| {
+                Status::invalid_argument(format!(
+                    "Invalid version requirement '{}': {e}",
+                    request.requirement
+                ))
+            })?
+            .ok_or_else(|| {
+                Status::not_found(format!(
+                    "No version of '{}' matches '{}'",
+                    agent.name, request.requirement
+                ))
+            })?
+            .clone();
+
+        Ok(Response::new(proto::ResolveResponse {
+            name: agent.name,
+            version,
+            versions: agent.versions,
+        }))
+    }
+
+    async fn get_download_info(
+        &self,
+        request: Request<proto::GetDownloadInfoRequest>,
+    ) -> Result<Response<proto::DownloadInfo>, Status> {
+        let user = caller(&request).await?;
+        let user_agent = request
+            .metadata()
+            .get("user-agent")
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("")
+            .to_string();
+        let ip_addr = request.remote_addr().map(|addr| addr.ip().to_string());
+        let request = request.into_inner();
+        let version = if request.version.is_empty() {
+            "latest"
+        } else {
+            request.version.as_str()
+        };
+
+        let (supabase_url, supabase_key) = service_config().map_err(Status::internal)?;
+
+        // Only finds public agents and the caller's own private ones. gRPC
+        // callers reach storage directly, so packages aren't proxied.
+        let download = DownloadRequest {
+            name: &request.name,
+            version,
+            inline: false,
+            proxy_base: None,
+            prefetch: false,
+            downloader: Downloader {
+                user_id: user.as_ref().map(|user| user.user_id),
+                user_agent: &user_agent,
+                ip_addr,
+            },
+        };
+        let target = download_info(&self.client, &supabase_url, &supabase_key, &download)
+            .await
+            .map_err(|e| {
+                if e.is::<DownloadNotFound>() {
+                    Status::not_found(format!(
+                        "Agent '{}' version '{version}' not found: {e}",
+                        request.name
+                    ))
+                } else {
+                    Status::internal(e.to_string())
+                }
+            })?;
+
+        Ok(Response::new(proto::DownloadInfo {
+            name: target.name,
+            version: target.version,
+            author: target.author,
+            download_url: target.download_url,
+            file_size: target.file_size,
+            checksum: target.checksum,
+            content_type: target.content_type,
+        }))
+    }
+
+    async fn get_metadata(
+        &self,
+        request: Request<proto::GetMetadataRequest>,
+    ) -> Result<Response<proto::AgentMetadata>, Status> {
+        let user = require_scope(caller(&request).await?, "read")?;
+        let name = request.into_inner().name;
+
+        match fetch_metadata(&name, &user)
+            .await
+            .map_err(Status::internal)?
+        {
+            Some(metadata) => Ok(Response::new(metadata.into())),
+            None => Err(not_owned(&name)),
+        }
+    }
+
+    async fn update_metadata(
+        &self,
+        request: Request<proto::UpdateMetadataRequest>,
+    ) -> Result<Response<proto::AgentMetadata>, Status> {
+        let maintenance = crate::maintenance::current().await;
+        if maintenance.read_only {
+            return Err(Status::unavailable(maintenance.message()));
+        }
+        let user = require_scope(caller(&request).await?, "write")?;
+        let request = request.into_inner();
+
+        let if_match = request
+            .if_metadata_version
+            .map_or(IfMatch::Any, IfMatch::Version);
+        let update = MetadataUpdate {
+            description: request.description,
+            tags: request.tags.map(|tags| tags.tags),
+            homepage: request.homepage,
+            readme: request.readme,
+        };
+        validate_update(&update).map_err(Status::invalid_argument)?;
+
+        if let Some(updated) = update_metadata(&request.name, &user, if_match, &update)
+            .await
+            .map_err(Status::internal)?
+        {
+            return Ok(Response::new(updated.into()));
+        }
+
+        // Nothing matched: either the agent isn't ours or someone else got there first
+        match fetch_metadata(&request.name, &user)
+            .await
+            .map_err(Status::internal)?
+        {
+            Some(current) => Err(Status::aborted(format!(
+                "Agent '{}' was changed by someone else; its metadata_version is now {}. \
+                 Fetch the latest metadata and try again.",
+                request.name, current.metadata_version
+            ))),
+            None => Err(not_owned(&request.name)),
+        }
+    }
+}
+
+/// The caller identified by the request's API key, if it sent one
+///
+/// Unlike the REST API, a key that doesn't authenticate is an error rather
+/// than an anonymous call, so misconfigured services notice.
+async fn caller<T>(request: &Request<T>) -> Result<Option<AuthenticatedUser>, Status> {
+    let Some(api_key) = api_key(request.metadata()) else {
+        return Ok(None);
+    };
+
+    if guess_token_type(&api_key) == TokenType::Jwt {
+        return Err(Status::unauthenticated(
+            "JWT tokens are not accepted. Use an API key.",
+        ));
+    }
+
+    authenticate_api_key(&api_key, &AuthConfig::from_env())
+        .await
+        .map(Some)
+        .map_err(|e| Status::unauthenticated(e.message))
+}
+
+/// The API key from `authorization: Bearer <key>` or `x-api-key`
+fn api_key(metadata: &MetadataMap) -> Option<String> {
+    let header = |name| {
+        metadata
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+    };
+
+    header("authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)
+        .or_else(|| header("x-api-key"))
+        .map(str::to_string)
+}
+
+fn require_scope(
+    user: Option<AuthenticatedUser>,
+    scope: &str,
+) -> Result<AuthenticatedUser, Status> {
+    let user = user.ok_or_else(|| {
+        Status::unauthenticated("API key authentication required for agent metadata")
+    })?;
+    if !check_scope(&user, scope) {
+        return Err(Status::permission_denied(format!(
+            "This API key lacks the '{scope}' scope"
+        )));
+    }
+    Ok(user)
+}
+
+async fn visibility(user: Option<&AuthenticatedUser>) -> Result<AgentVisibility, Status> {
+    AgentVisibility::for_user(user)
+        .await
+        .map_err(Status::internal)
+}
+
+fn not_owned(name: &str) -> Status {
+    Status::not_found(format!("Agent '{name}' not found or not owned by you"))
+}
+
+fn search_mode(mode: proto::SearchMode) -> SearchMode {
+    match mode {
+        proto::SearchMode::Unspecified | proto::SearchMode::Substring => SearchMode::Substring,
+        proto::SearchMode::Exact => SearchMode::Exact,
+        proto::SearchMode::Glob => SearchMode::Glob,
+        proto::SearchMode::Regex => SearchMode::Regex,
+    }
+}
+
+fn search_sort(sort: proto::SearchSort) -> SearchSort {
+    match sort {
+        proto::SearchSort::Unspecified | proto::SearchSort::Downloads => SearchSort::Downloads,
+        proto::SearchSort::Stars => SearchSort::Stars,
+        proto::SearchSort::Recent => SearchSort::Recent,
+    }
+}
+
+fn search_status(error: SearchError) -> Status {
+    match error {
+        SearchError::InvalidPattern(message) => Status::invalid_argument(message),
+        SearchError::Internal(message) => Status::internal(message),
+    }
+}
+
+/// The newest of `versions` satisfying `requirement`, read as Cargo does
+///
+/// An empty requirement or `latest` picks the newest version, which is the
+/// last one listed. Versions that aren't valid semver are never matched by
+/// a requirement.
+fn resolve_version<'a>(
+    versions: &'a [String],
+    requirement: &str,
+) -> Result<Option<&'a String>, semver::Error> {
+    let requirement = requirement.trim();
+    if requirement.is_empty() || requirement == "latest" {
+        return Ok(versions.last());
+    }
+
+    let requirement = semver::VersionReq::parse(requirement)?;
+    Ok(versions
+        .iter()
+        .filter_map(|version| Some((semver::Version::parse(version).ok()?, version)))
+        .filter(|(parsed, _)| requirement.matches(parsed))
+        .max_by(|(a, _), (b, _)| a.cmp(b))
+        .map(|(_, version)| version))
+}
+
+impl From<registry::search::Agent> for proto::Agent {
+    fn from(agent: registry::search::Agent) -> Self {
+        proto::Agent {
+            name: agent.name,
+            version: agent.version,
+            description: agent.description,
+            author: agent.author,
+            created_at: agent.created_at.to_rfc3339(),
+            updated_at: agent.updated_at.to_rfc3339(),
+            download_count: agent.download_count,
+            star_count: agent.star_count,
+            rating_count: agent.rating_count,
+            rating_average: agent.rating_average,
+            tags: agent.tags,
+            homepage: agent.homepage,
+            repository: agent.repository,
+            license: agent.license,
+            is_public: agent.is_public,
+        }
+    }
+}
+
+impl From<registry::metadata::AgentMetadata> for proto::AgentMetadata {
+    fn from(metadata: registry::metadata::AgentMetadata) -> Self {
+        proto::AgentMetadata {
+            name: metadata.name,
+            description: metadata.description,
+            tags: metadata.tags.unwrap_or_default(),
+            homepage: metadata.homepage,
+            readme: metadata.readme,
+            metadata_version: metadata.metadata_version,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn versions(versions: &[&str]) -> Vec<String> {
+        versions.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn test_resolve_picks_the_newest_matching_version() {
+        let versions = versions(&["1.0.0", "1.2.0", "1.10.0", "2.0.0-beta.1", "2.0.0"]);
+
+        let resolve = |requirement| resolve_version(&versions, requirement).unwrap().cloned();
+        assert_eq!(resolve(""), Some("2.0.0".to_string()));
+        assert_eq!(resolve("latest"), Some("2.0.0".to_string()));
+        // Compared as versions, not strings
+        assert_eq!(resolve("^1"), Some("1.10.0".to_string()));
+        assert_eq!(resolve("1.2.0"), Some("1.10.0".to_string()));
+        assert_eq!(resolve("=1.2.0"), Some("1.2.0".to_string()));
+        assert_eq!(resolve(">=3"), None);
+
+        assert!(resolve_version(&versions, "not a requirement").is_err());
+    }
+
+    #[test]
+    fn test_api_key_from_either_header() {
+        let mut metadata = MetadataMap::new();
+        assert_eq!(api_key(&metadata), None);
+
+        metadata.insert("x-api-key", "carp_abc".parse().unwrap());
+        assert_eq!(api_key(&metadata).as_deref(), Some("carp_abc"));
+
+        metadata.insert("authorization", "Bearer carp_def".parse().unwrap());
+        assert_eq!(api_key(&metadata).as_deref(), Some("carp_def"));
+    }
+}
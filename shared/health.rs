@@ -0,0 +1,171 @@
+//! Dependency probes shared by the health, liveness and readiness endpoints
+//!
+//! Each probe is timed and bounded so one hung dependency reports as
+//! unhealthy instead of stalling the whole check.
+
+use anyhow::anyhow;
+use serde::Serialize;
+use std::env;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+/// Components slower than this are reported as degraded
+pub const DEGRADED_LATENCY: Duration = Duration::from_millis(1000);
+
+/// Upper bound on each component probe
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Oldest database migration this build of the API can serve against.
+///
+/// Kept at the newest file in `migrations/`; a test fails when they drift.
+pub const REQUIRED_SCHEMA_VERSION: &str = "20250906000000";
+
+/// Health of a single backing service
+#[derive(Debug, Serialize)]
+pub struct ComponentHealth {
+    pub name: &'static str,
+    pub status: &'static str,
+    pub latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Worst status across a set of components
+pub fn overall_status(components: &[ComponentHealth]) -> &'static str {
+    if components.iter().any(|c| c.status == "unhealthy") {
+        "unhealthy"
+    } else if components.iter().any(|c| c.status == "degraded") {
+        "degraded"
+    } else {
+        "healthy"
+    }
+}
+
+/// Time a component check and classify the result
+pub async fn probe(
+    name: &'static str,
+    check: impl Future<Output = Result<(), anyhow::Error>>,
+) -> ComponentHealth {
+    let started = Instant::now();
+    let result = tokio::time::timeout(PROBE_TIMEOUT, check)
+        .await
+        .unwrap_or_else(|_| Err(anyhow!("Timed out after {}s", PROBE_TIMEOUT.as_secs())));
+    let latency = started.elapsed();
+
+    let (status, error) = match result {
+        Ok(()) if latency > DEGRADED_LATENCY => ("degraded", None),
+        Ok(()) => ("healthy", None),
+        Err(e) => ("unhealthy", Some(e.to_string())),
+    };
+
+    ComponentHealth {
+        name,
+        status,
+        latency_ms: latency.as_millis() as u64,
+        error,
+    }
+}
+
+/// Check that a Supabase service endpoint answers successfully
+pub async fn check_service(path: &str) -> Result<(), anyhow::Error> {
+    fetch_service(reqwest::Method::GET, path).await.map(|_| ())
+}
+
+/// Check that the database has every migration this build relies on
+pub async fn check_schema_version() -> Result<(), anyhow::Error> {
+    let response = fetch_service(reqwest::Method::POST, "rest/v1/rpc/schema_version").await?;
+    let applied: Option<String> = response
+        .json()
+        .await
+        .map_err(|e| anyhow!("Invalid schema version response: {}", e))?;
+
+    let applied = applied.ok_or_else(|| anyhow!("No migrations have been applied"))?;
+    if !schema_is_current(&applied) {
+        return Err(anyhow!(
+            "Database schema {} is older than required {}; run `carp-api migrate`",
+            applied,
+            REQUIRED_SCHEMA_VERSION
+        ));
+    }
+
+    Ok(())
+}
+
+/// Migration versions are fixed-width timestamps, so they compare lexically
+fn schema_is_current(applied: &str) -> bool {
+    applied.len() == REQUIRED_SCHEMA_VERSION.len() && applied >= REQUIRED_SCHEMA_VERSION
+}
+
+async fn fetch_service(
+    method: reqwest::Method,
+    path: &str,
+) -> Result<reqwest::Response, anyhow::Error> {
+    let supabase_url = env::var("SUPABASE_URL")
+        .map_err(|_| anyhow!("SUPABASE_URL environment variable not set"))?;
+    let supabase_key = env::var("SUPABASE_SERVICE_ROLE_KEY")
+        .map_err(|_| anyhow!("SUPABASE_SERVICE_ROLE_KEY environment variable not set"))?;
+
+    let mut request = reqwest::Client::new()
+        .request(
+            method.clone(),
+            format!("{}/{path}", supabase_url.trim_end_matches('/')),
+        )
+        .header("apikey", &supabase_key)
+        .header("Authorization", format!("Bearer {supabase_key}"));
+    if method == reqwest::Method::POST {
+        request = request.json(&serde_json::json!({}));
+    }
+
+    let response = request
+        .send()
+        .await
+        .map_err(|e| anyhow!("Request failed: {}", e))?;
+
+    if !response.status().is_success() {
+        return Err(anyhow!("Responded with status {}", response.status()));
+    }
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn component(status: &'static str) -> ComponentHealth {
+        ComponentHealth {
+            name: "test",
+            status,
+            latency_ms: 0,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn test_overall_status() {
+        assert_eq!(overall_status(&[component("healthy")]), "healthy");
+        assert_eq!(
+            overall_status(&[component("healthy"), component("degraded")]),
+            "degraded"
+        );
+        assert_eq!(
+            overall_status(&[component("degraded"), component("unhealthy")]),
+            "unhealthy"
+        );
+    }
+
+    #[test]
+    fn test_schema_is_current() {
+        assert!(schema_is_current(REQUIRED_SCHEMA_VERSION));
+        assert!(schema_is_current("29991231000000"));
+        assert!(!schema_is_current("20250727000000"));
+        assert!(!schema_is_current("2026"));
+    }
+
+    #[tokio::test]
+    async fn test_probe_reports_failure() {
+        let health = probe("database", async { Err(anyhow!("connection refused")) }).await;
+        assert_eq!(health.status, "unhealthy");
+        assert_eq!(health.error.as_deref(), Some("connection refused"));
+    }
+}
@@ -0,0 +1,448 @@
+//! Idempotency keys for endpoints that create agent versions
+//!
+//! Clients send an `Idempotency-Key` header with publish and upload requests
+//! and reuse it when retrying. The first request with a key claims it; once
+//! that request succeeds its response is stored, and later requests with the
+//! same key and payload get the stored response back instead of publishing a
+//! second time. Failed requests release the key so they can be retried.
+//! A claim whose request never finished, because the function timed out or
+//! crashed, goes stale after [`IDEMPOTENCY_CLAIM_TIMEOUT_SECS`] and the next
+//! request with the key takes it over.
+
+use crate::auth::{ApiError, AuthenticatedUser};
+use crate::tenant;
+use chrono::{DateTime, Duration, Utc};
+use reqwest::header::{HeaderValue, RETRY_AFTER};
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::env;
+use uuid::Uuid;
+use vercel_runtime::{Body, Request, Response};
+
+/// Request header carrying the client-supplied key
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// Response header set when a stored response is replayed
+pub const IDEMPOTENT_REPLAYED_HEADER: &str = "idempotent-replayed";
+
+/// How long a completed key keeps replaying its response
+pub const IDEMPOTENCY_KEY_TTL_HOURS: i64 = 24;
+
+/// How long a claim can stay in progress before it's taken to be abandoned;
+/// a little longer than the 30s functions are allowed to run
+pub const IDEMPOTENCY_CLAIM_TIMEOUT_SECS: i64 = 45;
+
+const MAX_KEY_LENGTH: usize = 255;
+
+/// Outcome of claiming the idempotency key on a request
+pub enum IdempotencyClaim {
+    /// The request carried no key, or there is no database to record it in
+    Untracked,
+    /// This request owns the key and must report its result via `finish`
+    Claimed(IdempotencyKey),
+    /// An earlier request with this key already succeeded
+    Replay(Response<Body>),
+}
+
+/// A key claimed by the current request
+pub struct IdempotencyKey {
+    user_id: Uuid,
+    key: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct StoredKey {
+    endpoint: String,
+    request_hash: String,
+    response_status: Option<u16>,
+    response_body: Option<String>,
+    created_at: DateTime<Utc>,
+}
+
+/// Claim the request's idempotency key for `endpoint`.
+///
+/// `payload` identifies what is being created; a key reused with a different
+/// payload is rejected rather than replayed.
+#[allow(clippy::result_large_err)]
+pub async fn claim_idempotency_key(
+    req: &Request,
+    user: &AuthenticatedUser,
+    endpoint: &str,
+    payload: &[u8],
+) -> Result<IdempotencyClaim, Response<Body>> {
+    let Some(key) = req.headers().get(IDEMPOTENCY_KEY_HEADER) else {
+        return Ok(IdempotencyClaim::Untracked);
+    };
+    let key = key
+        .to_str()
+        .ok()
+        .filter(|key| is_valid_key(key))
+        .ok_or_else(|| {
+            error_response(
+                400,
+                "invalid_idempotency_key",
+                format!("Idempotency-Key must be 1-{MAX_KEY_LENGTH} printable ASCII characters"),
+            )
+        })?;
+
+    let Some(store) = Store::from_env() else {
+        return Ok(IdempotencyClaim::Untracked);
+    };
+
+    let request_hash = payload_hash(payload);
+    let unavailable = |e: String| {
+        eprintln!("DEBUG: Idempotency key lookup failed: {e}");
+        error_response(
+            503,
+            "idempotency_unavailable",
+            "Could not check the Idempotency-Key; retry the request".to_string(),
+        )
+    };
+
+    if store
+        .insert(user.user_id, key, endpoint, &request_hash)
+        .await
+        .map_err(unavailable)?
+    {
+        return Ok(IdempotencyClaim::Claimed(IdempotencyKey {
+            user_id: user.user_id,
+            key: key.to_string(),
+        }));
+    }
+
+    let Some(stored) = store.get(user.user_id, key).await.map_err(unavailable)? else {
+        // Released between our insert and lookup; the client can simply retry
+        return Err(in_progress_response());
+    };
+
+    // Expired keys and abandoned claims are reclaimed rather than replayed
+    if stored.is_stale(Utc::now()) {
+        store
+            .delete_stale(user.user_id, key, stored.created_at)
+            .await
+            .map_err(unavailable)?;
+        return if store
+            .insert(user.user_id, key, endpoint, &request_hash)
+            .await
+            .map_err(unavailable)?
+        {
+            Ok(IdempotencyClaim::Claimed(IdempotencyKey {
+                user_id: user.user_id,
+                key: key.to_string(),
+            }))
+        } else {
+            Err(in_progress_response())
+        };
+    }
+
+    if stored.endpoint != endpoint || stored.request_hash != request_hash {
+        return Err(error_response(
+            422,
+            "idempotency_key_reused",
+            "Idempotency-Key was already used for a different request".to_string(),
+        ));
+    }
+
+    match (stored.response_status, stored.response_body) {
+        (Some(status), Some(body)) => Response::builder()
+            .status(status)
+            .header("content-type", "application/json")
+            .header(IDEMPOTENT_REPLAYED_HEADER, "true")
+            .body(body.into())
+            .map(IdempotencyClaim::Replay)
+            .map_err(|e| unavailable(e.to_string())),
+        _ => Err(in_progress_response()),
+    }
+}
+
+impl StoredKey {
+    /// Whether the key can be claimed again: its response has stopped
+    /// replaying, or the request holding it never finished
+    fn is_stale(&self, now: DateTime<Utc>) -> bool {
+        let age = now - self.created_at;
+        if self.response_status.is_some() {
+            age > Duration::hours(IDEMPOTENCY_KEY_TTL_HOURS)
+        } else {
+            age > Duration::seconds(IDEMPOTENCY_CLAIM_TIMEOUT_SECS)
+        }
+    }
+}
+
+impl IdempotencyClaim {
+    /// Record the response for a claimed key.
+    ///
+    /// Successful responses are stored for replay; anything else releases the
+    /// key so the same request can be retried.
+    pub async fn finish(self, status: u16, body: &str) {
+        let IdempotencyClaim::Claimed(claimed) = self else {
+            return;
+        };
+        let Some(store) = Store::from_env() else {
+            return;
+        };
+
+        let result = if (200..300).contains(&status) {
+            store
+                .complete(claimed.user_id, &claimed.key, status, body)
+                .await
+        } else {
+            store.delete(claimed.user_id, &claimed.key).await
+        };
+
+        // The request itself already succeeded or failed; a lost record only
+        // means a retry won't be deduplicated
+        if let Err(e) = result {
+            eprintln!("DEBUG: Failed to record idempotency key: {e}");
+        }
+    }
+}
+
+fn is_valid_key(key: &str) -> bool {
+    !key.is_empty() && key.len() <= MAX_KEY_LENGTH && key.bytes().all(|b| b.is_ascii_graphic())
+}
+
+fn payload_hash(payload: &[u8]) -> String {
+    Sha256::digest(payload)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+fn in_progress_response() -> Response<Body> {
+    let mut response = error_response(
+        409,
+        "idempotency_key_in_progress",
+        "A request with this Idempotency-Key is still being processed".to_string(),
+    );
+    response
+        .headers_mut()
+        .insert(RETRY_AFTER, HeaderValue::from_static("1"));
+    response
+}
+
+fn error_response(status: u16, error: &str, message: String) -> Response<Body> {
+    let error = ApiError {
+        error: error.to_string(),
+        message,
+        details: None,
+    };
+
+    Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(serde_json::to_string(&error).unwrap_or_default().into())
+        .unwrap_or_else(|_| {
+            Response::builder()
+                .status(500)
+                .body("Internal server error".into())
+                .unwrap()
+        })
+}
+
+/// PostgREST access to the `idempotency_keys` table
+struct Store {
+    client: reqwest::Client,
+    url: String,
+    key: String,
+}
+
+impl Store {
+    fn from_env() -> Option<Self> {
+        let supabase_url = env::var("SUPABASE_URL").unwrap_or_default();
+        let supabase_key = env::var("SUPABASE_SERVICE_ROLE_KEY").unwrap_or_default();
+        if supabase_url.is_empty() || supabase_key.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            client: tenant::http_client(),
+            url: format!("{supabase_url}/rest/v1/idempotency_keys"),
+            key: supabase_key,
+        })
+    }
+
+    fn request(&self, method: reqwest::Method, query: &str) -> reqwest::RequestBuilder {
+        self.client
+            .request(method, format!("{}{query}", self.url))
+            .header("apikey", &self.key)
+            .header("Authorization", format!("Bearer {}", self.key))
+    }
+
+    fn row_filter(user_id: Uuid, key: &str) -> String {
+        format!("?user_id=eq.{user_id}&key=eq.{}", urlencoding::encode(key))
+    }
+
+    /// Insert a new claim, returning false if the key already exists
+    async fn insert(
+        &self,
+        user_id: Uuid,
+        key: &str,
+        endpoint: &str,
+        request_hash: &str,
+    ) -> Result<bool, String> {
+        let response = self
+            .request(reqwest::Method::POST, "?on_conflict=user_id,key")
+            .header(
+                "Prefer",
+                "return=representation,resolution=ignore-duplicates",
+            )
+            .json(&json!({
+                "user_id": user_id,
+                "key": key,
+                "endpoint": endpoint,
+                "request_hash": request_hash,
+            }))
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if !response.status().is_success() {
+            return Err(response.text().await.unwrap_or_default());
+        }
+
+        let inserted: Vec<serde_json::Value> = response.json().await.map_err(|e| e.to_string())?;
+        Ok(!inserted.is_empty())
+    }
+
+    async fn get(&self, user_id: Uuid, key: &str) -> Result<Option<StoredKey>, String> {
+        let response = self
+            .request(
+                reqwest::Method::GET,
+                &format!(
+                    "{}&select=endpoint,request_hash,response_status,response_body,created_at",
+                    Self::row_filter(user_id, key)
+                ),
+            )
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if !response.status().is_success() {
+            return Err(response.text().await.unwrap_or_default());
+        }
+
+        let mut rows: Vec<StoredKey> = response.json().await.map_err(|e| e.to_string())?;
+        Ok(rows.pop())
+    }
+
+    async fn complete(
+        &self,
+        user_id: Uuid,
+        key: &str,
+        status: u16,
+        body: &str,
+    ) -> Result<(), String> {
+        let response = self
+            .request(reqwest::Method::PATCH, &Self::row_filter(user_id, key))
+            .json(&json!({
+                "response_status": status,
+                "response_body": body,
+            }))
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if !response.status().is_success() {
+            return Err(response.text().await.unwrap_or_default());
+        }
+        Ok(())
+    }
+
+    /// Delete the row only if it's still the one created at `created_at`, so
+    /// a claim another request just took over isn't released
+    async fn delete_stale(
+        &self,
+        user_id: Uuid,
+        key: &str,
+        created_at: DateTime<Utc>,
+    ) -> Result<(), String> {
+        let query = format!(
+            "{}&created_at=eq.{}",
+            Self::row_filter(user_id, key),
+            urlencoding::encode(&created_at.to_rfc3339())
+        );
+        let response = self
+            .request(reqwest::Method::DELETE, &query)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if !response.status().is_success() {
+            return Err(response.text().await.unwrap_or_default());
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, user_id: Uuid, key: &str) -> Result<(), String> {
+        let response = self
+            .request(reqwest::Method::DELETE, &Self::row_filter(user_id, key))
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if !response.status().is_success() {
+            return Err(response.text().await.unwrap_or_default());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_validation() {
+        assert!(is_valid_key("3f2b8c1e-7d4a-4f0e-9b6a-2c5d8e1f4a7b"));
+        assert!(!is_valid_key(""));
+        assert!(!is_valid_key("has space"));
+        assert!(!is_valid_key(&"k".repeat(MAX_KEY_LENGTH + 1)));
+    }
+
+    #[test]
+    fn test_payload_hash() {
+        assert_eq!(
+            payload_hash(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_ne!(payload_hash(b"a"), payload_hash(b"b"));
+    }
+
+    #[test]
+    fn test_abandoned_claims_go_stale_before_completed_keys() {
+        let now = Utc::now();
+        let stored = |age: Duration, status: Option<u16>| StoredKey {
+            endpoint: "upload".to_string(),
+            request_hash: payload_hash(b"{}"),
+            response_status: status,
+            response_body: status.map(|_| "{}".to_string()),
+            created_at: now - age,
+        };
+
+        assert!(!stored(Duration::seconds(10), None).is_stale(now));
+        assert!(stored(Duration::seconds(IDEMPOTENCY_CLAIM_TIMEOUT_SECS + 1), None).is_stale(now));
+        assert!(!stored(Duration::hours(1), Some(200)).is_stale(now));
+        assert!(stored(Duration::hours(IDEMPOTENCY_KEY_TTL_HOURS + 1), Some(200)).is_stale(now));
+    }
+
+    #[tokio::test]
+    async fn test_untracked_without_header() {
+        let req = Request::new(Body::Empty);
+        let user = AuthenticatedUser {
+            user_id: Uuid::new_v4(),
+            auth_method: crate::auth::AuthMethod::ApiKey {
+                key_id: Uuid::new_v4(),
+            },
+            scopes: vec!["upload".to_string()],
+            metadata: crate::auth::UserMetadata {
+                email: None,
+                github_username: None,
+                created_at: None,
+            },
+        };
+
+        let claim = claim_idempotency_key(&req, &user, "upload", b"{}").await;
+        assert!(matches!(claim, Ok(IdempotencyClaim::Untracked)));
+    }
+}
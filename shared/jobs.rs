@@ -0,0 +1,840 @@
+//! Background jobs
+//!
+//! Work that a request shouldn't wait for is queued in the `jobs` table and
+//! run by `carp-api worker`, a long-lived process beside the serverless
+//! functions. Jobs are queued with [`JobQueue::enqueue`] or, for work that
+//! follows from a row changing, by a trigger calling `enqueue_job` directly.
+//!
+//! Each job is leased to one worker at a time. A failed attempt is retried
+//! after [`retry_delay`], which doubles from 30 seconds up to an hour, until
+//! the job runs out of attempts and is left `failed` for an admin to look at
+//! through `/api/v1/admin/jobs`. A worker that dies mid-job loses its lease,
+//! and the job is picked up again once the lease runs out.
+//!
+//! Workers also queue `rollup_stats` and `expire_api_keys` jobs every 15
+//! minutes, and `carp-api worker --once` on each run, so the download
+//! rollups keep up and expired API keys are deactivated without anything
+//! else scheduling them.
+//!
+//! Adding a kind of job means a [`JobKind`] variant and a branch in
+//! [`perform`].
+
+use crate::email::Mailer;
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::env;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
+use uuid::Uuid;
+
+/// Attempts a job gets unless it's queued with more
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+
+/// Wait before the first retry, doubled for each later one
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(30);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+
+/// Finished jobs are deleted after this many days
+const KEEP_FINISHED_DAYS: u32 = 14;
+/// How often a worker deletes old finished jobs
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// How often a worker queues its scheduled jobs; one queued by any worker
+/// stands in for the rest
+const ROLLUP_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// What a job does
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobKind {
+    /// Recompute the `trending_agents_mv` materialized view
+    RefreshTrending,
+    /// Fold new `download_stats` rows into the daily and weekly per-agent
+    /// download tables, then refresh the trending view from them
+    RollupStats,
+    /// Mark API keys past their `expires_at` inactive
+    ExpireApiKeys,
+    /// Email an API key's owner that it was used from a new country or
+    /// network, queued by `record_api_key_use`
+    NotifyKeyLocation,
+}
+
+impl JobKind {
+    pub const ALL: [JobKind; 4] = [
+        JobKind::RefreshTrending,
+        JobKind::RollupStats,
+        JobKind::ExpireApiKeys,
+        JobKind::NotifyKeyLocation,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            JobKind::RefreshTrending => "refresh_trending",
+            JobKind::RollupStats => "rollup_stats",
+            JobKind::ExpireApiKeys => "expire_api_keys",
+            JobKind::NotifyKeyLocation => "notify_key_location",
+        }
+    }
+
+    pub fn parse(kind: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|known| known.as_str() == kind)
+    }
+}
+
+/// Where a job is in its life, as stored in the database
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    /// Waiting for `run_at`, or for a worker to be free
+    Queued,
+    /// Leased to a worker
+    Running,
+    Succeeded,
+    /// Out of attempts
+    Failed,
+}
+
+impl JobStatus {
+    pub const ALL: [JobStatus; 4] = [
+        JobStatus::Queued,
+        JobStatus::Running,
+        JobStatus::Succeeded,
+        JobStatus::Failed,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            JobStatus::Queued => "queued",
+            JobStatus::Running => "running",
+            JobStatus::Succeeded => "succeeded",
+            JobStatus::Failed => "failed",
+        }
+    }
+
+    pub fn parse(status: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|known| known.as_str() == status)
+    }
+}
+
+/// A row of the `jobs` table
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+    pub id: Uuid,
+    /// Kept as text, since a newer deployment may queue kinds this one
+    /// doesn't know
+    pub kind: String,
+    pub payload: Value,
+    pub status: JobStatus,
+    pub dedupe_key: Option<String>,
+    pub attempts: u32,
+    pub max_attempts: u32,
+    pub run_at: DateTime<Utc>,
+    pub locked_by: Option<String>,
+    pub locked_until: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+/// A job to queue
+#[derive(Debug, Clone)]
+pub struct NewJob {
+    pub kind: JobKind,
+    pub payload: Value,
+    /// When it's due; now if unset
+    pub run_at: Option<DateTime<Utc>>,
+    /// While a job with this key is queued, queueing another returns it
+    pub dedupe_key: Option<String>,
+    pub max_attempts: u32,
+}
+
+impl NewJob {
+    pub fn new(kind: JobKind) -> Self {
+        Self {
+            kind,
+            payload: json!({}),
+            run_at: None,
+            dedupe_key: None,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+        }
+    }
+
+    /// A job that merges into any queued job of the same kind
+    pub fn coalesced(kind: JobKind) -> Self {
+        Self {
+            dedupe_key: Some(kind.as_str().to_string()),
+            ..Self::new(kind)
+        }
+    }
+}
+
+/// Which jobs to list
+#[derive(Debug, Clone, Default)]
+pub struct JobFilter {
+    pub status: Option<JobStatus>,
+    pub kind: Option<String>,
+    pub limit: usize,
+}
+
+/// How long to wait before retrying a job that has failed `attempts` times
+pub fn retry_delay(attempts: u32) -> Duration {
+    let doublings = attempts.saturating_sub(1).min(31);
+    FIRST_RETRY_DELAY
+        .saturating_mul(1 << doublings)
+        .min(MAX_RETRY_DELAY)
+}
+
+/// The `jobs` table, through Supabase's REST API
+pub struct JobQueue {
+    client: reqwest::Client,
+    supabase_url: String,
+    supabase_key: String,
+}
+
+impl JobQueue {
+    /// The queue in the configured Supabase project, if there is one
+    pub fn from_env() -> Option<Self> {
+        let supabase_url = env::var("SUPABASE_URL").unwrap_or_default();
+        let supabase_key = env::var("SUPABASE_SERVICE_ROLE_KEY").unwrap_or_default();
+        if supabase_url.is_empty() || supabase_key.is_empty() {
+            return None;
+        }
+        Some(Self::new(supabase_url, supabase_key))
+    }
+
+    pub fn new(supabase_url: String, supabase_key: String) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            supabase_url,
+            supabase_key,
+        }
+    }
+
+    /// Queue `job`, returning its id, or the id of the queued job it was
+    /// merged into
+    pub async fn enqueue(&self, job: &NewJob) -> Result<Uuid> {
+        self.rpc(
+            "enqueue_job",
+            json!({
+                "p_kind": job.kind.as_str(),
+                "p_payload": job.payload,
+                "p_run_at": job.run_at.unwrap_or_else(Utc::now),
+                "p_dedupe_key": job.dedupe_key,
+                "p_max_attempts": job.max_attempts,
+            }),
+        )
+        .await
+    }
+
+    /// Lease up to `limit` due jobs to `worker` for `lease`
+    pub async fn claim(&self, worker: &str, limit: usize, lease: Duration) -> Result<Vec<Job>> {
+        self.rpc(
+            "claim_jobs",
+            json!({
+                "p_worker": worker,
+                "p_limit": limit,
+                "p_lease_seconds": lease.as_secs(),
+            }),
+        )
+        .await
+    }
+
+    /// Mark a job `worker` holds as done, returning false if its lease was
+    /// lost to another worker meanwhile
+    pub async fn complete(&self, id: Uuid, worker: &str) -> Result<bool> {
+        self.rpc("complete_job", json!({ "p_id": id, "p_worker": worker }))
+            .await
+    }
+
+    /// Record a failed attempt, retrying at `retry_at` if the job has
+    /// attempts left, and return its new status; `None` if the lease was lost
+    pub async fn fail(
+        &self,
+        id: Uuid,
+        worker: &str,
+        error: &str,
+        retry_at: Option<DateTime<Utc>>,
+    ) -> Result<Option<JobStatus>> {
+        self.rpc(
+            "fail_job",
+            json!({
+                "p_id": id,
+                "p_worker": worker,
+                "p_error": error,
+                "p_retry_at": retry_at,
+            }),
+        )
+        .await
+    }
+
+    /// Delete jobs that finished more than `keep_days` ago, returning how many
+    pub async fn prune(&self, keep_days: u32) -> Result<u64> {
+        self.rpc("prune_jobs", json!({ "p_keep_days": keep_days }))
+            .await
+    }
+
+    /// Jobs matching `filter`, most recently updated first
+    pub async fn list(&self, filter: &JobFilter) -> Result<Vec<Job>> {
+        let mut url = format!(
+            "{}/rest/v1/jobs?select=*&order=updated_at.desc&limit={}",
+            self.supabase_url, filter.limit
+        );
+        if let Some(status) = filter.status {
+            url.push_str(&format!("&status=eq.{}", status.as_str()));
+        }
+        if let Some(kind) = &filter.kind {
+            url.push_str(&format!("&kind=eq.{}", urlencoding::encode(kind)));
+        }
+
+        let response = self
+            .client
+            .get(url)
+            .header("apikey", &self.supabase_key)
+            .header("Authorization", format!("Bearer {}", self.supabase_key))
+            .send()
+            .await
+            .context("Failed to list jobs")?;
+        parse_response(response).await
+    }
+
+    /// How many jobs are in each status
+    pub async fn counts(&self) -> Result<BTreeMap<String, u64>> {
+        #[derive(Deserialize)]
+        struct Count {
+            status: String,
+            count: u64,
+        }
+
+        let counts: Vec<Count> = self.rpc("job_counts", json!({})).await?;
+        let mut by_status: BTreeMap<String, u64> = JobStatus::ALL
+            .iter()
+            .map(|status| (status.as_str().to_string(), 0))
+            .collect();
+        by_status.extend(counts.into_iter().map(|count| (count.status, count.count)));
+        Ok(by_status)
+    }
+
+    async fn rpc<T: DeserializeOwned>(&self, function: &str, args: Value) -> Result<T> {
+        let response = self
+            .client
+            .post(format!("{}/rest/v1/rpc/{function}", self.supabase_url))
+            .header("apikey", &self.supabase_key)
+            .header("Authorization", format!("Bearer {}", self.supabase_key))
+            .json(&args)
+            .send()
+            .await
+            .with_context(|| format!("Failed to call {function}"))?;
+        parse_response(response)
+            .await
+            .with_context(|| format!("{function} failed"))
+    }
+}
+
+async fn parse_response<T: DeserializeOwned>(response: reqwest::Response) -> Result<T> {
+    let status = response.status();
+    let body = response.text().await?;
+    if !status.is_success() {
+        bail!("status {status}: {body}");
+    }
+    // Functions returning void answer with no body
+    let body = if body.is_empty() { "null" } else { &body };
+    serde_json::from_str(body).with_context(|| format!("Unexpected response: {body}"))
+}
+
+/// How a worker runs
+#[derive(Debug, Clone)]
+pub struct WorkerConfig {
+    /// Recorded on the jobs it leases, to tell workers apart
+    pub name: String,
+    /// Jobs run at once
+    pub concurrency: usize,
+    /// Wait between checks when nothing is due
+    pub poll_interval: Duration,
+    /// How long a job may run before it's given up on and retried
+    pub lease: Duration,
+}
+
+impl WorkerConfig {
+    /// A worker named after the host and process
+    pub fn new(concurrency: usize, poll_interval: Duration) -> Self {
+        let host = env::var("HOSTNAME").unwrap_or_else(|_| "worker".to_string());
+        Self {
+            name: format!("{host}-{}", std::process::id()),
+            concurrency: concurrency.max(1),
+            poll_interval,
+            lease: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+/// Runs jobs from a queue
+pub struct Worker {
+    queue: Arc<JobQueue>,
+    config: WorkerConfig,
+}
+
+impl Worker {
+    pub fn new(queue: JobQueue, config: WorkerConfig) -> Self {
+        Self {
+            queue: Arc::new(queue),
+            config,
+        }
+    }
+
+    /// Run jobs as they come due, until the process is stopped
+    pub async fn run(&self) -> Result<()> {
+        let mut last_pruned = None;
+        let mut last_scheduled = None;
+        loop {
+            if last_scheduled.is_none_or(|at: Instant| at.elapsed() >= ROLLUP_INTERVAL) {
+                self.schedule().await;
+                last_scheduled = Some(Instant::now());
+            }
+
+            if last_pruned.is_none_or(|at: Instant| at.elapsed() >= PRUNE_INTERVAL) {
+                match self.queue.prune(KEEP_FINISHED_DAYS).await {
+                    Ok(deleted) if deleted > 0 => println!("Deleted {deleted} old finished job(s)"),
+                    Ok(_) => {}
+                    Err(e) => eprintln!("Failed to delete old jobs: {e:#}"),
+                }
+                last_pruned = Some(Instant::now());
+            }
+
+            // An unreachable database is waited out rather than ending the worker
+            match self.run_batch().await {
+                Ok(0) => tokio::time::sleep(self.config.poll_interval).await,
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!("Failed to claim jobs: {e:#}");
+                    tokio::time::sleep(self.config.poll_interval).await;
+                }
+            }
+        }
+    }
+
+    /// Run jobs until none are due, returning how many ran. The scheduled
+    /// jobs are queued first, as a running worker would.
+    pub async fn drain(&self) -> Result<usize> {
+        self.schedule().await;
+        let mut total = 0;
+        loop {
+            match self.run_batch().await? {
+                0 => return Ok(total),
+                ran => total += ran,
+            }
+        }
+    }
+
+    /// Queue the jobs workers run on a schedule
+    async fn schedule(&self) {
+        for kind in [JobKind::RollupStats, JobKind::ExpireApiKeys] {
+            if let Err(e) = self.queue.enqueue(&NewJob::coalesced(kind)).await {
+                eprintln!("Failed to queue a {} job: {e:#}", kind.as_str());
+            }
+        }
+    }
+
+    /// Claim a batch of due jobs and run them side by side, returning how
+    /// many were claimed
+    async fn run_batch(&self) -> Result<usize> {
+        let jobs = self
+            .queue
+            .claim(
+                &self.config.name,
+                self.config.concurrency,
+                self.config.lease,
+            )
+            .await?;
+        let claimed = jobs.len();
+
+        let mut running = JoinSet::new();
+        for job in jobs {
+            let queue = self.queue.clone();
+            let config = self.config.clone();
+            running.spawn(async move { run_job(&queue, &config, job).await });
+        }
+        while let Some(result) = running.join_next().await {
+            if let Err(e) = result {
+                eprintln!("Job task panicked: {e}");
+            }
+        }
+
+        Ok(claimed)
+    }
+}
+
+/// Run one leased job and report how it went
+async fn run_job(queue: &JobQueue, config: &WorkerConfig, job: Job) {
+    // Give up before the lease runs out, so no other worker starts it twice
+    let limit = config.lease.saturating_sub(Duration::from_secs(5));
+    let result = match tokio::time::timeout(limit, perform(queue, &job)).await {
+        Ok(result) => result,
+        Err(_) => Err(anyhow::anyhow!("Timed out after {}s", limit.as_secs())),
+    };
+
+    let reported = match result {
+        Ok(()) => {
+            println!("Job {} ({}) succeeded", job.id, job.kind);
+            queue.complete(job.id, &config.name).await.map(drop)
+        }
+        Err(e) => {
+            let error = format!("{e:#}");
+            let retry_at = chrono::Duration::from_std(retry_delay(job.attempts))
+                .ok()
+                .map(|delay| Utc::now() + delay);
+            let status = queue.fail(job.id, &config.name, &error, retry_at).await;
+            if let Ok(Some(status)) = &status {
+                eprintln!(
+                    "Job {} ({}) failed on attempt {} of {}, now {}: {error}",
+                    job.id,
+                    job.kind,
+                    job.attempts,
+                    job.max_attempts,
+                    status.as_str()
+                );
+            }
+            status.map(drop)
+        }
+    };
+
+    // The lease runs out on its own, and the job is retried then
+    if let Err(e) = reported {
+        eprintln!("Failed to record the result of job {}: {e:#}", job.id);
+    }
+}
+
+/// Do the work of `job`
+async fn perform(queue: &JobQueue, job: &Job) -> Result<()> {
+    let Some(kind) = JobKind::parse(&job.kind) else {
+        bail!(
+            "Unknown job kind '{}'; it may have been queued by a newer deployment",
+            job.kind
+        );
+    };
+
+    match kind {
+        JobKind::RefreshTrending => {
+            queue
+                .rpc::<Value>("refresh_trending_view_job", json!({}))
+                .await?;
+        }
+        JobKind::RollupStats => {
+            let rows: u64 = queue.rpc("rollup_download_stats", json!({})).await?;
+            println!("Rolled up downloads into {rows} daily total(s)");
+            // Ranked by the totals just written
+            queue
+                .rpc::<Value>("refresh_trending_view_job", json!({}))
+                .await?;
+        }
+        JobKind::ExpireApiKeys => {
+            let keys: u64 = queue.rpc("deactivate_expired_api_keys", json!({})).await?;
+            if keys > 0 {
+                println!("Deactivated {keys} expired API key(s)");
+            }
+        }
+        JobKind::NotifyKeyLocation => {
+            notify_key_location(queue, Mailer::from_env().as_ref(), &job.payload).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Payload of a `notify_key_location` job
+#[derive(Debug, Deserialize)]
+struct KeyLocationAlert {
+    key_id: Uuid,
+    /// `country:<code>` or `asn:<number>`
+    locations: Vec<String>,
+    ip: Option<String>,
+    user_agent: Option<String>,
+    seen_at: DateTime<Utc>,
+}
+
+/// Tell an API key's owner it was used from somewhere new. Without email
+/// configured, or once the key or its owner is gone, there's nobody to tell.
+async fn notify_key_location(
+    queue: &JobQueue,
+    mailer: Option<&Mailer>,
+    payload: &Value,
+) -> Result<()> {
+    #[derive(Deserialize)]
+    struct Owner {
+        name: Option<String>,
+        prefix: String,
+        email: Option<String>,
+    }
+
+    let alert: KeyLocationAlert =
+        serde_json::from_value(payload.clone()).context("Invalid notify_key_location payload")?;
+    let Some(mailer) = mailer else {
+        println!(
+            "Not alerting the owner of API key {} about a new location; email isn't configured",
+            alert.key_id
+        );
+        return Ok(());
+    };
+    let owners: Vec<Owner> = queue
+        .rpc("api_key_owner", json!({ "p_key_id": alert.key_id }))
+        .await?;
+    let Some((owner, email)) = owners
+        .into_iter()
+        .find_map(|owner| owner.email.clone().map(|email| (owner, email)))
+    else {
+        return Ok(());
+    };
+
+    let name = owner.name.as_deref().unwrap_or("unnamed");
+    let places: Vec<String> = alert
+        .locations
+        .iter()
+        .map(|location| match location.split_once(':') {
+            Some(("country", code)) => format!("country {code}"),
+            Some(("asn", number)) => format!("network AS{number}"),
+            _ => location.clone(),
+        })
+        .collect();
+    let text = format!(
+        "Your Carp API key \"{name}\" ({}...) was just used from {}, where it hasn't been used before.\n\n\
+         Time: {}\n\
+         IP address: {}\n\
+         Client: {}\n\n\
+         If this was you, there's nothing to do. If not, revoke the key in the registry dashboard and create a new one.\n",
+        owner.prefix,
+        places.join(" and "),
+        alert.seen_at.format("%Y-%m-%d %H:%M:%S UTC"),
+        alert.ip.as_deref().unwrap_or("unknown"),
+        alert.user_agent.as_deref().unwrap_or("unknown"),
+    );
+    mailer
+        .send(
+            &email,
+            &format!("Your API key \"{name}\" was used from a new location"),
+            &text,
+        )
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_delay_doubles_up_to_an_hour() {
+        assert_eq!(retry_delay(0), Duration::from_secs(30));
+        assert_eq!(retry_delay(1), Duration::from_secs(30));
+        assert_eq!(retry_delay(2), Duration::from_secs(60));
+        assert_eq!(retry_delay(4), Duration::from_secs(240));
+        assert_eq!(retry_delay(8), Duration::from_secs(3600));
+        assert_eq!(retry_delay(u32::MAX), Duration::from_secs(3600));
+    }
+
+    #[test]
+    fn test_kinds_and_statuses_round_trip() {
+        for kind in JobKind::ALL {
+            assert_eq!(JobKind::parse(kind.as_str()), Some(kind));
+            assert_eq!(serde_json::to_value(kind).unwrap(), json!(kind.as_str()));
+        }
+        for status in JobStatus::ALL {
+            assert_eq!(JobStatus::parse(status.as_str()), Some(status));
+        }
+        assert_eq!(JobKind::parse("send_webhook"), None);
+    }
+
+    /// A `kind` job leased to the worker named "test"
+    fn leased_job(kind: JobKind, attempts: u32) -> Value {
+        json!({
+            "id": Uuid::new_v4(),
+            "kind": kind.as_str(),
+            "payload": {},
+            "status": "running",
+            "dedupe_key": kind.as_str(),
+            "attempts": attempts,
+            "max_attempts": 5,
+            "run_at": Utc::now(),
+            "locked_by": "test",
+            "locked_until": Utc::now(),
+            "last_error": null,
+            "created_at": Utc::now(),
+            "updated_at": Utc::now(),
+            "finished_at": null,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_worker_retries_failed_jobs_later() {
+        use wiremock::matchers::{body_partial_json, method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        let job = leased_job(JobKind::RefreshTrending, 2);
+        Mock::given(method("POST"))
+            .and(path("/rest/v1/rpc/claim_jobs"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([job])))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/rest/v1/rpc/claim_jobs"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([])))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/rest/v1/rpc/refresh_trending_view_job"))
+            .respond_with(ResponseTemplate::new(500).set_body_string("canceling statement"))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/rest/v1/rpc/fail_job"))
+            .and(body_partial_json(json!({ "p_worker": "test" })))
+            .respond_with(ResponseTemplate::new(200).set_body_json("queued"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let queue = JobQueue::new(server.uri(), "key".to_string());
+        let mut config = WorkerConfig::new(2, Duration::from_secs(1));
+        config.name = "test".to_string();
+        assert_eq!(Worker::new(queue, config).drain().await.unwrap(), 1);
+
+        let requests = server.received_requests().await.unwrap();
+        let failed: Value = requests
+            .iter()
+            .find(|request| request.url.path().ends_with("fail_job"))
+            .unwrap()
+            .body_json()
+            .unwrap();
+        assert!(failed["p_error"]
+            .as_str()
+            .unwrap()
+            .contains("canceling statement"));
+        // The second failure waits a minute
+        let retry_at: DateTime<Utc> = serde_json::from_value(failed["p_retry_at"].clone()).unwrap();
+        let delay = retry_at - Utc::now();
+        assert!(delay > chrono::Duration::seconds(50) && delay <= chrono::Duration::seconds(60));
+    }
+
+    #[tokio::test]
+    async fn test_drain_queues_and_runs_the_scheduled_jobs() {
+        use wiremock::matchers::{body_partial_json, method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/rest/v1/rpc/enqueue_job"))
+            .and(body_partial_json(json!({
+                "p_kind": "rollup_stats",
+                "p_dedupe_key": "rollup_stats",
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(Uuid::new_v4()))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/rest/v1/rpc/enqueue_job"))
+            .and(body_partial_json(json!({
+                "p_kind": "expire_api_keys",
+                "p_dedupe_key": "expire_api_keys",
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(Uuid::new_v4()))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/rest/v1/rpc/claim_jobs"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+                leased_job(JobKind::RollupStats, 1),
+                leased_job(JobKind::ExpireApiKeys, 1)
+            ])))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/rest/v1/rpc/claim_jobs"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([])))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/rest/v1/rpc/rollup_download_stats"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(12))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/rest/v1/rpc/refresh_trending_view_job"))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/rest/v1/rpc/deactivate_expired_api_keys"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(3))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/rest/v1/rpc/complete_job"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(true))
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let queue = JobQueue::new(server.uri(), "key".to_string());
+        let mut config = WorkerConfig::new(2, Duration::from_secs(1));
+        config.name = "test".to_string();
+        assert_eq!(Worker::new(queue, config).drain().await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_key_location_alerts_email_the_owner() {
+        use wiremock::matchers::{body_partial_json, body_string_contains, method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/rest/v1/rpc/api_key_owner"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([{
+                "name": "laptop",
+                "prefix": "carp_abc1234",
+                "email": "alice@example.com"
+            }])))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/emails"))
+            .and(body_partial_json(json!({
+                "from": "Carp <alerts@example.com>",
+                "to": ["alice@example.com"],
+            })))
+            .and(body_string_contains("country DE and network AS3320"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "id": "1" })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let queue = JobQueue::new(server.uri(), "key".to_string());
+        let mailer = Mailer::new(
+            format!("{}/emails", server.uri()),
+            "key".to_string(),
+            "Carp <alerts@example.com>".to_string(),
+        );
+        let payload = json!({
+            "key_id": Uuid::new_v4(),
+            "locations": ["country:DE", "asn:3320"],
+            "ip": "203.0.113.7",
+            "user_agent": "curl/8.0",
+            "seen_at": Utc::now(),
+        });
+
+        notify_key_location(&queue, Some(&mailer), &payload)
+            .await
+            .unwrap();
+        // Without email there's nobody to tell, and nothing to look up
+        notify_key_location(&queue, None, &payload).await.unwrap();
+    }
+}
@@ -0,0 +1,95 @@
+//! Per-route request limits for the local registry
+//!
+//! Every request gets a cap on its body and a deadline for the whole
+//! exchange, both chosen by route: lookups and auth only ever carry a few
+//! bytes, while uploads and publishes carry whole packages and may wait on
+//! slow disks. A request over its body cap is answered 413 without reading
+//! the rest, and one past its deadline 503, so a stalled client or storage
+//! backend can't hold a connection's task forever.
+
+use hyper::Method;
+use std::time::Duration;
+
+/// Body cap and deadline for one route
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RouteLimits {
+    /// Largest request body accepted, in bytes
+    pub max_body: usize,
+    /// Time allowed to read the body and produce a response
+    pub timeout: Duration,
+}
+
+impl RouteLimits {
+    /// Auth, search and other requests that only carry a query
+    pub const LOOKUP: Self = Self {
+        max_body: 16 * 1024,
+        timeout: Duration::from_secs(10),
+    };
+
+    /// Batch info, which posts up to 100 agent names
+    pub const BATCH: Self = Self {
+        max_body: 64 * 1024,
+        timeout: Duration::from_secs(15),
+    };
+
+    /// Uploads and publishes, which carry a package
+    pub const PACKAGE: Self = Self {
+        max_body: 32 * 1024 * 1024,
+        timeout: Duration::from_secs(120),
+    };
+
+    /// Everything else, including downloads
+    pub const DEFAULT: Self = Self {
+        max_body: 1024 * 1024,
+        timeout: Duration::from_secs(30),
+    };
+
+    /// The limits for a request to `path`
+    pub fn for_route(method: &Method, path: &str) -> Self {
+        let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        match (method, segments.as_slice()) {
+            (&Method::POST, ["api", "v1", "agents", "upload" | "publish"]) => Self::PACKAGE,
+            (&Method::POST, ["api", "v1", "agents", "batch-info"]) => Self::BATCH,
+            (_, ["api", "v1", "auth", ..])
+            | (
+                &Method::GET,
+                ["api", "v1", "agents", "search" | "suggest" | "latest" | "changes"]
+                | ["api", "v1", "templates"],
+            ) => Self::LOOKUP,
+            _ => Self::DEFAULT,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limits_follow_the_route() {
+        assert_eq!(
+            RouteLimits::for_route(&Method::POST, "/api/v1/agents/upload"),
+            RouteLimits::PACKAGE
+        );
+        assert_eq!(
+            RouteLimits::for_route(&Method::POST, "/api/v1/agents/publish/"),
+            RouteLimits::PACKAGE
+        );
+        assert_eq!(
+            RouteLimits::for_route(&Method::GET, "/api/v1/agents/search"),
+            RouteLimits::LOOKUP
+        );
+        assert_eq!(
+            RouteLimits::for_route(&Method::POST, "/api/v1/auth/login"),
+            RouteLimits::LOOKUP
+        );
+        assert_eq!(
+            RouteLimits::for_route(&Method::POST, "/api/v1/agents/batch-info"),
+            RouteLimits::BATCH
+        );
+        assert_eq!(
+            RouteLimits::for_route(&Method::GET, "/api/v1/agents/helper/1.0.0/download"),
+            RouteLimits::DEFAULT
+        );
+    }
+}
@@ -0,0 +1,218 @@
+//! Self-contained registry for development and CI
+//!
+//! With `LOCAL_MODE=1`, `carp-api` serves the registry endpoints the CLI uses
+//! from a single process: agents live in a SQLite database, packages in a
+//! directory beside it, and any bearer token or API key is accepted as the
+//! local user. No Supabase project, storage bucket or Vercel deployment is
+//! needed, so a checkout can run the CLI end to end with `cargo run`.
+//!
+//! Configuration comes from the environment:
+//!
+//! - `LOCAL_MODE`: `1` or `true` to serve locally
+//! - `LOCAL_DATA_DIR`: where the database and packages are kept (default
+//!   `.carp-local`)
+//! - `LOCAL_USER`: username requests with a token act as (default `local`)
+//! - `LOCAL_BIND`: address to listen on (default `127.0.0.1`). Any token is
+//!   accepted, so listening on other interfaces, like `0.0.0.0` in a
+//!   container, has to be asked for.
+//! - `PORT`: port to listen on (default 3000)
+//! - `LOCAL_DRAIN_TIMEOUT`: seconds requests in flight get to finish after
+//!   SIGTERM or Ctrl-C (default 30)
+//!
+//! On SIGTERM or Ctrl-C the server stops accepting connections and turns
+//! away new uploads and publishes with `503` and `Retry-After`, while
+//! requests already in flight get up to the drain timeout to finish, so a
+//! restart doesn't cut off a package halfway through being stored.
+//!
+//! Agents can also be pulled over the OCI distribution API under `/v2`.
+//!
+//! Each tenant, under `/t/<id>/` or on its hosts in `TENANTS`, gets a
+//! registry of its own; see [`LocalRegistries`].
+//!
+//! Request bodies and handling times are capped per route; see
+//! [`RouteLimits`].
+//!
+//! Endpoints that need the hosted services, such as reviews and access grants,
+//! answer 501 here.
+
+mod limits;
+mod oci;
+mod routes;
+mod store;
+mod suggest;
+mod tenants;
+#[cfg(any(test, feature = "test-support"))]
+pub mod testing;
+
+use anyhow::{Context, Result};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper_util::rt::TokioIo;
+use hyper_util::server::graceful::GracefulShutdown;
+use std::env;
+use std::future::Future;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::time::timeout;
+
+pub use limits::RouteLimits;
+pub use store::{LocalStore, LocalUser, StoredAgent};
+pub use tenants::LocalRegistries;
+
+const DEFAULT_DATA_DIR: &str = ".carp-local";
+const DEFAULT_USER: &str = "local";
+const DEFAULT_PORT: u16 = 3000;
+const DEFAULT_BIND: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Whether `LOCAL_MODE` asks for the local registry
+pub fn enabled() -> bool {
+    env::var("LOCAL_MODE")
+        .map(|value| matches!(value.trim().to_ascii_lowercase().as_str(), "1" | "true"))
+        .unwrap_or(false)
+}
+
+/// Settings for the local registry
+#[derive(Debug, Clone)]
+pub struct LocalConfig {
+    pub data_dir: PathBuf,
+    pub username: String,
+    pub addr: SocketAddr,
+    /// How long requests in flight get to finish once asked to stop
+    pub drain_timeout: Duration,
+}
+
+impl LocalConfig {
+    pub fn from_env() -> Result<Self> {
+        let port = match env::var("PORT") {
+            Ok(port) => port
+                .trim()
+                .parse()
+                .with_context(|| format!("PORT '{port}' is not a port number"))?,
+            Err(_) => DEFAULT_PORT,
+        };
+        let bind = match env::var("LOCAL_BIND") {
+            Ok(bind) if !bind.trim().is_empty() => bind
+                .trim()
+                .parse()
+                .with_context(|| format!("LOCAL_BIND '{bind}' is not an IP address"))?,
+            _ => DEFAULT_BIND,
+        };
+        let drain_timeout = match env::var("LOCAL_DRAIN_TIMEOUT") {
+            Ok(seconds) => Duration::from_secs(seconds.trim().parse().with_context(|| {
+                format!("LOCAL_DRAIN_TIMEOUT '{seconds}' is not a number of seconds")
+            })?),
+            Err(_) => DEFAULT_DRAIN_TIMEOUT,
+        };
+
+        Ok(Self {
+            data_dir: env::var("LOCAL_DATA_DIR")
+                .map(PathBuf::from)
+                .unwrap_or_else(|_| PathBuf::from(DEFAULT_DATA_DIR)),
+            username: env::var("LOCAL_USER")
+                .ok()
+                .filter(|user| !user.trim().is_empty())
+                .unwrap_or_else(|| DEFAULT_USER.to_string()),
+            addr: SocketAddr::new(bind, port),
+            drain_timeout,
+        })
+    }
+}
+
+/// Serve the local registry until SIGTERM or Ctrl-C, then drain it
+pub async fn serve(config: LocalConfig) -> Result<()> {
+    let registries = Arc::new(LocalRegistries::open(&config.data_dir, &config.username).await?);
+    let listener = TcpListener::bind(config.addr)
+        .await
+        .with_context(|| format!("Failed to listen on {}", config.addr))?;
+
+    println!(
+        "Local registry on http://{} (data in {}, acting as '{}')",
+        config.addr,
+        config.data_dir.display(),
+        config.username
+    );
+
+    run(
+        listener,
+        registries,
+        shutdown_signal(),
+        config.drain_timeout,
+    )
+    .await
+}
+
+/// Answer connections on `listener` until `shutdown` completes, then stop
+/// accepting and give requests in flight up to `drain_timeout` to finish
+async fn run(
+    listener: TcpListener,
+    registries: Arc<LocalRegistries>,
+    shutdown: impl Future<Output = ()>,
+    drain_timeout: Duration,
+) -> Result<()> {
+    let connections = GracefulShutdown::new();
+    tokio::pin!(shutdown);
+
+    loop {
+        let stream = tokio::select! {
+            accepted = listener.accept() => accepted?.0,
+            () = &mut shutdown => break,
+        };
+        let registries = registries.clone();
+        let service = service_fn(move |req| routes::handle(registries.clone(), req));
+        let connection = connections
+            .watch(http1::Builder::new().serve_connection(TokioIo::new(stream), service));
+
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                eprintln!("DEBUG: Local connection failed: {e}");
+            }
+        });
+    }
+
+    drop(listener);
+    // Anything that gets through before its connection closes is turned away
+    // too, if it would start storing a package
+    registries.drain();
+    println!(
+        "Draining requests in flight, for up to {}s",
+        drain_timeout.as_secs()
+    );
+    if timeout(drain_timeout, connections.shutdown())
+        .await
+        .is_err()
+    {
+        eprintln!(
+            "Stopped with requests still in flight after {}s",
+            drain_timeout.as_secs()
+        );
+    }
+    Ok(())
+}
+
+/// Resolves on SIGTERM, as sent by `docker stop` and process managers, or
+/// on Ctrl-C
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                eprintln!("DEBUG: Failed to listen for SIGTERM: {e}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        () = terminate => {}
+    }
+}
@@ -0,0 +1,190 @@
+//! Pulling agents over the OCI distribution API
+//!
+//! Local mode serves manifests, blobs and tags as the hosted registry does,
+//! but takes pushes through the carp endpoints only.
+
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::header::{HeaderName, HeaderValue, CONTENT_TYPE};
+use hyper::{Method, Request, Response, StatusCode};
+use serde_json::json;
+
+use super::store::{LocalStore, LocalUser, StoredAgent};
+use crate::oci::{self, Blob, PublishedVersion, Route};
+
+type LocalResponse = Response<Full<Bytes>>;
+
+pub async fn handle(
+    store: &LocalStore,
+    req: &Request<Bytes>,
+    method: &Method,
+    user: Option<&LocalUser>,
+) -> anyhow::Result<LocalResponse> {
+    let Some(route) = oci::route(req.uri().path()) else {
+        return Ok(error(
+            StatusCode::NOT_FOUND,
+            "NAME_UNKNOWN",
+            "Unknown distribution API path",
+        ));
+    };
+
+    let response = match (method, route) {
+        (&Method::GET, Route::Base) => respond(StatusCode::OK, "application/json", "{}".into()),
+        (&Method::GET, Route::Manifest { name, reference }) => {
+            let Some((_, versions)) = published(store, &name, user).await? else {
+                return Ok(name_unknown(&name));
+            };
+            match oci::find_version(&versions, &reference) {
+                Some((_, artifact)) => with_digest(
+                    respond(
+                        StatusCode::OK,
+                        oci::MANIFEST_MEDIA_TYPE,
+                        artifact.manifest.into(),
+                    ),
+                    &artifact.manifest_digest,
+                )?,
+                None => error(
+                    StatusCode::NOT_FOUND,
+                    "MANIFEST_UNKNOWN",
+                    &format!("No manifest '{reference}' for agent '{name}'"),
+                ),
+            }
+        }
+        (&Method::GET, Route::Blob { name, digest }) => {
+            let Some((agent, versions)) = published(store, &name, user).await? else {
+                return Ok(name_unknown(&name));
+            };
+            match oci::find_blob(&versions, &digest) {
+                Some(Blob::Config(config)) => with_digest(
+                    respond(StatusCode::OK, oci::CONFIG_MEDIA_TYPE, config.into()),
+                    &digest,
+                )?,
+                Some(Blob::Layer(version)) => {
+                    let Some(stored) = store.version(&agent, &version.version).await? else {
+                        return Ok(error(
+                            StatusCode::NOT_FOUND,
+                            "BLOB_UNKNOWN",
+                            "Blob not found",
+                        ));
+                    };
+                    let package = store.read_package(&stored)?;
+                    // HEAD shares this route, and checking for a blob isn't a download
+                    if req.method() == Method::GET {
+                        store.record_download(&agent, &stored.version).await?;
+                    }
+                    with_digest(
+                        respond(StatusCode::OK, "application/octet-stream", package),
+                        &digest,
+                    )?
+                }
+                None => error(StatusCode::NOT_FOUND, "BLOB_UNKNOWN", "Blob not found"),
+            }
+        }
+        (&Method::GET, Route::Tags { name }) => {
+            let Some((_, versions)) = published(store, &name, user).await? else {
+                return Ok(name_unknown(&name));
+            };
+            let params: Vec<(String, String)> =
+                url::form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes())
+                    .into_owned()
+                    .collect();
+            let param = |key: &str| {
+                params
+                    .iter()
+                    .find(|(k, _)| k == key)
+                    .map(|(_, value)| value.as_str())
+            };
+            let n = param("n").and_then(|n| n.parse().ok());
+            let (tags, more) = oci::tags_page(&versions, n, param("last"));
+            let body = serde_json::to_vec(&json!({ "name": name, "tags": tags }))?;
+            let mut response = respond(StatusCode::OK, "application/json", body.into());
+            if let (true, Some(last)) = (more, tags.last()) {
+                let n = n.unwrap_or(oci::MAX_TAGS_PAGE);
+                response.headers_mut().insert(
+                    HeaderName::from_static("link"),
+                    HeaderValue::from_str(&format!(
+                        "</v2/{name}/tags/list?n={n}&last={}>; rel=\"next\"",
+                        urlencoding::encode(last)
+                    ))?,
+                );
+            }
+            response
+        }
+        (_, Route::Uploads { .. } | Route::Upload { .. })
+        | (&Method::PUT, Route::Manifest { .. }) => error(
+            StatusCode::METHOD_NOT_ALLOWED,
+            "UNSUPPORTED",
+            "Local mode doesn't take OCI pushes; publish with carp instead",
+        ),
+        _ => error(
+            StatusCode::METHOD_NOT_ALLOWED,
+            "UNSUPPORTED",
+            "Method not supported on this path",
+        ),
+    };
+
+    Ok(response)
+}
+
+/// The agent and its downloadable versions, oldest first
+async fn published(
+    store: &LocalStore,
+    name: &str,
+    user: Option<&LocalUser>,
+) -> anyhow::Result<Option<(StoredAgent, Vec<PublishedVersion>)>> {
+    let Some(agent) = store.agent(name, user).await? else {
+        return Ok(None);
+    };
+    let versions = store
+        .versions(&agent)
+        .await?
+        .into_iter()
+        .filter(|version| !version.yanked)
+        .map(|version| PublishedVersion {
+            name: agent.name.clone(),
+            version: version.version,
+            checksum: version.checksum,
+            file_size: version.file_size,
+            content_type: version.content_type,
+            definition: version.definition,
+            file_path: version.file_path,
+            oci_manifest: None,
+            oci_config: None,
+        })
+        .collect();
+    Ok(Some((agent, versions)))
+}
+
+fn respond(status: StatusCode, content_type: &'static str, body: Bytes) -> LocalResponse {
+    let mut response = Response::new(Full::new(body));
+    *response.status_mut() = status;
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+    let (name, value) = oci::API_VERSION_HEADER;
+    headers.insert(
+        HeaderName::from_static(name),
+        HeaderValue::from_static(value),
+    );
+    response
+}
+
+fn with_digest(mut response: LocalResponse, digest: &str) -> anyhow::Result<LocalResponse> {
+    response.headers_mut().insert(
+        HeaderName::from_static("docker-content-digest"),
+        HeaderValue::from_str(digest)?,
+    );
+    Ok(response)
+}
+
+fn error(status: StatusCode, code: &str, message: &str) -> LocalResponse {
+    let body = oci::error_body(code, message).to_string();
+    respond(status, "application/json", body.into())
+}
+
+fn name_unknown(name: &str) -> LocalResponse {
+    error(
+        StatusCode::NOT_FOUND,
+        "NAME_UNKNOWN",
+        &format!("Agent '{name}' not found"),
+    )
+}
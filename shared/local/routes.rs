@@ -0,0 +1,1224 @@
+//! Request handling for the local registry
+//!
+//! Responses use the same shapes as the hosted endpoints so the CLI can't tell
+//! the difference.
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use http_body_util::{BodyExt, Full, LengthLimitError, Limited};
+use hyper::body::Incoming;
+use hyper::header::{HeaderValue, CONTENT_LENGTH, CONTENT_TYPE, HOST, RETRY_AFTER};
+use hyper::{Method, Request, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::Arc;
+use tokio::time::timeout;
+
+use super::limits::RouteLimits;
+use super::store::{
+    LocalStore, LocalUser, Match, NewVersion, PublishError, SearchQuery, StoredAgent,
+};
+use super::{oci, suggest, LocalRegistries};
+use crate::archive::{build_markdown_package, validate_package, PackageFormat};
+use crate::auth::ApiError;
+use crate::content_policy::ContentPolicy;
+use crate::maintenance::Maintenance;
+use crate::problem::{Problem, PROBLEM_CONTENT_TYPE};
+use crate::registry::changes::{self as feed, ChangeKind};
+use crate::validation::validation_error;
+
+type LocalResponse = Response<Full<Bytes>>;
+
+/// Most agents the batch info endpoint looks up in one request
+const MAX_BATCH_SIZE: usize = 100;
+
+/// Seconds uploads turned away while the server drains are asked to wait,
+/// long enough for a restart to come back up
+const DRAIN_RETRY_AFTER_SECS: u64 = 10;
+
+/// Agent as returned by search, upload and publish
+#[derive(Debug, Serialize)]
+struct Agent {
+    name: String,
+    version: String,
+    description: String,
+    author: String,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    download_count: u64,
+    star_count: u64,
+    rating_count: u64,
+    rating_average: Option<f64>,
+    tags: Vec<String>,
+    readme: Option<String>,
+    homepage: Option<String>,
+    repository: Option<String>,
+    license: Option<String>,
+    is_public: bool,
+    is_template: bool,
+}
+
+impl From<StoredAgent> for Agent {
+    fn from(agent: StoredAgent) -> Self {
+        Agent {
+            name: agent.name,
+            version: agent.current_version,
+            description: agent.description,
+            author: agent.author,
+            created_at: agent.created_at,
+            updated_at: agent.updated_at,
+            download_count: agent.download_count,
+            star_count: 0,
+            rating_count: 0,
+            rating_average: None,
+            tags: agent.tags,
+            readme: agent.readme,
+            homepage: agent.homepage,
+            repository: agent.repository,
+            license: agent.license,
+            is_public: agent.is_public,
+            is_template: agent.is_template,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct BatchInfoRequest {
+    names: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct UploadAgentRequest {
+    name: String,
+    description: String,
+    content: String,
+    version: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    homepage: Option<String>,
+    repository: Option<String>,
+    license: Option<String>,
+    #[serde(default)]
+    private: bool,
+    #[serde(default)]
+    template: bool,
+    #[serde(default)]
+    allowed_secrets: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PublishRequest {
+    name: String,
+    version: String,
+    description: String,
+    readme: Option<String>,
+    homepage: Option<String>,
+    repository: Option<String>,
+    license: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+/// Where the registry a request is for is served, `/t/<id>` for a tenant
+/// asked for by path and empty otherwise, for URLs back to it
+#[derive(Debug, Clone)]
+struct Mount(String);
+
+pub async fn handle(
+    registries: Arc<LocalRegistries>,
+    mut req: Request<Incoming>,
+) -> Result<LocalResponse, Infallible> {
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    let host = req
+        .headers()
+        .get(HOST)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+
+    let Some((tenant, within)) = registries.resolve(&path, host.as_deref()) else {
+        return Ok(error_response(
+            StatusCode::NOT_FOUND,
+            "unknown_tenant",
+            "No registry is hosted at this path",
+        ));
+    };
+    let store = match registries.tenant(&tenant).await {
+        Ok(store) => store,
+        Err(e) => {
+            eprintln!("DEBUG: Failed to open tenant {tenant}: {e:#}");
+            return Ok(error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "internal_error",
+                &e.to_string(),
+            ));
+        }
+    };
+
+    // Routes see the path within the tenant's registry
+    let within = format!("/{within}");
+    let mount = if path.starts_with("/t/") {
+        format!("/t/{tenant}")
+    } else {
+        String::new()
+    };
+    if !mount.is_empty() {
+        let uri = match req.uri().query() {
+            Some(query) => format!("{within}?{query}"),
+            None => within.clone(),
+        };
+        if let Ok(uri) = uri.parse() {
+            *req.uri_mut() = uri;
+        }
+    }
+    req.extensions_mut().insert(Mount(mount));
+    let limits = RouteLimits::for_route(&method, &within);
+
+    // Only routes that store a package, which a shutdown mustn't cut off
+    if registries.draining() && limits == RouteLimits::PACKAGE {
+        let mut response = error_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "shutting_down",
+            "The registry is shutting down; retry shortly",
+        );
+        response
+            .headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(DRAIN_RETRY_AFTER_SECS));
+        println!("{method} {path} {}", response.status().as_u16());
+        return Ok(response);
+    }
+
+    let response = match timeout(limits.timeout, respond(&store, req, limits.max_body)).await {
+        Ok(response) => response,
+        Err(_) => {
+            eprintln!("DEBUG: Local request timed out: {method} {path}");
+            error_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "timeout",
+                &format!("The request took longer than {}s", limits.timeout.as_secs()),
+            )
+        }
+    };
+
+    println!("{method} {path} {}", response.status().as_u16());
+    Ok(response)
+}
+
+/// Read a body of at most `max_body` bytes and route the request
+async fn respond(store: &LocalStore, req: Request<Incoming>, max_body: usize) -> LocalResponse {
+    let too_large = || {
+        error_response(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "payload_too_large",
+            &format!("Request bodies to this endpoint cannot exceed {max_body} bytes"),
+        )
+    };
+    let declared = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    if declared.is_some_and(|length| length > max_body as u64) {
+        return too_large();
+    }
+
+    let (parts, body) = req.into_parts();
+    let body = match Limited::new(body, max_body).collect().await {
+        Ok(body) => body.to_bytes(),
+        Err(e) if e.is::<LengthLimitError>() => return too_large(),
+        Err(e) => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                "bad_request",
+                &format!("Failed to read request body: {e}"),
+            )
+        }
+    };
+    let req = Request::from_parts(parts, body);
+
+    match route(store, &req).await {
+        Ok(response) => response,
+        Err(e) => {
+            eprintln!("DEBUG: Local request failed: {e:#}");
+            error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "internal_error",
+                &e.to_string(),
+            )
+        }
+    }
+}
+
+async fn route(store: &LocalStore, req: &Request<Bytes>) -> anyhow::Result<LocalResponse> {
+    let path = req.uri().path().trim_end_matches('/');
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    let user = authenticate(store, req).await?;
+    let user = user.as_ref();
+    // hyper leaves the body off responses to HEAD, so they can share GET's routes
+    let method = match req.method() {
+        &Method::HEAD => &Method::GET,
+        method => method,
+    };
+
+    // Batch info is a lookup that only posts because of its body
+    let changes = method != Method::GET && segments != ["api", "v1", "agents", "batch-info"];
+    if let Some(maintenance) = Maintenance::from_env().filter(|_| changes) {
+        return Ok(error_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "maintenance",
+            maintenance.message(),
+        ));
+    }
+
+    let response = match (method, segments.as_slice()) {
+        (&Method::GET, ["healthz"] | ["api", "healthz"]) => json_response(
+            StatusCode::OK,
+            &json!({ "status": "ok", "timestamp": Utc::now().to_rfc3339() }),
+        ),
+        (&Method::GET, ["readyz"] | ["api", "readyz"]) => json_response(
+            StatusCode::OK,
+            &json!({ "status": "ready", "timestamp": Utc::now().to_rfc3339() }),
+        ),
+        (&Method::GET, ["api", "health"]) => health(store).await?,
+        (&Method::GET, ["api", "v1", "auth", "whoami"]) => whoami(user),
+        (&Method::GET, ["api", "v1", "agents", "search"]) => search(store, req, user).await?,
+        (&Method::GET, ["api", "v1", "templates"]) => templates(store, req, user).await?,
+        (&Method::GET, ["api", "v1", "agents", "latest"]) => latest(store, user).await?,
+        (&Method::GET, ["api", "v1", "agents", "suggest"]) => suggest(store, req, user).await?,
+        (&Method::GET, ["api", "v1", "agents", "changes"]) => agent_changes(store, req).await?,
+        (&Method::POST, ["api", "v1", "agents", "batch-info"]) => {
+            batch_info(store, req, user).await?
+        }
+        (&Method::POST, ["api", "v1", "agents", "upload"]) => upload(store, req, user).await?,
+        (&Method::POST, ["api", "v1", "agents", "publish"]) => publish(store, req, user).await?,
+        (&Method::GET, ["api", "v1", "agents", name, "info"]) => {
+            info(store, &decode(name), user).await?
+        }
+        (&Method::GET, ["api", "v1", "agents", name, "versions"]) => {
+            versions(store, &decode(name), user).await?
+        }
+        (&Method::GET, ["api", "v1", "agents", name, "checksum"]) => {
+            checksum(store, req, &decode(name), user).await?
+        }
+        (&Method::GET, ["api", "v1", "agents", name, version, "download"]) => {
+            download(store, req, &decode(name), &decode(version), user).await?
+        }
+        (_, ["v2", ..]) => oci::handle(store, req, method, user).await?,
+        (_, ["api", ..]) => error_response(
+            StatusCode::NOT_IMPLEMENTED,
+            "not_available_locally",
+            &format!("{} {path} is not available in local mode", req.method()),
+        ),
+        _ => error_response(StatusCode::NOT_FOUND, "not_found", "Not found"),
+    };
+
+    Ok(response)
+}
+
+/// The auth stub: any bearer token or API key is accepted, as the user it was
+/// registered for or else the default user
+async fn authenticate(
+    store: &LocalStore,
+    req: &Request<Bytes>,
+) -> anyhow::Result<Option<LocalUser>> {
+    let headers = req.headers();
+    let bearer = headers
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .filter(|token| !token.trim().is_empty());
+    let api_key = headers
+        .get("x-api-key")
+        .and_then(|v| v.to_str().ok())
+        .filter(|key| !key.trim().is_empty());
+
+    match bearer.or(api_key) {
+        Some(token) => Ok(Some(store.user_for_token(token.trim()).await?)),
+        None => Ok(None),
+    }
+}
+
+async fn health(store: &LocalStore) -> anyhow::Result<LocalResponse> {
+    let agent_count = store.agent_count().await?;
+    Ok(json_response(
+        StatusCode::OK,
+        &json!({
+            "status": "healthy",
+            "service": "carp-api",
+            "environment": "local",
+            "message": "Local registry is running",
+            "agent_count": agent_count,
+            "timestamp": Utc::now().to_rfc3339(),
+            "error": null,
+        }),
+    ))
+}
+
+fn whoami(user: Option<&LocalUser>) -> LocalResponse {
+    match user {
+        Some(user) => json_response(
+            StatusCode::OK,
+            &json!({
+                "user_id": user.id,
+                "key_id": null,
+                "scopes": ["read", "write", "upload", "publish"],
+                "github_username": user.username,
+            }),
+        ),
+        None => authentication_required(),
+    }
+}
+
+async fn search(
+    store: &LocalStore,
+    req: &Request<Bytes>,
+    user: Option<&LocalUser>,
+) -> anyhow::Result<LocalResponse> {
+    let params = query_params(req);
+    let limit = params
+        .get("limit")
+        .and_then(|s| s.parse::<usize>().ok())
+        .unwrap_or(20);
+    let page = params
+        .get("page")
+        .and_then(|s| s.parse::<usize>().ok())
+        .unwrap_or(1);
+    let matching = match params.get("mode").map(String::as_str) {
+        Some("substring") => Match::Substring,
+        Some("exact") => Match::Exact,
+        Some("glob") => Match::Glob,
+        Some(_) => {
+            return Ok(error_response(
+                StatusCode::BAD_REQUEST,
+                "invalid_mode",
+                "mode must be one of substring, exact or glob in local mode",
+            ))
+        }
+        None if params.contains_key("exact") => Match::Exact,
+        None => Match::Substring,
+    };
+    let recent_first = match params.get("sort").map(String::as_str) {
+        None | Some("downloads") | Some("stars") => false,
+        Some("recent") => true,
+        Some(_) => {
+            return Ok(error_response(
+                StatusCode::BAD_REQUEST,
+                "invalid_sort",
+                "sort must be one of downloads, stars or recent",
+            ))
+        }
+    };
+    let mine = params.get("mine").is_some_and(|v| v == "true" || v == "1");
+    if mine && user.is_none() {
+        return Ok(authentication_required());
+    }
+
+    let query = SearchQuery {
+        query: params.get("q").map(String::as_str).unwrap_or(""),
+        matching,
+        recent_first,
+        limit,
+        page,
+        mine,
+        templates_only: false,
+    };
+    let (agents, total) = store.search(&query, user).await?;
+
+    Ok(json_response(
+        StatusCode::OK,
+        &json!({
+            "agents": agents.into_iter().map(Agent::from).collect::<Vec<_>>(),
+            "total": total,
+            "page": page,
+            "per_page": limit,
+        }),
+    ))
+}
+
+async fn templates(
+    store: &LocalStore,
+    req: &Request<Bytes>,
+    user: Option<&LocalUser>,
+) -> anyhow::Result<LocalResponse> {
+    let params = query_params(req);
+    let limit = params
+        .get("limit")
+        .and_then(|s| s.parse::<usize>().ok())
+        .unwrap_or(20)
+        .clamp(1, 100);
+    let page = params
+        .get("page")
+        .and_then(|s| s.parse::<usize>().ok())
+        .unwrap_or(1)
+        .max(1);
+
+    let query = SearchQuery {
+        query: params.get("q").map(String::as_str).unwrap_or(""),
+        matching: Match::Substring,
+        recent_first: false,
+        limit,
+        page,
+        mine: false,
+        templates_only: true,
+    };
+    let (templates, total) = store.search(&query, user).await?;
+
+    Ok(json_response(
+        StatusCode::OK,
+        &json!({
+            "templates": templates.into_iter().map(Agent::from).collect::<Vec<_>>(),
+            "total": total,
+            "page": page,
+            "per_page": limit,
+        }),
+    ))
+}
+
+async fn suggest(
+    store: &LocalStore,
+    req: &Request<Bytes>,
+    user: Option<&LocalUser>,
+) -> anyhow::Result<LocalResponse> {
+    let params = query_params(req);
+    let query = params.get("q").map(|q| q.trim()).unwrap_or("");
+    if query.is_empty() {
+        return Ok(error_response(
+            StatusCode::BAD_REQUEST,
+            "bad_request",
+            "The q parameter is required",
+        ));
+    }
+    let limit = params
+        .get("limit")
+        .and_then(|s| s.parse::<usize>().ok())
+        .unwrap_or(3)
+        .clamp(1, 10);
+
+    let names = store.agent_names(user).await?;
+    let suggestions = suggest::rank(query, names.iter().map(String::as_str), limit);
+
+    Ok(json_response(
+        StatusCode::OK,
+        &json!({ "query": query, "suggestions": suggestions }),
+    ))
+}
+
+async fn agent_changes(store: &LocalStore, req: &Request<Bytes>) -> anyhow::Result<LocalResponse> {
+    let params = query_params(req);
+    let since = match params.get("since") {
+        Some(since) => match feed::parse_cursor(since) {
+            Some(since) => since,
+            None => {
+                return Ok(error_response(
+                    StatusCode::BAD_REQUEST,
+                    "invalid_cursor",
+                    "since must be a cursor returned by this endpoint",
+                ))
+            }
+        },
+        None => 0,
+    };
+    let limit = params
+        .get("limit")
+        .and_then(|s| s.parse::<usize>().ok())
+        .unwrap_or(feed::DEFAULT_LIMIT)
+        .clamp(1, feed::MAX_LIMIT);
+
+    // One extra row says whether there's another page
+    let logged = store.changes(since, limit + 1).await?;
+    let mut agents = HashMap::new();
+    for (_, name, kind) in logged.iter().take(limit) {
+        if *kind != ChangeKind::Removed {
+            if let Some(agent) = store.agent(name, None).await? {
+                agents.insert(name.clone(), Agent::from(agent));
+            }
+        }
+    }
+
+    Ok(json_response(
+        StatusCode::OK,
+        &feed::page(since, limit, logged, |name| agents.remove(name)),
+    ))
+}
+
+async fn latest(store: &LocalStore, user: Option<&LocalUser>) -> anyhow::Result<LocalResponse> {
+    let query = SearchQuery {
+        query: "",
+        matching: Match::Substring,
+        recent_first: true,
+        limit: 10,
+        page: 1,
+        mine: false,
+        templates_only: false,
+    };
+    let (agents, _) = store.search(&query, user).await?;
+
+    let agents: Vec<_> = agents
+        .into_iter()
+        .map(|agent| {
+            json!({
+                "id": agent.id,
+                "name": agent.name,
+                "current_version": agent.current_version,
+                "description": agent.description,
+                "author_name": agent.author,
+                "created_at": agent.created_at,
+                "updated_at": agent.updated_at,
+                "download_count": agent.download_count,
+                "view_count": 0,
+                "tags": agent.tags,
+                "definition": null,
+                "user_id": agent.user_id,
+                "profiles": {
+                    "user_id": agent.user_id,
+                    "github_username": agent.author,
+                    "display_name": agent.author,
+                    "avatar_url": null,
+                },
+            })
+        })
+        .collect();
+
+    Ok(json_response(
+        StatusCode::OK,
+        &json!({ "agents": agents, "cached_at": Utc::now() }),
+    ))
+}
+
+async fn info(
+    store: &LocalStore,
+    name: &str,
+    user: Option<&LocalUser>,
+) -> anyhow::Result<LocalResponse> {
+    let Some(agent) = store.agent(name, user).await? else {
+        return Ok(agent_not_found(name));
+    };
+    let info = agent_info(store, agent).await?;
+
+    Ok(json_response(StatusCode::OK, &info))
+}
+
+async fn batch_info(
+    store: &LocalStore,
+    req: &Request<Bytes>,
+    user: Option<&LocalUser>,
+) -> anyhow::Result<LocalResponse> {
+    let request: BatchInfoRequest = match serde_json::from_slice(req.body()) {
+        Ok(request) => request,
+        Err(e) => {
+            return Ok(error_response(
+                StatusCode::BAD_REQUEST,
+                "bad_request",
+                &format!("Invalid request body: {e}"),
+            ))
+        }
+    };
+
+    let mut names: Vec<String> = Vec::new();
+    for name in request.names {
+        let name = name.trim().to_string();
+        if !name.is_empty() && !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    if names.is_empty() {
+        return Ok(error_response(
+            StatusCode::BAD_REQUEST,
+            "bad_request",
+            "names must list at least one agent",
+        ));
+    }
+    if names.len() > MAX_BATCH_SIZE {
+        return Ok(error_response(
+            StatusCode::BAD_REQUEST,
+            "bad_request",
+            &format!("At most {MAX_BATCH_SIZE} agents can be looked up at once"),
+        ));
+    }
+
+    let mut agents = Vec::new();
+    let mut missing = Vec::new();
+    for name in names {
+        match store.agent(&name, user).await? {
+            Some(agent) => agents.push(agent_info(store, agent).await?),
+            None => missing.push(name),
+        }
+    }
+
+    Ok(json_response(
+        StatusCode::OK,
+        &json!({ "agents": agents, "missing": missing }),
+    ))
+}
+
+/// An agent as the info endpoints describe it
+async fn agent_info(store: &LocalStore, agent: StoredAgent) -> anyhow::Result<serde_json::Value> {
+    let versions = store.versions(&agent).await?;
+    let downloadable: Vec<&str> = versions
+        .iter()
+        .filter(|version| !version.yanked)
+        .map(|version| version.version.as_str())
+        .collect();
+
+    Ok(json!({
+        "name": agent.name,
+        "version": downloadable.last().copied().unwrap_or(&agent.current_version),
+        "versions": downloadable,
+        "description": agent.description,
+        "author": agent.author,
+        "created_at": agent.created_at,
+        "updated_at": agent.updated_at,
+        "download_count": agent.download_count,
+        "star_count": 0,
+        "rating_count": 0,
+        "rating_average": null,
+        "tags": agent.tags,
+        "homepage": agent.homepage,
+        "repository": agent.repository,
+        "license": agent.license,
+        "signed": false,
+        "transparency_log_index": null,
+        "signed_at": null,
+        "provenance": null,
+        "is_public": agent.is_public,
+    }))
+}
+
+async fn versions(
+    store: &LocalStore,
+    name: &str,
+    user: Option<&LocalUser>,
+) -> anyhow::Result<LocalResponse> {
+    let Some(agent) = store.agent(name, user).await? else {
+        return Ok(agent_not_found(name));
+    };
+    let versions: Vec<_> = store
+        .versions(&agent)
+        .await?
+        .into_iter()
+        .map(|version| {
+            json!({
+                "version": version.version,
+                "checksum": version.checksum,
+                "file_size": version.file_size,
+                "yanked": version.yanked,
+                "yanked_reason": null,
+                "created_at": version.created_at,
+            })
+        })
+        .collect();
+
+    Ok(json_response(
+        StatusCode::OK,
+        &json!({ "name": agent.name, "versions": versions }),
+    ))
+}
+
+/// The latest version's checksum, also in the `ETag`, `X-Carp-Version` and
+/// `X-Carp-Checksum` headers so `HEAD` is enough
+async fn checksum(
+    store: &LocalStore,
+    req: &Request<Bytes>,
+    name: &str,
+    user: Option<&LocalUser>,
+) -> anyhow::Result<LocalResponse> {
+    let Some(agent) = store.agent(name, user).await? else {
+        return Ok(agent_not_found(name));
+    };
+    let Some(latest) = store.version(&agent, "latest").await? else {
+        return Ok(agent_not_found(name));
+    };
+
+    let etag = format!("\"{}\"", latest.checksum);
+    let not_modified = req
+        .headers()
+        .get("if-none-match")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|tags| tags.split(',').any(|tag| tag.trim() == etag));
+
+    let mut response = if not_modified {
+        let mut response = Response::new(Full::new(Bytes::new()));
+        *response.status_mut() = StatusCode::NOT_MODIFIED;
+        response
+    } else {
+        json_response(
+            StatusCode::OK,
+            &json!({
+                "name": agent.name,
+                "version": latest.version,
+                "checksum": latest.checksum,
+            }),
+        )
+    };
+    let headers = response.headers_mut();
+    headers.insert("etag", HeaderValue::from_str(&etag)?);
+    headers.insert("x-carp-version", HeaderValue::from_str(&latest.version)?);
+    headers.insert("x-carp-checksum", HeaderValue::from_str(&latest.checksum)?);
+    Ok(response)
+}
+
+async fn download(
+    store: &LocalStore,
+    req: &Request<Bytes>,
+    name: &str,
+    version: &str,
+    user: Option<&LocalUser>,
+) -> anyhow::Result<LocalResponse> {
+    let Some(agent) = store.agent(name, user).await? else {
+        return Ok(agent_not_found(name));
+    };
+    let Some(stored) = store.version(&agent, version).await? else {
+        return Ok(error_response(
+            StatusCode::NOT_FOUND,
+            "not_found",
+            &format!("Agent '{name}' has no version '{version}'"),
+        ));
+    };
+
+    let params = query_params(req);
+    let flag = |name: &str| params.get(name).is_some_and(|v| v == "true" || v == "1");
+
+    // `?raw=true` is the package itself, which the JSON response links to
+    if flag("raw") {
+        let package = store.read_package(&stored)?;
+        let mut response = Response::new(Full::new(package));
+        response
+            .headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_str(&stored.content_type)?);
+        return Ok(response);
+    }
+
+    let content = if flag("inline") {
+        Some(STANDARD.encode(store.read_package(&stored)?))
+    } else {
+        None
+    };
+    let download_url = if content.is_some() {
+        String::new()
+    } else {
+        let host = req
+            .headers()
+            .get("host")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("localhost:3000");
+        let mount = req
+            .extensions()
+            .get::<Mount>()
+            .map_or("", |mount| mount.0.as_str());
+        format!(
+            "http://{host}{mount}/api/v1/agents/{}/{}/download?raw=true",
+            urlencoding::encode(&agent.name),
+            urlencoding::encode(&stored.version)
+        )
+    };
+
+    // Prefetches for a picker aren't downloads until the user picks
+    if !flag("prefetch") {
+        store.record_download(&agent, &stored.version).await?;
+    }
+
+    Ok(json_response(
+        StatusCode::OK,
+        &json!({
+            "agent_id": agent.id,
+            "name": agent.name,
+            "author": agent.author,
+            "version": stored.version,
+            "download_url": download_url,
+            "file_size": stored.file_size,
+            "checksum": stored.checksum,
+            "content_type": stored.content_type,
+            "definition": stored.definition,
+            "content": content,
+        }),
+    ))
+}
+
+async fn upload(
+    store: &LocalStore,
+    req: &Request<Bytes>,
+    user: Option<&LocalUser>,
+) -> anyhow::Result<LocalResponse> {
+    let Some(user) = user else {
+        return Ok(authentication_required());
+    };
+
+    let request: UploadAgentRequest = match serde_json::from_slice(req.body()) {
+        Ok(request) => request,
+        Err(e) => {
+            return Ok(error_response(
+                StatusCode::BAD_REQUEST,
+                "bad_request",
+                &format!("Invalid JSON in request body: {e}"),
+            ))
+        }
+    };
+
+    let mut errors: Vec<_> = validate_name(&request.name)
+        .err()
+        .map(|message| ("name".to_string(), message))
+        .into_iter()
+        .collect();
+    if request.description.trim().is_empty() {
+        errors.push((
+            "description".to_string(),
+            "Description cannot be empty".to_string(),
+        ));
+    }
+    let definition = match parse_agent_definition(&request.content) {
+        Ok(definition) => Some(definition),
+        Err(message) => {
+            errors.push(("content".to_string(), message));
+            None
+        }
+    };
+    let policy = ContentPolicy::from_env().map_err(anyhow::Error::msg)?;
+    errors.extend(
+        policy
+            .check_content("content", &request.content, &request.allowed_secrets)
+            .into_iter()
+            .chain(policy.check_text(
+                "description",
+                &request.description,
+                &request.allowed_secrets,
+            )),
+    );
+    let Some(definition) = definition.filter(|_| errors.is_empty()) else {
+        return Ok(problem_response(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            validation_error(&errors),
+        ));
+    };
+
+    let new = NewVersion {
+        version: request.version.unwrap_or_else(|| "1.0.0".to_string()),
+        description: request.description,
+        tags: request.tags,
+        package: build_markdown_package(&request.name, &request.content)?,
+        readme: Some(request.content),
+        homepage: request.homepage,
+        repository: request.repository,
+        license: request.license,
+        is_public: !request.private,
+        is_template: Some(request.template),
+        definition,
+        format: PackageFormat::Zip,
+        name: request.name,
+    };
+
+    published(
+        store.publish(user, new).await,
+        "Agent uploaded successfully",
+    )
+}
+
+async fn publish(
+    store: &LocalStore,
+    req: &Request<Bytes>,
+    user: Option<&LocalUser>,
+) -> anyhow::Result<LocalResponse> {
+    let Some(user) = user else {
+        return Ok(authentication_required());
+    };
+
+    let boundary = req
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| v.starts_with("multipart/form-data"))
+        .and_then(|v| {
+            v.split(';')
+                .find_map(|param| param.trim().strip_prefix("boundary="))
+        })
+        .map(|boundary| boundary.trim_matches('"').to_string());
+    let Some(boundary) = boundary else {
+        return Ok(error_response(
+            StatusCode::BAD_REQUEST,
+            "bad_request",
+            "Content-Type must be multipart/form-data",
+        ));
+    };
+
+    let metadata = multipart_part(req.body(), &boundary, "metadata")
+        .map(|(_, data)| serde_json::from_slice::<PublishRequest>(data));
+    let request = match metadata {
+        Some(Ok(request)) => request,
+        Some(Err(e)) => {
+            return Ok(error_response(
+                StatusCode::BAD_REQUEST,
+                "bad_request",
+                &format!("Invalid metadata: {e}"),
+            ))
+        }
+        None => {
+            return Ok(error_response(
+                StatusCode::BAD_REQUEST,
+                "bad_request",
+                "Request must include the agent's metadata in a 'metadata' part",
+            ))
+        }
+    };
+    if let Err(message) = validate_name(&request.name) {
+        return Ok(error_response(
+            StatusCode::BAD_REQUEST,
+            "invalid_name",
+            &message,
+        ));
+    }
+
+    // Package archives aren't unpacked here; the text shown on the agent's page is
+    let policy = ContentPolicy::from_env().map_err(anyhow::Error::msg)?;
+    let violations: Vec<String> = policy
+        .check_text("description", &request.description, &[])
+        .into_iter()
+        .chain(policy.check_content("readme", request.readme.as_deref().unwrap_or(""), &[]))
+        .map(|(field, message)| format!("{field}: {message}"))
+        .collect();
+    if !violations.is_empty() {
+        return Ok(error_response(
+            StatusCode::BAD_REQUEST,
+            "content_policy",
+            &violations.join("; "),
+        ));
+    }
+
+    let package = match multipart_part(req.body(), &boundary, "content") {
+        Some((content_type, data)) => validate_package(&content_type, data)
+            .map(|format| (format, Bytes::copy_from_slice(data))),
+        None => Err("Request must include the package archive in a 'content' part".to_string()),
+    };
+    let (format, package) = match package {
+        Ok(package) => package,
+        Err(message) => {
+            return Ok(error_response(
+                StatusCode::BAD_REQUEST,
+                "invalid_package",
+                &message,
+            ))
+        }
+    };
+
+    let new = NewVersion {
+        definition: json!({
+            "metadata": {
+                "name": request.name,
+                "version": request.version,
+                "description": request.description,
+                "tags": request.tags,
+            },
+        }),
+        name: request.name,
+        version: request.version,
+        description: request.description,
+        tags: request.tags,
+        readme: request.readme,
+        homepage: request.homepage,
+        repository: request.repository,
+        license: request.license,
+        is_public: true,
+        is_template: None,
+        format,
+        package,
+    };
+
+    published(
+        store.publish(user, new).await,
+        "Agent published successfully",
+    )
+}
+
+/// 201 with the agent, or why it couldn't be published
+fn published(
+    result: Result<StoredAgent, PublishError>,
+    message: &str,
+) -> anyhow::Result<LocalResponse> {
+    match result {
+        Ok(agent) => Ok(json_response(
+            StatusCode::CREATED,
+            &json!({
+                "success": true,
+                "message": message,
+                "agent": Agent::from(agent),
+            }),
+        )),
+        Err(PublishError::VersionExists) => Ok(error_response(
+            StatusCode::CONFLICT,
+            "version_exists",
+            "This version has already been published",
+        )),
+        Err(PublishError::NotOwner) => Ok(error_response(
+            StatusCode::FORBIDDEN,
+            "forbidden",
+            "The agent belongs to another user",
+        )),
+        Err(PublishError::Internal(e)) => Err(e),
+    }
+}
+
+fn validate_name(name: &str) -> Result<(), String> {
+    if name.trim().is_empty() {
+        Err("Agent name cannot be empty".to_string())
+    } else if !name
+        .chars()
+        .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+    {
+        Err(
+            "Agent name can only contain alphanumeric characters, hyphens, and underscores"
+                .to_string(),
+        )
+    } else if name.len() > 100 {
+        Err("Agent name cannot exceed 100 characters".to_string())
+    } else {
+        Ok(())
+    }
+}
+
+/// Definition in the shape the upload endpoint stores
+fn parse_agent_definition(content: &str) -> Result<serde_json::Value, String> {
+    let rest = content
+        .strip_prefix("---")
+        .ok_or_else(|| "Content must contain YAML frontmatter starting with ---".to_string())?;
+    let (frontmatter, body) = rest
+        .split_once("\n---")
+        .ok_or_else(|| "Invalid YAML frontmatter: missing closing ---".to_string())?;
+
+    let frontmatter: serde_json::Value =
+        serde_yaml::from_str(frontmatter).map_err(|e| format!("Invalid YAML frontmatter: {e}"))?;
+
+    Ok(json!({
+        "metadata": frontmatter,
+        "content": body.trim_start_matches('-').trim_start_matches('\n'),
+        "format": "markdown",
+        "frontmatter_type": "yaml"
+    }))
+}
+
+/// Find a named part of a multipart body, returning its content type and bytes
+fn multipart_part<'a>(body: &'a [u8], boundary: &str, name: &str) -> Option<(String, &'a [u8])> {
+    let delimiter = format!("--{boundary}");
+    let delimiter = delimiter.as_bytes();
+    let disposition = format!("name=\"{name}\"");
+
+    let mut rest = body;
+    while let Some(start) = find(rest, delimiter) {
+        rest = &rest[start + delimiter.len()..];
+        let end = find(rest, delimiter).unwrap_or(rest.len());
+        let part = rest[..end].strip_prefix(b"\r\n").unwrap_or(&rest[..end]);
+
+        let Some(header_end) = find(part, b"\r\n\r\n") else {
+            continue;
+        };
+        let headers = String::from_utf8_lossy(&part[..header_end]).to_ascii_lowercase();
+
+        if headers.contains(&disposition) {
+            let content_type = headers
+                .lines()
+                .find_map(|line| line.strip_prefix("content-type:"))
+                .unwrap_or("application/octet-stream")
+                .trim()
+                .to_string();
+            let data = &part[header_end + 4..];
+            let data = data.strip_suffix(b"\r\n").unwrap_or(data);
+            return Some((content_type, data));
+        }
+    }
+
+    None
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn query_params(req: &Request<Bytes>) -> HashMap<String, String> {
+    url::form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes())
+        .into_owned()
+        .collect()
+}
+
+fn decode(segment: &str) -> String {
+    urlencoding::decode(segment)
+        .map(|decoded| decoded.into_owned())
+        .unwrap_or_else(|_| segment.to_string())
+}
+
+fn json_response<T: Serialize>(status: StatusCode, body: &T) -> LocalResponse {
+    let mut response = Response::new(Full::new(Bytes::from(
+        serde_json::to_vec(body).unwrap_or_default(),
+    )));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+}
+
+fn error_response(status: StatusCode, error: &str, message: &str) -> LocalResponse {
+    problem_response(
+        status,
+        ApiError {
+            error: error.to_string(),
+            message: message.to_string(),
+            details: None,
+        },
+    )
+}
+
+fn problem_response(status: StatusCode, error: ApiError) -> LocalResponse {
+    let problem = Problem::new(status.as_u16(), error);
+    let mut response = json_response(status, &problem);
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static(PROBLEM_CONTENT_TYPE));
+    response
+}
+
+fn authentication_required() -> LocalResponse {
+    error_response(
+        StatusCode::UNAUTHORIZED,
+        "authentication_required",
+        "Pass any API key; local mode accepts every key",
+    )
+}
+
+fn agent_not_found(name: &str) -> LocalResponse {
+    error_response(
+        StatusCode::NOT_FOUND,
+        "not_found",
+        &format!("Agent '{name}' not found"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_multipart_part_finds_named_parts() {
+        let body = b"--b\r\nContent-Disposition: form-data; name=\"metadata\"\r\n\r\n{\"a\":1}\r\n\
+                     --b\r\nContent-Disposition: form-data; name=\"content\"; filename=\"agent.zip\"\r\n\
+                     Content-Type: application/zip\r\n\r\nPK\x03\x04\r\n--b--\r\n";
+
+        let (_, metadata) = multipart_part(body, "b", "metadata").unwrap();
+        assert_eq!(metadata, b"{\"a\":1}");
+        let (content_type, content) = multipart_part(body, "b", "content").unwrap();
+        assert_eq!(content_type, "application/zip");
+        assert_eq!(content, b"PK\x03\x04");
+        assert!(multipart_part(body, "b", "missing").is_none());
+    }
+
+    #[test]
+    fn test_parse_agent_definition() {
+        let definition =
+            parse_agent_definition("---\nname: helper\ndescription: Helps\n---\n# Helper\n")
+                .unwrap();
+        assert_eq!(definition["metadata"]["name"], "helper");
+        assert_eq!(definition["content"], "# Helper\n");
+        assert!(parse_agent_definition("# No frontmatter").is_err());
+    }
+}
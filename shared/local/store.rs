@@ -0,0 +1,997 @@
+//! SQLite and filesystem storage behind the local registry
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions, SqliteRow};
+use sqlx::Row;
+use std::fs;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+use crate::archive::{package_checksum, PackageFormat};
+use crate::auth::hash_api_key;
+use crate::registry::changes::ChangeKind;
+use crate::registry::search::page_rows;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS users (
+  id TEXT PRIMARY KEY,
+  username TEXT NOT NULL UNIQUE,
+  created_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS tokens (
+  token_hash TEXT PRIMARY KEY,
+  user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE
+);
+CREATE TABLE IF NOT EXISTS agents (
+  id TEXT PRIMARY KEY,
+  user_id TEXT NOT NULL REFERENCES users(id),
+  name TEXT NOT NULL UNIQUE,
+  description TEXT NOT NULL,
+  tags TEXT NOT NULL DEFAULT '[]',
+  is_public INTEGER NOT NULL DEFAULT 1,
+  is_template INTEGER NOT NULL DEFAULT 0,
+  current_version TEXT NOT NULL,
+  readme TEXT,
+  homepage TEXT,
+  repository TEXT,
+  license TEXT,
+  download_count INTEGER NOT NULL DEFAULT 0,
+  created_at TEXT NOT NULL,
+  updated_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS agent_versions (
+  agent_id TEXT NOT NULL REFERENCES agents(id) ON DELETE CASCADE,
+  version TEXT NOT NULL,
+  definition TEXT NOT NULL,
+  file_path TEXT NOT NULL,
+  content_type TEXT NOT NULL,
+  file_size INTEGER NOT NULL,
+  checksum TEXT NOT NULL,
+  download_count INTEGER NOT NULL DEFAULT 0,
+  yanked INTEGER NOT NULL DEFAULT 0,
+  created_at TEXT NOT NULL,
+  PRIMARY KEY (agent_id, version)
+);
+CREATE TABLE IF NOT EXISTS agent_changes (
+  seq INTEGER PRIMARY KEY AUTOINCREMENT,
+  name TEXT NOT NULL,
+  kind TEXT NOT NULL,
+  is_public INTEGER NOT NULL,
+  changed_at TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS agent_changes_name ON agent_changes (name, seq);
+";
+
+/// Columns added after a table was first created, as `(table, column, definition)`,
+/// added to registries created before them
+const ADDED_COLUMNS: &[(&str, &str, &str)] = &[
+    ("agent_versions", "yanked", "INTEGER NOT NULL DEFAULT 0"),
+    ("agents", "is_template", "INTEGER NOT NULL DEFAULT 0"),
+];
+
+/// The user requests with a token act as
+#[derive(Debug, Clone)]
+pub struct LocalUser {
+    pub id: Uuid,
+    pub username: String,
+}
+
+/// An agent with its newest version's metadata
+#[derive(Debug, Clone)]
+pub struct StoredAgent {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub author: String,
+    pub name: String,
+    pub description: String,
+    pub tags: Vec<String>,
+    pub is_public: bool,
+    /// A starting point for `carp new --template`
+    pub is_template: bool,
+    pub current_version: String,
+    pub readme: Option<String>,
+    pub homepage: Option<String>,
+    pub repository: Option<String>,
+    pub license: Option<String>,
+    pub download_count: u64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// One published version and where its package is kept
+#[derive(Debug, Clone)]
+pub struct StoredVersion {
+    pub version: String,
+    pub definition: serde_json::Value,
+    pub file_path: String,
+    pub content_type: String,
+    pub file_size: u64,
+    pub checksum: String,
+    /// Yanked versions stay listed but can't be downloaded
+    pub yanked: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A version to add, creating the agent on its first version
+pub struct NewVersion {
+    pub name: String,
+    pub version: String,
+    pub description: String,
+    pub tags: Vec<String>,
+    pub readme: Option<String>,
+    pub homepage: Option<String>,
+    pub repository: Option<String>,
+    pub license: Option<String>,
+    pub is_public: bool,
+    /// Whether the agent is a template; `None` keeps its current flag
+    pub is_template: Option<bool>,
+    pub definition: serde_json::Value,
+    pub format: PackageFormat,
+    pub package: Bytes,
+}
+
+/// How search matches the query against agent names
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Match {
+    Substring,
+    Exact,
+    Glob,
+}
+
+/// Search options, mirroring the hosted endpoint's query parameters
+pub struct SearchQuery<'a> {
+    pub query: &'a str,
+    pub matching: Match,
+    pub recent_first: bool,
+    pub limit: usize,
+    pub page: usize,
+    /// Only the viewer's own agents
+    pub mine: bool,
+    /// Only agents flagged as templates
+    pub templates_only: bool,
+}
+
+/// Why a version couldn't be published
+#[derive(Debug)]
+pub enum PublishError {
+    /// The agent belongs to someone else
+    NotOwner,
+    VersionExists,
+    Internal(anyhow::Error),
+}
+
+impl From<anyhow::Error> for PublishError {
+    fn from(e: anyhow::Error) -> Self {
+        PublishError::Internal(e)
+    }
+}
+
+impl From<sqlx::Error> for PublishError {
+    fn from(e: sqlx::Error) -> Self {
+        PublishError::Internal(e.into())
+    }
+}
+
+/// Agents in `registry.db` and their packages under `packages/`
+pub struct LocalStore {
+    pool: SqlitePool,
+    packages: PathBuf,
+    user: LocalUser,
+}
+
+const VERSION_COLUMNS: &str =
+    "version, definition, file_path, content_type, file_size, checksum, yanked, created_at";
+
+const AGENT_COLUMNS: &str = "a.id, a.user_id, u.username, a.name, a.description, a.tags,
+     a.is_public, a.is_template, a.current_version, a.readme, a.homepage, a.repository, a.license,
+     a.download_count, a.created_at, a.updated_at";
+
+impl LocalStore {
+    /// Open or create the registry in `data_dir`, registering `username`
+    pub async fn open(data_dir: &Path, username: &str) -> Result<Self> {
+        let packages = data_dir.join("packages");
+        fs::create_dir_all(&packages)
+            .with_context(|| format!("Failed to create {}", packages.display()))?;
+
+        let options = SqliteConnectOptions::new()
+            .filename(data_dir.join("registry.db"))
+            .create_if_missing(true)
+            .foreign_keys(true);
+        let pool = SqlitePoolOptions::new()
+            .connect_with(options)
+            .await
+            .context("Failed to open the local registry database")?;
+
+        sqlx::raw_sql(SCHEMA)
+            .execute(&pool)
+            .await
+            .context("Failed to create the local registry schema")?;
+        add_missing_columns(&pool).await?;
+        start_change_log(&pool).await?;
+
+        let user = add_user(&pool, username).await?;
+        Ok(Self {
+            pool,
+            packages,
+            user,
+        })
+    }
+
+    /// The user requests with an unregistered token act as
+    pub fn user(&self) -> &LocalUser {
+        &self.user
+    }
+
+    /// Register another user, or look up an existing one
+    pub async fn add_user(&self, username: &str) -> Result<LocalUser> {
+        add_user(&self.pool, username).await
+    }
+
+    /// Make requests with `token` act as `user` rather than the default user
+    pub async fn add_token(&self, user: &LocalUser, token: &str) -> Result<()> {
+        sqlx::query("INSERT OR REPLACE INTO tokens (token_hash, user_id) VALUES (?, ?)")
+            .bind(hash_api_key(token))
+            .bind(user.id.to_string())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Who a request with `token` acts as
+    pub async fn user_for_token(&self, token: &str) -> Result<LocalUser> {
+        let row: Option<(String, String)> = sqlx::query_as(
+            "SELECT u.id, u.username FROM tokens t JOIN users u ON u.id = t.user_id
+             WHERE t.token_hash = ?",
+        )
+        .bind(hash_api_key(token))
+        .fetch_optional(&self.pool)
+        .await?;
+
+        match row {
+            Some((id, username)) => Ok(LocalUser {
+                id: Uuid::parse_str(&id)?,
+                username,
+            }),
+            None => Ok(self.user.clone()),
+        }
+    }
+
+    pub async fn agent_count(&self) -> Result<i64> {
+        Ok(sqlx::query_scalar("SELECT COUNT(*) FROM agents")
+            .fetch_one(&self.pool)
+            .await?)
+    }
+
+    /// Matching agents the viewer may see, and how many there are in total
+    pub async fn search(
+        &self,
+        search: &SearchQuery<'_>,
+        viewer: Option<&LocalUser>,
+    ) -> Result<(Vec<StoredAgent>, usize)> {
+        let viewer_id = viewer.map(|user| user.id.to_string()).unwrap_or_default();
+        let mut filters = vec![if search.mine {
+            "a.user_id = ?"
+        } else {
+            "(a.is_public = 1 OR a.user_id = ?)"
+        }];
+        let mut args = vec![viewer_id];
+        if search.templates_only {
+            filters.push("a.is_template = 1");
+        }
+
+        let query = search.query.trim();
+        if !query.is_empty() {
+            match search.matching {
+                Match::Exact => {
+                    filters.push("a.name = ?");
+                    args.push(query.to_string());
+                }
+                Match::Glob => {
+                    filters.push("a.name GLOB ?");
+                    args.push(query.to_string());
+                }
+                Match::Substring => {
+                    filters.push(
+                        "(a.name LIKE ? ESCAPE '\\' OR a.description LIKE ? ESCAPE '\\'
+                          OR a.tags LIKE ? ESCAPE '\\')",
+                    );
+                    let pattern = format!(
+                        "%{}%",
+                        query
+                            .replace('\\', "\\\\")
+                            .replace('%', "\\%")
+                            .replace('_', "\\_")
+                    );
+                    args.extend([pattern.clone(), pattern.clone(), pattern]);
+                }
+            }
+        }
+
+        let filter = filters.join(" AND ");
+        let order = if search.recent_first {
+            "a.updated_at DESC"
+        } else {
+            "a.download_count DESC, a.name"
+        };
+
+        let count_sql = format!("SELECT COUNT(*) FROM agents a WHERE {filter}");
+        let mut count = sqlx::query_scalar::<_, i64>(&count_sql);
+        for arg in &args {
+            count = count.bind(arg);
+        }
+        let total = count.fetch_one(&self.pool).await?;
+
+        let page = page_rows(search.page, search.limit);
+        let sql = format!(
+            "SELECT {AGENT_COLUMNS} FROM agents a JOIN users u ON u.id = a.user_id
+             WHERE {filter} ORDER BY {order} LIMIT ? OFFSET ?"
+        );
+        let mut rows = sqlx::query(&sql);
+        for arg in &args {
+            rows = rows.bind(arg);
+        }
+        let agents = rows
+            .bind(i64::try_from(search.limit).unwrap_or(i64::MAX))
+            .bind(i64::try_from(page.start).unwrap_or(i64::MAX))
+            .fetch_all(&self.pool)
+            .await?
+            .iter()
+            .map(agent_from_row)
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok((agents, total as usize))
+    }
+
+    /// Names of every agent the viewer may see
+    pub async fn agent_names(&self, viewer: Option<&LocalUser>) -> Result<Vec<String>> {
+        Ok(
+            sqlx::query_scalar("SELECT name FROM agents WHERE is_public = 1 OR user_id = ?")
+                .bind(viewer.map(|user| user.id.to_string()).unwrap_or_default())
+                .fetch_all(&self.pool)
+                .await?,
+        )
+    }
+
+    /// The latest change to each public agent logged after `since`, oldest
+    /// first, as `(seq, name, kind)`
+    pub async fn changes(
+        &self,
+        since: i64,
+        limit: usize,
+    ) -> Result<Vec<(i64, String, ChangeKind)>> {
+        let rows: Vec<(i64, String, String)> = sqlx::query_as(
+            "SELECT c.seq, c.name, c.kind FROM agent_changes c
+             WHERE c.is_public = 1 AND c.seq > ?
+               AND c.seq = (SELECT MAX(l.seq) FROM agent_changes l
+                            WHERE l.name = c.name AND l.is_public = 1)
+             ORDER BY c.seq LIMIT ?",
+        )
+        .bind(since)
+        .bind(i64::try_from(limit).unwrap_or(i64::MAX))
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter()
+            .map(|(seq, name, kind)| {
+                let kind = ChangeKind::parse(&kind)
+                    .with_context(|| format!("Unknown change kind {kind:?}"))?;
+                Ok((seq, name, kind))
+            })
+            .collect()
+    }
+
+    /// The agent called `name`, if the viewer may see it
+    pub async fn agent(
+        &self,
+        name: &str,
+        viewer: Option<&LocalUser>,
+    ) -> Result<Option<StoredAgent>> {
+        let row = sqlx::query(&format!(
+            "SELECT {AGENT_COLUMNS} FROM agents a JOIN users u ON u.id = a.user_id
+             WHERE a.name = ? AND (a.is_public = 1 OR a.user_id = ?)"
+        ))
+        .bind(name)
+        .bind(viewer.map(|user| user.id.to_string()).unwrap_or_default())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.as_ref().map(agent_from_row).transpose()
+    }
+
+    /// A downloadable version of the agent, the newest one for `latest`
+    pub async fn version(
+        &self,
+        agent: &StoredAgent,
+        version: &str,
+    ) -> Result<Option<StoredVersion>> {
+        let row = if version == "latest" {
+            sqlx::query(&format!(
+                "SELECT {VERSION_COLUMNS} FROM agent_versions
+                 WHERE agent_id = ? AND yanked = 0 ORDER BY created_at DESC, rowid DESC LIMIT 1"
+            ))
+            .bind(agent.id.to_string())
+            .fetch_optional(&self.pool)
+            .await?
+        } else {
+            sqlx::query(&format!(
+                "SELECT {VERSION_COLUMNS} FROM agent_versions
+                 WHERE agent_id = ? AND version = ? AND yanked = 0"
+            ))
+            .bind(agent.id.to_string())
+            .bind(version)
+            .fetch_optional(&self.pool)
+            .await?
+        };
+
+        row.as_ref().map(version_from_row).transpose()
+    }
+
+    /// Every published version of the agent, yanked ones included, oldest first
+    pub async fn versions(&self, agent: &StoredAgent) -> Result<Vec<StoredVersion>> {
+        sqlx::query(&format!(
+            "SELECT {VERSION_COLUMNS} FROM agent_versions
+             WHERE agent_id = ? ORDER BY created_at, rowid"
+        ))
+        .bind(agent.id.to_string())
+        .fetch_all(&self.pool)
+        .await?
+        .iter()
+        .map(version_from_row)
+        .collect()
+    }
+
+    /// Store the package and record the version, returning the updated agent
+    pub async fn publish(
+        &self,
+        user: &LocalUser,
+        new: NewVersion,
+    ) -> Result<StoredAgent, PublishError> {
+        let now = Utc::now();
+        let mut tx = self.pool.begin().await?;
+
+        let existing: Option<(String, String, bool)> =
+            sqlx::query_as("SELECT id, user_id, is_public FROM agents WHERE name = ?")
+                .bind(&new.name)
+                .fetch_optional(&mut *tx)
+                .await?;
+
+        // The change as copies of the listing see it, and whether they see it
+        let (agent_id, (change, change_is_public)) = match existing {
+            Some((_, owner, _)) if owner != user.id.to_string() => {
+                return Err(PublishError::NotOwner)
+            }
+            // Copies drop agents made private and pick up ones made public
+            Some((id, _, true)) if !new.is_public => (id, (ChangeKind::Removed, true)),
+            Some((id, _, false)) if new.is_public => (id, (ChangeKind::Created, true)),
+            Some((id, _, _)) => (id, (ChangeKind::Updated, new.is_public)),
+            None => (
+                Uuid::new_v4().to_string(),
+                (ChangeKind::Created, new.is_public),
+            ),
+        };
+
+        let taken: Option<i64> =
+            sqlx::query_scalar("SELECT 1 FROM agent_versions WHERE agent_id = ? AND version = ?")
+                .bind(&agent_id)
+                .bind(&new.version)
+                .fetch_optional(&mut *tx)
+                .await?;
+        if taken.is_some() {
+            return Err(PublishError::VersionExists);
+        }
+
+        sqlx::query(
+            "INSERT INTO agents (id, user_id, name, description, tags, is_public, is_template,
+                                 current_version, readme, homepage, repository, license,
+                                 created_at, updated_at)
+             VALUES (?, ?, ?, ?, ?, ?, COALESCE(?, 0), ?, ?, ?, ?, ?, ?, ?)
+             ON CONFLICT (id) DO UPDATE SET
+               description = excluded.description, tags = excluded.tags,
+               is_public = excluded.is_public,
+               is_template = COALESCE(?, agents.is_template),
+               current_version = excluded.current_version,
+               readme = excluded.readme, homepage = excluded.homepage,
+               repository = excluded.repository, license = excluded.license,
+               updated_at = excluded.updated_at",
+        )
+        .bind(&agent_id)
+        .bind(user.id.to_string())
+        .bind(&new.name)
+        .bind(&new.description)
+        .bind(serde_json::to_string(&new.tags).map_err(anyhow::Error::from)?)
+        .bind(new.is_public)
+        .bind(new.is_template)
+        .bind(&new.version)
+        .bind(&new.readme)
+        .bind(&new.homepage)
+        .bind(&new.repository)
+        .bind(&new.license)
+        .bind(now)
+        .bind(now)
+        .bind(new.is_template)
+        .execute(&mut *tx)
+        .await?;
+
+        let file_path = format!("{}/{}.{}", new.name, new.version, new.format.extension());
+        sqlx::query(
+            "INSERT INTO agent_versions (agent_id, version, definition, file_path, content_type,
+                                         file_size, checksum, created_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&agent_id)
+        .bind(&new.version)
+        .bind(new.definition.to_string())
+        .bind(&file_path)
+        .bind(new.format.content_type())
+        .bind(new.package.len() as i64)
+        .bind(package_checksum(&new.package))
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+        log_change(&mut tx, &new.name, change, change_is_public, now).await?;
+
+        // Write the package before committing so a recorded version always has one
+        let path = self.packages.join(&file_path);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(anyhow::Error::from)?;
+        }
+        fs::write(&path, &new.package)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+
+        tx.commit().await?;
+
+        let agent = self.agent(&new.name, Some(user)).await?;
+        agent.ok_or_else(|| PublishError::Internal(anyhow::anyhow!("Published agent vanished")))
+    }
+
+    /// The package stored for a version
+    pub fn read_package(&self, version: &StoredVersion) -> Result<Bytes> {
+        let path = self.packages.join(&version.file_path);
+        let package =
+            fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        Ok(Bytes::from(package))
+    }
+
+    pub async fn record_download(&self, agent: &StoredAgent, version: &str) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("UPDATE agents SET download_count = download_count + 1 WHERE id = ?")
+            .bind(agent.id.to_string())
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            "UPDATE agent_versions SET download_count = download_count + 1
+             WHERE agent_id = ? AND version = ?",
+        )
+        .bind(agent.id.to_string())
+        .bind(version)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Record public `1.0.0` agents for `user` in one transaction, all
+    /// sharing `package`. Publishing 100k agents one at a time takes minutes;
+    /// this takes seconds.
+    #[cfg(any(test, feature = "test-support"))]
+    pub(crate) async fn seed(
+        &self,
+        user: &LocalUser,
+        format: PackageFormat,
+        package: &Bytes,
+        agents: impl IntoIterator<Item = SeededAgent>,
+    ) -> Result<()> {
+        let file_path = format!("seed/package.{}", format.extension());
+        let path = self.packages.join(&file_path);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&path, package).with_context(|| format!("Failed to write {}", path.display()))?;
+
+        let checksum = package_checksum(package);
+        let now = Utc::now();
+        let mut tx = self.pool.begin().await?;
+        for agent in agents {
+            let agent_id = Uuid::new_v4().to_string();
+            sqlx::query(
+                "INSERT INTO agents (id, user_id, name, description, tags, current_version,
+                                     license, download_count, created_at, updated_at)
+                 VALUES (?, ?, ?, ?, ?, '1.0.0', 'MIT', ?, ?, ?)",
+            )
+            .bind(&agent_id)
+            .bind(user.id.to_string())
+            .bind(&agent.name)
+            .bind(&agent.description)
+            .bind(serde_json::to_string(&agent.tags)?)
+            .bind(i64::try_from(agent.download_count).unwrap_or(i64::MAX))
+            .bind(now)
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query(
+                "INSERT INTO agent_versions (agent_id, version, definition, file_path,
+                                             content_type, file_size, checksum, created_at)
+                 VALUES (?, '1.0.0', ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(&agent_id)
+            .bind(
+                serde_json::json!({
+                    "metadata": {
+                        "name": agent.name,
+                        "description": agent.description,
+                        "version": "1.0.0",
+                    }
+                })
+                .to_string(),
+            )
+            .bind(&file_path)
+            .bind(format.content_type())
+            .bind(package.len() as i64)
+            .bind(&checksum)
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+            log_change(&mut tx, &agent.name, ChangeKind::Created, true, now).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+/// An agent for [`LocalStore::seed`]
+#[cfg(any(test, feature = "test-support"))]
+pub(crate) struct SeededAgent {
+    pub name: String,
+    pub description: String,
+    pub tags: Vec<String>,
+    pub download_count: u64,
+}
+
+async fn add_user(pool: &SqlitePool, username: &str) -> Result<LocalUser> {
+    sqlx::query("INSERT OR IGNORE INTO users (id, username, created_at) VALUES (?, ?, ?)")
+        .bind(Uuid::new_v4().to_string())
+        .bind(username)
+        .bind(Utc::now())
+        .execute(pool)
+        .await?;
+    let id: String = sqlx::query_scalar("SELECT id FROM users WHERE username = ?")
+        .bind(username)
+        .fetch_one(pool)
+        .await?;
+
+    Ok(LocalUser {
+        id: Uuid::parse_str(&id)?,
+        username: username.to_string(),
+    })
+}
+
+async fn log_change(
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    name: &str,
+    kind: ChangeKind,
+    is_public: bool,
+    at: DateTime<Utc>,
+) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO agent_changes (name, kind, is_public, changed_at) VALUES (?, ?, ?, ?)",
+    )
+    .bind(name)
+    .bind(kind.as_str())
+    .bind(is_public)
+    .bind(at)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+fn agent_from_row(row: &SqliteRow) -> Result<StoredAgent> {
+    Ok(StoredAgent {
+        id: Uuid::parse_str(&row.try_get::<String, _>("id")?)?,
+        user_id: Uuid::parse_str(&row.try_get::<String, _>("user_id")?)?,
+        author: row.try_get("username")?,
+        name: row.try_get("name")?,
+        description: row.try_get("description")?,
+        tags: serde_json::from_str(&row.try_get::<String, _>("tags")?)?,
+        is_public: row.try_get("is_public")?,
+        is_template: row.try_get("is_template")?,
+        current_version: row.try_get("current_version")?,
+        readme: row.try_get("readme")?,
+        homepage: row.try_get("homepage")?,
+        repository: row.try_get("repository")?,
+        license: row.try_get("license")?,
+        download_count: row.try_get::<i64, _>("download_count")? as u64,
+        created_at: row.try_get("created_at")?,
+        updated_at: row.try_get("updated_at")?,
+    })
+}
+
+fn version_from_row(row: &SqliteRow) -> Result<StoredVersion> {
+    Ok(StoredVersion {
+        version: row.try_get("version")?,
+        definition: serde_json::from_str(&row.try_get::<String, _>("definition")?)?,
+        file_path: row.try_get("file_path")?,
+        content_type: row.try_get("content_type")?,
+        file_size: row.try_get::<i64, _>("file_size")? as u64,
+        checksum: row.try_get("checksum")?,
+        yanked: row.try_get("yanked")?,
+        created_at: row.try_get("created_at")?,
+    })
+}
+
+/// Bring a registry created by an older build up to the current schema
+async fn add_missing_columns(pool: &SqlitePool) -> Result<()> {
+    for (table, column, definition) in ADDED_COLUMNS {
+        let present: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM pragma_table_info(?) WHERE name = ?")
+                .bind(table)
+                .bind(column)
+                .fetch_one(pool)
+                .await?;
+        if present == 0 {
+            sqlx::query(&format!(
+                "ALTER TABLE {table} ADD COLUMN {column} {definition}"
+            ))
+            .execute(pool)
+            .await
+            .with_context(|| format!("Failed to add {table}.{column}"))?;
+        }
+    }
+    Ok(())
+}
+
+/// Log every agent of a registry created before the change log as created,
+/// so the feed starts with them
+async fn start_change_log(pool: &SqlitePool) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO agent_changes (name, kind, is_public, changed_at)
+         SELECT name, 'created', is_public, updated_at FROM agents
+         WHERE NOT EXISTS (SELECT 1 FROM agent_changes)
+         ORDER BY updated_at, name",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to start the change log")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::build_markdown_package;
+
+    fn new_version(name: &str, version: &str, is_public: bool) -> NewVersion {
+        NewVersion {
+            name: name.to_string(),
+            version: version.to_string(),
+            description: format!("The {name} agent"),
+            tags: vec!["testing".to_string()],
+            readme: None,
+            homepage: None,
+            repository: None,
+            license: Some("MIT".to_string()),
+            is_public,
+            is_template: None,
+            definition: serde_json::json!({}),
+            format: PackageFormat::Zip,
+            package: build_markdown_package(name, "# Agent\n").unwrap(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_publish_search_and_download() {
+        let dir = std::env::temp_dir().join(format!("carp-local-test-{}", Uuid::new_v4()));
+        let store = LocalStore::open(&dir, "tester").await.unwrap();
+        let user = store.user().clone();
+
+        store
+            .publish(&user, new_version("code-reviewer", "1.0.0", true))
+            .await
+            .unwrap();
+        let agent = store
+            .publish(&user, new_version("code-reviewer", "1.1.0", true))
+            .await
+            .unwrap();
+        assert_eq!(agent.current_version, "1.1.0");
+        assert!(matches!(
+            store
+                .publish(&user, new_version("code-reviewer", "1.1.0", true))
+                .await,
+            Err(PublishError::VersionExists)
+        ));
+        store
+            .publish(&user, new_version("secret-agent", "1.0.0", false))
+            .await
+            .unwrap();
+
+        let search = SearchQuery {
+            query: "secret",
+            matching: Match::Substring,
+            recent_first: false,
+            limit: 10,
+            page: 1,
+            mine: false,
+            templates_only: false,
+        };
+        let (anonymous, total) = store.search(&search, None).await.unwrap();
+        assert_eq!(total, 0, "private agents are hidden from anonymous callers");
+        assert!(anonymous.is_empty());
+        let (own, _) = store.search(&search, Some(&user)).await.unwrap();
+        assert_eq!(own.len(), 1);
+
+        let version = store.version(&agent, "latest").await.unwrap().unwrap();
+        assert_eq!(version.version, "1.1.0");
+        let package = store.read_package(&version).unwrap();
+        assert_eq!(package_checksum(&package), version.checksum);
+
+        store.record_download(&agent, "1.1.0").await.unwrap();
+        let agent = store.agent("code-reviewer", None).await.unwrap().unwrap();
+        assert_eq!(agent.download_count, 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_yanked_versions_are_listed_but_not_downloadable() {
+        let dir = std::env::temp_dir().join(format!("carp-local-test-{}", Uuid::new_v4()));
+        let store = LocalStore::open(&dir, "tester").await.unwrap();
+        let user = store.user().clone();
+
+        store
+            .publish(&user, new_version("code-reviewer", "1.0.0", true))
+            .await
+            .unwrap();
+        let agent = store
+            .publish(&user, new_version("code-reviewer", "1.1.0", true))
+            .await
+            .unwrap();
+        sqlx::query("UPDATE agent_versions SET yanked = 1 WHERE version = '1.1.0'")
+            .execute(&store.pool)
+            .await
+            .unwrap();
+
+        let versions = store.versions(&agent).await.unwrap();
+        let listed: Vec<_> = versions
+            .iter()
+            .map(|v| (v.version.as_str(), v.yanked))
+            .collect();
+        assert_eq!(listed, [("1.0.0", false), ("1.1.0", true)]);
+
+        // `latest` falls back to the newest version that isn't yanked
+        let latest = store.version(&agent, "latest").await.unwrap().unwrap();
+        assert_eq!(latest.version, "1.0.0");
+        assert!(store.version(&agent, "1.1.0").await.unwrap().is_none());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_template_flag_is_kept_unless_given() {
+        let dir = std::env::temp_dir().join(format!("carp-local-test-{}", Uuid::new_v4()));
+        let store = LocalStore::open(&dir, "tester").await.unwrap();
+        let user = store.user().clone();
+        let templates = SearchQuery {
+            query: "",
+            matching: Match::Substring,
+            recent_first: false,
+            limit: 10,
+            page: 1,
+            mine: false,
+            templates_only: true,
+        };
+
+        let mut template = new_version("rust-reviewer", "1.0.0", true);
+        template.is_template = Some(true);
+        store.publish(&user, template).await.unwrap();
+        store
+            .publish(&user, new_version("code-reviewer", "1.0.0", true))
+            .await
+            .unwrap();
+        let (listed, total) = store.search(&templates, None).await.unwrap();
+        assert_eq!(total, 1);
+        assert_eq!(listed[0].name, "rust-reviewer");
+
+        // Versions that don't say, like OCI pushes, keep the flag
+        let agent = store
+            .publish(&user, new_version("rust-reviewer", "1.1.0", true))
+            .await
+            .unwrap();
+        assert!(agent.is_template);
+
+        let mut untemplated = new_version("rust-reviewer", "1.2.0", true);
+        untemplated.is_template = Some(false);
+        let agent = store.publish(&user, untemplated).await.unwrap();
+        assert!(!agent.is_template);
+        assert_eq!(store.search(&templates, None).await.unwrap().1, 0);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_changes_feed_the_latest_change_to_public_agents() {
+        let dir = std::env::temp_dir().join(format!("carp-local-test-{}", Uuid::new_v4()));
+        let store = LocalStore::open(&dir, "tester").await.unwrap();
+        let user = store.user().clone();
+        let names = |changes: Vec<(i64, String, ChangeKind)>| -> Vec<(String, ChangeKind)> {
+            changes
+                .into_iter()
+                .map(|(_, name, kind)| (name, kind))
+                .collect()
+        };
+
+        store
+            .publish(&user, new_version("code-reviewer", "1.0.0", true))
+            .await
+            .unwrap();
+        store
+            .publish(&user, new_version("secret-agent", "1.0.0", false))
+            .await
+            .unwrap();
+        store
+            .publish(&user, new_version("test-writer", "1.0.0", true))
+            .await
+            .unwrap();
+        let first = store.changes(0, 100).await.unwrap();
+        let cursor = first.last().unwrap().0;
+        assert_eq!(
+            names(first),
+            [
+                ("code-reviewer".to_string(), ChangeKind::Created),
+                ("test-writer".to_string(), ChangeKind::Created),
+            ]
+        );
+
+        store
+            .publish(&user, new_version("code-reviewer", "1.1.0", true))
+            .await
+            .unwrap();
+        store
+            .publish(&user, new_version("test-writer", "1.1.0", false))
+            .await
+            .unwrap();
+        store
+            .publish(&user, new_version("secret-agent", "1.1.0", false))
+            .await
+            .unwrap();
+        assert_eq!(
+            names(store.changes(cursor, 100).await.unwrap()),
+            [
+                ("code-reviewer".to_string(), ChangeKind::Updated),
+                ("test-writer".to_string(), ChangeKind::Removed),
+            ]
+        );
+        assert_eq!(store.changes(cursor, 1).await.unwrap().len(), 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_open_upgrades_older_registries() {
+        let dir = std::env::temp_dir().join(format!("carp-local-test-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let options = SqliteConnectOptions::new()
+            .filename(dir.join("registry.db"))
+            .create_if_missing(true);
+        let pool = SqlitePoolOptions::new()
+            .connect_with(options)
+            .await
+            .unwrap();
+        let old_schema = SCHEMA
+            .replace("  yanked INTEGER NOT NULL DEFAULT 0,\n", "")
+            .replace("  is_template INTEGER NOT NULL DEFAULT 0,\n", "");
+        sqlx::raw_sql(&old_schema).execute(&pool).await.unwrap();
+        pool.close().await;
+
+        let store = LocalStore::open(&dir, "tester").await.unwrap();
+        let user = store.user().clone();
+        let agent = store
+            .publish(&user, new_version("code-reviewer", "1.0.0", true))
+            .await
+            .unwrap();
+        assert!(!store.versions(&agent).await.unwrap()[0].yanked);
+        assert!(!agent.is_template);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,127 @@
+//! Near-miss name scoring for the local registry
+//!
+//! The hosted registry ranks suggestions in Postgres with `levenshtein` and
+//! pg_trgm's `similarity`; these reproduce both so local mode suggests the
+//! same names.
+
+use serde::Serialize;
+use std::collections::HashSet;
+
+/// Names within this many edits of the query are suggested
+const MAX_DISTANCE: usize = 2;
+
+/// Names at least this similar to the query are suggested
+const MIN_SIMILARITY: f32 = 0.3;
+
+/// A near-miss agent name
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Suggestion {
+    pub name: String,
+    pub distance: usize,
+    pub similarity: f32,
+}
+
+/// The names close enough to `query` to suggest, closest first
+pub fn rank<'a>(
+    query: &str,
+    names: impl IntoIterator<Item = &'a str>,
+    limit: usize,
+) -> Vec<Suggestion> {
+    let query = query.to_lowercase();
+    let mut suggestions: Vec<Suggestion> = names
+        .into_iter()
+        .map(|name| {
+            let lowered = name.to_lowercase();
+            Suggestion {
+                name: name.to_string(),
+                distance: levenshtein(&lowered, &query),
+                similarity: similarity(&lowered, &query),
+            }
+        })
+        .filter(|s| s.distance <= MAX_DISTANCE || s.similarity >= MIN_SIMILARITY)
+        .collect();
+
+    suggestions.sort_by(|a, b| {
+        a.distance
+            .cmp(&b.distance)
+            .then(b.similarity.total_cmp(&a.similarity))
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    suggestions.truncate(limit);
+    suggestions
+}
+
+/// Single-character insertions, deletions and substitutions between `a` and `b`
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                diagonal
+            } else {
+                1 + diagonal.min(above).min(row[j])
+            };
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+/// Shared trigrams over all trigrams, as pg_trgm computes it
+fn similarity(a: &str, b: &str) -> f32 {
+    let (a, b) = (trigrams(a), trigrams(b));
+    let union = a.union(&b).count();
+    if union == 0 {
+        return 0.0;
+    }
+    a.intersection(&b).count() as f32 / union as f32
+}
+
+/// pg_trgm's trigrams: each alphanumeric word padded with two spaces before
+/// and one after
+fn trigrams(text: &str) -> HashSet<[char; 3]> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .flat_map(|word| {
+            let padded: Vec<char> = format!("  {word} ").chars().collect();
+            padded
+                .windows(3)
+                .map(|w| [w[0], w[1], w[2]])
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rank_suggests_near_misses_closest_first() {
+        let names = [
+            "code-reviewer",
+            "code-review",
+            "doc-writer",
+            "Code-Reviewers",
+            "test-runner",
+        ];
+
+        let names_of = |suggestions: Vec<Suggestion>| -> Vec<String> {
+            suggestions.into_iter().map(|s| s.name).collect()
+        };
+        assert_eq!(
+            names_of(rank("code-reveiwer", names, 3)),
+            ["code-reviewer", "Code-Reviewers", "code-review"]
+        );
+        assert_eq!(names_of(rank("tset-runner", names, 3)), ["test-runner"]);
+        assert!(rank("zzz", names, 3).is_empty());
+
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+        assert_eq!(similarity("word", "word"), 1.0);
+        assert_eq!(similarity("", "word"), 0.0);
+    }
+}
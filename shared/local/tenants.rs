@@ -0,0 +1,122 @@
+//! Tenants of the local registry
+//!
+//! Requests under `/t/<id>/`, or to a host listed for a tenant in `TENANTS`,
+//! are served by that tenant's own registry, kept in `tenants/<id>/` under
+//! the data directory and opened the first time it's asked for. Unlike the
+//! hosted API, any well-formed tenant id works under `/t/`, so tests and
+//! local setups don't need `TENANTS`. Tokens registered in one tenant mean
+//! nothing in another.
+
+use anyhow::Result;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use super::store::LocalStore;
+use crate::tenant::{is_valid_id, Tenants, DEFAULT_TENANT};
+
+/// The default registry and those of the tenants asked for so far
+pub struct LocalRegistries {
+    data_dir: PathBuf,
+    username: String,
+    tenants: Tenants,
+    default: Arc<LocalStore>,
+    opened: Mutex<HashMap<String, Arc<LocalStore>>>,
+    draining: AtomicBool,
+}
+
+impl LocalRegistries {
+    /// Open or create the default registry in `data_dir`
+    pub async fn open(data_dir: &Path, username: &str) -> Result<Self> {
+        let tenants = Tenants::from_env().map_err(anyhow::Error::msg)?;
+        Ok(Self {
+            data_dir: data_dir.to_path_buf(),
+            username: username.to_string(),
+            tenants,
+            default: Arc::new(LocalStore::open(data_dir, username).await?),
+            opened: Mutex::new(HashMap::new()),
+            draining: AtomicBool::new(false),
+        })
+    }
+
+    /// Turn away new uploads and publishes while the server shuts down
+    pub fn drain(&self) {
+        self.draining.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether the server is shutting down
+    pub fn draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// The registry outside any tenant
+    pub fn default_store(&self) -> &Arc<LocalStore> {
+        &self.default
+    }
+
+    /// The registry of tenant `id`, opening it if need be
+    pub async fn tenant(&self, id: &str) -> Result<Arc<LocalStore>> {
+        if id == DEFAULT_TENANT {
+            return Ok(self.default.clone());
+        }
+        anyhow::ensure!(is_valid_id(id), "'{id}' is not a tenant id");
+
+        let mut opened = self.opened.lock().await;
+        if let Some(store) = opened.get(id) {
+            return Ok(store.clone());
+        }
+        let dir = self.data_dir.join("tenants").join(id);
+        let store = Arc::new(LocalStore::open(&dir, &self.username).await?);
+        opened.insert(id.to_string(), store.clone());
+        Ok(store)
+    }
+
+    /// The tenant a request to `path` on `host` is for, and the path within
+    /// its registry. `None` for a `/t/` path that names no tenant.
+    pub fn resolve<'a>(&self, path: &'a str, host: Option<&str>) -> Option<(String, &'a str)> {
+        if let Some(rest) = path.strip_prefix("/t/") {
+            let (id, rest) = rest.split_once('/').unwrap_or((rest, ""));
+            return is_valid_id(id).then(|| (id.to_string(), rest));
+        }
+        let tenant = host
+            .and_then(|host| self.tenants.for_host(host))
+            .map(|tenant| tenant.id.clone())
+            .unwrap_or_else(|| DEFAULT_TENANT.to_string());
+        Some((tenant, path.trim_start_matches('/')))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn test_tenants_get_registries_of_their_own() {
+        let dir = std::env::temp_dir().join(format!("carp-local-tenants-{}", Uuid::new_v4()));
+        let registries = LocalRegistries::open(&dir, "tester").await.unwrap();
+
+        assert_eq!(
+            registries.resolve("/t/acme/api/v1/agents/search", None),
+            Some(("acme".to_string(), "api/v1/agents/search"))
+        );
+        assert_eq!(
+            registries.resolve("/api/v1/agents/search", Some("localhost:3000")),
+            Some((DEFAULT_TENANT.to_string(), "api/v1/agents/search"))
+        );
+        assert_eq!(registries.resolve("/t/Not_A_Tenant/api", None), None);
+
+        let acme = registries.tenant("acme").await.unwrap();
+        assert!(Arc::ptr_eq(
+            &acme,
+            &registries.tenant("acme").await.unwrap()
+        ));
+        assert!(!Arc::ptr_eq(&acme, registries.default_store()));
+        assert!(dir.join("tenants/acme/registry.db").exists());
+        assert!(registries.tenant("../escape").await.is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
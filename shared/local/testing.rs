@@ -0,0 +1,512 @@
+//! In-process registry for tests
+//!
+//! [`TestRegistry`] runs the local registry on an ephemeral port against a
+//! temporary directory, so tests can talk HTTP to something that behaves like
+//! the real API instead of hand-writing mocks for every endpoint. Other crates
+//! get it with the `test-support` feature:
+//!
+//! ```rust,ignore
+//! let registry = TestRegistry::start().await?;
+//! registry.agent("code-reviewer").version("1.2.0").publish().await?;
+//!
+//! let alice = registry.user("alice").await?;
+//! registry.agent("secret").owner(&alice).private().publish().await?;
+//!
+//! // Point a client at registry.url() with registry.token() or alice.token
+//! ```
+//!
+//! Benchmarks and load tests fill it with [`TestRegistry::seed`] instead,
+//! which records thousands of agents at once.
+
+use anyhow::Result;
+use bytes::Bytes;
+use serde_json::json;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use super::store::{LocalStore, LocalUser, NewVersion, PublishError, SeededAgent, StoredAgent};
+use super::LocalRegistries;
+use crate::archive::{build_markdown_package, PackageFormat};
+
+/// API key that acts as the registry's default user
+pub const DEFAULT_TOKEN: &str = "carp_test0000_test0000_test0000";
+
+/// What seeded agents do, the first word of their names
+pub const SEED_SUBJECTS: &[&str] = &[
+    "code", "meeting", "data", "security", "docs", "api", "release", "incident",
+];
+
+/// What seeded agents are, the second word of their names
+pub const SEED_ROLES: &[&str] = &[
+    "reviewer",
+    "notes",
+    "analyst",
+    "writer",
+    "auditor",
+    "planner",
+    "helper",
+    "migrator",
+    "tester",
+    "summarizer",
+];
+
+/// Name of the `index`th agent [`TestRegistry::seed`] records, e.g.
+/// `meeting-notes-000011`
+pub fn seeded_agent_name(index: usize) -> String {
+    let subject = SEED_SUBJECTS[index % SEED_SUBJECTS.len()];
+    let role = SEED_ROLES[(index / SEED_SUBJECTS.len()) % SEED_ROLES.len()];
+    format!("{subject}-{role}-{index:06}")
+}
+
+/// A running registry, shut down and deleted when dropped
+pub struct TestRegistry {
+    url: String,
+    data_dir: PathBuf,
+    store: Arc<LocalStore>,
+    registries: Arc<LocalRegistries>,
+    server: JoinHandle<()>,
+}
+
+/// A registered user and the API key that acts as them
+#[derive(Debug, Clone)]
+pub struct TestUser {
+    pub user: LocalUser,
+    pub token: String,
+}
+
+impl TestRegistry {
+    /// Start an empty registry whose default user is `test`
+    pub async fn start() -> Result<Self> {
+        let data_dir = std::env::temp_dir().join(format!("carp-test-registry-{}", Uuid::new_v4()));
+        let registries = Arc::new(LocalRegistries::open(&data_dir, "test").await?);
+        let store = registries.default_store().clone();
+
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await?;
+        let url = format!("http://{}", listener.local_addr()?);
+        let server = tokio::spawn({
+            let registries = registries.clone();
+            async move {
+                let forever = std::future::pending();
+                if let Err(e) = super::run(listener, registries, forever, Duration::ZERO).await {
+                    eprintln!("DEBUG: Test registry stopped: {e}");
+                }
+            }
+        });
+
+        Ok(Self {
+            url,
+            data_dir,
+            store,
+            registries,
+            server,
+        })
+    }
+
+    /// Base URL to configure clients with, e.g. `http://127.0.0.1:41234`
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Base URL of tenant `id`'s registry, e.g. `http://127.0.0.1:41234/t/acme`
+    pub fn tenant_url(&self, id: &str) -> String {
+        format!("{}/t/{id}", self.url)
+    }
+
+    /// The storage behind tenant `id`'s registry, for assertions
+    pub async fn tenant_store(&self, id: &str) -> Result<Arc<LocalStore>> {
+        self.registries.tenant(id).await
+    }
+
+    /// API key for the default user
+    pub fn token(&self) -> &'static str {
+        DEFAULT_TOKEN
+    }
+
+    /// The default user, who owns agents published without an owner
+    pub fn default_user(&self) -> &LocalUser {
+        self.store.user()
+    }
+
+    /// Register a user with an API key of their own
+    pub async fn user(&self, username: &str) -> Result<TestUser> {
+        let user = self.store.add_user(username).await?;
+        let token = format!(
+            "carp_{:0<8.8}_test0000_{:08x}",
+            username,
+            rand::random::<u32>()
+        );
+        self.store.add_token(&user, &token).await?;
+        Ok(TestUser { user, token })
+    }
+
+    /// Start describing an agent to publish
+    pub fn agent(&self, name: &str) -> AgentBuilder<'_> {
+        AgentBuilder {
+            registry: self,
+            owner: None,
+            name: name.to_string(),
+            version: "1.0.0".to_string(),
+            description: format!("The {name} agent"),
+            tags: Vec::new(),
+            license: Some("MIT".to_string()),
+            is_public: true,
+            is_template: false,
+            content: None,
+            package: None,
+        }
+    }
+
+    /// Record `count` public agents owned by the default user, named by
+    /// [`seeded_agent_name`] and all at version `1.0.0`. Each subject and
+    /// role is shared by an even slice of them, so a search for `reviewer`
+    /// matches a tenth of the registry and one for `-000042` a single agent.
+    /// Download counts are spread so sorting by them isn't trivial.
+    pub async fn seed(&self, count: usize) -> Result<()> {
+        let package = build_markdown_package(
+            "seeded",
+            "---\nname: seeded\ndescription: A seeded agent\n---\n\n# seeded\n",
+        )?;
+        let agents = (0..count).map(|index| {
+            let name = seeded_agent_name(index);
+            let subject = SEED_SUBJECTS[index % SEED_SUBJECTS.len()];
+            let role = SEED_ROLES[(index / SEED_SUBJECTS.len()) % SEED_ROLES.len()];
+            SeededAgent {
+                description: format!("Seeded {subject} {role} agent #{index}"),
+                tags: vec![subject.to_string(), role.to_string()],
+                download_count: (index as u64).wrapping_mul(7919) % 100_003,
+                name,
+            }
+        });
+        self.store
+            .seed(self.store.user(), PackageFormat::Zip, &package, agents)
+            .await
+    }
+
+    /// The storage behind the registry, for assertions
+    pub fn store(&self) -> &LocalStore {
+        &self.store
+    }
+}
+
+impl Drop for TestRegistry {
+    fn drop(&mut self) {
+        self.server.abort();
+        let _ = fs::remove_dir_all(&self.data_dir);
+    }
+}
+
+/// An agent version to publish, with defaults for anything not set
+pub struct AgentBuilder<'a> {
+    registry: &'a TestRegistry,
+    owner: Option<LocalUser>,
+    name: String,
+    version: String,
+    description: String,
+    tags: Vec<String>,
+    license: Option<String>,
+    is_public: bool,
+    is_template: bool,
+    content: Option<String>,
+    package: Option<(PackageFormat, Bytes)>,
+}
+
+impl AgentBuilder<'_> {
+    pub fn owner(mut self, owner: &TestUser) -> Self {
+        self.owner = Some(owner.user.clone());
+        self
+    }
+
+    pub fn version(mut self, version: &str) -> Self {
+        self.version = version.to_string();
+        self
+    }
+
+    pub fn description(mut self, description: &str) -> Self {
+        self.description = description.to_string();
+        self
+    }
+
+    pub fn tags(mut self, tags: &[&str]) -> Self {
+        self.tags = tags.iter().map(|tag| tag.to_string()).collect();
+        self
+    }
+
+    pub fn license(mut self, license: Option<&str>) -> Self {
+        self.license = license.map(str::to_string);
+        self
+    }
+
+    pub fn private(mut self) -> Self {
+        self.is_public = false;
+        self
+    }
+
+    /// Flag the agent as a template for `carp new --template`
+    pub fn template(mut self) -> Self {
+        self.is_template = true;
+        self
+    }
+
+    /// Markdown packaged as `{name}.md`; defaults to a minimal agent file
+    pub fn content(mut self, markdown: &str) -> Self {
+        self.content = Some(markdown.to_string());
+        self
+    }
+
+    /// Publish this archive as the package instead of one built from the content
+    pub fn package(mut self, format: PackageFormat, package: impl Into<Bytes>) -> Self {
+        self.package = Some((format, package.into()));
+        self
+    }
+
+    /// Publish the version, creating the agent if it's new
+    pub async fn publish(self) -> Result<StoredAgent> {
+        let content = self.content.unwrap_or_else(|| {
+            format!(
+                "---\nname: {}\ndescription: {}\n---\n\n# {}\n",
+                self.name, self.description, self.name
+            )
+        });
+        let (format, package) = match self.package {
+            Some(package) => package,
+            None => (
+                PackageFormat::Zip,
+                build_markdown_package(&self.name, &content)?,
+            ),
+        };
+        let owner = self
+            .owner
+            .unwrap_or_else(|| self.registry.store.user().clone());
+
+        let new = NewVersion {
+            definition: json!({
+                "metadata": {
+                    "name": self.name,
+                    "description": self.description,
+                    "version": self.version,
+                },
+                "content": content,
+                "format": "markdown",
+                "frontmatter_type": "yaml"
+            }),
+            name: self.name,
+            version: self.version,
+            description: self.description,
+            tags: self.tags,
+            readme: Some(content),
+            homepage: None,
+            repository: None,
+            license: self.license,
+            is_public: self.is_public,
+            is_template: Some(self.is_template),
+            format,
+            package,
+        };
+
+        match self.registry.store.publish(&owner, new).await {
+            Ok(agent) => Ok(agent),
+            Err(PublishError::NotOwner) => Err(anyhow::anyhow!("Agent belongs to another user")),
+            Err(PublishError::VersionExists) => Err(anyhow::anyhow!("Version already published")),
+            Err(PublishError::Internal(e)) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::package_checksum;
+    use crate::local::RouteLimits;
+
+    #[tokio::test]
+    async fn test_registry_serves_published_agents() {
+        let registry = TestRegistry::start().await.unwrap();
+        let alice = registry.user("alice").await.unwrap();
+        registry
+            .agent("code-reviewer")
+            .tags(&["review"])
+            .publish()
+            .await
+            .unwrap();
+        registry
+            .agent("secret-agent")
+            .owner(&alice)
+            .private()
+            .publish()
+            .await
+            .unwrap();
+
+        let client = reqwest::Client::new();
+        let search = |token: Option<&str>| {
+            let mut request = client.get(format!("{}/api/v1/agents/search", registry.url()));
+            if let Some(token) = token {
+                request = request.bearer_auth(token);
+            }
+            request.send()
+        };
+
+        let anonymous: serde_json::Value = search(None).await.unwrap().json().await.unwrap();
+        assert_eq!(anonymous["total"], 1);
+        let as_alice: serde_json::Value = search(Some(&alice.token))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(as_alice["total"], 2);
+
+        let whoami: serde_json::Value = client
+            .get(format!("{}/api/v1/auth/whoami", registry.url()))
+            .bearer_auth(registry.token())
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(whoami["github_username"], "test");
+    }
+
+    #[tokio::test]
+    async fn test_seeded_agents_are_searchable_and_downloadable() {
+        let registry = TestRegistry::start().await.unwrap();
+        registry.seed(1_600).await.unwrap();
+        assert_eq!(registry.store().agent_count().await.unwrap(), 1_600);
+
+        let client = reqwest::Client::new();
+        let search: serde_json::Value = client
+            .get(format!(
+                "{}/api/v1/agents/search?q=reviewer&limit=5",
+                registry.url()
+            ))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(search["total"], 160);
+        assert_eq!(search["agents"].as_array().unwrap().len(), 5);
+
+        let name = seeded_agent_name(42);
+        let info: serde_json::Value = client
+            .get(format!(
+                "{}/api/v1/agents/{name}/latest/download",
+                registry.url()
+            ))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(info["version"], "1.0.0");
+        let package = client
+            .get(info["download_url"].as_str().unwrap())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(package.status(), 200);
+        assert_eq!(
+            package_checksum(&package.bytes().await.unwrap()),
+            info["checksum"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bodies_over_the_route_limit_are_refused() {
+        let registry = TestRegistry::start().await.unwrap();
+        let client = reqwest::Client::new();
+
+        let login = client
+            .post(format!("{}/api/v1/auth/login", registry.url()))
+            .body(vec![b'a'; RouteLimits::LOOKUP.max_body + 1])
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(login.status(), 413);
+        assert_eq!(
+            login.headers()["content-type"],
+            crate::problem::PROBLEM_CONTENT_TYPE
+        );
+        let error: serde_json::Value = login.json().await.unwrap();
+        assert_eq!(error["error"], "payload_too_large");
+        assert_eq!(
+            error["type"],
+            crate::problem::problem_type("payload_too_large")
+        );
+
+        // The same body is fine where packages are uploaded
+        let upload = client
+            .post(format!("{}/api/v1/agents/upload", registry.url()))
+            .bearer_auth(registry.token())
+            .body(vec![b'a'; RouteLimits::LOOKUP.max_body + 1])
+            .send()
+            .await
+            .unwrap();
+        assert_ne!(upload.status(), 413);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_uploads_list_each_field_as_a_problem() {
+        let registry = TestRegistry::start().await.unwrap();
+        let upload = reqwest::Client::new()
+            .post(format!("{}/api/v1/agents/upload", registry.url()))
+            .bearer_auth(registry.token())
+            .json(&serde_json::json!({
+                "name": "bad name",
+                "description": " ",
+                "content": "no frontmatter",
+                "tags": [],
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(upload.status(), 422);
+        assert_eq!(
+            upload.headers()["content-type"],
+            crate::problem::PROBLEM_CONTENT_TYPE
+        );
+        let error: serde_json::Value = upload.json().await.unwrap();
+        assert_eq!(error["error"], "validation_failed");
+        let fields: Vec<_> = error["details"]["fields"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|field| field["field"].as_str().unwrap())
+            .collect();
+        assert_eq!(fields, ["name", "description", "content"]);
+    }
+
+    #[tokio::test]
+    async fn test_draining_turns_away_uploads_only() {
+        let registry = TestRegistry::start().await.unwrap();
+        registry.registries.drain();
+        let client = reqwest::Client::new();
+
+        let upload = client
+            .post(format!("{}/api/v1/agents/upload", registry.url()))
+            .bearer_auth(registry.token())
+            .body("{}")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(upload.status(), 503);
+        assert_eq!(upload.headers()["retry-after"], "10");
+        let error: serde_json::Value = upload.json().await.unwrap();
+        assert_eq!(error["error"], "shutting_down");
+
+        let search = client
+            .get(format!("{}/api/v1/agents/search", registry.url()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(search.status(), 200);
+    }
+}
@@ -0,0 +1,310 @@
+//! Read-only maintenance mode
+//!
+//! During database migrations or an incident the registry can be put in
+//! read-only mode: search, info and downloads keep working, while publishes
+//! and other changes answer 503 with a maintenance message until it's lifted.
+//!
+//! It's switched on either way:
+//!
+//! - `MAINTENANCE_MODE=read-only` (or `1`/`true`), with an optional
+//!   `MAINTENANCE_MESSAGE`, for when the database itself is unavailable;
+//!   this can only be lifted by unsetting it
+//! - `PUT /api/v1/admin/maintenance` by an admin, stored in the
+//!   `maintenance_mode` table, which takes effect without a redeploy
+//!
+//! Handlers that change anything call [`ensure_writable`] before doing so. If
+//! the stored setting can't be read, requests are let through rather than
+//! refused, like the rate limiter.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::env;
+use uuid::Uuid;
+use vercel_runtime::{Body, Response};
+
+use crate::auth::ApiError;
+use crate::registry::service_config;
+use crate::tenant;
+
+/// Shown when no message was given
+pub const DEFAULT_MESSAGE: &str =
+    "The registry is in read-only maintenance. Searching and downloading still work; please try again later.";
+
+/// Seconds clients are asked to wait before retrying a change
+pub const RETRY_AFTER_SECS: u64 = 300;
+
+/// Where read-only mode was switched on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MaintenanceSource {
+    Env,
+    Admin,
+}
+
+/// Whether the registry is read-only, and why
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Maintenance {
+    pub read_only: bool,
+    pub message: Option<String>,
+    pub source: Option<MaintenanceSource>,
+    pub since: Option<DateTime<Utc>>,
+}
+
+impl Maintenance {
+    /// The registry accepting changes
+    pub fn off() -> Self {
+        Self {
+            read_only: false,
+            message: None,
+            source: None,
+            since: None,
+        }
+    }
+
+    /// Read-only mode from `MAINTENANCE_MODE`, if it's set
+    pub fn from_env() -> Option<Self> {
+        let mode = env::var("MAINTENANCE_MODE").unwrap_or_default();
+        if !matches!(
+            mode.trim().to_ascii_lowercase().as_str(),
+            "1" | "true" | "read-only" | "readonly"
+        ) {
+            return None;
+        }
+        Some(Self {
+            read_only: true,
+            message: env::var("MAINTENANCE_MESSAGE")
+                .ok()
+                .filter(|message| !message.trim().is_empty()),
+            source: Some(MaintenanceSource::Env),
+            since: None,
+        })
+    }
+
+    /// The message for clients
+    pub fn message(&self) -> &str {
+        self.message.as_deref().unwrap_or(DEFAULT_MESSAGE)
+    }
+
+    /// The 503 answered to changes while read-only
+    pub fn response(&self) -> Response<Body> {
+        let error = ApiError {
+            error: "maintenance".to_string(),
+            message: self.message().to_string(),
+            details: Some(json!({
+                "read_only": true,
+                "since": self.since,
+            })),
+        };
+        Response::builder()
+            .status(503)
+            .header("content-type", "application/json")
+            .header("Retry-After", RETRY_AFTER_SECS.to_string())
+            .body(
+                serde_json::to_string(&error)
+                    .unwrap_or_else(|_| r#"{"error":"maintenance"}"#.to_string())
+                    .into(),
+            )
+            .unwrap_or_else(|_| {
+                Response::builder()
+                    .status(503)
+                    .body("Service unavailable".into())
+                    .unwrap()
+            })
+    }
+}
+
+/// The current mode: `MAINTENANCE_MODE` if set, else what an admin set
+pub async fn current() -> Maintenance {
+    if let Some(maintenance) = Maintenance::from_env() {
+        return maintenance;
+    }
+    let Ok((supabase_url, supabase_key)) = service_config() else {
+        return Maintenance::off();
+    };
+    match MaintenanceStore::new(supabase_url, supabase_key)
+        .get()
+        .await
+    {
+        Ok(maintenance) => maintenance,
+        Err(e) => {
+            eprintln!("WARN: Couldn't read maintenance mode, allowing changes: {e:#}");
+            Maintenance::off()
+        }
+    }
+}
+
+/// Refuse a change with a 503 while the registry is read-only
+#[allow(clippy::result_large_err)]
+pub async fn ensure_writable() -> Result<(), Response<Body>> {
+    let maintenance = current().await;
+    if maintenance.read_only {
+        return Err(maintenance.response());
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+struct MaintenanceRow {
+    read_only: bool,
+    message: Option<String>,
+    updated_at: DateTime<Utc>,
+}
+
+impl From<MaintenanceRow> for Maintenance {
+    fn from(row: MaintenanceRow) -> Self {
+        if !row.read_only {
+            return Self::off();
+        }
+        Self {
+            read_only: true,
+            message: row.message,
+            source: Some(MaintenanceSource::Admin),
+            since: Some(row.updated_at),
+        }
+    }
+}
+
+/// The admin-set mode, through Supabase's REST API
+pub struct MaintenanceStore {
+    client: reqwest::Client,
+    supabase_url: String,
+    supabase_key: String,
+}
+
+impl MaintenanceStore {
+    pub fn new(supabase_url: String, supabase_key: String) -> Self {
+        Self {
+            client: tenant::http_client(),
+            supabase_url,
+            supabase_key,
+        }
+    }
+
+    pub async fn get(&self) -> Result<Maintenance> {
+        let rows: Vec<MaintenanceRow> = self
+            .request(self.client.get(format!(
+                "{}/rest/v1/maintenance_mode?select=read_only,message,updated_at",
+                self.supabase_url
+            )))
+            .await?;
+        Ok(rows
+            .into_iter()
+            .next()
+            .map(Maintenance::from)
+            .unwrap_or_else(Maintenance::off))
+    }
+
+    /// Switch read-only mode on or off for the admin `admin_id`
+    pub async fn set(
+        &self,
+        read_only: bool,
+        message: Option<&str>,
+        admin_id: Uuid,
+    ) -> Result<Maintenance> {
+        let mut rows: Vec<MaintenanceRow> = self
+            .request(
+                self.client
+                    .post(format!(
+                        "{}/rest/v1/maintenance_mode?on_conflict=id&select=read_only,message,updated_at",
+                        self.supabase_url
+                    ))
+                    .header("Prefer", "resolution=merge-duplicates,return=representation")
+                    .json(&json!({
+                        "id": true,
+                        "read_only": read_only,
+                        "message": message,
+                        "updated_by": admin_id,
+                        "updated_at": Utc::now(),
+                    })),
+            )
+            .await?;
+        Ok(rows.pop().context("Maintenance mode was not saved")?.into())
+    }
+
+    async fn request<T: serde::de::DeserializeOwned>(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<T> {
+        let response = request
+            .header("apikey", &self.supabase_key)
+            .header("Authorization", format!("Bearer {}", self.supabase_key))
+            .send()
+            .await
+            .context("Database request failed")?;
+        let status = response.status();
+        let body = response.text().await?;
+        if !status.is_success() {
+            bail!("Database request failed with status {status}: {body}");
+        }
+        serde_json::from_str(&body).with_context(|| format!("Unexpected database response: {body}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{body_partial_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_read_only_refuses_changes() {
+        let maintenance = Maintenance {
+            read_only: true,
+            message: None,
+            source: Some(MaintenanceSource::Env),
+            since: None,
+        };
+        let response = maintenance.response();
+        assert_eq!(response.status(), 503);
+        assert_eq!(response.headers()["retry-after"], "300");
+        let Body::Text(body) = response.body() else {
+            panic!("expected a JSON body");
+        };
+        let body: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(body["error"], "maintenance");
+        assert_eq!(body["message"], DEFAULT_MESSAGE);
+    }
+
+    #[tokio::test]
+    async fn test_store_round_trip() {
+        let server = MockServer::start().await;
+        let admin_id = Uuid::new_v4();
+        Mock::given(method("POST"))
+            .and(path("/rest/v1/maintenance_mode"))
+            .and(body_partial_json(json!({
+                "id": true,
+                "read_only": true,
+                "message": "Migrating",
+                "updated_by": admin_id,
+            })))
+            .respond_with(ResponseTemplate::new(201).set_body_json(json!([{
+                "read_only": true,
+                "message": "Migrating",
+                "updated_at": "2025-09-02T10:00:00Z",
+            }])))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/rest/v1/maintenance_mode"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([{
+                "read_only": false,
+                "message": "Migrating",
+                "updated_at": "2025-09-02T11:00:00Z",
+            }])))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let store = MaintenanceStore::new(server.uri(), "service-key".to_string());
+        let set = store.set(true, Some("Migrating"), admin_id).await.unwrap();
+        assert!(set.read_only);
+        assert_eq!(set.message(), "Migrating");
+        assert_eq!(set.source, Some(MaintenanceSource::Admin));
+
+        // Once lifted, the old message no longer matters
+        assert_eq!(store.get().await.unwrap(), Maintenance::off());
+    }
+}
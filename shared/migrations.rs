@@ -0,0 +1,171 @@
+//! Database migrations embedded in the API
+//!
+//! `migrations/` (the Supabase project's migration directory) is compiled
+//! into the crate, so a build always knows the schema it needs and can apply
+//! it with `carp-api migrate` instead of relying on functions such as
+//! `get_agent_download_info` having been created by hand.
+//!
+//! Applied versions are recorded in `supabase_migrations.schema_migrations`,
+//! the table the Supabase CLI uses, so databases migrated with either tool
+//! agree on what has run and `/readyz` keeps reporting the right version.
+
+use anyhow::{Context, Result};
+use sqlx::migrate::{Migration, Migrator};
+use sqlx::{Connection, PgConnection};
+
+/// Every migration, in version order
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// Newest migration in this build, as the fixed-width version string
+pub fn latest_version() -> Option<String> {
+    MIGRATOR
+        .iter()
+        .map(|migration| migration.version)
+        .max()
+        .map(|version| version.to_string())
+}
+
+/// Connect to the database named by `url`, typically `DATABASE_URL`
+pub async fn connect(url: &str) -> Result<PgConnection> {
+    PgConnection::connect(url)
+        .await
+        .context("Failed to connect to the database")
+}
+
+/// Migrations in this build the database hasn't applied yet
+pub async fn pending(conn: &mut PgConnection) -> Result<Vec<&'static Migration>> {
+    ensure_history_table(conn).await?;
+
+    let applied: Vec<String> =
+        sqlx::query_scalar("SELECT version FROM supabase_migrations.schema_migrations")
+            .fetch_all(&mut *conn)
+            .await
+            .context("Failed to read applied migrations")?;
+
+    Ok(MIGRATOR
+        .iter()
+        .filter(|migration| !applied.contains(&migration.version.to_string()))
+        .collect())
+}
+
+/// Apply every pending migration in order, each in its own transaction,
+/// returning the ones applied. Stops at the first failure.
+pub async fn run(conn: &mut PgConnection) -> Result<Vec<&'static Migration>> {
+    let pending = pending(conn).await?;
+
+    for migration in &pending {
+        let mut tx = conn.begin().await?;
+
+        sqlx::raw_sql(&migration.sql)
+            .execute(&mut *tx)
+            .await
+            .with_context(|| {
+                format!(
+                    "Migration {} {} failed",
+                    migration.version, migration.description
+                )
+            })?;
+
+        sqlx::query(
+            "INSERT INTO supabase_migrations.schema_migrations (version, name, statements)
+             VALUES ($1, $2, ARRAY[$3])",
+        )
+        .bind(migration.version.to_string())
+        .bind(migration.description.replace(' ', "_"))
+        .bind(migration.sql.as_ref())
+        .execute(&mut *tx)
+        .await
+        .context("Failed to record migration")?;
+
+        tx.commit().await?;
+    }
+
+    Ok(pending)
+}
+
+/// Create the history table for databases the Supabase CLI never touched
+async fn ensure_history_table(conn: &mut PgConnection) -> Result<()> {
+    sqlx::raw_sql(
+        "CREATE SCHEMA IF NOT EXISTS supabase_migrations;
+         CREATE TABLE IF NOT EXISTS supabase_migrations.schema_migrations (
+           version TEXT NOT NULL PRIMARY KEY,
+           statements TEXT[],
+           name TEXT
+         );",
+    )
+    .execute(&mut *conn)
+    .await
+    .context("Failed to create the migration history table")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn test_required_schema_is_latest_migration() {
+        assert_eq!(
+            latest_version().as_deref(),
+            Some(crate::health::REQUIRED_SCHEMA_VERSION),
+            "bump REQUIRED_SCHEMA_VERSION to the newest migration"
+        );
+    }
+
+    /// Every database function the handlers call must come from a migration
+    #[test]
+    fn test_called_functions_are_migrated() {
+        fn sources(dir: &Path, found: &mut Vec<String>) {
+            for entry in fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    sources(&path, found);
+                } else if path.extension().is_some_and(|ext| ext == "rs") {
+                    found.push(fs::read_to_string(path).unwrap());
+                }
+            }
+        }
+
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let mut code = Vec::new();
+        sources(&root.join("api"), &mut code);
+        sources(&root.join("shared"), &mut code);
+
+        let schema = MIGRATOR
+            .iter()
+            .map(|migration| migration.sql.to_lowercase())
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        for source in &code {
+            // REST calls to `rpc/<function>` and postgrest `.rpc(<function>, ..)` ones
+            let calls = source.split("rpc/").skip(1).chain(
+                source
+                    .split(".rpc(")
+                    .skip(1)
+                    .map(|call| call.trim_start().trim_start_matches('"')),
+            );
+            for call in calls {
+                let name: String = call
+                    .chars()
+                    .take_while(|c| c.is_ascii_alphanumeric() || *c == '_')
+                    .collect();
+                if name.is_empty() {
+                    continue;
+                }
+                let defined = [
+                    format!("function public.{name}("),
+                    format!("function \"public\".\"{name}\"("),
+                    format!("function {name}("),
+                ];
+                assert!(
+                    defined.iter().any(|definition| schema.contains(definition)),
+                    "rpc/{name} is called but no migration defines it"
+                );
+            }
+        }
+    }
+}
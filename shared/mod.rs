@@ -21,7 +21,7 @@
 //!
 //! ### For JWT-only endpoints (API key management):
 //!
-//! ```rust
+//! ```rust,ignore
 //! use shared::middleware::jwt_middleware;
 //!
 //! pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
@@ -32,7 +32,7 @@
 //!
 //! ### For API key-only endpoints (agent operations):
 //!
-//! ```rust
+//! ```rust,ignore
 //! use shared::middleware::{api_key_middleware, require_scope};
 //!
 //! pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
@@ -42,14 +42,58 @@
 //! }
 //! ```
 
+pub mod archive;
 pub mod auth;
+pub mod content_policy;
+pub mod email;
+pub mod feature_flags;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod health;
+pub mod idempotency;
+pub mod jobs;
+pub mod local;
+pub mod maintenance;
 pub mod middleware;
+pub mod migrations;
+pub mod moderation;
+pub mod namespaces;
+pub mod oci;
+pub mod package_cache;
+pub mod problem;
+pub mod provenance;
+pub mod rate_limit;
+pub mod registry;
+pub mod resolution;
+pub mod seed;
+pub mod signing;
+pub mod sso;
+pub mod tenant;
+pub mod url_signer;
+pub mod validation;
+pub mod visibility;
 
 // Re-export commonly used types and functions
 pub use auth::{
     authenticate_api_key, authenticate_jwt, check_scope, extract_bearer_token, guess_token_type,
-    hash_api_key, validate_jwt_token, ApiError, AuthConfig, AuthMethod, AuthenticatedUser,
-    SupabaseJwtClaims, TokenType, UserMetadata,
+    hash_api_key, record_api_key_use, sync_api_key_user, sync_jwt_user, validate_jwt_token,
+    ApiError, AuthConfig, AuthMethod, AuthenticatedUser, KeyUsage, SupabaseJwtClaims, TokenType,
+    UserMetadata,
 };
 
-pub use middleware::{api_key_middleware, jwt_middleware, require_scope, AuthStrategy};
+pub use middleware::{
+    api_key_middleware, authenticate_request, jwt_middleware, jwt_or_api_key_middleware,
+    optional_api_key_middleware, require_scope, AuthStrategy,
+};
+
+pub use archive::{validate_package, PackageFormat};
+pub use content_policy::ContentPolicy;
+pub use feature_flags::{ensure_enabled, Flag};
+pub use idempotency::{claim_idempotency_key, IdempotencyClaim};
+pub use maintenance::{ensure_writable, Maintenance};
+pub use moderation::{screen_text, ModerationStatus, ReportReason, Screening};
+pub use provenance::{validate_provenance, Provenance};
+pub use rate_limit::{check_rate_limit, client_ip, RateLimit, RateLimitTier};
+pub use signing::{inspect_signature_bundle, TransparencyLogEntry};
+pub use validation::{Validate, ValidatedJson, ValidatedQuery};
+pub use visibility::AgentVisibility;
@@ -0,0 +1,164 @@
+//! Screening of user-written text before it is shown to others
+//!
+//! Reviews run through [`screen_text`] when they are written. Text that trips
+//! a rule isn't rejected: it is stored as `pending` so a moderator can publish
+//! or hide it, and it stays out of public listings and aggregates meanwhile.
+//!
+//! Abuse reports against agents land in the same moderation queue, tagged
+//! with a [`ReportReason`].
+
+use serde::{Deserialize, Serialize};
+use std::env;
+
+/// Links allowed before text is held for review; reviews rarely need many
+const MAX_LINKS: usize = 2;
+
+/// Moderation state of user-written content, as stored in the database
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModerationStatus {
+    /// Visible to everyone
+    Published,
+    /// Held until a moderator looks at it
+    Pending,
+    /// Removed by a moderator
+    Hidden,
+}
+
+impl ModerationStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ModerationStatus::Published => "published",
+            ModerationStatus::Pending => "pending",
+            ModerationStatus::Hidden => "hidden",
+        }
+    }
+}
+
+/// Why an agent was reported
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportReason {
+    /// Prompts or files that try to cause harm, e.g. exfiltrating secrets
+    Malicious,
+    /// Published in breach of the original work's license
+    LicenseViolation,
+    /// Pretends to be another author or project
+    Impersonation,
+    /// Low-effort, duplicated or promotional content
+    Spam,
+    /// Offensive or otherwise inappropriate content
+    Inappropriate,
+    /// Anything else; details are required
+    Other,
+}
+
+impl ReportReason {
+    pub const ALL: [ReportReason; 6] = [
+        ReportReason::Malicious,
+        ReportReason::LicenseViolation,
+        ReportReason::Impersonation,
+        ReportReason::Spam,
+        ReportReason::Inappropriate,
+        ReportReason::Other,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ReportReason::Malicious => "malicious",
+            ReportReason::LicenseViolation => "license_violation",
+            ReportReason::Impersonation => "impersonation",
+            ReportReason::Spam => "spam",
+            ReportReason::Inappropriate => "inappropriate",
+            ReportReason::Other => "other",
+        }
+    }
+}
+
+/// Outcome of screening a piece of text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Screening {
+    Allow,
+    /// Hold for a moderator, with the rule that matched
+    Hold(String),
+}
+
+impl Screening {
+    /// Status to store the screened content with
+    pub fn status(&self) -> ModerationStatus {
+        match self {
+            Screening::Allow => ModerationStatus::Published,
+            Screening::Hold(_) => ModerationStatus::Pending,
+        }
+    }
+}
+
+/// Screen text against the built-in rules and the terms in
+/// `MODERATION_BLOCKLIST` (comma-separated, matched case-insensitively)
+pub fn screen_text(text: &str) -> Screening {
+    let blocklist = env::var("MODERATION_BLOCKLIST").unwrap_or_default();
+    screen_text_with(text, &blocklist)
+}
+
+fn screen_text_with(text: &str, blocklist: &str) -> Screening {
+    let lowered = text.to_lowercase();
+
+    let blocked = blocklist
+        .split(',')
+        .map(|term| term.trim().to_lowercase())
+        .find(|term| !term.is_empty() && lowered.contains(term.as_str()));
+    if let Some(term) = blocked {
+        return Screening::Hold(format!("contains blocked term '{term}'"));
+    }
+
+    let links = lowered.matches("http://").count() + lowered.matches("https://").count();
+    if links > MAX_LINKS {
+        return Screening::Hold(format!("contains {links} links"));
+    }
+
+    Screening::Allow
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_screen_text_allows_plain_text() {
+        let screening = screen_text_with("Works well for reviewing PRs", "spam");
+        assert_eq!(screening, Screening::Allow);
+        assert_eq!(screening.status(), ModerationStatus::Published);
+    }
+
+    #[test]
+    fn test_screen_text_holds_blocked_terms() {
+        let screening = screen_text_with("Buy CHEAP pills here", "spam, cheap pills");
+        assert_eq!(
+            screening,
+            Screening::Hold("contains blocked term 'cheap pills'".to_string())
+        );
+        assert_eq!(screening.status(), ModerationStatus::Pending);
+    }
+
+    #[test]
+    fn test_report_reason_round_trips() {
+        for reason in ReportReason::ALL {
+            let json = serde_json::to_string(&reason).unwrap();
+            assert_eq!(json, format!("\"{}\"", reason.as_str()));
+            assert_eq!(serde_json::from_str::<ReportReason>(&json).unwrap(), reason);
+        }
+        assert!(serde_json::from_str::<ReportReason>("\"boring\"").is_err());
+    }
+
+    #[test]
+    fn test_screen_text_holds_link_heavy_text() {
+        let text = "see https://a.example https://b.example http://c.example";
+        assert_eq!(
+            screen_text_with(text, ""),
+            Screening::Hold("contains 3 links".to_string())
+        );
+        assert_eq!(
+            screen_text_with("docs at https://a.example", ""),
+            Screening::Allow
+        );
+    }
+}
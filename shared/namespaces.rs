@@ -0,0 +1,662 @@
+//! Verified namespaces
+//!
+//! An organization's slug is a namespace covering the agent of the same name
+//! and every agent named `<slug>-...`. A namespace is claimed by proving
+//! control of the GitHub organization with the same name, in one of three
+//! ways:
+//!
+//! - `github`: the caller's GitHub identity, from signing in with GitHub, is
+//!   a public member of the organization
+//! - `dns`: a `_carp-challenge.<domain>` TXT record holds the challenge
+//! - `url`: `https://<domain>/.well-known/carp-verification.txt` holds it
+//!
+//! where `<domain>` is the host of the website on the organization's GitHub
+//! profile, so a domain proves nothing about organizations that don't list
+//! it. A verified claim marks the organization verified and makes the
+//! claimant an owner; agents in the namespace owned by its members are then
+//! shown as verified.
+//!
+//! Admins can also verify a user or an existing organization directly, as a
+//! [`Publisher`], without a proof.
+
+use crate::tenant;
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::env;
+use uuid::Uuid;
+
+/// Label prepended to the domain for the DNS challenge record
+pub const DNS_CHALLENGE_LABEL: &str = "_carp-challenge";
+
+/// Path of the URL challenge file
+pub const WELL_KNOWN_PATH: &str = "/.well-known/carp-verification.txt";
+
+const GITHUB_API_URL: &str = "https://api.github.com";
+/// DNS-over-HTTPS resolver answering in Google's JSON format
+const DOH_URL: &str = "https://cloudflare-dns.com/dns-query";
+
+/// How a namespace claim is proven
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClaimMethod {
+    Github,
+    Dns,
+    Url,
+}
+
+impl ClaimMethod {
+    pub const ALL: [ClaimMethod; 3] = [ClaimMethod::Github, ClaimMethod::Dns, ClaimMethod::Url];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ClaimMethod::Github => "github",
+            ClaimMethod::Dns => "dns",
+            ClaimMethod::Url => "url",
+        }
+    }
+
+    pub fn parse(method: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|known| known.as_str() == method)
+    }
+}
+
+/// `namespace` as an organization slug, without a leading `@`, if it's a
+/// valid one
+pub fn parse_namespace(namespace: &str) -> Option<String> {
+    let slug = namespace.trim().trim_start_matches('@').to_lowercase();
+    let valid = (1..=39).contains(&slug.len())
+        && !slug.starts_with('-')
+        && slug
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    valid.then_some(slug)
+}
+
+/// A GitHub organization, as much of it as a claim needs
+#[derive(Debug, Clone, Deserialize)]
+pub struct GithubOrg {
+    pub login: String,
+    /// The website on its profile, with or without a scheme
+    #[serde(default)]
+    pub blog: Option<String>,
+}
+
+impl GithubOrg {
+    /// Host of the organization's website, which DNS and URL challenges are
+    /// published on
+    pub fn domain(&self) -> Option<String> {
+        let blog = self.blog.as_deref()?.trim();
+        if blog.is_empty() {
+            return None;
+        }
+        let with_scheme = if blog.contains("://") {
+            blog.to_string()
+        } else {
+            format!("https://{blog}")
+        };
+        let host = url::Url::parse(&with_scheme)
+            .ok()?
+            .host_str()?
+            .to_lowercase();
+        Some(host.trim_start_matches("www.").to_string())
+    }
+}
+
+/// A challenge handed out for a DNS or URL claim
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Challenge {
+    pub namespace: String,
+    pub method: ClaimMethod,
+    pub domain: String,
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl Challenge {
+    /// The DNS record name or URL the value must be published at
+    pub fn location(&self) -> String {
+        match self.method {
+            ClaimMethod::Dns => format!("{DNS_CHALLENGE_LABEL}.{}", self.domain),
+            ClaimMethod::Github | ClaimMethod::Url => {
+                format!("https://{}{WELL_KNOWN_PATH}", self.domain)
+            }
+        }
+    }
+
+    /// What to publish there
+    pub fn value(&self) -> String {
+        format!("carp-verification={}", self.token)
+    }
+}
+
+/// A verified organization, as stored
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VerifiedNamespace {
+    pub slug: String,
+    pub verified_at: DateTime<Utc>,
+    pub verification_method: ClaimMethod,
+    pub verified_domain: Option<String>,
+}
+
+/// A user, by GitHub username, or an organization, by `@slug`, that admins
+/// can mark verified
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Publisher {
+    User(String),
+    Organization(String),
+}
+
+impl Publisher {
+    pub fn parse(publisher: &str) -> Option<Self> {
+        let publisher = publisher.trim();
+        if publisher.starts_with('@') {
+            return parse_namespace(publisher).map(Publisher::Organization);
+        }
+        let valid = (1..=39).contains(&publisher.len())
+            && !publisher.starts_with('-')
+            && publisher
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-');
+        valid.then(|| Publisher::User(publisher.to_string()))
+    }
+}
+
+impl std::fmt::Display for Publisher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Publisher::User(username) => f.write_str(username),
+            Publisher::Organization(slug) => write!(f, "@{slug}"),
+        }
+    }
+}
+
+/// Whether a publisher is verified, after an admin changed it
+#[derive(Debug, Clone, Serialize)]
+pub struct PublisherVerification {
+    pub publisher: String,
+    pub verified: bool,
+    pub verified_at: Option<DateTime<Utc>>,
+}
+
+/// Checks proofs against GitHub, DNS and the organization's website
+pub struct ProofChecker {
+    client: reqwest::Client,
+    github_api_url: String,
+    github_token: Option<String>,
+    doh_url: String,
+    /// Scheme of challenge URLs; only tests serve them over plain HTTP
+    scheme: &'static str,
+}
+
+impl ProofChecker {
+    /// Public GitHub and DNS-over-HTTPS endpoints, unless
+    /// `CARP_GITHUB_API_URL` or `CARP_DOH_URL` point elsewhere. A
+    /// `GITHUB_TOKEN` raises GitHub's rate limit.
+    pub fn from_env() -> Self {
+        Self::new(
+            env::var("CARP_GITHUB_API_URL").unwrap_or_else(|_| GITHUB_API_URL.to_string()),
+            env::var("CARP_DOH_URL").unwrap_or_else(|_| DOH_URL.to_string()),
+            env::var("GITHUB_TOKEN")
+                .ok()
+                .filter(|token| !token.is_empty()),
+        )
+    }
+
+    pub fn new(github_api_url: String, doh_url: String, github_token: Option<String>) -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .timeout(std::time::Duration::from_secs(10))
+                // A challenge URL that redirects elsewhere isn't on the domain
+                .redirect(reqwest::redirect::Policy::none())
+                .user_agent("carp-registry")
+                .build()
+                .unwrap_or_default(),
+            github_api_url: github_api_url.trim_end_matches('/').to_string(),
+            github_token,
+            doh_url,
+            scheme: "https",
+        }
+    }
+
+    /// The GitHub organization called `name`, if there is one
+    pub async fn github_org(&self, name: &str) -> Result<Option<GithubOrg>> {
+        let response = self
+            .github(&format!("/orgs/{}", urlencoding::encode(name)))
+            .send()
+            .await
+            .context("Failed to reach GitHub")?;
+        match response.status().as_u16() {
+            200 => Ok(Some(
+                response
+                    .json()
+                    .await
+                    .context("Unexpected GitHub response")?,
+            )),
+            404 => Ok(None),
+            status => bail!("GitHub answered {status} looking up organization '{name}'"),
+        }
+    }
+
+    /// Whether `username` shows as a member of the GitHub organization `org`
+    /// to everyone
+    pub async fn is_public_member(&self, org: &str, username: &str) -> Result<bool> {
+        let response = self
+            .github(&format!(
+                "/orgs/{}/public_members/{}",
+                urlencoding::encode(org),
+                urlencoding::encode(username)
+            ))
+            .send()
+            .await
+            .context("Failed to reach GitHub")?;
+        match response.status().as_u16() {
+            204 => Ok(true),
+            404 => Ok(false),
+            status => bail!("GitHub answered {status} checking membership of '{org}'"),
+        }
+    }
+
+    /// Whether the challenge's value is published where it asks
+    pub async fn is_published(&self, challenge: &Challenge) -> Result<bool> {
+        match challenge.method {
+            ClaimMethod::Dns => self.has_txt_record(challenge).await,
+            ClaimMethod::Url => self.has_well_known_file(challenge).await,
+            ClaimMethod::Github => bail!("GitHub claims have no challenge"),
+        }
+    }
+
+    async fn has_txt_record(&self, challenge: &Challenge) -> Result<bool> {
+        #[derive(Deserialize)]
+        struct Answer {
+            data: String,
+        }
+        #[derive(Deserialize)]
+        struct DnsResponse {
+            #[serde(rename = "Answer", default)]
+            answer: Vec<Answer>,
+        }
+
+        let response: DnsResponse = self
+            .client
+            .get(&self.doh_url)
+            .query(&[("name", challenge.location().as_str()), ("type", "TXT")])
+            .header("accept", "application/dns-json")
+            .send()
+            .await
+            .context("Failed to look up the challenge record")?
+            .error_for_status()
+            .context("DNS lookup failed")?
+            .json()
+            .await
+            .context("Unexpected DNS response")?;
+
+        // TXT data comes quoted, and long values split into quoted strings
+        let value = challenge.value();
+        Ok(response
+            .answer
+            .iter()
+            .any(|answer| answer.data.replace("\" \"", "").trim_matches('"') == value))
+    }
+
+    async fn has_well_known_file(&self, challenge: &Challenge) -> Result<bool> {
+        let url = format!("{}://{}{WELL_KNOWN_PATH}", self.scheme, challenge.domain);
+        let response = self
+            .client
+            .get(&url)
+            .send()
+            .await
+            .with_context(|| format!("Failed to fetch {url}"))?;
+        if !response.status().is_success() {
+            return Ok(false);
+        }
+
+        let body = response.text().await.unwrap_or_default();
+        let value = challenge.value();
+        Ok(body.lines().any(|line| line.trim() == value))
+    }
+
+    fn github(&self, path: &str) -> reqwest::RequestBuilder {
+        let request = self
+            .client
+            .get(format!("{}{path}", self.github_api_url))
+            .header("accept", "application/vnd.github+json");
+        match &self.github_token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+}
+
+/// Namespace claims and verified organizations, through Supabase's REST API
+pub struct NamespaceStore {
+    client: reqwest::Client,
+    supabase_url: String,
+    supabase_key: String,
+}
+
+impl NamespaceStore {
+    pub fn new(supabase_url: String, supabase_key: String) -> Self {
+        Self {
+            client: tenant::http_client(),
+            supabase_url,
+            supabase_key,
+        }
+    }
+
+    /// The user's outstanding challenge for `namespace` by `method` on
+    /// `domain`, or a new one if there's none or it expired
+    pub async fn challenge(
+        &self,
+        namespace: &str,
+        user_id: Uuid,
+        method: ClaimMethod,
+        domain: &str,
+    ) -> Result<Challenge> {
+        let existing: Vec<Challenge> = self
+            .request(
+                self.client.get(format!(
+                    "{}/rest/v1/namespace_claims?namespace=eq.{}&user_id=eq.{user_id}&method=eq.{}&domain=eq.{}&expires_at=gt.now&select=namespace,method,domain,token,expires_at",
+                    self.supabase_url,
+                    urlencoding::encode(namespace),
+                    method.as_str(),
+                    urlencoding::encode(domain)
+                )),
+            )
+            .await?;
+        if let Some(challenge) = existing.into_iter().next() {
+            return Ok(challenge);
+        }
+
+        // Replaces an expired challenge, or one for a domain the
+        // organization no longer lists
+        let mut created: Vec<Challenge> = self
+            .request(
+                self.client
+                    .post(format!(
+                        "{}/rest/v1/namespace_claims?on_conflict=namespace,user_id,method&select=namespace,method,domain,token,expires_at",
+                        self.supabase_url
+                    ))
+                    .header("Prefer", "resolution=merge-duplicates,return=representation")
+                    .json(&json!({
+                        "namespace": namespace,
+                        "user_id": user_id,
+                        "method": method.as_str(),
+                        "domain": domain,
+                        "token": Uuid::new_v4().simple().to_string(),
+                        "created_at": Utc::now(),
+                        "expires_at": Utc::now() + chrono::Duration::days(7),
+                    })),
+            )
+            .await?;
+        created.pop().context("Challenge was not saved")
+    }
+
+    /// Mark `namespace` verified for the user, who becomes an owner of its
+    /// organization
+    pub async fn verify(
+        &self,
+        namespace: &str,
+        user_id: Uuid,
+        method: ClaimMethod,
+        domain: Option<&str>,
+    ) -> Result<VerifiedNamespace> {
+        self.request(
+            self.client
+                .post(format!(
+                    "{}/rest/v1/rpc/verify_namespace",
+                    self.supabase_url
+                ))
+                .json(&json!({
+                    "p_namespace": namespace,
+                    "p_user_id": user_id,
+                    "p_method": method.as_str(),
+                    "p_domain": domain,
+                })),
+        )
+        .await
+    }
+
+    /// Mark `publisher` verified by the admin `admin_id`, or no longer
+    /// verified. `None` if there's no such user or organization; admins
+    /// don't create organizations, since nobody would belong to them.
+    pub async fn set_verified(
+        &self,
+        publisher: &Publisher,
+        admin_id: Uuid,
+        verified: bool,
+    ) -> Result<Option<PublisherVerification>> {
+        let verified_at = verified.then(Utc::now);
+        let verified_by = verified.then_some(admin_id);
+        let (table, filter, body) = match publisher {
+            Publisher::User(username) => (
+                "profiles",
+                format!("github_username=ilike.{}", urlencoding::encode(username)),
+                json!({ "verified_at": verified_at, "verified_by": verified_by }),
+            ),
+            Publisher::Organization(slug) => (
+                "organizations",
+                format!("slug=eq.{}", urlencoding::encode(slug)),
+                json!({
+                    "verified_at": verified_at,
+                    "verified_by": verified_by,
+                    "verification_method": verified.then_some("admin"),
+                    "verified_domain": null,
+                }),
+            ),
+        };
+
+        #[derive(Deserialize)]
+        struct Row {
+            verified_at: Option<DateTime<Utc>>,
+        }
+        let updated: Vec<Row> = self
+            .request(
+                self.client
+                    .patch(format!(
+                        "{}/rest/v1/{table}?{filter}&select=verified_at",
+                        self.supabase_url
+                    ))
+                    .header("Prefer", "return=representation")
+                    .json(&body),
+            )
+            .await?;
+        Ok(updated.into_iter().next().map(|row| PublisherVerification {
+            publisher: publisher.to_string(),
+            verified: row.verified_at.is_some(),
+            verified_at: row.verified_at,
+        }))
+    }
+
+    async fn request<T: DeserializeOwned>(&self, request: reqwest::RequestBuilder) -> Result<T> {
+        let response = request
+            .header("apikey", &self.supabase_key)
+            .header("Authorization", format!("Bearer {}", self.supabase_key))
+            .send()
+            .await
+            .context("Database request failed")?;
+        let status = response.status();
+        let body = response.text().await?;
+        if !status.is_success() {
+            bail!("Database request failed with status {status}: {body}");
+        }
+        serde_json::from_str(&body).with_context(|| format!("Unexpected database response: {body}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn challenge(method: ClaimMethod, domain: &str) -> Challenge {
+        Challenge {
+            namespace: "acme".to_string(),
+            method,
+            domain: domain.to_string(),
+            token: "0123abcd".to_string(),
+            expires_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_namespaces_and_domains() {
+        assert_eq!(parse_namespace("@Acme").as_deref(), Some("acme"));
+        assert_eq!(parse_namespace(" acme-labs ").as_deref(), Some("acme-labs"));
+        assert_eq!(parse_namespace("-acme"), None);
+        assert_eq!(parse_namespace("acme_labs"), None);
+        assert_eq!(parse_namespace(""), None);
+
+        let org = |blog: &str| GithubOrg {
+            login: "acme".to_string(),
+            blog: Some(blog.to_string()),
+        };
+        assert_eq!(
+            org("https://www.Acme.dev/about").domain().as_deref(),
+            Some("acme.dev")
+        );
+        assert_eq!(org("acme.dev").domain().as_deref(), Some("acme.dev"));
+        assert_eq!(org("").domain(), None);
+
+        assert_eq!(
+            Publisher::parse("@Acme"),
+            Some(Publisher::Organization("acme".to_string()))
+        );
+        assert_eq!(
+            Publisher::parse("OctoCat"),
+            Some(Publisher::User("OctoCat".to_string()))
+        );
+        assert_eq!(Publisher::parse("octo cat"), None);
+        assert_eq!(Publisher::parse("@"), None);
+        assert_eq!(
+            Publisher::Organization("acme".to_string()).to_string(),
+            "@acme"
+        );
+
+        assert_eq!(
+            challenge(ClaimMethod::Dns, "acme.dev").location(),
+            "_carp-challenge.acme.dev"
+        );
+        assert_eq!(
+            challenge(ClaimMethod::Url, "acme.dev").location(),
+            "https://acme.dev/.well-known/carp-verification.txt"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_proofs_are_checked_where_published() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/orgs/acme/public_members/alice"))
+            .respond_with(ResponseTemplate::new(204))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/orgs/acme/public_members/mallory"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/dns-query"))
+            .and(query_param("name", "_carp-challenge.acme.dev"))
+            .and(query_param("type", "TXT"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "Status": 0,
+                "Answer": [
+                    { "name": "_carp-challenge.acme.dev", "type": 16, "data": "\"v=spf1 -all\"" },
+                    { "name": "_carp-challenge.acme.dev", "type": 16, "data": "\"carp-verification=0123abcd\"" },
+                ],
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/.well-known/carp-verification.txt"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_string("carp-verification=0123abcd\n"),
+            )
+            .mount(&server)
+            .await;
+
+        let mut checker =
+            ProofChecker::new(server.uri(), format!("{}/dns-query", server.uri()), None);
+        checker.scheme = "http";
+
+        assert!(checker.is_public_member("acme", "alice").await.unwrap());
+        assert!(!checker.is_public_member("acme", "mallory").await.unwrap());
+
+        assert!(checker
+            .is_published(&challenge(ClaimMethod::Dns, "acme.dev"))
+            .await
+            .unwrap());
+        let mut wrong = challenge(ClaimMethod::Dns, "acme.dev");
+        wrong.token = "ffff".to_string();
+        assert!(!checker.is_published(&wrong).await.unwrap());
+
+        let domain = server.address().to_string();
+        assert!(checker
+            .is_published(&challenge(ClaimMethod::Url, &domain))
+            .await
+            .unwrap());
+        let mut wrong = challenge(ClaimMethod::Url, &domain);
+        wrong.token = "ffff".to_string();
+        assert!(!checker.is_published(&wrong).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_admins_verify_existing_publishers() {
+        let server = MockServer::start().await;
+        Mock::given(method("PATCH"))
+            .and(path("/rest/v1/organizations"))
+            .and(query_param("slug", "eq.acme"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+                { "verified_at": "2025-08-28T00:00:00Z" }
+            ])))
+            .mount(&server)
+            .await;
+        Mock::given(method("PATCH"))
+            .and(path("/rest/v1/profiles"))
+            .and(query_param("github_username", "ilike.octocat"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+                { "verified_at": null }
+            ])))
+            .mount(&server)
+            .await;
+        Mock::given(method("PATCH"))
+            .and(path("/rest/v1/profiles"))
+            .and(query_param("github_username", "ilike.nobody"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([])))
+            .mount(&server)
+            .await;
+
+        let store = NamespaceStore::new(server.uri(), "key".to_string());
+        let admin = Uuid::new_v4();
+
+        let org = Publisher::Organization("acme".to_string());
+        let verification = store
+            .set_verified(&org, admin, true)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(verification.publisher, "@acme");
+        assert!(verification.verified);
+
+        let user = Publisher::User("octocat".to_string());
+        let verification = store
+            .set_verified(&user, admin, false)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!verification.verified);
+        assert_eq!(verification.verified_at, None);
+
+        let missing = Publisher::User("nobody".to_string());
+        assert!(store
+            .set_verified(&missing, admin, true)
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
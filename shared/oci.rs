@@ -0,0 +1,563 @@
+//! OCI distribution mapping for agent packages
+//!
+//! Each published version is an OCI artifact: a manifest whose config blob
+//! names the version and carries its definition, and whose only layer is the
+//! package archive. The layer digest is the package checksum, so tools that
+//! verify digests check the same bytes `carp pull` does.
+//!
+//! Versions pushed through the OCI API keep the manifest and config they were
+//! pushed with. Others get a manifest generated from the version, which only
+//! depends on immutable version data and so keeps its digest.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::archive::{package_checksum, PackageFormat};
+
+pub const MANIFEST_MEDIA_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+pub const ARTIFACT_TYPE: &str = "application/vnd.carp.agent.v1";
+pub const CONFIG_MEDIA_TYPE: &str = "application/vnd.carp.agent.config.v1+json";
+
+/// Header every distribution API response carries
+pub const API_VERSION_HEADER: (&str, &str) = ("docker-distribution-api-version", "registry/2.0");
+
+/// Most tags listed per page when the client doesn't ask for fewer
+pub const MAX_TAGS_PAGE: usize = 1000;
+
+/// Media type of the layer holding a package in `format`
+pub fn layer_media_type(format: PackageFormat) -> &'static str {
+    match format {
+        PackageFormat::Zip => "application/vnd.carp.agent.package.v1.zip",
+        PackageFormat::TarGz => "application/vnd.carp.agent.package.v1.tar+gzip",
+        PackageFormat::TarZst => "application/vnd.carp.agent.package.v1.tar+zstd",
+    }
+}
+
+/// Package format of a pushed layer, accepting plain archive types and OCI
+/// tar layers as well as carp's own
+pub fn layer_format(media_type: &str) -> Option<PackageFormat> {
+    match media_type {
+        "application/vnd.carp.agent.package.v1.zip" => Some(PackageFormat::Zip),
+        "application/vnd.carp.agent.package.v1.tar+gzip"
+        | "application/vnd.oci.image.layer.v1.tar+gzip" => Some(PackageFormat::TarGz),
+        "application/vnd.carp.agent.package.v1.tar+zstd"
+        | "application/vnd.oci.image.layer.v1.tar+zstd" => Some(PackageFormat::TarZst),
+        other => PackageFormat::from_content_type(other),
+    }
+}
+
+/// Reference to a blob within a manifest
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Descriptor {
+    #[serde(rename = "mediaType")]
+    pub media_type: String,
+    pub digest: String,
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub annotations: BTreeMap<String, String>,
+}
+
+/// OCI image manifest, as used for artifacts
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Manifest {
+    pub schema_version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artifact_type: Option<String>,
+    pub config: Descriptor,
+    pub layers: Vec<Descriptor>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub annotations: BTreeMap<String, String>,
+}
+
+/// Config blob of an agent artifact
+///
+/// Generated configs hold only the name, version and definition. A pushed
+/// config may also carry the metadata the version is published with.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AgentConfig {
+    pub name: String,
+    #[serde(default)]
+    pub version: String,
+    #[serde(default)]
+    pub definition: serde_json::Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub homepage: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repository: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub license: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub readme: Option<String>,
+}
+
+impl AgentConfig {
+    /// Description to publish with: the config's own, or the one in the
+    /// definition's frontmatter
+    pub fn description(&self) -> Option<String> {
+        self.description
+            .clone()
+            .or_else(|| {
+                self.definition
+                    .pointer("/metadata/description")
+                    .and_then(|description| description.as_str())
+                    .map(str::to_string)
+            })
+            .filter(|description| !description.trim().is_empty())
+    }
+}
+
+/// A downloadable version, as needed to describe it as an artifact
+#[derive(Debug, Clone)]
+pub struct PublishedVersion {
+    pub name: String,
+    pub version: String,
+    /// `sha256:<hex>`; older rows may lack the prefix
+    pub checksum: String,
+    pub file_size: u64,
+    pub content_type: String,
+    pub definition: serde_json::Value,
+    /// Path of the package in storage
+    pub file_path: String,
+    /// Manifest and config as pushed, for versions published over OCI
+    pub oci_manifest: Option<String>,
+    pub oci_config: Option<String>,
+}
+
+/// A version's manifest and config, with their digests
+#[derive(Debug, Clone)]
+pub struct Artifact {
+    pub manifest: Vec<u8>,
+    pub manifest_digest: String,
+    pub config: Vec<u8>,
+    pub config_digest: String,
+    pub layer_digest: String,
+}
+
+impl Artifact {
+    /// The artifact for `version`, or `None` if it has no usable checksum
+    pub fn for_version(version: &PublishedVersion) -> Option<Self> {
+        let layer_digest = normalize_digest(&version.checksum)?;
+
+        if let (Some(manifest), Some(config)) = (&version.oci_manifest, &version.oci_config) {
+            return Some(Artifact {
+                manifest_digest: digest(manifest.as_bytes()),
+                manifest: manifest.clone().into_bytes(),
+                config_digest: digest(config.as_bytes()),
+                config: config.clone().into_bytes(),
+                layer_digest,
+            });
+        }
+
+        let format =
+            PackageFormat::from_content_type(&version.content_type).unwrap_or(PackageFormat::Zip);
+        let config = serde_json::to_vec(&AgentConfig {
+            name: version.name.clone(),
+            version: version.version.clone(),
+            definition: version.definition.clone(),
+            ..AgentConfig::default()
+        })
+        .ok()?;
+        let config_digest = digest(&config);
+
+        let manifest = Manifest {
+            schema_version: 2,
+            media_type: Some(MANIFEST_MEDIA_TYPE.to_string()),
+            artifact_type: Some(ARTIFACT_TYPE.to_string()),
+            config: Descriptor {
+                media_type: CONFIG_MEDIA_TYPE.to_string(),
+                digest: config_digest.clone(),
+                size: config.len() as u64,
+                annotations: BTreeMap::new(),
+            },
+            layers: vec![Descriptor {
+                media_type: layer_media_type(format).to_string(),
+                digest: layer_digest.clone(),
+                size: version.file_size,
+                annotations: BTreeMap::from([(
+                    "org.opencontainers.image.title".to_string(),
+                    format!(
+                        "{}-{}.{}",
+                        version.name,
+                        version.version,
+                        format.extension()
+                    ),
+                )]),
+            }],
+            annotations: BTreeMap::from([
+                (
+                    "org.opencontainers.image.title".to_string(),
+                    version.name.clone(),
+                ),
+                (
+                    "org.opencontainers.image.version".to_string(),
+                    version.version.clone(),
+                ),
+            ]),
+        };
+        let manifest = serde_json::to_vec(&manifest).ok()?;
+
+        Some(Artifact {
+            manifest_digest: digest(&manifest),
+            manifest,
+            config,
+            config_digest,
+            layer_digest,
+        })
+    }
+}
+
+/// A blob found by digest among an agent's versions
+pub enum Blob<'a> {
+    /// Config blobs are small and served directly
+    Config(Vec<u8>),
+    /// Package layers are fetched from storage
+    Layer(&'a PublishedVersion),
+}
+
+/// `sha256:<hex>` digest of some bytes
+pub fn digest(data: &[u8]) -> String {
+    package_checksum(data)
+}
+
+/// Whether a reference is a digest rather than a tag
+pub fn is_digest(reference: &str) -> bool {
+    reference
+        .strip_prefix("sha256:")
+        .is_some_and(|hex| hex.len() == 64 && hex.bytes().all(|b| b.is_ascii_hexdigit()))
+}
+
+fn normalize_digest(checksum: &str) -> Option<String> {
+    let checksum = checksum.trim().to_ascii_lowercase();
+    let digest = if checksum.starts_with("sha256:") {
+        checksum
+    } else {
+        format!("sha256:{checksum}")
+    };
+    is_digest(&digest).then_some(digest)
+}
+
+/// Tag naming a version. OCI tags can't contain `+`, so build metadata is
+/// separated with `_` instead, as Helm does.
+pub fn tag(version: &str) -> String {
+    version.replace('+', "_")
+}
+
+/// The version a tag or digest refers to, among versions listed oldest first
+///
+/// `latest` is the newest version unless one is literally called that.
+pub fn find_version<'a>(
+    versions: &'a [PublishedVersion],
+    reference: &str,
+) -> Option<(&'a PublishedVersion, Artifact)> {
+    let version = if is_digest(reference) {
+        return versions.iter().find_map(|version| {
+            let artifact = Artifact::for_version(version)?;
+            (artifact.manifest_digest == reference).then_some((version, artifact))
+        });
+    } else if let Some(version) = versions.iter().rev().find(|v| tag(&v.version) == reference) {
+        version
+    } else if reference == "latest" {
+        versions.last()?
+    } else {
+        return None;
+    };
+
+    Some((version, Artifact::for_version(version)?))
+}
+
+/// The config or layer blob with `digest`
+pub fn find_blob<'a>(versions: &'a [PublishedVersion], digest: &str) -> Option<Blob<'a>> {
+    versions.iter().rev().find_map(|version| {
+        let artifact = Artifact::for_version(version)?;
+        if artifact.layer_digest == digest {
+            Some(Blob::Layer(version))
+        } else if artifact.config_digest == digest {
+            Some(Blob::Config(artifact.config))
+        } else {
+            None
+        }
+    })
+}
+
+/// One page of an agent's tags: at most `n` after `last`, and whether more follow
+pub fn tags_page(
+    versions: &[PublishedVersion],
+    n: Option<usize>,
+    last: Option<&str>,
+) -> (Vec<String>, bool) {
+    let mut tags: Vec<String> = versions.iter().map(|v| tag(&v.version)).collect();
+    tags.sort();
+    tags.dedup();
+
+    let start = match last {
+        Some(last) => tags.partition_point(|tag| tag.as_str() <= last),
+        None => 0,
+    };
+    let n = n.unwrap_or(MAX_TAGS_PAGE).min(MAX_TAGS_PAGE);
+    let end = (start + n).min(tags.len());
+    let more = end < tags.len();
+    (tags[start..end].to_vec(), more)
+}
+
+/// The config and package layer of a pushed manifest
+#[derive(Debug)]
+pub struct PushedArtifact {
+    pub manifest: Manifest,
+    pub config: Descriptor,
+    pub layer: Descriptor,
+    pub format: PackageFormat,
+}
+
+/// Check a pushed manifest describes an agent: a carp config and a single
+/// package layer
+pub fn parse_pushed_manifest(data: &[u8]) -> Result<PushedArtifact, String> {
+    let manifest: Manifest =
+        serde_json::from_slice(data).map_err(|e| format!("Invalid manifest: {e}"))?;
+
+    if manifest.schema_version != 2 {
+        return Err("Only schemaVersion 2 manifests are supported".to_string());
+    }
+    if let Some(media_type) = &manifest.media_type {
+        if media_type != MANIFEST_MEDIA_TYPE {
+            return Err(format!(
+                "Unsupported manifest type '{media_type}'; push an OCI image manifest"
+            ));
+        }
+    }
+    if manifest.config.media_type != CONFIG_MEDIA_TYPE {
+        return Err(format!(
+            "The config blob must be {CONFIG_MEDIA_TYPE}, not '{}'",
+            manifest.config.media_type
+        ));
+    }
+    let [layer] = manifest.layers.as_slice() else {
+        return Err("An agent artifact has exactly one layer, its package".to_string());
+    };
+    let format = layer_format(&layer.media_type).ok_or_else(|| {
+        format!(
+            "Unsupported layer type '{}'; the layer must be a zip, tar.gz or tar.zst package",
+            layer.media_type
+        )
+    })?;
+    if !is_digest(&manifest.config.digest) || !is_digest(&layer.digest) {
+        return Err("Blobs must be referenced by sha256 digest".to_string());
+    }
+
+    Ok(PushedArtifact {
+        config: manifest.config.clone(),
+        layer: layer.clone(),
+        format,
+        manifest,
+    })
+}
+
+/// A distribution API endpoint, with the repository name it's for
+#[derive(Debug, PartialEq, Eq)]
+pub enum Route {
+    /// `/v2/`, which clients probe for API support
+    Base,
+    Manifest {
+        name: String,
+        reference: String,
+    },
+    Blob {
+        name: String,
+        digest: String,
+    },
+    Tags {
+        name: String,
+    },
+    /// Starting a blob upload
+    Uploads {
+        name: String,
+    },
+    /// Continuing or finishing a blob upload
+    Upload {
+        name: String,
+        session: String,
+    },
+}
+
+/// Parse a request path, with or without the leading `/v2`
+pub fn route(path: &str) -> Option<Route> {
+    let mut segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    if segments.first() == Some(&"v2") {
+        segments.remove(0);
+    }
+    if segments.is_empty() {
+        return Some(Route::Base);
+    }
+
+    let name = |end: usize| {
+        let name = segments[..end].join("/");
+        (!name.is_empty()).then_some(name)
+    };
+    let n = segments.len();
+    match segments.as_slice() {
+        [.., "blobs", "uploads"] => Some(Route::Uploads { name: name(n - 2)? }),
+        [.., "blobs", "uploads", session] => Some(Route::Upload {
+            name: name(n - 3)?,
+            session: session.to_string(),
+        }),
+        [.., "blobs", digest] => Some(Route::Blob {
+            name: name(n - 2)?,
+            digest: digest.to_string(),
+        }),
+        [.., "manifests", reference] => Some(Route::Manifest {
+            name: name(n - 2)?,
+            reference: reference.to_string(),
+        }),
+        [.., "tags", "list"] => Some(Route::Tags { name: name(n - 2)? }),
+        _ => None,
+    }
+}
+
+/// Error body in the distribution spec's format
+pub fn error_body(code: &str, message: &str) -> serde_json::Value {
+    serde_json::json!({
+        "errors": [{ "code": code, "message": message }]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(version: &str, package: &[u8]) -> PublishedVersion {
+        PublishedVersion {
+            name: "code-reviewer".to_string(),
+            version: version.to_string(),
+            checksum: package_checksum(package),
+            file_size: package.len() as u64,
+            content_type: "application/zip".to_string(),
+            definition: serde_json::json!({ "metadata": { "description": "Reviews code" } }),
+            file_path: format!("code-reviewer/{version}.zip"),
+            oci_manifest: None,
+            oci_config: None,
+        }
+    }
+
+    #[test]
+    fn test_route() {
+        assert_eq!(route("/v2/"), Some(Route::Base));
+        assert_eq!(
+            route("/v2/code-reviewer/manifests/1.0.0"),
+            Some(Route::Manifest {
+                name: "code-reviewer".to_string(),
+                reference: "1.0.0".to_string()
+            })
+        );
+        assert_eq!(
+            route("team/agent/blobs/uploads/"),
+            Some(Route::Uploads {
+                name: "team/agent".to_string()
+            })
+        );
+        assert_eq!(
+            route("/v2/agent/blobs/uploads/abc"),
+            Some(Route::Upload {
+                name: "agent".to_string(),
+                session: "abc".to_string()
+            })
+        );
+        assert_eq!(
+            route("/v2/agent/tags/list"),
+            Some(Route::Tags {
+                name: "agent".to_string()
+            })
+        );
+        assert_eq!(route("/v2/manifests/latest"), None);
+        assert_eq!(route("/v2/agent/other"), None);
+    }
+
+    #[test]
+    fn test_generated_artifact_is_stable_and_resolvable() {
+        let versions = vec![
+            version("1.0.0", b"PK\x03\x04one"),
+            version("1.1.0+build.5", b"PK\x03\x04two"),
+        ];
+
+        let first = Artifact::for_version(&versions[1]).unwrap();
+        let again = Artifact::for_version(&versions[1]).unwrap();
+        assert_eq!(first.manifest, again.manifest);
+        assert_eq!(first.layer_digest, versions[1].checksum);
+
+        let manifest = parse_pushed_manifest(&first.manifest).unwrap();
+        assert_eq!(manifest.format, PackageFormat::Zip);
+        assert_eq!(manifest.config.digest, first.config_digest);
+
+        let (latest, _) = find_version(&versions, "latest").unwrap();
+        assert_eq!(latest.version, "1.1.0+build.5");
+        let (tagged, _) = find_version(&versions, "1.1.0_build.5").unwrap();
+        assert_eq!(tagged.version, "1.1.0+build.5");
+        let (by_digest, _) = find_version(&versions, &first.manifest_digest).unwrap();
+        assert_eq!(by_digest.version, "1.1.0+build.5");
+        assert!(find_version(&versions, "2.0.0").is_none());
+
+        assert!(matches!(
+            find_blob(&versions, &versions[0].checksum),
+            Some(Blob::Layer(v)) if v.version == "1.0.0"
+        ));
+        assert!(matches!(
+            find_blob(&versions, &first.config_digest),
+            Some(Blob::Config(config)) if config == first.config
+        ));
+    }
+
+    #[test]
+    fn test_tags_page() {
+        let versions: Vec<_> = ["1.0.0", "1.1.0", "2.0.0"]
+            .iter()
+            .map(|v| version(v, v.as_bytes()))
+            .collect();
+
+        assert_eq!(
+            tags_page(&versions, Some(2), None),
+            (vec!["1.0.0".to_string(), "1.1.0".to_string()], true)
+        );
+        assert_eq!(
+            tags_page(&versions, Some(2), Some("1.1.0")),
+            (vec!["2.0.0".to_string()], false)
+        );
+    }
+
+    #[test]
+    fn test_pushed_manifest_must_describe_an_agent() {
+        let descriptor = |media_type: &str| Descriptor {
+            media_type: media_type.to_string(),
+            digest: digest(media_type.as_bytes()),
+            size: 1,
+            annotations: BTreeMap::new(),
+        };
+        let manifest = |config: &str, layers: &[&str]| {
+            serde_json::to_vec(&Manifest {
+                schema_version: 2,
+                media_type: Some(MANIFEST_MEDIA_TYPE.to_string()),
+                artifact_type: None,
+                config: descriptor(config),
+                layers: layers.iter().map(|layer| descriptor(layer)).collect(),
+                annotations: BTreeMap::new(),
+            })
+            .unwrap()
+        };
+
+        let pushed =
+            parse_pushed_manifest(&manifest(CONFIG_MEDIA_TYPE, &["application/gzip"])).unwrap();
+        assert_eq!(pushed.format, PackageFormat::TarGz);
+
+        assert!(parse_pushed_manifest(&manifest(
+            "application/vnd.oci.image.config.v1+json",
+            &["application/zip"]
+        ))
+        .is_err());
+        assert!(parse_pushed_manifest(&manifest(CONFIG_MEDIA_TYPE, &[])).is_err());
+        assert!(parse_pushed_manifest(&manifest(CONFIG_MEDIA_TYPE, &["text/plain"])).is_err());
+    }
+}
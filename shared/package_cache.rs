@@ -0,0 +1,302 @@
+//! Server-side cache of package archives
+//!
+//! When enabled, public packages are served by the download endpoint itself
+//! instead of through a signed storage URL. Cached packages skip both the
+//! storage round trip and URL signing, and the least recently downloaded ones
+//! are evicted first, so the cache ends up holding the hot agents.
+//!
+//! Configuration comes from the environment:
+//!
+//! - `PACKAGE_CACHE`: `off` (default), `disk` or `redis`
+//! - `PACKAGE_CACHE_MAX_ENTRY_BYTES`: largest package served through the
+//!   cache (default 4 MiB); bigger ones always get a signed URL
+//! - Disk: `PACKAGE_CACHE_DIR` (default `/tmp/carp-package-cache`) and
+//!   `PACKAGE_CACHE_MAX_BYTES` (default 512 MiB). Each function instance has
+//!   its own disk, so this suits long-lived deployments best.
+//! - Redis (REST API, e.g. Upstash): `PACKAGE_CACHE_REDIS_URL`,
+//!   `PACKAGE_CACHE_REDIS_TOKEN` and `PACKAGE_CACHE_TTL_SECS` (default one
+//!   day). Eviction is left to the server's `allkeys-lru` policy.
+
+use anyhow::{anyhow, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use bytes::Bytes;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+const DEFAULT_MAX_ENTRY_BYTES: u64 = 4 * 1024 * 1024;
+const DEFAULT_DISK_DIR: &str = "/tmp/carp-package-cache";
+const DEFAULT_DISK_MAX_BYTES: u64 = 512 * 1024 * 1024;
+const DEFAULT_REDIS_TTL_SECS: u64 = 24 * 3600;
+
+/// Least recently used packages in a local directory
+pub struct DiskCache {
+    dir: PathBuf,
+    max_bytes: u64,
+}
+
+/// Packages in Redis, reached over its REST API
+pub struct RedisCache {
+    client: reqwest::Client,
+    url: String,
+    token: String,
+    ttl_secs: u64,
+}
+
+/// The cache selected by `PACKAGE_CACHE`
+pub enum PackageCache {
+    Disk(DiskCache),
+    Redis(RedisCache),
+}
+
+impl PackageCache {
+    /// Build the configured cache, or `None` when caching is off
+    pub fn from_env() -> Result<Option<Self>> {
+        let backend = env::var("PACKAGE_CACHE").unwrap_or_default();
+
+        match backend.trim().to_ascii_lowercase().as_str() {
+            "" | "off" => Ok(None),
+            "disk" => Ok(Some(PackageCache::Disk(DiskCache::new(
+                env::var("PACKAGE_CACHE_DIR")
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|_| PathBuf::from(DEFAULT_DISK_DIR)),
+                env_u64("PACKAGE_CACHE_MAX_BYTES").unwrap_or(DEFAULT_DISK_MAX_BYTES),
+            )))),
+            "redis" => Ok(Some(PackageCache::Redis(RedisCache {
+                client: reqwest::Client::new(),
+                url: required_env("PACKAGE_CACHE_REDIS_URL")?
+                    .trim_end_matches('/')
+                    .to_string(),
+                token: required_env("PACKAGE_CACHE_REDIS_TOKEN")?,
+                ttl_secs: env_u64("PACKAGE_CACHE_TTL_SECS").unwrap_or(DEFAULT_REDIS_TTL_SECS),
+            }))),
+            other => Err(anyhow!(
+                "Unknown PACKAGE_CACHE '{other}'. Use off, disk or redis"
+            )),
+        }
+    }
+
+    /// Whether a package of `size` bytes is served through the cache
+    pub fn accepts(size: u64) -> bool {
+        size <= env_u64("PACKAGE_CACHE_MAX_ENTRY_BYTES").unwrap_or(DEFAULT_MAX_ENTRY_BYTES)
+    }
+
+    /// Cached package stored at `file_path`, if any
+    pub async fn get(&self, file_path: &str) -> Result<Option<Bytes>> {
+        let key = cache_key(file_path);
+        match self {
+            PackageCache::Disk(cache) => cache.get(&key),
+            PackageCache::Redis(cache) => cache.get(&key).await,
+        }
+    }
+
+    /// Store the package at `file_path`
+    pub async fn put(&self, file_path: &str, package: &Bytes) -> Result<()> {
+        let key = cache_key(file_path);
+        match self {
+            PackageCache::Disk(cache) => cache.put(&key, package),
+            PackageCache::Redis(cache) => cache.put(&key, package).await,
+        }
+    }
+}
+
+impl DiskCache {
+    pub fn new(dir: PathBuf, max_bytes: u64) -> Self {
+        Self { dir, max_bytes }
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Bytes>> {
+        let path = self.dir.join(key);
+        let package = match fs::read(&path) {
+            Ok(package) => package,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).context("Failed to read cached package"),
+        };
+
+        // The modification time doubles as the last use for eviction
+        if let Ok(file) = fs::File::options().write(true).open(&path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+
+        Ok(Some(Bytes::from(package)))
+    }
+
+    fn put(&self, key: &str, package: &Bytes) -> Result<()> {
+        if package.len() as u64 > self.max_bytes {
+            return Ok(());
+        }
+
+        fs::create_dir_all(&self.dir).context("Failed to create package cache directory")?;
+
+        // Write then rename so readers never see a partial package
+        let partial = self.dir.join(format!("{key}.partial"));
+        fs::write(&partial, package).context("Failed to write cached package")?;
+        fs::rename(&partial, self.dir.join(key)).context("Failed to store cached package")?;
+
+        self.evict()
+    }
+
+    /// Remove least recently used packages until the cache fits
+    fn evict(&self) -> Result<()> {
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(&self.dir).context("Failed to list package cache")? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if metadata.is_file() {
+                let used = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                entries.push((used, metadata.len(), entry.path()));
+            }
+        }
+
+        let mut total: u64 = entries.iter().map(|(_, size, _)| size).sum();
+        entries.sort_by_key(|(used, _, _)| *used);
+
+        for (_, size, path) in entries {
+            if total <= self.max_bytes {
+                break;
+            }
+            // Another instance may have evicted it already
+            if fs::remove_file(&path).is_ok() {
+                total -= size;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RedisResponse {
+    result: Option<String>,
+}
+
+impl RedisCache {
+    async fn get(&self, key: &str) -> Result<Option<Bytes>> {
+        let response = self
+            .client
+            .get(format!("{}/get/{key}", self.url))
+            .bearer_auth(&self.token)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            let error_text = response.text().await.unwrap_or_default();
+            return Err(anyhow!("Package cache lookup failed: {}", error_text));
+        }
+
+        let response: RedisResponse = response.json().await?;
+        response
+            .result
+            .map(|encoded| STANDARD.decode(encoded).map(Bytes::from))
+            .transpose()
+            .context("Cached package is not valid base64")
+    }
+
+    async fn put(&self, key: &str, package: &Bytes) -> Result<()> {
+        let response = self
+            .client
+            .post(format!("{}/set/{key}?EX={}", self.url, self.ttl_secs))
+            .bearer_auth(&self.token)
+            .body(STANDARD.encode(package))
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            let error_text = response.text().await.unwrap_or_default();
+            return Err(anyhow!("Failed to cache package: {}", error_text));
+        }
+
+        Ok(())
+    }
+}
+
+/// Storage paths contain slashes; keys are a fixed-length hex digest
+fn cache_key(file_path: &str) -> String {
+    let digest: String = Sha256::digest(file_path.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    format!("carp-package-{digest}")
+}
+
+fn env_u64(name: &str) -> Option<u64> {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+}
+
+fn required_env(name: &str) -> Result<String> {
+    env::var(name)
+        .ok()
+        .filter(|value| !value.is_empty())
+        .ok_or_else(|| anyhow!("PACKAGE_CACHE needs {name} to be set"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn temp_dir() -> PathBuf {
+        let dir = env::temp_dir().join(format!("carp-cache-test-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn test_disk_cache_round_trip() {
+        let dir = temp_dir();
+        let cache = PackageCache::Disk(DiskCache::new(dir.clone(), 1024));
+
+        assert!(cache.get("a/b/1.0.0.zip").await.unwrap().is_none());
+        cache
+            .put("a/b/1.0.0.zip", &Bytes::from_static(b"zip"))
+            .await
+            .unwrap();
+        assert_eq!(
+            cache.get("a/b/1.0.0.zip").await.unwrap().as_deref(),
+            Some(&b"zip"[..])
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_disk_cache_evicts_least_recently_used() {
+        let dir = temp_dir();
+        let cache = DiskCache::new(dir.clone(), 10);
+        let package = Bytes::from_static(b"12345");
+
+        cache.put("first", &package).unwrap();
+        cache.put("second", &package).unwrap();
+
+        // Age both, then use the first again before overflowing the cache
+        for (key, age) in [("first", 30), ("second", 20)] {
+            fs::File::options()
+                .write(true)
+                .open(dir.join(key))
+                .unwrap()
+                .set_modified(SystemTime::now() - Duration::from_secs(age))
+                .unwrap();
+        }
+        cache.get("first").unwrap();
+        cache.put("third", &package).unwrap();
+
+        assert!(cache.get("first").unwrap().is_some());
+        assert!(cache.get("second").unwrap().is_none());
+        assert!(cache.get("third").unwrap().is_some());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_cache_keys_are_path_safe() {
+        let key = cache_key("user/agent/1.0.0/package.zip");
+        assert!(key.starts_with("carp-package-"));
+        assert!(key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'));
+        assert_ne!(key, cache_key("user/agent/1.0.1/package.zip"));
+    }
+}
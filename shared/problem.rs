@@ -0,0 +1,187 @@
+//! Error responses as RFC 7807 problem details
+//!
+//! Every error the API answers with is `application/problem+json`:
+//!
+//! ```json
+//! {
+//!   "type": "https://carp.refcell.org/problems/not_found",
+//!   "title": "Not found",
+//!   "status": 404,
+//!   "detail": "Agent 'code-reviewer' not found",
+//!   "error": "not_found",
+//!   "message": "Agent 'code-reviewer' not found"
+//! }
+//! ```
+//!
+//! Each error code has a stable `type` URI, which clients should match on
+//! rather than the wording of `detail`. `error`, `message` and `details` are
+//! the fields errors had before problem details; they're kept as extension
+//! members so CLIs released before the switch still read them.
+//!
+//! Handlers keep building [`ApiError`]s, and each function's `main` passes its
+//! responses through [`respond`], which turns JSON errors into problems.
+
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use vercel_runtime::{Body, Error, Response};
+
+use crate::auth::ApiError;
+
+/// Media type of every error response
+pub const PROBLEM_CONTENT_TYPE: &str = "application/problem+json";
+
+/// Prefix of every problem `type`, followed by the error code
+pub const PROBLEM_TYPE_BASE: &str = "https://carp.refcell.org/problems/";
+
+/// An error response, as RFC 7807 problem details
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub type_uri: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+    /// The error code, as errors had it before problem details
+    pub error: String,
+    /// `detail`, as errors had it before problem details
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<serde_json::Value>,
+}
+
+impl Problem {
+    /// The problem an error answered with `status` describes
+    pub fn new(status: u16, error: ApiError) -> Self {
+        Self {
+            type_uri: problem_type(&error.error),
+            title: title(&error.error),
+            status,
+            detail: error.message.clone(),
+            error: error.error,
+            message: error.message,
+            details: error.details,
+        }
+    }
+}
+
+/// The `type` URI of an error code
+pub fn problem_type(code: &str) -> String {
+    format!("{PROBLEM_TYPE_BASE}{code}")
+}
+
+/// Answer with the responses of `handler`, errors as problem details
+///
+/// ```rust,ignore
+/// run(|req| respond(scope(req, handler))).await
+/// ```
+pub async fn respond<F>(response: F) -> Result<Response<Body>, Error>
+where
+    F: Future<Output = Result<Response<Body>, Error>>,
+{
+    response.await.map(into_problem)
+}
+
+/// Rewrite a JSON [`ApiError`] response as problem details
+///
+/// Successful responses and error bodies of any other shape, such as the
+/// OCI API's, are passed through unchanged. Headers like `Retry-After` and
+/// `Allow` are kept.
+pub fn into_problem(response: Response<Body>) -> Response<Body> {
+    let status = response.status().as_u16();
+    let is_json = response
+        .headers()
+        .get("content-type")
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    if status < 400 || !is_json {
+        return response;
+    }
+
+    let error = match response.body() {
+        Body::Text(text) => serde_json::from_str::<ApiError>(text).ok(),
+        Body::Binary(bytes) => serde_json::from_slice::<ApiError>(bytes).ok(),
+        Body::Empty => None,
+    };
+    let Some(body) =
+        error.and_then(|error| serde_json::to_string(&Problem::new(status, error)).ok())
+    else {
+        return response;
+    };
+
+    let (mut parts, _) = response.into_parts();
+    parts.headers.insert(
+        "content-type",
+        PROBLEM_CONTENT_TYPE.parse().expect("valid header value"),
+    );
+    Response::from_parts(parts, body.into())
+}
+
+/// "validation_failed" reads "Validation failed"
+fn title(code: &str) -> String {
+    let words = code.replace(['_', '-'], " ");
+    let mut chars = words.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => "Error".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_response(status: u16, body: &str) -> Response<Body> {
+        Response::builder()
+            .status(status)
+            .header("content-type", "application/json")
+            .header("retry-after", "300")
+            .body(body.to_string().into())
+            .unwrap()
+    }
+
+    fn body(response: &Response<Body>) -> serde_json::Value {
+        match response.body() {
+            Body::Text(text) => serde_json::from_str(text).unwrap(),
+            Body::Binary(bytes) => serde_json::from_slice(bytes).unwrap(),
+            Body::Empty => serde_json::Value::Null,
+        }
+    }
+
+    #[test]
+    fn test_into_problem() {
+        let response = into_problem(error_response(
+            422,
+            r#"{"error": "validation_failed", "message": "name: too long", "details": {"fields": []}}"#,
+        ));
+        assert_eq!(response.status(), 422);
+        assert_eq!(response.headers()["content-type"], PROBLEM_CONTENT_TYPE);
+        assert_eq!(response.headers()["retry-after"], "300");
+
+        let problem: Problem = serde_json::from_value(body(&response)).unwrap();
+        assert_eq!(
+            problem,
+            Problem {
+                type_uri: "https://carp.refcell.org/problems/validation_failed".to_string(),
+                title: "Validation failed".to_string(),
+                status: 422,
+                detail: "name: too long".to_string(),
+                error: "validation_failed".to_string(),
+                message: "name: too long".to_string(),
+                details: Some(serde_json::json!({ "fields": [] })),
+            }
+        );
+    }
+
+    #[test]
+    fn test_other_responses_pass_through() {
+        let ok = into_problem(error_response(200, r#"{"error": "x", "message": "y"}"#));
+        assert_eq!(ok.headers()["content-type"], "application/json");
+
+        let oci = into_problem(error_response(
+            404,
+            r#"{"errors": [{"code": "MANIFEST_UNKNOWN", "message": "unknown"}]}"#,
+        ));
+        assert_eq!(oci.headers()["content-type"], "application/json");
+        assert_eq!(body(&oci)["errors"][0]["code"], "MANIFEST_UNKNOWN");
+    }
+}
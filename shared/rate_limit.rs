@@ -0,0 +1,320 @@
+//! Per-caller request limits
+//!
+//! Anonymous callers are limited per IP address. Callers with an API key are
+//! limited per user at a higher rate, and members of an organization on a paid
+//! plan get that plan's limit. Limits apply per endpoint over a fixed window
+//! and are counted in the `rate_limits` table by the `hit_rate_limit`
+//! function, which also looks up the caller's plan.
+//!
+//! Every limit is requests per minute and can be overridden from the
+//! environment: `RATE_LIMIT_RPM` (anonymous), `RATE_LIMIT_AUTHENTICATED_RPM`,
+//! `RATE_LIMIT_TEAM_RPM` and `RATE_LIMIT_ENTERPRISE_RPM`. Tenants are counted
+//! apart and can set their own limits; see [`crate::tenant`].
+
+use chrono::{DateTime, Duration, Utc};
+use reqwest::header::{HeaderName, HeaderValue, RETRY_AFTER};
+use serde::Deserialize;
+use serde_json::json;
+use std::env;
+use vercel_runtime::{Body, Request, Response};
+
+use crate::auth::{ApiError, AuthenticatedUser};
+use crate::tenant;
+
+/// Length of a rate limit window
+pub const RATE_LIMIT_WINDOW_SECS: i64 = 60;
+
+/// Who a limit applies to, from least to most generous
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitTier {
+    /// No API key; limited per IP address
+    Anonymous,
+    /// Any valid API key or session
+    Authenticated,
+    /// Member of an organization on the team plan
+    Team,
+    /// Member of an organization on the enterprise plan
+    Enterprise,
+}
+
+impl RateLimitTier {
+    /// Tier for a caller and the best plan of their organizations
+    fn for_caller(user: Option<&AuthenticatedUser>, plan: Option<&str>) -> Self {
+        match (user, plan) {
+            (None, _) => RateLimitTier::Anonymous,
+            (Some(_), Some("enterprise")) => RateLimitTier::Enterprise,
+            (Some(_), Some("team")) => RateLimitTier::Team,
+            (Some(_), _) => RateLimitTier::Authenticated,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RateLimitTier::Anonymous => "anonymous",
+            RateLimitTier::Authenticated => "authenticated",
+            RateLimitTier::Team => "team",
+            RateLimitTier::Enterprise => "enterprise",
+        }
+    }
+
+    /// Requests allowed per window, in the current tenant
+    pub fn limit(self) -> u32 {
+        if let Some(limit) = crate::tenant::current()
+            .rate_limit(self)
+            .filter(|&limit| limit > 0)
+        {
+            return limit;
+        }
+
+        let (var, default) = match self {
+            RateLimitTier::Anonymous => ("RATE_LIMIT_RPM", 60),
+            RateLimitTier::Authenticated => ("RATE_LIMIT_AUTHENTICATED_RPM", 600),
+            RateLimitTier::Team => ("RATE_LIMIT_TEAM_RPM", 3000),
+            RateLimitTier::Enterprise => ("RATE_LIMIT_ENTERPRISE_RPM", 10000),
+        };
+
+        env::var(var)
+            .ok()
+            .and_then(|limit| limit.trim().parse().ok())
+            .filter(|&limit| limit > 0)
+            .unwrap_or(default)
+    }
+}
+
+/// The caller's standing in the current window
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateLimit {
+    pub tier: RateLimitTier,
+    pub limit: u32,
+    /// Requests made in this window, including the current one
+    pub used: u32,
+    /// When the window ends and the count starts over
+    pub reset_at: DateTime<Utc>,
+}
+
+impl RateLimit {
+    pub fn remaining(&self) -> u32 {
+        self.limit.saturating_sub(self.used)
+    }
+
+    pub fn exceeded(&self) -> bool {
+        self.used > self.limit
+    }
+
+    /// Add the `X-RateLimit-*` headers to a response
+    pub fn apply_headers(&self, response: &mut Response<Body>) {
+        let headers = response.headers_mut();
+        let mut insert = |name: &'static str, value: String| {
+            if let Ok(value) = HeaderValue::from_str(&value) {
+                headers.insert(HeaderName::from_static(name), value);
+            }
+        };
+
+        insert("x-ratelimit-limit", self.limit.to_string());
+        insert("x-ratelimit-remaining", self.remaining().to_string());
+        insert("x-ratelimit-reset", self.reset_at.timestamp().to_string());
+        insert("x-ratelimit-tier", self.tier.as_str().to_string());
+    }
+
+    /// 429 response for a caller over their limit
+    pub fn exceeded_response(&self, now: DateTime<Utc>) -> Response<Body> {
+        let message = match self.tier {
+            RateLimitTier::Anonymous => format!(
+                "Anonymous requests are limited to {} per minute per IP address. \
+                 Authenticate with an API key for a higher limit.",
+                self.limit
+            ),
+            tier => format!(
+                "Requests are limited to {} per minute on the {} tier",
+                self.limit,
+                tier.as_str()
+            ),
+        };
+
+        let error = ApiError {
+            error: "rate_limited".to_string(),
+            message,
+            details: Some(json!({
+                "limit": self.limit,
+                "tier": self.tier.as_str(),
+            })),
+        };
+
+        let mut response = Response::builder()
+            .status(429)
+            .header("content-type", "application/json")
+            .body(serde_json::to_string(&error).unwrap_or_default().into())
+            .unwrap_or_else(|_| {
+                Response::builder()
+                    .status(429)
+                    .body("Too many requests".into())
+                    .unwrap()
+            });
+
+        self.apply_headers(&mut response);
+        let wait = (self.reset_at - now).num_seconds().max(1);
+        response
+            .headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(wait));
+        response
+    }
+}
+
+/// The caller's IP address as reported by the proxy in front of the function
+pub fn client_ip(req: &Request) -> String {
+    req.headers()
+        .get("x-forwarded-for")
+        .or_else(|| req.headers().get("x-real-ip"))
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(',').next())
+        .map(str::trim)
+        .filter(|ip| !ip.is_empty())
+        .unwrap_or("127.0.0.1")
+        .to_string()
+}
+
+#[derive(Debug, Deserialize)]
+struct Hit {
+    request_count: u32,
+    window_start: DateTime<Utc>,
+    plan: Option<String>,
+}
+
+/// Count a request to `endpoint` against the caller's limit.
+///
+/// Returns `None` when the limit can't be checked, e.g. without a database;
+/// requests are let through rather than failing because the limiter is down.
+pub async fn check_rate_limit(
+    req: &Request,
+    user: Option<&AuthenticatedUser>,
+    endpoint: &str,
+) -> Option<RateLimit> {
+    let supabase_url = env::var("SUPABASE_URL").unwrap_or_default();
+    let supabase_key = env::var("SUPABASE_SERVICE_ROLE_KEY").unwrap_or_default();
+    if supabase_url.is_empty() || supabase_key.is_empty() {
+        return None;
+    }
+
+    let identifier = crate::tenant::current().rate_limit_key(&match user {
+        Some(user) => format!("user:{}", user.user_id),
+        None => format!("ip:{}", client_ip(req)),
+    });
+
+    let result = async {
+        let response = tenant::http_client()
+            .post(format!("{supabase_url}/rest/v1/rpc/hit_rate_limit"))
+            .header("apikey", &supabase_key)
+            .header("Authorization", format!("Bearer {supabase_key}"))
+            .json(&json!({
+                "p_identifier": identifier,
+                "p_endpoint": endpoint,
+                "p_window_seconds": RATE_LIMIT_WINDOW_SECS,
+                "p_user_id": user.map(|user| user.user_id),
+            }))
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!("status {status}: {body}"));
+        }
+
+        let hits: Vec<Hit> = response.json().await.map_err(|e| e.to_string())?;
+        hits.into_iter()
+            .next()
+            .ok_or_else(|| "no window returned".to_string())
+    }
+    .await;
+
+    match result {
+        Ok(hit) => {
+            let tier = RateLimitTier::for_caller(user, hit.plan.as_deref());
+            Some(RateLimit {
+                tier,
+                limit: tier.limit(),
+                used: hit.request_count,
+                reset_at: hit.window_start + Duration::seconds(RATE_LIMIT_WINDOW_SECS),
+            })
+        }
+        Err(e) => {
+            eprintln!("DEBUG: Rate limit check failed: {e}");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{AuthMethod, UserMetadata};
+    use uuid::Uuid;
+
+    #[test]
+    fn test_tier_depends_on_auth_and_plan() {
+        let user = AuthenticatedUser {
+            user_id: Uuid::new_v4(),
+            auth_method: AuthMethod::ApiKey {
+                key_id: Uuid::new_v4(),
+            },
+            scopes: vec!["read".to_string()],
+            metadata: UserMetadata {
+                email: None,
+                github_username: None,
+                created_at: None,
+            },
+        };
+
+        assert_eq!(
+            RateLimitTier::for_caller(None, Some("enterprise")),
+            RateLimitTier::Anonymous
+        );
+        assert_eq!(
+            RateLimitTier::for_caller(Some(&user), None),
+            RateLimitTier::Authenticated
+        );
+        assert_eq!(
+            RateLimitTier::for_caller(Some(&user), Some("free")),
+            RateLimitTier::Authenticated
+        );
+        assert_eq!(
+            RateLimitTier::for_caller(Some(&user), Some("team")),
+            RateLimitTier::Team
+        );
+        assert!(RateLimitTier::Anonymous.limit() < RateLimitTier::Authenticated.limit());
+    }
+
+    #[test]
+    fn test_exceeded_response_headers() {
+        let now = Utc::now();
+        let limit = RateLimit {
+            tier: RateLimitTier::Anonymous,
+            limit: 60,
+            used: 61,
+            reset_at: now + Duration::seconds(42),
+        };
+        assert!(limit.exceeded());
+        assert_eq!(limit.remaining(), 0);
+
+        let response = limit.exceeded_response(now);
+        assert_eq!(response.status(), 429);
+        let header = |name: &str| response.headers().get(name).unwrap().to_str().unwrap();
+        assert_eq!(header("retry-after"), "42");
+        assert_eq!(header("x-ratelimit-limit"), "60");
+        assert_eq!(header("x-ratelimit-remaining"), "0");
+        assert_eq!(header("x-ratelimit-tier"), "anonymous");
+    }
+
+    #[test]
+    fn test_client_ip_uses_first_forwarded_address() {
+        let mut req = Request::new(Body::Empty);
+        assert_eq!(client_ip(&req), "127.0.0.1");
+
+        req.headers_mut().insert(
+            "x-forwarded-for",
+            HeaderValue::from_static("203.0.113.7, 10.0.0.1"),
+        );
+        assert_eq!(client_ip(&req), "203.0.113.7");
+    }
+}
@@ -0,0 +1,70 @@
+//! Downloadable versions described as OCI artifacts
+
+use serde::Deserialize;
+
+use super::{database_client, fetch};
+use crate::oci::PublishedVersion;
+use crate::AgentVisibility;
+
+#[derive(Debug, Deserialize)]
+struct DbAgent {
+    id: String,
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct DbVersion {
+    version: String,
+    checksum: Option<String>,
+    package_size: Option<u64>,
+    content_type: Option<String>,
+    definition: serde_json::Value,
+    file_path: String,
+    oci_manifest: Option<String>,
+    oci_config: Option<String>,
+}
+
+/// Every downloadable version of the agent called `name`, oldest first, or
+/// `None` if `visibility` doesn't allow the agent
+pub async fn published_versions(
+    name: &str,
+    visibility: &AgentVisibility,
+) -> Result<Option<Vec<PublishedVersion>>, String> {
+    let client = database_client(visibility)?;
+
+    let query = client.from("agents").select("id,name").eq("name", name);
+    let agents: Vec<DbAgent> = fetch(visibility.apply(query)).await?;
+    let Some(agent) = agents.into_iter().next() else {
+        return Ok(None);
+    };
+
+    // Unyanked versions with a stored package, as for agent info
+    let query = client
+        .from("agent_versions")
+        .select("version,checksum,package_size,content_type,definition,file_path,oci_manifest,oci_config")
+        .eq("agent_id", &agent.id)
+        .eq("yanked", "false")
+        .not("is", "file_path", "null")
+        .order("created_at.asc");
+    let versions: Vec<DbVersion> = fetch(query).await?;
+
+    Ok(Some(
+        versions
+            .into_iter()
+            .map(|version| PublishedVersion {
+                name: agent.name.clone(),
+                version: version.version,
+                checksum: version.checksum.unwrap_or_default(),
+                file_size: version.package_size.unwrap_or(0),
+                // Packages stored before the format was recorded are all zip
+                content_type: version
+                    .content_type
+                    .unwrap_or_else(|| "application/zip".to_string()),
+                definition: version.definition,
+                file_path: version.file_path,
+                oci_manifest: version.oci_manifest,
+                oci_config: version.oci_config,
+            })
+            .collect(),
+    ))
+}
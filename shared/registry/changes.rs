@@ -0,0 +1,247 @@
+//! The change feed: what happened to public agents since a cursor
+//!
+//! Every change that a copy of the registry's listing would notice is logged
+//! in `agent_changes` with an increasing sequence number. A page of the feed
+//! holds the latest change to each agent after the cursor, oldest first, and
+//! the cursor to ask from next, so a copy that applies pages in order ends
+//! up with the registry's listing however rarely it syncs.
+
+use serde::{Deserialize, Serialize};
+
+use super::search::{Agent, DbAgent, AGENT_COLUMNS};
+use super::{database_client, fetch};
+use crate::AgentVisibility;
+
+/// Changes in a page when the caller doesn't ask for a number
+pub const DEFAULT_LIMIT: usize = 100;
+
+/// Most changes in one page
+pub const MAX_LIMIT: usize = 1000;
+
+/// What happened to an agent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    /// Published, or made public
+    Created,
+    /// New version or metadata
+    Updated,
+    /// A version was yanked
+    Yanked,
+    /// Deleted, or made private
+    Removed,
+}
+
+impl ChangeKind {
+    pub fn parse(kind: &str) -> Option<Self> {
+        match kind {
+            "created" => Some(ChangeKind::Created),
+            "updated" => Some(ChangeKind::Updated),
+            "yanked" => Some(ChangeKind::Yanked),
+            "removed" => Some(ChangeKind::Removed),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ChangeKind::Created => "created",
+            ChangeKind::Updated => "updated",
+            ChangeKind::Yanked => "yanked",
+            ChangeKind::Removed => "removed",
+        }
+    }
+}
+
+/// The latest change to one agent, `A` being how the registry shapes agents
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentChange<A = Agent> {
+    pub kind: ChangeKind,
+    pub name: String,
+    /// The agent as it is now; `None` once it's removed
+    pub agent: Option<A>,
+}
+
+/// One page of the feed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChangesPage<A = Agent> {
+    /// Oldest first
+    pub changes: Vec<AgentChange<A>>,
+    /// Where the next page starts
+    pub cursor: String,
+    /// Whether changes after `cursor` were left for the next page
+    pub has_more: bool,
+}
+
+/// The sequence number in a cursor, `None` for one this registry didn't hand out
+pub fn parse_cursor(cursor: &str) -> Option<i64> {
+    cursor.parse().ok().filter(|seq| *seq >= 0)
+}
+
+/// A page with `logged` latest changes, as `(seq, name, kind)` oldest first,
+/// of which at most `limit` fit, given the current state of the agents they
+/// name. An agent no longer found is reported removed.
+pub fn page<A>(
+    since: i64,
+    limit: usize,
+    logged: Vec<(i64, String, ChangeKind)>,
+    mut current: impl FnMut(&str) -> Option<A>,
+) -> ChangesPage<A> {
+    let has_more = logged.len() > limit;
+    let mut cursor = since;
+    let changes = logged
+        .into_iter()
+        .take(limit)
+        .map(|(seq, name, kind)| {
+            cursor = seq;
+            let agent = match kind {
+                ChangeKind::Removed => None,
+                _ => current(&name),
+            };
+            AgentChange {
+                kind: if agent.is_some() {
+                    kind
+                } else {
+                    ChangeKind::Removed
+                },
+                name,
+                agent,
+            }
+        })
+        .collect();
+
+    ChangesPage {
+        changes,
+        cursor: cursor.to_string(),
+        has_more,
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct LoggedChange {
+    seq: i64,
+    name: String,
+    kind: String,
+}
+
+/// Up to `limit` of the latest changes to public agents after `since`
+pub async fn changes(since: i64, limit: usize) -> Result<ChangesPage, String> {
+    let visibility = AgentVisibility::Public;
+    let client = database_client(&visibility)?;
+
+    // One extra row says whether there's another page
+    let logged: Vec<LoggedChange> = fetch(
+        client
+            .rpc("latest_agent_changes", "{}")
+            .select("seq,name,kind")
+            .gt("seq", since.to_string())
+            .order("seq.asc")
+            .limit(limit + 1),
+    )
+    .await?;
+    let logged: Vec<(i64, String, ChangeKind)> = logged
+        .into_iter()
+        .map(|change| {
+            let kind = ChangeKind::parse(&change.kind)
+                .ok_or_else(|| format!("Unknown change kind {:?}", change.kind))?;
+            Ok((change.seq, change.name, kind))
+        })
+        .collect::<Result<_, String>>()?;
+
+    let names: Vec<&str> = logged
+        .iter()
+        .take(limit)
+        .filter(|(_, _, kind)| *kind != ChangeKind::Removed)
+        .map(|(_, name, _)| name.as_str())
+        .collect();
+    let agents: Vec<DbAgent> = if names.is_empty() {
+        Vec::new()
+    } else {
+        fetch(
+            visibility.apply(
+                client
+                    .from("agents")
+                    .select(AGENT_COLUMNS)
+                    .in_("name", names),
+            ),
+        )
+        .await?
+    };
+    let mut agents: std::collections::HashMap<String, Agent> = agents
+        .into_iter()
+        .map(|agent| (agent.name.clone(), Agent::from(agent)))
+        .collect();
+
+    Ok(page(since, limit, logged, |name| agents.remove(name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn agent(name: &str) -> Agent {
+        Agent {
+            name: name.to_string(),
+            version: "1.0.0".to_string(),
+            description: String::new(),
+            author: "octocat".to_string(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            download_count: 0,
+            star_count: 0,
+            rating_count: 0,
+            rating_average: None,
+            tags: Vec::new(),
+            readme: None,
+            homepage: None,
+            repository: None,
+            license: None,
+            signature_bundle: None,
+            is_public: true,
+            is_template: false,
+            verified_namespace: None,
+            verified: false,
+        }
+    }
+
+    #[test]
+    fn test_pages_carry_the_cursor_and_current_state() {
+        let logged = vec![
+            (3, "code-reviewer".to_string(), ChangeKind::Updated),
+            (5, "meeting-notes".to_string(), ChangeKind::Removed),
+            (6, "made-private".to_string(), ChangeKind::Yanked),
+            (9, "test-writer".to_string(), ChangeKind::Created),
+        ];
+        let page = page(2, 3, logged, |name| {
+            (name != "made-private").then(|| agent(name))
+        });
+
+        assert!(page.has_more);
+        assert_eq!(page.cursor, "6");
+        let changes: Vec<_> = page
+            .changes
+            .iter()
+            .map(|change| (change.name.as_str(), change.kind, change.agent.is_some()))
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                ("code-reviewer", ChangeKind::Updated, true),
+                ("meeting-notes", ChangeKind::Removed, false),
+                // Gone since the change was logged
+                ("made-private", ChangeKind::Removed, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_an_empty_page_keeps_the_cursor() {
+        let page = page(42, 100, Vec::new(), |_| None::<Agent>);
+        assert!(page.changes.is_empty() && !page.has_more);
+        assert_eq!(page.cursor, "42");
+        assert_eq!(parse_cursor("42"), Some(42));
+        assert_eq!(parse_cursor("-1"), None);
+        assert_eq!(parse_cursor("2025-01-01"), None);
+    }
+}
@@ -0,0 +1,608 @@
+//! Finding the package behind `name@version`, handing it out and counting
+//! downloads
+//!
+//! [`download_info`] is the whole of a download-info request; the REST
+//! function and the gRPC service only parse their requests into a
+//! [`DownloadRequest`] and shape the [`AgentDownload`] it returns.
+
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::env;
+use std::fmt;
+use uuid::Uuid;
+use vercel_runtime::Request;
+
+use crate::package_cache::PackageCache;
+use crate::tenant;
+use crate::url_signer::{url_ttl, DownloadSigner, UrlSigner};
+use crate::{client_ip, AuthenticatedUser, PackageFormat};
+
+/// Window in which repeat downloads by the same user or IP count once
+const DEFAULT_DEDUP_WINDOW_SECS: i32 = 3600;
+
+/// Largest package returned inline. Base64 adds a third, and the result has
+/// to fit in a single serverless response.
+pub const INLINE_DOWNLOAD_LIMIT: u64 = 3 * 1024 * 1024;
+
+/// A downloadable version and where its package is stored
+#[derive(Debug, Clone)]
+pub struct DownloadTarget {
+    pub agent_id: String,
+    pub name: String,
+    pub author: String,
+    pub version: String,
+    /// Path in the `agent-packages` bucket
+    pub file_path: String,
+    pub checksum: String,
+    pub file_size: u64,
+    pub content_type: String,
+    pub definition: serde_json::Value,
+}
+
+/// Raised by [`find_download`] when no visible version matches, as opposed to
+/// the lookup itself failing
+#[derive(Debug)]
+pub struct DownloadNotFound;
+
+impl fmt::Display for DownloadNotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Agent not found or no valid response from database")
+    }
+}
+
+impl std::error::Error for DownloadNotFound {}
+
+/// The version of `name` that `version` names, `latest` for the newest
+///
+/// Only public agents and the requester's own private ones are found, so no
+/// further access check is needed.
+pub async fn find_download(
+    client: &reqwest::Client,
+    supabase_url: &str,
+    supabase_key: &str,
+    name: &str,
+    version: &str,
+    requester_id: Option<Uuid>,
+) -> Result<DownloadTarget> {
+    let url = format!("{}/rest/v1/rpc/get_agent_download_info", supabase_url);
+
+    let payload = json!({
+        "p_agent_name": name,
+        "p_version_text": if version == "latest" { "" } else { version },
+        "p_requester_id": requester_id
+    });
+
+    let response = client
+        .post(&url)
+        .header("apikey", supabase_key)
+        .header("Authorization", format!("Bearer {}", supabase_key))
+        .header("Content-Type", "application/json")
+        .json(&payload)
+        .send()
+        .await?;
+
+    if !response.status().is_success() {
+        let error_text = response.text().await.unwrap_or_default();
+        return Err(anyhow!("Database query failed: {}", error_text));
+    }
+
+    let result: serde_json::Value = response.json().await?;
+
+    // Parse the result from the database function
+    if let Some(data) = result.as_array().and_then(|arr| arr.first()) {
+        Ok(DownloadTarget {
+            agent_id: data
+                .get("agent_id")
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| anyhow!("Missing agent_id in database response"))?
+                .to_string(),
+            name: data
+                .get("agent_name")
+                .and_then(|v| v.as_str())
+                .unwrap_or(name)
+                .to_string(),
+            author: data
+                .get("author")
+                .and_then(|v| v.as_str())
+                .unwrap_or("Unknown")
+                .to_string(),
+            version: data
+                .get("version")
+                .and_then(|v| v.as_str())
+                .unwrap_or(version)
+                .to_string(),
+            file_path: data
+                .get("file_path")
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| anyhow!("Missing file_path in database response"))?
+                .to_string(),
+            checksum: data
+                .get("checksum")
+                .and_then(|v| v.as_str())
+                .unwrap_or("")
+                .to_string(),
+            file_size: data.get("file_size").and_then(|v| v.as_u64()).unwrap_or(0),
+            // Packages stored before the format was recorded are all zip
+            content_type: data
+                .get("content_type")
+                .and_then(|v| v.as_str())
+                .and_then(PackageFormat::from_content_type)
+                .unwrap_or(PackageFormat::Zip)
+                .content_type()
+                .to_string(),
+            definition: data
+                .get("definition")
+                .cloned()
+                .unwrap_or(serde_json::json!({})),
+        })
+    } else {
+        Err(DownloadNotFound.into())
+    }
+}
+
+/// Who fetched a package, for download counts
+#[derive(Debug, Clone, Default)]
+pub struct Downloader<'a> {
+    pub user_id: Option<Uuid>,
+    pub user_agent: &'a str,
+    pub ip_addr: Option<String>,
+}
+
+impl<'a> Downloader<'a> {
+    /// The caller of a REST request
+    pub fn from_request(req: &'a Request, user: Option<&AuthenticatedUser>) -> Self {
+        Downloader {
+            user_id: user.map(|user| user.user_id),
+            user_agent: req
+                .headers()
+                .get("user-agent")
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or(""),
+            ip_addr: Some(client_ip(req)),
+        }
+    }
+}
+
+/// Configured dedup window; `DOWNLOAD_DEDUP_WINDOW_SECS=0` counts every download
+fn dedup_window_secs() -> i32 {
+    env::var("DOWNLOAD_DEDUP_WINDOW_SECS")
+        .ok()
+        .and_then(|secs| secs.trim().parse().ok())
+        .unwrap_or(DEFAULT_DEDUP_WINDOW_SECS)
+}
+
+/// Count a download. Failures are logged rather than returned, since a
+/// missed count shouldn't fail the download.
+pub async fn record_download(
+    client: &reqwest::Client,
+    supabase_url: &str,
+    supabase_key: &str,
+    name: &str,
+    version: &str,
+    downloader: &Downloader<'_>,
+) -> Result<()> {
+    let url = format!("{}/rest/v1/rpc/record_download", supabase_url);
+
+    let payload = json!({
+        "agent_name": name,
+        "version_text": if version == "latest" { "" } else { version },
+        "user_agent_text": downloader.user_agent,
+        "ip_addr": downloader.ip_addr,
+        "downloader_id": downloader.user_id,
+        "dedup_window_seconds": dedup_window_secs()
+    });
+
+    let response = client
+        .post(&url)
+        .header("apikey", supabase_key)
+        .header("Authorization", format!("Bearer {}", supabase_key))
+        .header("Content-Type", "application/json")
+        .json(&payload)
+        .send()
+        .await?;
+
+    if !response.status().is_success() {
+        let error_text = response.text().await.unwrap_or_default();
+        // Don't fail the entire request if download tracking fails
+        eprintln!("Warning: Failed to record download: {}", error_text);
+    }
+
+    Ok(())
+}
+
+/// Raised by [`download_info`] when an inline download is asked for a
+/// package over [`INLINE_DOWNLOAD_LIMIT`]
+#[derive(Debug)]
+pub struct InlineTooLarge(pub u64);
+
+impl fmt::Display for InlineTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Package is {} bytes; inline downloads are limited to {} bytes",
+            self.0, INLINE_DOWNLOAD_LIMIT
+        )
+    }
+}
+
+impl std::error::Error for InlineTooLarge {}
+
+/// A download-info request, whichever transport it came over
+#[derive(Debug, Clone, Default)]
+pub struct DownloadRequest<'a> {
+    pub name: &'a str,
+    /// A version, or `latest` for the newest
+    pub version: &'a str,
+    /// Return the package itself instead of a URL to fetch it from
+    pub inline: bool,
+    /// Base URL of the REST package proxy, for callers that can be pointed
+    /// at it; without one, public packages get signed URLs like the rest
+    pub proxy_base: Option<String>,
+    /// Looked up ahead of a pick the user may not make, so not counted; the
+    /// client asks again without it once they do
+    pub prefetch: bool,
+    pub downloader: Downloader<'a>,
+}
+
+/// Agent download information
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AgentDownload {
+    pub agent_id: String,
+    pub name: String,
+    pub author: String,
+    pub version: String,
+    pub download_url: String,
+    pub file_size: u64,
+    pub checksum: String,
+    pub content_type: String,
+    pub definition: serde_json::Value,
+    /// Base64 package bytes, for clients that can't reach the storage host
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+}
+
+/// Find the version asked for, decide how its package is handed out and
+/// count the download, unless it's a prefetch
+///
+/// Fails with [`DownloadNotFound`] when no visible version matches and
+/// [`InlineTooLarge`] when the package can't be returned inline.
+pub async fn download_info(
+    client: &reqwest::Client,
+    supabase_url: &str,
+    supabase_key: &str,
+    request: &DownloadRequest<'_>,
+) -> Result<AgentDownload> {
+    let DownloadRequest {
+        name,
+        version,
+        inline,
+        ref proxy_base,
+        prefetch,
+        ref downloader,
+    } = *request;
+
+    let agent_info = find_download(
+        client,
+        supabase_url,
+        supabase_key,
+        name,
+        version,
+        downloader.user_id,
+    )
+    .await?;
+
+    let cache = PackageCache::from_env()?;
+
+    // Public packages the cache takes are served by the proxy, so there's no
+    // URL to sign
+    let proxied = match proxy_base.as_deref() {
+        Some(proxy_base)
+            if !inline
+                && cache.is_some()
+                && PackageCache::accepts(agent_info.file_size)
+                && is_public(client, supabase_url, supabase_key, name, downloader.user_id)
+                    .await =>
+        {
+            Some(package_proxy_url(
+                proxy_base,
+                &agent_info.name,
+                &agent_info.version,
+            ))
+        }
+        _ => None,
+    };
+
+    let (download_url, content) = if inline {
+        if agent_info.file_size > INLINE_DOWNLOAD_LIMIT {
+            return Err(InlineTooLarge(agent_info.file_size).into());
+        }
+        let package = load_package(
+            client,
+            supabase_url,
+            supabase_key,
+            &agent_info.file_path,
+            cache.as_ref(),
+        )
+        .await?;
+        if package.len() as u64 > INLINE_DOWNLOAD_LIMIT {
+            // The recorded size can be stale; never embed more than the limit
+            return Err(InlineTooLarge(package.len() as u64).into());
+        }
+        (String::new(), Some(STANDARD.encode(package)))
+    } else if let Some(download_url) = proxied {
+        (download_url, None)
+    } else {
+        // Sign a URL with Supabase storage or the configured CDN
+        let signer = DownloadSigner::from_env(client.clone(), supabase_url, supabase_key)?;
+        let download_url = signer.sign(&agent_info.file_path, url_ttl()).await?;
+        (download_url, None)
+    };
+
+    if !prefetch {
+        record_download(
+            client,
+            supabase_url,
+            supabase_key,
+            name,
+            version,
+            downloader,
+        )
+        .await?;
+    }
+
+    Ok(AgentDownload {
+        agent_id: agent_info.agent_id,
+        name: agent_info.name,
+        author: agent_info.author,
+        version: agent_info.version,
+        download_url,
+        file_size: agent_info.file_size,
+        checksum: agent_info.checksum,
+        content_type: agent_info.content_type,
+        definition: agent_info.definition,
+        content,
+    })
+}
+
+/// Whether anonymous callers can download the agent and so its package can
+/// be proxied without checking who fetches it
+async fn is_public(
+    client: &reqwest::Client,
+    supabase_url: &str,
+    supabase_key: &str,
+    name: &str,
+    requester_id: Option<Uuid>,
+) -> bool {
+    // Anonymous lookups only ever find public agents
+    if requester_id.is_none() {
+        return true;
+    }
+
+    let url = format!(
+        "{}/rest/v1/agents?name=eq.{}&tenant_id=eq.{}&select=is_public&limit=1",
+        supabase_url,
+        urlencoding::encode(name),
+        tenant::current().id
+    );
+    let response = client
+        .get(&url)
+        .header("apikey", supabase_key)
+        .header("Authorization", format!("Bearer {}", supabase_key))
+        .send()
+        .await;
+
+    let rows: Vec<serde_json::Value> = match response {
+        Ok(response) if response.status().is_success() => response.json().await.unwrap_or_default(),
+        _ => return false,
+    };
+
+    rows.first()
+        .and_then(|row| row.get("is_public"))
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false)
+}
+
+/// URL of the package proxy for a resolved version
+fn package_proxy_url(proxy_base: &str, name: &str, version: &str) -> String {
+    format!(
+        "{}/api/v1/agents/{}/{}/download?raw=true",
+        proxy_base.trim_end_matches('/'),
+        urlencoding::encode(name),
+        urlencoding::encode(version)
+    )
+}
+
+/// Read a package through the cache, filling it on a miss. Cache failures
+/// only cost the storage round trip they would have saved.
+pub async fn load_package(
+    client: &reqwest::Client,
+    supabase_url: &str,
+    supabase_key: &str,
+    file_path: &str,
+    cache: Option<&PackageCache>,
+) -> Result<bytes::Bytes> {
+    if let Some(cache) = cache {
+        match cache.get(file_path).await {
+            Ok(Some(package)) => return Ok(package),
+            Ok(None) => {}
+            Err(e) => eprintln!("Warning: Package cache lookup failed: {}", e),
+        }
+    }
+
+    let package = fetch_package(client, supabase_url, supabase_key, file_path).await?;
+
+    if let Some(cache) = cache.filter(|_| PackageCache::accepts(package.len() as u64)) {
+        if let Err(e) = cache.put(file_path, &package).await {
+            eprintln!("Warning: Failed to cache package: {}", e);
+        }
+    }
+
+    Ok(package)
+}
+
+/// Read a package straight from storage using the service key
+async fn fetch_package(
+    client: &reqwest::Client,
+    supabase_url: &str,
+    supabase_key: &str,
+    file_path: &str,
+) -> Result<bytes::Bytes> {
+    let url = format!(
+        "{}/storage/v1/object/agent-packages/{}",
+        supabase_url, file_path
+    );
+
+    let response = client
+        .get(&url)
+        .header("apikey", supabase_key)
+        .header("Authorization", format!("Bearer {}", supabase_key))
+        .send()
+        .await?;
+
+    if !response.status().is_success() {
+        let error_text = response.text().await.unwrap_or_default();
+        return Err(anyhow!("Failed to fetch package: {}", error_text));
+    }
+
+    Ok(response.bytes().await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_missing_agents_are_told_apart_from_failed_lookups() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/rest/v1/rpc/get_agent_download_info"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([])))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/rest/v1/rpc/get_agent_download_info"))
+            .respond_with(ResponseTemplate::new(503).set_body_string("upstream unavailable"))
+            .mount(&server)
+            .await;
+
+        let client = reqwest::Client::new();
+        let missing = find_download(&client, &server.uri(), "key", "ghost", "latest", None)
+            .await
+            .unwrap_err();
+        assert!(missing.is::<DownloadNotFound>());
+
+        let failed = find_download(&client, &server.uri(), "key", "ghost", "latest", None)
+            .await
+            .unwrap_err();
+        assert!(!failed.is::<DownloadNotFound>());
+    }
+
+    #[tokio::test]
+    async fn test_download_info_inlines_and_counts_the_download() {
+        let server = MockServer::start().await;
+        let row = |file_size: u64| {
+            json!([{
+                "agent_id": "00000000-0000-0000-0000-000000000001",
+                "agent_name": "code-reviewer",
+                "author": "octocat",
+                "version": "1.2.3",
+                "file_path": "octocat/code-reviewer/1.2.3.zip",
+                "checksum": "sha256:aa",
+                "file_size": file_size
+            }])
+        };
+        Mock::given(method("POST"))
+            .and(path("/rest/v1/rpc/get_agent_download_info"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(row(5)))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/rest/v1/rpc/get_agent_download_info"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(row(INLINE_DOWNLOAD_LIMIT + 1)))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(
+                "/storage/v1/object/agent-packages/octocat/code-reviewer/1.2.3.zip",
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_string("hello"))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/rest/v1/rpc/record_download"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = reqwest::Client::new();
+        let request = DownloadRequest {
+            name: "code-reviewer",
+            version: "latest",
+            inline: true,
+            ..Default::default()
+        };
+        let download = download_info(&client, &server.uri(), "key", &request)
+            .await
+            .unwrap();
+        assert_eq!(download.version, "1.2.3");
+        assert_eq!(download.content.as_deref(), Some("aGVsbG8="));
+        assert!(download.download_url.is_empty());
+
+        // Too large to inline, and not counted
+        let too_large = download_info(&client, &server.uri(), "key", &request)
+            .await
+            .unwrap_err();
+        assert!(too_large.is::<InlineTooLarge>());
+    }
+
+    #[tokio::test]
+    async fn test_prefetched_download_info_is_not_counted() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/rest/v1/rpc/get_agent_download_info"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([{
+                "agent_id": "00000000-0000-0000-0000-000000000001",
+                "agent_name": "code-reviewer",
+                "author": "octocat",
+                "version": "1.2.3",
+                "file_path": "octocat/code-reviewer/1.2.3.zip",
+                "checksum": "sha256:aa",
+                "file_size": 5
+            }])))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(
+                "/storage/v1/object/agent-packages/octocat/code-reviewer/1.2.3.zip",
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_string("hello"))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/rest/v1/rpc/record_download"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&server)
+            .await;
+
+        let client = reqwest::Client::new();
+        let request = DownloadRequest {
+            name: "code-reviewer",
+            version: "latest",
+            inline: true,
+            prefetch: true,
+            ..Default::default()
+        };
+        let download = download_info(&client, &server.uri(), "key", &request)
+            .await
+            .unwrap();
+        assert_eq!(download.version, "1.2.3");
+    }
+}
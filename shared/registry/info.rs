@@ -0,0 +1,182 @@
+//! Agent details, including every downloadable version
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use super::{database_client, fetch};
+use crate::{AgentVisibility, Provenance};
+
+/// Columns of `agents` that make up an [`AgentInfo`]
+const AGENT_COLUMNS: &str = "id,name,current_version,description,author_name,created_at,updated_at,download_count,star_count,rating_count,rating_average,tags,homepage,repository,license,transparency_log_index,signed_at,provenance,is_public,verified_namespace,verified";
+
+/// Database agent row with publish metadata
+#[derive(Debug, Clone, Deserialize)]
+struct DbAgent {
+    pub id: String,
+    pub name: String,
+    #[serde(rename = "current_version")]
+    pub version: String,
+    pub description: String,
+    pub author_name: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub download_count: u64,
+    pub star_count: u64,
+    pub rating_count: u64,
+    pub rating_average: Option<f64>,
+    pub tags: Option<Vec<String>>,
+    pub homepage: Option<String>,
+    pub repository: Option<String>,
+    pub license: Option<String>,
+    pub transparency_log_index: Option<i64>,
+    pub signed_at: Option<DateTime<Utc>>,
+    pub provenance: Option<Provenance>,
+    pub is_public: bool,
+    pub verified_namespace: Option<String>,
+    pub verified: bool,
+}
+
+/// Database agent_versions row, only what's needed to list an agent's versions
+#[derive(Debug, Deserialize)]
+struct DbVersion {
+    pub agent_id: String,
+    pub version: String,
+}
+
+/// Detailed agent information including how it was published
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentInfo {
+    pub name: String,
+    pub version: String,
+    /// Every version that can still be downloaded, oldest first
+    pub versions: Vec<String>,
+    pub description: String,
+    pub author: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub download_count: u64,
+    pub star_count: u64,
+    pub rating_count: u64,
+    pub rating_average: Option<f64>,
+    pub tags: Vec<String>,
+    pub homepage: Option<String>,
+    pub repository: Option<String>,
+    pub license: Option<String>,
+    pub signed: bool,
+    pub transparency_log_index: Option<i64>,
+    pub signed_at: Option<DateTime<Utc>>,
+    pub provenance: Option<Provenance>,
+    pub is_public: bool,
+    /// The verified namespace the agent is published in, if any
+    pub verified_namespace: Option<String>,
+    /// Published by a verified user or in a verified namespace
+    pub verified: bool,
+}
+
+impl AgentInfo {
+    fn new(db_agent: DbAgent, versions: Vec<String>) -> Self {
+        AgentInfo {
+            name: db_agent.name,
+            // The newest downloadable version row, rather than the
+            // denormalized current_version, is what `latest` resolves to
+            version: versions.last().cloned().unwrap_or(db_agent.version),
+            versions,
+            description: db_agent.description,
+            author: db_agent
+                .author_name
+                .unwrap_or_else(|| "Unknown".to_string()),
+            created_at: db_agent.created_at,
+            updated_at: db_agent.updated_at,
+            download_count: db_agent.download_count,
+            star_count: db_agent.star_count,
+            rating_count: db_agent.rating_count,
+            rating_average: db_agent.rating_average,
+            tags: db_agent.tags.unwrap_or_default(),
+            homepage: db_agent.homepage,
+            repository: db_agent.repository,
+            license: db_agent.license,
+            signed: db_agent.transparency_log_index.is_some(),
+            transparency_log_index: db_agent.transparency_log_index,
+            signed_at: db_agent.signed_at,
+            provenance: db_agent.provenance,
+            is_public: db_agent.is_public,
+            verified_namespace: db_agent.verified_namespace,
+            verified: db_agent.verified,
+        }
+    }
+}
+
+/// The requested agents that were found, in request order, and the names
+/// that weren't
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct BatchInfo {
+    pub agents: Vec<AgentInfo>,
+    pub missing: Vec<String>,
+}
+
+/// The agent called `name`, if `visibility` allows it
+pub async fn get_agent_info(
+    name: &str,
+    visibility: &AgentVisibility,
+) -> Result<Option<AgentInfo>, String> {
+    let names = [name.to_string()];
+    Ok(get_agents_info(&names, visibility).await?.agents.pop())
+}
+
+/// Look up every named agent with two queries, one for the agents and one
+/// for their versions
+pub async fn get_agents_info(
+    names: &[String],
+    visibility: &AgentVisibility,
+) -> Result<BatchInfo, String> {
+    let client = database_client(visibility)?;
+
+    let query = client
+        .from("agents")
+        .select(AGENT_COLUMNS)
+        .in_("name", names.iter().map(|name| quote(name)));
+    let db_agents: Vec<DbAgent> = fetch(visibility.apply(query)).await?;
+
+    let mut versions: HashMap<String, Vec<String>> = HashMap::new();
+    if !db_agents.is_empty() {
+        // Unyanked versions with a stored package, oldest first
+        let query = client
+            .from("agent_versions")
+            .select("agent_id,version")
+            .in_("agent_id", db_agents.iter().map(|agent| agent.id.as_str()))
+            .eq("yanked", "false")
+            .not("is", "file_path", "null")
+            .order("created_at.asc");
+        let db_versions: Vec<DbVersion> = fetch(query).await?;
+        for db_version in db_versions {
+            versions
+                .entry(db_version.agent_id)
+                .or_default()
+                .push(db_version.version);
+        }
+    }
+
+    let mut found: HashMap<String, DbAgent> = db_agents
+        .into_iter()
+        .map(|agent| (agent.name.clone(), agent))
+        .collect();
+    let mut batch = BatchInfo::default();
+    for name in names {
+        match found.remove(name) {
+            Some(db_agent) => {
+                let agent_versions = versions.remove(&db_agent.id).unwrap_or_default();
+                batch.agents.push(AgentInfo::new(db_agent, agent_versions));
+            }
+            None => batch.missing.push(name.clone()),
+        }
+    }
+
+    Ok(batch)
+}
+
+/// Quote a value for a PostgREST `in` list, so commas and parentheses in
+/// it can't change the filter
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
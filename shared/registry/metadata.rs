@@ -0,0 +1,200 @@
+//! Editable agent metadata, updated with optimistic concurrency
+
+use serde::{Deserialize, Serialize};
+
+use super::service_config;
+use crate::tenant;
+use crate::validation::Validate;
+use crate::AuthenticatedUser;
+
+/// Columns returned for the editable part of an agent
+const METADATA_COLUMNS: &str = "name,description,tags,homepage,readme,metadata_version";
+
+/// Editable agent metadata. `metadata_version` is bumped by the database on
+/// every change and is exposed to clients as the ETag.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentMetadata {
+    pub name: String,
+    pub description: String,
+    #[serde(default)]
+    pub tags: Option<Vec<String>>,
+    pub homepage: Option<String>,
+    pub readme: Option<String>,
+    pub metadata_version: i64,
+}
+
+/// Fields an update may change; omitted fields are left as they are
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct MetadataUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub homepage: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub readme: Option<String>,
+}
+
+/// Which stored version an update expects to replace
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IfMatch {
+    Any,
+    Version(i64),
+}
+
+impl IfMatch {
+    /// Parse an `If-Match` header: `*` or a quoted, possibly weak, ETag
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        if value == "*" {
+            return Some(IfMatch::Any);
+        }
+
+        value
+            .trim_start_matches("W/")
+            .strip_prefix('"')?
+            .strip_suffix('"')?
+            .parse()
+            .ok()
+            .map(IfMatch::Version)
+    }
+}
+
+impl Validate for MetadataUpdate {
+    fn validate(&self) -> Vec<(String, String)> {
+        if self.description.is_none()
+            && self.tags.is_none()
+            && self.homepage.is_none()
+            && self.readme.is_none()
+        {
+            return vec![("body".to_string(), "Nothing to update".to_string())];
+        }
+
+        let mut errors = Vec::new();
+        let mut fail = |field: &str, message: &str| {
+            errors.push((field.to_string(), message.to_string()));
+        };
+
+        if let Some(description) = &self.description {
+            if description.trim().is_empty() {
+                fail("description", "Description cannot be empty");
+            } else if description.len() > 1000 {
+                fail("description", "Description cannot exceed 1000 characters");
+            }
+        }
+
+        if let Some(tags) = &self.tags {
+            if tags.len() > 20 {
+                fail("tags", "Cannot have more than 20 tags");
+            } else if tags
+                .iter()
+                .any(|tag| tag.trim().is_empty() || tag.len() > 50)
+            {
+                fail("tags", "Tags must be between 1 and 50 characters");
+            }
+        }
+
+        if let Some(homepage) = &self.homepage {
+            let is_url = homepage.starts_with("https://") || homepage.starts_with("http://");
+            if !homepage.is_empty() && !is_url {
+                fail("homepage", "Homepage must be an http(s) URL");
+            }
+        }
+
+        if let Some(readme) = &self.readme {
+            if readme.len() > 1024 * 1024 {
+                fail("readme", "Readme size exceeds maximum allowed size (1MB)");
+            }
+        }
+
+        errors
+    }
+}
+
+/// Why an update can't be applied, before touching the database
+pub fn validate_update(update: &MetadataUpdate) -> Result<(), String> {
+    match update.validate().into_iter().next() {
+        Some((_, message)) => Err(message),
+        None => Ok(()),
+    }
+}
+
+fn owned_agent_filter(name: &str, user: &AuthenticatedUser) -> String {
+    format!(
+        "name=eq.{}&tenant_id=eq.{}&user_id=eq.{}&select={METADATA_COLUMNS}",
+        urlencoding::encode(name),
+        tenant::current().id,
+        user.user_id
+    )
+}
+
+/// Metadata for an agent owned by `user`
+pub async fn fetch_metadata(
+    name: &str,
+    user: &AuthenticatedUser,
+) -> Result<Option<AgentMetadata>, String> {
+    let (supabase_url, supabase_key) = service_config()?;
+
+    let response = tenant::http_client()
+        .get(format!(
+            "{supabase_url}/rest/v1/agents?{}",
+            owned_agent_filter(name, user)
+        ))
+        .header("apikey", &supabase_key)
+        .header("Authorization", format!("Bearer {supabase_key}"))
+        .send()
+        .await
+        .map_err(|e| format!("Database query failed: {e}"))?;
+
+    parse_rows(response).await
+}
+
+/// Apply the update only if the stored version still matches `if_match`
+///
+/// `None` means nothing matched: either the agent isn't owned by `user` or
+/// someone else changed it first.
+pub async fn update_metadata(
+    name: &str,
+    user: &AuthenticatedUser,
+    if_match: IfMatch,
+    update: &MetadataUpdate,
+) -> Result<Option<AgentMetadata>, String> {
+    let (supabase_url, supabase_key) = service_config()?;
+
+    let mut filter = owned_agent_filter(name, user);
+    if let IfMatch::Version(version) = if_match {
+        filter.push_str(&format!("&metadata_version=eq.{version}"));
+    }
+
+    let response = tenant::http_client()
+        .patch(format!("{supabase_url}/rest/v1/agents?{filter}"))
+        .header("apikey", &supabase_key)
+        .header("Authorization", format!("Bearer {supabase_key}"))
+        .header("Prefer", "return=representation")
+        .json(update)
+        .send()
+        .await
+        .map_err(|e| format!("Database update failed: {e}"))?;
+
+    parse_rows(response).await
+}
+
+async fn parse_rows(response: reqwest::Response) -> Result<Option<AgentMetadata>, String> {
+    let status = response.status();
+    let body = response
+        .text()
+        .await
+        .map_err(|e| format!("Failed to read response: {e}"))?;
+
+    if !status.is_success() {
+        return Err(format!(
+            "Database request failed with status {status}: {body}"
+        ));
+    }
+
+    let rows: Vec<AgentMetadata> =
+        serde_json::from_str(&body).map_err(|e| format!("Failed to parse agent metadata: {e}"))?;
+
+    Ok(rows.into_iter().next())
+}
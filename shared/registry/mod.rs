@@ -0,0 +1,89 @@
+//! Registry queries shared by the REST handlers and the gRPC service
+//!
+//! Each REST function parses its request, calls into these modules and
+//! shapes the HTTP response; the gRPC service (`grpc` feature) does the same
+//! for its calls.
+//! Keeping the Supabase queries here means both transports apply the same
+//! visibility rules and return the same data.
+
+pub mod artifacts;
+pub mod changes;
+pub mod download;
+pub mod info;
+pub mod metadata;
+pub mod search;
+pub mod stats;
+
+use std::env;
+
+use crate::{tenant, AgentVisibility};
+
+/// A PostgREST client able to read the agents `visibility` allows, acting in
+/// the current tenant
+///
+/// Public reads use the anon key. Private agents are hidden from it by row
+/// level security, so reads that include them use the service role and rely
+/// on [`AgentVisibility::apply`] instead.
+pub fn database_client(visibility: &AgentVisibility) -> Result<postgrest::Postgrest, String> {
+    let supabase_url = env::var("SUPABASE_URL").unwrap_or_default();
+    let supabase_key = if visibility.includes_private() {
+        env::var("SUPABASE_SERVICE_ROLE_KEY").unwrap_or_default()
+    } else {
+        env::var("SUPABASE_ANON_KEY")
+            .or_else(|_| env::var("SUPABASE_SERVICE_ROLE_KEY"))
+            .unwrap_or_default()
+    };
+
+    if supabase_url.is_empty() || supabase_key.is_empty() {
+        return Err(
+            "Database not configured - missing SUPABASE_URL or SUPABASE_ANON_KEY".to_string(),
+        );
+    }
+
+    let client =
+        tenant::database(format!("{supabase_url}/rest/v1")).insert_header("apikey", &supabase_key);
+    if visibility.includes_private() {
+        return Ok(client.insert_header("Authorization", format!("Bearer {supabase_key}")));
+    }
+
+    // Public reads need only the apikey header
+    Ok(client)
+}
+
+/// Supabase URL and service role key, for queries that act on the caller's
+/// behalf and check access themselves
+pub fn service_config() -> Result<(String, String), String> {
+    let supabase_url = env::var("SUPABASE_URL").unwrap_or_default();
+    let supabase_key = env::var("SUPABASE_SERVICE_ROLE_KEY").unwrap_or_default();
+
+    if supabase_url.is_empty() || supabase_key.is_empty() {
+        return Err(
+            "Database not configured - missing SUPABASE_URL or SUPABASE_SERVICE_ROLE_KEY"
+                .to_string(),
+        );
+    }
+
+    Ok((supabase_url, supabase_key))
+}
+
+/// Run a PostgREST query and parse its JSON rows
+async fn fetch<T: serde::de::DeserializeOwned>(query: postgrest::Builder) -> Result<T, String> {
+    let response = query
+        .execute()
+        .await
+        .map_err(|e| format!("Database query failed: {e}"))?;
+
+    let status = response.status();
+    let body = response
+        .text()
+        .await
+        .map_err(|e| format!("Failed to read response: {e}"))?;
+
+    if !status.is_success() {
+        return Err(format!(
+            "Database query failed with status {status}: {body}"
+        ));
+    }
+
+    serde_json::from_str(&body).map_err(|e| format!("Failed to parse response: {e}"))
+}
@@ -0,0 +1,402 @@
+//! Agent search by substring, exact name, glob or regex
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+use super::database_client;
+use crate::AgentVisibility;
+
+/// Longest glob or regex pattern accepted, to bound matching cost
+pub const MAX_PATTERN_LENGTH: usize = 128;
+
+/// Columns of `agents` that make up an [`Agent`]
+pub(super) const AGENT_COLUMNS: &str = "name,current_version,description,author_name,created_at,updated_at,download_count,star_count,rating_count,rating_average,tags,readme,homepage,repository,license,signature_bundle,is_public,is_template,verified_namespace,verified";
+
+/// How the query is matched against agents
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchMode {
+    /// Substring of the name, description, author or a tag
+    Substring,
+    /// The exact agent name
+    Exact,
+    /// Agent name against a glob with `*` and `?`, case-insensitively
+    Glob,
+    /// Agent name against a POSIX regular expression, case-insensitively
+    Regex,
+}
+
+impl SearchMode {
+    pub fn parse(mode: &str) -> Option<Self> {
+        match mode {
+            "substring" => Some(SearchMode::Substring),
+            "exact" => Some(SearchMode::Exact),
+            "glob" => Some(SearchMode::Glob),
+            "regex" => Some(SearchMode::Regex),
+            _ => None,
+        }
+    }
+}
+
+/// Order of search results
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchSort {
+    /// Most downloaded first
+    Downloads,
+    /// Most starred first
+    Stars,
+    /// Most recently updated first
+    Recent,
+}
+
+impl SearchSort {
+    pub fn parse(sort: &str) -> Option<Self> {
+        match sort {
+            "downloads" => Some(SearchSort::Downloads),
+            "stars" => Some(SearchSort::Stars),
+            "recent" => Some(SearchSort::Recent),
+            _ => None,
+        }
+    }
+
+    /// PostgREST `order` clause
+    fn order(self) -> &'static str {
+        match self {
+            SearchSort::Downloads => "download_count.desc,updated_at.desc",
+            SearchSort::Stars => "star_count.desc,updated_at.desc",
+            SearchSort::Recent => "updated_at.desc",
+        }
+    }
+}
+
+/// One page of a search
+#[derive(Debug, Clone)]
+pub struct SearchQuery<'a> {
+    pub query: &'a str,
+    pub mode: SearchMode,
+    pub sort: SearchSort,
+    pub limit: usize,
+    /// Starting from 1
+    pub page: usize,
+    /// Only agents flagged as templates
+    pub templates_only: bool,
+}
+
+/// Rows of the results that `page` of `limit` covers, counting pages from
+/// 1 (0 is taken as 1). Pages too far out to address are empty rather than
+/// overflowing.
+pub fn page_rows(page: usize, limit: usize) -> Range<usize> {
+    let start = page.saturating_sub(1).saturating_mul(limit);
+    start..start.saturating_add(limit)
+}
+
+/// Why a search couldn't be answered
+#[derive(Debug)]
+pub enum SearchError {
+    /// The pattern is too long, or the database rejected it, e.g. a
+    /// malformed regex
+    InvalidPattern(String),
+    Internal(String),
+}
+
+impl From<String> for SearchError {
+    fn from(error: String) -> Self {
+        SearchError::Internal(error)
+    }
+}
+
+/// Database agent structure (matches actual DB schema)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(super) struct DbAgent {
+    pub name: String,
+    #[serde(rename = "current_version")]
+    pub version: String,
+    pub description: String,
+    #[serde(rename = "author_name")]
+    pub author_name: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub download_count: u64,
+    pub star_count: u64,
+    pub rating_count: u64,
+    pub rating_average: Option<f64>,
+    pub tags: Option<Vec<String>>,
+    pub readme: Option<String>,
+    pub homepage: Option<String>,
+    pub repository: Option<String>,
+    pub license: Option<String>,
+    pub signature_bundle: Option<serde_json::Value>,
+    pub is_public: bool,
+    pub is_template: bool,
+    pub verified_namespace: Option<String>,
+    pub verified: bool,
+}
+
+/// Agent metadata returned by the API (matches expected client schema)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Agent {
+    pub name: String,
+    pub version: String,
+    pub description: String,
+    pub author: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub download_count: u64,
+    pub star_count: u64,
+    pub rating_count: u64,
+    pub rating_average: Option<f64>,
+    pub tags: Vec<String>,
+    pub readme: Option<String>,
+    pub homepage: Option<String>,
+    pub repository: Option<String>,
+    pub license: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature_bundle: Option<serde_json::Value>,
+    pub is_public: bool,
+    /// A starting point for `carp new --template`
+    pub is_template: bool,
+    /// The verified namespace the agent is published in, if its owner
+    /// belongs to the organization that claimed it
+    pub verified_namespace: Option<String>,
+    /// Published by a verified user or in a verified namespace
+    pub verified: bool,
+}
+
+impl From<DbAgent> for Agent {
+    fn from(db_agent: DbAgent) -> Self {
+        Agent {
+            name: db_agent.name,
+            version: db_agent.version,
+            description: db_agent.description,
+            author: db_agent
+                .author_name
+                .unwrap_or_else(|| "Unknown".to_string()),
+            created_at: db_agent.created_at,
+            updated_at: db_agent.updated_at,
+            download_count: db_agent.download_count,
+            star_count: db_agent.star_count,
+            rating_count: db_agent.rating_count,
+            rating_average: db_agent.rating_average,
+            tags: db_agent.tags.unwrap_or_default(),
+            readme: db_agent.readme,
+            homepage: db_agent.homepage,
+            repository: db_agent.repository,
+            license: db_agent.license,
+            signature_bundle: db_agent.signature_bundle,
+            is_public: db_agent.is_public,
+            is_template: db_agent.is_template,
+            verified_namespace: db_agent.verified_namespace,
+            verified: db_agent.verified,
+        }
+    }
+}
+
+/// One page of the visible agents matching a query, and how many match in total
+pub async fn search(
+    search: &SearchQuery<'_>,
+    visibility: &AgentVisibility,
+) -> Result<(Vec<Agent>, usize), SearchError> {
+    let agents = search_page(search, visibility).await?;
+    let total = get_total_agent_count(search, visibility).await?;
+    Ok((agents, total))
+}
+
+/// One page of the visible agents matching a query, without counting them all
+pub async fn search_page(
+    search: &SearchQuery<'_>,
+    visibility: &AgentVisibility,
+) -> Result<Vec<Agent>, SearchError> {
+    if matches!(search.mode, SearchMode::Glob | SearchMode::Regex)
+        && search.query.chars().count() > MAX_PATTERN_LENGTH
+    {
+        return Err(SearchError::InvalidPattern(format!(
+            "Patterns can be at most {MAX_PATTERN_LENGTH} characters"
+        )));
+    }
+
+    search_agents_in_db(search, visibility).await
+}
+
+/// Restrict a query to agents matching the search's query under its mode
+fn apply_search_filter(
+    mut query_builder: postgrest::Builder,
+    search: &SearchQuery<'_>,
+) -> postgrest::Builder {
+    if search.templates_only {
+        query_builder = query_builder.eq("is_template", "true");
+    }
+
+    let query = search.query;
+    if query.is_empty() {
+        return query_builder;
+    }
+
+    match search.mode {
+        SearchMode::Exact => query_builder.eq("name", query),
+        SearchMode::Glob => query_builder.ilike("name", glob_to_like(query)),
+        // Quoted so commas and parentheses in the pattern survive PostgREST's
+        // filter syntax
+        SearchMode::Regex => query_builder.or(format!(
+            "name.imatch.\"{}\"",
+            query.replace('\\', "\\\\").replace('"', "\\\"")
+        )),
+        // Use full-text search with existing GIN index for better performance
+        // Falls back to ILIKE if FTS doesn't work
+        SearchMode::Substring => query_builder.or(format!(
+            "name.ilike.*{query}*,description.ilike.*{query}*,author_name.ilike.*{query}*,tags.cs.{{{query}}}"
+        )),
+    }
+}
+
+/// Translate a `*`/`?` glob into a LIKE pattern, escaping LIKE's own
+/// wildcards so `_` in agent names matches literally
+fn glob_to_like(glob: &str) -> String {
+    let mut pattern = String::with_capacity(glob.len());
+    for c in glob.chars() {
+        match c {
+            '\\' | '_' | '%' => {
+                pattern.push('\\');
+                pattern.push(c);
+            }
+            '?' => pattern.push('_'),
+            c => pattern.push(c),
+        }
+    }
+    pattern
+}
+
+/// Map a failed PostgREST response to a search error
+fn query_error(status: impl std::fmt::Display, error_text: &str, mode: SearchMode) -> SearchError {
+    // 2201B is Postgres' invalid_regular_expression
+    if mode == SearchMode::Regex && error_text.contains("2201B") {
+        return SearchError::InvalidPattern("Invalid regular expression".to_string());
+    }
+
+    SearchError::Internal(format!(
+        "Database query failed with status {status}: {error_text}"
+    ))
+}
+
+async fn search_agents_in_db(
+    search: &SearchQuery<'_>,
+    visibility: &AgentVisibility,
+) -> Result<Vec<Agent>, SearchError> {
+    let client = database_client(visibility)?;
+
+    let rows = page_rows(search.page, search.limit);
+
+    // Build query based on search parameters
+    // Note: Using actual database column names
+    let mut query_builder = client.from("agents").select(AGENT_COLUMNS);
+
+    // Apply search filter if query is provided
+    query_builder = apply_search_filter(query_builder, search);
+
+    // Restrict to visible agents and optimize ordering
+    query_builder = visibility
+        .apply(query_builder)
+        .range(rows.start, rows.end.saturating_sub(1))
+        .order(search.sort.order());
+
+    // Execute query
+    let response = query_builder
+        .execute()
+        .await
+        .map_err(|e| format!("Database query failed: {e}"))?;
+
+    if !response.status().is_success() {
+        let status = response.status();
+        let error_text = response
+            .text()
+            .await
+            .unwrap_or_else(|_| "Unknown error".to_string());
+        return Err(query_error(status, &error_text, search.mode));
+    }
+
+    let body = response
+        .text()
+        .await
+        .map_err(|e| format!("Failed to read response: {e}"))?;
+
+    // Parse response as Vec<DbAgent> then convert to Vec<Agent>
+    let db_agents: Vec<DbAgent> =
+        serde_json::from_str(&body).map_err(|e| format!("Failed to parse agents: {e}"))?;
+
+    let agents: Vec<Agent> = db_agents.into_iter().map(Agent::from).collect();
+
+    Ok(agents)
+}
+
+async fn get_total_agent_count(
+    search: &SearchQuery<'_>,
+    visibility: &AgentVisibility,
+) -> Result<usize, SearchError> {
+    let client = database_client(visibility)?;
+
+    // Build count query using PostgREST's exact_count feature
+    let mut query_builder = client.from("agents").select("id").exact_count();
+
+    // Apply same search and visibility filters as main query
+    query_builder = apply_search_filter(query_builder, search);
+    query_builder = visibility.apply(query_builder);
+
+    // Execute count query
+    let response = query_builder
+        .execute()
+        .await
+        .map_err(|e| format!("Database count query failed: {e}"))?;
+
+    if !response.status().is_success() {
+        let status = response.status();
+        let error_text = response
+            .text()
+            .await
+            .unwrap_or_else(|_| "Unknown error".to_string());
+        return Err(query_error(status, &error_text, search.mode));
+    }
+
+    // PostgREST returns the count in the Content-Range header when using exact_count
+    if let Some(content_range) = response.headers().get("content-range") {
+        if let Ok(range_str) = content_range.to_str() {
+            // Parse the content-range header to get total count
+            // Format: "0-4/5" where 5 is the total count, or "*/0" if no records
+            if let Some(total_str) = range_str.split('/').nth(1) {
+                if let Ok(count) = total_str.parse::<usize>() {
+                    return Ok(count);
+                }
+            }
+        }
+    }
+
+    // Fallback to 0 if count parsing fails
+    Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_page_zero_is_the_first_page() {
+        assert_eq!(page_rows(0, 20), 0..20);
+        assert_eq!(page_rows(1, 20), 0..20);
+    }
+
+    proptest! {
+        #[test]
+        fn pages_tile_the_results(page in 1usize..1_000_000, limit in 1usize..=100) {
+            let rows = page_rows(page, limit);
+            prop_assert_eq!(rows.len(), limit);
+            prop_assert_eq!(rows.end, page_rows(page + 1, limit).start);
+            prop_assert_eq!(rows.start / limit + 1, page);
+        }
+
+        #[test]
+        fn page_rows_never_overflow(page in any::<usize>(), limit in any::<usize>()) {
+            let rows = page_rows(page, limit);
+            prop_assert!(rows.start <= rows.end);
+            prop_assert!(rows.len() <= limit);
+        }
+    }
+}
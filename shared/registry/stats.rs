@@ -0,0 +1,134 @@
+//! Download totals for one agent, read from the daily and weekly rollups
+//! the `rollup_stats` background job keeps up to date
+
+use chrono::{Datelike, Days, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use super::{database_client, fetch};
+use crate::AgentVisibility;
+
+/// Days covered when no range is asked for
+pub const DEFAULT_DAYS: u32 = 30;
+pub const MAX_DAYS: u32 = 365;
+
+#[derive(Debug, Deserialize)]
+struct DbAgent {
+    id: String,
+    name: String,
+    download_count: u64,
+    is_public: bool,
+}
+
+/// Downloads of an agent on one UTC day
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DailyDownloads {
+    pub day: NaiveDate,
+    /// Every download, including repeats
+    pub downloads: u64,
+    /// Distinct users, or IPs for anonymous downloads
+    pub unique_downloaders: u64,
+    pub bytes: u64,
+}
+
+/// Downloads of an agent in one week, starting on the Monday `week`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WeeklyDownloads {
+    pub week: NaiveDate,
+    pub downloads: u64,
+    pub unique_downloaders: u64,
+    pub bytes: u64,
+}
+
+/// How often an agent has been downloaded lately
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentStats {
+    pub name: String,
+    /// All-time downloads, counting repeats by the same downloader once an hour
+    pub download_count: u64,
+    pub days: u32,
+    /// Downloads over the last `days` days
+    pub downloads: u64,
+    /// Each of the last `days` days, oldest first, including days without
+    /// downloads
+    pub daily: Vec<DailyDownloads>,
+    /// The weeks those days fall in, oldest first; weeks without downloads
+    /// are left out
+    pub weekly: Vec<WeeklyDownloads>,
+    #[serde(skip)]
+    pub is_public: bool,
+}
+
+/// Download stats for the last `days` days of the agent called `name`, if
+/// `visibility` allows it. Today is included, up to the last rollup.
+pub async fn get_agent_stats(
+    name: &str,
+    visibility: &AgentVisibility,
+    days: u32,
+) -> Result<Option<AgentStats>, String> {
+    let days = days.clamp(1, MAX_DAYS);
+    let client = database_client(visibility)?;
+
+    let query = client
+        .from("agents")
+        .select("id,name,download_count,is_public")
+        .eq("name", name);
+    let db_agents: Vec<DbAgent> = fetch(visibility.apply(query)).await?;
+    let Some(agent) = db_agents.into_iter().next() else {
+        return Ok(None);
+    };
+
+    let today = Utc::now().date_naive();
+    let first_day = today - Days::new(u64::from(days - 1));
+    let first_week = first_day - Days::new(u64::from(first_day.weekday().num_days_from_monday()));
+
+    let query = client
+        .from("agent_downloads_daily")
+        .select("day,downloads,unique_downloaders,bytes")
+        .eq("agent_id", &agent.id)
+        .gte("day", first_day.to_string())
+        .order("day.asc");
+    let recorded: Vec<DailyDownloads> = fetch(query).await?;
+
+    let query = client
+        .from("agent_downloads_weekly")
+        .select("week,downloads,unique_downloaders,bytes")
+        .eq("agent_id", &agent.id)
+        .gte("week", first_week.to_string())
+        .order("week.asc");
+    let weekly: Vec<WeeklyDownloads> = fetch(query).await?;
+
+    let daily = every_day(first_day, today, recorded);
+    Ok(Some(AgentStats {
+        name: agent.name,
+        download_count: agent.download_count,
+        days,
+        downloads: daily.iter().map(|day| day.downloads).sum(),
+        daily,
+        weekly,
+        is_public: agent.is_public,
+    }))
+}
+
+/// The `recorded` totals for each day from `first` to `last`, with zeroes
+/// for the days missing from them
+fn every_day(
+    first: NaiveDate,
+    last: NaiveDate,
+    recorded: Vec<DailyDownloads>,
+) -> Vec<DailyDownloads> {
+    let mut recorded: HashMap<NaiveDate, DailyDownloads> =
+        recorded.into_iter().map(|day| (day.day, day)).collect();
+    first
+        .iter_days()
+        .take_while(|day| *day <= last)
+        .map(|day| {
+            recorded.remove(&day).unwrap_or(DailyDownloads {
+                day,
+                downloads: 0,
+                unique_downloaders: 0,
+                bytes: 0,
+            })
+        })
+        .collect()
+}
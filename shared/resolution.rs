@@ -0,0 +1,673 @@
+//! Signed dependency resolution for `carp pull --with-deps`
+//!
+//! Each published version records the agents it depends on, as a map of agent
+//! name to semver requirement. `GET /api/v1/agents/{name}/resolve` walks those
+//! maps from one agent to its whole closure and returns it as a
+//! [`ResolutionManifest`] signed with the registry's Ed25519 key. The CLI
+//! checks the signature, downloads exactly the versions and checksums listed,
+//! and keeps the manifest in `carp.lock`, so a mirror or proxy in between
+//! can't swap in other versions halfway through.
+//!
+//! Resolution keeps one version per agent: the newest one the first
+//! requirement for it allows. A later requirement that version doesn't meet
+//! is reported as a conflict rather than backtracked.
+//!
+//! Configuration comes from the environment:
+//!
+//! - `RESOLUTION_SIGNING_KEY`: base64 of a 32-byte Ed25519 seed, e.g. from
+//!   `openssl rand -base64 32`; without it the resolve endpoint answers 503
+
+use crate::tenant;
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use chrono::{DateTime, Utc};
+use ring::signature::{Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519};
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fmt;
+use std::future::Future;
+use uuid::Uuid;
+
+/// Most dependencies one version may declare
+pub const MAX_DEPENDENCIES: usize = 50;
+
+/// Most agents one resolution may return
+pub const MAX_CLOSURE: usize = 200;
+
+/// Agent name to semver requirement, e.g. `code-reviewer = "^1.2"`
+pub type Dependencies = BTreeMap<String, String>;
+
+/// The dependencies declared in an agent's frontmatter, as stored in its
+/// definition's `metadata`
+pub fn dependencies_from_definition(
+    definition: &serde_json::Value,
+) -> Result<Dependencies, String> {
+    match definition.pointer("/metadata/dependencies") {
+        None | Some(serde_json::Value::Null) => Ok(Dependencies::new()),
+        Some(serde_json::Value::Object(map)) => map
+            .iter()
+            .map(|(name, requirement)| match requirement {
+                serde_json::Value::String(requirement) => Ok((name.clone(), requirement.clone())),
+                _ => Err(format!(
+                    "Dependency '{name}' must map to a version requirement string"
+                )),
+            })
+            .collect(),
+        Some(_) => Err(
+            "Frontmatter 'dependencies' must map agent names to version requirements".to_string(),
+        ),
+    }
+}
+
+/// Problems with the dependencies `name` declares, as `(field, message)`
+pub fn validate_dependencies(name: &str, dependencies: &Dependencies) -> Vec<(String, String)> {
+    let mut errors = Vec::new();
+    if dependencies.len() > MAX_DEPENDENCIES {
+        errors.push((
+            "dependencies".to_string(),
+            format!("Cannot have more than {MAX_DEPENDENCIES} dependencies"),
+        ));
+    }
+    for (dependency, requirement) in dependencies {
+        let field = format!("dependencies.{dependency}");
+        if dependency.is_empty()
+            || dependency.len() > 100
+            || !dependency
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+        {
+            errors.push((field, format!("'{dependency}' is not a valid agent name")));
+        } else if dependency == name {
+            errors.push((field, "An agent can't depend on itself".to_string()));
+        } else if let Err(e) = parse_requirement(requirement) {
+            errors.push((
+                field,
+                format!("Invalid version requirement '{requirement}': {e}"),
+            ));
+        }
+    }
+    errors
+}
+
+/// A requirement in Cargo's syntax; empty and `latest` mean any version
+fn parse_requirement(requirement: &str) -> Result<VersionReq, semver::Error> {
+    match requirement.trim() {
+        "" | "latest" => Ok(VersionReq::STAR),
+        requirement => VersionReq::parse(requirement),
+    }
+}
+
+/// A downloadable version the resolver may pick
+#[derive(Debug, Clone, Deserialize)]
+pub struct Candidate {
+    pub agent_name: String,
+    pub version: String,
+    pub checksum: String,
+    #[serde(default)]
+    pub dependencies: Dependencies,
+}
+
+/// One agent of a resolved closure
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResolvedAgent {
+    pub name: String,
+    pub version: String,
+    /// SHA-256 of the package, as `sha256:<hex>`
+    pub checksum: String,
+}
+
+/// What the registry vouches for: `root` at `requirement` and everything it
+/// depends on, one version each, sorted by name
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResolutionManifest {
+    pub root: String,
+    pub requirement: String,
+    pub agents: Vec<ResolvedAgent>,
+    pub issued_at: DateTime<Utc>,
+}
+
+/// Why a closure couldn't be resolved
+#[derive(Debug)]
+pub enum ResolveError {
+    InvalidRequirement {
+        name: String,
+        requirement: String,
+    },
+    /// No visible version of `name` meets `requirement`
+    NotFound {
+        name: String,
+        requirement: String,
+        required_by: Option<String>,
+    },
+    /// `name` was already resolved to `version`, which `required_by` doesn't accept
+    Conflict {
+        name: String,
+        version: String,
+        requirement: String,
+        required_by: String,
+    },
+    TooLarge,
+    Lookup(anyhow::Error),
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResolveError::InvalidRequirement { name, requirement } => {
+                write!(f, "Invalid version requirement '{requirement}' for '{name}'")
+            }
+            ResolveError::NotFound {
+                name,
+                requirement,
+                required_by: None,
+            } => write!(f, "No version of '{name}' matches '{requirement}'"),
+            ResolveError::NotFound {
+                name,
+                requirement,
+                required_by: Some(required_by),
+            } => write!(
+                f,
+                "{required_by} depends on '{name}' {requirement}, which matches no published version"
+            ),
+            ResolveError::Conflict {
+                name,
+                version,
+                requirement,
+                required_by,
+            } => write!(
+                f,
+                "{required_by} depends on '{name}' {requirement}, but {name}@{version} was already resolved"
+            ),
+            ResolveError::TooLarge => {
+                write!(f, "The dependency closure has more than {MAX_CLOSURE} agents")
+            }
+            ResolveError::Lookup(e) => write!(f, "Failed to look up versions: {e:#}"),
+        }
+    }
+}
+
+impl std::error::Error for ResolveError {}
+
+/// Resolve `root` at `requirement` and everything it depends on
+///
+/// `candidates` is asked once per level of the dependency graph for every
+/// visible version of the agents it names.
+pub async fn resolve<F, Fut>(
+    root: &str,
+    requirement: &str,
+    mut candidates: F,
+) -> Result<Vec<ResolvedAgent>, ResolveError>
+where
+    F: FnMut(Vec<String>) -> Fut,
+    Fut: Future<Output = Result<Vec<Candidate>>>,
+{
+    let mut known: HashMap<String, Vec<Candidate>> = HashMap::new();
+    let mut resolved: BTreeMap<String, Candidate> = BTreeMap::new();
+    let mut pending = vec![(root.to_string(), requirement.to_string(), None::<String>)];
+
+    while !pending.is_empty() {
+        let mut missing: Vec<String> = pending
+            .iter()
+            .map(|(name, _, _)| name.clone())
+            .filter(|name| !known.contains_key(name))
+            .collect();
+        missing.sort();
+        missing.dedup();
+        if !missing.is_empty() {
+            for candidate in candidates(missing.clone())
+                .await
+                .map_err(ResolveError::Lookup)?
+            {
+                known
+                    .entry(candidate.agent_name.clone())
+                    .or_default()
+                    .push(candidate);
+            }
+            for name in missing {
+                known.entry(name).or_default();
+            }
+        }
+
+        let mut next = Vec::new();
+        for (name, requirement, required_by) in pending.drain(..) {
+            let parsed =
+                parse_requirement(&requirement).map_err(|_| ResolveError::InvalidRequirement {
+                    name: name.clone(),
+                    requirement: requirement.clone(),
+                })?;
+
+            if let Some(existing) = resolved.get(&name) {
+                let accepted =
+                    Version::parse(&existing.version).is_ok_and(|version| parsed.matches(&version));
+                if !accepted {
+                    return Err(ResolveError::Conflict {
+                        name,
+                        version: existing.version.clone(),
+                        requirement,
+                        required_by: required_by.unwrap_or_else(|| root.to_string()),
+                    });
+                }
+                continue;
+            }
+
+            let Some(choice) = known[&name]
+                .iter()
+                .filter_map(|c| Version::parse(&c.version).ok().map(|version| (version, c)))
+                .filter(|(version, _)| parsed.matches(version))
+                .max_by(|(a, _), (b, _)| a.cmp(b))
+                .map(|(_, candidate)| candidate.clone())
+            else {
+                return Err(ResolveError::NotFound {
+                    name,
+                    requirement,
+                    required_by,
+                });
+            };
+
+            let dependent = format!("{name}@{}", choice.version);
+            next.extend(
+                choice
+                    .dependencies
+                    .iter()
+                    .map(|(dep, req)| (dep.clone(), req.clone(), Some(dependent.clone()))),
+            );
+            resolved.insert(name, choice);
+            if resolved.len() > MAX_CLOSURE {
+                return Err(ResolveError::TooLarge);
+            }
+        }
+        pending = next;
+    }
+
+    Ok(resolved
+        .into_values()
+        .map(|candidate| ResolvedAgent {
+            name: candidate.agent_name,
+            version: candidate.version,
+            checksum: candidate.checksum,
+        })
+        .collect())
+}
+
+/// A manifest and the registry's signature over its exact bytes
+///
+/// The manifest stays a JSON string so the bytes that were signed are the
+/// bytes that are checked, whatever re-serializes them in between.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedResolution {
+    pub manifest: String,
+    /// Base64 Ed25519 signature of `manifest`
+    pub signature: String,
+    /// Base64 Ed25519 public key to check it with
+    pub public_key: String,
+}
+
+impl SignedResolution {
+    /// Check the signature and read the manifest
+    pub fn verify(&self) -> Result<ResolutionManifest> {
+        let public_key = STANDARD
+            .decode(&self.public_key)
+            .context("Public key is not base64")?;
+        let signature = STANDARD
+            .decode(&self.signature)
+            .context("Signature is not base64")?;
+        UnparsedPublicKey::new(&ED25519, public_key)
+            .verify(self.manifest.as_bytes(), &signature)
+            .map_err(|_| anyhow!("Resolution manifest signature is invalid"))?;
+        serde_json::from_str(&self.manifest).context("Resolution manifest is malformed")
+    }
+}
+
+/// Signs resolution manifests with the registry's Ed25519 key
+pub struct ResolutionSigner {
+    key_pair: Ed25519KeyPair,
+}
+
+impl ResolutionSigner {
+    /// The signer for `RESOLUTION_SIGNING_KEY`, if it's set
+    pub fn from_env() -> Result<Option<Self>> {
+        match env::var("RESOLUTION_SIGNING_KEY") {
+            Ok(seed) if !seed.trim().is_empty() => {
+                let seed = STANDARD
+                    .decode(seed.trim())
+                    .context("RESOLUTION_SIGNING_KEY is not base64")?;
+                Self::from_seed(&seed).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    pub fn from_seed(seed: &[u8]) -> Result<Self> {
+        if seed.len() != 32 {
+            bail!("An Ed25519 seed is 32 bytes, not {}", seed.len());
+        }
+        let key_pair = Ed25519KeyPair::from_seed_unchecked(seed)
+            .map_err(|e| anyhow!("Invalid Ed25519 seed: {e}"))?;
+        Ok(Self { key_pair })
+    }
+
+    /// The base64 public key clients check signatures with
+    pub fn public_key(&self) -> String {
+        STANDARD.encode(self.key_pair.public_key().as_ref())
+    }
+
+    pub fn sign(&self, manifest: &ResolutionManifest) -> Result<SignedResolution> {
+        let manifest = serde_json::to_string(manifest)?;
+        let signature = self.key_pair.sign(manifest.as_bytes());
+        Ok(SignedResolution {
+            signature: STANDARD.encode(signature.as_ref()),
+            public_key: self.public_key(),
+            manifest,
+        })
+    }
+}
+
+/// Looks up resolution candidates through Supabase's REST API
+pub struct ResolutionStore {
+    client: reqwest::Client,
+    supabase_url: String,
+    supabase_key: String,
+}
+
+impl ResolutionStore {
+    pub fn new(supabase_url: String, supabase_key: String) -> Self {
+        Self {
+            client: tenant::http_client(),
+            supabase_url,
+            supabase_key,
+        }
+    }
+
+    /// Every downloadable version of `names` that `requester_id` can read
+    pub async fn candidates(
+        &self,
+        names: &[String],
+        requester_id: Option<Uuid>,
+    ) -> Result<Vec<Candidate>> {
+        let response = self
+            .client
+            .post(format!(
+                "{}/rest/v1/rpc/get_resolution_candidates",
+                self.supabase_url
+            ))
+            .header("apikey", &self.supabase_key)
+            .header("Authorization", format!("Bearer {}", self.supabase_key))
+            .json(&json!({ "p_names": names, "p_requester_id": requester_id }))
+            .send()
+            .await
+            .context("Database request failed")?;
+        let status = response.status();
+        let body = response.text().await?;
+        if !status.is_success() {
+            bail!("Database request failed with status {status}: {body}");
+        }
+        serde_json::from_str(&body).with_context(|| format!("Unexpected database response: {body}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn candidate(name: &str, version: &str, dependencies: &[(&str, &str)]) -> Candidate {
+        Candidate {
+            agent_name: name.to_string(),
+            version: version.to_string(),
+            checksum: format!("sha256:{name}-{version}"),
+            dependencies: dependencies
+                .iter()
+                .map(|(name, req)| (name.to_string(), req.to_string()))
+                .collect(),
+        }
+    }
+
+    fn registry() -> Vec<Candidate> {
+        vec![
+            candidate("app", "1.0.0", &[("lib", "^1.0"), ("util", "~0.2")]),
+            candidate("app", "2.0.0", &[("lib", "^2")]),
+            candidate("lib", "1.0.0", &[]),
+            candidate("lib", "1.4.0", &[("util", "0.2")]),
+            candidate("lib", "2.0.0", &[("util", "^0.3")]),
+            candidate("util", "0.2.1", &[]),
+            candidate("util", "0.2.5", &[]),
+        ]
+    }
+
+    async fn resolve_in(
+        registry: Vec<Candidate>,
+        root: &str,
+        requirement: &str,
+    ) -> Result<Vec<ResolvedAgent>, ResolveError> {
+        resolve(root, requirement, |names| {
+            let found: Vec<Candidate> = registry
+                .iter()
+                .filter(|c| names.contains(&c.agent_name))
+                .cloned()
+                .collect();
+            async move { Ok(found) }
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_resolves_the_newest_matching_closure() {
+        let resolved = resolve_in(registry(), "app", "^1").await.unwrap();
+        let versions: Vec<String> = resolved
+            .iter()
+            .map(|agent| format!("{}@{}", agent.name, agent.version))
+            .collect();
+        assert_eq!(versions, ["app@1.0.0", "lib@1.4.0", "util@0.2.5"]);
+        assert_eq!(resolved[1].checksum, "sha256:lib-1.4.0");
+    }
+
+    #[tokio::test]
+    async fn test_reports_missing_and_conflicting_dependencies() {
+        let error = resolve_in(registry(), "app", "latest").await.unwrap_err();
+        assert!(
+            matches!(&error, ResolveError::NotFound { name, required_by: Some(by), .. } if name == "util" && by == "lib@2.0.0"),
+            "{error}"
+        );
+
+        let conflicting = vec![
+            candidate("tool", "1.0.0", &[("lib", "1"), ("util", "^0.2.5")]),
+            candidate("lib", "1.0.0", &[("util", "=0.2.1")]),
+            candidate("util", "0.2.1", &[]),
+            candidate("util", "0.2.5", &[]),
+        ];
+        let error = resolve_in(conflicting, "tool", "*").await.unwrap_err();
+        assert!(matches!(error, ResolveError::Conflict { .. }), "{error}");
+    }
+
+    const AGENTS: [&str; 4] = ["app", "lib", "util", "fmt"];
+
+    /// Release and pre-release versions, close enough together to collide
+    /// with the requirements below
+    fn version() -> impl Strategy<Value = String> {
+        (
+            0u64..3,
+            0u64..3,
+            0u64..3,
+            prop::option::weighted(0.3, prop::sample::select(vec!["alpha.1", "beta.2", "rc.1"])),
+        )
+            .prop_map(|(major, minor, patch, pre)| match pre {
+                Some(pre) => format!("{major}.{minor}.{patch}-{pre}"),
+                None => format!("{major}.{minor}.{patch}"),
+            })
+    }
+
+    fn requirement() -> impl Strategy<Value = String> {
+        prop::sample::select(vec![
+            "",
+            "latest",
+            "*",
+            "^1",
+            "^0.2",
+            "~1.1",
+            ">=1.0.0, <2.0.0",
+            "=2.1.0",
+            "^1.0.0-beta.2",
+            ">=0.0.0-alpha.1",
+        ])
+        .prop_map(String::from)
+    }
+
+    /// Up to a dozen versions of a few agents depending on each other,
+    /// cycles included
+    fn registries() -> impl Strategy<Value = Vec<Candidate>> {
+        let dependencies =
+            prop::collection::btree_map(prop::sample::select(AGENTS.to_vec()), requirement(), 0..3);
+        prop::collection::vec(
+            (
+                prop::sample::select(AGENTS.to_vec()),
+                version(),
+                dependencies,
+            ),
+            1..12,
+        )
+        .prop_map(|versions| {
+            let mut registry: Vec<Candidate> = Vec::new();
+            for (name, version, dependencies) in versions {
+                if registry
+                    .iter()
+                    .any(|c| c.agent_name == name && c.version == version)
+                {
+                    continue;
+                }
+                let dependencies: Vec<(&str, &str)> = dependencies
+                    .iter()
+                    .filter(|(dependency, _)| **dependency != name)
+                    .map(|(dependency, requirement)| (*dependency, requirement.as_str()))
+                    .collect();
+                registry.push(candidate(name, &version, &dependencies));
+            }
+            registry
+        })
+    }
+
+    fn matches(requirement: &str, version: &str) -> bool {
+        let requirement = parse_requirement(requirement).unwrap();
+        requirement.matches(&Version::parse(version).unwrap())
+    }
+
+    proptest! {
+        #[test]
+        fn resolutions_satisfy_every_requirement(
+            registry in registries(),
+            root_requirement in requirement(),
+        ) {
+            let result =
+                futures::executor::block_on(resolve_in(registry.clone(), "app", &root_requirement));
+            let find = |name: &str, version: &str| {
+                registry
+                    .iter()
+                    .find(|c| c.agent_name == name && c.version == version)
+                    .unwrap()
+            };
+
+            match result {
+                Ok(resolved) => {
+                    // One version per agent, sorted by name
+                    prop_assert!(resolved.windows(2).all(|pair| pair[0].name < pair[1].name));
+
+                    // The root is the newest version its requirement allows
+                    let root = resolved.iter().find(|agent| agent.name == "app").unwrap();
+                    prop_assert!(matches(&root_requirement, &root.version));
+                    let newest = registry
+                        .iter()
+                        .filter(|c| c.agent_name == "app" && matches(&root_requirement, &c.version))
+                        .map(|c| Version::parse(&c.version).unwrap())
+                        .max()
+                        .unwrap();
+                    prop_assert_eq!(Version::parse(&root.version).unwrap(), newest);
+
+                    // Every dependency of every resolved agent is resolved to a
+                    // version it accepts
+                    for agent in &resolved {
+                        let chosen = find(&agent.name, &agent.version);
+                        prop_assert_eq!(&agent.checksum, &chosen.checksum);
+                        for (dependency, requirement) in &chosen.dependencies {
+                            let resolved_dependency =
+                                resolved.iter().find(|agent| &agent.name == dependency);
+                            prop_assert!(
+                                resolved_dependency
+                                    .is_some_and(|dep| matches(requirement, &dep.version)),
+                                "{}@{} needs {} {}",
+                                agent.name,
+                                agent.version,
+                                dependency,
+                                requirement
+                            );
+                        }
+                    }
+                }
+                Err(ResolveError::NotFound { name, requirement, .. }) => {
+                    prop_assert!(!registry
+                        .iter()
+                        .any(|c| c.agent_name == name && matches(&requirement, &c.version)));
+                }
+                Err(ResolveError::Conflict { version, requirement, .. }) => {
+                    prop_assert!(!matches(&requirement, &version));
+                }
+                Err(error) => prop_assert!(false, "unexpected error: {}", error),
+            }
+        }
+    }
+
+    #[test]
+    fn test_signed_manifests_verify_until_changed() {
+        let signer = ResolutionSigner::from_seed(&[7; 32]).unwrap();
+        let manifest = ResolutionManifest {
+            root: "app".to_string(),
+            requirement: "^1".to_string(),
+            agents: vec![ResolvedAgent {
+                name: "app".to_string(),
+                version: "1.0.0".to_string(),
+                checksum: "sha256:abc".to_string(),
+            }],
+            issued_at: Utc::now(),
+        };
+        let signed = signer.sign(&manifest).unwrap();
+        assert_eq!(signed.verify().unwrap(), manifest);
+
+        let mut tampered = signed.clone();
+        tampered.manifest = tampered.manifest.replace("1.0.0", "1.0.1");
+        assert!(tampered.verify().is_err());
+    }
+
+    #[test]
+    fn test_validate_dependencies() {
+        let dependencies: Dependencies = [
+            ("helper".to_string(), "^1.2".to_string()),
+            ("bad name".to_string(), "1".to_string()),
+            ("other".to_string(), "not-a-version".to_string()),
+            ("self".to_string(), "*".to_string()),
+        ]
+        .into();
+        let fields: Vec<String> = validate_dependencies("self", &dependencies)
+            .into_iter()
+            .map(|(field, _)| field)
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                "dependencies.bad name",
+                "dependencies.other",
+                "dependencies.self"
+            ]
+        );
+
+        let definition = json!({ "metadata": { "dependencies": { "helper": "^1" } } });
+        assert_eq!(
+            dependencies_from_definition(&definition).unwrap()["helper"],
+            "^1"
+        );
+        assert!(
+            dependencies_from_definition(&json!({ "metadata": { "dependencies": ["a"] } }))
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,434 @@
+//! Demo data for local development
+//!
+//! `carp-api seed` fills a freshly migrated database with a couple of users,
+//! their API keys, an organization and a handful of agents with versions,
+//! packages, stars and download history, so the site, the API and the CLI all
+//! have something to work with without hand-crafting Supabase state.
+//!
+//! Seeding replaces any earlier demo data: the demo users are deleted first and
+//! everything they own goes with them, so it can be re-run at will.
+
+use anyhow::{anyhow, Context, Result};
+use bytes::Bytes;
+use chrono::{Duration, Utc};
+use serde_json::json;
+use sqlx::{Connection, PgConnection, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::archive::{build_markdown_package, package_checksum};
+use crate::auth::hash_api_key;
+
+/// Scopes granted to the demo API keys
+pub const DEMO_SCOPES: &[&str] = &["read", "write", "upload", "publish"];
+
+/// A demo account and the API key it can be used with
+pub struct DemoUser {
+    pub id: &'static str,
+    pub username: &'static str,
+    pub display_name: &'static str,
+    pub api_key: &'static str,
+}
+
+pub const DEMO_USERS: &[DemoUser] = &[
+    DemoUser {
+        id: "00000000-0000-4000-8000-00000000a11c",
+        username: "demo-alice",
+        display_name: "Alice (demo)",
+        api_key: "carp_demoalce_localdev_00000001",
+    },
+    DemoUser {
+        id: "00000000-0000-4000-8000-000000000b0b",
+        username: "demo-bob",
+        display_name: "Bob (demo)",
+        api_key: "carp_demobob0_localdev_00000002",
+    },
+];
+
+/// Organization on the team plan with the first demo user as its owner
+pub const DEMO_ORG: &str = "demo-org";
+
+struct DemoAgent {
+    owner: usize,
+    name: &'static str,
+    description: &'static str,
+    tags: &'static [&'static str],
+    is_public: bool,
+    /// Downloads recorded against each version, oldest version first
+    downloads: &'static [u32],
+}
+
+const DEMO_AGENTS: &[DemoAgent] = &[
+    DemoAgent {
+        owner: 0,
+        name: "code-reviewer",
+        description: "Reviews diffs for bugs, style issues and missing tests",
+        tags: &["review", "quality"],
+        is_public: true,
+        downloads: &[12, 30],
+    },
+    DemoAgent {
+        owner: 0,
+        name: "test-writer",
+        description: "Writes unit tests for the code you point it at",
+        tags: &["testing", "quality"],
+        is_public: true,
+        downloads: &[4, 9],
+    },
+    DemoAgent {
+        owner: 0,
+        name: "release-notes",
+        description: "Drafts release notes from the commits since the last tag",
+        tags: &["docs", "release"],
+        is_public: false,
+        downloads: &[1, 2],
+    },
+    DemoAgent {
+        owner: 1,
+        name: "sql-optimizer",
+        description: "Suggests indexes and rewrites for slow SQL queries",
+        tags: &["database", "performance"],
+        is_public: true,
+        downloads: &[7, 15],
+    },
+    DemoAgent {
+        owner: 1,
+        name: "security-auditor",
+        description: "Audits code for common security vulnerabilities",
+        tags: &["security", "review"],
+        is_public: true,
+        downloads: &[20, 3],
+    },
+];
+
+const DEMO_VERSIONS: &[&str] = &["1.0.0", "1.1.0"];
+
+/// A package to place in the `agent-packages` bucket
+pub struct SeededPackage {
+    pub file_path: String,
+    pub bytes: Bytes,
+}
+
+/// Replace the demo data, returning the packages the rows refer to
+pub async fn run(conn: &mut PgConnection) -> Result<Vec<SeededPackage>> {
+    let mut tx = conn.begin().await?;
+
+    clear(&mut tx).await?;
+
+    for user in DEMO_USERS {
+        insert_user(&mut tx, user).await?;
+    }
+    insert_org(&mut tx).await?;
+
+    let mut packages = Vec::new();
+    for agent in DEMO_AGENTS {
+        packages.extend(insert_agent(&mut tx, agent).await?);
+    }
+
+    tx.commit().await?;
+    Ok(packages)
+}
+
+async fn clear(tx: &mut Transaction<'_, Postgres>) -> Result<()> {
+    let ids = user_ids()?;
+
+    sqlx::query("DELETE FROM public.organizations WHERE slug = $1")
+        .bind(DEMO_ORG)
+        .execute(&mut **tx)
+        .await
+        .context("Failed to remove the demo organization")?;
+
+    // Everything else the demo users own cascades from auth.users
+    sqlx::query("DELETE FROM auth.users WHERE id = ANY($1)")
+        .bind(&ids)
+        .execute(&mut **tx)
+        .await
+        .context("Failed to remove earlier demo data")?;
+
+    Ok(())
+}
+
+async fn insert_user(tx: &mut Transaction<'_, Postgres>, user: &DemoUser) -> Result<()> {
+    let id = Uuid::parse_str(user.id)?;
+
+    // The profile is created from the metadata by the `handle_new_user` trigger
+    sqlx::query(
+        "INSERT INTO auth.users (id, aud, role, email, raw_user_meta_data, raw_app_meta_data)
+         VALUES ($1, 'authenticated', 'authenticated', $2, $3, $4)",
+    )
+    .bind(id)
+    .bind(format!("{}@example.com", user.username))
+    .bind(json!({
+        "user_name": user.username,
+        "full_name": user.display_name,
+    }))
+    .bind(json!({ "provider": "github", "providers": ["github"] }))
+    .execute(&mut **tx)
+    .await
+    .with_context(|| format!("Failed to create demo user {}", user.username))?;
+
+    let prefix: String = user.api_key.chars().take(12).collect();
+    sqlx::query(
+        "INSERT INTO public.api_keys (user_id, name, key_hash, prefix, key_prefix, scopes)
+         VALUES ($1, 'Local development', $2, $3, $3, $4)",
+    )
+    .bind(id)
+    .bind(hash_api_key(user.api_key))
+    .bind(prefix)
+    .bind(DEMO_SCOPES)
+    .execute(&mut **tx)
+    .await
+    .with_context(|| format!("Failed to create an API key for {}", user.username))?;
+
+    Ok(())
+}
+
+async fn insert_org(tx: &mut Transaction<'_, Postgres>) -> Result<()> {
+    let org_id: Uuid = sqlx::query_scalar(
+        "INSERT INTO public.organizations (slug, display_name, plan)
+         VALUES ($1, 'Demo Org', 'team')
+         RETURNING id",
+    )
+    .bind(DEMO_ORG)
+    .fetch_one(&mut **tx)
+    .await
+    .context("Failed to create the demo organization")?;
+
+    sqlx::query(
+        "INSERT INTO public.organization_members (org_id, user_id, role)
+         VALUES ($1, $2, 'owner')",
+    )
+    .bind(org_id)
+    .bind(Uuid::parse_str(DEMO_USERS[0].id)?)
+    .execute(&mut **tx)
+    .await
+    .context("Failed to add the demo organization's owner")?;
+
+    Ok(())
+}
+
+async fn insert_agent(
+    tx: &mut Transaction<'_, Postgres>,
+    agent: &DemoAgent,
+) -> Result<Vec<SeededPackage>> {
+    let owner = &DEMO_USERS[agent.owner];
+    let owner_id = Uuid::parse_str(owner.id)?;
+    let latest = DEMO_VERSIONS[DEMO_VERSIONS.len() - 1];
+    let now = Utc::now();
+    let created_at = now - Duration::days(30);
+    let content = agent_markdown(agent, latest);
+    let tags: Vec<&str> = agent.tags.to_vec();
+
+    let agent_id: Uuid = sqlx::query_scalar(
+        "INSERT INTO public.agents (user_id, name, description, definition, tags, keywords,
+                                    is_public, current_version, author_name, license, readme,
+                                    created_at)
+         VALUES ($1, $2, $3, $4, $5, $5, $6, $7, $8, 'MIT', $9, $10)
+         RETURNING id",
+    )
+    .bind(owner_id)
+    .bind(agent.name)
+    .bind(agent.description)
+    .bind(definition(agent, latest))
+    .bind(&tags)
+    .bind(agent.is_public)
+    .bind(latest)
+    .bind(owner.username)
+    .bind(&content)
+    .bind(created_at)
+    .fetch_one(&mut **tx)
+    .await
+    .with_context(|| format!("Failed to create demo agent {}", agent.name))?;
+
+    let mut packages = Vec::new();
+    let mut latest_version_id = None;
+    for (index, version) in DEMO_VERSIONS.iter().enumerate() {
+        // Space the versions out so "latest" and trending have an order to go by
+        let released_at = created_at + Duration::days(14 * index as i64);
+        let package = build_markdown_package(agent.name, &agent_markdown(agent, version))?;
+        let checksum = package_checksum(&package);
+        let size = package.len() as i64;
+        let file_path = format!(
+            "{owner_id}/{}/{version}/{}-{version}.zip",
+            agent.name, agent.name
+        );
+        let downloads = agent.downloads.get(index).copied().unwrap_or(0);
+
+        let version_id: Uuid = sqlx::query_scalar(
+            "INSERT INTO public.agent_versions (agent_id, version, description, definition,
+                                                file_path, content_type, package_size, checksum,
+                                                download_count, created_at)
+             VALUES ($1, $2, $3, $4, $5, 'application/zip', $6, $7, $8, $9)
+             RETURNING id",
+        )
+        .bind(agent_id)
+        .bind(*version)
+        .bind(agent.description)
+        .bind(definition(agent, version))
+        .bind(&file_path)
+        .bind(size)
+        .bind(&checksum)
+        .bind(downloads as i64)
+        .bind(released_at)
+        .fetch_one(&mut **tx)
+        .await
+        .with_context(|| format!("Failed to create {}@{version}", agent.name))?;
+
+        let package_id: Uuid = sqlx::query_scalar(
+            "INSERT INTO public.agent_packages (version_id, file_name, file_path, content_type,
+                                                file_size, checksum, upload_completed, created_at)
+             VALUES ($1, $2, $3, 'application/zip', $4, $5, true, $6)
+             RETURNING id",
+        )
+        .bind(version_id)
+        .bind(format!("{}-{version}.zip", agent.name))
+        .bind(&file_path)
+        .bind(size)
+        .bind(&checksum)
+        .bind(released_at)
+        .fetch_one(&mut **tx)
+        .await
+        .with_context(|| format!("Failed to create the package for {}@{version}", agent.name))?;
+
+        // Spread the downloads over the time since the release, alternating
+        // between anonymous callers and the other demo user
+        let downloader = Uuid::parse_str(DEMO_USERS[1 - agent.owner].id)?;
+        sqlx::query(
+            "INSERT INTO public.download_stats (agent_id, version_id, package_id, user_id,
+                                                ip_address, user_agent, downloaded_at, file_size)
+             SELECT $1, $2, $3,
+                    CASE WHEN n % 2 = 0 THEN $4 END,
+                    ('198.51.100.' || (n % 250 + 1))::inet,
+                    'carp-cli/seed',
+                    $5 + (($6 - $5) * n / ($7 + 1)),
+                    $8
+             FROM generate_series(1, $7) AS n",
+        )
+        .bind(agent_id)
+        .bind(version_id)
+        .bind(package_id)
+        .bind(downloader)
+        .bind(released_at)
+        .bind(now)
+        .bind(downloads as i32)
+        .bind(size)
+        .execute(&mut **tx)
+        .await
+        .with_context(|| format!("Failed to record downloads of {}@{version}", agent.name))?;
+
+        latest_version_id = Some(version_id);
+        packages.push(SeededPackage {
+            file_path,
+            bytes: package,
+        });
+    }
+
+    let total: i64 = agent.downloads.iter().map(|&n| n as i64).sum();
+    sqlx::query(
+        "UPDATE public.agents SET latest_version_id = $2, download_count = $3 WHERE id = $1",
+    )
+    .bind(agent_id)
+    .bind(latest_version_id)
+    .bind(total)
+    .execute(&mut **tx)
+    .await?;
+
+    // The other demo user stars every public agent
+    if agent.is_public {
+        sqlx::query("INSERT INTO public.agent_stars (user_id, agent_id) VALUES ($1, $2)")
+            .bind(Uuid::parse_str(DEMO_USERS[1 - agent.owner].id)?)
+            .bind(agent_id)
+            .execute(&mut **tx)
+            .await?;
+    }
+
+    Ok(packages)
+}
+
+/// Upload seeded packages to the `agent-packages` bucket through the storage
+/// API, replacing any already there
+pub async fn upload_packages(
+    supabase_url: &str,
+    supabase_key: &str,
+    packages: &[SeededPackage],
+) -> Result<()> {
+    let client = reqwest::Client::new();
+
+    for package in packages {
+        let response = client
+            .post(format!(
+                "{}/storage/v1/object/agent-packages/{}",
+                supabase_url.trim_end_matches('/'),
+                package.file_path
+            ))
+            .header("apikey", supabase_key)
+            .header("Authorization", format!("Bearer {supabase_key}"))
+            .header("Content-Type", "application/zip")
+            .header("x-upsert", "true")
+            .body(package.bytes.clone())
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            let error_text = response.text().await.unwrap_or_default();
+            return Err(anyhow!(
+                "Failed to upload {}: {}",
+                package.file_path,
+                error_text
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn user_ids() -> Result<Vec<Uuid>> {
+    DEMO_USERS
+        .iter()
+        .map(|user| Uuid::parse_str(user.id).map_err(Into::into))
+        .collect()
+}
+
+fn agent_markdown(agent: &DemoAgent, version: &str) -> String {
+    format!(
+        "---\nname: {}\ndescription: {}\nversion: {version}\ntags: [{}]\n---\n\n\
+         # {}\n\n{}.\n\nThis is demo data created by `carp-api seed`.\n",
+        agent.name,
+        agent.description,
+        agent.tags.join(", "),
+        agent.name,
+        agent.description,
+    )
+}
+
+/// Definition in the shape the upload endpoint stores
+fn definition(agent: &DemoAgent, version: &str) -> serde_json::Value {
+    json!({
+        "metadata": {
+            "name": agent.name,
+            "description": agent.description,
+            "version": version,
+            "tags": agent.tags,
+        },
+        "content": format!("# {}\n\n{}.", agent.name, agent.description),
+        "format": "markdown",
+        "frontmatter_type": "yaml"
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_demo_keys_look_like_api_keys() {
+        for user in DEMO_USERS {
+            assert_eq!(
+                crate::guess_token_type(user.api_key),
+                crate::TokenType::ApiKey,
+                "{} has a malformed demo key",
+                user.username
+            );
+            assert!(Uuid::parse_str(user.id).is_ok());
+        }
+    }
+}
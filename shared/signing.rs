@@ -0,0 +1,115 @@
+//! Sigstore bundle inspection for keyless-signed agent uploads
+//!
+//! The API does not re-verify certificate chains (clients verify against the
+//! Sigstore trust root on pull); it checks that a bundle actually covers the
+//! uploaded content and extracts the transparency-log entry to record.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+/// Transparency-log entry recorded for a signed agent
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TransparencyLogEntry {
+    pub log_index: i64,
+    pub log_id: Option<String>,
+    pub integrated_time: Option<i64>,
+}
+
+/// Check that a Sigstore bundle signs `content` and return its transparency-log entry
+pub fn inspect_signature_bundle(
+    bundle: &Value,
+    content: &str,
+) -> Result<TransparencyLogEntry, String> {
+    let digest = bundle
+        .pointer("/messageSignature/messageDigest/digest")
+        .and_then(Value::as_str)
+        .ok_or_else(|| "Signature bundle is missing a message digest".to_string())?;
+
+    let digest = STANDARD
+        .decode(digest)
+        .map_err(|e| format!("Signature bundle digest is not valid base64: {e}"))?;
+
+    if digest.as_slice() != Sha256::digest(content.as_bytes()).as_slice() {
+        return Err("Signature bundle does not match the uploaded content".to_string());
+    }
+
+    let entry = bundle
+        .pointer("/verificationMaterial/tlogEntries/0")
+        .ok_or_else(|| "Signature bundle has no transparency log entry".to_string())?;
+
+    let log_index = entry
+        .get("logIndex")
+        .and_then(json_int)
+        .ok_or_else(|| "Transparency log entry is missing logIndex".to_string())?;
+
+    Ok(TransparencyLogEntry {
+        log_index,
+        log_id: entry
+            .pointer("/logId/keyId")
+            .and_then(Value::as_str)
+            .map(str::to_string),
+        integrated_time: entry.get("integratedTime").and_then(json_int),
+    })
+}
+
+/// Protobuf JSON encodes 64-bit integers as strings
+fn json_int(value: &Value) -> Option<i64> {
+    value
+        .as_i64()
+        .or_else(|| value.as_str().and_then(|s| s.parse().ok()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn bundle_for(content: &str) -> Value {
+        json!({
+            "messageSignature": {
+                "messageDigest": {
+                    "algorithm": "SHA2_256",
+                    "digest": STANDARD.encode(Sha256::digest(content.as_bytes())),
+                },
+                "signature": "c2ln",
+            },
+            "verificationMaterial": {
+                "tlogEntries": [{
+                    "logIndex": "123456",
+                    "logId": { "keyId": "a2V5" },
+                    "integratedTime": "1700000000",
+                }],
+            },
+        })
+    }
+
+    #[test]
+    fn test_inspect_signature_bundle() {
+        let entry = inspect_signature_bundle(&bundle_for("content"), "content").unwrap();
+
+        assert_eq!(
+            entry,
+            TransparencyLogEntry {
+                log_index: 123456,
+                log_id: Some("a2V5".to_string()),
+                integrated_time: Some(1700000000),
+            }
+        );
+    }
+
+    #[test]
+    fn test_inspect_signature_bundle_rejects_other_content() {
+        let result = inspect_signature_bundle(&bundle_for("content"), "tampered");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_inspect_signature_bundle_requires_tlog_entry() {
+        let mut bundle = bundle_for("content");
+        bundle["verificationMaterial"]["tlogEntries"] = json!([]);
+
+        assert!(inspect_signature_bundle(&bundle, "content").is_err());
+    }
+}
@@ -0,0 +1,432 @@
+//! Isolated registries in one deployment
+//!
+//! Besides the registry every deployment serves, it can host tenants, e.g.
+//! one registry per business unit, listed in `TENANTS`, a JSON array such as:
+//!
+//! ```json
+//! [{
+//!   "id": "acme",
+//!   "hosts": ["agents.acme.com"],
+//!   "jwt_secret": "...",
+//!   "rate_limits": { "anonymous": 30, "authenticated": 1200 }
+//! }]
+//! ```
+//!
+//! A request is for a tenant when it arrives on one of the tenant's `hosts`,
+//! or under `/t/<id>/`, which `vercel.json` rewrites to the usual path with a
+//! `tenant` query parameter. Anything else is for the default tenant, which
+//! is the registry as it was before tenants, so deployments without `TENANTS`
+//! are unchanged.
+//!
+//! Each function's `main` runs its handler in [`scope`], and everything the
+//! handler does then acts within the request's tenant:
+//!
+//! - agents, their change feed and API keys carry a `tenant_id`. Requests to
+//!   the database send [`TENANT_HEADER`], which `public.current_tenant()`
+//!   reads, so rows are created in the caller's tenant and the database
+//!   functions only find the tenant's own; direct reads of agents filter on
+//!   it with [`Tenant::apply`]
+//! - packages are stored under `tenants/<id>/` in the bucket
+//! - a tenant with its own `jwt_secret` is its own auth realm: sessions
+//!   signed for another tenant aren't accepted, and API keys only
+//!   authenticate in the tenant they were created in
+//! - requests are counted against limits per tenant, and `rate_limits`
+//!   overrides the `RATE_LIMIT_*` limits for it
+
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashSet;
+use std::env;
+use std::future::Future;
+use vercel_runtime::{Body, Error, Request, Response};
+
+use crate::auth::ApiError;
+use crate::RateLimitTier;
+
+/// Id of the registry requests are for when they name no tenant
+pub const DEFAULT_TENANT: &str = "default";
+
+/// Header telling the database which tenant a request acts in
+pub const TENANT_HEADER: &str = "x-carp-tenant";
+
+/// Query parameter `vercel.json` sets from `/t/<id>/` paths
+pub const TENANT_PARAM: &str = "tenant";
+
+/// Bucket folder holding tenants' packages
+const STORAGE_PREFIX: &str = "tenants";
+
+tokio::task_local! {
+    static CURRENT: Tenant;
+}
+
+/// Requests per minute overriding `RATE_LIMIT_*` for a tenant
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TenantRateLimits {
+    pub anonymous: Option<u32>,
+    pub authenticated: Option<u32>,
+    pub team: Option<u32>,
+    pub enterprise: Option<u32>,
+}
+
+/// One registry in `TENANTS`, or the default one
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Tenant {
+    /// Name in paths, storage and the database, e.g. `acme`
+    pub id: String,
+    /// Hosts serving only this tenant
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    /// Secret sessions for this tenant are signed with, instead of
+    /// `SUPABASE_JWT_SECRET`
+    #[serde(default)]
+    pub jwt_secret: Option<String>,
+    #[serde(default)]
+    pub rate_limits: TenantRateLimits,
+}
+
+impl Default for Tenant {
+    fn default() -> Self {
+        Self {
+            id: DEFAULT_TENANT.to_string(),
+            hosts: Vec::new(),
+            jwt_secret: None,
+            rate_limits: TenantRateLimits::default(),
+        }
+    }
+}
+
+impl Tenant {
+    pub fn is_default(&self) -> bool {
+        self.id == DEFAULT_TENANT
+    }
+
+    /// Where a package at `path` is kept in the bucket
+    pub fn storage_path(&self, path: &str) -> String {
+        if self.is_default() {
+            path.to_string()
+        } else {
+            format!("{STORAGE_PREFIX}/{}/{path}", self.id)
+        }
+    }
+
+    /// Key requests from `identifier` are counted under
+    pub fn rate_limit_key(&self, identifier: &str) -> String {
+        if self.is_default() {
+            identifier.to_string()
+        } else {
+            format!("tenant:{}:{identifier}", self.id)
+        }
+    }
+
+    /// This tenant's limit for `tier`, if it overrides the deployment's
+    pub fn rate_limit(&self, tier: RateLimitTier) -> Option<u32> {
+        let limits = &self.rate_limits;
+        match tier {
+            RateLimitTier::Anonymous => limits.anonymous,
+            RateLimitTier::Authenticated => limits.authenticated,
+            RateLimitTier::Team => limits.team,
+            RateLimitTier::Enterprise => limits.enterprise,
+        }
+    }
+
+    /// Secret this tenant's sessions are signed with
+    pub fn jwt_secret(&self) -> String {
+        self.jwt_secret
+            .clone()
+            .unwrap_or_else(|| env::var("SUPABASE_JWT_SECRET").unwrap_or_default())
+    }
+
+    /// Restrict an agents query to this tenant's agents
+    pub fn apply(&self, query: postgrest::Builder) -> postgrest::Builder {
+        query.eq("tenant_id", &self.id)
+    }
+}
+
+/// Whether `id` can name a tenant in paths, storage and the database
+pub fn is_valid_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 63
+        && !id.starts_with('-')
+        && id
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+/// The tenants a deployment hosts besides the default one
+#[derive(Debug, Clone, Default)]
+pub struct Tenants {
+    pub tenants: Vec<Tenant>,
+}
+
+impl Tenants {
+    /// The tenants in `TENANTS`, if set
+    pub fn from_env() -> Result<Self, String> {
+        match env::var("TENANTS") {
+            Ok(json) if !json.trim().is_empty() => Self::parse(&json),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    pub fn parse(json: &str) -> Result<Self, String> {
+        let mut tenants: Vec<Tenant> =
+            serde_json::from_str(json).map_err(|e| format!("Invalid TENANTS: {e}"))?;
+
+        let mut ids = HashSet::new();
+        let mut hosts = HashSet::new();
+        for tenant in &mut tenants {
+            if !is_valid_id(&tenant.id) {
+                return Err(format!(
+                    "Invalid TENANTS: tenant id '{}' must be lowercase letters, digits and dashes",
+                    tenant.id
+                ));
+            }
+            if tenant.is_default() {
+                return Err(format!(
+                    "Invalid TENANTS: '{DEFAULT_TENANT}' is the registry outside any tenant"
+                ));
+            }
+            if !ids.insert(tenant.id.clone()) {
+                return Err(format!(
+                    "Invalid TENANTS: tenant '{}' is listed twice",
+                    tenant.id
+                ));
+            }
+            for host in &mut tenant.hosts {
+                *host = normalize_host(host);
+                if !hosts.insert(host.clone()) {
+                    return Err(format!(
+                        "Invalid TENANTS: host '{host}' belongs to more than one tenant"
+                    ));
+                }
+            }
+            if tenant
+                .jwt_secret
+                .as_deref()
+                .is_some_and(|secret| secret.trim().is_empty())
+            {
+                return Err(format!(
+                    "Invalid TENANTS: jwt_secret of '{}' is empty",
+                    tenant.id
+                ));
+            }
+        }
+
+        Ok(Self { tenants })
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Tenant> {
+        self.tenants.iter().find(|tenant| tenant.id == id)
+    }
+
+    /// The tenant serving `host`
+    pub fn for_host(&self, host: &str) -> Option<&Tenant> {
+        let host = normalize_host(host);
+        self.tenants
+            .iter()
+            .find(|tenant| tenant.hosts.contains(&host))
+    }
+
+    /// The tenant a request is for: the one named by its path, else the one
+    /// serving its host, else the default one. `None` for a path naming a
+    /// tenant this deployment doesn't host.
+    pub fn resolve(&self, req: &Request) -> Option<Tenant> {
+        if let Some(id) = path_tenant(req) {
+            if id == DEFAULT_TENANT {
+                return Some(Tenant::default());
+            }
+            return self.get(&id).cloned();
+        }
+
+        let host = req
+            .headers()
+            .get("x-forwarded-host")
+            .or_else(|| req.headers().get("host"))
+            .and_then(|value| value.to_str().ok());
+        Some(
+            host.and_then(|host| self.for_host(host))
+                .cloned()
+                .unwrap_or_default(),
+        )
+    }
+}
+
+/// The tenant `vercel.json` took from a `/t/<id>/` path
+fn path_tenant(req: &Request) -> Option<String> {
+    url::form_urlencoded::parse(req.uri().query()?.as_bytes())
+        .find(|(name, _)| name == TENANT_PARAM)
+        .map(|(_, id)| id.into_owned())
+}
+
+/// `Agents.ACME.com:443` reads `agents.acme.com`
+fn normalize_host(host: &str) -> String {
+    let host = host.trim().trim_end_matches('.');
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if port.chars().all(|c| c.is_ascii_digit()) => name,
+        _ => host,
+    };
+    host.to_ascii_lowercase()
+}
+
+/// The tenant the current request is for; the default one outside [`scope`]
+pub fn current() -> Tenant {
+    CURRENT
+        .try_with(|tenant| tenant.clone())
+        .unwrap_or_default()
+}
+
+/// Run `future` as a request for `tenant`
+pub async fn within<F: Future>(tenant: Tenant, future: F) -> F::Output {
+    CURRENT.scope(tenant, future).await
+}
+
+/// Answer a request with `handler`, within the tenant it's for
+///
+/// ```rust,ignore
+/// run(|req| respond(scope(req, handler))).await
+/// ```
+pub async fn scope<H, F>(req: Request, handler: H) -> Result<Response<Body>, Error>
+where
+    H: FnOnce(Request) -> F,
+    F: Future<Output = Result<Response<Body>, Error>>,
+{
+    let tenants = match Tenants::from_env() {
+        Ok(tenants) => tenants,
+        Err(e) => {
+            eprintln!("ERROR: {e}");
+            return error_response(500, "tenant_config", "Tenants are misconfigured", None);
+        }
+    };
+
+    match tenants.resolve(&req) {
+        Some(tenant) => within(tenant, handler(req)).await,
+        None => error_response(
+            404,
+            "unknown_tenant",
+            "No registry is hosted at this path",
+            path_tenant(&req).map(|id| json!({ "tenant": id })),
+        ),
+    }
+}
+
+fn error_response(
+    status: u16,
+    error: &str,
+    message: &str,
+    details: Option<serde_json::Value>,
+) -> Result<Response<Body>, Error> {
+    let error = ApiError {
+        error: error.to_string(),
+        message: message.to_string(),
+        details,
+    };
+    Ok(Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(serde_json::to_string(&error)?.into())?)
+}
+
+/// A PostgREST client at `url`, acting in the current tenant
+pub fn database(url: impl Into<String>) -> postgrest::Postgrest {
+    postgrest::Postgrest::new(url).insert_header(TENANT_HEADER, current().id)
+}
+
+/// A client for the database and storage, acting in the current tenant
+pub fn http_client() -> reqwest::Client {
+    let mut headers = reqwest::header::HeaderMap::new();
+    if let Ok(tenant) = current().id.parse() {
+        headers.insert(TENANT_HEADER, tenant);
+    }
+    reqwest::Client::builder()
+        .default_headers(headers)
+        .build()
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TENANTS: &str = r#"[
+        {"id": "acme", "hosts": ["Agents.Acme.com"], "rate_limits": {"authenticated": 1200}},
+        {"id": "globex", "hosts": ["carp.globex.dev"]}
+    ]"#;
+
+    fn request(uri: &str, host: Option<&str>) -> Request {
+        let mut req = Request::new(Body::Empty);
+        *req.uri_mut() = uri.parse().unwrap();
+        if let Some(host) = host {
+            req.headers_mut().insert("host", host.parse().unwrap());
+        }
+        req
+    }
+
+    #[test]
+    fn test_requests_resolve_by_path_then_host() {
+        let tenants = Tenants::parse(TENANTS).unwrap();
+        let resolve = |uri: &str, host: Option<&str>| {
+            tenants.resolve(&request(uri, host)).map(|tenant| tenant.id)
+        };
+
+        assert_eq!(
+            resolve("/api/v1/agents/search", Some("agents.acme.com:443")),
+            Some("acme".to_string())
+        );
+        assert_eq!(
+            resolve(
+                "/api/v1/agents/search?tenant=globex",
+                Some("agents.acme.com")
+            ),
+            Some("globex".to_string())
+        );
+        assert_eq!(
+            resolve("/api/v1/agents/search", Some("carp.refcell.org")),
+            Some(DEFAULT_TENANT.to_string())
+        );
+        assert_eq!(
+            resolve("/api/v1/agents/search", None),
+            Some(DEFAULT_TENANT.to_string())
+        );
+        assert_eq!(resolve("/api/v1/agents/search?tenant=initech", None), None);
+    }
+
+    #[test]
+    fn test_tenants_are_checked() {
+        assert!(Tenants::parse(r#"[{"id": "Acme"}]"#).is_err());
+        assert!(Tenants::parse(r#"[{"id": "default"}]"#).is_err());
+        assert!(Tenants::parse(r#"[{"id": "acme"}, {"id": "acme"}]"#).is_err());
+        assert!(Tenants::parse(
+            r#"[{"id": "acme", "hosts": ["a.dev"]}, {"id": "globex", "hosts": ["A.dev"]}]"#
+        )
+        .is_err());
+        assert!(Tenants::parse(r#"[{"id": "acme", "jwt_secret": " "}]"#).is_err());
+        assert!(Tenants::parse(r#"[{"id": "acme", "quota": 10}]"#).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_tenants_keep_their_own_storage_and_limits() {
+        let tenants = Tenants::parse(TENANTS).unwrap();
+        let acme = tenants.get("acme").unwrap().clone();
+
+        assert_eq!(current().id, DEFAULT_TENANT);
+        assert_eq!(
+            current().storage_path("octocat/code-reviewer/1.0.0.zip"),
+            "octocat/code-reviewer/1.0.0.zip"
+        );
+
+        within(acme, async {
+            let tenant = current();
+            assert_eq!(
+                tenant.storage_path("octocat/code-reviewer/1.0.0.zip"),
+                "tenants/acme/octocat/code-reviewer/1.0.0.zip"
+            );
+            assert_eq!(
+                tenant.rate_limit_key("ip:203.0.113.7"),
+                "tenant:acme:ip:203.0.113.7"
+            );
+            assert_eq!(RateLimitTier::Authenticated.limit(), 1200);
+        })
+        .await;
+    }
+}
@@ -0,0 +1,300 @@
+//! Signed download URLs for agent packages
+//!
+//! By default packages are served from Supabase storage. Deployments that
+//! front the bucket with a CDN can sign URLs for CloudFront or Fastly
+//! instead; the download handler still records every download either way.
+//!
+//! Configuration comes from the environment:
+//!
+//! - `DOWNLOAD_URL_TTL_SECS`: lifetime of issued URLs (default 3600)
+//! - `DOWNLOAD_URL_SIGNER`: `supabase` (default), `cloudfront` or `fastly`
+//! - CloudFront: `CLOUDFRONT_DOMAIN`, `CLOUDFRONT_KEY_PAIR_ID`,
+//!   `CLOUDFRONT_PRIVATE_KEY` (PEM)
+//! - Fastly: `FASTLY_DOMAIN`, `FASTLY_TOKEN_SECRET`
+
+use anyhow::{anyhow, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use hmac::{Hmac, Mac};
+use rsa::pkcs1::DecodeRsaPrivateKey;
+use rsa::pkcs1v15::SigningKey;
+use rsa::pkcs8::DecodePrivateKey;
+use rsa::signature::{SignatureEncoding, Signer};
+use rsa::RsaPrivateKey;
+use serde::Deserialize;
+use serde_json::json;
+use sha2::Sha256;
+use std::env;
+use std::future::Future;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Lifetime of a download URL when none is configured
+pub const DEFAULT_URL_TTL: Duration = Duration::from_secs(3600);
+
+/// Bounds on the configured lifetime
+const MIN_URL_TTL: Duration = Duration::from_secs(60);
+const MAX_URL_TTL: Duration = Duration::from_secs(7 * 24 * 3600);
+
+/// Issues time-limited URLs for objects in the `agent-packages` bucket
+pub trait UrlSigner {
+    /// Sign a URL for `file_path` that stops working after `ttl`
+    fn sign(&self, file_path: &str, ttl: Duration) -> impl Future<Output = Result<String>> + Send;
+}
+
+/// Signs URLs with Supabase storage's own signing endpoint
+pub struct SupabaseSigner {
+    client: reqwest::Client,
+    supabase_url: String,
+    supabase_key: String,
+}
+
+/// Signs CloudFront URLs with a canned policy
+pub struct CloudFrontSigner {
+    domain: String,
+    key_pair_id: String,
+    signing_key: SigningKey<sha1::Sha1>,
+}
+
+/// Signs Fastly URLs with an HMAC token validated at the edge.
+///
+/// The token is `{expires}_{hex(HMAC-SHA256(secret, "{path}{expires}"))}`,
+/// passed as the `token` query parameter.
+pub struct FastlySigner {
+    domain: String,
+    secret: Vec<u8>,
+}
+
+/// The signer selected by `DOWNLOAD_URL_SIGNER`
+pub enum DownloadSigner {
+    Supabase(SupabaseSigner),
+    CloudFront(Box<CloudFrontSigner>),
+    Fastly(FastlySigner),
+}
+
+#[derive(Debug, Deserialize)]
+struct SignedUrlResponse {
+    #[serde(rename = "signedURL")]
+    signed_url: String,
+}
+
+impl DownloadSigner {
+    /// Build the signer selected by `DOWNLOAD_URL_SIGNER`, failing if its
+    /// settings are missing rather than silently serving from storage
+    pub fn from_env(
+        client: reqwest::Client,
+        supabase_url: &str,
+        supabase_key: &str,
+    ) -> Result<Self> {
+        let signer = env::var("DOWNLOAD_URL_SIGNER").unwrap_or_default();
+
+        match signer.trim().to_ascii_lowercase().as_str() {
+            "" | "supabase" => Ok(DownloadSigner::Supabase(SupabaseSigner {
+                client,
+                supabase_url: supabase_url.to_string(),
+                supabase_key: supabase_key.to_string(),
+            })),
+            "cloudfront" => Ok(DownloadSigner::CloudFront(Box::new(CloudFrontSigner::new(
+                required_env("CLOUDFRONT_DOMAIN")?,
+                required_env("CLOUDFRONT_KEY_PAIR_ID")?,
+                &required_env("CLOUDFRONT_PRIVATE_KEY")?,
+            )?))),
+            "fastly" => Ok(DownloadSigner::Fastly(FastlySigner::new(
+                required_env("FASTLY_DOMAIN")?,
+                required_env("FASTLY_TOKEN_SECRET")?.into_bytes(),
+            ))),
+            other => Err(anyhow!(
+                "Unknown DOWNLOAD_URL_SIGNER '{other}'. Use supabase, cloudfront or fastly"
+            )),
+        }
+    }
+}
+
+impl UrlSigner for DownloadSigner {
+    async fn sign(&self, file_path: &str, ttl: Duration) -> Result<String> {
+        match self {
+            DownloadSigner::Supabase(signer) => signer.sign(file_path, ttl).await,
+            DownloadSigner::CloudFront(signer) => signer.sign(file_path, ttl).await,
+            DownloadSigner::Fastly(signer) => signer.sign(file_path, ttl).await,
+        }
+    }
+}
+
+impl UrlSigner for SupabaseSigner {
+    async fn sign(&self, file_path: &str, ttl: Duration) -> Result<String> {
+        let url = format!(
+            "{}/storage/v1/object/sign/agent-packages/{}",
+            self.supabase_url, file_path
+        );
+
+        let response = self
+            .client
+            .post(&url)
+            .header("apikey", &self.supabase_key)
+            .header("Authorization", format!("Bearer {}", self.supabase_key))
+            .header("Content-Type", "application/json")
+            .json(&json!({ "expiresIn": ttl.as_secs() }))
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            let error_text = response.text().await.unwrap_or_default();
+            return Err(anyhow!("Failed to generate signed URL: {}", error_text));
+        }
+
+        let signed_response: SignedUrlResponse = response.json().await?;
+        Ok(format!(
+            "{}{}",
+            self.supabase_url, signed_response.signed_url
+        ))
+    }
+}
+
+impl CloudFrontSigner {
+    pub fn new(domain: String, key_pair_id: String, private_key_pem: &str) -> Result<Self> {
+        let private_key = RsaPrivateKey::from_pkcs1_pem(private_key_pem)
+            .or_else(|_| RsaPrivateKey::from_pkcs8_pem(private_key_pem))
+            .context("CLOUDFRONT_PRIVATE_KEY is not a PEM-encoded RSA private key")?;
+
+        Ok(Self {
+            domain,
+            key_pair_id,
+            signing_key: SigningKey::new(private_key),
+        })
+    }
+
+    fn sign_at(&self, file_path: &str, expires: u64) -> String {
+        let resource = object_url(&self.domain, file_path);
+        let policy = canned_policy(&resource, expires);
+        let signature = self.signing_key.sign(policy.as_bytes()).to_vec();
+
+        format!(
+            "{resource}?Expires={expires}&Signature={}&Key-Pair-Id={}",
+            cloudfront_base64(&signature),
+            self.key_pair_id
+        )
+    }
+}
+
+impl UrlSigner for CloudFrontSigner {
+    async fn sign(&self, file_path: &str, ttl: Duration) -> Result<String> {
+        Ok(self.sign_at(file_path, expires_at(ttl)?))
+    }
+}
+
+impl FastlySigner {
+    pub fn new(domain: String, secret: Vec<u8>) -> Self {
+        Self { domain, secret }
+    }
+
+    fn sign_at(&self, file_path: &str, expires: u64) -> String {
+        let resource = object_url(&self.domain, file_path);
+        let path = format!("/{}", file_path.trim_start_matches('/'));
+
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC accepts keys of any length");
+        mac.update(format!("{path}{expires}").as_bytes());
+        let digest: String = mac
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+
+        format!("{resource}?token={expires}_{digest}")
+    }
+}
+
+impl UrlSigner for FastlySigner {
+    async fn sign(&self, file_path: &str, ttl: Duration) -> Result<String> {
+        Ok(self.sign_at(file_path, expires_at(ttl)?))
+    }
+}
+
+/// Configured URL lifetime, clamped to sensible bounds
+pub fn url_ttl() -> Duration {
+    env::var("DOWNLOAD_URL_TTL_SECS")
+        .ok()
+        .and_then(|secs| secs.trim().parse().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_URL_TTL)
+        .clamp(MIN_URL_TTL, MAX_URL_TTL)
+}
+
+fn required_env(name: &str) -> Result<String> {
+    env::var(name)
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+        .ok_or_else(|| anyhow!("{name} environment variable not set"))
+}
+
+fn expires_at(ttl: Duration) -> Result<u64> {
+    Ok((SystemTime::now() + ttl)
+        .duration_since(UNIX_EPOCH)
+        .context("System clock is before the Unix epoch")?
+        .as_secs())
+}
+
+fn object_url(domain: &str, file_path: &str) -> String {
+    let domain = domain.trim_start_matches("https://").trim_end_matches('/');
+    format!("https://{domain}/{}", file_path.trim_start_matches('/'))
+}
+
+fn canned_policy(resource: &str, expires: u64) -> String {
+    json!({
+        "Statement": [{
+            "Resource": resource,
+            "Condition": { "DateLessThan": { "AWS:EpochTime": expires } }
+        }]
+    })
+    .to_string()
+}
+
+/// CloudFront's URL-safe base64 variant
+fn cloudfront_base64(data: &[u8]) -> String {
+    STANDARD
+        .encode(data)
+        .chars()
+        .map(|c| match c {
+            '+' => '-',
+            '=' => '_',
+            '/' => '~',
+            c => c,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fastly_token() {
+        let signer = FastlySigner::new("cdn.example.com".to_string(), b"secret".to_vec());
+        let url = signer.sign_at("user/agent/1.0.0.zip", 1_700_000_000);
+
+        assert!(url.starts_with("https://cdn.example.com/user/agent/1.0.0.zip?token=1700000000_"));
+        let digest = url.rsplit('_').next().unwrap();
+        assert_eq!(digest.len(), 64);
+        assert_ne!(
+            url,
+            signer.sign_at("user/agent/1.0.1.zip", 1_700_000_000),
+            "the token must cover the path"
+        );
+    }
+
+    #[test]
+    fn test_canned_policy_and_encoding() {
+        let policy = canned_policy("https://cdn.example.com/a.zip", 42);
+        assert!(policy.contains(r#""AWS:EpochTime":42"#));
+        assert!(policy.contains(r#""Resource":"https://cdn.example.com/a.zip""#));
+
+        assert_eq!(cloudfront_base64(&[0xfb, 0xff]), "-~8_");
+    }
+
+    #[test]
+    fn test_object_url() {
+        assert_eq!(
+            object_url("https://cdn.example.com/", "/a/b.zip"),
+            "https://cdn.example.com/a/b.zip"
+        );
+    }
+}
@@ -0,0 +1,240 @@
+//! Request validation for the API functions
+//!
+//! Request types implement [`Validate`], listing each problem as a
+//! `(field, message)` pair like the other validators in this crate.
+//! [`ValidatedJson`] and [`ValidatedQuery`] parse a request's body or query
+//! string and validate it, so a handler only ever sees requests that passed:
+//!
+//! ```rust,ignore
+//! use shared::validation::ValidatedJson;
+//!
+//! let ValidatedJson(request) = match ValidatedJson::<CreateApiKeyRequest>::from_request(&req) {
+//!     Ok(request) => request,
+//!     Err(response) => return Ok(response),
+//! };
+//! ```
+//!
+//! A body or query string that doesn't parse is answered 400 `bad_request`.
+//! One that parses but breaks a rule is answered 422 `validation_failed`,
+//! with every problem in `details.fields` as `{"field", "message"}`.
+
+use serde::de::DeserializeOwned;
+use serde_json::json;
+use vercel_runtime::{Body, Request, Response};
+
+use crate::auth::ApiError;
+
+/// A request that can check its own fields
+pub trait Validate {
+    /// Every problem with the request, as `(field, message)`
+    fn validate(&self) -> Vec<(String, String)>;
+}
+
+/// A JSON body that parsed and passed [`Validate`]
+#[derive(Debug)]
+pub struct ValidatedJson<T>(pub T);
+
+impl<T: DeserializeOwned + Validate> ValidatedJson<T> {
+    /// Parse and validate the request's body
+    #[allow(clippy::result_large_err)]
+    pub fn from_request(req: &Request) -> Result<Self, Response<Body>> {
+        Self::from_slice(req.body())
+    }
+
+    /// Parse and validate JSON that's only part of a body, such as one part
+    /// of a multipart form
+    #[allow(clippy::result_large_err)]
+    pub fn from_slice(json: &[u8]) -> Result<Self, Response<Body>> {
+        let value = serde_json::from_slice(json)
+            .map_err(|e| bad_request(format!("Invalid JSON in request body: {e}")))?;
+        checked(value).map(Self)
+    }
+}
+
+/// A query string that parsed and passed [`Validate`]
+#[derive(Debug)]
+pub struct ValidatedQuery<T>(pub T);
+
+impl<T: DeserializeOwned + Validate> ValidatedQuery<T> {
+    /// Parse and validate the request's query string
+    #[allow(clippy::result_large_err)]
+    pub fn from_request(req: &Request) -> Result<Self, Response<Body>> {
+        let query = req.uri().query().unwrap_or("");
+        let value = serde_urlencoded::from_str(query)
+            .map_err(|e| bad_request(format!("Invalid query string: {e}")))?;
+        checked(value).map(Self)
+    }
+}
+
+/// The 422 answer to a request with `errors`
+pub fn validation_failed(errors: &[(String, String)]) -> Response<Body> {
+    error_response(422, &validation_error(errors))
+}
+
+/// The `validation_failed` error for `errors`, listing each one in
+/// `details.fields`
+pub fn validation_error(errors: &[(String, String)]) -> ApiError {
+    let message = errors
+        .iter()
+        .map(|(field, message)| format!("{field}: {message}"))
+        .collect::<Vec<_>>()
+        .join("; ");
+    let fields: Vec<_> = errors
+        .iter()
+        .map(|(field, message)| json!({ "field": field, "message": message }))
+        .collect();
+    ApiError {
+        error: "validation_failed".to_string(),
+        message,
+        details: Some(json!({ "fields": fields })),
+    }
+}
+
+/// Problems with an agent name, as the upload endpoint checks them
+pub fn check_agent_name(field: &str, name: &str) -> Option<(String, String)> {
+    let message = if name.trim().is_empty() {
+        "Agent name cannot be empty"
+    } else if name.len() > 100 {
+        "Agent name cannot exceed 100 characters"
+    } else if !name
+        .chars()
+        .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+    {
+        "Agent name can only contain alphanumeric characters, hyphens, and underscores"
+    } else {
+        return None;
+    };
+    Some((field.to_string(), message.to_string()))
+}
+
+/// A problem with a required text field that's blank or longer than `max`
+/// characters
+pub fn check_text(field: &str, value: &str, max: usize) -> Option<(String, String)> {
+    let message = if value.trim().is_empty() {
+        format!("{field} cannot be empty")
+    } else if value.chars().count() > max {
+        format!("{field} cannot exceed {max} characters")
+    } else {
+        return None;
+    };
+    Some((field.to_string(), message))
+}
+
+#[allow(clippy::result_large_err)]
+fn checked<T: Validate>(value: T) -> Result<T, Response<Body>> {
+    let errors = value.validate();
+    if errors.is_empty() {
+        Ok(value)
+    } else {
+        Err(validation_failed(&errors))
+    }
+}
+
+fn bad_request(message: String) -> Response<Body> {
+    error_response(
+        400,
+        &ApiError {
+            error: "bad_request".to_string(),
+            message,
+            details: None,
+        },
+    )
+}
+
+fn error_response(status: u16, error: &ApiError) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(
+            serde_json::to_string(error)
+                .unwrap_or_else(|_| r#"{"error":"serialization_error","message":"Failed to serialize error response"}"#.to_string())
+                .into(),
+        )
+        .unwrap_or_else(|_| {
+            Response::builder()
+                .status(500)
+                .body("Internal server error".into())
+                .unwrap()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    struct Listing {
+        name: String,
+        limit: Option<usize>,
+    }
+
+    impl Validate for Listing {
+        fn validate(&self) -> Vec<(String, String)> {
+            let mut errors: Vec<_> = check_agent_name("name", &self.name).into_iter().collect();
+            if self.limit == Some(0) {
+                errors.push(("limit".to_string(), "limit must be at least 1".to_string()));
+            }
+            errors
+        }
+    }
+
+    fn request(uri: &str, body: &str) -> Request {
+        hyper::Request::builder()
+            .uri(uri)
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    fn error(response: Response<Body>) -> (u16, serde_json::Value) {
+        let status = response.status().as_u16();
+        let body = match response.body() {
+            Body::Text(text) => text.clone(),
+            Body::Binary(bytes) => String::from_utf8(bytes.clone()).unwrap(),
+            Body::Empty => String::new(),
+        };
+        (status, serde_json::from_str(&body).unwrap())
+    }
+
+    #[test]
+    fn test_validated_json() {
+        let ValidatedJson(listing) =
+            ValidatedJson::<Listing>::from_request(&request("/", r#"{"name": "helper"}"#)).unwrap();
+        assert_eq!(listing.name, "helper");
+
+        let (status, body) =
+            error(ValidatedJson::<Listing>::from_request(&request("/", "{")).unwrap_err());
+        assert_eq!((status, body["error"].as_str()), (400, Some("bad_request")));
+
+        let (status, body) = error(
+            ValidatedJson::<Listing>::from_request(&request(
+                "/",
+                r#"{"name": "no spaces", "limit": 0}"#,
+            ))
+            .unwrap_err(),
+        );
+        assert_eq!(status, 422);
+        assert_eq!(body["error"], "validation_failed");
+        assert_eq!(body["details"]["fields"][0]["field"], "name");
+        assert_eq!(body["details"]["fields"][1]["field"], "limit");
+    }
+
+    #[test]
+    fn test_validated_query() {
+        let ValidatedQuery(listing) =
+            ValidatedQuery::<Listing>::from_request(&request("/?name=helper&limit=5", "")).unwrap();
+        assert_eq!(listing.limit, Some(5));
+
+        let (status, _) = error(
+            ValidatedQuery::<Listing>::from_request(&request("/?name=helper&limit=many", ""))
+                .unwrap_err(),
+        );
+        assert_eq!(status, 400);
+        let (status, body) = error(
+            ValidatedQuery::<Listing>::from_request(&request("/?name=helper&limit=0", ""))
+                .unwrap_err(),
+        );
+        assert_eq!(status, 422);
+        assert_eq!(body["message"], "limit: limit must be at least 1");
+    }
+}
@@ -0,0 +1,129 @@
+//! Which agents a caller may see
+//!
+//! Private agents (`is_public = false`) are only visible to their owner and
+//! to users they have been shared with, directly or through an organization.
+//! The read endpoints query with the service role, which bypasses row level
+//! security, so they restrict rows with [`AgentVisibility`] themselves,
+//! which also keeps them to the request's tenant.
+
+use serde_json::json;
+use std::env;
+use uuid::Uuid;
+
+use crate::auth::AuthenticatedUser;
+use crate::tenant;
+
+/// Agents visible to a request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AgentVisibility {
+    /// Anonymous callers only see public agents
+    Public,
+    /// Public agents plus the caller's own private ones and those shared with them
+    PublicAndOwn { user_id: Uuid, shared: Vec<Uuid> },
+    /// Only the caller's own agents, private ones included
+    Own(Uuid),
+}
+
+impl AgentVisibility {
+    /// Visibility for a caller who may or may not be signed in, looking up
+    /// the private agents shared with them
+    pub async fn for_user(user: Option<&AuthenticatedUser>) -> Result<Self, String> {
+        match user {
+            Some(user) => Ok(AgentVisibility::PublicAndOwn {
+                user_id: user.user_id,
+                shared: shared_agent_ids(user.user_id).await?,
+            }),
+            None => Ok(AgentVisibility::Public),
+        }
+    }
+
+    /// Whether private agents can appear in the results
+    pub fn includes_private(&self) -> bool {
+        *self != AgentVisibility::Public
+    }
+
+    /// Restrict a query on `agents` to the visible rows of the current tenant
+    pub fn apply(&self, query: postgrest::Builder) -> postgrest::Builder {
+        let query = tenant::current().apply(query);
+        match self {
+            AgentVisibility::Public => query.eq("is_public", "true"),
+            // `and` so the filter composes with a search's own `or`
+            AgentVisibility::PublicAndOwn { user_id, shared } if shared.is_empty() => {
+                query.and(format!("or(is_public.eq.true,user_id.eq.{user_id})"))
+            }
+            AgentVisibility::PublicAndOwn { user_id, shared } => {
+                let shared: Vec<String> = shared.iter().map(Uuid::to_string).collect();
+                query.and(format!(
+                    "or(is_public.eq.true,user_id.eq.{user_id},id.in.({}))",
+                    shared.join(",")
+                ))
+            }
+            AgentVisibility::Own(user_id) => query.eq("user_id", user_id.to_string()),
+        }
+    }
+
+    /// `Cache-Control` for a response listing visible agents. Responses that
+    /// may include private agents must not be stored by shared caches.
+    pub fn cache_control(&self) -> &'static str {
+        if self.includes_private() {
+            "private, no-store"
+        } else {
+            "public, max-age=30"
+        }
+    }
+}
+
+/// Private agents shared with a user, directly or through an organization
+async fn shared_agent_ids(user_id: Uuid) -> Result<Vec<Uuid>, String> {
+    let supabase_url = env::var("SUPABASE_URL").unwrap_or_default();
+    let supabase_key = env::var("SUPABASE_SERVICE_ROLE_KEY").unwrap_or_default();
+
+    if supabase_url.is_empty() || supabase_key.is_empty() {
+        return Err(
+            "Database not configured - missing SUPABASE_URL or SUPABASE_SERVICE_ROLE_KEY"
+                .to_string(),
+        );
+    }
+
+    let response = tenant::http_client()
+        .post(format!("{supabase_url}/rest/v1/rpc/shared_agent_ids"))
+        .header("apikey", &supabase_key)
+        .header("Authorization", format!("Bearer {supabase_key}"))
+        .json(&json!({ "p_user_id": user_id }))
+        .send()
+        .await
+        .map_err(|e| format!("Shared agent lookup failed: {e}"))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        return Err(format!(
+            "Shared agent lookup failed with status {status}: {body}"
+        ));
+    }
+
+    response
+        .json()
+        .await
+        .map_err(|e| format!("Failed to parse shared agents: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_signed_in_callers_see_private_agents() {
+        let anonymous = AgentVisibility::for_user(None).await.unwrap();
+        assert_eq!(anonymous, AgentVisibility::Public);
+        assert!(!anonymous.includes_private());
+        assert_eq!(anonymous.cache_control(), "public, max-age=30");
+
+        let signed_in = AgentVisibility::PublicAndOwn {
+            user_id: Uuid::new_v4(),
+            shared: vec![Uuid::new_v4()],
+        };
+        assert!(signed_in.includes_private());
+        assert_eq!(signed_in.cache_control(), "private, no-store");
+    }
+}
@@ -128,8 +128,8 @@ export function useApiKeys() {
             throw new Error('Authentication failed. Please sign out and sign in again to refresh your session.');
           case 'configuration_error':
             throw new Error('Server configuration error. Please contact support.');
-          case 'invalid_scope':
-            throw new Error(`Invalid permission scope: ${error.apiError.message}`);
+          case 'validation_failed':
+            throw new Error(`Invalid API key settings: ${error.apiError.message}`);
           case 'database_error':
             throw new Error('Database error occurred. Please try again later.');
           default:
@@ -0,0 +1,19 @@
+-- Add Sigstore keyless signature tracking to agents
+-- The API stores the full bundle so clients can verify on pull, plus the
+-- transparency log entry it was recorded under for auditing
+
+ALTER TABLE public.agents
+  ADD COLUMN IF NOT EXISTS signature_bundle JSONB,
+  ADD COLUMN IF NOT EXISTS transparency_log_index BIGINT,
+  ADD COLUMN IF NOT EXISTS transparency_log_id TEXT,
+  ADD COLUMN IF NOT EXISTS signed_at TIMESTAMP WITH TIME ZONE;
+
+-- Look up agents by their transparency log entry
+CREATE INDEX IF NOT EXISTS idx_agents_transparency_log_index
+  ON public.agents (transparency_log_index)
+  WHERE transparency_log_index IS NOT NULL;
+
+COMMENT ON COLUMN public.agents.signature_bundle IS
+'Sigstore bundle covering the uploaded agent content, set for keyless-signed uploads.';
+COMMENT ON COLUMN public.agents.transparency_log_index IS
+'Rekor transparency log index of the signature recorded in signature_bundle.';
@@ -0,0 +1,9 @@
+-- Add publisher-supplied provenance to agents
+-- Records the git commit, repository URL, CI run ID and builder identity the
+-- CLI captured at publish time so consumers can trace an agent to its source
+
+ALTER TABLE public.agents
+  ADD COLUMN IF NOT EXISTS provenance JSONB;
+
+COMMENT ON COLUMN public.agents.provenance IS
+'Self-reported build provenance: {git_commit, repository_url, ci_run_id, builder}. Pair with signature_bundle for a verified builder identity.';
@@ -0,0 +1,18 @@
+-- Expose the applied migration version for readiness checks
+--
+-- /readyz compares this against the oldest schema the deployed API supports
+-- so traffic isn't routed to an instance whose database is behind.
+
+CREATE OR REPLACE FUNCTION public.schema_version()
+RETURNS TEXT
+LANGUAGE sql
+STABLE
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+  SELECT max(version)::TEXT FROM supabase_migrations.schema_migrations;
+$$;
+
+-- Only the API's service role needs this
+REVOKE EXECUTE ON FUNCTION public.schema_version FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION public.schema_version TO service_role;
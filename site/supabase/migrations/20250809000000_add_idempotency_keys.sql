@@ -0,0 +1,41 @@
+-- Idempotency keys for publish and upload
+--
+-- A request that sends an Idempotency-Key claims a row here before creating
+-- anything. Once it succeeds the response is stored, and retries with the
+-- same key and payload replay it instead of creating a duplicate version.
+-- Rows are only read back within 24 hours of being created.
+
+CREATE TABLE IF NOT EXISTS public.idempotency_keys (
+  user_id UUID NOT NULL REFERENCES auth.users(id) ON DELETE CASCADE,
+  key TEXT NOT NULL CHECK (char_length(key) BETWEEN 1 AND 255),
+  endpoint TEXT NOT NULL,
+  request_hash TEXT NOT NULL,
+  response_status INTEGER,
+  response_body TEXT,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  PRIMARY KEY (user_id, key)
+);
+
+CREATE INDEX IF NOT EXISTS idx_idempotency_keys_created_at
+  ON public.idempotency_keys(created_at);
+
+-- Only the API's service role touches this table
+ALTER TABLE public.idempotency_keys ENABLE ROW LEVEL SECURITY;
+
+-- Expired keys are never replayed, so they can be pruned at any time
+CREATE OR REPLACE FUNCTION public.prune_idempotency_keys()
+RETURNS INTEGER
+LANGUAGE sql
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+  WITH deleted AS (
+    DELETE FROM public.idempotency_keys
+    WHERE created_at < NOW() - INTERVAL '24 hours'
+    RETURNING 1
+  )
+  SELECT count(*)::INTEGER FROM deleted;
+$$;
+
+REVOKE EXECUTE ON FUNCTION public.prune_idempotency_keys FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION public.prune_idempotency_keys TO service_role;
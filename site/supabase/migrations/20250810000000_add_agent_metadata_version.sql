@@ -0,0 +1,29 @@
+-- Optimistic concurrency for agent metadata edits
+--
+-- metadata_version is served as the ETag of /api/v1/agents/{name}/metadata.
+-- Updates must match the version the editor started from, so two owners
+-- editing at once get a conflict instead of silently overwriting each other.
+
+ALTER TABLE public.agents
+  ADD COLUMN IF NOT EXISTS metadata_version INTEGER NOT NULL DEFAULT 1;
+
+-- Bump the version on any metadata change, whichever client made it
+CREATE OR REPLACE FUNCTION public.bump_agent_metadata_version()
+RETURNS TRIGGER AS $$
+BEGIN
+  IF NEW.description IS DISTINCT FROM OLD.description
+    OR NEW.tags IS DISTINCT FROM OLD.tags
+    OR NEW.homepage IS DISTINCT FROM OLD.homepage
+    OR NEW.readme IS DISTINCT FROM OLD.readme
+  THEN
+    NEW.metadata_version = OLD.metadata_version + 1;
+  END IF;
+  RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS bump_agents_metadata_version ON public.agents;
+CREATE TRIGGER bump_agents_metadata_version
+  BEFORE UPDATE ON public.agents
+  FOR EACH ROW
+  EXECUTE FUNCTION public.bump_agent_metadata_version();
@@ -0,0 +1,142 @@
+-- Deduplicate download counts
+--
+-- Every download is still written to download_stats, but repeated downloads
+-- of the same agent by the same user (or, for anonymous downloads, the same
+-- IP) within the dedup window only count once toward download_count. This
+-- keeps CI loops and retrying clients from inflating popularity metrics.
+--
+-- The remote schema sync dropped record_download, so it is recreated here
+-- with the API passing the downloader explicitly: it calls with the service
+-- role key, so auth.uid() is always NULL.
+
+DROP FUNCTION IF EXISTS public.record_download(TEXT, TEXT, TEXT, INET);
+
+CREATE OR REPLACE FUNCTION public.record_download(
+  agent_name TEXT,
+  version_text TEXT DEFAULT '',
+  user_agent_text TEXT DEFAULT '',
+  ip_addr INET DEFAULT NULL,
+  downloader_id UUID DEFAULT NULL,
+  dedup_window_seconds INTEGER DEFAULT 3600
+)
+RETURNS BOOLEAN
+LANGUAGE plpgsql
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+DECLARE
+  agent_record RECORD;
+  version_record RECORD;
+  package_record RECORD;
+  window_start TIMESTAMPTZ;
+  seen_agent BOOLEAN;
+  seen_version BOOLEAN;
+BEGIN
+  -- Find the agent
+  SELECT id INTO agent_record
+  FROM public.agents
+  WHERE name = agent_name AND is_public = true;
+
+  IF NOT FOUND THEN
+    RETURN FALSE;
+  END IF;
+
+  -- Find the version (use latest if not specified)
+  IF version_text = '' THEN
+    SELECT av.id, av.package_size INTO version_record
+    FROM public.agent_versions av
+    WHERE av.agent_id = agent_record.id
+    ORDER BY av.created_at DESC
+    LIMIT 1;
+  ELSE
+    SELECT av.id, av.package_size INTO version_record
+    FROM public.agent_versions av
+    WHERE av.agent_id = agent_record.id AND av.version = version_text;
+  END IF;
+
+  IF NOT FOUND THEN
+    RETURN FALSE;
+  END IF;
+
+  -- Find the package
+  SELECT id, file_size INTO package_record
+  FROM public.agent_packages ap
+  WHERE ap.version_id = version_record.id
+  LIMIT 1;
+
+  -- Serialize concurrent downloads by the same downloader so parallel
+  -- requests can't both see an empty window
+  PERFORM pg_advisory_xact_lock(
+    hashtext(agent_record.id::TEXT || ':' || COALESCE(downloader_id::TEXT, host(ip_addr), ''))
+  );
+
+  window_start := now() - make_interval(secs => GREATEST(dedup_window_seconds, 0));
+
+  -- Downloads with neither a user nor an IP can't be attributed, so they
+  -- always count
+  seen_agent := (downloader_id IS NOT NULL OR ip_addr IS NOT NULL) AND EXISTS (
+    SELECT 1
+    FROM public.download_stats ds
+    WHERE ds.agent_id = agent_record.id
+      AND ds.downloaded_at >= window_start
+      AND (
+        (downloader_id IS NOT NULL AND ds.user_id = downloader_id)
+        OR (downloader_id IS NULL AND ds.user_id IS NULL AND ds.ip_address = ip_addr)
+      )
+  );
+
+  seen_version := seen_agent AND EXISTS (
+    SELECT 1
+    FROM public.download_stats ds
+    WHERE ds.version_id = version_record.id
+      AND ds.downloaded_at >= window_start
+      AND (
+        (downloader_id IS NOT NULL AND ds.user_id = downloader_id)
+        OR (downloader_id IS NULL AND ds.user_id IS NULL AND ds.ip_address = ip_addr)
+      )
+  );
+
+  -- Always keep the raw event for stats
+  INSERT INTO public.download_stats (
+    agent_id,
+    version_id,
+    package_id,
+    user_id,
+    ip_address,
+    user_agent,
+    file_size
+  ) VALUES (
+    agent_record.id,
+    version_record.id,
+    package_record.id,
+    downloader_id,
+    ip_addr,
+    user_agent_text,
+    COALESCE(package_record.file_size, version_record.package_size)
+  );
+
+  IF NOT seen_agent THEN
+    UPDATE public.agents
+    SET download_count = COALESCE(download_count, 0) + 1
+    WHERE id = agent_record.id;
+  END IF;
+
+  IF NOT seen_version THEN
+    UPDATE public.agent_versions
+    SET download_count = COALESCE(download_count, 0) + 1
+    WHERE id = version_record.id;
+  END IF;
+
+  RETURN TRUE;
+END;
+$$;
+
+-- Only the API records downloads
+REVOKE ALL ON FUNCTION public.record_download(TEXT, TEXT, TEXT, INET, UUID, INTEGER) FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION public.record_download(TEXT, TEXT, TEXT, INET, UUID, INTEGER) TO service_role;
+
+-- Support the dedup lookups
+CREATE INDEX IF NOT EXISTS idx_download_stats_agent_user_date
+  ON public.download_stats(agent_id, user_id, downloaded_at DESC);
+CREATE INDEX IF NOT EXISTS idx_download_stats_agent_ip_date
+  ON public.download_stats(agent_id, ip_address, downloaded_at DESC);
@@ -0,0 +1,37 @@
+-- Anonymous CLI usage telemetry
+--
+-- Written by /api/v1/telemetry when a user has run `carp telemetry on`.
+-- Rows hold only the command name, duration, outcome and platform; nothing
+-- identifies who sent them.
+
+CREATE TABLE IF NOT EXISTS public.telemetry_events (
+  id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+  command TEXT NOT NULL CHECK (char_length(command) BETWEEN 1 AND 32),
+  duration_ms BIGINT NOT NULL CHECK (duration_ms >= 0),
+  success BOOLEAN NOT NULL,
+  error_kind TEXT CHECK (char_length(error_kind) BETWEEN 1 AND 32),
+  cli_version TEXT NOT NULL CHECK (char_length(cli_version) BETWEEN 1 AND 32),
+  os TEXT NOT NULL CHECK (char_length(os) BETWEEN 1 AND 32),
+  arch TEXT NOT NULL CHECK (char_length(arch) BETWEEN 1 AND 32),
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_telemetry_events_command_created_at
+  ON public.telemetry_events(command, created_at DESC);
+
+-- Only the API's service role touches this table
+ALTER TABLE public.telemetry_events ENABLE ROW LEVEL SECURITY;
+
+-- Failure rate per command over the last 30 days
+CREATE OR REPLACE VIEW public.telemetry_command_failures
+WITH (security_invoker = true) AS
+SELECT
+  command,
+  COUNT(*) AS runs,
+  COUNT(*) FILTER (WHERE NOT success) AS failures,
+  ROUND(COUNT(*) FILTER (WHERE NOT success)::NUMERIC / COUNT(*), 4) AS failure_rate,
+  PERCENTILE_CONT(0.5) WITHIN GROUP (ORDER BY duration_ms) AS median_duration_ms
+FROM public.telemetry_events
+WHERE created_at > NOW() - INTERVAL '30 days'
+GROUP BY command
+ORDER BY failure_rate DESC;
@@ -0,0 +1,30 @@
+-- Tag popularity for /api/v1/tags
+--
+-- Counts how many public agents use each tag so users can discover the
+-- registry's vocabulary. Tags are compared case-insensitively and reported
+-- in lowercase.
+
+CREATE OR REPLACE FUNCTION public.tag_counts(max_tags INTEGER DEFAULT 100)
+RETURNS TABLE (
+  tag TEXT,
+  agent_count BIGINT
+)
+LANGUAGE sql
+STABLE
+SET search_path = ''
+AS $$
+  SELECT
+    lower(btrim(t.tag)) AS tag,
+    COUNT(DISTINCT a.id) AS agent_count
+  FROM public.agents a
+  CROSS JOIN LATERAL unnest(a.tags) AS t(tag)
+  WHERE a.is_public = true
+    AND btrim(t.tag) <> ''
+  GROUP BY lower(btrim(t.tag))
+  ORDER BY agent_count DESC, tag ASC
+  LIMIT LEAST(GREATEST(max_tags, 1), 1000);
+$$;
+
+-- Only public agents are counted, so anyone may call it
+GRANT EXECUTE ON FUNCTION public.tag_counts(INTEGER) TO anon;
+GRANT EXECUTE ON FUNCTION public.tag_counts(INTEGER) TO authenticated;
@@ -0,0 +1,65 @@
+-- Agent stars
+--
+-- Users star agents they find useful through /api/v1/agents/{name}/star.
+-- star_count is kept on agents by trigger so search can sort by it without
+-- counting stars on every query.
+
+CREATE TABLE IF NOT EXISTS public.agent_stars (
+  user_id UUID NOT NULL REFERENCES auth.users(id) ON DELETE CASCADE,
+  agent_id UUID NOT NULL REFERENCES public.agents(id) ON DELETE CASCADE,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  PRIMARY KEY (user_id, agent_id)
+);
+
+-- A user's starred list, newest first
+CREATE INDEX IF NOT EXISTS idx_agent_stars_user_created_at
+  ON public.agent_stars(user_id, created_at DESC);
+CREATE INDEX IF NOT EXISTS idx_agent_stars_agent_id
+  ON public.agent_stars(agent_id);
+
+-- Only the API's service role touches this table
+ALTER TABLE public.agent_stars ENABLE ROW LEVEL SECURITY;
+
+ALTER TABLE public.agents
+  ADD COLUMN IF NOT EXISTS star_count INTEGER NOT NULL DEFAULT 0;
+
+CREATE OR REPLACE FUNCTION public.update_agent_star_count()
+RETURNS TRIGGER
+LANGUAGE plpgsql
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+BEGIN
+  IF TG_OP = 'INSERT' THEN
+    UPDATE public.agents
+    SET star_count = star_count + 1
+    WHERE id = NEW.agent_id;
+  ELSIF TG_OP = 'DELETE' THEN
+    UPDATE public.agents
+    SET star_count = GREATEST(star_count - 1, 0)
+    WHERE id = OLD.agent_id;
+  END IF;
+  RETURN NULL;
+END;
+$$;
+
+DROP TRIGGER IF EXISTS update_agent_star_count ON public.agent_stars;
+CREATE TRIGGER update_agent_star_count
+  AFTER INSERT OR DELETE ON public.agent_stars
+  FOR EACH ROW
+  EXECUTE FUNCTION public.update_agent_star_count();
+
+-- Backfill in case stars were added before the trigger existed
+UPDATE public.agents a
+SET star_count = s.stars
+FROM (
+  SELECT agent_id, COUNT(*) AS stars
+  FROM public.agent_stars
+  GROUP BY agent_id
+) s
+WHERE a.id = s.agent_id;
+
+-- Support search?sort=stars
+CREATE INDEX IF NOT EXISTS idx_agents_public_stars
+  ON public.agents(star_count DESC, updated_at DESC)
+  WHERE is_public = true;
@@ -0,0 +1,91 @@
+-- Agent reviews and ratings
+--
+-- Each user may leave one 1-5 star review per agent through
+-- /api/v1/agents/{name}/reviews. Reviews are screened when written; held
+-- ones are stored as 'pending' and, like 'hidden' ones, don't count toward
+-- the agent's rating until a moderator publishes them.
+
+CREATE TABLE IF NOT EXISTS public.agent_reviews (
+  id UUID NOT NULL DEFAULT gen_random_uuid() PRIMARY KEY,
+  agent_id UUID NOT NULL REFERENCES public.agents(id) ON DELETE CASCADE,
+  user_id UUID NOT NULL REFERENCES auth.users(id) ON DELETE CASCADE,
+  author_name TEXT NOT NULL DEFAULT 'Unknown',
+  rating SMALLINT NOT NULL CHECK (rating BETWEEN 1 AND 5),
+  body TEXT NOT NULL DEFAULT '' CHECK (char_length(body) <= 5000),
+  status TEXT NOT NULL DEFAULT 'published'
+    CHECK (status IN ('published', 'pending', 'hidden')),
+  moderation_reason TEXT,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  UNIQUE (agent_id, user_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_agent_reviews_agent_published
+  ON public.agent_reviews(agent_id, updated_at DESC)
+  WHERE status = 'published';
+
+-- Moderators work through held reviews oldest first
+CREATE INDEX IF NOT EXISTS idx_agent_reviews_pending
+  ON public.agent_reviews(created_at)
+  WHERE status = 'pending';
+
+DROP TRIGGER IF EXISTS update_agent_reviews_updated_at ON public.agent_reviews;
+CREATE TRIGGER update_agent_reviews_updated_at
+  BEFORE UPDATE ON public.agent_reviews
+  FOR EACH ROW
+  EXECUTE FUNCTION public.update_updated_at_column();
+
+-- Only the API's service role touches this table
+ALTER TABLE public.agent_reviews ENABLE ROW LEVEL SECURITY;
+
+ALTER TABLE public.agents
+  ADD COLUMN IF NOT EXISTS rating_count INTEGER NOT NULL DEFAULT 0,
+  ADD COLUMN IF NOT EXISTS rating_average NUMERIC(3, 2);
+
+-- Recompute an agent's rating from its published reviews
+CREATE OR REPLACE FUNCTION public.refresh_agent_rating(target_agent_id UUID)
+RETURNS VOID
+LANGUAGE sql
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+  UPDATE public.agents a
+  SET
+    rating_count = r.reviews,
+    rating_average = r.average
+  FROM (
+    SELECT
+      COUNT(*) AS reviews,
+      ROUND(AVG(rating), 2) AS average
+    FROM public.agent_reviews
+    WHERE agent_id = target_agent_id AND status = 'published'
+  ) r
+  WHERE a.id = target_agent_id;
+$$;
+
+CREATE OR REPLACE FUNCTION public.update_agent_rating()
+RETURNS TRIGGER
+LANGUAGE plpgsql
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+BEGIN
+  IF TG_OP IN ('INSERT', 'UPDATE') THEN
+    PERFORM public.refresh_agent_rating(NEW.agent_id);
+  END IF;
+  IF TG_OP = 'DELETE' OR (TG_OP = 'UPDATE' AND OLD.agent_id <> NEW.agent_id) THEN
+    PERFORM public.refresh_agent_rating(OLD.agent_id);
+  END IF;
+  RETURN NULL;
+END;
+$$;
+
+-- Also fires when a moderator changes a review's status
+DROP TRIGGER IF EXISTS update_agent_rating ON public.agent_reviews;
+CREATE TRIGGER update_agent_rating
+  AFTER INSERT OR UPDATE OR DELETE ON public.agent_reviews
+  FOR EACH ROW
+  EXECUTE FUNCTION public.update_agent_rating();
+
+REVOKE ALL ON FUNCTION public.refresh_agent_rating(UUID) FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION public.refresh_agent_rating(UUID) TO service_role;
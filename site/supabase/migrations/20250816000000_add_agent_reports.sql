@@ -0,0 +1,59 @@
+-- Abuse reports against agents
+--
+-- Written by /api/v1/agents/{name}/report. A user can have one open report
+-- per agent; repeats are ignored until a moderator resolves or dismisses it.
+-- Open reports and held reviews are worked through in moderation_queue.
+
+CREATE TABLE IF NOT EXISTS public.agent_reports (
+  id UUID NOT NULL DEFAULT gen_random_uuid() PRIMARY KEY,
+  agent_id UUID NOT NULL REFERENCES public.agents(id) ON DELETE CASCADE,
+  reporter_id UUID NOT NULL REFERENCES auth.users(id) ON DELETE CASCADE,
+  reason TEXT NOT NULL CHECK (reason IN (
+    'malicious', 'license_violation', 'impersonation', 'spam', 'inappropriate', 'other'
+  )),
+  details TEXT NOT NULL DEFAULT '' CHECK (char_length(details) <= 2000),
+  status TEXT NOT NULL DEFAULT 'open' CHECK (status IN ('open', 'resolved', 'dismissed')),
+  resolution_note TEXT,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  resolved_at TIMESTAMPTZ
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_agent_reports_one_open_per_reporter
+  ON public.agent_reports(agent_id, reporter_id)
+  WHERE status = 'open';
+
+CREATE INDEX IF NOT EXISTS idx_agent_reports_open
+  ON public.agent_reports(created_at)
+  WHERE status = 'open';
+
+-- Only the API's service role touches this table
+ALTER TABLE public.agent_reports ENABLE ROW LEVEL SECURITY;
+
+-- Everything awaiting a moderator, oldest first. Malicious reports come
+-- first since they may need the agent taken down.
+CREATE OR REPLACE VIEW public.moderation_queue
+WITH (security_invoker = true) AS
+SELECT * FROM (
+  SELECT
+    'report' AS kind,
+    r.id,
+    a.name AS agent_name,
+    r.reason,
+    r.details AS content,
+    r.created_at
+  FROM public.agent_reports r
+  JOIN public.agents a ON a.id = r.agent_id
+  WHERE r.status = 'open'
+  UNION ALL
+  SELECT
+    'review' AS kind,
+    rv.id,
+    a.name AS agent_name,
+    rv.moderation_reason AS reason,
+    rv.body AS content,
+    rv.created_at
+  FROM public.agent_reviews rv
+  JOIN public.agents a ON a.id = rv.agent_id
+  WHERE rv.status = 'pending'
+) queue
+ORDER BY (reason = 'malicious') DESC, created_at ASC;
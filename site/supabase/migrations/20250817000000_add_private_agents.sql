@@ -0,0 +1,111 @@
+-- Private agents
+--
+-- agents.is_public has existed since the initial schema but every upload set
+-- it to true. Uploads can now be private: they are only visible to their
+-- owner, which the API enforces since it reads with the service role.
+--
+-- get_agent_download_info takes the requesting user so owners can download
+-- their private agents. The requester is supplied by the caller, so the
+-- function is restricted to the service role. Downloads of private agents
+-- are not counted by record_download.
+
+DROP FUNCTION IF EXISTS public.get_agent_download_info(TEXT, TEXT);
+
+CREATE OR REPLACE FUNCTION public.get_agent_download_info(
+  p_agent_name TEXT,
+  p_version_text TEXT DEFAULT '',
+  p_requester_id UUID DEFAULT NULL
+)
+RETURNS TABLE (
+  agent_id TEXT,
+  agent_name TEXT,
+  author TEXT,
+  version TEXT,
+  file_path TEXT,
+  checksum TEXT,
+  file_size BIGINT,
+  content_type TEXT,
+  definition JSONB
+)
+LANGUAGE plpgsql
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+DECLARE
+  agent_record RECORD;
+  version_record RECORD;
+  package_record RECORD;
+  author_info RECORD;
+BEGIN
+  -- Find the agent
+  SELECT a.id, a.name, a.user_id INTO agent_record
+  FROM public.agents a
+  WHERE a.name = p_agent_name
+    AND (a.is_public = true OR a.user_id = p_requester_id);
+  
+  IF NOT FOUND THEN
+    RETURN;
+  END IF;
+  
+  -- Get author information
+  SELECT 
+    COALESCE(p.display_name, p.github_username, 'Unknown') as display_name
+  INTO author_info
+  FROM public.profiles p
+  WHERE p.user_id = agent_record.user_id
+  LIMIT 1;
+  
+  -- Find the version (use latest if not specified or "latest")
+  IF p_version_text = '' OR p_version_text = 'latest' THEN
+    -- Get the latest version
+    SELECT av.id, av.version, av.checksum, av.package_size, av.definition INTO version_record
+    FROM public.agent_versions av
+    WHERE av.agent_id = agent_record.id
+      AND av.yanked = false
+    ORDER BY av.created_at DESC
+    LIMIT 1;
+  ELSE
+    -- Get specific version
+    SELECT av.id, av.version, av.checksum, av.package_size, av.definition INTO version_record
+    FROM public.agent_versions av
+    WHERE av.agent_id = agent_record.id 
+      AND av.version = p_version_text
+      AND av.yanked = false;
+  END IF;
+  
+  IF NOT FOUND THEN
+    RETURN;
+  END IF;
+  
+  -- Find the package file
+  SELECT ap.file_path, ap.checksum, ap.file_size, ap.content_type INTO package_record
+  FROM public.agent_packages ap
+  WHERE ap.version_id = version_record.id
+    AND ap.upload_completed = true
+  ORDER BY ap.created_at DESC
+  LIMIT 1;
+  
+  IF NOT FOUND THEN
+    RETURN;
+  END IF;
+  
+  -- Return the download information
+  RETURN QUERY SELECT 
+    agent_record.id::TEXT,
+    agent_record.name::TEXT,
+    COALESCE(author_info.display_name, 'Unknown')::TEXT,
+    version_record.version::TEXT,
+    package_record.file_path::TEXT,
+    COALESCE(package_record.checksum, version_record.checksum, '')::TEXT,
+    COALESCE(package_record.file_size, version_record.package_size, 0)::BIGINT,
+    package_record.content_type::TEXT,
+    version_record.definition::JSONB;
+END;
+$$;
+
+REVOKE ALL ON FUNCTION public.get_agent_download_info(TEXT, TEXT, UUID) FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION public.get_agent_download_info(TEXT, TEXT, UUID) TO service_role;
+
+-- Listing an owner's agents, private ones included
+CREATE INDEX IF NOT EXISTS idx_agents_user_id_updated_at
+  ON public.agents(user_id, updated_at DESC);
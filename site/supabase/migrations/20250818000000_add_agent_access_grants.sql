@@ -0,0 +1,187 @@
+-- Shared access to private agents
+--
+-- Owners can let specific users, or every member of an organization, read a
+-- private agent. Grants are managed through /api/v1/agents/{name}/access and
+-- honoured by search, info and download.
+--
+-- Organizations are a minimal record for now: members are added by admins.
+
+CREATE TABLE IF NOT EXISTS public.organizations (
+  id UUID NOT NULL DEFAULT gen_random_uuid() PRIMARY KEY,
+  slug TEXT NOT NULL UNIQUE CHECK (slug ~ '^[a-z0-9][a-z0-9-]{0,38}$'),
+  display_name TEXT,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS public.organization_members (
+  org_id UUID NOT NULL REFERENCES public.organizations(id) ON DELETE CASCADE,
+  user_id UUID NOT NULL REFERENCES auth.users(id) ON DELETE CASCADE,
+  role TEXT NOT NULL DEFAULT 'member' CHECK (role IN ('owner', 'member')),
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  PRIMARY KEY (org_id, user_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_organization_members_user_id
+  ON public.organization_members(user_id);
+
+-- Exactly one of user_id and org_id is set. grantee_name is the username or
+-- organization slug at the time of the grant, for listing.
+CREATE TABLE IF NOT EXISTS public.agent_access_grants (
+  id UUID NOT NULL DEFAULT gen_random_uuid() PRIMARY KEY,
+  agent_id UUID NOT NULL REFERENCES public.agents(id) ON DELETE CASCADE,
+  user_id UUID REFERENCES auth.users(id) ON DELETE CASCADE,
+  org_id UUID REFERENCES public.organizations(id) ON DELETE CASCADE,
+  grantee_name TEXT NOT NULL,
+  granted_by UUID NOT NULL REFERENCES auth.users(id) ON DELETE CASCADE,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  CHECK ((user_id IS NULL) <> (org_id IS NULL)),
+  UNIQUE (agent_id, user_id),
+  UNIQUE (agent_id, org_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_agent_access_grants_user_id
+  ON public.agent_access_grants(user_id) WHERE user_id IS NOT NULL;
+CREATE INDEX IF NOT EXISTS idx_agent_access_grants_org_id
+  ON public.agent_access_grants(org_id) WHERE org_id IS NOT NULL;
+
+-- Only the API's service role touches these tables
+ALTER TABLE public.organizations ENABLE ROW LEVEL SECURITY;
+ALTER TABLE public.organization_members ENABLE ROW LEVEL SECURITY;
+ALTER TABLE public.agent_access_grants ENABLE ROW LEVEL SECURITY;
+
+-- Private agents shared with a user, directly or through an organization
+CREATE OR REPLACE FUNCTION public.shared_agent_ids(p_user_id UUID)
+RETURNS SETOF UUID
+LANGUAGE sql
+STABLE
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+  SELECT g.agent_id
+  FROM public.agent_access_grants g
+  WHERE g.user_id = p_user_id
+  UNION
+  SELECT g.agent_id
+  FROM public.agent_access_grants g
+  JOIN public.organization_members m ON m.org_id = g.org_id
+  WHERE m.user_id = p_user_id;
+$$;
+
+CREATE OR REPLACE FUNCTION public.can_read_agent(
+  p_agent_id UUID,
+  p_is_public BOOLEAN,
+  p_owner_id UUID,
+  p_user_id UUID
+)
+RETURNS BOOLEAN
+LANGUAGE sql
+STABLE
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+  SELECT p_is_public
+    OR p_owner_id = p_user_id
+    OR EXISTS (
+      SELECT 1 FROM public.shared_agent_ids(p_user_id) AS shared(agent_id)
+      WHERE shared.agent_id = p_agent_id
+    );
+$$;
+
+-- The user is supplied by the caller, so only the service role may ask
+REVOKE ALL ON FUNCTION public.shared_agent_ids(UUID) FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION public.shared_agent_ids(UUID) TO service_role;
+REVOKE ALL ON FUNCTION public.can_read_agent(UUID, BOOLEAN, UUID, UUID) FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION public.can_read_agent(UUID, BOOLEAN, UUID, UUID) TO service_role;
+
+-- Downloads honour grants as well as ownership
+CREATE OR REPLACE FUNCTION public.get_agent_download_info(
+  p_agent_name TEXT,
+  p_version_text TEXT DEFAULT '',
+  p_requester_id UUID DEFAULT NULL
+)
+RETURNS TABLE (
+  agent_id TEXT,
+  agent_name TEXT,
+  author TEXT,
+  version TEXT,
+  file_path TEXT,
+  checksum TEXT,
+  file_size BIGINT,
+  content_type TEXT,
+  definition JSONB
+)
+LANGUAGE plpgsql
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+DECLARE
+  agent_record RECORD;
+  version_record RECORD;
+  package_record RECORD;
+  author_info RECORD;
+BEGIN
+  -- Find the agent
+  SELECT a.id, a.name, a.user_id INTO agent_record
+  FROM public.agents a
+  WHERE a.name = p_agent_name
+    AND public.can_read_agent(a.id, a.is_public, a.user_id, p_requester_id);
+  
+  IF NOT FOUND THEN
+    RETURN;
+  END IF;
+  
+  -- Get author information
+  SELECT 
+    COALESCE(p.display_name, p.github_username, 'Unknown') as display_name
+  INTO author_info
+  FROM public.profiles p
+  WHERE p.user_id = agent_record.user_id
+  LIMIT 1;
+  
+  -- Find the version (use latest if not specified or "latest")
+  IF p_version_text = '' OR p_version_text = 'latest' THEN
+    -- Get the latest version
+    SELECT av.id, av.version, av.checksum, av.package_size, av.definition INTO version_record
+    FROM public.agent_versions av
+    WHERE av.agent_id = agent_record.id
+      AND av.yanked = false
+    ORDER BY av.created_at DESC
+    LIMIT 1;
+  ELSE
+    -- Get specific version
+    SELECT av.id, av.version, av.checksum, av.package_size, av.definition INTO version_record
+    FROM public.agent_versions av
+    WHERE av.agent_id = agent_record.id 
+      AND av.version = p_version_text
+      AND av.yanked = false;
+  END IF;
+  
+  IF NOT FOUND THEN
+    RETURN;
+  END IF;
+  
+  -- Find the package file
+  SELECT ap.file_path, ap.checksum, ap.file_size, ap.content_type INTO package_record
+  FROM public.agent_packages ap
+  WHERE ap.version_id = version_record.id
+    AND ap.upload_completed = true
+  ORDER BY ap.created_at DESC
+  LIMIT 1;
+  
+  IF NOT FOUND THEN
+    RETURN;
+  END IF;
+  
+  -- Return the download information
+  RETURN QUERY SELECT 
+    agent_record.id::TEXT,
+    agent_record.name::TEXT,
+    COALESCE(author_info.display_name, 'Unknown')::TEXT,
+    version_record.version::TEXT,
+    package_record.file_path::TEXT,
+    COALESCE(package_record.checksum, version_record.checksum, '')::TEXT,
+    COALESCE(package_record.file_size, version_record.package_size, 0)::BIGINT,
+    package_record.content_type::TEXT,
+    version_record.definition::JSONB;
+END;
+$$;
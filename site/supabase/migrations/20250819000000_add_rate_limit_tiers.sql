@@ -0,0 +1,67 @@
+-- Rate limits that depend on who is calling
+--
+-- Anonymous callers are limited per IP address, API key holders per user, and
+-- members of a paying organization get that organization's plan limit. The
+-- limits themselves are configured on the API; the database only counts
+-- requests per fixed window in public.rate_limits.
+
+ALTER TABLE public.organizations
+  ADD COLUMN IF NOT EXISTS plan TEXT NOT NULL DEFAULT 'free'
+    CHECK (plan IN ('free', 'team', 'enterprise'));
+
+-- Count a request against the caller's current window and return the new
+-- count, when the window started, and the best plan of the user's
+-- organizations (NULL for anonymous callers and users outside any paid org).
+CREATE OR REPLACE FUNCTION public.hit_rate_limit(
+  p_identifier TEXT,
+  p_endpoint TEXT,
+  p_window_seconds INTEGER,
+  p_user_id UUID DEFAULT NULL
+)
+RETURNS TABLE (request_count INTEGER, window_start TIMESTAMPTZ, plan TEXT)
+LANGUAGE plpgsql
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+-- The OUT columns share names with rate_limits' columns
+#variable_conflict use_column
+DECLARE
+  v_window_start TIMESTAMPTZ;
+BEGIN
+  v_window_start := to_timestamp(
+    floor(extract(epoch FROM now()) / p_window_seconds) * p_window_seconds
+  );
+
+  -- Earlier windows for this caller no longer matter
+  DELETE FROM public.rate_limits r
+  WHERE r.identifier = p_identifier
+    AND r.endpoint = p_endpoint
+    AND r.window_start < v_window_start;
+
+  RETURN QUERY
+  WITH hit AS (
+    INSERT INTO public.rate_limits AS r (identifier, endpoint, window_start, request_count)
+    VALUES (p_identifier, p_endpoint, v_window_start, 1)
+    ON CONFLICT (identifier, endpoint, window_start)
+    DO UPDATE SET request_count = r.request_count + 1
+    RETURNING r.request_count, r.window_start
+  )
+  SELECT
+    hit.request_count,
+    hit.window_start,
+    (
+      SELECT o.plan
+      FROM public.organization_members m
+      JOIN public.organizations o ON o.id = m.org_id
+      WHERE p_user_id IS NOT NULL
+        AND m.user_id = p_user_id
+        AND o.plan <> 'free'
+      ORDER BY CASE o.plan WHEN 'enterprise' THEN 0 ELSE 1 END
+      LIMIT 1
+    )
+  FROM hit;
+END;
+$$;
+
+REVOKE ALL ON FUNCTION public.hit_rate_limit(TEXT, TEXT, INTEGER, UUID) FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION public.hit_rate_limit(TEXT, TEXT, INTEGER, UUID) TO service_role;
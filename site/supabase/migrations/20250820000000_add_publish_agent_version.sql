@@ -0,0 +1,116 @@
+-- Immutable agent versions
+--
+-- Uploads went through create_agent_safe, which only inserts an agents row
+-- and records the version in agents.current_version. Uploading a new version
+-- of an existing agent failed on unique_agent_name, and nothing kept the
+-- content of earlier versions.
+--
+-- publish_agent_version creates the agent on its first upload and stores
+-- every upload as an agent_versions row, including the uploaded content, so
+-- name@version always resolves to what was originally published. A version
+-- can't be published twice: it raises unique_violation, which PostgREST
+-- returns as 409 Conflict. Publishing to another user's agent raises
+-- insufficient_privilege (403). This replaces the earlier function of the
+-- same name, which the remote schema had already dropped.
+
+DROP FUNCTION IF EXISTS public.publish_agent_version(TEXT, TEXT, TEXT, TEXT, JSONB, JSONB);
+
+ALTER TABLE public.agent_versions ADD COLUMN IF NOT EXISTS readme TEXT;
+
+CREATE OR REPLACE FUNCTION public.publish_agent_version(
+  p_user_id UUID,
+  p_name TEXT,
+  p_version TEXT,
+  p_description TEXT,
+  p_definition JSONB DEFAULT '{}',
+  p_tags TEXT[] DEFAULT '{}',
+  p_author_name TEXT DEFAULT NULL,
+  p_license TEXT DEFAULT 'MIT',
+  p_homepage TEXT DEFAULT NULL,
+  p_repository TEXT DEFAULT NULL,
+  p_readme TEXT DEFAULT NULL,
+  p_checksum TEXT DEFAULT NULL,
+  p_is_public BOOLEAN DEFAULT true
+)
+RETURNS SETOF public.agents
+LANGUAGE plpgsql
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+DECLARE
+  agent_record RECORD;
+  new_version_id UUID;
+BEGIN
+  -- Serialize concurrent publishes of the same agent
+  SELECT a.id, a.user_id INTO agent_record
+  FROM public.agents a
+  WHERE a.name = p_name
+  FOR UPDATE;
+
+  IF NOT FOUND THEN
+    INSERT INTO public.agents (
+      user_id, name, description, definition, tags, author_name, license,
+      homepage, repository, readme, keywords, current_version, is_public,
+      view_count, download_count
+    ) VALUES (
+      p_user_id, p_name, p_description, p_definition, p_tags,
+      COALESCE(p_author_name, 'user-' || p_user_id::TEXT), p_license,
+      p_homepage, p_repository, p_readme, p_tags, p_version, p_is_public,
+      0, 0
+    )
+    RETURNING id, user_id INTO agent_record;
+  ELSIF agent_record.user_id <> p_user_id THEN
+    RAISE EXCEPTION 'Agent % belongs to another user', p_name
+      USING ERRCODE = 'insufficient_privilege';
+  END IF;
+
+  IF EXISTS (
+    SELECT 1 FROM public.agent_versions av
+    WHERE av.agent_id = agent_record.id AND av.version = p_version
+  ) THEN
+    RAISE EXCEPTION '%@% has already been published', p_name, p_version
+      USING ERRCODE = 'unique_violation';
+  END IF;
+
+  INSERT INTO public.agent_versions (
+    agent_id, version, description, definition, readme, package_size, checksum
+  ) VALUES (
+    agent_record.id, p_version, p_description, p_definition, p_readme,
+    octet_length(COALESCE(p_readme, '')), p_checksum
+  )
+  RETURNING id INTO new_version_id;
+
+  UPDATE public.agents a SET
+    description = p_description,
+    definition = p_definition,
+    tags = p_tags,
+    keywords = p_tags,
+    license = p_license,
+    homepage = p_homepage,
+    repository = p_repository,
+    readme = p_readme,
+    current_version = p_version,
+    latest_version_id = new_version_id,
+    is_public = p_is_public,
+    updated_at = NOW()
+  WHERE a.id = agent_record.id;
+
+  RETURN QUERY SELECT * FROM public.agents a WHERE a.id = agent_record.id;
+END;
+$$;
+
+REVOKE ALL ON FUNCTION public.publish_agent_version(
+  UUID, TEXT, TEXT, TEXT, JSONB, TEXT[], TEXT, TEXT, TEXT, TEXT, TEXT, TEXT, BOOLEAN
+) FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION public.publish_agent_version(
+  UUID, TEXT, TEXT, TEXT, JSONB, TEXT[], TEXT, TEXT, TEXT, TEXT, TEXT, TEXT, BOOLEAN
+) TO service_role;
+
+-- Agents uploaded before this migration have no version rows; give each
+-- one for its current version so it can be downloaded by version too
+INSERT INTO public.agent_versions (agent_id, version, description, definition, readme, package_size)
+SELECT a.id, a.current_version, a.description, COALESCE(a.definition, '{}'), a.readme,
+       octet_length(COALESCE(a.readme, ''))
+FROM public.agents a
+WHERE a.current_version IS NOT NULL
+ON CONFLICT (agent_id, version) DO NOTHING;
@@ -0,0 +1,211 @@
+-- Downloads by version row
+--
+-- Each agent_versions row now records where its package is stored, so a
+-- version is self-contained: name@version resolves to one row with its
+-- package path, size, checksum and yanked flag. agents.current_version is
+-- kept up to date by publish_agent_version as a denormalized copy for
+-- listings, but downloads and version listings read agent_versions.
+--
+-- Packages previously recorded only in agent_packages are backfilled, and
+-- get_agent_download_info reads the version row instead of joining
+-- agent_packages. publish_agent_version takes the package's location, which
+-- the upload API stores before calling it.
+
+ALTER TABLE public.agent_versions ADD COLUMN IF NOT EXISTS file_path TEXT;
+ALTER TABLE public.agent_versions ADD COLUMN IF NOT EXISTS content_type TEXT;
+
+UPDATE public.agent_versions av SET
+  file_path = ap.file_path,
+  content_type = ap.content_type,
+  package_size = COALESCE(ap.file_size, av.package_size),
+  checksum = COALESCE(ap.checksum, av.checksum)
+FROM (
+  SELECT DISTINCT ON (version_id) version_id, file_path, content_type, file_size, checksum
+  FROM public.agent_packages
+  WHERE upload_completed = true
+  ORDER BY version_id, created_at DESC
+) ap
+WHERE ap.version_id = av.id AND av.file_path IS NULL;
+
+-- Newest downloadable version first, for resolving "latest"
+CREATE INDEX IF NOT EXISTS idx_agent_versions_agent_id_created_at
+  ON public.agent_versions(agent_id, created_at DESC)
+  WHERE yanked = false;
+
+CREATE OR REPLACE FUNCTION public.get_agent_download_info(
+  p_agent_name TEXT,
+  p_version_text TEXT DEFAULT '',
+  p_requester_id UUID DEFAULT NULL
+)
+RETURNS TABLE (
+  agent_id TEXT,
+  agent_name TEXT,
+  author TEXT,
+  version TEXT,
+  file_path TEXT,
+  checksum TEXT,
+  file_size BIGINT,
+  content_type TEXT,
+  definition JSONB
+)
+LANGUAGE plpgsql
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+DECLARE
+  agent_record RECORD;
+  version_record RECORD;
+  author_info RECORD;
+BEGIN
+  SELECT a.id, a.name, a.user_id INTO agent_record
+  FROM public.agents a
+  WHERE a.name = p_agent_name
+    AND (a.is_public = true OR a.user_id = p_requester_id);
+
+  IF NOT FOUND THEN
+    RETURN;
+  END IF;
+
+  SELECT
+    COALESCE(p.display_name, p.github_username, 'Unknown') as display_name
+  INTO author_info
+  FROM public.profiles p
+  WHERE p.user_id = agent_record.user_id
+  LIMIT 1;
+
+  -- Versions without a stored package can't be downloaded
+  IF p_version_text = '' OR p_version_text = 'latest' THEN
+    SELECT av.version, av.file_path, av.checksum, av.package_size, av.content_type, av.definition
+    INTO version_record
+    FROM public.agent_versions av
+    WHERE av.agent_id = agent_record.id
+      AND av.yanked = false
+      AND av.file_path IS NOT NULL
+    ORDER BY av.created_at DESC
+    LIMIT 1;
+  ELSE
+    SELECT av.version, av.file_path, av.checksum, av.package_size, av.content_type, av.definition
+    INTO version_record
+    FROM public.agent_versions av
+    WHERE av.agent_id = agent_record.id
+      AND av.version = p_version_text
+      AND av.yanked = false
+      AND av.file_path IS NOT NULL;
+  END IF;
+
+  IF NOT FOUND THEN
+    RETURN;
+  END IF;
+
+  RETURN QUERY SELECT
+    agent_record.id::TEXT,
+    agent_record.name::TEXT,
+    COALESCE(author_info.display_name, 'Unknown')::TEXT,
+    version_record.version::TEXT,
+    version_record.file_path::TEXT,
+    COALESCE(version_record.checksum, '')::TEXT,
+    COALESCE(version_record.package_size, 0)::BIGINT,
+    COALESCE(version_record.content_type, 'application/zip')::TEXT,
+    version_record.definition::JSONB;
+END;
+$$;
+
+REVOKE ALL ON FUNCTION public.get_agent_download_info(TEXT, TEXT, UUID) FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION public.get_agent_download_info(TEXT, TEXT, UUID) TO service_role;
+
+DROP FUNCTION IF EXISTS public.publish_agent_version(
+  UUID, TEXT, TEXT, TEXT, JSONB, TEXT[], TEXT, TEXT, TEXT, TEXT, TEXT, TEXT, BOOLEAN
+);
+
+CREATE OR REPLACE FUNCTION public.publish_agent_version(
+  p_user_id UUID,
+  p_name TEXT,
+  p_version TEXT,
+  p_description TEXT,
+  p_file_path TEXT,
+  p_content_type TEXT,
+  p_package_size BIGINT,
+  p_checksum TEXT,
+  p_definition JSONB DEFAULT '{}',
+  p_tags TEXT[] DEFAULT '{}',
+  p_author_name TEXT DEFAULT NULL,
+  p_license TEXT DEFAULT 'MIT',
+  p_homepage TEXT DEFAULT NULL,
+  p_repository TEXT DEFAULT NULL,
+  p_readme TEXT DEFAULT NULL,
+  p_is_public BOOLEAN DEFAULT true
+)
+RETURNS SETOF public.agents
+LANGUAGE plpgsql
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+DECLARE
+  agent_record RECORD;
+  new_version_id UUID;
+BEGIN
+  -- Serialize concurrent publishes of the same agent
+  SELECT a.id, a.user_id INTO agent_record
+  FROM public.agents a
+  WHERE a.name = p_name
+  FOR UPDATE;
+
+  IF NOT FOUND THEN
+    INSERT INTO public.agents (
+      user_id, name, description, definition, tags, author_name, license,
+      homepage, repository, readme, keywords, current_version, is_public,
+      view_count, download_count
+    ) VALUES (
+      p_user_id, p_name, p_description, p_definition, p_tags,
+      COALESCE(p_author_name, 'user-' || p_user_id::TEXT), p_license,
+      p_homepage, p_repository, p_readme, p_tags, p_version, p_is_public,
+      0, 0
+    )
+    RETURNING id, user_id INTO agent_record;
+  ELSIF agent_record.user_id <> p_user_id THEN
+    RAISE EXCEPTION 'Agent % belongs to another user', p_name
+      USING ERRCODE = 'insufficient_privilege';
+  END IF;
+
+  IF EXISTS (
+    SELECT 1 FROM public.agent_versions av
+    WHERE av.agent_id = agent_record.id AND av.version = p_version
+  ) THEN
+    RAISE EXCEPTION '%@% has already been published', p_name, p_version
+      USING ERRCODE = 'unique_violation';
+  END IF;
+
+  INSERT INTO public.agent_versions (
+    agent_id, version, description, definition, readme,
+    file_path, content_type, package_size, checksum
+  ) VALUES (
+    agent_record.id, p_version, p_description, p_definition, p_readme,
+    p_file_path, p_content_type, p_package_size, p_checksum
+  )
+  RETURNING id INTO new_version_id;
+
+  UPDATE public.agents a SET
+    description = p_description,
+    definition = p_definition,
+    tags = p_tags,
+    keywords = p_tags,
+    license = p_license,
+    homepage = p_homepage,
+    repository = p_repository,
+    readme = p_readme,
+    current_version = p_version,
+    latest_version_id = new_version_id,
+    is_public = p_is_public,
+    updated_at = NOW()
+  WHERE a.id = agent_record.id;
+
+  RETURN QUERY SELECT * FROM public.agents a WHERE a.id = agent_record.id;
+END;
+$$;
+
+REVOKE ALL ON FUNCTION public.publish_agent_version(
+  UUID, TEXT, TEXT, TEXT, TEXT, TEXT, BIGINT, TEXT, JSONB, TEXT[], TEXT, TEXT, TEXT, TEXT, TEXT, BOOLEAN
+) FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION public.publish_agent_version(
+  UUID, TEXT, TEXT, TEXT, TEXT, TEXT, BIGINT, TEXT, JSONB, TEXT[], TEXT, TEXT, TEXT, TEXT, TEXT, BOOLEAN
+) TO service_role;
@@ -0,0 +1,46 @@
+-- Near-miss agent name suggestions
+--
+-- When a pull names an agent that doesn't exist, or a search finds nothing,
+-- the CLI asks for the closest names so a typo isn't a dead end.
+-- suggest_agent_names scores every agent name against the query by edit
+-- distance (fuzzystrmatch) and trigram similarity (pg_trgm), keeping names
+-- within two edits or at least 0.3 similar.
+--
+-- It returns the columns AgentVisibility filters on, so the API applies the
+-- same visibility rules to suggestions as to search results, and orders and
+-- limits them through PostgREST.
+
+CREATE EXTENSION IF NOT EXISTS pg_trgm WITH SCHEMA extensions;
+CREATE EXTENSION IF NOT EXISTS fuzzystrmatch WITH SCHEMA extensions;
+
+CREATE OR REPLACE FUNCTION public.suggest_agent_names(p_query TEXT)
+RETURNS TABLE (
+  id UUID,
+  name TEXT,
+  user_id UUID,
+  is_public BOOLEAN,
+  distance INTEGER,
+  similarity REAL
+)
+LANGUAGE sql
+STABLE
+SECURITY INVOKER
+SET search_path = ''
+AS $$
+  SELECT *
+  FROM (
+    SELECT
+      a.id,
+      a.name,
+      a.user_id,
+      a.is_public,
+      extensions.levenshtein(lower(a.name), lower(p_query)) AS distance,
+      extensions.similarity(lower(a.name), lower(p_query)) AS similarity
+    FROM public.agents a
+    -- levenshtein only accepts strings up to 255 characters
+    WHERE length(p_query) BETWEEN 1 AND 255
+  ) scored
+  WHERE scored.distance <= 2 OR scored.similarity >= 0.3;
+$$;
+
+GRANT EXECUTE ON FUNCTION public.suggest_agent_names(TEXT) TO anon, authenticated, service_role;
@@ -0,0 +1,115 @@
+-- OCI artifacts
+--
+-- The OCI distribution API (/v2) serves each downloadable version as an
+-- artifact whose layer is the version's package. Versions pushed through it
+-- keep the manifest and config blob they were pushed with, so clients get
+-- back the digests they pushed. Other versions have both generated from the
+-- version row, which leaves these columns null.
+--
+-- publish_agent_version takes the pushed manifest and config so a version is
+-- never visible without them.
+--
+-- Blobs pushed before their manifest are staged in the agent-packages bucket
+-- under oci-blobs/{user_id}/ and oci-uploads/{user_id}/ until then.
+
+ALTER TABLE public.agent_versions ADD COLUMN IF NOT EXISTS oci_manifest TEXT;
+ALTER TABLE public.agent_versions ADD COLUMN IF NOT EXISTS oci_config TEXT;
+
+DROP FUNCTION IF EXISTS public.publish_agent_version(
+  UUID, TEXT, TEXT, TEXT, TEXT, TEXT, BIGINT, TEXT, JSONB, TEXT[], TEXT, TEXT, TEXT, TEXT, TEXT, BOOLEAN
+);
+
+CREATE OR REPLACE FUNCTION public.publish_agent_version(
+  p_user_id UUID,
+  p_name TEXT,
+  p_version TEXT,
+  p_description TEXT,
+  p_file_path TEXT,
+  p_content_type TEXT,
+  p_package_size BIGINT,
+  p_checksum TEXT,
+  p_definition JSONB DEFAULT '{}',
+  p_tags TEXT[] DEFAULT '{}',
+  p_author_name TEXT DEFAULT NULL,
+  p_license TEXT DEFAULT 'MIT',
+  p_homepage TEXT DEFAULT NULL,
+  p_repository TEXT DEFAULT NULL,
+  p_readme TEXT DEFAULT NULL,
+  p_is_public BOOLEAN DEFAULT true,
+  p_oci_manifest TEXT DEFAULT NULL,
+  p_oci_config TEXT DEFAULT NULL
+)
+RETURNS SETOF public.agents
+LANGUAGE plpgsql
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+DECLARE
+  agent_record RECORD;
+  new_version_id UUID;
+BEGIN
+  -- Serialize concurrent publishes of the same agent
+  SELECT a.id, a.user_id INTO agent_record
+  FROM public.agents a
+  WHERE a.name = p_name
+  FOR UPDATE;
+
+  IF NOT FOUND THEN
+    INSERT INTO public.agents (
+      user_id, name, description, definition, tags, author_name, license,
+      homepage, repository, readme, keywords, current_version, is_public,
+      view_count, download_count
+    ) VALUES (
+      p_user_id, p_name, p_description, p_definition, p_tags,
+      COALESCE(p_author_name, 'user-' || p_user_id::TEXT), p_license,
+      p_homepage, p_repository, p_readme, p_tags, p_version, p_is_public,
+      0, 0
+    )
+    RETURNING id, user_id INTO agent_record;
+  ELSIF agent_record.user_id <> p_user_id THEN
+    RAISE EXCEPTION 'Agent % belongs to another user', p_name
+      USING ERRCODE = 'insufficient_privilege';
+  END IF;
+
+  IF EXISTS (
+    SELECT 1 FROM public.agent_versions av
+    WHERE av.agent_id = agent_record.id AND av.version = p_version
+  ) THEN
+    RAISE EXCEPTION '%@% has already been published', p_name, p_version
+      USING ERRCODE = 'unique_violation';
+  END IF;
+
+  INSERT INTO public.agent_versions (
+    agent_id, version, description, definition, readme,
+    file_path, content_type, package_size, checksum, oci_manifest, oci_config
+  ) VALUES (
+    agent_record.id, p_version, p_description, p_definition, p_readme,
+    p_file_path, p_content_type, p_package_size, p_checksum, p_oci_manifest, p_oci_config
+  )
+  RETURNING id INTO new_version_id;
+
+  UPDATE public.agents a SET
+    description = p_description,
+    definition = p_definition,
+    tags = p_tags,
+    keywords = p_tags,
+    license = p_license,
+    homepage = p_homepage,
+    repository = p_repository,
+    readme = p_readme,
+    current_version = p_version,
+    latest_version_id = new_version_id,
+    is_public = p_is_public,
+    updated_at = NOW()
+  WHERE a.id = agent_record.id;
+
+  RETURN QUERY SELECT * FROM public.agents a WHERE a.id = agent_record.id;
+END;
+$$;
+
+REVOKE ALL ON FUNCTION public.publish_agent_version(
+  UUID, TEXT, TEXT, TEXT, TEXT, TEXT, BIGINT, TEXT, JSONB, TEXT[], TEXT, TEXT, TEXT, TEXT, TEXT, BOOLEAN, TEXT, TEXT
+) FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION public.publish_agent_version(
+  UUID, TEXT, TEXT, TEXT, TEXT, TEXT, BIGINT, TEXT, JSONB, TEXT[], TEXT, TEXT, TEXT, TEXT, TEXT, BOOLEAN, TEXT, TEXT
+) TO service_role;
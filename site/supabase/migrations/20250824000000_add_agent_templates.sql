@@ -0,0 +1,116 @@
+-- Agent templates
+--
+-- Agents flagged as templates are starting points for new agents: `carp new
+-- my-agent --template <name>` pulls one and substitutes the new name into it.
+-- The flag is set by whoever publishes the agent, on each upload, and
+-- GET /api/v1/templates lists the flagged agents.
+--
+-- publish_agent_version takes the flag. Leaving it out, as OCI pushes do,
+-- keeps the agent's current flag.
+
+ALTER TABLE public.agents ADD COLUMN IF NOT EXISTS is_template BOOLEAN NOT NULL DEFAULT false;
+
+CREATE INDEX IF NOT EXISTS idx_agents_is_template
+  ON public.agents(download_count DESC)
+  WHERE is_template = true;
+
+DROP FUNCTION IF EXISTS public.publish_agent_version(
+  UUID, TEXT, TEXT, TEXT, TEXT, TEXT, BIGINT, TEXT, JSONB, TEXT[], TEXT, TEXT, TEXT, TEXT, TEXT, BOOLEAN, TEXT, TEXT
+);
+
+CREATE OR REPLACE FUNCTION public.publish_agent_version(
+  p_user_id UUID,
+  p_name TEXT,
+  p_version TEXT,
+  p_description TEXT,
+  p_file_path TEXT,
+  p_content_type TEXT,
+  p_package_size BIGINT,
+  p_checksum TEXT,
+  p_definition JSONB DEFAULT '{}',
+  p_tags TEXT[] DEFAULT '{}',
+  p_author_name TEXT DEFAULT NULL,
+  p_license TEXT DEFAULT 'MIT',
+  p_homepage TEXT DEFAULT NULL,
+  p_repository TEXT DEFAULT NULL,
+  p_readme TEXT DEFAULT NULL,
+  p_is_public BOOLEAN DEFAULT true,
+  p_oci_manifest TEXT DEFAULT NULL,
+  p_oci_config TEXT DEFAULT NULL,
+  p_is_template BOOLEAN DEFAULT NULL
+)
+RETURNS SETOF public.agents
+LANGUAGE plpgsql
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+DECLARE
+  agent_record RECORD;
+  new_version_id UUID;
+BEGIN
+  -- Serialize concurrent publishes of the same agent
+  SELECT a.id, a.user_id INTO agent_record
+  FROM public.agents a
+  WHERE a.name = p_name
+  FOR UPDATE;
+
+  IF NOT FOUND THEN
+    INSERT INTO public.agents (
+      user_id, name, description, definition, tags, author_name, license,
+      homepage, repository, readme, keywords, current_version, is_public,
+      is_template, view_count, download_count
+    ) VALUES (
+      p_user_id, p_name, p_description, p_definition, p_tags,
+      COALESCE(p_author_name, 'user-' || p_user_id::TEXT), p_license,
+      p_homepage, p_repository, p_readme, p_tags, p_version, p_is_public,
+      COALESCE(p_is_template, false), 0, 0
+    )
+    RETURNING id, user_id INTO agent_record;
+  ELSIF agent_record.user_id <> p_user_id THEN
+    RAISE EXCEPTION 'Agent % belongs to another user', p_name
+      USING ERRCODE = 'insufficient_privilege';
+  END IF;
+
+  IF EXISTS (
+    SELECT 1 FROM public.agent_versions av
+    WHERE av.agent_id = agent_record.id AND av.version = p_version
+  ) THEN
+    RAISE EXCEPTION '%@% has already been published', p_name, p_version
+      USING ERRCODE = 'unique_violation';
+  END IF;
+
+  INSERT INTO public.agent_versions (
+    agent_id, version, description, definition, readme,
+    file_path, content_type, package_size, checksum, oci_manifest, oci_config
+  ) VALUES (
+    agent_record.id, p_version, p_description, p_definition, p_readme,
+    p_file_path, p_content_type, p_package_size, p_checksum, p_oci_manifest, p_oci_config
+  )
+  RETURNING id INTO new_version_id;
+
+  UPDATE public.agents a SET
+    description = p_description,
+    definition = p_definition,
+    tags = p_tags,
+    keywords = p_tags,
+    license = p_license,
+    homepage = p_homepage,
+    repository = p_repository,
+    readme = p_readme,
+    current_version = p_version,
+    latest_version_id = new_version_id,
+    is_public = p_is_public,
+    is_template = COALESCE(p_is_template, a.is_template),
+    updated_at = NOW()
+  WHERE a.id = agent_record.id;
+
+  RETURN QUERY SELECT * FROM public.agents a WHERE a.id = agent_record.id;
+END;
+$$;
+
+REVOKE ALL ON FUNCTION public.publish_agent_version(
+  UUID, TEXT, TEXT, TEXT, TEXT, TEXT, BIGINT, TEXT, JSONB, TEXT[], TEXT, TEXT, TEXT, TEXT, TEXT, BOOLEAN, TEXT, TEXT, BOOLEAN
+) FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION public.publish_agent_version(
+  UUID, TEXT, TEXT, TEXT, TEXT, TEXT, BIGINT, TEXT, JSONB, TEXT[], TEXT, TEXT, TEXT, TEXT, TEXT, BOOLEAN, TEXT, TEXT, BOOLEAN
+) TO service_role;
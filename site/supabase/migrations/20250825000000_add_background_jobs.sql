@@ -0,0 +1,269 @@
+-- Background jobs
+--
+-- Work that doesn't have to finish before a request returns is queued in
+-- public.jobs and run by `carp-api worker`. Workers claim due jobs with
+-- claim_jobs, which leases each one to a single worker, and report back with
+-- complete_job or fail_job. A job whose worker disappears is claimed again
+-- once its lease runs out.
+--
+-- Jobs with a dedupe_key are coalesced: while one with the key is still
+-- queued, enqueueing another returns the queued one instead. Triggers use
+-- this to ask for work on every change without queueing it once per row.
+
+CREATE TABLE IF NOT EXISTS public.jobs (
+  id UUID NOT NULL DEFAULT gen_random_uuid() PRIMARY KEY,
+  kind TEXT NOT NULL,
+  payload JSONB NOT NULL DEFAULT '{}'::jsonb,
+  status TEXT NOT NULL DEFAULT 'queued'
+    CHECK (status IN ('queued', 'running', 'succeeded', 'failed')),
+  dedupe_key TEXT,
+  attempts INTEGER NOT NULL DEFAULT 0,
+  max_attempts INTEGER NOT NULL DEFAULT 5 CHECK (max_attempts > 0),
+  -- When a queued job is next due
+  run_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  locked_by TEXT,
+  locked_until TIMESTAMPTZ,
+  last_error TEXT,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  finished_at TIMESTAMPTZ
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_jobs_queued_dedupe_key
+  ON public.jobs(dedupe_key)
+  WHERE status = 'queued';
+
+CREATE INDEX IF NOT EXISTS idx_jobs_due
+  ON public.jobs(run_at)
+  WHERE status = 'queued';
+
+CREATE INDEX IF NOT EXISTS idx_jobs_leased
+  ON public.jobs(locked_until)
+  WHERE status = 'running';
+
+CREATE INDEX IF NOT EXISTS idx_jobs_recent
+  ON public.jobs(status, updated_at DESC);
+
+-- Only the API's service role touches this table
+ALTER TABLE public.jobs ENABLE ROW LEVEL SECURITY;
+
+-- Queue a job, returning its id, or the id of the queued job with the same
+-- dedupe_key
+CREATE OR REPLACE FUNCTION public.enqueue_job(
+  p_kind TEXT,
+  p_payload JSONB DEFAULT '{}'::jsonb,
+  p_run_at TIMESTAMPTZ DEFAULT NOW(),
+  p_dedupe_key TEXT DEFAULT NULL,
+  p_max_attempts INTEGER DEFAULT 5
+)
+RETURNS UUID
+LANGUAGE plpgsql
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+DECLARE
+  v_id UUID;
+BEGIN
+  INSERT INTO public.jobs (kind, payload, run_at, dedupe_key, max_attempts)
+  VALUES (p_kind, COALESCE(p_payload, '{}'::jsonb), COALESCE(p_run_at, NOW()), p_dedupe_key, p_max_attempts)
+  ON CONFLICT (dedupe_key) WHERE status = 'queued' DO NOTHING
+  RETURNING id INTO v_id;
+
+  IF v_id IS NULL THEN
+    SELECT j.id INTO v_id
+    FROM public.jobs j
+    WHERE j.dedupe_key = p_dedupe_key AND j.status = 'queued';
+  END IF;
+
+  RETURN v_id;
+END;
+$$;
+
+-- Lease up to p_limit due jobs to p_worker for p_lease_seconds, counting an
+-- attempt on each. Jobs whose lease ran out are due again, unless that was
+-- their last attempt, in which case they fail.
+CREATE OR REPLACE FUNCTION public.claim_jobs(
+  p_worker TEXT,
+  p_limit INTEGER,
+  p_lease_seconds INTEGER
+)
+RETURNS SETOF public.jobs
+LANGUAGE plpgsql
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+BEGIN
+  UPDATE public.jobs j
+  SET status = 'failed',
+      last_error = 'Worker ' || COALESCE(j.locked_by, 'unknown') || ' stopped before finishing',
+      locked_by = NULL,
+      locked_until = NULL,
+      updated_at = NOW(),
+      finished_at = NOW()
+  WHERE j.status = 'running'
+    AND j.locked_until < NOW()
+    AND j.attempts >= j.max_attempts;
+
+  RETURN QUERY
+  UPDATE public.jobs j
+  SET status = 'running',
+      attempts = j.attempts + 1,
+      locked_by = p_worker,
+      locked_until = NOW() + make_interval(secs => p_lease_seconds),
+      updated_at = NOW()
+  WHERE j.id IN (
+    SELECT due.id
+    FROM public.jobs due
+    WHERE (due.status = 'queued' AND due.run_at <= NOW())
+       OR (due.status = 'running' AND due.locked_until < NOW())
+    ORDER BY due.run_at
+    LIMIT p_limit
+    FOR UPDATE SKIP LOCKED
+  )
+  RETURNING j.*;
+END;
+$$;
+
+-- Mark a job p_worker holds as done. False when the lease was lost to
+-- another worker, whose result then counts instead.
+CREATE OR REPLACE FUNCTION public.complete_job(p_id UUID, p_worker TEXT)
+RETURNS BOOLEAN
+LANGUAGE plpgsql
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+BEGIN
+  UPDATE public.jobs j
+  SET status = 'succeeded',
+      locked_by = NULL,
+      locked_until = NULL,
+      last_error = NULL,
+      updated_at = NOW(),
+      finished_at = NOW()
+  WHERE j.id = p_id AND j.status = 'running' AND j.locked_by = p_worker;
+
+  RETURN FOUND;
+END;
+$$;
+
+-- Record a failed attempt by p_worker. The job is queued again at
+-- p_retry_at while it has attempts left, and fails for good otherwise or
+-- when p_retry_at is NULL. A retry of a deduplicated job merges into a job
+-- with the same key queued meanwhile, which does the same work. Returns the
+-- job's new status, or NULL when the lease was lost.
+CREATE OR REPLACE FUNCTION public.fail_job(
+  p_id UUID,
+  p_worker TEXT,
+  p_error TEXT,
+  p_retry_at TIMESTAMPTZ DEFAULT NULL
+)
+RETURNS TEXT
+LANGUAGE plpgsql
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+DECLARE
+  v_job public.jobs;
+  v_queued UUID;
+  v_retry BOOLEAN;
+BEGIN
+  SELECT * INTO v_job
+  FROM public.jobs j
+  WHERE j.id = p_id AND j.status = 'running' AND j.locked_by = p_worker
+  FOR UPDATE;
+
+  IF NOT FOUND THEN
+    RETURN NULL;
+  END IF;
+
+  v_retry := p_retry_at IS NOT NULL AND v_job.attempts < v_job.max_attempts;
+  IF v_retry AND v_job.dedupe_key IS NOT NULL THEN
+    SELECT q.id INTO v_queued
+    FROM public.jobs q
+    WHERE q.dedupe_key = v_job.dedupe_key AND q.status = 'queued';
+  END IF;
+
+  UPDATE public.jobs j
+  SET status = CASE WHEN v_retry AND v_queued IS NULL THEN 'queued' ELSE 'failed' END,
+      run_at = CASE WHEN v_retry AND v_queued IS NULL THEN p_retry_at ELSE j.run_at END,
+      locked_by = NULL,
+      locked_until = NULL,
+      last_error = left(p_error, 2000)
+        || CASE WHEN v_queued IS NULL THEN '' ELSE ' (retrying as job ' || v_queued || ')' END,
+      updated_at = NOW(),
+      finished_at = CASE WHEN v_retry AND v_queued IS NULL THEN NULL ELSE NOW() END
+  WHERE j.id = p_id;
+
+  RETURN CASE WHEN v_retry AND v_queued IS NULL THEN 'queued' ELSE 'failed' END;
+END;
+$$;
+
+-- Delete finished jobs older than p_keep_days, returning how many
+CREATE OR REPLACE FUNCTION public.prune_jobs(p_keep_days INTEGER)
+RETURNS INTEGER
+LANGUAGE plpgsql
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+DECLARE
+  v_deleted INTEGER;
+BEGIN
+  DELETE FROM public.jobs j
+  WHERE j.status IN ('succeeded', 'failed')
+    AND j.finished_at < NOW() - make_interval(days => p_keep_days);
+
+  GET DIAGNOSTICS v_deleted = ROW_COUNT;
+  RETURN v_deleted;
+END;
+$$;
+
+-- How many jobs are in each status, for the admin jobs endpoint
+CREATE OR REPLACE FUNCTION public.job_counts()
+RETURNS TABLE (status TEXT, count BIGINT)
+LANGUAGE sql
+STABLE
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+  SELECT j.status, COUNT(*) FROM public.jobs j GROUP BY j.status;
+$$;
+
+REVOKE ALL ON FUNCTION public.enqueue_job(TEXT, JSONB, TIMESTAMPTZ, TEXT, INTEGER) FROM PUBLIC, anon, authenticated;
+REVOKE ALL ON FUNCTION public.claim_jobs(TEXT, INTEGER, INTEGER) FROM PUBLIC, anon, authenticated;
+REVOKE ALL ON FUNCTION public.complete_job(UUID, TEXT) FROM PUBLIC, anon, authenticated;
+REVOKE ALL ON FUNCTION public.fail_job(UUID, TEXT, TEXT, TIMESTAMPTZ) FROM PUBLIC, anon, authenticated;
+REVOKE ALL ON FUNCTION public.prune_jobs(INTEGER) FROM PUBLIC, anon, authenticated;
+REVOKE ALL ON FUNCTION public.job_counts() FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION public.enqueue_job(TEXT, JSONB, TIMESTAMPTZ, TEXT, INTEGER) TO service_role;
+GRANT EXECUTE ON FUNCTION public.claim_jobs(TEXT, INTEGER, INTEGER) TO service_role;
+GRANT EXECUTE ON FUNCTION public.complete_job(UUID, TEXT) TO service_role;
+GRANT EXECUTE ON FUNCTION public.fail_job(UUID, TEXT, TEXT, TIMESTAMPTZ) TO service_role;
+GRANT EXECUTE ON FUNCTION public.prune_jobs(INTEGER) TO service_role;
+GRANT EXECUTE ON FUNCTION public.job_counts() TO service_role;
+
+-- Agent changes used to pg_notify a listener that was never written, so the
+-- trending view only caught up through pg_cron, where it was enabled. Queue
+-- a refresh instead, at most one every five minutes however many agents
+-- change meanwhile.
+CREATE OR REPLACE FUNCTION public.refresh_trending_on_agent_update()
+RETURNS trigger
+LANGUAGE plpgsql
+SECURITY DEFINER
+AS $$
+BEGIN
+  IF (TG_OP = 'UPDATE' AND (
+    OLD.download_count != NEW.download_count OR
+    OLD.is_public != NEW.is_public OR
+    OLD.updated_at < NEW.updated_at - interval '1 hour'
+  )) OR TG_OP = 'INSERT' THEN
+    PERFORM public.enqueue_job(
+      'refresh_trending',
+      '{}'::jsonb,
+      NOW() + interval '5 minutes',
+      'refresh_trending'
+    );
+  END IF;
+
+  RETURN COALESCE(NEW, OLD);
+END
+$$;
@@ -0,0 +1,180 @@
+-- Download rollups
+--
+-- download_stats keeps a row for every download, which makes anything that
+-- ranks or charts downloads slower as the registry grows. The
+-- rollup_stats background job folds new rows into per-agent daily and weekly
+-- totals, and the trending view and the agent stats endpoint read those
+-- instead of the raw events.
+--
+-- Days and weeks are UTC; weeks start on Monday.
+
+CREATE TABLE IF NOT EXISTS public.agent_downloads_daily (
+  agent_id UUID NOT NULL REFERENCES public.agents(id) ON DELETE CASCADE,
+  day DATE NOT NULL,
+  downloads BIGINT NOT NULL DEFAULT 0,
+  -- Distinct users, or IPs for anonymous downloads
+  unique_downloaders BIGINT NOT NULL DEFAULT 0,
+  bytes BIGINT NOT NULL DEFAULT 0,
+  PRIMARY KEY (agent_id, day)
+);
+
+CREATE TABLE IF NOT EXISTS public.agent_downloads_weekly (
+  agent_id UUID NOT NULL REFERENCES public.agents(id) ON DELETE CASCADE,
+  week DATE NOT NULL,
+  downloads BIGINT NOT NULL DEFAULT 0,
+  unique_downloaders BIGINT NOT NULL DEFAULT 0,
+  bytes BIGINT NOT NULL DEFAULT 0,
+  PRIMARY KEY (agent_id, week)
+);
+
+CREATE INDEX IF NOT EXISTS idx_agent_downloads_daily_day
+  ON public.agent_downloads_daily(day);
+
+-- Totals are as public as the agent they count
+ALTER TABLE public.agent_downloads_daily ENABLE ROW LEVEL SECURITY;
+ALTER TABLE public.agent_downloads_weekly ENABLE ROW LEVEL SECURITY;
+
+DROP POLICY IF EXISTS "Daily downloads of public agents are viewable" ON public.agent_downloads_daily;
+CREATE POLICY "Daily downloads of public agents are viewable"
+  ON public.agent_downloads_daily FOR SELECT
+  USING (EXISTS (
+    SELECT 1 FROM public.agents a
+    WHERE a.id = agent_downloads_daily.agent_id AND a.is_public = true
+  ));
+
+DROP POLICY IF EXISTS "Weekly downloads of public agents are viewable" ON public.agent_downloads_weekly;
+CREATE POLICY "Weekly downloads of public agents are viewable"
+  ON public.agent_downloads_weekly FOR SELECT
+  USING (EXISTS (
+    SELECT 1 FROM public.agents a
+    WHERE a.id = agent_downloads_weekly.agent_id AND a.is_public = true
+  ));
+
+GRANT SELECT ON public.agent_downloads_daily TO anon, authenticated;
+GRANT SELECT ON public.agent_downloads_weekly TO anon, authenticated;
+
+-- Recompute the totals for every day from p_since on, and for the weeks
+-- those days fall in. Without p_since it starts at the last day already
+-- rolled up, which is usually still being added to, or at the first
+-- download when nothing is. Returns how many daily rows were written.
+CREATE OR REPLACE FUNCTION public.rollup_download_stats(p_since DATE DEFAULT NULL)
+RETURNS INTEGER
+LANGUAGE plpgsql
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+DECLARE
+  v_since DATE;
+  v_week DATE;
+  v_rows INTEGER;
+BEGIN
+  -- Two workers rolling up at once would insert the same rows
+  PERFORM pg_advisory_xact_lock(hashtext('rollup_download_stats'));
+
+  v_since := COALESCE(
+    p_since,
+    (SELECT MAX(d.day) FROM public.agent_downloads_daily d),
+    (SELECT MIN(ds.downloaded_at AT TIME ZONE 'UTC')::date FROM public.download_stats ds)
+  );
+  IF v_since IS NULL THEN
+    RETURN 0;
+  END IF;
+  v_week := date_trunc('week', v_since)::date;
+
+  DELETE FROM public.agent_downloads_daily d WHERE d.day >= v_since;
+  INSERT INTO public.agent_downloads_daily (agent_id, day, downloads, unique_downloaders, bytes)
+  SELECT
+    ds.agent_id,
+    (ds.downloaded_at AT TIME ZONE 'UTC')::date,
+    COUNT(*),
+    COUNT(DISTINCT COALESCE(ds.user_id::TEXT, host(ds.ip_address))),
+    COALESCE(SUM(ds.file_size), 0)
+  FROM public.download_stats ds
+  WHERE ds.downloaded_at >= v_since::timestamp AT TIME ZONE 'UTC'
+  GROUP BY 1, 2;
+  GET DIAGNOSTICS v_rows = ROW_COUNT;
+
+  -- Counted from the raw events, since downloaders can't be summed across days
+  DELETE FROM public.agent_downloads_weekly w WHERE w.week >= v_week;
+  INSERT INTO public.agent_downloads_weekly (agent_id, week, downloads, unique_downloaders, bytes)
+  SELECT
+    ds.agent_id,
+    date_trunc('week', ds.downloaded_at AT TIME ZONE 'UTC')::date,
+    COUNT(*),
+    COUNT(DISTINCT COALESCE(ds.user_id::TEXT, host(ds.ip_address))),
+    COALESCE(SUM(ds.file_size), 0)
+  FROM public.download_stats ds
+  WHERE ds.downloaded_at >= v_week::timestamp AT TIME ZONE 'UTC'
+  GROUP BY 1, 2;
+
+  RETURN v_rows;
+END;
+$$;
+
+REVOKE ALL ON FUNCTION public.rollup_download_stats(DATE) FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION public.rollup_download_stats(DATE) TO service_role;
+
+-- Backfill from the first download
+SELECT public.rollup_download_stats();
+
+-- Trending score weighted toward recent downloads: an agent many people
+-- fetched this week outranks one with a larger but older total
+CREATE OR REPLACE FUNCTION public.calculate_trending_score(
+  download_count BIGINT,
+  recent_downloads BIGINT,
+  created_at TIMESTAMP WITH TIME ZONE,
+  updated_at TIMESTAMP WITH TIME ZONE
+) RETURNS NUMERIC
+LANGUAGE sql
+STABLE
+AS $$
+  SELECT public.calculate_trending_score(download_count, created_at, updated_at)
+    + LN(GREATEST(recent_downloads, 0) + 1) * 20
+$$;
+
+COMMENT ON FUNCTION public.calculate_trending_score(BIGINT, BIGINT, TIMESTAMP WITH TIME ZONE, TIMESTAMP WITH TIME ZONE) IS
+'Trending score plus a logarithmic bonus for downloaders over the last 7 days';
+
+-- Rebuilt with the recent downloads, and with the columns the trending
+-- endpoint selects, which the original view lacked
+DROP MATERIALIZED VIEW IF EXISTS public.trending_agents_mv;
+
+CREATE MATERIALIZED VIEW public.trending_agents_mv AS
+WITH recent AS (
+  SELECT d.agent_id, SUM(d.unique_downloaders)::BIGINT AS downloads
+  FROM public.agent_downloads_daily d
+  WHERE d.day > (NOW() AT TIME ZONE 'UTC')::date - 7
+  GROUP BY d.agent_id
+)
+SELECT
+  a.id,
+  a.name,
+  a.current_version,
+  a.description,
+  a.author_name,
+  a.created_at,
+  a.updated_at,
+  a.download_count,
+  a.view_count,
+  a.tags,
+  a.definition,
+  a.user_id,
+  COALESCE(r.downloads, 0) AS recent_downloads,
+  public.calculate_trending_score(a.download_count, COALESCE(r.downloads, 0), a.created_at, a.updated_at) AS trending_score
+FROM public.agents a
+LEFT JOIN recent r ON r.agent_id = a.id
+WHERE a.is_public = true
+  AND a.download_count > 0
+ORDER BY trending_score DESC
+LIMIT 100;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_trending_agents_mv_id
+  ON public.trending_agents_mv(id);
+
+CREATE INDEX IF NOT EXISTS idx_trending_agents_mv_score
+  ON public.trending_agents_mv(trending_score DESC);
+
+GRANT SELECT ON public.trending_agents_mv TO anon, authenticated;
+
+COMMENT ON MATERIALIZED VIEW public.trending_agents_mv IS
+'Cached trending agents ranked by calculated trending score. Refreshed by the refresh_trending and rollup_stats jobs.';
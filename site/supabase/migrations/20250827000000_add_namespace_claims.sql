@@ -0,0 +1,98 @@
+-- Verified namespaces
+--
+-- An organization's slug is a namespace: `@acme` covers the agent `acme` and
+-- every agent named `acme-...`. Anyone who proves control of the GitHub
+-- organization with the same name can claim it through
+-- /api/v1/namespaces/{namespace}/claim, either as a public member of the
+-- organization, or by publishing a challenge on the domain of its GitHub
+-- website as a DNS TXT record or a file under /.well-known. Agents in a
+-- verified namespace whose owner belongs to the organization are shown as
+-- verified.
+
+ALTER TABLE public.organizations
+  ADD COLUMN IF NOT EXISTS verified_at TIMESTAMPTZ,
+  ADD COLUMN IF NOT EXISTS verified_by UUID REFERENCES auth.users(id) ON DELETE SET NULL,
+  ADD COLUMN IF NOT EXISTS verification_method TEXT
+    CHECK (verification_method IN ('github', 'dns', 'url')),
+  ADD COLUMN IF NOT EXISTS verified_domain TEXT;
+
+-- Challenges handed out for DNS and URL proofs, one per user, namespace and
+-- method, until they're met or expire
+CREATE TABLE IF NOT EXISTS public.namespace_claims (
+  id UUID NOT NULL DEFAULT gen_random_uuid() PRIMARY KEY,
+  namespace TEXT NOT NULL CHECK (namespace ~ '^[a-z0-9][a-z0-9-]{0,38}$'),
+  user_id UUID NOT NULL REFERENCES auth.users(id) ON DELETE CASCADE,
+  method TEXT NOT NULL CHECK (method IN ('dns', 'url')),
+  domain TEXT NOT NULL,
+  token TEXT NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  expires_at TIMESTAMPTZ NOT NULL DEFAULT NOW() + interval '7 days',
+  UNIQUE (namespace, user_id, method)
+);
+
+-- Only the API's service role touches this table
+ALTER TABLE public.namespace_claims ENABLE ROW LEVEL SECURITY;
+
+-- Mark p_namespace verified for p_user_id, creating its organization if
+-- there isn't one and making the user an owner. The user's challenges for
+-- it are no longer needed.
+CREATE OR REPLACE FUNCTION public.verify_namespace(
+  p_namespace TEXT,
+  p_user_id UUID,
+  p_method TEXT,
+  p_domain TEXT DEFAULT NULL
+)
+RETURNS public.organizations
+LANGUAGE plpgsql
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+DECLARE
+  v_org public.organizations;
+BEGIN
+  INSERT INTO public.organizations (slug)
+  VALUES (p_namespace)
+  ON CONFLICT (slug) DO NOTHING;
+
+  UPDATE public.organizations o
+  SET verified_at = NOW(),
+      verified_by = p_user_id,
+      verification_method = p_method,
+      verified_domain = p_domain
+  WHERE o.slug = p_namespace
+  RETURNING * INTO v_org;
+
+  INSERT INTO public.organization_members (org_id, user_id, role)
+  VALUES (v_org.id, p_user_id, 'owner')
+  ON CONFLICT (org_id, user_id) DO UPDATE SET role = 'owner';
+
+  DELETE FROM public.namespace_claims c
+  WHERE c.namespace = p_namespace AND c.user_id = p_user_id;
+
+  RETURN v_org;
+END;
+$$;
+
+REVOKE ALL ON FUNCTION public.verify_namespace(TEXT, UUID, TEXT, TEXT) FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION public.verify_namespace(TEXT, UUID, TEXT, TEXT) TO service_role;
+
+-- The verified namespace an agent is published in, if any: the longest
+-- verified slug its name falls under, provided its owner is a member. Read
+-- as a computed `verified_namespace` column of agents.
+CREATE OR REPLACE FUNCTION public.verified_namespace(public.agents)
+RETURNS TEXT
+LANGUAGE sql
+STABLE
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+  SELECT o.slug
+  FROM public.organizations o
+  JOIN public.organization_members m ON m.org_id = o.id AND m.user_id = $1.user_id
+  WHERE o.verified_at IS NOT NULL
+    AND (lower($1.name) = o.slug OR lower($1.name) LIKE o.slug || '-%')
+  ORDER BY length(o.slug) DESC
+  LIMIT 1;
+$$;
+
+GRANT EXECUTE ON FUNCTION public.verified_namespace(public.agents) TO anon, authenticated, service_role;
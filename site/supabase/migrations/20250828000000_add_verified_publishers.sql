@@ -0,0 +1,65 @@
+-- Verified publishers
+--
+-- A publisher is verified when an admin vouches for them, or, for an
+-- organization, when someone proves control of its namespace. Users are
+-- verified by admins only. Agents from a verified user, or in a verified
+-- namespace, carry a computed `verified` flag so clients can tell official
+-- agents apart.
+
+ALTER TABLE public.profiles
+  ADD COLUMN IF NOT EXISTS verified_at TIMESTAMPTZ,
+  ADD COLUMN IF NOT EXISTS verified_by UUID REFERENCES auth.users(id) ON DELETE SET NULL;
+
+-- Admins can verify an organization without a proof
+ALTER TABLE public.organizations
+  DROP CONSTRAINT IF EXISTS organizations_verification_method_check;
+ALTER TABLE public.organizations
+  ADD CONSTRAINT organizations_verification_method_check
+    CHECK (verification_method IN ('github', 'dns', 'url', 'admin'));
+
+-- Users may edit their own profile, but not verify themselves; only the
+-- API's service role changes the verification columns
+CREATE OR REPLACE FUNCTION public.protect_profile_verification()
+RETURNS TRIGGER
+LANGUAGE plpgsql
+SET search_path = ''
+AS $$
+BEGIN
+  IF current_user IN ('service_role', 'postgres', 'supabase_admin') THEN
+    RETURN NEW;
+  END IF;
+
+  IF TG_OP = 'INSERT' THEN
+    NEW.verified_at := NULL;
+    NEW.verified_by := NULL;
+  ELSE
+    NEW.verified_at := OLD.verified_at;
+    NEW.verified_by := OLD.verified_by;
+  END IF;
+  RETURN NEW;
+END;
+$$;
+
+DROP TRIGGER IF EXISTS protect_profile_verification ON public.profiles;
+CREATE TRIGGER protect_profile_verification
+  BEFORE INSERT OR UPDATE ON public.profiles
+  FOR EACH ROW EXECUTE FUNCTION public.protect_profile_verification();
+
+-- Whether an agent comes from a verified publisher: its owner's profile is
+-- verified, or it's published in a verified namespace. Read as a computed
+-- `verified` column of agents.
+CREATE OR REPLACE FUNCTION public.verified(public.agents)
+RETURNS BOOLEAN
+LANGUAGE sql
+STABLE
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+  SELECT EXISTS (
+      SELECT 1 FROM public.profiles p
+      WHERE p.user_id = $1.user_id AND p.verified_at IS NOT NULL
+    )
+    OR public.verified_namespace($1) IS NOT NULL;
+$$;
+
+GRANT EXECUTE ON FUNCTION public.verified(public.agents) TO anon, authenticated, service_role;
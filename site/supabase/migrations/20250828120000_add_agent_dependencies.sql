@@ -0,0 +1,149 @@
+-- Agent dependencies and their resolution
+--
+-- Each version records the agents it depends on, as a map of agent name to
+-- semver requirement, e.g. {"code-reviewer": "^1.2"}. Uploads take them from
+-- the request or the agent's frontmatter. /api/v1/agents/{name}/resolve
+-- walks them with get_resolution_candidates to return a signed dependency
+-- closure for `carp pull --with-deps`.
+
+ALTER TABLE public.agent_versions
+  ADD COLUMN IF NOT EXISTS dependencies JSONB NOT NULL DEFAULT '{}'::jsonb;
+
+ALTER TABLE public.agent_versions
+  DROP CONSTRAINT IF EXISTS agent_versions_dependencies_object;
+ALTER TABLE public.agent_versions
+  ADD CONSTRAINT agent_versions_dependencies_object
+  CHECK (jsonb_typeof(dependencies) = 'object');
+
+DROP FUNCTION IF EXISTS public.publish_agent_version(
+  UUID, TEXT, TEXT, TEXT, TEXT, TEXT, BIGINT, TEXT, JSONB, TEXT[], TEXT, TEXT, TEXT, TEXT, TEXT, BOOLEAN, TEXT, TEXT, BOOLEAN
+);
+
+CREATE OR REPLACE FUNCTION public.publish_agent_version(
+  p_user_id UUID,
+  p_name TEXT,
+  p_version TEXT,
+  p_description TEXT,
+  p_file_path TEXT,
+  p_content_type TEXT,
+  p_package_size BIGINT,
+  p_checksum TEXT,
+  p_definition JSONB DEFAULT '{}',
+  p_tags TEXT[] DEFAULT '{}',
+  p_author_name TEXT DEFAULT NULL,
+  p_license TEXT DEFAULT 'MIT',
+  p_homepage TEXT DEFAULT NULL,
+  p_repository TEXT DEFAULT NULL,
+  p_readme TEXT DEFAULT NULL,
+  p_is_public BOOLEAN DEFAULT true,
+  p_oci_manifest TEXT DEFAULT NULL,
+  p_oci_config TEXT DEFAULT NULL,
+  p_is_template BOOLEAN DEFAULT NULL,
+  p_dependencies JSONB DEFAULT '{}'
+)
+RETURNS SETOF public.agents
+LANGUAGE plpgsql
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+DECLARE
+  agent_record RECORD;
+  new_version_id UUID;
+BEGIN
+  -- Serialize concurrent publishes of the same agent
+  SELECT a.id, a.user_id INTO agent_record
+  FROM public.agents a
+  WHERE a.name = p_name
+  FOR UPDATE;
+
+  IF NOT FOUND THEN
+    INSERT INTO public.agents (
+      user_id, name, description, definition, tags, author_name, license,
+      homepage, repository, readme, keywords, current_version, is_public,
+      is_template, view_count, download_count
+    ) VALUES (
+      p_user_id, p_name, p_description, p_definition, p_tags,
+      COALESCE(p_author_name, 'user-' || p_user_id::TEXT), p_license,
+      p_homepage, p_repository, p_readme, p_tags, p_version, p_is_public,
+      COALESCE(p_is_template, false), 0, 0
+    )
+    RETURNING id, user_id INTO agent_record;
+  ELSIF agent_record.user_id <> p_user_id THEN
+    RAISE EXCEPTION 'Agent % belongs to another user', p_name
+      USING ERRCODE = 'insufficient_privilege';
+  END IF;
+
+  IF EXISTS (
+    SELECT 1 FROM public.agent_versions av
+    WHERE av.agent_id = agent_record.id AND av.version = p_version
+  ) THEN
+    RAISE EXCEPTION '%@% has already been published', p_name, p_version
+      USING ERRCODE = 'unique_violation';
+  END IF;
+
+  INSERT INTO public.agent_versions (
+    agent_id, version, description, definition, readme,
+    file_path, content_type, package_size, checksum, oci_manifest, oci_config,
+    dependencies
+  ) VALUES (
+    agent_record.id, p_version, p_description, p_definition, p_readme,
+    p_file_path, p_content_type, p_package_size, p_checksum, p_oci_manifest, p_oci_config,
+    COALESCE(p_dependencies, '{}'::jsonb)
+  )
+  RETURNING id INTO new_version_id;
+
+  UPDATE public.agents a SET
+    description = p_description,
+    definition = p_definition,
+    tags = p_tags,
+    keywords = p_tags,
+    license = p_license,
+    homepage = p_homepage,
+    repository = p_repository,
+    readme = p_readme,
+    current_version = p_version,
+    latest_version_id = new_version_id,
+    is_public = p_is_public,
+    is_template = COALESCE(p_is_template, a.is_template),
+    updated_at = NOW()
+  WHERE a.id = agent_record.id;
+
+  RETURN QUERY SELECT * FROM public.agents a WHERE a.id = agent_record.id;
+END;
+$$;
+
+REVOKE ALL ON FUNCTION public.publish_agent_version(
+  UUID, TEXT, TEXT, TEXT, TEXT, TEXT, BIGINT, TEXT, JSONB, TEXT[], TEXT, TEXT, TEXT, TEXT, TEXT, BOOLEAN, TEXT, TEXT, BOOLEAN, JSONB
+) FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION public.publish_agent_version(
+  UUID, TEXT, TEXT, TEXT, TEXT, TEXT, BIGINT, TEXT, JSONB, TEXT[], TEXT, TEXT, TEXT, TEXT, TEXT, BOOLEAN, TEXT, TEXT, BOOLEAN, JSONB
+) TO service_role;
+
+-- Every downloadable version of the named agents the requester can read,
+-- with its checksum and dependencies; the API picks among them
+CREATE OR REPLACE FUNCTION public.get_resolution_candidates(
+  p_names TEXT[],
+  p_requester_id UUID DEFAULT NULL
+)
+RETURNS TABLE (
+  agent_name TEXT,
+  version TEXT,
+  checksum TEXT,
+  dependencies JSONB
+)
+LANGUAGE sql
+STABLE
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+  SELECT a.name::TEXT, av.version::TEXT, COALESCE(av.checksum, '')::TEXT, av.dependencies
+  FROM public.agents a
+  JOIN public.agent_versions av ON av.agent_id = a.id
+  WHERE a.name = ANY(p_names)
+    AND public.can_read_agent(a.id, a.is_public, a.user_id, p_requester_id)
+    AND av.yanked = false
+    AND av.file_path IS NOT NULL;
+$$;
+
+REVOKE ALL ON FUNCTION public.get_resolution_candidates(TEXT[], UUID) FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION public.get_resolution_candidates(TEXT[], UUID) TO service_role;
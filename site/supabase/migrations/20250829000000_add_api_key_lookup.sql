@@ -0,0 +1,40 @@
+-- Timing-safe API key lookup
+--
+-- validate_api_key finds a key by comparing its hash in the database, so
+-- how long that takes depends on the stored hashes. Keys are now looked up
+-- by their non-secret prefix, the first 12 characters, and the API compares
+-- the hashes of the candidates itself in constant time. Whether a candidate
+-- matched, was revoked or expired is decided after the comparison, so all
+-- three fail the same way.
+
+CREATE OR REPLACE FUNCTION public.lookup_api_keys(p_prefix TEXT)
+RETURNS TABLE (
+  key_id UUID,
+  user_id UUID,
+  key_hash TEXT,
+  scopes TEXT[],
+  is_valid BOOLEAN,
+  user_email TEXT,
+  github_username TEXT
+)
+LANGUAGE sql
+STABLE
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+  SELECT
+    ak.id,
+    ak.user_id,
+    ak.key_hash,
+    ak.scopes,
+    ak.is_active AND (ak.expires_at IS NULL OR ak.expires_at > NOW()),
+    u.email::TEXT,
+    p.github_username
+  FROM public.api_keys ak
+  LEFT JOIN auth.users u ON u.id = ak.user_id
+  LEFT JOIN public.profiles p ON p.user_id = ak.user_id
+  WHERE ak.prefix = p_prefix;
+$$;
+
+REVOKE ALL ON FUNCTION public.lookup_api_keys(TEXT) FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION public.lookup_api_keys(TEXT) TO service_role;
@@ -0,0 +1,64 @@
+-- API key expiry
+--
+-- A key past its `expires_at` is rejected with its own error, so clients
+-- can ask for a new key rather than report a bad one. lookup_api_keys now
+-- tells whether a key is active and whether it has expired apart, and the
+-- background worker runs deactivate_expired_api_keys so expired keys also
+-- show as inactive in key listings.
+
+DROP FUNCTION IF EXISTS public.lookup_api_keys(TEXT);
+
+CREATE FUNCTION public.lookup_api_keys(p_prefix TEXT)
+RETURNS TABLE (
+  key_id UUID,
+  user_id UUID,
+  key_hash TEXT,
+  scopes TEXT[],
+  is_active BOOLEAN,
+  is_expired BOOLEAN,
+  user_email TEXT,
+  github_username TEXT
+)
+LANGUAGE sql
+STABLE
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+  SELECT
+    ak.id,
+    ak.user_id,
+    ak.key_hash,
+    ak.scopes,
+    ak.is_active,
+    ak.expires_at IS NOT NULL AND ak.expires_at <= NOW(),
+    u.email::TEXT,
+    p.github_username
+  FROM public.api_keys ak
+  LEFT JOIN auth.users u ON u.id = ak.user_id
+  LEFT JOIN public.profiles p ON p.user_id = ak.user_id
+  WHERE ak.prefix = p_prefix;
+$$;
+
+REVOKE ALL ON FUNCTION public.lookup_api_keys(TEXT) FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION public.lookup_api_keys(TEXT) TO service_role;
+
+-- Marks keys past their expiry inactive, returning how many were
+CREATE OR REPLACE FUNCTION public.deactivate_expired_api_keys()
+RETURNS INTEGER
+LANGUAGE plpgsql
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+DECLARE
+  deactivated INTEGER;
+BEGIN
+  UPDATE public.api_keys
+  SET is_active = FALSE, updated_at = NOW()
+  WHERE is_active AND expires_at <= NOW();
+  GET DIAGNOSTICS deactivated = ROW_COUNT;
+  RETURN deactivated;
+END;
+$$;
+
+REVOKE ALL ON FUNCTION public.deactivate_expired_api_keys() FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION public.deactivate_expired_api_keys() TO service_role;
@@ -0,0 +1,104 @@
+-- API key usage tracking
+--
+-- Every request authenticated with an API key records when, from which
+-- address and with which client the key was last used, so owners can spot
+-- keys they've forgotten or that someone else is using. The countries and
+-- networks (ASNs) a key has been used from are kept too; when a key that has
+-- been used before shows up somewhere new, a `notify_key_location` job is
+-- queued to email its owner.
+
+ALTER TABLE public.api_keys
+  ADD COLUMN IF NOT EXISTS last_used_ip TEXT,
+  ADD COLUMN IF NOT EXISTS last_used_user_agent TEXT,
+  ADD COLUMN IF NOT EXISTS last_used_country TEXT;
+
+-- Where each key has been used from, as `country:<ISO code>` or `asn:<number>`
+CREATE TABLE IF NOT EXISTS public.api_key_locations (
+  key_id UUID NOT NULL REFERENCES public.api_keys(id) ON DELETE CASCADE,
+  location TEXT NOT NULL,
+  first_seen_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  PRIMARY KEY (key_id, location)
+);
+
+-- Only the API's service role reads or writes it
+ALTER TABLE public.api_key_locations ENABLE ROW LEVEL SECURITY;
+
+CREATE OR REPLACE FUNCTION public.record_api_key_use(
+  p_key_id UUID,
+  p_ip TEXT,
+  p_user_agent TEXT,
+  p_country TEXT DEFAULT NULL,
+  p_asn TEXT DEFAULT NULL
+)
+RETURNS VOID
+LANGUAGE plpgsql
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+DECLARE
+  v_location TEXT;
+  v_new TEXT[] := '{}';
+BEGIN
+  UPDATE public.api_keys
+  SET last_used_at = NOW(),
+      last_used_ip = p_ip,
+      last_used_user_agent = LEFT(p_user_agent, 512),
+      last_used_country = COALESCE(p_country, last_used_country)
+  WHERE id = p_key_id;
+
+  FOREACH v_location IN ARRAY ARRAY[
+    'country:' || UPPER(NULLIF(p_country, '')),
+    'asn:' || NULLIF(p_asn, '')
+  ] LOOP
+    CONTINUE WHEN v_location IS NULL;
+
+    INSERT INTO public.api_key_locations (key_id, location)
+    VALUES (p_key_id, v_location)
+    ON CONFLICT DO NOTHING;
+    CONTINUE WHEN NOT FOUND;
+
+    -- The first country or network a key is used from isn't news
+    IF EXISTS (
+      SELECT 1 FROM public.api_key_locations l
+      WHERE l.key_id = p_key_id
+        AND l.location <> v_location
+        AND split_part(l.location, ':', 1) = split_part(v_location, ':', 1)
+    ) THEN
+      v_new := v_new || v_location;
+    END IF;
+  END LOOP;
+
+  IF cardinality(v_new) > 0 THEN
+    PERFORM public.enqueue_job(
+      'notify_key_location',
+      jsonb_build_object(
+        'key_id', p_key_id,
+        'locations', to_jsonb(v_new),
+        'ip', p_ip,
+        'user_agent', LEFT(p_user_agent, 512),
+        'seen_at', NOW()
+      )
+    );
+  END IF;
+END;
+$$;
+
+REVOKE ALL ON FUNCTION public.record_api_key_use(UUID, TEXT, TEXT, TEXT, TEXT) FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION public.record_api_key_use(UUID, TEXT, TEXT, TEXT, TEXT) TO service_role;
+
+-- Who to tell about a key: its name, prefix and owner's email
+CREATE OR REPLACE FUNCTION public.api_key_owner(p_key_id UUID)
+RETURNS TABLE (name TEXT, prefix TEXT, email TEXT)
+LANGUAGE sql
+STABLE
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+  SELECT ak.name, ak.prefix, u.email::TEXT
+  FROM public.api_keys ak
+  JOIN auth.users u ON u.id = ak.user_id
+  WHERE ak.id = p_key_id;
+$$;
+
+REVOKE ALL ON FUNCTION public.api_key_owner(UUID) FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION public.api_key_owner(UUID) TO service_role;
@@ -0,0 +1,88 @@
+-- Single sign-on through enterprise identity providers
+--
+-- Besides GitHub through Supabase Auth, users can sign in through an OIDC
+-- identity provider configured in `SSO_PROVIDERS`. Each provider account is
+-- linked to one user, found by the provider's stable subject, or on first
+-- sign-in by email (the API only accepts emails in the provider's own
+-- domains). Users whose email domain maps to an organization are added to it
+-- as members when they sign in.
+
+CREATE TABLE IF NOT EXISTS public.sso_identities (
+  provider TEXT NOT NULL,
+  subject TEXT NOT NULL,
+  user_id UUID NOT NULL REFERENCES auth.users(id) ON DELETE CASCADE,
+  email TEXT NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  last_sign_in_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  PRIMARY KEY (provider, subject)
+);
+
+CREATE INDEX IF NOT EXISTS idx_sso_identities_user_id
+  ON public.sso_identities(user_id);
+
+-- Only the API's service role reads or writes it
+ALTER TABLE public.sso_identities ENABLE ROW LEVEL SECURITY;
+
+-- The user a provider account belongs to: the one linked to it, else the one
+-- with its email, else NULL for the API to create
+CREATE OR REPLACE FUNCTION public.find_sso_user(
+  p_provider TEXT,
+  p_subject TEXT,
+  p_email TEXT
+)
+RETURNS UUID
+LANGUAGE sql
+STABLE
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+  SELECT COALESCE(
+    (SELECT i.user_id FROM public.sso_identities i
+     WHERE i.provider = p_provider AND i.subject = p_subject),
+    (SELECT u.id FROM auth.users u
+     WHERE LOWER(u.email) = LOWER(p_email)
+     ORDER BY u.created_at
+     LIMIT 1)
+  );
+$$;
+
+REVOKE ALL ON FUNCTION public.find_sso_user(TEXT, TEXT, TEXT) FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION public.find_sso_user(TEXT, TEXT, TEXT) TO service_role;
+
+-- Link a provider account to a user and, when `p_org_slug` names an existing
+-- organization, make the user a member of it. Existing roles are kept.
+-- Returns whether the user was added to the organization.
+CREATE OR REPLACE FUNCTION public.link_sso_user(
+  p_user_id UUID,
+  p_provider TEXT,
+  p_subject TEXT,
+  p_email TEXT,
+  p_org_slug TEXT DEFAULT NULL
+)
+RETURNS BOOLEAN
+LANGUAGE plpgsql
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+BEGIN
+  INSERT INTO public.sso_identities (provider, subject, user_id, email)
+  VALUES (p_provider, p_subject, p_user_id, p_email)
+  ON CONFLICT (provider, subject) DO UPDATE
+  SET email = EXCLUDED.email,
+      last_sign_in_at = NOW();
+
+  IF p_org_slug IS NULL THEN
+    RETURN FALSE;
+  END IF;
+
+  INSERT INTO public.organization_members (org_id, user_id)
+  SELECT o.id, p_user_id
+  FROM public.organizations o
+  WHERE o.slug = p_org_slug
+  ON CONFLICT DO NOTHING;
+  RETURN FOUND;
+END;
+$$;
+
+REVOKE ALL ON FUNCTION public.link_sso_user(UUID, TEXT, TEXT, TEXT, TEXT) FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION public.link_sso_user(UUID, TEXT, TEXT, TEXT, TEXT) TO service_role;
@@ -0,0 +1,17 @@
+-- Read-only maintenance mode
+--
+-- A single row, set by admins through /api/v1/admin/maintenance. While
+-- `read_only` is true the API refuses publishes and other changes with a 503
+-- and keeps serving search, info and downloads. `MAINTENANCE_MODE` in the
+-- API's environment has the same effect without the database.
+
+CREATE TABLE IF NOT EXISTS public.maintenance_mode (
+  id BOOLEAN NOT NULL DEFAULT TRUE PRIMARY KEY CHECK (id),
+  read_only BOOLEAN NOT NULL DEFAULT FALSE,
+  message TEXT CHECK (char_length(message) <= 500),
+  updated_by UUID REFERENCES auth.users(id) ON DELETE SET NULL,
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Only the API's service role reads or writes it
+ALTER TABLE public.maintenance_mode ENABLE ROW LEVEL SECURITY;
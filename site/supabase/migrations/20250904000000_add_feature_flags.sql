@@ -0,0 +1,18 @@
+-- Feature flags for gradual rollout
+--
+-- One row per flag, set by admins through /api/v1/admin/flags. A flag with no
+-- row is on. `rollout_percent` turns it on for that share of callers, and a
+-- non-empty `environments` limits it to those deployments (`VERCEL_ENV`).
+-- `FEATURE_<NAME>` in the API's environment overrides the row.
+
+CREATE TABLE IF NOT EXISTS public.feature_flags (
+  name TEXT PRIMARY KEY CHECK (name ~ '^[a-z][a-z0-9_]{0,63}$'),
+  enabled BOOLEAN NOT NULL DEFAULT TRUE,
+  rollout_percent SMALLINT NOT NULL DEFAULT 100 CHECK (rollout_percent BETWEEN 0 AND 100),
+  environments TEXT[] NOT NULL DEFAULT '{}',
+  updated_by UUID REFERENCES auth.users(id) ON DELETE SET NULL,
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Only the API's service role reads or writes it
+ALTER TABLE public.feature_flags ENABLE ROW LEVEL SECURITY;
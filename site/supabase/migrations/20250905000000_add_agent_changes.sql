@@ -0,0 +1,115 @@
+-- Change feed for index replication
+--
+-- Every change to an agent that a copy of the registry's listing would
+-- notice appends a row to agent_changes: created, updated (metadata or a new
+-- version), yanked (a version was yanked) or removed (deleted, or made
+-- private). GET /api/v1/agents/changes?since=<seq> returns the latest row per
+-- agent after a cursor, so offline indexes, mirrors and replicas fetch only
+-- what changed since they last looked.
+--
+-- Only changes to public agents are fed. An agent made private is recorded
+-- once as removed, publicly, so copies drop it; later changes to it are
+-- recorded with is_public = false and stay hidden. Counter updates
+-- (downloads, views, stars, ratings) are not recorded, so copies' counts
+-- only move when something else about the agent changes.
+
+CREATE TABLE IF NOT EXISTS public.agent_changes (
+  seq BIGSERIAL PRIMARY KEY,
+  agent_id UUID NOT NULL,
+  name TEXT NOT NULL,
+  kind TEXT NOT NULL CHECK (kind IN ('created', 'updated', 'yanked', 'removed')),
+  is_public BOOLEAN NOT NULL,
+  changed_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_agent_changes_public_name_seq
+  ON public.agent_changes (name, seq DESC)
+  WHERE is_public;
+
+-- Only the API reads it, through latest_agent_changes
+ALTER TABLE public.agent_changes ENABLE ROW LEVEL SECURITY;
+
+CREATE OR REPLACE FUNCTION public.record_agent_change()
+RETURNS TRIGGER
+LANGUAGE plpgsql
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+DECLARE
+  counters TEXT[] := ARRAY[
+    'download_count', 'view_count', 'star_count', 'rating_count', 'rating_average', 'updated_at'
+  ];
+BEGIN
+  IF TG_OP = 'INSERT' THEN
+    INSERT INTO public.agent_changes (agent_id, name, kind, is_public)
+    VALUES (NEW.id, NEW.name, 'created', NEW.is_public);
+  ELSIF TG_OP = 'DELETE' THEN
+    INSERT INTO public.agent_changes (agent_id, name, kind, is_public)
+    VALUES (OLD.id, OLD.name, 'removed', OLD.is_public);
+  ELSIF (to_jsonb(NEW) - counters) IS DISTINCT FROM (to_jsonb(OLD) - counters) THEN
+    INSERT INTO public.agent_changes (agent_id, name, kind, is_public)
+    VALUES (
+      NEW.id,
+      NEW.name,
+      CASE
+        WHEN OLD.is_public AND NOT NEW.is_public THEN 'removed'
+        WHEN NEW.is_public AND NOT OLD.is_public THEN 'created'
+        ELSE 'updated'
+      END,
+      OLD.is_public OR NEW.is_public
+    );
+  END IF;
+  RETURN NULL;
+END;
+$$;
+
+DROP TRIGGER IF EXISTS agents_record_change ON public.agents;
+CREATE TRIGGER agents_record_change
+  AFTER INSERT OR UPDATE OR DELETE ON public.agents
+  FOR EACH ROW EXECUTE FUNCTION public.record_agent_change();
+
+CREATE OR REPLACE FUNCTION public.record_agent_yank()
+RETURNS TRIGGER
+LANGUAGE plpgsql
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+BEGIN
+  IF NEW.yanked AND NOT COALESCE(OLD.yanked, false) THEN
+    INSERT INTO public.agent_changes (agent_id, name, kind, is_public)
+    SELECT a.id, a.name, 'yanked', a.is_public
+    FROM public.agents a
+    WHERE a.id = NEW.agent_id;
+  END IF;
+  RETURN NULL;
+END;
+$$;
+
+DROP TRIGGER IF EXISTS agent_versions_record_yank ON public.agent_versions;
+CREATE TRIGGER agent_versions_record_yank
+  AFTER UPDATE OF yanked ON public.agent_versions
+  FOR EACH ROW EXECUTE FUNCTION public.record_agent_yank();
+
+-- Agents that existed before the feed start it as created
+INSERT INTO public.agent_changes (agent_id, name, kind, is_public, changed_at)
+SELECT a.id, a.name, 'created', a.is_public, a.updated_at
+FROM public.agents a
+WHERE NOT EXISTS (SELECT 1 FROM public.agent_changes)
+ORDER BY a.updated_at, a.name;
+
+-- The latest public change to each agent. Callers filter on seq and order
+-- and limit through PostgREST.
+CREATE OR REPLACE FUNCTION public.latest_agent_changes()
+RETURNS TABLE (seq BIGINT, name TEXT, kind TEXT)
+LANGUAGE sql
+STABLE
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+  SELECT DISTINCT ON (c.name) c.seq, c.name, c.kind
+  FROM public.agent_changes c
+  WHERE c.is_public
+  ORDER BY c.name, c.seq DESC;
+$$;
+
+GRANT EXECUTE ON FUNCTION public.latest_agent_changes() TO anon, authenticated, service_role;
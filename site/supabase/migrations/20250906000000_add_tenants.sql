@@ -0,0 +1,604 @@
+-- Tenants: isolated registries in one deployment
+--
+-- A deployment can host several registries, e.g. one per business unit,
+-- configured in the API's TENANTS. Agents, their change feed and API keys
+-- belong to one tenant; everything from before tenants belongs to
+-- 'default'. The API sends the tenant of each request in the x-carp-tenant
+-- header, which current_tenant() reads, so:
+--
+-- - rows created by a request land in its tenant by default
+-- - the functions the API calls only find rows in the request's tenant
+-- - anon and authenticated reads, such as the site's, only see the tenant
+--   they ask for, which is 'default' unless they send the header
+--
+-- Agent names are unique per tenant rather than across the deployment.
+-- Other tables hang off agents or users and follow them.
+
+CREATE OR REPLACE FUNCTION public.current_tenant()
+RETURNS TEXT
+LANGUAGE sql
+STABLE
+SET search_path = ''
+AS $$
+  SELECT COALESCE(
+    NULLIF(current_setting('request.headers', true)::json ->> 'x-carp-tenant', ''),
+    'default'
+  );
+$$;
+
+GRANT EXECUTE ON FUNCTION public.current_tenant() TO anon, authenticated, service_role;
+
+ALTER TABLE public.agents
+  ADD COLUMN IF NOT EXISTS tenant_id TEXT NOT NULL DEFAULT public.current_tenant();
+ALTER TABLE public.api_keys
+  ADD COLUMN IF NOT EXISTS tenant_id TEXT NOT NULL DEFAULT public.current_tenant();
+ALTER TABLE public.agent_changes
+  ADD COLUMN IF NOT EXISTS tenant_id TEXT NOT NULL DEFAULT 'default';
+
+ALTER TABLE public.agents DROP CONSTRAINT IF EXISTS unique_agent_name;
+ALTER TABLE public.agents DROP CONSTRAINT IF EXISTS agents_name_key;
+ALTER TABLE public.agents DROP CONSTRAINT IF EXISTS unique_tenant_agent_name;
+ALTER TABLE public.agents
+  ADD CONSTRAINT unique_tenant_agent_name UNIQUE (tenant_id, name);
+
+CREATE INDEX IF NOT EXISTS idx_api_keys_tenant_prefix
+  ON public.api_keys (tenant_id, prefix);
+
+DROP INDEX IF EXISTS public.idx_agent_changes_public_name_seq;
+CREATE INDEX IF NOT EXISTS idx_agent_changes_public_tenant_name_seq
+  ON public.agent_changes (tenant_id, name, seq DESC)
+  WHERE is_public;
+
+-- Restrictive, so it narrows every existing policy rather than adding to them
+DROP POLICY IF EXISTS "Agents are read within their tenant" ON public.agents;
+CREATE POLICY "Agents are read within their tenant"
+  ON public.agents AS RESTRICTIVE
+  FOR ALL TO anon, authenticated
+  USING (tenant_id = public.current_tenant())
+  WITH CHECK (tenant_id = public.current_tenant());
+
+DROP POLICY IF EXISTS "API keys are read within their tenant" ON public.api_keys;
+CREATE POLICY "API keys are read within their tenant"
+  ON public.api_keys AS RESTRICTIVE
+  FOR ALL TO anon, authenticated
+  USING (tenant_id = public.current_tenant())
+  WITH CHECK (tenant_id = public.current_tenant());
+
+-- Changes are logged in the tenant of the agent they're to, whoever makes them
+CREATE OR REPLACE FUNCTION public.record_agent_change()
+RETURNS TRIGGER
+LANGUAGE plpgsql
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+DECLARE
+  counters TEXT[] := ARRAY[
+    'download_count', 'view_count', 'star_count', 'rating_count', 'rating_average', 'updated_at'
+  ];
+BEGIN
+  IF TG_OP = 'INSERT' THEN
+    INSERT INTO public.agent_changes (agent_id, tenant_id, name, kind, is_public)
+    VALUES (NEW.id, NEW.tenant_id, NEW.name, 'created', NEW.is_public);
+  ELSIF TG_OP = 'DELETE' THEN
+    INSERT INTO public.agent_changes (agent_id, tenant_id, name, kind, is_public)
+    VALUES (OLD.id, OLD.tenant_id, OLD.name, 'removed', OLD.is_public);
+  ELSIF (to_jsonb(NEW) - counters) IS DISTINCT FROM (to_jsonb(OLD) - counters) THEN
+    INSERT INTO public.agent_changes (agent_id, tenant_id, name, kind, is_public)
+    VALUES (
+      NEW.id,
+      NEW.tenant_id,
+      NEW.name,
+      CASE
+        WHEN OLD.is_public AND NOT NEW.is_public THEN 'removed'
+        WHEN NEW.is_public AND NOT OLD.is_public THEN 'created'
+        ELSE 'updated'
+      END,
+      OLD.is_public OR NEW.is_public
+    );
+  END IF;
+  RETURN NULL;
+END;
+$$;
+
+CREATE OR REPLACE FUNCTION public.record_agent_yank()
+RETURNS TRIGGER
+LANGUAGE plpgsql
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+BEGIN
+  IF NEW.yanked AND NOT COALESCE(OLD.yanked, false) THEN
+    INSERT INTO public.agent_changes (agent_id, tenant_id, name, kind, is_public)
+    SELECT a.id, a.tenant_id, a.name, 'yanked', a.is_public
+    FROM public.agents a
+    WHERE a.id = NEW.agent_id;
+  END IF;
+  RETURN NULL;
+END;
+$$;
+
+CREATE OR REPLACE FUNCTION public.latest_agent_changes()
+RETURNS TABLE (seq BIGINT, name TEXT, kind TEXT)
+LANGUAGE sql
+STABLE
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+  SELECT DISTINCT ON (c.name) c.seq, c.name, c.kind
+  FROM public.agent_changes c
+  WHERE c.is_public
+    AND c.tenant_id = public.current_tenant()
+  ORDER BY c.name, c.seq DESC;
+$$;
+
+-- Tags are counted in the request's tenant only, even when the service role
+-- calls this and row level security doesn't apply
+CREATE OR REPLACE FUNCTION public.tag_counts(max_tags INTEGER DEFAULT 100)
+RETURNS TABLE (
+  tag TEXT,
+  agent_count BIGINT
+)
+LANGUAGE sql
+STABLE
+SET search_path = ''
+AS $$
+  SELECT
+    lower(btrim(t.tag)) AS tag,
+    COUNT(DISTINCT a.id) AS agent_count
+  FROM public.agents a
+  CROSS JOIN LATERAL unnest(a.tags) AS t(tag)
+  WHERE a.is_public = true
+    AND a.tenant_id = public.current_tenant()
+    AND btrim(t.tag) <> ''
+  GROUP BY lower(btrim(t.tag))
+  ORDER BY agent_count DESC, tag ASC
+  LIMIT LEAST(GREATEST(max_tags, 1), 1000);
+$$;
+
+-- API keys only authenticate in the tenant they were created in
+CREATE OR REPLACE FUNCTION public.lookup_api_keys(p_prefix TEXT)
+RETURNS TABLE (
+  key_id UUID,
+  user_id UUID,
+  key_hash TEXT,
+  scopes TEXT[],
+  is_active BOOLEAN,
+  is_expired BOOLEAN,
+  user_email TEXT,
+  github_username TEXT
+)
+LANGUAGE sql
+STABLE
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+  SELECT
+    ak.id,
+    ak.user_id,
+    ak.key_hash,
+    ak.scopes,
+    ak.is_active,
+    ak.expires_at IS NOT NULL AND ak.expires_at <= NOW(),
+    u.email::TEXT,
+    p.github_username
+  FROM public.api_keys ak
+  LEFT JOIN auth.users u ON u.id = ak.user_id
+  LEFT JOIN public.profiles p ON p.user_id = ak.user_id
+  WHERE ak.prefix = p_prefix
+    AND ak.tenant_id = public.current_tenant();
+$$;
+
+CREATE OR REPLACE FUNCTION public.publish_agent_version(
+  p_user_id UUID,
+  p_name TEXT,
+  p_version TEXT,
+  p_description TEXT,
+  p_file_path TEXT,
+  p_content_type TEXT,
+  p_package_size BIGINT,
+  p_checksum TEXT,
+  p_definition JSONB DEFAULT '{}',
+  p_tags TEXT[] DEFAULT '{}',
+  p_author_name TEXT DEFAULT NULL,
+  p_license TEXT DEFAULT 'MIT',
+  p_homepage TEXT DEFAULT NULL,
+  p_repository TEXT DEFAULT NULL,
+  p_readme TEXT DEFAULT NULL,
+  p_is_public BOOLEAN DEFAULT true,
+  p_oci_manifest TEXT DEFAULT NULL,
+  p_oci_config TEXT DEFAULT NULL,
+  p_is_template BOOLEAN DEFAULT NULL,
+  p_dependencies JSONB DEFAULT '{}'
+)
+RETURNS SETOF public.agents
+LANGUAGE plpgsql
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+DECLARE
+  agent_record RECORD;
+  new_version_id UUID;
+BEGIN
+  -- Serialize concurrent publishes of the same agent
+  SELECT a.id, a.user_id INTO agent_record
+  FROM public.agents a
+  WHERE a.tenant_id = public.current_tenant()
+    AND a.name = p_name
+  FOR UPDATE;
+
+  IF NOT FOUND THEN
+    INSERT INTO public.agents (
+      tenant_id, user_id, name, description, definition, tags, author_name, license,
+      homepage, repository, readme, keywords, current_version, is_public,
+      is_template, view_count, download_count
+    ) VALUES (
+      public.current_tenant(), p_user_id, p_name, p_description, p_definition, p_tags,
+      COALESCE(p_author_name, 'user-' || p_user_id::TEXT), p_license,
+      p_homepage, p_repository, p_readme, p_tags, p_version, p_is_public,
+      COALESCE(p_is_template, false), 0, 0
+    )
+    RETURNING id, user_id INTO agent_record;
+  ELSIF agent_record.user_id <> p_user_id THEN
+    RAISE EXCEPTION 'Agent % belongs to another user', p_name
+      USING ERRCODE = 'insufficient_privilege';
+  END IF;
+
+  IF EXISTS (
+    SELECT 1 FROM public.agent_versions av
+    WHERE av.agent_id = agent_record.id AND av.version = p_version
+  ) THEN
+    RAISE EXCEPTION '%@% has already been published', p_name, p_version
+      USING ERRCODE = 'unique_violation';
+  END IF;
+
+  INSERT INTO public.agent_versions (
+    agent_id, version, description, definition, readme,
+    file_path, content_type, package_size, checksum, oci_manifest, oci_config,
+    dependencies
+  ) VALUES (
+    agent_record.id, p_version, p_description, p_definition, p_readme,
+    p_file_path, p_content_type, p_package_size, p_checksum, p_oci_manifest, p_oci_config,
+    COALESCE(p_dependencies, '{}'::jsonb)
+  )
+  RETURNING id INTO new_version_id;
+
+  UPDATE public.agents a SET
+    description = p_description,
+    definition = p_definition,
+    tags = p_tags,
+    keywords = p_tags,
+    license = p_license,
+    homepage = p_homepage,
+    repository = p_repository,
+    readme = p_readme,
+    current_version = p_version,
+    latest_version_id = new_version_id,
+    is_public = p_is_public,
+    is_template = COALESCE(p_is_template, a.is_template),
+    updated_at = NOW()
+  WHERE a.id = agent_record.id;
+
+  RETURN QUERY SELECT * FROM public.agents a WHERE a.id = agent_record.id;
+END;
+$$;
+
+CREATE OR REPLACE FUNCTION public.get_agent_download_info(
+  p_agent_name TEXT,
+  p_version_text TEXT DEFAULT '',
+  p_requester_id UUID DEFAULT NULL
+)
+RETURNS TABLE (
+  agent_id TEXT,
+  agent_name TEXT,
+  author TEXT,
+  version TEXT,
+  file_path TEXT,
+  checksum TEXT,
+  file_size BIGINT,
+  content_type TEXT,
+  definition JSONB
+)
+LANGUAGE plpgsql
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+DECLARE
+  agent_record RECORD;
+  version_record RECORD;
+  author_info RECORD;
+BEGIN
+  SELECT a.id, a.name, a.user_id INTO agent_record
+  FROM public.agents a
+  WHERE a.tenant_id = public.current_tenant()
+    AND a.name = p_agent_name
+    AND (a.is_public = true OR a.user_id = p_requester_id);
+
+  IF NOT FOUND THEN
+    RETURN;
+  END IF;
+
+  SELECT
+    COALESCE(p.display_name, p.github_username, 'Unknown') as display_name
+  INTO author_info
+  FROM public.profiles p
+  WHERE p.user_id = agent_record.user_id
+  LIMIT 1;
+
+  -- Versions without a stored package can't be downloaded
+  IF p_version_text = '' OR p_version_text = 'latest' THEN
+    SELECT av.version, av.file_path, av.checksum, av.package_size, av.content_type, av.definition
+    INTO version_record
+    FROM public.agent_versions av
+    WHERE av.agent_id = agent_record.id
+      AND av.yanked = false
+      AND av.file_path IS NOT NULL
+    ORDER BY av.created_at DESC
+    LIMIT 1;
+  ELSE
+    SELECT av.version, av.file_path, av.checksum, av.package_size, av.content_type, av.definition
+    INTO version_record
+    FROM public.agent_versions av
+    WHERE av.agent_id = agent_record.id
+      AND av.version = p_version_text
+      AND av.yanked = false
+      AND av.file_path IS NOT NULL;
+  END IF;
+
+  IF NOT FOUND THEN
+    RETURN;
+  END IF;
+
+  RETURN QUERY SELECT
+    agent_record.id::TEXT,
+    agent_record.name::TEXT,
+    COALESCE(author_info.display_name, 'Unknown')::TEXT,
+    version_record.version::TEXT,
+    version_record.file_path::TEXT,
+    COALESCE(version_record.checksum, '')::TEXT,
+    COALESCE(version_record.package_size, 0)::BIGINT,
+    COALESCE(version_record.content_type, 'application/zip')::TEXT,
+    version_record.definition::JSONB;
+END;
+$$;
+
+CREATE OR REPLACE FUNCTION public.get_resolution_candidates(
+  p_names TEXT[],
+  p_requester_id UUID DEFAULT NULL
+)
+RETURNS TABLE (
+  agent_name TEXT,
+  version TEXT,
+  checksum TEXT,
+  dependencies JSONB
+)
+LANGUAGE sql
+STABLE
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+  SELECT a.name::TEXT, av.version::TEXT, COALESCE(av.checksum, '')::TEXT, av.dependencies
+  FROM public.agents a
+  JOIN public.agent_versions av ON av.agent_id = a.id
+  WHERE a.tenant_id = public.current_tenant()
+    AND a.name = ANY(p_names)
+    AND public.can_read_agent(a.id, a.is_public, a.user_id, p_requester_id)
+    AND av.yanked = false
+    AND av.file_path IS NOT NULL;
+$$;
+
+-- Downloads count against the agent of that name in the request's tenant
+CREATE OR REPLACE FUNCTION public.record_download(
+  agent_name TEXT,
+  version_text TEXT DEFAULT '',
+  user_agent_text TEXT DEFAULT '',
+  ip_addr INET DEFAULT NULL,
+  downloader_id UUID DEFAULT NULL,
+  dedup_window_seconds INTEGER DEFAULT 3600
+)
+RETURNS BOOLEAN
+LANGUAGE plpgsql
+SECURITY DEFINER
+SET search_path = ''
+AS $$
+DECLARE
+  agent_record RECORD;
+  version_record RECORD;
+  package_record RECORD;
+  window_start TIMESTAMPTZ;
+  seen_agent BOOLEAN;
+  seen_version BOOLEAN;
+BEGIN
+  -- Find the agent
+  SELECT id INTO agent_record
+  FROM public.agents
+  WHERE tenant_id = public.current_tenant()
+    AND name = agent_name
+    AND is_public = true;
+
+  IF NOT FOUND THEN
+    RETURN FALSE;
+  END IF;
+
+  -- Find the version (use latest if not specified)
+  IF version_text = '' THEN
+    SELECT av.id, av.package_size INTO version_record
+    FROM public.agent_versions av
+    WHERE av.agent_id = agent_record.id
+    ORDER BY av.created_at DESC
+    LIMIT 1;
+  ELSE
+    SELECT av.id, av.package_size INTO version_record
+    FROM public.agent_versions av
+    WHERE av.agent_id = agent_record.id AND av.version = version_text;
+  END IF;
+
+  IF NOT FOUND THEN
+    RETURN FALSE;
+  END IF;
+
+  -- Find the package
+  SELECT id, file_size INTO package_record
+  FROM public.agent_packages ap
+  WHERE ap.version_id = version_record.id
+  LIMIT 1;
+
+  -- Serialize concurrent downloads by the same downloader so parallel
+  -- requests can't both see an empty window
+  PERFORM pg_advisory_xact_lock(
+    hashtext(agent_record.id::TEXT || ':' || COALESCE(downloader_id::TEXT, host(ip_addr), ''))
+  );
+
+  window_start := now() - make_interval(secs => GREATEST(dedup_window_seconds, 0));
+
+  -- Downloads with neither a user nor an IP can't be attributed, so they
+  -- always count
+  seen_agent := (downloader_id IS NOT NULL OR ip_addr IS NOT NULL) AND EXISTS (
+    SELECT 1
+    FROM public.download_stats ds
+    WHERE ds.agent_id = agent_record.id
+      AND ds.downloaded_at >= window_start
+      AND (
+        (downloader_id IS NOT NULL AND ds.user_id = downloader_id)
+        OR (downloader_id IS NULL AND ds.user_id IS NULL AND ds.ip_address = ip_addr)
+      )
+  );
+
+  seen_version := seen_agent AND EXISTS (
+    SELECT 1
+    FROM public.download_stats ds
+    WHERE ds.version_id = version_record.id
+      AND ds.downloaded_at >= window_start
+      AND (
+        (downloader_id IS NOT NULL AND ds.user_id = downloader_id)
+        OR (downloader_id IS NULL AND ds.user_id IS NULL AND ds.ip_address = ip_addr)
+      )
+  );
+
+  -- Always keep the raw event for stats
+  INSERT INTO public.download_stats (
+    agent_id,
+    version_id,
+    package_id,
+    user_id,
+    ip_address,
+    user_agent,
+    file_size
+  ) VALUES (
+    agent_record.id,
+    version_record.id,
+    package_record.id,
+    downloader_id,
+    ip_addr,
+    user_agent_text,
+    COALESCE(package_record.file_size, version_record.package_size)
+  );
+
+  IF NOT seen_agent THEN
+    UPDATE public.agents
+    SET download_count = COALESCE(download_count, 0) + 1
+    WHERE id = agent_record.id;
+  END IF;
+
+  IF NOT seen_version THEN
+    UPDATE public.agent_versions
+    SET download_count = COALESCE(download_count, 0) + 1
+    WHERE id = version_record.id;
+  END IF;
+
+  RETURN TRUE;
+END;
+$$;
+
+
+-- Suggestions return the tenant, so AgentVisibility can filter on it
+DROP FUNCTION IF EXISTS public.suggest_agent_names(TEXT);
+
+CREATE FUNCTION public.suggest_agent_names(p_query TEXT)
+RETURNS TABLE (
+  id UUID,
+  name TEXT,
+  user_id UUID,
+  is_public BOOLEAN,
+  tenant_id TEXT,
+  distance INTEGER,
+  similarity REAL
+)
+LANGUAGE sql
+STABLE
+SECURITY INVOKER
+SET search_path = ''
+AS $$
+  SELECT *
+  FROM (
+    SELECT
+      a.id,
+      a.name,
+      a.user_id,
+      a.is_public,
+      a.tenant_id,
+      extensions.levenshtein(lower(a.name), lower(p_query)) AS distance,
+      extensions.similarity(lower(a.name), lower(p_query)) AS similarity
+    FROM public.agents a
+    -- levenshtein only accepts strings up to 255 characters
+    WHERE length(p_query) BETWEEN 1 AND 255
+  ) scored
+  WHERE scored.distance <= 2 OR scored.similarity >= 0.3;
+$$;
+
+GRANT EXECUTE ON FUNCTION public.suggest_agent_names(TEXT) TO anon, authenticated, service_role;
+
+-- Trending keeps the top 100 of each tenant
+DROP MATERIALIZED VIEW IF EXISTS public.trending_agents_mv;
+
+CREATE MATERIALIZED VIEW public.trending_agents_mv AS
+WITH recent AS (
+  SELECT d.agent_id, SUM(d.unique_downloaders)::BIGINT AS downloads
+  FROM public.agent_downloads_daily d
+  WHERE d.day > (NOW() AT TIME ZONE 'UTC')::date - 7
+  GROUP BY d.agent_id
+),
+scored AS (
+  SELECT
+    a.id,
+    a.tenant_id,
+    a.name,
+    a.current_version,
+    a.description,
+    a.author_name,
+    a.created_at,
+    a.updated_at,
+    a.download_count,
+    a.view_count,
+    a.tags,
+    a.definition,
+    a.user_id,
+    COALESCE(r.downloads, 0) AS recent_downloads,
+    public.calculate_trending_score(a.download_count, COALESCE(r.downloads, 0), a.created_at, a.updated_at) AS trending_score
+  FROM public.agents a
+  LEFT JOIN recent r ON r.agent_id = a.id
+  WHERE a.is_public = true
+    AND a.download_count > 0
+),
+ranked AS (
+  SELECT
+    scored.*,
+    row_number() OVER (PARTITION BY scored.tenant_id ORDER BY scored.trending_score DESC) AS rank
+  FROM scored
+)
+SELECT
+  id, tenant_id, name, current_version, description, author_name, created_at, updated_at,
+  download_count, view_count, tags, definition, user_id, recent_downloads, trending_score
+FROM ranked
+WHERE rank <= 100
+ORDER BY tenant_id, trending_score DESC;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_trending_agents_mv_id
+  ON public.trending_agents_mv(id);
+
+CREATE INDEX IF NOT EXISTS idx_trending_agents_mv_tenant_score
+  ON public.trending_agents_mv(tenant_id, trending_score DESC);
+
+GRANT SELECT ON public.trending_agents_mv TO anon, authenticated;
+
+COMMENT ON MATERIALIZED VIEW public.trending_agents_mv IS
+'Cached trending agents of each tenant ranked by calculated trending score. Refreshed by the refresh_trending and rollup_stats jobs.';
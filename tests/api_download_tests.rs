@@ -250,7 +250,7 @@ mod database_integration_tests {
             .await;
 
         // Set environment variables to use mock server
-        env::set_var("SUPABASE_URL", &mock_server.uri());
+        env::set_var("SUPABASE_URL", mock_server.uri());
         env::set_var("SUPABASE_SERVICE_ROLE_KEY", "test_service_key");
 
         // Test the database function call logic by directly testing the HTTP calls
@@ -258,7 +258,7 @@ mod database_integration_tests {
 
         // Test specific version
         let response1 = client
-            .post(&format!(
+            .post(format!(
                 "{}/rest/v1/rpc/get_agent_download_info",
                 mock_server.uri()
             ))
@@ -277,7 +277,7 @@ mod database_integration_tests {
 
         // Test latest version
         let response2 = client
-            .post(&format!(
+            .post(format!(
                 "{}/rest/v1/rpc/get_agent_download_info",
                 mock_server.uri()
             ))
@@ -392,7 +392,7 @@ mod database_integration_tests {
 
         let client = reqwest::Client::new();
         let response = client
-            .post(&format!(
+            .post(format!(
                 "{}/rest/v1/rpc/get_agent_download_info",
                 mock_server.uri()
             ))
@@ -485,7 +485,7 @@ mod signed_url_generation_tests {
 
         let client = reqwest::Client::new();
         let response = client
-            .post(&format!(
+            .post(format!(
                 "{}/storage/v1/object/sign/agent-packages/test-agent/1.0.0/agent.zip",
                 mock_server.uri()
             ))
@@ -542,7 +542,7 @@ mod signed_url_generation_tests {
 
         let client = reqwest::Client::new();
         let response = client
-            .post(&format!(
+            .post(format!(
                 "{}/storage/v1/object/sign/agent-packages/nonexistent/file.zip",
                 mock_server.uri()
             ))
@@ -617,10 +617,7 @@ mod download_tracking_tests {
 
         // Test specific version recording
         let response1 = client
-            .post(&format!(
-                "{}/rest/v1/rpc/record_download",
-                mock_server.uri()
-            ))
+            .post(format!("{}/rest/v1/rpc/record_download", mock_server.uri()))
             .header("apikey", "test_service_key")
             .header("Authorization", "Bearer test_service_key")
             .header("Content-Type", "application/json")
@@ -636,10 +633,7 @@ mod download_tracking_tests {
 
         // Test latest version recording
         let response2 = client
-            .post(&format!(
-                "{}/rest/v1/rpc/record_download",
-                mock_server.uri()
-            ))
+            .post(format!("{}/rest/v1/rpc/record_download", mock_server.uri()))
             .header("apikey", "test_service_key")
             .header("Authorization", "Bearer test_service_key")
             .header("Content-Type", "application/json")
@@ -900,7 +894,7 @@ mod regression_tests {
         // Test that the correct parameter names are used
         let client = reqwest::Client::new();
         let response = client
-            .post(&format!(
+            .post(format!(
                 "{}/rest/v1/rpc/get_agent_download_info",
                 mock_server.uri()
             ))
@@ -925,7 +919,7 @@ mod regression_tests {
 
         // This request should not match any mock and would fail in real scenario
         let response_wrong = client
-            .post(&format!(
+            .post(format!(
                 "{}/rest/v1/rpc/get_agent_download_info",
                 mock_server.uri()
             ))
@@ -985,7 +979,7 @@ mod regression_tests {
         });
 
         let response = client
-            .post(&format!(
+            .post(format!(
                 "{}/rest/v1/rpc/get_agent_download_info",
                 mock_server.uri()
             ))
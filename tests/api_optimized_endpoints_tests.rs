@@ -97,7 +97,7 @@ mod latest_endpoint_tests {
 
         let client = reqwest::Client::new();
         let response = client
-            .get(&format!("{}/rest/v1/agents", mock_server.uri()))
+            .get(format!("{}/rest/v1/agents", mock_server.uri()))
             .header("apikey", "test_service_key")
             .query(&[
                 ("select", "name,current_version,description,author_name,created_at,updated_at,download_count,tags"),
@@ -135,7 +135,7 @@ mod latest_endpoint_tests {
 
         let client = reqwest::Client::new();
         let response = client
-            .get(&format!("{}/rest/v1/agents", mock_server.uri()))
+            .get(format!("{}/rest/v1/agents", mock_server.uri()))
             .header("apikey", "test_service_key")
             .query(&[
                 ("select", "name,current_version,description,author_name,created_at,updated_at,download_count,tags"),
@@ -170,7 +170,7 @@ mod latest_endpoint_tests {
 
         let client = reqwest::Client::new();
         let response = client
-            .get(&format!("{}/rest/v1/agents", mock_server.uri()))
+            .get(format!("{}/rest/v1/agents", mock_server.uri()))
             .header("apikey", "test_service_key")
             .query(&[
                 ("select", "name,current_version,description,author_name,created_at,updated_at,download_count,tags"),
@@ -261,7 +261,7 @@ mod trending_endpoint_tests {
 
         let client = reqwest::Client::new();
         let response = client
-            .get(&format!("{}/rest/v1/trending_agents_mv", mock_server.uri()))
+            .get(format!("{}/rest/v1/trending_agents_mv", mock_server.uri()))
             .header("apikey", "test_service_key")
             .query(&[
                 ("select", "name,current_version,description,author_name,created_at,updated_at,download_count,tags"),
@@ -324,7 +324,7 @@ mod trending_endpoint_tests {
 
         // First try materialized view (will fail)
         let mv_response = client
-            .get(&format!("{}/rest/v1/trending_agents_mv", mock_server.uri()))
+            .get(format!("{}/rest/v1/trending_agents_mv", mock_server.uri()))
             .header("apikey", "test_service_key")
             .send()
             .await
@@ -334,7 +334,7 @@ mod trending_endpoint_tests {
 
         // Then try fallback query (will succeed)
         let fallback_response = client
-            .get(&format!("{}/rest/v1/agents", mock_server.uri()))
+            .get(format!("{}/rest/v1/agents", mock_server.uri()))
             .header("apikey", "test_service_key")
             .query(&[
                 ("select", "name,current_version,description,author_name,created_at,updated_at,download_count,tags"),
@@ -374,7 +374,7 @@ mod trending_endpoint_tests {
 
         let client = reqwest::Client::new();
         let response = client
-            .post(&format!(
+            .post(format!(
                 "{}/rest/v1/rpc/ensure_trending_view_populated",
                 mock_server.uri()
             ))
@@ -497,7 +497,7 @@ mod error_handling_tests {
 
             let client = reqwest::Client::new();
             let response = client
-                .get(&format!("{}/rest/v1/agents", mock_server.uri()))
+                .get(format!("{}/rest/v1/agents", mock_server.uri()))
                 .header("apikey", "test_service_key")
                 .send()
                 .await
@@ -532,7 +532,7 @@ mod error_handling_tests {
 
         let client = reqwest::Client::new();
         let response = client
-            .get(&format!("{}/rest/v1/agents", mock_server.uri()))
+            .get(format!("{}/rest/v1/agents", mock_server.uri()))
             .header("apikey", "test_service_key")
             .send()
             .await
@@ -565,7 +565,7 @@ mod error_handling_tests {
 
         let client = reqwest::Client::new();
         let response = client
-            .get(&format!("{}/rest/v1/agents", mock_server.uri()))
+            .get(format!("{}/rest/v1/agents", mock_server.uri()))
             .header("apikey", "test_service_key")
             .send()
             .await
@@ -1,16 +1,15 @@
 /// Comprehensive authentication flow testing
 /// Tests both JWT and API key authentication flows
-use chrono::{DateTime, Utc};
+use chrono::Utc;
 use serde_json::json;
-use std::collections::HashMap;
 use std::env;
 use uuid::Uuid;
 
 // Import the shared authentication module
 use shared::{
-    authenticate_api_key, authenticate_jwt, extract_bearer_token, guess_token_type, hash_api_key,
-    validate_jwt_token, ApiError, AuthConfig, AuthMethod, AuthenticatedUser, SupabaseJwtClaims,
-    TokenType, UserMetadata,
+    authenticate_api_key, authenticate_jwt, guess_token_type, hash_api_key, validate_jwt_token,
+    ApiError, AuthConfig, AuthMethod, AuthenticatedUser, SupabaseJwtClaims, TokenType,
+    UserMetadata,
 };
 
 /// Test configuration for authentication tests
@@ -396,7 +395,7 @@ mod auth_tests {
 #[cfg(test)]
 mod middleware_tests {
     use super::*;
-    use shared::{authenticate_request, AuthStrategy};
+    use shared::AuthStrategy;
     // Note: Full middleware tests would require proper HTTP request mocking
     // These tests focus on the core logic that can be tested without full HTTP infrastructure
 
@@ -1,13 +1,13 @@
 /// Integration tests for complete authentication flows
 /// Tests the full cycle: GitHub OAuth -> JWT -> API key creation -> CLI operations
-use chrono::{DateTime, Utc};
+use chrono::Utc;
 use serde_json::json;
 use std::env;
 use uuid::Uuid;
 
 use shared::{
-    authenticate_api_key, authenticate_jwt, hash_api_key, sync_jwt_user, ApiError, AuthConfig,
-    AuthMethod, AuthenticatedUser, SupabaseJwtClaims, UserMetadata,
+    authenticate_api_key, authenticate_jwt, hash_api_key, sync_jwt_user, AuthConfig, AuthMethod,
+    AuthenticatedUser, SupabaseJwtClaims, UserMetadata,
 };
 
 /// Mock HTTP client for testing database operations
@@ -16,6 +16,12 @@ pub struct MockHttpClient {
     pub mock_responses: std::collections::HashMap<String, serde_json::Value>,
 }
 
+impl Default for MockHttpClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl MockHttpClient {
     pub fn new() -> Self {
         Self {
@@ -77,6 +83,7 @@ impl IntegrationTestConfig {
         }
     }
 
+    #[allow(dead_code)]
     fn create_mock_jwt_claims(&self) -> SupabaseJwtClaims {
         SupabaseJwtClaims {
             sub: self.github_user_id.clone(),
@@ -439,7 +446,7 @@ mod integration_flow_tests {
 
         for token in special_tokens {
             let jwt_result = authenticate_jwt(token, &auth_config).await;
-            let api_result = authenticate_api_key(token, &auth_config).await;
+            let _api_result = authenticate_api_key(token, &auth_config).await;
 
             // These should be handled gracefully
             if auth_config.is_development() {
@@ -1,12 +1,8 @@
 /// Authentication separation testing
 /// Tests that JWT-only and API-key-only endpoints properly enforce authentication methods
 use std::collections::HashMap;
-use std::env;
 
-use shared::{
-    api_key_middleware, jwt_middleware, require_scope, ApiError, AuthMethod, AuthenticatedUser,
-    TokenType, UserMetadata,
-};
+use shared::{ApiError, AuthMethod, AuthenticatedUser, TokenType, UserMetadata};
 use uuid::Uuid;
 
 /// Mock HTTP request builder for testing
@@ -18,6 +14,12 @@ pub struct MockRequest {
     uri: String,
 }
 
+impl Default for MockRequest {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl MockRequest {
     pub fn new() -> Self {
         Self {
@@ -173,7 +175,7 @@ mod auth_separation_tests {
     #[test]
     fn test_jwt_only_endpoint_requirements() {
         // Endpoints that should only accept JWT tokens (frontend operations)
-        let jwt_only_endpoints = vec![
+        let _jwt_only_endpoints = [
             "/v1/auth/api-keys POST", // Create API key
             "/profile",               // User profile
             "/dashboard",             // User dashboard
@@ -193,7 +195,7 @@ mod auth_separation_tests {
     #[test]
     fn test_api_key_only_endpoint_requirements() {
         // Endpoints that should only accept API keys (CLI operations)
-        let api_key_only_endpoints = vec![
+        let _api_key_only_endpoints = [
             "/v1/agents/upload POST",                   // Upload agent
             "/v1/agents/publish POST",                  // Publish agent
             "/v1/agents/{name}/{version}/download GET", // Download agent
@@ -325,10 +327,10 @@ mod auth_separation_tests {
         // Test that different endpoint types have appropriate scope requirements
 
         // Frontend endpoints (JWT-only) typically require these scopes
-        let frontend_scopes = vec!["read", "api_key_create", "api_key_manage"];
+        let frontend_scopes = ["read", "api_key_create", "api_key_manage"];
 
         // CLI endpoints (API-key-only) typically require these scopes
-        let cli_scopes = vec!["read", "write", "upload", "publish"];
+        let cli_scopes = ["read", "write", "upload", "publish"];
 
         // Create users with different scope sets
         let frontend_user = AuthenticatedUser {
@@ -34,7 +34,7 @@ use std::process::Command;
 use std::time::Duration;
 use tempfile::TempDir;
 use wiremock::{
-    matchers::{body_json, header, method, path, query_param},
+    matchers::{body_json, header, method, path},
     Mock, MockServer, ResponseTemplate,
 };
 
@@ -1231,7 +1231,7 @@ mod e2e_download_integration_tests {
                 || stdout.contains("1.0.0")
                 || stdout.contains("Pulling")
                 || stdout.contains("Found")
-                || stderr.len() > 0,
+                || !stderr.is_empty(),
             "Verbose mode should provide detailed output. STDOUT: {} STDERR: {}",
             stdout,
             stderr
@@ -1299,15 +1299,10 @@ mod e2e_download_integration_tests {
 
         // At least some downloads should succeed or attempt
         let mut successful_attempts = 0;
-        for result in results {
-            if let Ok(output) = result {
-                let stderr = String::from_utf8_lossy(&output.stderr);
-                if output.status.success()
-                    || stderr.contains("Downloaded")
-                    || stderr.contains("zip")
-                {
-                    successful_attempts += 1;
-                }
+        for output in results.into_iter().flatten() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if output.status.success() || stderr.contains("Downloaded") || stderr.contains("zip") {
+                successful_attempts += 1;
             }
         }
 
@@ -4,7 +4,6 @@
 /// These tests verify that the search functionality integrates properly with
 /// the download flow and that users can select agents interactively.
 use serde_json::json;
-use std::env;
 use std::fs;
 use std::path::PathBuf;
 use std::process::{Command, Stdio};
@@ -517,7 +516,7 @@ mod interactive_download_tests {
 
         // Verbose should provide additional information
         assert!(
-            stdout2.len() >= stdout1.len() || stderr2.len() > 0,
+            stdout2.len() >= stdout1.len() || !stderr2.is_empty(),
             "Verbose mode should provide at least as much output"
         );
     }
@@ -641,7 +640,7 @@ verify_ssl = false
         let cli_binary_path = InteractiveTestContext::find_cli_binary();
 
         let output = Command::new(&cli_binary_path)
-            .args(&["search", "test"])
+            .args(["search", "test"])
             .env("CARP_CONFIG", &config_path)
             .output()
             .expect("Failed to execute CLI command");
@@ -664,6 +663,7 @@ verify_ssl = false
 }
 
 /// Test utilities for interactive scenarios
+#[allow(dead_code)]
 mod test_utils {
     use super::*;
 
@@ -0,0 +1,216 @@
+//! Load test for the registry's hot endpoints
+//!
+//! Concurrent workers hammer search and download-info for a fixed time and
+//! report throughput and latency percentiles per endpoint. It's ignored by
+//! default; run it before a release with
+//!
+//! ```sh
+//! cargo test --release --features test-support --test load_test -- --ignored --nocapture
+//! ```
+//!
+//! Configuration comes from the environment:
+//!
+//! - `CARP_LOAD_URL`: registry to load, e.g. a preview deployment. Without
+//!   it an in-process registry seeded with `CARP_LOAD_AGENTS` agents
+//!   (default 100,000) is used
+//! - `CARP_LOAD_CONCURRENCY`: concurrent workers (default 32)
+//! - `CARP_LOAD_SECONDS`: how long to run (default 10)
+//! - `CARP_LOAD_P99_MS`: fail if any endpoint's 99th percentile is slower
+//!
+//! Any 5xx or transport error fails the run.
+
+#![cfg(feature = "test-support")]
+
+use shared::local::testing::{seeded_agent_name, TestRegistry, SEED_ROLES, SEED_SUBJECTS};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+/// Latencies and failures recorded for one endpoint
+#[derive(Default)]
+struct Samples {
+    latencies: Vec<Duration>,
+    failures: Vec<String>,
+}
+
+impl Samples {
+    fn percentile(&self, p: f64) -> Duration {
+        let index = ((self.latencies.len() as f64 * p).ceil() as usize).saturating_sub(1);
+        self.latencies[index.min(self.latencies.len() - 1)]
+    }
+}
+
+/// The request a worker makes on its `n`th iteration, and the endpoint it
+/// counts towards
+fn request(base: &str, agents: &[String], n: usize) -> (&'static str, String) {
+    let agent = &agents[n.wrapping_mul(2_654_435_761) % agents.len()];
+    match n % 4 {
+        0 => (
+            "search",
+            format!(
+                "{base}/api/v1/agents/search?q={}&limit=20",
+                SEED_ROLES[n % SEED_ROLES.len()]
+            ),
+        ),
+        1 => (
+            "search",
+            format!(
+                "{base}/api/v1/agents/search?q={}&sort=recent&page={}",
+                SEED_SUBJECTS[n % SEED_SUBJECTS.len()],
+                n % 10 + 1
+            ),
+        ),
+        2 => (
+            "download_info",
+            format!("{base}/api/v1/agents/{agent}/latest/download"),
+        ),
+        _ => (
+            "download_info",
+            format!("{base}/api/v1/agents/{agent}/1.0.0/download"),
+        ),
+    }
+}
+
+/// Names of agents to request the download info of from a remote registry
+async fn remote_agents(client: &reqwest::Client, base: &str) -> Vec<String> {
+    let search: serde_json::Value = client
+        .get(format!("{base}/api/v1/agents/search?limit=100"))
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .expect("search the registry under test")
+        .json()
+        .await
+        .unwrap();
+    let agents: Vec<String> = search["agents"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|agent| agent["name"].as_str().map(str::to_string))
+        .collect();
+    assert!(
+        !agents.is_empty(),
+        "{base} has no public agents to download"
+    );
+    agents
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "load test; run explicitly with --ignored"]
+async fn load_test_hot_endpoints() {
+    let concurrency: usize = env_or("CARP_LOAD_CONCURRENCY", 32);
+    let duration = Duration::from_secs(env_or("CARP_LOAD_SECONDS", 10));
+    let client = reqwest::Client::new();
+
+    // Keep the seeded registry alive until the workers are done
+    let (_registry, base, agents) = match std::env::var("CARP_LOAD_URL") {
+        Ok(url) => {
+            let base = url.trim_end_matches('/').to_string();
+            let agents = remote_agents(&client, &base).await;
+            (None, base, agents)
+        }
+        Err(_) => {
+            let count = env_or("CARP_LOAD_AGENTS", 100_000);
+            let registry = TestRegistry::start().await.unwrap();
+            registry.seed(count).await.unwrap();
+            let base = registry.url().to_string();
+            // Spread downloads over the registry rather than one hot row
+            let agents = (0..count).step_by(count / 1_000 + 1).map(seeded_agent_name);
+            (Some(registry), base, agents.collect())
+        }
+    };
+    println!("Loading {base} with {concurrency} workers for {duration:?}");
+
+    let samples: Arc<Mutex<[(&str, Samples); 2]>> = Arc::new(Mutex::new([
+        ("search", Samples::default()),
+        ("download_info", Samples::default()),
+    ]));
+    let next = Arc::new(AtomicUsize::new(0));
+    let agents = Arc::new(agents);
+    let deadline = Instant::now() + duration;
+
+    let workers: Vec<_> = (0..concurrency)
+        .map(|_| {
+            let (client, base, agents) = (client.clone(), base.clone(), agents.clone());
+            let (samples, next) = (samples.clone(), next.clone());
+            tokio::spawn(async move {
+                while Instant::now() < deadline {
+                    let n = next.fetch_add(1, Ordering::Relaxed);
+                    let (endpoint, url) = request(&base, &agents, n);
+                    let started = Instant::now();
+                    let result = match client.get(&url).send().await {
+                        Ok(response) if response.status().is_server_error() => {
+                            Err(format!("GET {url}: {}", response.status()))
+                        }
+                        Ok(response) => response
+                            .bytes()
+                            .await
+                            .map(drop)
+                            .map_err(|e| format!("GET {url}: {e}")),
+                        Err(e) => Err(format!("GET {url}: {e}")),
+                    };
+                    let elapsed = started.elapsed();
+
+                    let mut samples = samples.lock().unwrap();
+                    let (_, samples) = samples
+                        .iter_mut()
+                        .find(|(name, _)| *name == endpoint)
+                        .unwrap();
+                    match result {
+                        Ok(()) => samples.latencies.push(elapsed),
+                        Err(failure) => samples.failures.push(failure),
+                    }
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker.await.unwrap();
+    }
+
+    let budget = std::env::var("CARP_LOAD_P99_MS")
+        .ok()
+        .and_then(|ms| ms.parse().ok())
+        .map(Duration::from_millis);
+    let mut samples = samples.lock().unwrap();
+    let mut problems = Vec::new();
+    println!(
+        "{:<14} {:>8} {:>8} {:>10} {:>10} {:>10} {:>10}",
+        "endpoint", "requests", "req/s", "p50", "p95", "p99", "max"
+    );
+    for (endpoint, samples) in samples.iter_mut() {
+        samples.latencies.sort();
+        if samples.latencies.is_empty() {
+            problems.push(format!("{endpoint}: no successful requests"));
+        } else {
+            let p99 = samples.percentile(0.99);
+            println!(
+                "{:<14} {:>8} {:>8.0} {:>10.2?} {:>10.2?} {:>10.2?} {:>10.2?}",
+                endpoint,
+                samples.latencies.len(),
+                samples.latencies.len() as f64 / duration.as_secs_f64(),
+                samples.percentile(0.50),
+                samples.percentile(0.95),
+                p99,
+                samples.latencies.last().unwrap(),
+            );
+            if let Some(budget) = budget.filter(|budget| p99 > *budget) {
+                problems.push(format!("{endpoint}: p99 {p99:?} is over {budget:?}"));
+            }
+        }
+        if let Some(first) = samples.failures.first() {
+            problems.push(format!(
+                "{endpoint}: {} failed requests, first: {first}",
+                samples.failures.len()
+            ));
+        }
+    }
+    assert!(problems.is_empty(), "{}", problems.join("\n"));
+}
@@ -279,7 +279,7 @@ impl TestRunner {
         // Check if the CLI binary can be built
         println!("🔨 Building CLI binary for testing...");
         let build_output = Command::new(&self.config.cargo_binary)
-            .args(&["build", "--bin", "carp"])
+            .args(["build", "--bin", "carp"])
             .output()
             .map_err(|e| format!("Failed to build CLI binary: {}", e))?;
 
@@ -330,12 +330,12 @@ impl TestRunner {
         report.push_str(&format!("- **Failed:** {}\n", results.failed_tests));
 
         if results.is_successful() {
-            report.push_str(&format!("- **Status:** ✅ **PASS**\n\n"));
+            report.push_str("- **Status:** ✅ **PASS**\n\n");
             report.push_str(
                 "All tests passed successfully! The download pipeline is working correctly.\n\n",
             );
         } else {
-            report.push_str(&format!("- **Status:** ❌ **FAIL**\n\n"));
+            report.push_str("- **Status:** ❌ **FAIL**\n\n");
             report.push_str("Some tests failed. The download pipeline may have issues.\n\n");
         }
 
@@ -344,7 +344,7 @@ impl TestRunner {
             for failure in &results.failures {
                 report.push_str(&format!("- {}\n", failure));
             }
-            report.push_str("\n");
+            report.push('\n');
         }
 
         report.push_str("## Test Coverage\n\n");
@@ -402,16 +402,16 @@ fn main() {
             }
             "--help" | "-h" => {
                 println!("E2E Download Integration Test Runner");
-                println!("");
+                println!();
                 println!("USAGE:");
                 println!("    cargo run --bin test_runner [OPTIONS]");
-                println!("");
+                println!();
                 println!("OPTIONS:");
                 println!("    -v, --verbose       Enable verbose output");
                 println!("    --no-fail-fast     Continue running tests after first failure");
                 println!("    --timeout <SECS>   Set test timeout in seconds (default: 300)");
                 println!("    -h, --help          Print this help message");
-                println!("");
+                println!();
                 println!("EXAMPLES:");
                 println!("    cargo run --bin test_runner");
                 println!("    cargo run --bin test_runner --verbose");